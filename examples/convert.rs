@@ -0,0 +1,72 @@
+//! Convert in-toto metadata between the classic and the attestation formats:
+//!
+//! ```sh
+//! cargo run --example convert -- to-statement key.pk8 build.776a00e2.link build.json
+//! ```
+//!
+//! The conversions are those of `in_toto::models`:
+//!
+//! * `to-statement`: a link to a DSSE envelope of a statement with the link
+//!   predicate
+//! * `to-link`: such an envelope back to a link
+//! * `to-envelope`: a link or layout to a DSSE envelope holding it
+//! * `to-metablock`: such an envelope back to a link or layout
+//!
+//! The converted metadata is signed with the Ed25519 key in PKCS#8 format at
+//! the second argument. Verify the metadata before converting it.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use in_toto::crypto::{PrivateKey, SignatureScheme};
+use in_toto::models::{
+    envelope_to_metablock, link_to_statement, metablock_to_envelope, statement_to_link,
+    EnvelopeFile, Metablock,
+};
+use in_toto::Result;
+
+fn convert(conversion: &str, key: &PrivateKey, input: &[u8]) -> Result<Vec<u8>> {
+    let keys = [key];
+    let metablock = || serde_json::from_slice::<Metablock>(input);
+    Ok(match conversion {
+        "to-statement" => link_to_statement(&metablock()?, &keys)?.to_bytes()?,
+        "to-envelope" => metablock_to_envelope(&metablock()?, &keys)?.to_bytes()?,
+        "to-link" => serde_json::to_vec_pretty(&statement_to_link(
+            &EnvelopeFile::from_bytes(input)?,
+            &keys,
+        )?)?,
+        "to-metablock" => serde_json::to_vec_pretty(&envelope_to_metablock(
+            &EnvelopeFile::from_bytes(input)?,
+            &keys,
+        )?)?,
+        _ => {
+            return Err(in_toto::Error::IllegalArgument(format!(
+                "unknown conversion {}",
+                conversion
+            )))
+        }
+    })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 4 {
+        eprintln!(
+            "usage: convert <to-statement|to-link|to-envelope|to-metablock> <key.pk8> <input> <output>"
+        );
+        process::exit(2);
+    }
+    let converted = fs::read(&args[1])
+        .map_err(in_toto::Error::from)
+        .and_then(|key| PrivateKey::from_pkcs8(&key, SignatureScheme::Ed25519))
+        .and_then(|key| {
+            let input = fs::read(&args[2])?;
+            convert(&args[0], &key, &input)
+        })
+        .and_then(|converted| Ok(fs::write(&args[3], converted)?));
+    if let Err(e) = converted {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,251 @@
+//! The classic in-toto demo: a project owner, Alice, defines a supply chain in
+//! which Bob writes a python script and Carl packages it. Both record their
+//! step, and a client finally verifies the package against Alice's layout.
+//!
+//! Run it with `cargo run --example demo [<directory>]`. Without a directory
+//! the demo runs in a temporary one. Adapt the steps, rules and inspection to
+//! model your own supply chain.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{Duration, Utc};
+use in_toto::crypto::{KeyType, PrivateKey, SignatureScheme};
+use in_toto::models::inspection::Inspection;
+use in_toto::models::rule::ArtifactRule;
+use in_toto::models::step::{Command, Step};
+use in_toto::models::{link_filename, LayoutMetadataBuilder, Metablock, MetablockBuilder};
+use in_toto::runlib::in_toto_run;
+use in_toto::verifylib::{in_toto_verify, VerificationReport};
+use in_toto::{Error, Result};
+
+/// Everyone taking part in the demo supply chain.
+struct Participants {
+    alice: PrivateKey,
+    bob: PrivateKey,
+    carl: PrivateKey,
+}
+
+/// Generate fresh ed25519 keys, storing the private keys as PKCS#8 in `dir`.
+fn generate_keys(dir: &Path) -> Result<Participants> {
+    let generate = |name: &str| -> Result<PrivateKey> {
+        let pkcs8 = PrivateKey::new(KeyType::Ed25519)?;
+        fs::write(dir.join(format!("{}.pk8.der", name)), &pkcs8)?;
+        PrivateKey::from_pkcs8(&pkcs8, SignatureScheme::Ed25519)
+    };
+    Ok(Participants {
+        alice: generate("alice")?,
+        bob: generate("bob")?,
+        carl: generate("carl")?,
+    })
+}
+
+fn rules(json: &str) -> Result<Vec<ArtifactRule>> {
+    Ok(serde_json::from_str(json)?)
+}
+
+/// `path` as the string the run and verify functions take.
+fn utf8(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::NonUtf8Path(path.to_path_buf()))
+}
+
+/// Alice's layout: Bob creates `foo.py`, Carl packages exactly that file into
+/// `foo.tar.gz`, and the client unpacks the package to check its contents.
+fn create_layout(participants: &Participants) -> Result<Metablock> {
+    let bob = participants.bob.public();
+    let carl = participants.carl.public();
+    let layout = LayoutMetadataBuilder::new()
+        .expires(Utc::now() + Duration::days(7))
+        .readme("in-toto demo supply chain".into())
+        .add_key(bob.clone())
+        .add_key(carl.clone())
+        .add_step(
+            Step::new("write-code")
+                .threshold(1)
+                .add_key(bob.key_id().clone())
+                .expected_command(Command::new(["vi"]))
+                .expected_products(rules(r#"[["CREATE", "foo.py"], ["DISALLOW", "*"]]"#)?),
+        )
+        .add_step(
+            Step::new("package")
+                .threshold(1)
+                .add_key(carl.key_id().clone())
+                .expected_command(Command::new(["tar", "zcvf", "foo.tar.gz", "foo.py"]))
+                .expected_materials(rules(
+                    r#"[["MATCH", "foo.py", "WITH", "PRODUCTS", "FROM", "write-code"],
+                        ["DISALLOW", "*"]]"#,
+                )?)
+                .expected_products(rules(
+                    r#"[["CREATE", "foo.tar.gz"], ["ALLOW", "foo.py"], ["DISALLOW", "*"]]"#,
+                )?),
+        )
+        .add_inspect(
+            Inspection::new("untar")
+                .run(Command::new(["tar", "xzf", "foo.tar.gz"]))
+                .expected_materials(rules(
+                    r#"[["MATCH", "foo.tar.gz", "WITH", "PRODUCTS", "FROM", "package"],
+                        ["DISALLOW", "*"]]"#,
+                )?)
+                .expected_products(rules(
+                    r#"[["MATCH", "foo.py", "WITH", "PRODUCTS", "FROM", "write-code"],
+                        ["MATCH", "foo.tar.gz", "WITH", "PRODUCTS", "FROM", "package"],
+                        ["DISALLOW", "*"]]"#,
+                )?),
+        )
+        .build()?;
+    layout.validate_steps()?;
+    println!("{}", layout.explain());
+
+    Ok(MetablockBuilder::from_metadata(Box::new(layout))
+        .sign(&[&participants.alice])?
+        .build())
+}
+
+/// Record step `name` run by the owner of `key` in `work_dir`, storing the
+/// signed link in `link_dir`.
+fn run_step(
+    name: &str,
+    key: &PrivateKey,
+    work_dir: &Path,
+    link_dir: &Path,
+    materials: bool,
+    command: &[&str],
+) -> Result<()> {
+    let work = utf8(work_dir)?;
+    let lstrip = format!("{}/", work);
+    let materials: &[&str] = if materials { &[work] } else { &[] };
+    let link = in_toto_run(
+        name,
+        Some(work),
+        materials,
+        &[work],
+        command,
+        Some(key),
+        None,
+        Some(&[&lstrip]),
+    )?;
+    let path = link_dir.join(link_filename(name, key.public().key_id()));
+    fs::write(&path, serde_json::to_vec_pretty(&link)?)?;
+    println!("{} recorded {}", name, path.display());
+    Ok(())
+}
+
+/// Run the whole demo in `dir`, returning the verification report.
+fn run_demo(dir: &Path) -> Result<VerificationReport> {
+    let dir = fs::canonicalize(dir)?;
+    let [keys, functionary, links, final_product] =
+        ["keys", "functionary", "links", "final_product"].map(|d| dir.join(d));
+    for d in [&keys, &functionary, &links, &final_product] {
+        fs::create_dir_all(d)?;
+    }
+
+    // Alice creates and signs the layout
+    let participants = generate_keys(&keys)?;
+    let layout = create_layout(&participants)?;
+    fs::write(dir.join("root.layout"), serde_json::to_vec_pretty(&layout)?)?;
+
+    // Bob writes the code, Carl packages it
+    run_step(
+        "write-code",
+        &participants.bob,
+        &functionary,
+        &links,
+        false,
+        &["sh", "-c", "echo 'print(\"Hello in-toto\")' > foo.py"],
+    )?;
+    run_step(
+        "package",
+        &participants.carl,
+        &functionary,
+        &links,
+        true,
+        &["tar", "zcvf", "foo.tar.gz", "foo.py"],
+    )?;
+
+    // The client receives the package and verifies it
+    fs::copy(
+        functionary.join("foo.tar.gz"),
+        final_product.join("foo.tar.gz"),
+    )?;
+    in_toto_verify(
+        &layout,
+        &[participants.alice.public()],
+        utf8(&links)?,
+        Some(utf8(&final_product)?),
+    )
+}
+
+fn main() -> Result<()> {
+    let temp;
+    let dir: PathBuf = match env::args().nth(1) {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            dir.into()
+        }
+        None => {
+            temp = tempfile::tempdir()?;
+            temp.path().into()
+        }
+    };
+
+    let report = run_demo(&dir)?;
+    for inspection in report.inspections() {
+        println!(
+            "inspection {} saw products {:?}",
+            inspection.name(),
+            inspection.link().products().keys().collect::<Vec<_>>()
+        );
+    }
+    println!(
+        "Verification of the demo supply chain in {} passed",
+        dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::run_demo;
+
+    #[test]
+    fn demo_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let report = run_demo(dir.path()).unwrap();
+        assert_eq!(report.links().len(), 2);
+        assert_eq!(report.inspection("untar").unwrap().return_value(), 0);
+    }
+
+    #[test]
+    fn demo_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        run_demo(dir.path()).unwrap();
+        // a malicious package that does not contain Bob's foo.py
+        let product = dir.path().join("final_product");
+        std::fs::write(product.join("foo.py"), "print(\"evil\")").unwrap();
+        let status = std::process::Command::new("tar")
+            .args(["zcf", "foo.tar.gz", "foo.py"])
+            .current_dir(&product)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::remove_file(product.join("foo.py")).unwrap();
+
+        let layout =
+            serde_json::from_slice(&std::fs::read(dir.path().join("root.layout")).unwrap())
+                .unwrap();
+        let alice = in_toto::crypto::PrivateKey::from_pkcs8(
+            &std::fs::read(dir.path().join("keys/alice.pk8.der")).unwrap(),
+            in_toto::crypto::SignatureScheme::Ed25519,
+        )
+        .unwrap();
+        assert!(in_toto::verifylib::in_toto_verify(
+            &layout,
+            &[alice.public()],
+            dir.path().join("links").to_str().unwrap(),
+            Some(product.to_str().unwrap()),
+        )
+        .is_err());
+    }
+}
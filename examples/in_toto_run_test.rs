@@ -1,10 +1,11 @@
 use in_toto::crypto::PrivateKey;
 use in_toto::runlib::in_toto_run;
+use in_toto::Result;
 
-const ED25519_1_PRIVATE_KEY: &'static [u8] = include_bytes!("../tests/ed25519/ed25519-1");
+const ED25519_1_PRIVATE_KEY: &[u8] = include_bytes!("../tests/ed25519/ed25519-1");
 
-fn main() {
-    let key = PrivateKey::from_ed25519(ED25519_1_PRIVATE_KEY).unwrap();
+fn main() -> Result<()> {
+    let key = PrivateKey::from_ed25519(ED25519_1_PRIVATE_KEY)?;
 
     let link = in_toto_run(
         "example",
@@ -15,9 +16,9 @@ fn main() {
         Some(&key),
         Some(&["sha512", "sha256"]),
         None,
-    )
-    .unwrap();
-    let json = serde_json::to_value(&link).unwrap();
+    )?;
+    let json = serde_json::to_value(&link)?;
 
-    println!("Generated link: {}", json)
+    println!("Generated link: {}", json);
+    Ok(())
 }
@@ -0,0 +1,34 @@
+//! Print the draft of a layout inferred from the links in a directory:
+//!
+//! ```sh
+//! cargo run --example infer_layout -- path/to/links
+//! ```
+
+use std::env;
+use std::process;
+
+use in_toto::models::LayoutMetadataBuilder;
+use in_toto::store::DirectoryStore;
+
+fn main() {
+    let dir = match env::args().nth(1) {
+        Some(dir) => dir,
+        None => {
+            eprintln!("usage: infer_layout <directory of links>");
+            process::exit(2);
+        }
+    };
+    let layout = LayoutMetadataBuilder::infer_from_store(&DirectoryStore::new(&dir), &[])
+        .and_then(|draft| draft.build())
+        .and_then(|layout| Ok((layout.explain(), serde_json::to_string_pretty(&layout)?)));
+    match layout {
+        Ok((explanation, layout)) => {
+            eprintln!("{}", explanation);
+            println!("{}", layout);
+        }
+        Err(e) => {
+            eprintln!("Failed to infer a layout from {}: {}", dir, e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Check link and layout files for violations of the specification:
+//!
+//! ```sh
+//! cargo run --example lint -- root.layout package.776a00e2.link
+//! ```
+//!
+//! Every problem is printed as `<file>#<JSON pointer>: <problem>`, the exit
+//! status is 1 if there was any.
+
+use std::env;
+use std::fs;
+use std::process;
+
+use in_toto::models::lint;
+
+fn main() {
+    let files: Vec<String> = env::args().skip(1).collect();
+    if files.is_empty() {
+        eprintln!("usage: lint <link or layout file>...");
+        process::exit(2);
+    }
+    let mut failed = false;
+    for file in &files {
+        let issues = fs::read(file)
+            .map_err(in_toto::Error::from)
+            .and_then(|bytes| lint(&bytes));
+        match issues {
+            Ok(issues) => {
+                for issue in &issues {
+                    println!("{}#{}: {}", file, issue.pointer(), issue.message());
+                }
+                failed |= !issues.is_empty();
+            }
+            Err(e) => {
+                println!("{}: {}", file, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
@@ -1,25 +1,27 @@
 use in_toto::crypto::{KeyType, PrivateKey, SignatureScheme};
+use in_toto::Result;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::os::unix::fs::OpenOptionsExt;
 
-fn main() {
+fn main() -> Result<()> {
     // Generate a new Ed25519 signing key
-    let key = PrivateKey::new(KeyType::Ed25519).unwrap();
-    let mut privkey = PrivateKey::from_pkcs8(&key, SignatureScheme::Ed25519).unwrap();
+    let key = PrivateKey::new(KeyType::Ed25519)?;
+    let mut privkey = PrivateKey::from_pkcs8(&key, SignatureScheme::Ed25519)?;
     println!("Generated keypair {:?}", &privkey.public());
 
     let mut target = OpenOptions::new()
         .mode(0o640)
         .write(true)
         .create(true)
-        .open("test-key")
-        .unwrap();
-    target.write_all(&key).unwrap();
+        .truncate(true)
+        .open("test-key")?;
+    target.write_all(&key)?;
 
-    let loaded_key = fs::read("test-key").unwrap();
-    privkey = PrivateKey::from_pkcs8(&loaded_key, SignatureScheme::Ed25519).unwrap();
+    let loaded_key = fs::read("test-key")?;
+    privkey = PrivateKey::from_pkcs8(&loaded_key, SignatureScheme::Ed25519)?;
 
-    println!("loaded keypair: {:?}", &privkey.public())
+    println!("loaded keypair: {:?}", &privkey.public());
+    Ok(())
 }
@@ -0,0 +1,14 @@
+//! Parse artifact rules and write them back.
+
+#![no_main]
+
+use in_toto::models::rule::ArtifactRule;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rule) = serde_json::from_slice::<ArtifactRule>(data) {
+        let json = serde_json::to_string(&rule).expect("parsed rules serialize");
+        let again: ArtifactRule = serde_json::from_str(&json).expect("serialized rules parse");
+        assert_eq!(again, rule);
+    }
+});
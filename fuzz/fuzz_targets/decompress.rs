@@ -0,0 +1,17 @@
+//! Decompress raw bytes as gzip and zstd payloads, bounded like the
+//! payloads of untrusted envelopes.
+
+#![no_main]
+
+use in_toto::models::PayloadCompression;
+use libfuzzer_sys::fuzz_target;
+
+const MAX_SIZE: usize = 1 << 20;
+
+fuzz_target!(|data: &[u8]| {
+    for compression in [PayloadCompression::Gzip, PayloadCompression::Zstd] {
+        if let Ok(payload) = compression.decompress(data, MAX_SIZE) {
+            assert!(payload.len() <= MAX_SIZE);
+        }
+    }
+});
@@ -0,0 +1,23 @@
+//! Parse DSSE and Sigstore envelopes, and the statements and predicates
+//! they carry.
+
+#![no_main]
+
+use in_toto::models::{
+    CosignBundle, CosignEnvelope, EnvelopeFile, PredicateWrapper, StatementWrapper,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(envelope) = EnvelopeFile::from_bytes(data) {
+        let _ = CosignEnvelope::from_envelope(&envelope).to_envelope(None);
+    }
+    if let Ok(bundle) = CosignBundle::from_bytes(data) {
+        let _ = bundle.certificates();
+        let _ = bundle.certificates_pem();
+    }
+    if let Ok(value) = serde_json::from_slice::<serde_json::Value>(data) {
+        let _ = StatementWrapper::try_from_value(value.clone());
+        let _ = PredicateWrapper::try_from_value(value);
+    }
+});
@@ -0,0 +1,21 @@
+//! Parse link and layout files, and everything done to them before their
+//! signatures are known to be good.
+
+#![no_main]
+
+use in_toto::models::{Metablock, MetadataLimits, MetadataWrapper};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = MetadataWrapper::try_from_bytes(data);
+    let metablock: Metablock = match MetadataLimits::new().parse_metablock(data) {
+        Ok(metablock) => metablock,
+        Err(_) => return,
+    };
+    let _ = metablock.metadata().to_bytes();
+    let _ = serde_json::to_vec(&metablock);
+    if let MetadataWrapper::Layout(layout) = metablock.metadata() {
+        let keys: Vec<_> = layout.keys().values().collect();
+        let _ = metablock.verify(1, keys);
+    }
+});
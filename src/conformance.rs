@@ -0,0 +1,309 @@
+//! Wire compatibility invariants of the metadata formats.
+//!
+//! Links and layouts are signed over their canonical JSON, so a change to
+//! how a model serializes silently breaks the signatures of metadata written
+//! by other in-toto implementations, or by older versions of this crate.
+//! Forks changing the models can check that random metadata still satisfies
+//! the invariants tested here:
+//!
+//! ```
+//! use in_toto::conformance::check_wire_compatibility;
+//!
+//! check_wire_compatibility(42, 16).unwrap();
+//! ```
+//!
+//! The metadata is drawn from a seeded `Generator`, so a failing seed can be
+//! reproduced. Only available with the `conformance` feature.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use chrono::DateTime;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::crypto::{HashAlgorithm, HashValue, PrivateKey};
+use crate::interchange::{DataInterchange, Json};
+use crate::models::byproducts::ByProducts;
+use crate::models::inspection::Inspection;
+use crate::models::rule::{ArtifactRule, ArtifactRuleBuilder};
+use crate::models::step::{Command, Step};
+use crate::models::{
+    LayoutMetadata, LayoutMetadataBuilder, LinkMetadata, LinkMetadataBuilder, Metablock,
+    MetablockBuilder, TargetDescription, VirtualTargetPath,
+};
+use crate::test_utils::key;
+use crate::{Error, Result};
+
+/// Characters strings are drawn from, including some JSON has to escape.
+const CHARS: &[char] = &[
+    'a', 'b', 'z', 'A', 'Z', '0', '9', '-', '_', '.', ' ', '"', '\\', '/', '\n', '\t', 'é', '✓',
+];
+
+/// Characters of step names and path segments.
+const NAME_CHARS: &[char] = &['a', 'b', 'c', 'x', 'y', 'z', '0', '1', '_', '-'];
+
+const RULE_TYPES: &[&str] = &[
+    "MATCH", "CREATE", "DELETE", "MODIFY", "ALLOW", "REQUIRE", "DISALLOW",
+];
+
+/// Check that `value` survives serialization:
+///
+/// * parsing the serialized `value` gives `value` again
+/// * serializing the parsed value gives the same JSON, though the order of
+///   keys may differ, e.g. of the hashes of an artifact
+/// * the canonical bytes do not depend on whether `value` was serialized
+///   compactly or pretty printed, and parse to `value` as well
+pub fn check_round_trip<T>(value: &T) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = serde_json::to_vec(value)?;
+    let parsed: T = serde_json::from_slice(&bytes)?;
+    if &parsed != value {
+        return Err(violation(
+            value,
+            "parsing the serialized value gives",
+            &parsed,
+        ));
+    }
+    let json: serde_json::Value = serde_json::from_slice(&bytes)?;
+    let reserialized = serde_json::to_value(&parsed)?;
+    if reserialized != json {
+        return Err(violation(value, "serializing it again gives", reserialized));
+    }
+
+    let canonical = Json::canonicalize(&json)?;
+    let pretty = serde_json::to_vec_pretty(value)?;
+    let canonical_pretty = Json::canonicalize(&serde_json::from_slice(&pretty)?)?;
+    if canonical_pretty != canonical {
+        return Err(violation(
+            value,
+            "the canonical bytes of its pretty form are",
+            String::from_utf8_lossy(&canonical_pretty),
+        ));
+    }
+    let from_canonical: T = serde_json::from_slice(&canonical)?;
+    if &from_canonical != value {
+        return Err(violation(
+            value,
+            "parsing its canonical bytes gives",
+            &from_canonical,
+        ));
+    }
+    Ok(())
+}
+
+/// Check the invariants of `check_round_trip` for `cases` random artifact
+/// rules, links, layouts and signed metablocks drawn from `seed`, and that
+/// the signatures of the metablocks still verify after the round trip.
+pub fn check_wire_compatibility(seed: u64, cases: usize) -> Result<()> {
+    let mut generator = Generator::new(seed);
+    for case in 0..cases {
+        let context = |e: Error| Error::Encoding(format!("seed {}, case {}: {}", seed, case, e));
+        check_round_trip(&generator.rule()).map_err(context)?;
+        check_round_trip(&generator.link()).map_err(context)?;
+        check_round_trip(&generator.layout()).map_err(context)?;
+
+        let (metablock, keys) = generator.metablock();
+        check_round_trip(&metablock).map_err(context)?;
+        let parsed: Metablock = serde_json::from_slice(&serde_json::to_vec(&metablock)?)?;
+        parsed
+            .verify(keys.len() as u32, keys.iter().map(PrivateKey::public))
+            .map_err(context)?;
+    }
+    Ok(())
+}
+
+/// Random, but valid metadata drawn from a seed.
+#[derive(Debug)]
+pub struct Generator {
+    rng: StdRng,
+}
+
+impl Generator {
+    /// A generator drawing from `seed`
+    pub fn new(seed: u64) -> Self {
+        Generator {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    fn string(&mut self, chars: &[char], min: usize, max: usize) -> String {
+        let len = self.rng.gen_range(min..=max);
+        (0..len)
+            .map(|_| chars[self.rng.gen_range(0..chars.len())])
+            .collect()
+    }
+
+    fn text(&mut self) -> String {
+        self.string(CHARS, 0, 24)
+    }
+
+    fn map(&mut self) -> BTreeMap<String, String> {
+        let len = self.rng.gen_range(0..4);
+        (0..len)
+            .map(|_| (self.string(NAME_CHARS, 1, 8), self.text()))
+            .collect()
+    }
+
+    /// A valid step name
+    pub fn step_name(&mut self) -> String {
+        format!("s{}", self.string(NAME_CHARS, 0, 12))
+    }
+
+    /// A relative artifact path
+    pub fn path(&mut self) -> String {
+        let segments = self.rng.gen_range(1..4);
+        (0..segments)
+            .map(|_| self.string(NAME_CHARS, 1, 8))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn command(&mut self) -> Command {
+        let len = self.rng.gen_range(0..4);
+        Command::new((0..len).map(|_| self.text()).collect::<Vec<_>>())
+    }
+
+    fn artifacts(&mut self) -> BTreeMap<VirtualTargetPath, TargetDescription> {
+        let len = self.rng.gen_range(0..6);
+        (0..len)
+            .map(|_| {
+                let path = VirtualTargetPath::new(self.path()).expect("valid artifact path");
+                let mut sha256 = vec![0; 32];
+                self.rng.fill(&mut sha256[..]);
+                let mut description = TargetDescription::new();
+                description.insert(HashAlgorithm::Sha256, HashValue::new(sha256));
+                if self.rng.gen_bool(0.3) {
+                    let mut sha512 = vec![0; 64];
+                    self.rng.fill(&mut sha512[..]);
+                    description.insert(HashAlgorithm::Sha512, HashValue::new(sha512));
+                }
+                (path, description)
+            })
+            .collect()
+    }
+
+    /// An artifact rule of any type
+    pub fn rule(&mut self) -> ArtifactRule {
+        let typ = RULE_TYPES[self.rng.gen_range(0..RULE_TYPES.len())];
+        let mut rule = ArtifactRuleBuilder::new().rule(typ).pattern(&self.path());
+        if typ == "MATCH" {
+            if self.rng.gen_bool(0.5) {
+                rule = rule.in_source_path_prefix(&self.path());
+            }
+            if self.rng.gen_bool(0.5) {
+                rule = rule.in_destination_path_prefix(&self.path());
+            }
+            rule = match self.rng.gen_bool(0.5) {
+                true => rule.with_materials(),
+                false => rule.with_products(),
+            };
+            rule = rule.from_step(&self.step_name());
+        }
+        rule.build().expect("valid artifact rule")
+    }
+
+    fn rules(&mut self) -> Vec<ArtifactRule> {
+        let len = self.rng.gen_range(0..4);
+        (0..len).map(|_| self.rule()).collect()
+    }
+
+    /// A link with random artifacts, byproducts and environment
+    pub fn link(&mut self) -> LinkMetadata {
+        let byproducts = ByProducts::new()
+            .set_return_value(self.rng.gen())
+            .set_stdout(self.text())
+            .set_stderr(self.text())
+            .set_other_fields(self.map());
+        let env = match self.rng.gen_bool(0.5) {
+            true => Some(self.map()),
+            false => None,
+        };
+        LinkMetadataBuilder::new()
+            .name(self.step_name())
+            .materials(self.artifacts())
+            .products(self.artifacts())
+            .byproducts(byproducts)
+            .command(self.command())
+            .env(env)
+            .build()
+            .expect("valid link")
+    }
+
+    /// A layout with random steps and inspections, whose keys are derived
+    /// with `test_utils::key`
+    pub fn layout(&mut self) -> LayoutMetadata {
+        // second precision, as serialized
+        let expires = self.rng.gen_range(0..4_102_444_800);
+        let mut layout = LayoutMetadataBuilder::new()
+            .expires(DateTime::from_timestamp(expires, 0).expect("valid date"))
+            .readme(self.text());
+        let keys: Vec<PrivateKey> = (0..self.rng.gen_range(1..3))
+            .map(|_| key(&self.text()))
+            .collect();
+        for key in &keys {
+            layout = layout.add_key(key.public().clone());
+        }
+        for _ in 0..self.rng.gen_range(0..4) {
+            let key = &keys[self.rng.gen_range(0..keys.len())];
+            let step = Step::new(&self.step_name())
+                .threshold(1)
+                .add_key(key.key_id().clone())
+                .expected_command(self.command())
+                .expected_materials(self.rules())
+                .expected_products(self.rules());
+            layout = layout.add_step(step);
+        }
+        for _ in 0..self.rng.gen_range(0..3) {
+            let inspection = Inspection::new(&self.step_name())
+                .run(self.command())
+                .expected_materials(self.rules())
+                .expected_products(self.rules());
+            layout = layout.add_inspect(inspection);
+        }
+        layout.build().expect("valid layout")
+    }
+
+    /// A link or layout signed by one to three keys, returned along with
+    /// the metablock.
+    pub fn metablock(&mut self) -> (Metablock, Vec<PrivateKey>) {
+        let keys: Vec<PrivateKey> = (0..self.rng.gen_range(1..4))
+            .map(|i| key(&format!("{}{}", i, self.text())))
+            .collect();
+        let metadata: Box<dyn crate::models::Metadata> = match self.rng.gen_bool(0.5) {
+            true => Box::new(self.link()),
+            false => Box::new(self.layout()),
+        };
+        let metablock = MetablockBuilder::from_metadata(metadata)
+            .sign(&keys.iter().collect::<Vec<_>>())
+            .expect("signing succeeds")
+            .build();
+        (metablock, keys)
+    }
+}
+
+fn violation<T: Debug, U: Debug>(value: &T, what: &str, got: U) -> Error {
+    Error::Encoding(format!(
+        "round trip of {:?} broken, {} {:?}",
+        value, what, got
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_round_trip, check_wire_compatibility, Generator};
+
+    #[test]
+    fn random_metadata_round_trips() {
+        check_wire_compatibility(0, 64).unwrap();
+
+        // the same seed gives the same metadata
+        assert_eq!(Generator::new(7).link(), Generator::new(7).link());
+        assert_eq!(Generator::new(7).layout(), Generator::new(7).layout());
+        assert!(check_round_trip(&Generator::new(7).metablock().0).is_ok());
+    }
+}
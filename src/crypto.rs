@@ -1,6 +1,8 @@
 //! Cryptographic structures and functions.
 
-use data_encoding::HEXLOWER;
+use data_encoding::{
+    BASE64, BASE64URL, BASE64URL_NOPAD, BASE64_NOPAD, HEXLOWER, HEXLOWER_PERMISSIVE,
+};
 use derp::{self, Der, Tag};
 use ring::digest::{self, SHA256, SHA512};
 use ring::rand::SystemRandom;
@@ -14,17 +16,23 @@ use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::{self, Debug, Display};
+#[cfg(all(feature = "mmap", unix))]
+use std::fs::File;
 use std::hash;
 use std::io::{Read, Write};
 use std::process::{Command, Stdio};
-use std::str::FromStr;
+use std::str::{self, FromStr};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use untrusted::Input;
 
 use crate::error::Error;
 use crate::interchange::cjson::shims;
 use crate::Result;
 
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+
 const HASH_ALG_PREFS: &[HashAlgorithm] = &[HashAlgorithm::Sha512, HashAlgorithm::Sha256];
 
 /// 1.2.840.113549.1.1.1 rsaEncryption(PKCS #1)
@@ -65,9 +73,9 @@ fn python_sslib_compatibility_keyid_hash_algorithms() -> Option<Vec<String>> {
 /// let _ = map.insert(HashAlgorithm::Sha256, HashValue::new(vec![0x02, 0x03]));
 /// assert_eq!(hash_preference(&map).unwrap().0, &HashAlgorithm::Sha512);
 /// ```
-pub fn hash_preference<'a>(
-    hashes: &'a HashMap<HashAlgorithm, HashValue>,
-) -> Result<(&'static HashAlgorithm, &'a HashValue)> {
+pub fn hash_preference(
+    hashes: &HashMap<HashAlgorithm, HashValue>,
+) -> Result<(&'static HashAlgorithm, &HashValue)> {
     for alg in HASH_ALG_PREFS {
         match hashes.get(alg) {
             Some(v) => return Ok((alg, v)),
@@ -84,46 +92,136 @@ pub(crate) fn calculate_hash(data: &[u8], hash_alg: HashAlgorithm) -> HashValue
     HashValue::new(context.finish().as_ref().to_vec())
 }
 
-/// Calculate the size and hash digest from a given `Read`.
+/// The size of the chunks `calculate_hashes` reads.
+pub const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Calculate the size and hash digests from a given `Read`.
+///
+/// The reader is consumed in a single pass, each chunk read is fed to the
+/// digests of all of `hash_algs`, so a file is read once however many
+/// algorithms are asked for. Reads interrupted by a signal are retried.
 pub fn calculate_hashes<R: Read>(
+    read: R,
+    hash_algs: &[HashAlgorithm],
+) -> Result<(u64, HashMap<HashAlgorithm, HashValue>)> {
+    calculate_hashes_with_buffer(read, hash_algs, HASH_BUFFER_SIZE)
+}
+
+/// Calculate the size and hash digests from a given `Read` like
+/// `calculate_hashes`, reading chunks of `buffer_size` bytes.
+pub fn calculate_hashes_with_buffer<R: Read>(
+    read: R,
+    hash_algs: &[HashAlgorithm],
+    buffer_size: usize,
+) -> Result<(u64, HashMap<HashAlgorithm, HashValue>)> {
+    calculate_hashes_timed(read, hash_algs, buffer_size, None)
+}
+
+/// Like `calculate_hashes_with_buffer`, adding how long each algorithm took
+/// to `times` if given.
+pub(crate) fn calculate_hashes_timed<R: Read>(
     mut read: R,
     hash_algs: &[HashAlgorithm],
+    buffer_size: usize,
+    mut times: Option<&mut HashMap<HashAlgorithm, Duration>>,
 ) -> Result<(u64, HashMap<HashAlgorithm, HashValue>)> {
+    let mut hashes = digest_contexts(hash_algs)?;
+    let mut size = 0;
+    let mut buf = vec![0; buffer_size.max(1)];
+    loop {
+        match read.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read_bytes) => {
+                size += read_bytes as u64;
+                update_digests(&mut hashes, &buf[0..read_bytes], times.as_deref_mut());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((size, finish_digests(hashes)))
+}
+
+/// Files of at least this many bytes are hashed memory-mapped when
+/// recording with `RecordOptions::mmap_large_files`.
+pub const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Like `calculate_hashes_timed` for the file `file`, but if it has at
+/// least `MMAP_THRESHOLD` bytes it is mapped into memory and digested in
+/// chunks of `buffer_size` bytes rather than read, so it is not copied.
+/// Files that cannot be mapped, like some on FUSE, are read.
+///
+/// # Safety
+///
+/// `file` must not be truncated or written to while it is hashed. Reading
+/// a mapped page past the new end of a truncated file raises `SIGBUS`, and
+/// bytes changing under the slice they are read through is undefined
+/// behavior.
+#[cfg(all(feature = "mmap", unix))]
+pub(crate) unsafe fn calculate_mapped_file_hashes(
+    file: &File,
+    hash_algs: &[HashAlgorithm],
+    buffer_size: usize,
+    mut times: Option<&mut HashMap<HashAlgorithm, Duration>>,
+) -> Result<(u64, HashMap<HashAlgorithm, HashValue>)> {
+    let size = file.metadata()?.len();
+    if size >= MMAP_THRESHOLD {
+        // SAFETY: the caller keeps the file as it is while it is hashed
+        if let Ok(map) = unsafe { mmap::Mmap::map(file, size) } {
+            let mut hashes = digest_contexts(hash_algs)?;
+            for chunk in map.as_slice().chunks(buffer_size.max(1)) {
+                update_digests(&mut hashes, chunk, times.as_deref_mut());
+            }
+            return Ok((size, finish_digests(hashes)));
+        }
+    }
+    calculate_hashes_timed(file, hash_algs, buffer_size, times)
+}
+
+fn digest_contexts(
+    hash_algs: &[HashAlgorithm],
+) -> Result<HashMap<&HashAlgorithm, digest::Context>> {
     if hash_algs.is_empty() {
         return Err(Error::IllegalArgument(
             "Cannot provide empty set of hash algorithms".into(),
         ));
     }
-
-    let mut size = 0;
     let mut hashes = HashMap::new();
     for alg in hash_algs {
         let _ = hashes.insert(alg, alg.digest_context()?);
     }
+    Ok(hashes)
+}
 
-    let mut buf = vec![0; 1024];
-    loop {
-        match read.read(&mut buf) {
-            Ok(read_bytes) => {
-                if read_bytes == 0 {
-                    break;
-                }
-
-                size += read_bytes as u64;
-
-                for context in hashes.values_mut() {
-                    context.update(&buf[0..read_bytes]);
-                }
+/// Feed `chunk` to each of `hashes`, adding how long each took to `times`.
+fn update_digests(
+    hashes: &mut HashMap<&HashAlgorithm, digest::Context>,
+    chunk: &[u8],
+    times: Option<&mut HashMap<HashAlgorithm, Duration>>,
+) {
+    match times {
+        Some(times) => {
+            for (alg, context) in hashes.iter_mut() {
+                let start = Instant::now();
+                context.update(chunk);
+                *times.entry((*alg).clone()).or_default() += start.elapsed();
+            }
+        }
+        None => {
+            for context in hashes.values_mut() {
+                context.update(chunk);
             }
-            e @ Err(_) => e.map(|_| ())?,
         }
     }
+}
 
-    let hashes = hashes
+fn finish_digests(
+    mut hashes: HashMap<&HashAlgorithm, digest::Context>,
+) -> HashMap<HashAlgorithm, HashValue> {
+    hashes
         .drain()
         .map(|(k, v)| (k.clone(), HashValue::new(v.finish().as_ref().to_vec())))
-        .collect();
-    Ok((size, hashes))
+        .collect()
 }
 
 fn shim_public_key(
@@ -225,6 +323,22 @@ impl<'de> Deserialize<'de> for KeyId {
     }
 }
 
+impl Display for KeyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Formats a `PublicKey` fingerprint can be rendered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FingerprintFormat {
+    /// The hex encoded in-toto key ID.
+    KeyIdHex,
+    /// The OpenSSH style `SHA256:<base64>` digest of the key's SSH wire encoding,
+    /// as printed by `ssh-keygen -l`.
+    Sha256Base64,
+}
+
 /// Cryptographic signature schemes.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SignatureScheme {
@@ -241,6 +355,17 @@ pub enum SignatureScheme {
     Unknown(String),
 }
 
+impl Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SignatureScheme::Ed25519 => f.write_str("ed25519"),
+            SignatureScheme::RsaSsaPssSha256 => f.write_str("rsassa-pss-sha256"),
+            SignatureScheme::RsaSsaPssSha512 => f.write_str("rsassa-pss-sha512"),
+            SignatureScheme::Unknown(ref s) => f.write_str(s),
+        }
+    }
+}
+
 /// Wrapper type for the value of a cryptographic signature.
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SignatureValue(#[serde(with = "crate::format_hex")] Vec<u8>);
@@ -318,12 +443,12 @@ impl FromStr for KeyType {
     }
 }
 
-impl ToString for KeyType {
-    fn to_string(&self) -> String {
+impl Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            KeyType::Ed25519 => "ed25519".to_string(),
-            KeyType::Rsa => "rsa".to_string(),
-            KeyType::Unknown(ref s) => s.to_string(),
+            KeyType::Ed25519 => f.write_str("ed25519"),
+            KeyType::Rsa => f.write_str("rsa"),
+            KeyType::Unknown(ref s) => f.write_str(s),
         }
     }
 }
@@ -528,21 +653,21 @@ impl PrivateKey {
     /// Sign a message.
     pub fn sign(&self, msg: &[u8]) -> Result<Signature> {
         let value = match (&self.private, &self.public.scheme) {
-            (&PrivateKeyType::Rsa(ref rsa), &SignatureScheme::RsaSsaPssSha256) => {
+            (PrivateKeyType::Rsa(rsa), SignatureScheme::RsaSsaPssSha256) => {
                 let rng = SystemRandom::new();
                 let mut buf = vec![0; rsa.public_modulus_len()];
                 rsa.sign(&RSA_PSS_SHA256, &rng, msg, &mut buf)
                     .map_err(|_| Error::Opaque("Failed to sign message.".into()))?;
                 SignatureValue(buf)
             }
-            (&PrivateKeyType::Rsa(ref rsa), &SignatureScheme::RsaSsaPssSha512) => {
+            (PrivateKeyType::Rsa(rsa), SignatureScheme::RsaSsaPssSha512) => {
                 let rng = SystemRandom::new();
                 let mut buf = vec![0; rsa.public_modulus_len()];
                 rsa.sign(&RSA_PSS_SHA512, &rng, msg, &mut buf)
                     .map_err(|_| Error::Opaque("Failed to sign message.".into()))?;
                 SignatureValue(buf)
             }
-            (&PrivateKeyType::Ed25519(ref ed), &SignatureScheme::Ed25519) => {
+            (PrivateKeyType::Ed25519(ed), SignatureScheme::Ed25519) => {
                 SignatureValue(ed.sign(msg).as_ref().into())
             }
             (k, s) => {
@@ -553,15 +678,12 @@ impl PrivateKey {
             }
         };
 
-        Ok(Signature {
-            key_id: self.key_id().clone(),
-            value,
-        })
+        Ok(Signature::new(self.key_id().clone(), value))
     }
 
     fn rsa_gen() -> Result<Vec<u8>> {
         let gen = Command::new("openssl")
-            .args(&[
+            .args([
                 "genpkey",
                 "-algorithm",
                 "RSA",
@@ -575,7 +697,7 @@ impl PrivateKey {
             .output()?;
 
         let mut pk8 = Command::new("openssl")
-            .args(&[
+            .args([
                 "pkcs8", "-inform", "der", "-topk8", "-nocrypt", "-outform", "der",
             ])
             .stdin(Stdio::piped())
@@ -601,6 +723,106 @@ impl PrivateKey {
     }
 }
 
+/// A signer that delegates to an external command, for environments where
+/// signing has to go through approved tooling (e.g. `cosign sign-blob` or a
+/// corporate signing proxy).
+///
+/// The canonical payload is written to the command's stdin, and the command
+/// must print the signature on stdout, either hex or base64 encoded.
+///
+/// ```no_run
+/// # use std::str::FromStr;
+/// # use in_toto::crypto::{ExternalCommandSigner, KeyId};
+/// let key_id = KeyId::from_str(
+///     "e0294a3f17cc8563c3ed5fceb3bd8d3f6bfeeaca499b5c9572729ae015566554",
+/// ).unwrap();
+/// let signer = ExternalCommandSigner::new(
+///     "cosign",
+///     &["sign-blob", "--key", "cosign.key", "-"],
+///     key_id,
+/// );
+/// let signature = signer.sign(b"payload").unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ExternalCommandSigner {
+    program: String,
+    args: Vec<String>,
+    key_id: KeyId,
+    public: Option<PublicKey>,
+}
+
+impl ExternalCommandSigner {
+    /// Create a signer running `program` with `args`, whose signatures are
+    /// attributed to `key_id`.
+    pub fn new(program: &str, args: &[&str], key_id: KeyId) -> Self {
+        ExternalCommandSigner {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            key_id,
+            public: None,
+        }
+    }
+
+    /// Create a signer whose signatures are checked against `public` before
+    /// being returned, so a misconfigured command can't produce unusable metadata.
+    pub fn with_public_key(program: &str, args: &[&str], public: PublicKey) -> Self {
+        ExternalCommandSigner {
+            public: Some(public.clone()),
+            ..Self::new(program, args, public.key_id().clone())
+        }
+    }
+
+    /// An immutable reference to the key ID signatures are attributed to.
+    pub fn key_id(&self) -> &KeyId {
+        &self.key_id
+    }
+
+    /// Sign a message by piping it to the external command.
+    pub fn sign(&self, msg: &[u8]) -> Result<Signature> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                Error::Opaque(format!("Failed to spawn signer {}: {:?}", self.program, e))
+            })?;
+
+        match child.stdin.take() {
+            Some(mut stdin) => stdin.write_all(msg)?,
+            None => return Err(Error::Opaque("could not write to signer stdin".into())),
+        };
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::Opaque(format!(
+                "Signer {} exited with {}",
+                self.program, output.status
+            )));
+        }
+
+        // Tools commonly wrap base64 output, so ignore any whitespace.
+        let encoded = str::from_utf8(&output.stdout)?
+            .split_whitespace()
+            .collect::<String>();
+        let value = HEXLOWER
+            .decode(encoded.as_bytes())
+            .or_else(|_| BASE64.decode(encoded.as_bytes()))
+            .map_err(|_| {
+                Error::Encoding(format!(
+                    "Signer {} output is neither hex nor base64",
+                    self.program
+                ))
+            })?;
+
+        let signature = Signature::new(self.key_id.clone(), SignatureValue(value));
+        if let Some(public) = &self.public {
+            public.verify(msg, &signature)?;
+        }
+        Ok(signature)
+    }
+}
+
 /// A structure containing information about a public key.
 #[derive(Clone, Debug)]
 pub struct PublicKey {
@@ -722,6 +944,50 @@ impl PublicKey {
         &self.value.0
     }
 
+    /// Render a fingerprint of this key in the given format.
+    ///
+    /// ```
+    /// # use in_toto::crypto::{FingerprintFormat, PublicKey};
+    /// const ED25519_1_PUB_KEY: &[u8] = include_bytes!("../tests/ed25519/ed25519-1.pub");
+    /// let key = PublicKey::from_ed25519(ED25519_1_PUB_KEY).unwrap();
+    /// assert_eq!(
+    ///     key.fingerprint(FingerprintFormat::Sha256Base64).unwrap(),
+    ///     "SHA256:2Rg69fou6kgZYLH6DkbKoq+fmlLmsg/6CEYTYRxFJ9U"
+    /// );
+    /// ```
+    pub fn fingerprint(&self, format: FingerprintFormat) -> Result<String> {
+        match format {
+            FingerprintFormat::KeyIdHex => Ok(self.key_id.to_string()),
+            FingerprintFormat::Sha256Base64 => {
+                let mut context = digest::Context::new(&SHA256);
+                context.update(&self.ssh_wire_format()?);
+                Ok(format!(
+                    "SHA256:{}",
+                    BASE64_NOPAD.encode(context.finish().as_ref())
+                ))
+            }
+        }
+    }
+
+    /// Encode the key as an SSH public key blob (RFC 4253 and RFC 8709).
+    fn ssh_wire_format(&self) -> Result<Vec<u8>> {
+        let mut blob = Vec::new();
+        match self.typ {
+            KeyType::Ed25519 => {
+                write_ssh_string(&mut blob, b"ssh-ed25519");
+                write_ssh_string(&mut blob, &self.value.0);
+            }
+            KeyType::Rsa => {
+                let (n, e) = read_pkcs1(&self.value.0)?;
+                write_ssh_string(&mut blob, b"ssh-rsa");
+                write_ssh_mpint(&mut blob, &e);
+                write_ssh_mpint(&mut blob, &n);
+            }
+            KeyType::Unknown(ref s) => return Err(Error::UnknownKeyType(s.clone())),
+        }
+        Ok(blob)
+    }
+
     /// Use this key to verify a message with a signature.
     pub fn verify(&self, msg: &[u8], sig: &Signature) -> Result<()> {
         let alg: &dyn ring::signature::VerificationAlgorithm = match self.scheme {
@@ -754,6 +1020,15 @@ impl PartialEq for PublicKey {
 
 impl Eq for PublicKey {}
 
+impl Display for PublicKey {
+    /// Summarize the key as `<keytype>/<scheme> <keyid>`. In-toto keys carry
+    /// no creation time, so unlike OpenPGP or SSH certificate summaries there
+    /// is none to show.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{} {}", self.typ, self.scheme, self.key_id)
+    }
+}
+
 impl Ord for PublicKey {
     fn cmp(&self, other: &Self) -> Ordering {
         self.key_id.cmp(&other.key_id)
@@ -762,7 +1037,7 @@ impl Ord for PublicKey {
 
 impl PartialOrd for PublicKey {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.key_id.cmp(&other.key_id))
+        Some(self.cmp(other))
     }
 }
 
@@ -872,9 +1147,38 @@ pub struct Signature {
     key_id: KeyId,
     #[serde(rename = "sig")]
     value: SignatureValue,
+    #[serde(rename = "signer-id", default, skip_serializing_if = "Option::is_none")]
+    signer_id: Option<String>,
 }
 
 impl Signature {
+    /// Create a new `Signature` of the key `key_id` from its value.
+    ///
+    /// Note: It is unlikely that you ever want to do this manually.
+    pub fn new(key_id: KeyId, value: SignatureValue) -> Self {
+        Signature {
+            key_id,
+            value,
+            signer_id: None,
+        }
+    }
+
+    /// This signature annotated with who made it, e.g. `alice@corp / job
+    /// 1234`, for humans reading verification output.
+    ///
+    /// The annotation is not covered by any signature, so it is a hint
+    /// anyone can change and must not be relied on.
+    pub fn with_signer_id(mut self, signer_id: &str) -> Self {
+        self.signer_id = Some(signer_id.to_string());
+        self
+    }
+
+    /// Who made the signature according to its unauthenticated annotation,
+    /// if any
+    pub fn signer_id(&self) -> Option<&str> {
+        self.signer_id.as_deref()
+    }
+
     /// An immutable reference to the `KeyId` of the key that produced the signature.
     pub fn key_id(&self) -> &KeyId {
         &self.key_id
@@ -887,19 +1191,27 @@ impl Signature {
 }
 
 /// The available hash algorithms.
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum HashAlgorithm {
     /// SHA256 as describe in [RFC-6234](https://tools.ietf.org/html/rfc6234)
-    #[serde(rename = "sha256")]
     Sha256,
     /// SHA512 as describe in [RFC-6234](https://tools.ietf.org/html/rfc6234)
-    #[serde(rename = "sha512")]
     Sha512,
-    /// Placeholder for an unknown hash algorithm.
+    /// Placeholder for an unknown hash algorithm, or a custom field of a
+    /// `TargetDescription`, kept by its name.
     Unknown(String),
 }
 
 impl HashAlgorithm {
+    /// The name of the algorithm in metadata
+    pub fn name(&self) -> &str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Unknown(name) => name,
+        }
+    }
+
     /// Create a new `digest::Context` suitable for computing the hash of some data using this hash
     /// algorithm.
     pub(crate) fn digest_context(&self) -> Result<digest::Context> {
@@ -920,9 +1232,86 @@ impl HashAlgorithm {
     }
 }
 
+impl Serialize for HashAlgorithm {
+    fn serialize<S: Serializer>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error> {
+        ser.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for HashAlgorithm {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> ::std::result::Result<Self, D::Error> {
+        let name: String = Deserialize::deserialize(de)?;
+        Ok(match name.as_str() {
+            "sha256" => HashAlgorithm::Sha256,
+            "sha512" => HashAlgorithm::Sha512,
+            _ => HashAlgorithm::Unknown(name),
+        })
+    }
+}
+
+/// An encoding of digests in metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestEncoding {
+    /// Hex, as the specification requires. Upper case is accepted on decoding.
+    Hex,
+    /// Standard or URL-safe base64, with or without padding, as used by some
+    /// attestation producers
+    Base64,
+}
+
+impl DigestEncoding {
+    /// The encodings accepted when parsing metadata, in the order they are
+    /// tried
+    pub const ACCEPTED: [DigestEncoding; 2] = [DigestEncoding::Hex, DigestEncoding::Base64];
+
+    /// Decode the digest `encoded`.
+    pub fn decode(&self, encoded: &str) -> Result<Vec<u8>> {
+        let bytes = encoded.as_bytes();
+        let decoded = match self {
+            DigestEncoding::Hex => HEXLOWER_PERMISSIVE.decode(bytes),
+            DigestEncoding::Base64 => BASE64
+                .decode(bytes)
+                .or_else(|_| BASE64_NOPAD.decode(bytes))
+                .or_else(|_| BASE64URL.decode(bytes))
+                .or_else(|_| BASE64URL_NOPAD.decode(bytes)),
+        };
+        decoded.map_err(|e| Error::Encoding(format!("digest {:?}: {}", encoded, e)))
+    }
+
+    /// Encode the digest `bytes`.
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            DigestEncoding::Hex => HEXLOWER.encode(bytes),
+            DigestEncoding::Base64 => BASE64.encode(bytes),
+        }
+    }
+}
+
 /// Wrapper for the value of a hash digest.
+///
+/// Digests are written as lower case hex. On parsing, base64 digests are
+/// accepted as well and normalized. Hex is tried first: digests are at
+/// least 32 bytes, whose base64 encoding is hex only by a vanishing chance.
+/// Only attestations benefit from this, as signatures of DSSE envelopes
+/// cover the payload as is; the signature of a link with base64 digests is
+/// checked over the hex encoded digests, and fails.
 #[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct HashValue(#[serde(with = "crate::format_hex")] Vec<u8>);
+pub struct HashValue(
+    #[serde(
+        serialize_with = "crate::format_hex::serialize",
+        deserialize_with = "deserialize_digest"
+    )]
+    Vec<u8>,
+);
+
+fn deserialize_digest<'de, D: Deserializer<'de>>(
+    de: D,
+) -> ::std::result::Result<Vec<u8>, D::Error> {
+    let encoded: String = Deserialize::deserialize(de)?;
+    HashValue::decode(&encoded, &DigestEncoding::ACCEPTED)
+        .map(|digest| digest.0)
+        .map_err(|e| DeserializeError::custom(format!("{:?}", e)))
+}
 
 impl HashValue {
     /// Create a new `HashValue` from the given digest bytes.
@@ -930,6 +1319,23 @@ impl HashValue {
         HashValue(bytes)
     }
 
+    /// Decode `encoded` with the first of `encodings` that can.
+    pub fn decode(encoded: &str, encodings: &[DigestEncoding]) -> Result<Self> {
+        let mut error = Error::Encoding(format!("digest {:?}: no encoding accepted", encoded));
+        for encoding in encodings {
+            match encoding.decode(encoded) {
+                Ok(bytes) => return Ok(HashValue(bytes)),
+                Err(e) => error = e,
+            }
+        }
+        Err(error)
+    }
+
+    /// The digest encoded with `encoding`
+    pub fn encode(&self, encoding: DigestEncoding) -> String {
+        encoding.encode(&self.0)
+    }
+
     /// An immutable reference to the bytes of the hash value.
     pub fn value(&self) -> &[u8] {
         &self.0
@@ -1002,6 +1408,38 @@ fn extract_rsa_pub_from_pkcs8(der_key: &[u8]) -> ::std::result::Result<Vec<u8>,
     })
 }
 
+fn read_pkcs1(der_key: &[u8]) -> ::std::result::Result<(Vec<u8>, Vec<u8>), derp::Error> {
+    let input = Input::from(der_key);
+    input.read_all(derp::Error::Read, |input| {
+        derp::nested(input, Tag::Sequence, |input| {
+            let n = derp::positive_integer(input)?;
+            let e = derp::positive_integer(input)?;
+            Ok((
+                n.as_slice_less_safe().to_vec(),
+                e.as_slice_less_safe().to_vec(),
+            ))
+        })
+    })
+}
+
+fn write_ssh_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend(&(data.len() as u32).to_be_bytes());
+    buf.extend(data);
+}
+
+fn write_ssh_mpint(buf: &mut Vec<u8>, data: &[u8]) {
+    let start = data.iter().position(|b| *b != 0).unwrap_or(data.len());
+    let data = &data[start..];
+    match data.first() {
+        Some(b) if b & 0x80 != 0 => {
+            buf.extend(&((data.len() + 1) as u32).to_be_bytes());
+            buf.push(0);
+            buf.extend(data);
+        }
+        _ => write_ssh_string(buf, data),
+    }
+}
+
 fn write_pkcs1(n: &[u8], e: &[u8]) -> ::std::result::Result<Vec<u8>, derp::Error> {
     let mut output = Vec::new();
     {
@@ -1016,6 +1454,7 @@ fn write_pkcs1(n: &[u8], e: &[u8]) -> ::std::result::Result<Vec<u8>, derp::Error
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes, clippy::manual_hash_one)]
 mod test {
     use super::*;
     use pretty_assertions::assert_eq;
@@ -1038,6 +1477,50 @@ mod test {
     const DEMO_KEY_ID: &str = "556caebdc0877eed53d419b60eddb1e57fa773e4e31d70698b588f3e9cc48b35";
     const DEMO_PUBLIC_KEY: &'static [u8] = include_bytes!("../tests/rsa/alice.pub");
 
+    /// Counts the reads of its bytes, interrupted once before the first.
+    struct CountingReader<'a> {
+        bytes: &'a [u8],
+        reads: usize,
+        interrupted: bool,
+        ended: bool,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            assert!(!self.ended, "read again after the end");
+            self.reads += 1;
+            let read = self.bytes.read(buf)?;
+            self.ended = read == 0;
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn calculate_hashes_in_one_pass() {
+        let content = vec![7; HASH_BUFFER_SIZE * 2 + 1];
+        let mut reader = CountingReader {
+            bytes: &content,
+            reads: 0,
+            interrupted: false,
+            ended: false,
+        };
+        let algorithms = [HashAlgorithm::Sha256, HashAlgorithm::Sha512];
+        let (size, hashes) = calculate_hashes(&mut reader, &algorithms).unwrap();
+        assert_eq!(size, content.len() as u64);
+        // three chunks and the end
+        assert_eq!(reader.reads, 4);
+        for algorithm in algorithms {
+            assert_eq!(
+                hashes[&algorithm],
+                calculate_hash(&content, algorithm.clone())
+            );
+        }
+    }
+
     #[test]
     fn parse_public_rsa_2048_spki() {
         let key = PublicKey::from_spki(RSA_2048_SPKI, SignatureScheme::RsaSsaPssSha256).unwrap();
@@ -1464,6 +1947,95 @@ mod test {
         assert_ne!(hasher256.finish(), hasher512.finish());
     }
 
+    #[test]
+    fn fingerprint_ed25519() {
+        let key = PublicKey::from_ed25519(ED25519_1_PUBLIC_KEY).unwrap();
+        assert_eq!(
+            key.fingerprint(FingerprintFormat::KeyIdHex).unwrap(),
+            key.key_id().to_string()
+        );
+        assert_eq!(
+            key.fingerprint(FingerprintFormat::Sha256Base64).unwrap(),
+            format!(
+                "SHA256:{}",
+                BASE64_NOPAD.encode(
+                    calculate_hash(&key.ssh_wire_format().unwrap(), HashAlgorithm::Sha256).value()
+                )
+            )
+        );
+        assert_eq!(
+            key.fingerprint(FingerprintFormat::Sha256Base64).unwrap(),
+            "SHA256:2Rg69fou6kgZYLH6DkbKoq+fmlLmsg/6CEYTYRxFJ9U"
+        );
+    }
+
+    #[test]
+    fn fingerprint_rsa() {
+        // expected value generated by `ssh-keygen -lf` on the same key
+        let key = PublicKey::from_spki(RSA_2048_SPKI, SignatureScheme::RsaSsaPssSha256).unwrap();
+        assert_eq!(
+            key.fingerprint(FingerprintFormat::Sha256Base64).unwrap(),
+            "SHA256:RhG7/whp4AalhopZMYb3Eoh5DGj8SyTd/Myy93AzBZI"
+        );
+    }
+
+    #[test]
+    fn display_public_key() {
+        let key = PublicKey::from_spki(RSA_2048_SPKI, SignatureScheme::RsaSsaPssSha256).unwrap();
+        assert_eq!(
+            key.to_string(),
+            format!("rsa/rsassa-pss-sha256 {}", key.key_id())
+        );
+    }
+
+    #[test]
+    fn external_command_signer_hex_output() {
+        let key_id = PublicKey::from_ed25519(ED25519_1_PUBLIC_KEY)
+            .unwrap()
+            .key_id()
+            .clone();
+        let signer =
+            ExternalCommandSigner::new("sh", &["-c", "cat > /dev/null; echo deadbeef"], key_id);
+        let sig = signer.sign(b"payload").unwrap();
+        assert_eq!(sig.key_id(), signer.key_id());
+        assert_eq!(sig.value().as_bytes(), &[0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn external_command_signer_verified() {
+        let public = PublicKey::from_spki(RSA_2048_SPKI, SignatureScheme::RsaSsaPssSha256).unwrap();
+        let signer = ExternalCommandSigner::with_public_key(
+            "sh",
+            &[
+                "-c",
+                "openssl dgst -sha256 -sigopt rsa_padding_mode:pss -sigopt rsa_pss_saltlen:32 \
+                 -keyform DER -sign tests/rsa/rsa-2048.pk8.der | base64",
+            ],
+            public.clone(),
+        );
+        let sig = signer.sign(b"payload").unwrap();
+        assert!(public.verify(b"payload", &sig).is_ok());
+
+        let bogus = ExternalCommandSigner::with_public_key(
+            "sh",
+            &["-c", "cat > /dev/null; echo deadbeef"],
+            public,
+        );
+        assert_eq!(bogus.sign(b"payload"), Err(Error::BadSignature));
+    }
+
+    #[test]
+    fn external_command_signer_failure() {
+        let key_id = PublicKey::from_ed25519(ED25519_1_PUBLIC_KEY)
+            .unwrap()
+            .key_id()
+            .clone();
+        let signer = ExternalCommandSigner::new("sh", &["-c", "exit 1"], key_id.clone());
+        assert!(signer.sign(b"payload").is_err());
+        let signer = ExternalCommandSigner::new("command-does-not-exist", &[], key_id);
+        assert!(signer.sign(b"payload").is_err());
+    }
+
     #[test]
     fn compatibility_with_python_in_toto() {
         let der = pem::parse(DEMO_PUBLIC_KEY)
@@ -1473,4 +2045,32 @@ mod test {
             .expect("create PublicKey failed");
         assert_eq!(key.key_id.0, DEMO_KEY_ID);
     }
+
+    #[test]
+    fn decode_base64_digests() {
+        // python3 -c 'import hashlib; print(hashlib.sha256(b"in-toto").hexdigest())'
+        let hex = "218b19e828c138ea1d9262d4a48b57db65f23baeacdac18e42ac9bcfea28f27c";
+        let expected = HashValue::new(HEXLOWER.decode(hex.as_bytes()).unwrap());
+        for encoded in [
+            hex,
+            "218B19E828C138EA1D9262D4A48B57DB65F23BAEACDAC18E42AC9BCFEA28F27C",
+            "IYsZ6CjBOOodkmLUpItX22XyO66s2sGOQqybz+oo8nw=",
+            "IYsZ6CjBOOodkmLUpItX22XyO66s2sGOQqybz-oo8nw",
+        ] {
+            let parsed: HashValue = serde_json::from_value(json!(encoded)).unwrap();
+            assert_eq!(parsed, expected, "{}", encoded);
+        }
+        // normalized to hex
+        assert_eq!(serde_json::to_value(&expected).unwrap(), json!(hex));
+        assert_eq!(
+            expected.encode(DigestEncoding::Base64),
+            "IYsZ6CjBOOodkmLUpItX22XyO66s2sGOQqybz+oo8nw="
+        );
+        assert!(HashValue::decode(
+            "IYsZ6CjBOOodkmLUpItX22XyO66s2sGOQqybz+oo8nw=",
+            &[DigestEncoding::Hex]
+        )
+        .is_err());
+        assert!(serde_json::from_value::<HashValue>(json!("not a digest!")).is_err());
+    }
 }
@@ -0,0 +1,106 @@
+//! Read-only memory maps of files hashed, see `calculate_mapped_file_hashes`.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+/// A file mapped into memory, read-only and private.
+pub(super) struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    /// Map the first `len` bytes of `file`, which must not be empty.
+    ///
+    /// # Safety
+    ///
+    /// `file` must not be truncated or written to while it is mapped, or
+    /// reading `as_slice` raises `SIGBUS` or sees its bytes change.
+    pub(super) unsafe fn map(file: &File, len: u64) -> io::Result<Self> {
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| *len > 0)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "cannot map file"))?;
+        // SAFETY: a new mapping is made of an open file, it aliases no memory
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the advice only concerns the mapping just made
+        unsafe {
+            // only a hint, mapping works without it
+            libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    /// The bytes mapped
+    pub(super) fn as_slice(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` bytes long and readable until dropped,
+        // and `map` requires the file not to change meanwhile
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is not used after, `as_slice` borrows `self`
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+// the mapping is read-only, so it may be read from any thread
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::fs::File;
+
+    use super::Mmap;
+    use crate::crypto::{
+        calculate_hashes, calculate_mapped_file_hashes, HashAlgorithm, HASH_BUFFER_SIZE,
+        MMAP_THRESHOLD,
+    };
+
+    #[test]
+    fn hash_mapped_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large");
+        let file = File::create(&path).unwrap();
+        file.set_len(MMAP_THRESHOLD).unwrap();
+        drop(file);
+        let file = File::open(&path).unwrap();
+
+        // SAFETY: nothing else knows of the file
+        let map = unsafe { Mmap::map(&file, MMAP_THRESHOLD) }.unwrap();
+        assert_eq!(map.as_slice().len() as u64, MMAP_THRESHOLD);
+        assert!(unsafe { Mmap::map(&file, 0) }.is_err());
+
+        let algorithms = [HashAlgorithm::Sha256, HashAlgorithm::Sha512];
+        let mut times = HashMap::new();
+        let mapped = unsafe {
+            calculate_mapped_file_hashes(&file, &algorithms, HASH_BUFFER_SIZE, Some(&mut times))
+        }
+        .unwrap();
+        let read = calculate_hashes(File::open(&path).unwrap(), &algorithms).unwrap();
+        assert_eq!(mapped, read);
+        assert_eq!(times.len(), 2);
+    }
+}
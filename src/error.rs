@@ -2,10 +2,12 @@
 
 use data_encoding::DecodeError;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str;
 use thiserror::Error;
 
+use crate::models::byproducts::ByProducts;
+
 /// Error type for all in-toto related errors.
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum Error {
@@ -64,6 +66,19 @@ pub enum Error {
     #[error("runlib failed: {0}")]
     RunLibError(String),
 
+    /// The command of a step exited with a non-zero return value, while run
+    /// with `runlib::ExitPolicy::FailFast`. Holds what the command left.
+    #[error("command failed with return value {}", .0.return_value())]
+    CommandFailed(Box<ByProducts>),
+
+    /// A file path to record is not valid UTF-8, so a link cannot hold it.
+    #[error("non-UTF-8 path {}", .0.display())]
+    NonUtf8Path(PathBuf),
+
+    /// Verification or a command did not finish before its deadline.
+    #[error("deadline exceeded: {0}")]
+    Timeout(String),
+
     #[error("attestation state and predicate version dismatch: {0} and {1}")]
     AttestationFormatDismatch(String, String),
 
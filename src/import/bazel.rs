@@ -0,0 +1,255 @@
+//! Links from Bazel execution logs.
+//!
+//! Bazel writes every spawned action, with the exact digests of its inputs
+//! and outputs, into the log given by `--execution_log_json_file`. The log is
+//! a stream of JSON `SpawnExec` messages, which are grouped by the label of
+//! the target they were run for into one link per target:
+//!
+//! * the materials are the inputs of the target's actions, except those
+//!   produced by another action of the same target,
+//! * the products are the outputs of the target's actions,
+//! * the command is the one of the action if the target has a single one,
+//!   the mnemonics of all actions are kept in the byproducts field
+//!   `mnemonics`, and the return value is the first non-zero exit code.
+//!
+//! The links are returned by target label, e.g. `//main:hello`, and named
+//! after it with `sanitize_step_name`, e.g. `main_hello`. They may be
+//! renamed with `LinkMetadataBuilder::from_metadata` before signing.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use data_encoding::HEXLOWER_PERMISSIVE;
+use log::debug;
+use serde_derive::Deserialize;
+
+use crate::crypto::{HashAlgorithm, HashValue};
+use crate::models::byproducts::ByProducts;
+use crate::models::step::Command;
+use crate::models::{
+    sanitize_step_name, LinkMetadata, LinkMetadataBuilder, TargetDescription, VirtualTargetPath,
+};
+use crate::Result;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Digest {
+    #[serde(default)]
+    hash: String,
+    #[serde(default)]
+    hash_function_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct File {
+    path: String,
+    #[serde(default)]
+    digest: Option<Digest>,
+    #[serde(default)]
+    is_tool: bool,
+}
+
+/// A single action spawned by Bazel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpawnExec {
+    #[serde(default)]
+    command_args: Vec<String>,
+    #[serde(default)]
+    inputs: Vec<File>,
+    #[serde(default)]
+    actual_outputs: Vec<File>,
+    #[serde(default)]
+    mnemonic: String,
+    #[serde(default)]
+    target_label: String,
+    #[serde(default)]
+    exit_code: i32,
+}
+
+/// A parsed Bazel execution log.
+#[derive(Debug, Clone)]
+pub struct ExecutionLog {
+    spawns: Vec<SpawnExec>,
+}
+
+impl ExecutionLog {
+    /// Parse the JSON execution log read from `reader`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let spawns = serde_json::Deserializer::from_reader(reader)
+            .into_iter::<SpawnExec>()
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(ExecutionLog { spawns })
+    }
+
+    /// The number of actions in the log
+    pub fn len(&self) -> usize {
+        self.spawns.len()
+    }
+
+    /// Whether the log has no actions
+    pub fn is_empty(&self) -> bool {
+        self.spawns.is_empty()
+    }
+
+    /// The labels of all targets actions were run for
+    pub fn targets(&self) -> BTreeSet<&str> {
+        self.spawns
+            .iter()
+            .map(|spawn| spawn.target_label.as_str())
+            .filter(|label| !label.is_empty())
+            .collect()
+    }
+
+    /// One link per target, by target label. Inputs marked as tools, such
+    /// as compilers, are only recorded as materials if `include_tools`.
+    /// Actions without target and files without digest are left out.
+    pub fn links(&self, include_tools: bool) -> Result<BTreeMap<String, LinkMetadata>> {
+        let mut by_target: BTreeMap<&str, Vec<&SpawnExec>> = BTreeMap::new();
+        for spawn in &self.spawns {
+            if spawn.target_label.is_empty() {
+                debug!("Skipping {} action without target", spawn.mnemonic);
+                continue;
+            }
+            by_target
+                .entry(spawn.target_label.as_str())
+                .or_default()
+                .push(spawn);
+        }
+
+        let mut links = BTreeMap::new();
+        for (label, spawns) in by_target {
+            let mut products = BTreeMap::new();
+            for output in spawns.iter().flat_map(|s| &s.actual_outputs) {
+                if let Some((path, hashes)) = artifact(output)? {
+                    products.insert(path, hashes);
+                }
+            }
+            let mut materials = BTreeMap::new();
+            for input in spawns.iter().flat_map(|s| &s.inputs) {
+                if input.is_tool && !include_tools {
+                    continue;
+                }
+                if let Some((path, hashes)) = artifact(input)? {
+                    if !products.contains_key(&path) {
+                        materials.insert(path, hashes);
+                    }
+                }
+            }
+
+            let command = match spawns.as_slice() {
+                [spawn] => Command::new(spawn.command_args.iter().cloned()),
+                _ => Command::default(),
+            };
+            let mnemonics: Vec<&str> = spawns.iter().map(|s| s.mnemonic.as_str()).collect();
+            let return_value = spawns
+                .iter()
+                .map(|s| s.exit_code)
+                .find(|code| *code != 0)
+                .unwrap_or(0);
+            let byproducts = ByProducts::new()
+                .set_return_value(return_value)
+                .set_other_field("mnemonics".into(), mnemonics.join(","));
+
+            let link = LinkMetadataBuilder::new()
+                .name(sanitize_step_name(label))
+                .materials(materials)
+                .products(products)
+                .command(command)
+                .byproducts(byproducts)
+                .build()?;
+            links.insert(label.to_string(), link);
+        }
+        Ok(links)
+    }
+}
+
+/// The path and digest Bazel recorded for `file`, if it has a digest.
+fn artifact(file: &File) -> Result<Option<(VirtualTargetPath, TargetDescription)>> {
+    let digest = match &file.digest {
+        Some(digest) if !digest.hash.is_empty() => digest,
+        _ => {
+            debug!("Skipping {} without digest", file.path);
+            return Ok(None);
+        }
+    };
+    let algorithm = match digest.hash_function_name.as_str() {
+        // older logs leave out the default function
+        "SHA-256" | "" => HashAlgorithm::Sha256,
+        "SHA-512" => HashAlgorithm::Sha512,
+        other => HashAlgorithm::Unknown(other.to_lowercase()),
+    };
+    let hash = HashValue::new(HEXLOWER_PERMISSIVE.decode(digest.hash.as_bytes())?);
+    let hashes = vec![(algorithm, hash)].into_iter().collect();
+    Ok(Some((VirtualTargetPath::new(file.path.clone())?, hashes)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExecutionLog;
+    use crate::models::VirtualTargetPath;
+
+    const LOG: &str = r#"{
+  "commandArgs": ["external/cc/gcc", "-c", "main/hello.cc", "-o", "bazel-out/k8-fastbuild/bin/main/_objs/hello/hello.o"],
+  "inputs": [
+    {"path": "external/cc/gcc", "digest": {"hash": "aa", "sizeBytes": "1", "hashFunctionName": "SHA-256"}, "isTool": true},
+    {"path": "main/hello.cc", "digest": {"hash": "bb", "sizeBytes": "1", "hashFunctionName": "SHA-256"}}
+  ],
+  "actualOutputs": [{"path": "bazel-out/k8-fastbuild/bin/main/_objs/hello/hello.o", "digest": {"hash": "cc", "sizeBytes": "1", "hashFunctionName": "SHA-256"}}],
+  "mnemonic": "CppCompile",
+  "targetLabel": "//main:hello"
+}
+{
+  "commandArgs": ["external/cc/gcc", "-o", "bazel-out/k8-fastbuild/bin/main/hello", "bazel-out/k8-fastbuild/bin/main/_objs/hello/hello.o"],
+  "inputs": [
+    {"path": "bazel-out/k8-fastbuild/bin/main/_objs/hello/hello.o", "digest": {"hash": "cc", "sizeBytes": "1", "hashFunctionName": "SHA-256"}},
+    {"path": "bazel-out/volatile-status.txt"}
+  ],
+  "actualOutputs": [{"path": "bazel-out/k8-fastbuild/bin/main/hello", "digest": {"hash": "dd", "sizeBytes": "1", "hashFunctionName": "SHA-256"}}],
+  "mnemonic": "CppLink",
+  "targetLabel": "//main:hello",
+  "exitCode": 1
+}
+{"commandArgs": ["touch", "out"], "mnemonic": "Genrule", "targetLabel": "//gen:out", "actualOutputs": [{"path": "bazel-out/gen/out", "digest": {"hash": "ee"}}]}
+{"mnemonic": "BazelWorkspaceStatusAction"}"#;
+
+    #[test]
+    fn import_execution_log() {
+        let log = ExecutionLog::from_reader(LOG.as_bytes()).unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(
+            log.targets().into_iter().collect::<Vec<_>>(),
+            ["//gen:out", "//main:hello"]
+        );
+
+        let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+        let links = log.links(false).unwrap();
+        assert_eq!(links.len(), 2);
+        let hello = &links["//main:hello"];
+        assert_eq!(hello.name(), "main_hello");
+        // the object file is built and linked within the target, the
+        // compiler is a tool and the status file has no digest
+        assert_eq!(
+            hello.materials().keys().collect::<Vec<_>>(),
+            [&path("main/hello.cc")]
+        );
+        assert_eq!(hello.products().len(), 2);
+        assert!(hello.command().is_empty());
+        assert_eq!(hello.byproducts().return_value(), 1);
+        assert_eq!(
+            hello.byproducts().other_fields()["mnemonics"],
+            "CppCompile,CppLink"
+        );
+
+        let out = &links["//gen:out"];
+        assert_eq!(out.command().argv(), ["touch", "out"]);
+        assert!(out.materials().is_empty());
+        assert!(out.products().contains_key(&path("bazel-out/gen/out")));
+
+        let links = log.links(true).unwrap();
+        assert!(links["//main:hello"]
+            .materials()
+            .contains_key(&path("external/cc/gcc")));
+    }
+}
@@ -0,0 +1,290 @@
+//! Materials from the dependency files of Make and Ninja builds.
+//!
+//! Compilers write the files they actually read into depfiles, e.g. with
+//! `gcc -MD`, and Ninja collects these into its deps log `.ninja_deps`.
+//! Recording only these files as the materials of a step keeps its link far
+//! smaller than hashing the whole source tree.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::runlib::record_artifacts;
+use crate::{Error, Result};
+
+/// Magic the deps log of Ninja starts with.
+const NINJA_DEPS_MAGIC: &[u8] = b"# ninjadeps\n";
+
+/// Flag in the record header marking a deps record, the rest is the size.
+const NINJA_DEPS_RECORD: u32 = 0x8000_0000;
+
+/// Parse the Make rules of a depfile into the prerequisites of each target.
+///
+/// Line continuations, escaped spaces and `$$` are unescaped, rules without
+/// prerequisites, such as the ones written by `gcc -MP`, are kept with an
+/// empty set.
+pub fn parse_depfile(contents: &str) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let mut rules: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in contents.lines() {
+        let words = split_words(line);
+        if words.is_empty() {
+            continue;
+        }
+        let colon = words
+            .iter()
+            .position(|word| word.ends_with(':'))
+            .ok_or_else(|| Error::Encoding(format!("depfile rule without target: {}", line)))?;
+        let mut targets = words[..=colon].to_vec();
+        targets[colon].pop();
+        for target in targets.into_iter().filter(|t| !t.is_empty()) {
+            rules
+                .entry(target)
+                .or_default()
+                .extend(words[colon + 1..].iter().cloned());
+        }
+    }
+    Ok(rules)
+}
+
+/// Split a depfile line into unescaped words, up to a comment.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#')) => {
+                word.push(chars.next().unwrap_or_default())
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                word.push('$');
+            }
+            '#' => break,
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            // `a.o:b.c` is a rule, a drive letter as in `C:\` is not
+            ':' if chars.peek().is_none_or(|next| next.is_whitespace()) || word.len() != 1 => {
+                word.push(':');
+                words.push(std::mem::take(&mut word));
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The dependencies Ninja discovered while building, as kept in its deps
+/// log `.ninja_deps`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NinjaDepsLog {
+    deps: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl NinjaDepsLog {
+    /// Parse the binary deps log of version 3 or 4 read from `reader`.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let invalid = |what: &str| Error::Encoding(format!("invalid ninja deps log: {}", what));
+
+        let rest = bytes
+            .strip_prefix(NINJA_DEPS_MAGIC)
+            .ok_or_else(|| invalid("no deps log"))?;
+        let (version, mut rest) = split_u32(rest).ok_or_else(|| invalid("no version"))?;
+        let mtime_words = match version {
+            3 => 1,
+            4 => 2,
+            v => return Err(invalid(&format!("unsupported version {}", v))),
+        };
+
+        let mut paths: Vec<String> = Vec::new();
+        let mut ids: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+        while !rest.is_empty() {
+            let (header, body) = split_u32(rest).ok_or_else(|| invalid("truncated record"))?;
+            let size = (header & !NINJA_DEPS_RECORD) as usize;
+            if size > body.len() || !size.is_multiple_of(4) {
+                return Err(invalid("truncated record"));
+            }
+            let (record, next) = body.split_at(size);
+            rest = next;
+
+            let words: Vec<u32> = record
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect();
+            if header & NINJA_DEPS_RECORD != 0 {
+                if words.len() < 1 + mtime_words {
+                    return Err(invalid("truncated deps record"));
+                }
+                // a later record for the same output is from a later build
+                ids.insert(words[0], words[1 + mtime_words..].to_vec());
+            } else {
+                let (path, checksum) = match record.len().checked_sub(4) {
+                    Some(len) => (&record[..len], words[words.len() - 1]),
+                    None => return Err(invalid("truncated path record")),
+                };
+                if checksum != !(paths.len() as u32) {
+                    return Err(invalid("path record out of order"));
+                }
+                let end = path.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                let path = String::from_utf8(path[..end].to_vec())
+                    .map_err(|_| invalid("path is no UTF-8"))?;
+                paths.push(path);
+            }
+        }
+
+        let path = |id: &u32| {
+            paths
+                .get(*id as usize)
+                .cloned()
+                .ok_or_else(|| invalid(&format!("unknown path {}", id)))
+        };
+        let mut deps = BTreeMap::new();
+        for (output, inputs) in &ids {
+            deps.insert(
+                path(output)?,
+                inputs.iter().map(path).collect::<Result<_>>()?,
+            );
+        }
+        Ok(NinjaDepsLog { deps })
+    }
+
+    /// The outputs the log holds dependencies of
+    pub fn outputs(&self) -> impl Iterator<Item = &str> {
+        self.deps.keys().map(String::as_str)
+    }
+
+    /// The files read to build `output`, if it is in the log
+    pub fn deps(&self, output: &str) -> Option<&BTreeSet<String>> {
+        self.deps.get(output)
+    }
+}
+
+/// Hash the dependencies `deps` of a step as its materials, see
+/// `runlib::record_artifacts` for `hash_algorithms` and `lstrip_paths`.
+/// Relative paths are relative to the directory the build ran in.
+pub fn record_materials<'a, I>(
+    deps: I,
+    hash_algorithms: Option<&[&str]>,
+    lstrip_paths: Option<&[&str]>,
+) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let paths: Vec<&str> = deps.into_iter().collect();
+    record_artifacts(&paths, hash_algorithms, lstrip_paths)
+}
+
+/// Split the leading little endian `u32` from `bytes`.
+fn split_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    if bytes.len() < 4 {
+        return None;
+    }
+    let (word, rest) = bytes.split_at(4);
+    Some((
+        u32::from_le_bytes([word[0], word[1], word[2], word[3]]),
+        rest,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::fs;
+
+    use super::{parse_depfile, record_materials, NinjaDepsLog, NINJA_DEPS_MAGIC};
+    use crate::models::VirtualTargetPath;
+
+    fn set(items: &[&str]) -> BTreeSet<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_make_depfile() {
+        let rules = parse_depfile(
+            "# written by gcc -MD -MP\n\
+             out/foo.o out/foo.d: src/foo.c \\\n  include/my\\ header.h \\\n  C:\\sdk\\x.h $$HOME.h\n\
+             include/my\\ header.h:\n",
+        )
+        .unwrap();
+        let deps = set(&[
+            "src/foo.c",
+            "include/my header.h",
+            "C:\\sdk\\x.h",
+            "$HOME.h",
+        ]);
+        assert_eq!(rules["out/foo.o"], deps);
+        assert_eq!(rules["out/foo.d"], deps);
+        assert!(rules["include/my header.h"].is_empty());
+        assert_eq!(parse_depfile("a.o:b.c").unwrap()["a.o"], set(&["b.c"]));
+        assert!(parse_depfile("no rule here").is_err());
+    }
+
+    #[test]
+    fn parse_ninja_deps_log() {
+        let mut log = NINJA_DEPS_MAGIC.to_vec();
+        log.extend(4u32.to_le_bytes());
+        let mut id = 0;
+        let mut path = |log: &mut Vec<u8>, path: &str| {
+            let mut bytes = path.as_bytes().to_vec();
+            while !bytes.len().is_multiple_of(4) {
+                bytes.push(0);
+            }
+            log.extend((bytes.len() as u32 + 4).to_le_bytes());
+            log.extend(bytes);
+            log.extend((!id as u32).to_le_bytes());
+            id += 1;
+        };
+        let deps = |log: &mut Vec<u8>, ids: &[u32]| {
+            log.extend((super::NINJA_DEPS_RECORD | (12 + 4 * ids.len() as u32 - 4)).to_le_bytes());
+            log.extend(ids[0].to_le_bytes());
+            log.extend(7u64.to_le_bytes());
+            for id in &ids[1..] {
+                log.extend(id.to_le_bytes());
+            }
+        };
+        path(&mut log, "foo.o");
+        path(&mut log, "foo.c");
+        path(&mut log, "old.h");
+        deps(&mut log, &[0, 1, 2]);
+        path(&mut log, "foo.h");
+        deps(&mut log, &[0, 1, 3]);
+
+        let parsed = NinjaDepsLog::from_reader(&log[..]).unwrap();
+        assert_eq!(parsed.outputs().collect::<Vec<_>>(), ["foo.o"]);
+        assert_eq!(parsed.deps("foo.o"), Some(&set(&["foo.c", "foo.h"])));
+        assert_eq!(parsed.deps("foo.c"), None);
+
+        assert!(NinjaDepsLog::from_reader(&log[..log.len() - 2]).is_err());
+        assert!(NinjaDepsLog::from_reader(&b"# ninjalog\n"[..]).is_err());
+    }
+
+    #[test]
+    fn record_depfile_materials() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("foo.c");
+        fs::write(&src, "int main;").unwrap();
+        fs::write(dir.path().join("unused.c"), "int other;").unwrap();
+        let prefix = format!("{}/", dir.path().display());
+
+        let rules = parse_depfile(&format!("foo.o: {}\n", src.display())).unwrap();
+        let materials = record_materials(
+            rules["foo.o"].iter().map(String::as_str),
+            None,
+            Some(&[prefix.as_str()]),
+        )
+        .unwrap();
+        assert_eq!(
+            materials.keys().collect::<Vec<_>>(),
+            [&VirtualTargetPath::new("foo.c".into()).unwrap()]
+        );
+    }
+}
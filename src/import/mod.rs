@@ -0,0 +1,6 @@
+//! Importers turning what other build tools record about a build into
+//! links, so their users get links without wrapping every command in
+//! `in_toto_run`.
+
+pub mod bazel;
+pub mod depfile;
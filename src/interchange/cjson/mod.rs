@@ -255,10 +255,10 @@ impl DataInterchange for Json {
     ///     r#"{"o":{"0":null,"a":[1,2,3],"f":false,"n":123,"s":"string","t":true}}"#
     /// );
     /// ```
-    fn to_writer<W, T: Sized>(mut writer: W, value: &T) -> Result<()>
+    fn to_writer<W, T>(mut writer: W, value: &T) -> Result<()>
     where
         W: Write,
-        T: Serialize,
+        T: Sized + Serialize,
     {
         let bytes = Self::canonicalize(&Self::serialize(value)?)?;
         writer.write_all(&bytes)?;
@@ -330,6 +330,7 @@ impl Value {
             Value::Number(Number::U64(n)) => itoa::write(buf, n)
                 .map(|_| ())
                 .map_err(|err| format!("Write error: {}", err)),
+            Value::Number(Number::F64(n)) => write_float(buf, n),
             Value::String(ref s) => {
                 // this mess is abusing serde_json to get json escaping
                 let s = serde_json::Value::String(s.clone());
@@ -377,6 +378,63 @@ impl Value {
 enum Number {
     I64(i64),
     U64(u64),
+    F64(f64),
+}
+
+/// Write `n` the way python's `json` module does, which is `repr(n)`: the
+/// shortest digits that round trip, in positional notation with at least one
+/// fractional digit, or in exponent notation with a signed exponent of at
+/// least two digits if the exponent is below -4 or at least 16. Other
+/// implementations sign what python wrote, so any other formatting of a
+/// number breaks their signatures.
+fn write_float(buf: &mut Vec<u8>, n: f64) -> std::result::Result<(), String> {
+    if !n.is_finite() {
+        return Err(format!("{} has no JSON representation", n));
+    }
+    // Rust's `{:e}` has the same shortest digits, e.g. `-1.5e-7`
+    let scientific = format!("{:e}", n);
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .ok_or_else(|| format!("unexpected float formatting {}", scientific))?;
+    let exponent: i32 = exponent
+        .parse()
+        .map_err(|_| format!("unexpected float formatting {}", scientific))?;
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+
+    let formatted = if !(-4..16).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let rest = match rest.is_empty() {
+            true => String::new(),
+            false => format!(".{}", rest),
+        };
+        let exp_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}{}e{}{:02}",
+            sign,
+            first,
+            rest,
+            exp_sign,
+            exponent.abs()
+        )
+    } else if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        let integer_digits = exponent as usize + 1;
+        if digits.len() > integer_digits {
+            let (integer, fraction) = digits.split_at(integer_digits);
+            format!("{}{}.{}", sign, integer, fraction)
+        } else {
+            let zeros = "0".repeat(integer_digits - digits.len());
+            format!("{}{}{}.0", sign, digits, zeros)
+        }
+    };
+    buf.extend(formatted.as_bytes());
+    Ok(())
 }
 
 fn convert(jsn: &serde_json::Value) -> std::result::Result<Value, String> {
@@ -387,8 +445,9 @@ fn convert(jsn: &serde_json::Value) -> std::result::Result<Value, String> {
             .as_i64()
             .map(Number::I64)
             .or_else(|| n.as_u64().map(Number::U64))
+            .or_else(|| n.as_f64().map(Number::F64))
             .map(Value::Number)
-            .ok_or_else(|| String::from("only i64 and u64 are supported")),
+            .ok_or_else(|| format!("unsupported number {}", n)),
         serde_json::Value::Array(ref arr) => {
             let mut out = Vec::new();
             for res in arr.iter().map(convert) {
@@ -444,4 +503,51 @@ mod test {
         jsn.write(&mut out).unwrap();
         assert_eq!(&out, &b"{\"lol\":[\"haha\",\"new\\nline\"]}");
     }
+
+    #[test]
+    fn write_float_like_python() {
+        // python3 -c 'import json; print(json.dumps(n))'
+        let cases: &[(f64, &str)] = &[
+            (0.0, "0.0"),
+            (-0.0, "-0.0"),
+            (1.0, "1.0"),
+            (-2.5, "-2.5"),
+            (0.1, "0.1"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (100.0, "100.0"),
+            (1e15, "1000000000000000.0"),
+            (1e16, "1e+16"),
+            (1.5e16, "1.5e+16"),
+            (123456789012345680.0, "1.2345678901234568e+17"),
+            (0.0001, "0.0001"),
+            (0.00001, "1e-05"),
+            (1.5e-7, "1.5e-07"),
+            (2.5e-300, "2.5e-300"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (5e-324, "5e-324"),
+            (1e22, "1e+22"),
+            (12.5e-3, "0.0125"),
+        ];
+        for (n, python) in cases {
+            let mut out = Vec::new();
+            write_float(&mut out, *n).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), *python, "{:e}", n);
+        }
+        assert!(write_float(&mut Vec::new(), f64::NAN).is_err());
+        assert!(write_float(&mut Vec::new(), f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn canonicalize_numbers_like_python() {
+        // integers stay integers, floats stay floats, even if integral
+        let jsn: serde_json::Value = serde_json::from_str(
+            r#"{"return-value": 0, "b": [1, 2.0, -3], "a": {"y": 0.50, "x": 1E-5}}"#,
+        )
+        .unwrap();
+        // python3 -c 'import json; print(json.dumps(json.loads(s), sort_keys=True, separators=(",", ":")))'
+        assert_eq!(
+            String::from_utf8(canonicalize(&jsn).unwrap()).unwrap(),
+            r#"{"a":{"x":1e-05,"y":0.5},"b":[1,2.0,-3],"return-value":0}"#
+        );
+    }
 }
@@ -1,6 +1,7 @@
 //! Structures and functions to aid in various in-toto data interchange formats.
 
 pub(crate) mod cjson;
+pub(crate) mod toml;
 pub use cjson::{Json, JsonPretty};
 
 use serde::de::DeserializeOwned;
@@ -33,10 +34,10 @@ pub trait DataInterchange: Debug + PartialEq + Clone {
 
     /// Write a struct to a stream.
     #[allow(clippy::wrong_self_convention)]
-    fn to_writer<W, T: Sized>(writer: W, value: &T) -> Result<()>
+    fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
     where
         W: Write,
-        T: Serialize;
+        T: Sized + Serialize;
 
     /// Read a struct from a stream.
     fn from_reader<R, T>(rdr: R) -> Result<T>
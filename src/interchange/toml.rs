@@ -0,0 +1,370 @@
+//! Reading configuration files in TOML.
+//!
+//! Only the part of TOML configuration needs is read: tables, dotted and
+//! quoted keys, basic and literal strings, integers, booleans, arrays and
+//! inline tables. Floats, dates, multi-line strings and arrays of tables
+//! are rejected, as are duplicate keys. Documents are read into a JSON
+//! value, to be deserialized as usual.
+
+use serde_json::{Map, Value};
+
+use crate::{Error, Result};
+
+/// The JSON object holding the TOML document `text`.
+pub(crate) fn parse(text: &str) -> Result<Value> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        position: 0,
+        line: 1,
+    };
+    let mut root = Map::new();
+    let mut table: Vec<String> = Vec::new();
+    let mut defined: Vec<Vec<String>> = Vec::new();
+    loop {
+        parser.skip_blank(true);
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.next();
+                if parser.peek() == Some('[') {
+                    return Err(parser.error("arrays of tables are not supported"));
+                }
+                parser.skip_blank(false);
+                table = parser.key()?;
+                parser.skip_blank(false);
+                parser.expect(']')?;
+                if defined.contains(&table) {
+                    return Err(parser.error(&format!("table {} defined twice", table.join("."))));
+                }
+                defined.push(table.clone());
+                descend(&mut root, &table, &parser)?;
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_blank(false);
+                parser.expect('=')?;
+                parser.skip_blank(false);
+                let value = parser.value()?;
+                let path: Vec<String> = table.iter().chain(&key).cloned().collect();
+                insert(&mut root, &path, value, &parser)?;
+            }
+        }
+        parser.end_of_line()?;
+    }
+    Ok(Value::Object(root))
+}
+
+/// The table at `path` below `root`, created where missing.
+fn descend<'a>(
+    mut table: &'a mut Map<String, Value>,
+    path: &[String],
+    parser: &Parser,
+) -> Result<&'a mut Map<String, Value>> {
+    for key in path {
+        table = match table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(table) => table,
+            _ => return Err(parser.error(&format!("{} is not a table", key))),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(
+    root: &mut Map<String, Value>,
+    path: &[String],
+    value: Value,
+    parser: &Parser,
+) -> Result<()> {
+    let (key, tables) = match path.split_last() {
+        Some(split) => split,
+        None => return Err(parser.error("empty key")),
+    };
+    let table = descend(root, tables, parser)?;
+    if table.contains_key(key) {
+        return Err(parser.error(&format!("{} defined twice", path.join("."))));
+    }
+    table.insert(key.clone(), value);
+    Ok(())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        Error::Encoding(format!("TOML line {}: {}", self.line, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(&format!("expected {:?}, found {:?}", expected, c))),
+            None => Err(self.error(&format!("expected {:?}", expected))),
+        }
+    }
+
+    /// Skip spaces and comments, and line breaks if `newlines`
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => (),
+                '\n' if newlines => (),
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.next();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            self.next();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip_blank(false);
+        match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => Err(self.error(&format!("unexpected {:?}", c))),
+        }
+    }
+
+    /// A key, dotted keys give the path to a value in nested tables
+    fn key(&mut self) -> Result<Vec<String>> {
+        let mut path = Vec::new();
+        loop {
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.position;
+                    while self
+                        .peek()
+                        .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+                    {
+                        self.next();
+                    }
+                    if start == self.position {
+                        return Err(self.error("expected a key"));
+                    }
+                    self.chars[start..self.position].iter().collect()
+                }
+            };
+            path.push(part);
+            self.skip_blank(false);
+            if self.peek() != Some('.') {
+                return Ok(path);
+            }
+            self.next();
+            self.skip_blank(false);
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => {
+                let start = self.position;
+                while self
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || "+-_.:".contains(c))
+                {
+                    self.next();
+                }
+                let word: String = self.chars[start..self.position].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => {
+                        let digits = word.replace('_', "");
+                        match digits.parse::<i64>() {
+                            Ok(n) if !word.starts_with('_') && !word.ends_with('_') => {
+                                Ok(Value::from(n))
+                            }
+                            _ => Err(self.error(&format!("unsupported value {:?}", word))),
+                        }
+                    }
+                }
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        if self.peek() == Some('"') && self.chars.get(self.position + 1) == Some(&'"') {
+            return Err(self.error("multi-line strings are not supported"));
+        }
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.unicode(4)?,
+                        Some('U') => self.unicode(8)?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    string.push(escaped);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn unicode(&mut self, digits: usize) -> Result<char> {
+        let hex: String = (0..digits).filter_map(|_| self.next()).collect();
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        self.expect('\'')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(string),
+                Some('\n') | None => return Err(self.error("unterminated string")),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank(true);
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank(true);
+            match self.next() {
+                Some(',') => (),
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value> {
+        self.expect('{')?;
+        let mut table = Map::new();
+        self.skip_blank(false);
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Value::Object(table));
+        }
+        loop {
+            self.skip_blank(false);
+            let key = self.key()?;
+            self.expect('=')?;
+            self.skip_blank(false);
+            let value = self.value()?;
+            insert(&mut table, &key, value, self)?;
+            self.skip_blank(false);
+            match self.next() {
+                Some(',') => (),
+                Some('}') => return Ok(Value::Object(table)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn parse_documents() {
+        let document = r#"
+            # a comment
+            title = "in-toto" # after a value
+            "quoted key" = 'C:\path'
+
+            [layout]
+            path = "root.layout"
+            keys = [
+                "a.json",  # with comments
+                "b.json",
+            ]
+            threshold = 1_000
+            inline = { enabled = true, depth = -2 }
+
+            [layout.limits]
+            escaped = "tab\there \u00e9"
+            dotted.key = false
+        "#;
+        assert_eq!(
+            parse(document).unwrap(),
+            json!({
+                "title": "in-toto",
+                "quoted key": "C:\\path",
+                "layout": {
+                    "path": "root.layout",
+                    "keys": ["a.json", "b.json"],
+                    "threshold": 1000,
+                    "inline": { "enabled": true, "depth": -2 },
+                    "limits": { "escaped": "tab\there é", "dotted": { "key": false } }
+                }
+            })
+        );
+        assert_eq!(parse("").unwrap(), json!({}));
+    }
+
+    #[test]
+    fn reject_documents() {
+        for document in [
+            "a = 1\na = 2",
+            "[t]\n[t]",
+            "a = 1\n[a]",
+            "a = 1.5",
+            "a = 1979-05-27",
+            "a = \"\"\"\nmulti\"\"\"",
+            "[[tables]]",
+            "a = \"unterminated",
+            "a = 1 b = 2",
+            "a = [1 2]",
+            "= 1",
+        ] {
+            assert!(parse(document).is_err(), "{}", document);
+        }
+    }
+}
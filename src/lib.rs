@@ -1,6 +1,9 @@
 //! This crate provides an API for talking to repositories that implements in-toto
 
 //#![deny(missing_docs)]
+// the crate is embedded in long-running services, so failures are errors,
+// not panics, except in the helpers for tests
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used))]
 #![allow(
     clippy::collapsible_if,
     clippy::implicit_hasher,
@@ -9,11 +12,23 @@
     clippy::too_many_arguments
 )]
 
+#[cfg(any(test, feature = "conformance"))]
+#[allow(clippy::unwrap_used, clippy::expect_used)]
+pub mod conformance;
 pub mod crypto;
 pub mod error;
+pub mod import;
 pub mod interchange;
+pub mod metrics;
 pub mod models;
+pub mod resolver;
 pub mod runlib;
+pub mod store;
+#[cfg(any(test, feature = "test_utils"))]
+#[allow(clippy::unwrap_used, clippy::expect_used)]
+pub mod test_utils;
+#[cfg(all(feature = "tracer", target_os = "linux", target_arch = "x86_64"))]
+pub mod tracer;
 pub mod verifylib;
 
 mod format_hex;
@@ -0,0 +1,300 @@
+//! Monitoring verification in production.
+//!
+//! Verifications and recordings report what they do to a `Metrics`
+//! implementation given in `VerifyOptions::metrics` and
+//! `RecordOptions::metrics`, e.g. to feed the metrics library of a service.
+//! `Counters` keeps them in memory and renders them in the Prometheus text
+//! exposition format, to be served as they are.
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use in_toto::metrics::Counters;
+//! # use in_toto::verifylib::VerifyOptions;
+//! let counters = Arc::new(Counters::new());
+//! let options = VerifyOptions::new().metrics(counters.clone());
+//! // ... verify with options, then serve
+//! let text = counters.render();
+//! assert!(text.contains("in_toto_verifications_total 0"));
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Display, Formatter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The stage of a verification, by which failures are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerificationStage {
+    /// Checking the layout against the `MetadataLimits`
+    Limits,
+    /// Checking the signatures of the layout
+    Signatures,
+    /// Checking the expiration and validity of the layout
+    Expiration,
+    /// Reading and checking the links of the steps
+    Links,
+    /// Checking the artifact rules
+    Rules,
+    /// Running the inspections
+    Inspections,
+}
+
+impl VerificationStage {
+    /// All stages, in the order verification goes through them, which is
+    /// that of their declaration
+    pub const ALL: [VerificationStage; 6] = [
+        VerificationStage::Limits,
+        VerificationStage::Signatures,
+        VerificationStage::Expiration,
+        VerificationStage::Links,
+        VerificationStage::Rules,
+        VerificationStage::Inspections,
+    ];
+
+    /// The name of the stage, as used for labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationStage::Limits => "limits",
+            VerificationStage::Signatures => "signatures",
+            VerificationStage::Expiration => "expiration",
+            VerificationStage::Links => "links",
+            VerificationStage::Rules => "rules",
+            VerificationStage::Inspections => "inspections",
+        }
+    }
+}
+
+impl Display for VerificationStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What verifications and recordings report. Implementations are called
+/// from several threads and should return quickly.
+pub trait Metrics: Send + Sync {
+    /// A verification finished after `duration`, failing at `failed` if it
+    /// failed.
+    fn verification(&self, duration: Duration, failed: Option<VerificationStage>) {
+        let _ = (duration, failed);
+    }
+
+    /// A file of `bytes` was hashed in `duration` while recording artifacts.
+    /// Files whose digests were cached are not reported.
+    fn file_hashed(&self, bytes: u64, duration: Duration) {
+        let _ = (bytes, duration);
+    }
+}
+
+/// A `Metrics` shared by options, compared by identity.
+#[derive(Clone)]
+pub(crate) struct MetricsHook(pub(crate) Arc<dyn Metrics>);
+
+impl Debug for MetricsHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsHook")
+    }
+}
+
+impl PartialEq for MetricsHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MetricsHook {}
+
+/// The upper bounds in seconds of the buckets of the verification duration
+/// histogram of `Counters`.
+pub const DURATION_BUCKETS: [f64; 10] = [0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0];
+
+/// Metrics counted in memory.
+#[derive(Debug, Default)]
+pub struct Counters {
+    verifications: AtomicU64,
+    failures: [AtomicU64; VerificationStage::ALL.len()],
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_nanos: AtomicU64,
+    hashed_files: AtomicU64,
+    hashed_bytes: AtomicU64,
+    hashing_nanos: AtomicU64,
+}
+
+impl Counters {
+    /// Counters all at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of verifications finished
+    pub fn verifications(&self) -> u64 {
+        self.verifications.load(Ordering::Relaxed)
+    }
+
+    /// The number of verifications failed at `stage`
+    pub fn failures(&self, stage: VerificationStage) -> u64 {
+        self.failures[stage as usize].load(Ordering::Relaxed)
+    }
+
+    /// The number and total size of the files hashed, and the time hashing
+    /// them took
+    pub fn hashed(&self) -> (u64, u64, Duration) {
+        (
+            self.hashed_files.load(Ordering::Relaxed),
+            self.hashed_bytes.load(Ordering::Relaxed),
+            Duration::from_nanos(self.hashing_nanos.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// The counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let metric = |text: &mut String, name: &str, kind: &str, help: &str| {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        };
+        metric(
+            &mut text,
+            "in_toto_verifications_total",
+            "counter",
+            "Verifications finished.",
+        );
+        let _ = writeln!(text, "in_toto_verifications_total {}", self.verifications());
+
+        metric(
+            &mut text,
+            "in_toto_verification_failures_total",
+            "counter",
+            "Verifications failed, by the stage they failed at.",
+        );
+        for stage in VerificationStage::ALL {
+            let _ = writeln!(
+                text,
+                "in_toto_verification_failures_total{{stage=\"{}\"}} {}",
+                stage,
+                self.failures(stage)
+            );
+        }
+
+        metric(
+            &mut text,
+            "in_toto_verification_duration_seconds",
+            "histogram",
+            "How long verifications took.",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(
+                text,
+                "in_toto_verification_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let count = self.verifications();
+        let _ = writeln!(
+            text,
+            "in_toto_verification_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            count
+        );
+        let _ = writeln!(
+            text,
+            "in_toto_verification_duration_seconds_sum {}",
+            Duration::from_nanos(self.duration_nanos.load(Ordering::Relaxed)).as_secs_f64()
+        );
+        let _ = writeln!(
+            text,
+            "in_toto_verification_duration_seconds_count {}",
+            count
+        );
+
+        let (files, bytes, hashing) = self.hashed();
+        for (name, help, value) in [
+            (
+                "in_toto_hashed_files_total",
+                "Files hashed recording artifacts.",
+                files.to_string(),
+            ),
+            (
+                "in_toto_hashed_bytes_total",
+                "Bytes hashed recording artifacts.",
+                bytes.to_string(),
+            ),
+            (
+                "in_toto_hashing_seconds_total",
+                "Time spent hashing files recording artifacts.",
+                hashing.as_secs_f64().to_string(),
+            ),
+        ] {
+            metric(&mut text, name, "counter", help);
+            let _ = writeln!(text, "{} {}", name, value);
+        }
+        text
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl Metrics for Counters {
+    fn verification(&self, duration: Duration, failed: Option<VerificationStage>) {
+        if let Some(stage) = failed {
+            self.failures[stage as usize].fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.duration_nanos
+            .fetch_add(nanos(duration), Ordering::Relaxed);
+        // counted last, so the total never lags behind the buckets
+        self.verifications.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn file_hashed(&self, bytes: u64, duration: Duration) {
+        self.hashed_files.fetch_add(1, Ordering::Relaxed);
+        self.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.hashing_nanos
+            .fetch_add(nanos(duration), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Counters, Metrics, VerificationStage};
+
+    #[test]
+    fn count_and_render() {
+        let counters = Counters::new();
+        counters.verification(Duration::from_millis(20), None);
+        counters.verification(Duration::from_secs(2), Some(VerificationStage::Rules));
+        counters.verification(Duration::from_secs(120), Some(VerificationStage::Rules));
+        counters.file_hashed(1000, Duration::from_millis(1));
+        counters.file_hashed(24, Duration::from_millis(1));
+
+        assert_eq!(counters.verifications(), 3);
+        assert_eq!(counters.failures(VerificationStage::Rules), 2);
+        assert_eq!(counters.failures(VerificationStage::Links), 0);
+        assert_eq!(counters.hashed(), (2, 1024, Duration::from_millis(2)));
+
+        let text = counters.render();
+        for line in [
+            "in_toto_verifications_total 3",
+            "in_toto_verification_failures_total{stage=\"rules\"} 2",
+            "in_toto_verification_failures_total{stage=\"links\"} 0",
+            "in_toto_verification_duration_seconds_bucket{le=\"0.01\"} 0",
+            "in_toto_verification_duration_seconds_bucket{le=\"0.05\"} 1",
+            "in_toto_verification_duration_seconds_bucket{le=\"5\"} 2",
+            "in_toto_verification_duration_seconds_bucket{le=\"60\"} 2",
+            "in_toto_verification_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "in_toto_verification_duration_seconds_sum 122.02",
+            "in_toto_hashed_bytes_total 1024",
+            "# TYPE in_toto_verification_duration_seconds histogram",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{} in\n{}", line, text);
+        }
+    }
+}
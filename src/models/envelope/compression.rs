@@ -0,0 +1,125 @@
+//! Compressed DSSE payloads.
+//!
+//! Some attestation stores serve envelopes whose payload is compressed, as
+//! provenance of large builds lists thousands of materials. The compression
+//! is told by a suffix of the payload type, e.g.
+//! `application/vnd.in-toto+json+gzip`, or, without suffix, by the magic
+//! bytes the payload starts with, which JSON never does. It is a transport
+//! encoding only: signatures are made over the uncompressed payload and the
+//! payload type without suffix, so a compressed envelope verifies like the
+//! uncompressed one. Compressed payloads are binary, so only the base64
+//! encoded `CosignEnvelope` carries them.
+
+use super::{gzip, zstd};
+use crate::{Error, Result};
+
+/// A compression of DSSE payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadCompression {
+    /// gzip, RFC 1952
+    Gzip,
+    /// Zstandard, RFC 8878, without dictionaries
+    Zstd,
+}
+
+impl PayloadCompression {
+    /// The suffix of the payload type of payloads compressed this way
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            PayloadCompression::Gzip => "+gzip",
+            PayloadCompression::Zstd => "+zstd",
+        }
+    }
+
+    /// Split `payload_type` into the type of the uncompressed payload and
+    /// the compression its suffix names, if any.
+    pub fn from_payload_type(payload_type: &str) -> (&str, Option<Self>) {
+        for compression in [PayloadCompression::Gzip, PayloadCompression::Zstd] {
+            if let Some(base) = payload_type.strip_suffix(compression.suffix()) {
+                return (base, Some(compression));
+            }
+        }
+        (payload_type, None)
+    }
+
+    /// The compression of `payload` told by its magic bytes, if any
+    pub fn detect(payload: &[u8]) -> Option<Self> {
+        if gzip::is_gzip(payload) {
+            Some(PayloadCompression::Gzip)
+        } else if zstd::is_zstd(payload) {
+            Some(PayloadCompression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Compress `payload`.
+    pub fn compress(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            PayloadCompression::Gzip => Ok(gzip::compress(payload)),
+            PayloadCompression::Zstd => Ok(zstd::compress(payload)),
+        }
+    }
+
+    /// Decompress `payload`, failing if the result would exceed `max_size`
+    /// bytes.
+    pub fn decompress(&self, payload: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        match self {
+            PayloadCompression::Gzip => gzip::decompress(payload, max_size),
+            PayloadCompression::Zstd => zstd::decompress(payload, max_size),
+        }
+    }
+}
+
+/// The payload type and payload of an envelope with the given ones, with
+/// any compression removed.
+pub(super) fn decompress_payload(
+    payload_type: &str,
+    payload: Vec<u8>,
+    max_size: usize,
+) -> Result<(String, Vec<u8>)> {
+    let (base, compression) = PayloadCompression::from_payload_type(payload_type);
+    match compression.or_else(|| PayloadCompression::detect(&payload)) {
+        Some(compression) => Ok((base.into(), compression.decompress(&payload, max_size)?)),
+        None if payload.len() > max_size => Err(Error::Encoding(format!(
+            "payload of {} bytes exceeds the limit of {} bytes",
+            payload.len(),
+            max_size
+        ))),
+        None => Ok((base.into(), payload)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decompress_payload, PayloadCompression};
+
+    #[test]
+    fn negotiate_compression() {
+        let typ = "application/vnd.in-toto+json";
+        assert_eq!(
+            PayloadCompression::from_payload_type("application/vnd.in-toto+json+gzip"),
+            (typ, Some(PayloadCompression::Gzip))
+        );
+        assert_eq!(PayloadCompression::from_payload_type(typ), (typ, None));
+
+        let payload = br#"{"_type": "https://in-toto.io/Statement/v0.1"}"#.to_vec();
+        assert_eq!(PayloadCompression::detect(&payload), None);
+        for compression in [PayloadCompression::Gzip, PayloadCompression::Zstd] {
+            let compressed = compression.compress(&payload).unwrap();
+            assert_eq!(PayloadCompression::detect(&compressed), Some(compression));
+            let suffixed = format!("{}{}", typ, compression.suffix());
+            for typ in [typ, &suffixed] {
+                let (typ, decompressed) =
+                    decompress_payload(typ, compressed.clone(), 1024).unwrap();
+                assert_eq!(typ, "application/vnd.in-toto+json");
+                assert_eq!(decompressed, payload);
+            }
+            assert!(decompress_payload(typ, compressed, 8).is_err());
+        }
+        assert!(decompress_payload(typ, payload, 8).is_err());
+
+        let zstd = [0x28, 0xb5, 0x2f, 0xfd, 0];
+        assert!(decompress_payload(typ, zstd.to_vec(), 1024).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Converting metadata between the classic and the attestation formats.
+//!
+//! Archives of classic metadata, links and layouts signed as a `Metablock`,
+//! have to be converted to DSSE envelopes when moving to attestations, and
+//! back for tools not supporting them yet. A link converts to an in-toto
+//! statement with the `LinkV02` predicate and its products as the subject,
+//! and any metadata to an envelope holding it as is, as the reference
+//! implementation signs links and layouts with DSSE.
+//!
+//! Signatures cover the format they were made in, so converted metadata is
+//! signed anew with the keys given. Check the signatures of metadata before
+//! converting it, the converters do not.
+
+use crate::crypto::PrivateKey;
+use crate::models::predicate::LinkV02;
+use crate::models::{
+    DSSEVersion, EnvelopeFile, Metablock, MetadataWrapper, PredicateWrapper, StatementVer,
+    StatementWrapper,
+};
+use crate::verifylib::IN_TOTO_PAYLOAD_TYPE;
+use crate::{Error, Result};
+
+/// The link of `link` as a statement with the `LinkV02` predicate, in an
+/// envelope signed by `keys`.
+pub fn link_to_statement(link: &Metablock, keys: &[&PrivateKey]) -> Result<EnvelopeFile> {
+    let link = match link.metadata() {
+        MetadataWrapper::Link(link) => link,
+        MetadataWrapper::Layout(_) => {
+            return Err(Error::IllegalArgument(
+                "a layout cannot be a statement".into(),
+            ))
+        }
+    };
+    let predicate = Box::new(LinkV02::from(link));
+    let statement = StatementWrapper::from_meta(link.clone(), Some(predicate), StatementVer::V0_1)?;
+    let payload = String::from_utf8(statement.into_trait().to_bytes()?)
+        .map_err(|e| Error::Encoding(e.to_string()))?;
+    sign_envelope(payload, keys)
+}
+
+/// The link in the statement with the `LinkV02` predicate in `envelope`, as
+/// a `Metablock` signed by `keys`.
+pub fn statement_to_link(envelope: &EnvelopeFile, keys: &[&PrivateKey]) -> Result<Metablock> {
+    check_payload_type(envelope)?;
+    let statement: StatementWrapper = serde_json::from_str(envelope.payload())?;
+    let link = match &statement {
+        StatementWrapper::V0_1(statement) => match statement.predicate() {
+            PredicateWrapper::LinkV0_2(predicate) => {
+                predicate.to_link(statement.subject().clone())?
+            }
+            _ => {
+                return Err(Error::AttestationFormatDismatch(
+                    StatementVer::V0_1.to_string(),
+                    String::from(statement.predicate_type()),
+                ))
+            }
+        },
+        StatementWrapper::Naive(_) => {
+            return Err(Error::AttestationFormatDismatch(
+                StatementVer::Naive.to_string(),
+                "None".into(),
+            ))
+        }
+    };
+    Metablock::new(MetadataWrapper::Link(link), keys)
+}
+
+/// The metadata of `metablock`, a link or a layout, in an envelope signed
+/// by `keys`.
+pub fn metablock_to_envelope(metablock: &Metablock, keys: &[&PrivateKey]) -> Result<EnvelopeFile> {
+    let payload = String::from_utf8(metablock.metadata().to_bytes()?)
+        .map_err(|e| Error::Encoding(e.to_string()))?;
+    sign_envelope(payload, keys)
+}
+
+/// The link or layout in `envelope` as a `Metablock` signed by `keys`.
+pub fn envelope_to_metablock(envelope: &EnvelopeFile, keys: &[&PrivateKey]) -> Result<Metablock> {
+    check_payload_type(envelope)?;
+    let metadata = MetadataWrapper::try_from_bytes(envelope.payload().as_bytes())?;
+    Metablock::new(metadata, keys)
+}
+
+fn check_payload_type(envelope: &EnvelopeFile) -> Result<()> {
+    if envelope.payload_type() != IN_TOTO_PAYLOAD_TYPE {
+        return Err(Error::Encoding(format!(
+            "the payload type is {}",
+            envelope.payload_type()
+        )));
+    }
+    Ok(())
+}
+
+fn sign_envelope(payload: String, keys: &[&PrivateKey]) -> Result<EnvelopeFile> {
+    let message = DSSEVersion::V1.pack(payload.as_bytes(), IN_TOTO_PAYLOAD_TYPE.into());
+    let signatures = keys
+        .iter()
+        .map(|key| key.sign(&message))
+        .collect::<Result<_>>()?;
+    Ok(EnvelopeFile::new(
+        payload,
+        IN_TOTO_PAYLOAD_TYPE.into(),
+        signatures,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        envelope_to_metablock, link_to_statement, metablock_to_envelope, statement_to_link,
+    };
+    use crate::models::MetadataWrapper;
+    use crate::test_utils::{functionary_key, link, owner_key, sign, signed_layout};
+    use crate::verifylib::{verify_subject, AttestationTrust};
+
+    #[test]
+    fn convert_link() {
+        let functionary = functionary_key();
+        let classic = sign(
+            Box::new(link("build", &[("src", b"src")], &[("app", b"app")])),
+            &[&functionary],
+        );
+        let envelope = link_to_statement(&classic, &[&functionary]).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app");
+        std::fs::write(&app, b"app").unwrap();
+        let trust = AttestationTrust::new(
+            crate::models::PredicateVer::LinkV0_2,
+            &[functionary.public()],
+        );
+        assert!(verify_subject(&app, std::slice::from_ref(&envelope), &trust).is_ok());
+
+        let converted = statement_to_link(&envelope, &[&functionary]).unwrap();
+        assert_eq!(converted, classic);
+        assert!(link_to_statement(&signed_layout(&owner_key(), &functionary), &[]).is_err());
+    }
+
+    #[test]
+    fn convert_metablock() {
+        let owner = owner_key();
+        let layout = signed_layout(&owner, &functionary_key());
+        let envelope = metablock_to_envelope(&layout, &[&owner]).unwrap();
+        assert!(matches!(
+            serde_json::from_str(envelope.payload()).unwrap(),
+            MetadataWrapper::Layout(_)
+        ));
+        let converted = envelope_to_metablock(&envelope, &[&owner]).unwrap();
+        assert_eq!(converted, layout);
+        assert!(statement_to_link(&envelope, &[&owner]).is_err());
+    }
+}
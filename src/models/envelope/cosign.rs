@@ -0,0 +1,410 @@
+//! DSSE envelopes and sigstore bundles as written by cosign.
+//!
+//! cosign follows the DSSE specification: the payload and signatures are
+//! base64 encoded, the payload type is called `payloadType` and signatures
+//! may come without key ID, e.g. when made with a keyless certificate. Key
+//! IDs of this crate are hex encoded sha256 digests, so signatures without
+//! such a key ID are attributed to a key given on import.
+//!
+//! Payloads may be compressed, see `PayloadCompression`. They are
+//! decompressed on decoding, and only compressed on request.
+
+use data_encoding::{BASE64, BASE64URL};
+use serde_derive::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::compression::decompress_payload;
+use super::{EnvelopeFile, PayloadCompression};
+use crate::crypto::{KeyId, Signature, SignatureValue};
+use crate::models::DEFAULT_MAX_METADATA_SIZE;
+use crate::{Error, Result};
+
+/// Media type of the sigstore bundles written by cosign.
+pub const SIGSTORE_BUNDLE_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.bundle+json;version=0.1";
+
+/// PEM tag of the certificates of a `CosignBundle`.
+const PEM_CERTIFICATE: &str = "CERTIFICATE";
+
+/// A signature of a `CosignEnvelope`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CosignSignature {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    keyid: String,
+    sig: String,
+}
+
+impl CosignSignature {
+    /// The key ID, empty if the signature has none
+    pub fn key_id(&self) -> &str {
+        &self.keyid
+    }
+
+    /// The base64 encoded signature
+    pub fn sig(&self) -> &str {
+        &self.sig
+    }
+}
+
+/// A DSSE envelope in the encoding used by cosign.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CosignEnvelope {
+    payload_type: String,
+    payload: String,
+    signatures: Vec<CosignSignature>,
+}
+
+impl CosignEnvelope {
+    /// Parse an envelope as written by `cosign attest` or `cosign download attestation`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Serialize the envelope as JSON.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Encode `envelope` the way cosign does.
+    pub fn from_envelope(envelope: &EnvelopeFile) -> Self {
+        CosignEnvelope {
+            payload_type: envelope.payload_type().clone(),
+            payload: BASE64.encode(envelope.payload().as_bytes()),
+            signatures: envelope
+                .signatures()
+                .iter()
+                .map(|sig| CosignSignature {
+                    keyid: sig.key_id().to_string(),
+                    sig: BASE64.encode(sig.value().as_bytes()),
+                })
+                .collect(),
+        }
+    }
+
+    /// Encode `envelope` the way cosign does, compressing the payload with
+    /// `compression`. The payload type gets the suffix of the compression,
+    /// the signatures stay those over the uncompressed payload.
+    pub fn from_envelope_compressed(
+        envelope: &EnvelopeFile,
+        compression: PayloadCompression,
+    ) -> Result<Self> {
+        let mut encoded = Self::from_envelope(envelope);
+        encoded.payload = BASE64.encode(&compression.compress(envelope.payload().as_bytes())?);
+        encoded.payload_type.push_str(compression.suffix());
+        Ok(encoded)
+    }
+
+    /// Decode into this crate's envelope type. Signatures without a key ID
+    /// of this crate are attributed to `default_key_id`, failing if there is
+    /// none. A compressed payload is decompressed, and the suffix of the
+    /// compression removed from the payload type.
+    pub fn to_envelope(&self, default_key_id: Option<&KeyId>) -> Result<EnvelopeFile> {
+        let (payload_type, payload) = decompress_payload(
+            &self.payload_type,
+            decode_base64(&self.payload)?,
+            DEFAULT_MAX_METADATA_SIZE,
+        )?;
+        let payload = String::from_utf8(payload)
+            .map_err(|e| Error::Encoding(format!("DSSE payload is no UTF-8: {}", e)))?;
+        let signatures = self
+            .signatures
+            .iter()
+            .map(|sig| {
+                let key_id = match KeyId::from_str(&sig.keyid) {
+                    Ok(key_id) => key_id,
+                    Err(_) => default_key_id.cloned().ok_or_else(|| {
+                        Error::IllegalArgument(format!(
+                            "DSSE signature with key ID {:?} needs the ID of the key it was made with",
+                            sig.keyid
+                        ))
+                    })?,
+                };
+                Ok(Signature::new(
+                    key_id,
+                    SignatureValue::new(decode_base64(&sig.sig)?),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(EnvelopeFile::new(payload, payload_type, signatures))
+    }
+
+    /// The payload type, e.g. `application/vnd.in-toto+json`
+    pub fn payload_type(&self) -> &str {
+        &self.payload_type
+    }
+
+    /// The base64 encoded payload
+    pub fn payload(&self) -> &str {
+        &self.payload
+    }
+
+    /// The decoded payload, decompressed if it is compressed
+    pub fn decoded_payload(&self) -> Result<Vec<u8>> {
+        self.decoded_payload_with_limit(DEFAULT_MAX_METADATA_SIZE)
+    }
+
+    /// The decoded payload, failing if it is larger than `max_size` bytes
+    /// once decompressed
+    pub fn decoded_payload_with_limit(&self, max_size: usize) -> Result<Vec<u8>> {
+        let payload = decode_base64(&self.payload)?;
+        Ok(decompress_payload(&self.payload_type, payload, max_size)?.1)
+    }
+
+    /// The compression of the payload, told by the payload type or the
+    /// payload itself
+    pub fn compression(&self) -> Option<PayloadCompression> {
+        PayloadCompression::from_payload_type(&self.payload_type)
+            .1
+            .or_else(|| {
+                decode_base64(&self.payload)
+                    .ok()
+                    .and_then(|payload| PayloadCompression::detect(&payload))
+            })
+    }
+
+    /// The signatures of the envelope
+    pub fn signatures(&self) -> &[CosignSignature] {
+        &self.signatures
+    }
+}
+
+/// Raw DER bytes, base64 encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CertificateChain {
+    certificates: Vec<RawBytes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PublicKeyHint {
+    hint: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMaterial {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    x509_certificate_chain: Option<CertificateChain>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    certificate: Option<RawBytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_key: Option<PublicKeyHint>,
+    /// Transparency log entries and timestamps, kept as they are
+    #[serde(flatten)]
+    other: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A sigstore bundle holding a DSSE envelope along with the material to
+/// verify it, as written by `cosign attest --bundle`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CosignBundle {
+    media_type: String,
+    #[serde(default)]
+    verification_material: VerificationMaterial,
+    dsse_envelope: CosignEnvelope,
+}
+
+impl CosignBundle {
+    /// Bundle `envelope` with the DER encoded certificate chain it was
+    /// signed with, leaf first.
+    pub fn new(envelope: CosignEnvelope, certificates: &[Vec<u8>]) -> Self {
+        let chain = match certificates.is_empty() {
+            true => None,
+            false => Some(CertificateChain {
+                certificates: certificates
+                    .iter()
+                    .map(|der| RawBytes {
+                        raw_bytes: BASE64.encode(der),
+                    })
+                    .collect(),
+            }),
+        };
+        CosignBundle {
+            media_type: SIGSTORE_BUNDLE_MEDIA_TYPE.into(),
+            verification_material: VerificationMaterial {
+                x509_certificate_chain: chain,
+                ..Default::default()
+            },
+            dsse_envelope: envelope,
+        }
+    }
+
+    /// Parse a sigstore bundle holding a DSSE envelope.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    /// Serialize the bundle as JSON.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// The media type of the bundle
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// The bundled envelope
+    pub fn envelope(&self) -> &CosignEnvelope {
+        &self.dsse_envelope
+    }
+
+    /// The hint naming the public key the envelope was signed with, if any
+    pub fn public_key_hint(&self) -> Option<&str> {
+        self.verification_material
+            .public_key
+            .as_ref()
+            .map(|key| key.hint.as_str())
+    }
+
+    /// The DER encoded certificates the envelope was signed with, leaf first
+    pub fn certificates(&self) -> Result<Vec<Vec<u8>>> {
+        let material = &self.verification_material;
+        material
+            .x509_certificate_chain
+            .iter()
+            .flat_map(|chain| chain.certificates.iter())
+            .chain(material.certificate.iter())
+            .map(|cert| decode_base64(&cert.raw_bytes))
+            .collect()
+    }
+
+    /// The certificates the envelope was signed with as PEM, leaf first
+    pub fn certificates_pem(&self) -> Result<Vec<String>> {
+        Ok(self
+            .certificates()?
+            .into_iter()
+            .map(|contents| {
+                pem::encode(&pem::Pem {
+                    tag: PEM_CERTIFICATE.to_string(),
+                    contents,
+                })
+                .replace("\r\n", "\n")
+            })
+            .collect())
+    }
+}
+
+/// Decode standard or URL-safe base64, as both are found in the wild.
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(encoded.as_bytes())
+        .or_else(|_| BASE64URL.decode(encoded.as_bytes()))
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{CosignBundle, CosignEnvelope};
+    use crate::crypto::KeyId;
+    use crate::models::{DSSEVersion, EnvelopeFile, PayloadCompression};
+    use crate::test_utils::key;
+
+    const KEY_ID: &str = "e0294a3f17cc8563c3ed5fceb3bd8d3f6bfeeaca499b5c9572729ae015566554";
+
+    // As written by cosign, the payload being `{"_type":"https://in-toto.io/Statement/v0.1"}`
+    const BUNDLE: &str = r#"{
+        "mediaType": "application/vnd.dev.sigstore.bundle+json;version=0.1",
+        "verificationMaterial": {
+            "x509CertificateChain": {"certificates": [{"rawBytes": "MIIBfw=="}]},
+            "tlogEntries": [{"logIndex": "42"}]
+        },
+        "dsseEnvelope": {
+            "payloadType": "application/vnd.in-toto+json",
+            "payload": "eyJfdHlwZSI6Imh0dHBzOi8vaW4tdG90by5pby9TdGF0ZW1lbnQvdjAuMSJ9",
+            "signatures": [{"sig": "MEUCIQ+/"}]
+        }
+    }"#;
+
+    #[test]
+    fn import_cosign_bundle() {
+        let bundle = CosignBundle::from_bytes(BUNDLE.as_bytes()).unwrap();
+        assert_eq!(
+            bundle.certificates().unwrap(),
+            [vec![0x30, 0x82, 0x01, 0x7f]]
+        );
+        assert!(bundle.certificates_pem().unwrap()[0].starts_with("-----BEGIN CERTIFICATE-----\n"));
+        assert_eq!(bundle.public_key_hint(), None);
+
+        // keyless signatures need a key ID
+        assert!(bundle.envelope().to_envelope(None).is_err());
+        let key_id = KeyId::from_str(KEY_ID).unwrap();
+        let envelope = bundle.envelope().to_envelope(Some(&key_id)).unwrap();
+        assert_eq!(
+            envelope.payload(),
+            r#"{"_type":"https://in-toto.io/Statement/v0.1"}"#
+        );
+        assert_eq!(envelope.payload_type(), "application/vnd.in-toto+json");
+        assert_eq!(envelope.signatures()[0].key_id(), &key_id);
+        assert_eq!(
+            envelope.signatures()[0].value().as_bytes(),
+            [0x30, 0x45, 0x02, 0x21, 0x0f, 0xbf]
+        );
+
+        // and back, now with the key ID
+        let exported = CosignEnvelope::from_envelope(&envelope);
+        assert_eq!(exported.payload(), bundle.envelope().payload());
+        assert_eq!(exported.signatures()[0].key_id(), KEY_ID);
+        assert_eq!(exported.signatures()[0].sig(), "MEUCIQ+/");
+        let env = CosignEnvelope::from_bytes(&exported.to_bytes().unwrap()).unwrap();
+        assert_eq!(env.to_envelope(None).unwrap(), envelope);
+
+        let rebundled = CosignBundle::new(exported, &bundle.certificates().unwrap());
+        let parsed = CosignBundle::from_bytes(&rebundled.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, rebundled);
+        assert_eq!(parsed.media_type(), bundle.media_type());
+        assert_eq!(
+            parsed.certificates().unwrap(),
+            bundle.certificates().unwrap()
+        );
+    }
+
+    #[test]
+    fn compressed_payloads() {
+        let signer = key("signer");
+        let payload = r#"{"_type":"https://in-toto.io/Statement/v0.1"}"#.repeat(10);
+        let payload_type = "application/vnd.in-toto+json".to_string();
+        let sig = signer
+            .sign(&DSSEVersion::V1.pack(payload.as_bytes(), payload_type.clone()))
+            .unwrap();
+        let envelope = EnvelopeFile::new(payload.clone(), payload_type, vec![sig]);
+
+        let compressed =
+            CosignEnvelope::from_envelope_compressed(&envelope, PayloadCompression::Gzip).unwrap();
+        assert_eq!(
+            compressed.payload_type(),
+            "application/vnd.in-toto+json+gzip"
+        );
+        assert!(
+            compressed.payload().len() < CosignEnvelope::from_envelope(&envelope).payload().len()
+        );
+        assert_eq!(compressed.compression(), Some(PayloadCompression::Gzip));
+        assert_eq!(compressed.decoded_payload().unwrap(), payload.as_bytes());
+        assert!(compressed.decoded_payload_with_limit(64).is_err());
+
+        // the signatures over the uncompressed payload still verify
+        let restored = compressed.to_envelope(None).unwrap();
+        assert_eq!(restored, envelope);
+        let message = DSSEVersion::V1.pack(
+            restored.payload().as_bytes(),
+            restored.payload_type().clone(),
+        );
+        assert!(signer
+            .public()
+            .verify(&message, &restored.signatures()[0])
+            .is_ok());
+
+        let zstd =
+            CosignEnvelope::from_envelope_compressed(&envelope, PayloadCompression::Zstd).unwrap();
+        assert_eq!(zstd.compression(), Some(PayloadCompression::Zstd));
+        assert_eq!(zstd.to_envelope(None).unwrap(), envelope);
+    }
+}
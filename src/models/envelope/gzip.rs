@@ -0,0 +1,508 @@
+//! gzip (RFC 1952) around DEFLATE (RFC 1951).
+//!
+//! Payloads are small JSON documents, so compression uses the fixed Huffman
+//! codes with a greedy LZ77 match search, which gets most of the gain on
+//! repetitive JSON. Decompression handles all block types and concatenated
+//! members, and stops as soon as the output exceeds the size limit.
+
+use crate::{Error, Result};
+
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order the code length code lengths of a dynamic block are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;
+
+/// Whether `bytes` start like a gzip stream.
+pub(super) fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Compress `data` into a single gzip member.
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        // no flags, no mtime, unknown OS
+        out: vec![MAGIC[0], MAGIC[1], METHOD_DEFLATE, 0, 0, 0, 0, 0, 0, 255],
+        bits: 0,
+        count: 0,
+    };
+    deflate(data, &mut writer);
+    let mut out = writer.finish();
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Decompress the gzip members in `bytes`, failing if the result would
+/// exceed `max_size` bytes.
+pub(crate) fn decompress(bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = bytes;
+    loop {
+        let start = out.len();
+        let body = skip_header(rest)?;
+        let mut reader = BitReader::new(body);
+        inflate(&mut reader, &mut out, max_size)?;
+        let trailer = body
+            .get(reader.pos..reader.pos + 8)
+            .ok_or_else(|| invalid("truncated trailer"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(invalid("checksum mismatch"));
+        }
+        rest = &body[reader.pos + 8..];
+        if rest.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decompress the raw deflate stream in `bytes`, as zip archives hold,
+/// failing if the result would exceed `max_size` bytes.
+pub(crate) fn inflate_raw(bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    inflate(&mut BitReader::new(bytes), &mut out, max_size)?;
+    Ok(out)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Encoding(format!("invalid gzip data: {}", reason))
+}
+
+/// The deflate stream after the gzip header at the start of `bytes`.
+fn skip_header(bytes: &[u8]) -> Result<&[u8]> {
+    let header = bytes.get(..10).ok_or_else(|| invalid("truncated header"))?;
+    if header[..2] != MAGIC || header[2] != METHOD_DEFLATE {
+        return Err(invalid("bad magic or method"));
+    }
+    let flags = header[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = bytes
+            .get(pos..pos + 2)
+            .ok_or_else(|| invalid("truncated header"))?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = bytes
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+                .ok_or_else(|| invalid("truncated header"))?;
+            pos += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    bytes.get(pos..).ok_or_else(|| invalid("truncated header"))
+}
+
+/// The CRC-32 of `data`, as gzip files and zip archives carry.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        while self.count < n {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| invalid("truncated deflate stream"))?;
+            self.bits |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.bits & ((1u32 << n) - 1);
+        self.bits = self.bits.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drop the bits left of the current byte.
+    fn align(&mut self) {
+        self.bits = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, by the number of codes and the symbols of
+/// each length.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i32;
+        for count in &counts[1..] {
+            left = left * 2 - *count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| invalid("incomplete Huffman code"));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(invalid("too many codes"));
+    }
+    let mut lengths = [0u8; 19];
+    for index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[*index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if i > 0 => (lengths[i - 1], 3 + reader.bits(2)? as usize),
+            17 => (0, 3 + reader.bits(3)? as usize),
+            18 => (0, 11 + reader.bits(7)? as usize),
+            _ => return Err(invalid("bad code length repeat")),
+        };
+        let run = lengths
+            .get_mut(i..i + repeat)
+            .ok_or_else(|| invalid("too many code lengths"))?;
+        run.fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("no end of block code"));
+    }
+    Ok((
+        Huffman::new(&lengths[..literals])?,
+        Huffman::new(&lengths[literals..])?,
+    ))
+}
+
+fn inflate(reader: &mut BitReader, out: &mut Vec<u8>, max_size: usize) -> Result<()> {
+    let start = out.len();
+    let too_large = || {
+        Error::Encoding(format!(
+            "decompressed payload exceeds the limit of {} bytes",
+            max_size
+        ))
+    };
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(invalid("bad stored block length"));
+                }
+                reader.pos += 4;
+                let block = reader
+                    .data
+                    .get(reader.pos..reader.pos + len as usize)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                if out.len() + block.len() > max_size {
+                    return Err(too_large());
+                }
+                out.extend_from_slice(block);
+                reader.pos += len as usize;
+            }
+            kind @ (1 | 2) => {
+                let (literals, distances) = match kind {
+                    1 => fixed_codes()?,
+                    _ => dynamic_codes(reader)?,
+                };
+                loop {
+                    let symbol = literals.decode(reader)? as usize;
+                    if symbol < 256 {
+                        if out.len() >= max_size {
+                            return Err(too_large());
+                        }
+                        out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        break;
+                    }
+                    let index = symbol - 257;
+                    let base = *LENGTH_BASE
+                        .get(index)
+                        .ok_or_else(|| invalid("bad length symbol"))?;
+                    let length = base as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                    let index = distances.decode(reader)? as usize;
+                    let base = *DISTANCE_BASE
+                        .get(index)
+                        .ok_or_else(|| invalid("bad distance symbol"))?;
+                    let distance =
+                        base as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                    if distance > out.len() - start {
+                        return Err(invalid("distance too far back"));
+                    }
+                    if out.len() + length > max_size {
+                        return Err(too_large());
+                    }
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+            _ => return Err(invalid("bad block type")),
+        }
+        if last {
+            // the trailer starts at the next byte
+            reader.align();
+            return Ok(());
+        }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Put a Huffman code, which is stored starting with its highest bit.
+    fn put_code(&mut self, code: u32, n: u32) {
+        self.put(code.reverse_bits() >> (32 - n), n)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn put_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.put_code(0x30 + symbol, 8),
+        144..=255 => writer.put_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.put_code(symbol - 256, 7),
+        _ => writer.put_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn put_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap_or(0);
+    put_literal(writer, 257 + index as u32);
+    writer.put(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index] as u32,
+    );
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap_or(0);
+    writer.put_code(index as u32, 5);
+    writer.put(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index] as u32,
+    );
+}
+
+/// Write `data` as a single block with the fixed Huffman codes.
+fn deflate(data: &[u8], writer: &mut BitWriter) {
+    // last block, fixed codes
+    writer.put(1, 1);
+    writer.put(1, 2);
+    let hash = |i: usize| {
+        (((data[i] as usize) << 10) ^ ((data[i + 1] as usize) << 5) ^ data[i + 2] as usize) & 0x7fff
+    };
+    let mut head = vec![usize::MAX; 0x8000];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            let mut candidate = head[h];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let max = MAX_MATCH.min(data.len() - i);
+                let length = (0..max)
+                    .take_while(|k| data[candidate + k] == data[i + k])
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+        let advance = if best.0 >= MIN_MATCH {
+            put_match(writer, best.0, best.1);
+            best.0
+        } else {
+            put_literal(writer, data[i] as u32);
+            1
+        };
+        let end = (i + advance).min((data.len() + 1).saturating_sub(MIN_MATCH));
+        for (j, previous) in previous.iter_mut().enumerate().take(end).skip(i) {
+            let h = hash(j);
+            *previous = head[h];
+            head[h] = j;
+        }
+        i += advance;
+    }
+    put_literal(writer, 256);
+}
+
+#[cfg(test)]
+mod test {
+    use data_encoding::BASE64;
+
+    use super::{compress, decompress};
+
+    // python3 -c 'import gzip, base64; print(base64.b64encode(
+    //   gzip.compress(b"{\"_type\": \"link\"}" * 20, mtime=0)).decode())'
+    const PYTHON_GZIP: &str = "H4sIAAAAAAACA6tWii+pLEhVslJQysnMy1aqrR4VoFQAADqjZ4JUAQAA";
+
+    #[test]
+    fn gzip_round_trip() {
+        let data = br#"{"_type": "link"}"#.repeat(20);
+        let python = BASE64.decode(PYTHON_GZIP.as_bytes()).unwrap();
+        assert_eq!(decompress(&python, 1 << 20).unwrap(), data);
+
+        for data in [
+            &b""[..],
+            b"a",
+            &data,
+            &(0..=255).cycle().take(70_000).collect::<Vec<u8>>(),
+        ] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed, 1 << 20).unwrap(), data);
+        }
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len() / 4);
+
+        // concatenated members, limits and corruption
+        let twice = [compressed.clone(), compressed.clone()].concat();
+        assert_eq!(decompress(&twice, 1 << 20).unwrap(), data.repeat(2));
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+        let mut corrupt = compressed.clone();
+        corrupt[12] ^= 0xff;
+        assert!(decompress(&corrupt, 1 << 20).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1], 1 << 20).is_err());
+    }
+}
@@ -4,8 +4,20 @@ use strum_macros::EnumIter;
 use self::pae_v1::PaeV1;
 use crate::{Error, Result};
 
+mod compression;
+mod convert;
+mod cosign;
 mod envelope_file;
+pub(crate) mod gzip;
 mod pae_v1;
+mod zstd;
+
+pub use compression::PayloadCompression;
+pub use convert::{
+    envelope_to_metablock, link_to_statement, metablock_to_envelope, statement_to_link,
+};
+pub use cosign::{CosignBundle, CosignEnvelope, CosignSignature, SIGSTORE_BUNDLE_MEDIA_TYPE};
+pub use envelope_file::EnvelopeFile;
 
 pub trait DSSEParser {
     fn pae_pack(payload_ver: String, payload: &[u8]) -> Vec<u8>;
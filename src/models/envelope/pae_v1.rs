@@ -39,7 +39,7 @@ impl DSSEParser for PaeV1 {
             "{prefix}{split}{payload_ver_len}{split}{payload_ver}{split}{payload_len}{split}",
             prefix = PREFIX,
             split = SPLIT,
-            payload_ver_len = payload_ver.as_bytes().len(),
+            payload_ver_len = payload_ver.len(),
             payload_ver = payload_ver.as_str(),
             payload_len = payload.len(),
         );
@@ -60,21 +60,26 @@ impl DSSEParser for PaeV1 {
                 ))
             })?;
 
-        // Extract payload_ver from bytes
+        // Extract payload_ver from bytes, the lengths are untrusted
         let (payload_ver_len, raw) = consume_load_len(raw)?;
-        let payload_ver = str::from_utf8(&raw[0..payload_ver_len])?
-            .parse::<String>()
-            .map_err(|_| Error::PAEParseFailed(format!("parse to string failed for {:?}", raw)))?;
+        let truncated = || Error::PAEParseFailed(format!("truncated payload in {:?}", bytes));
+        let payload_ver = raw.get(..payload_ver_len).ok_or_else(truncated)?;
+        let payload_ver = str::from_utf8(payload_ver)?.to_string();
+        let raw = raw
+            .get(payload_ver_len..)
+            .and_then(|raw| raw.strip_prefix(SPLIT.as_bytes()))
+            .ok_or_else(truncated)?;
 
         // Extract payload from bytes
-        let (payload_len, raw) = consume_load_len(&raw[(payload_ver_len + 1)..])?;
-        let payload = raw[0..payload_len].to_vec();
+        let (payload_len, raw) = consume_load_len(raw)?;
+        let payload = raw.get(..payload_len).ok_or_else(truncated)?.to_vec();
 
         Ok((payload, payload_ver))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::let_and_return)]
 mod pae_test {
     use std::collections::HashMap;
     use std::str;
@@ -128,4 +133,21 @@ mod pae_test {
             assert_eq!(real, right, "unpack assert failed for {}", file_tuple.name);
         }
     }
+
+    #[test]
+    fn test_unpack_malformed() {
+        for outer in [
+            "DSSEv1 40 link 0 ",
+            "DSSEv1 18446744073709551615 link 0 ",
+            "DSSEv1 4 link",
+            "DSSEv1 4 linkx0 ",
+            "DSSEv1 4 link 5 abc",
+        ] {
+            assert!(
+                DSSEVersion::V1.unpack(outer.as_bytes()).is_err(),
+                "{}",
+                outer
+            );
+        }
+    }
 }
@@ -0,0 +1,1252 @@
+//! Zstandard (RFC 8878).
+//!
+//! As with gzip, payloads are small JSON documents, so compression keeps the
+//! literals raw and codes the matches a greedy LZ77 search finds with the
+//! predefined codes of sequences, which gets most of the gain on repetitive
+//! JSON. Decompression handles all block, literals and code types, and
+//! concatenated and skippable frames, but not dictionaries, and stops as
+//! soon as the output exceeds the size limit.
+
+use crate::{Error, Result};
+
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// The magic number of skippable frames, with any value in the low 4 bits.
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a50;
+const FCS_SINGLE_SEGMENT: u8 = 0x20;
+const FCS_RESERVED: u8 = 0x08;
+const FCS_CHECKSUM: u8 = 0x04;
+
+const BLOCK_RAW: u32 = 0;
+const BLOCK_RLE: u32 = 1;
+const BLOCK_COMPRESSED: u32 = 2;
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+const MAX_HUFFMAN_BITS: u32 = 11;
+const MAX_HUFFMAN_WEIGHTS_LOG: u32 = 6;
+
+const LITERALS_LENGTH_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LITERALS_LENGTH_EXTRA: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const MATCH_LENGTH_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const MATCH_LENGTH_EXTRA: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+const MAX_OFFSET_CODE: usize = 31;
+
+/// The predefined distributions of literals lengths, offsets and match
+/// lengths codes, with their accuracy logs.
+const LITERALS_LENGTH_DISTRIBUTION: (&[i16], u32) = (
+    &[
+        4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1,
+        1, 1, -1, -1, -1, -1,
+    ],
+    6,
+);
+const OFFSET_DISTRIBUTION: (&[i16], u32) = (
+    &[
+        1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+    ],
+    5,
+);
+const MATCH_LENGTH_DISTRIBUTION: (&[i16], u32) = (
+    &[
+        1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+    ],
+    6,
+);
+/// The largest symbol and accuracy log of the codes of literals lengths,
+/// offsets and match lengths, in the order they are described in blocks.
+const SEQUENCE_CODES: [(usize, u32); 3] = [(35, 9), (MAX_OFFSET_CODE, 8), (52, 9)];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 131_074;
+const MAX_CHAIN: usize = 64;
+const HASH_SIZE: usize = 1 << 16;
+
+/// Whether `bytes` start like a zstd frame.
+pub(super) fn is_zstd(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Compress `data` into a single zstd frame with its content size and
+/// checksum.
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    let size = data.len() as u64;
+    let (flag, content_size) = if size < 256 {
+        (0u8, vec![size as u8])
+    } else if size < 256 + 65536 {
+        (1, ((size - 256) as u16).to_le_bytes().to_vec())
+    } else if size <= u32::MAX as u64 {
+        (2, (size as u32).to_le_bytes().to_vec())
+    } else {
+        (3, size.to_le_bytes().to_vec())
+    };
+    out.push(flag << 6 | FCS_SINGLE_SEGMENT | FCS_CHECKSUM);
+    out.extend_from_slice(&content_size);
+
+    let mut matcher = Matcher::new(data);
+    let mut start = 0;
+    loop {
+        let end = data.len().min(start + MAX_BLOCK_SIZE);
+        let last = (end == data.len()) as u32;
+        match compress_block(data, start, end, &mut matcher) {
+            Some(block) if block.len() < end - start => {
+                put_block_header(&mut out, last, BLOCK_COMPRESSED, block.len());
+                out.extend_from_slice(&block);
+            }
+            _ => {
+                put_block_header(&mut out, last, BLOCK_RAW, end - start);
+                out.extend_from_slice(&data[start..end]);
+            }
+        }
+        if last == 1 {
+            break;
+        }
+        start = end;
+    }
+    out.extend_from_slice(&(xxh64(data) as u32).to_le_bytes());
+    out
+}
+
+/// Decompress the zstd frames in `bytes`, failing if the result would
+/// exceed `max_size` bytes.
+pub(super) fn decompress(bytes: &[u8], max_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = Bytes::new(bytes);
+    loop {
+        let magic = rest.take(4)?;
+        let magic = u32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]);
+        if magic & !0xf == SKIPPABLE_MAGIC {
+            let size = rest.uint(4)? as usize;
+            rest.take(size)?;
+        } else if magic.to_le_bytes() == MAGIC {
+            decompress_frame(&mut rest, &mut out, max_size)?;
+        } else {
+            return Err(invalid("bad magic"));
+        }
+        if rest.is_empty() {
+            return Ok(out);
+        }
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Encoding(format!("invalid zstd data: {}", reason))
+}
+
+/// Fails if `out` cannot grow by `len` bytes within `max_size`.
+fn reserve(out: &[u8], len: usize, max_size: usize) -> Result<()> {
+    match out.len().checked_add(len) {
+        Some(size) if size <= max_size => Ok(()),
+        _ => Err(Error::Encoding(format!(
+            "decompressed payload exceeds the limit of {} bytes",
+            max_size
+        ))),
+    }
+}
+
+/// The state decoding blocks shares within a frame.
+struct Frame {
+    /// Where the output of the frame starts
+    start: usize,
+    huffman: Option<Huffman>,
+    /// The last codes of literals lengths, offsets and match lengths
+    codes: [Option<Fse>; 3],
+    offsets: [usize; 3],
+}
+
+fn decompress_frame(bytes: &mut Bytes, out: &mut Vec<u8>, max_size: usize) -> Result<()> {
+    let descriptor = bytes.byte()?;
+    if descriptor & FCS_RESERVED != 0 {
+        return Err(invalid("reserved bit set"));
+    }
+    let single_segment = descriptor & FCS_SINGLE_SEGMENT != 0;
+    if !single_segment {
+        // the window descriptor, any window is accepted as the output
+        // is kept anyway
+        bytes.byte()?;
+    }
+    let dictionary = bytes.uint([0, 1, 2, 4][(descriptor & 3) as usize])?;
+    if dictionary != 0 {
+        return Err(Error::Encoding(
+            "zstd payloads with dictionaries are not supported".into(),
+        ));
+    }
+    let content_size = match (descriptor >> 6, single_segment) {
+        (0, false) => None,
+        (0, true) => Some(bytes.uint(1)?),
+        (1, _) => Some(bytes.uint(2)? + 256),
+        (2, _) => Some(bytes.uint(4)?),
+        _ => Some(bytes.uint(8)?),
+    };
+
+    let mut frame = Frame {
+        start: out.len(),
+        huffman: None,
+        codes: [None, None, None],
+        offsets: [1, 4, 8],
+    };
+    loop {
+        let header = bytes.uint(3)? as u32;
+        let size = (header >> 3) as usize;
+        match (header >> 1) & 3 {
+            BLOCK_RAW => {
+                let block = bytes.take(size)?;
+                reserve(out, size, max_size)?;
+                out.extend_from_slice(block);
+            }
+            BLOCK_RLE => {
+                let byte = bytes.byte()?;
+                reserve(out, size, max_size)?;
+                out.resize(out.len() + size, byte);
+            }
+            BLOCK_COMPRESSED if size <= MAX_BLOCK_SIZE => {
+                let block = bytes.take(size)?;
+                decompress_block(block, &mut frame, out, max_size)?;
+            }
+            _ => return Err(invalid("bad block")),
+        }
+        if header & 1 == 1 {
+            break;
+        }
+    }
+
+    let content = &out[frame.start..];
+    if content_size.is_some_and(|size| size != content.len() as u64) {
+        return Err(invalid("content size mismatch"));
+    }
+    if descriptor & FCS_CHECKSUM != 0 {
+        let checksum = bytes.uint(4)? as u32;
+        if checksum != xxh64(content) as u32 {
+            return Err(invalid("checksum mismatch"));
+        }
+    }
+    Ok(())
+}
+
+fn decompress_block(
+    block: &[u8],
+    frame: &mut Frame,
+    out: &mut Vec<u8>,
+    max_size: usize,
+) -> Result<()> {
+    let mut bytes = Bytes::new(block);
+    let literals = read_literals(&mut bytes, &mut frame.huffman)?;
+    let mut literals = &literals[..];
+    for (literals_length, offset, match_length) in read_sequences(&mut bytes, frame)? {
+        if literals_length > literals.len() {
+            return Err(invalid("literals length too long"));
+        }
+        let (copied, rest) = literals.split_at(literals_length);
+        reserve(out, literals_length + match_length, max_size)?;
+        out.extend_from_slice(copied);
+        literals = rest;
+        let start = out
+            .len()
+            .checked_sub(offset)
+            .filter(|start| *start >= frame.start)
+            .ok_or_else(|| invalid("offset too far back"))?;
+        if offset >= match_length {
+            out.extend_from_within(start..start + match_length);
+        } else {
+            for i in 0..match_length {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    reserve(out, literals.len(), max_size)?;
+    out.extend_from_slice(literals);
+    Ok(())
+}
+
+/// The literals section at the start of a compressed block, decoded with
+/// the Huffman code it describes or the last one described in the frame.
+fn read_literals(bytes: &mut Bytes, huffman: &mut Option<Huffman>) -> Result<Vec<u8>> {
+    let first = bytes.byte()?;
+    let kind = first & 3;
+    let format = (first >> 2) & 3;
+    if kind < 2 {
+        let size = match format {
+            0 | 2 => (first >> 3) as usize,
+            1 => (first >> 4) as usize | (bytes.byte()? as usize) << 4,
+            _ => (first >> 4) as usize | (bytes.uint(2)? as usize) << 4,
+        };
+        return match kind {
+            0 => Ok(bytes.take(size)?.to_vec()),
+            _ => Ok(vec![bytes.byte()?; size]),
+        };
+    }
+
+    let (streams, header_size, bits) = match format {
+        0 => (1, 2, 10),
+        1 => (4, 2, 10),
+        2 => (4, 3, 14),
+        _ => (4, 4, 18),
+    };
+    let header = (first >> 4) as u64 | bytes.uint(header_size)? << 4;
+    let mask = (1 << bits) - 1;
+    let regenerated = (header & mask) as usize;
+    let compressed = (header >> bits & mask) as usize;
+    if regenerated > MAX_BLOCK_SIZE {
+        return Err(invalid("literals too long"));
+    }
+    let mut data = bytes.take(compressed)?;
+    if kind == 2 {
+        let (table, used) = Huffman::read(data)?;
+        *huffman = Some(table);
+        data = &data[used..];
+    }
+    let huffman = huffman
+        .as_ref()
+        .ok_or_else(|| invalid("literals reuse a missing Huffman code"))?;
+
+    let mut literals = Vec::with_capacity(regenerated);
+    if streams == 1 {
+        huffman.decode(data, regenerated, &mut literals)?;
+        return Ok(literals);
+    }
+    let mut jump = Bytes::new(data);
+    let sizes = [jump.uint(2)?, jump.uint(2)?, jump.uint(2)?];
+    let each = regenerated.div_ceil(4);
+    let last = regenerated
+        .checked_sub(3 * each)
+        .ok_or_else(|| invalid("literals too short for four streams"))?;
+    for size in sizes {
+        huffman.decode(jump.take(size as usize)?, each, &mut literals)?;
+    }
+    huffman.decode(jump.take(jump.remaining())?, last, &mut literals)?;
+    Ok(literals)
+}
+
+/// The sequences section after the literals of a compressed block, as the
+/// literals length, offset and match length of each sequence.
+fn read_sequences(bytes: &mut Bytes, frame: &mut Frame) -> Result<Vec<(usize, usize, usize)>> {
+    let count = match bytes.byte()? as usize {
+        0 => return Ok(Vec::new()),
+        first @ 1..=127 => first,
+        first @ 128..=254 => ((first - 128) << 8) + bytes.byte()? as usize,
+        _ => bytes.uint(2)? as usize + 0x7f00,
+    };
+    let modes = bytes.byte()?;
+    if modes & 3 != 0 {
+        return Err(invalid("reserved bits set"));
+    }
+    let predefined = [
+        LITERALS_LENGTH_DISTRIBUTION,
+        OFFSET_DISTRIBUTION,
+        MATCH_LENGTH_DISTRIBUTION,
+    ];
+    for (i, ((max_symbol, max_log), (probabilities, log))) in
+        SEQUENCE_CODES.iter().zip(predefined).enumerate()
+    {
+        let code = match (modes >> (6 - 2 * i)) & 3 {
+            0 => Fse::new(probabilities, log)?,
+            1 => match bytes.byte()? {
+                symbol if symbol as usize <= *max_symbol => Fse::rle(symbol),
+                _ => return Err(invalid("bad symbol")),
+            },
+            2 => {
+                let (probabilities, log, used) =
+                    read_distribution(bytes.rest(), *max_symbol, *max_log)?;
+                bytes.take(used)?;
+                Fse::new(&probabilities, log)?
+            }
+            _ => frame.codes[i]
+                .take()
+                .ok_or_else(|| invalid("sequences reuse a missing code"))?,
+        };
+        frame.codes[i] = Some(code);
+    }
+    let (literals_lengths, offsets, match_lengths) = match &frame.codes {
+        [Some(literals_lengths), Some(offsets), Some(match_lengths)] => {
+            (literals_lengths, offsets, match_lengths)
+        }
+        _ => return Err(Error::Programming("zstd sequence codes missing".into())),
+    };
+
+    let mut bits = BackwardBits::new(bytes.take(bytes.remaining())?)?;
+    let mut states = [
+        bits.bits(literals_lengths.log)? as usize,
+        bits.bits(offsets.log)? as usize,
+        bits.bits(match_lengths.log)? as usize,
+    ];
+    let mut sequences = Vec::with_capacity(count);
+    for i in 0..count {
+        let entries = [
+            literals_lengths.entry(states[0])?,
+            offsets.entry(states[1])?,
+            match_lengths.entry(states[2])?,
+        ];
+        let code = entries[1].symbol as u32;
+        let offset_value = (1usize << code) + bits.bits(code)? as usize;
+        let code = entries[2].symbol as usize;
+        let match_length =
+            (MATCH_LENGTH_BASE[code] + bits.bits(MATCH_LENGTH_EXTRA[code])? as u32) as usize;
+        let code = entries[0].symbol as usize;
+        let literals_length =
+            (LITERALS_LENGTH_BASE[code] + bits.bits(LITERALS_LENGTH_EXTRA[code])? as u32) as usize;
+
+        let [first, second, third] = frame.offsets;
+        let offset = if offset_value > 3 {
+            frame.offsets = [offset_value - 3, first, second];
+            offset_value - 3
+        } else {
+            // one of the last offsets, shifted by one after no literals
+            match offset_value - 1 + (literals_length == 0) as usize {
+                0 => first,
+                1 => {
+                    frame.offsets = [second, first, third];
+                    second
+                }
+                2 => {
+                    frame.offsets = [third, first, second];
+                    third
+                }
+                _ => {
+                    frame.offsets = [first.wrapping_sub(1), first, second];
+                    first.wrapping_sub(1)
+                }
+            }
+        };
+        if offset == 0 || offset == usize::MAX {
+            return Err(invalid("zero offset"));
+        }
+        sequences.push((literals_length, offset, match_length));
+
+        if i + 1 < count {
+            for index in [0, 2, 1] {
+                let entry = entries[index];
+                states[index] = entry.baseline as usize + bits.bits(entry.bits as u32)? as usize;
+            }
+        }
+    }
+    if !bits.is_empty() {
+        return Err(invalid("sequences bitstream not consumed"));
+    }
+    Ok(sequences)
+}
+
+/// The normalized probabilities, accuracy log and size of the distribution
+/// described at the start of `data`.
+fn read_distribution(
+    data: &[u8],
+    max_symbol: usize,
+    max_log: u32,
+) -> Result<(Vec<i16>, u32, usize)> {
+    let mut bits = ForwardBits::new(data);
+    let log = bits.bits(4) as u32 + 5;
+    if log > max_log {
+        return Err(invalid("accuracy log too large"));
+    }
+    let mut remaining = (1i32 << log) + 1;
+    let mut threshold = 1i32 << log;
+    let mut width = log + 1;
+    let mut probabilities = Vec::new();
+    while remaining > 1 {
+        if probabilities.len() > max_symbol {
+            return Err(invalid("too many symbols"));
+        }
+        let max = 2 * threshold - 1 - remaining;
+        let low = bits.peek(width - 1) as i32;
+        let value = if low < max {
+            bits.skip(width - 1);
+            low
+        } else {
+            let value = bits.peek(width) as i32;
+            bits.skip(width);
+            if value >= threshold {
+                value - max
+            } else {
+                value
+            }
+        };
+        let probability = value - 1;
+        remaining -= probability.abs();
+        probabilities.push(probability as i16);
+        if probability == 0 {
+            loop {
+                let repeat = bits.bits(2);
+                probabilities.extend((0..repeat).map(|_| 0));
+                if repeat != 3 {
+                    break;
+                }
+            }
+        }
+        if remaining < 1 {
+            return Err(invalid("probabilities too large"));
+        }
+        while remaining < threshold {
+            width -= 1;
+            threshold >>= 1;
+        }
+    }
+    let used = bits.pos.div_ceil(8);
+    if probabilities.len() > max_symbol + 1 || used > data.len() {
+        return Err(invalid("bad distribution"));
+    }
+    Ok((probabilities, log, used))
+}
+
+/// The symbols of the states of the FSE table of `probabilities`, see RFC
+/// 8878 section 4.1.1.
+fn spread_symbols(probabilities: &[i16], log: u32) -> Result<Vec<u8>> {
+    let size = 1usize << log;
+    let total: usize = probabilities
+        .iter()
+        .map(|p| p.unsigned_abs() as usize)
+        .sum();
+    if total != size || probabilities.len() > 256 {
+        return Err(invalid("probabilities do not add up"));
+    }
+    let mut symbols = vec![0; size];
+    let mut high = size;
+    for (symbol, probability) in probabilities.iter().enumerate() {
+        if *probability == -1 {
+            high -= 1;
+            symbols[high] = symbol as u8;
+        }
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut position = 0;
+    for (symbol, probability) in probabilities.iter().enumerate() {
+        for _ in 0..(*probability).max(0) {
+            symbols[position] = symbol as u8;
+            position = (position + step) & (size - 1);
+            while position >= high {
+                position = (position + step) & (size - 1);
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// The number of bits below the highest set bit of `value`.
+fn highest_bit(value: u32) -> u32 {
+    31 - value.leading_zeros()
+}
+
+#[derive(Clone, Copy)]
+struct FseEntry {
+    symbol: u8,
+    bits: u8,
+    baseline: u16,
+}
+
+/// A finite state entropy decoding table.
+#[derive(Clone)]
+struct Fse {
+    log: u32,
+    entries: Vec<FseEntry>,
+}
+
+impl Fse {
+    fn new(probabilities: &[i16], log: u32) -> Result<Self> {
+        let symbols = spread_symbols(probabilities, log)?;
+        let mut next: Vec<u32> = probabilities
+            .iter()
+            .map(|p| p.unsigned_abs() as u32)
+            .collect();
+        let entries = symbols
+            .iter()
+            .map(|symbol| {
+                let state = next[*symbol as usize];
+                next[*symbol as usize] += 1;
+                let bits = log - highest_bit(state);
+                FseEntry {
+                    symbol: *symbol,
+                    bits: bits as u8,
+                    baseline: ((state << bits) - (1 << log)) as u16,
+                }
+            })
+            .collect();
+        Ok(Fse { log, entries })
+    }
+
+    /// The table of a code that is always `symbol`.
+    fn rle(symbol: u8) -> Self {
+        Fse {
+            log: 0,
+            entries: vec![FseEntry {
+                symbol,
+                bits: 0,
+                baseline: 0,
+            }],
+        }
+    }
+
+    fn entry(&self, state: usize) -> Result<FseEntry> {
+        self.entries
+            .get(state)
+            .copied()
+            .ok_or_else(|| invalid("bad state"))
+    }
+}
+
+/// A Huffman code of literals, as a table from the next `max_bits` bits of
+/// a stream to the symbol they start with and its length.
+struct Huffman {
+    max_bits: u32,
+    entries: Vec<(u8, u8)>,
+}
+
+impl Huffman {
+    /// The code described at the start of `data`, and the size of its
+    /// description.
+    fn read(data: &[u8]) -> Result<(Self, usize)> {
+        let header = *data.first().ok_or_else(|| invalid("truncated literals"))? as usize;
+        let (mut weights, used) = if header < 128 {
+            let description = data
+                .get(1..1 + header)
+                .ok_or_else(|| invalid("truncated Huffman code"))?;
+            (read_weights(description)?, 1 + header)
+        } else {
+            let count = header - 127;
+            let packed = data
+                .get(1..1 + count.div_ceil(2))
+                .ok_or_else(|| invalid("truncated Huffman code"))?;
+            let weights = (0..count)
+                .map(|i| match i % 2 {
+                    0 => packed[i / 2] >> 4,
+                    _ => packed[i / 2] & 0xf,
+                })
+                .collect();
+            (weights, 1 + count.div_ceil(2))
+        };
+
+        // the weight of the last symbol completes the code
+        if weights.len() > 255 || weights.iter().any(|w| *w as u32 > MAX_HUFFMAN_BITS) {
+            return Err(invalid("bad Huffman weights"));
+        }
+        let total: u32 = weights
+            .iter()
+            .filter(|w| **w > 0)
+            .map(|w| 1 << (w - 1))
+            .sum();
+        if total == 0 {
+            return Err(invalid("bad Huffman weights"));
+        }
+        let max_bits = highest_bit(total) + 1;
+        let rest = (1 << max_bits) - total;
+        if max_bits > MAX_HUFFMAN_BITS || !rest.is_power_of_two() {
+            return Err(invalid("incomplete Huffman code"));
+        }
+        weights.push(highest_bit(rest) as u8 + 1);
+
+        let mut entries = Vec::with_capacity(1 << max_bits);
+        for weight in 1..=max_bits as u8 {
+            for (symbol, _) in weights.iter().enumerate().filter(|(_, w)| **w == weight) {
+                let length = (max_bits + 1 - weight as u32) as u8;
+                entries.extend((0..1 << (weight - 1)).map(|_| (symbol as u8, length)));
+            }
+        }
+        Ok((Huffman { max_bits, entries }, used))
+    }
+
+    /// Decode the `count` literals of the stream `data` into `out`.
+    fn decode(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> Result<()> {
+        let mut bits = BackwardBits::new(data)?;
+        for _ in 0..count {
+            let (symbol, length) = self.entries[bits.peek(self.max_bits) as usize];
+            bits.bits(length as u32)?;
+            out.push(symbol);
+        }
+        if !bits.is_empty() {
+            return Err(invalid("literals stream not consumed"));
+        }
+        Ok(())
+    }
+}
+
+/// The Huffman weights FSE compressed in `data`, decoded with two states
+/// taking turns until the stream ends.
+fn read_weights(data: &[u8]) -> Result<Vec<u8>> {
+    let (probabilities, log, used) = read_distribution(data, 255, MAX_HUFFMAN_WEIGHTS_LOG)?;
+    let fse = Fse::new(&probabilities, log)?;
+    let mut bits = BackwardBits::new(&data[used..])?;
+    let mut states = [bits.bits(log)? as usize, bits.bits(log)? as usize];
+    let mut weights = Vec::new();
+    for turn in [0, 1].iter().cycle() {
+        let entry = fse.entry(states[*turn])?;
+        weights.push(entry.symbol);
+        if weights.len() > 255 {
+            return Err(invalid("too many Huffman weights"));
+        }
+        let overflow = entry.bits as usize > bits.pos;
+        states[*turn] = entry.baseline as usize + bits.padded(entry.bits as u32) as usize;
+        if overflow {
+            weights.push(fse.entry(states[1 - *turn])?.symbol);
+            break;
+        }
+    }
+    Ok(weights)
+}
+
+/// Reading a frame byte by byte.
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Bytes { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.data.len() {
+            return Err(invalid("truncated frame"));
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A little endian integer of `n` bytes.
+    fn uint(&mut self, n: usize) -> Result<u64> {
+        Ok(self
+            .take(n)?
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u64))
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+/// Reading bits from the start of a little endian bitstream, as
+/// distributions are described.
+struct ForwardBits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ForwardBits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ForwardBits { data, pos: 0 }
+    }
+
+    /// The next `n` bits, zeros past the end.
+    fn peek(&self, n: u32) -> u64 {
+        (0..n as usize).fold(0, |value, i| {
+            let pos = self.pos + i;
+            let bit = self
+                .data
+                .get(pos / 8)
+                .map_or(0, |byte| (byte >> (pos % 8)) & 1);
+            value | (bit as u64) << i
+        })
+    }
+
+    fn skip(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    fn bits(&mut self, n: u32) -> u64 {
+        let value = self.peek(n);
+        self.skip(n);
+        value
+    }
+}
+
+/// Reading bits from the end of a little endian bitstream, after the
+/// padding up to its highest set bit, as Huffman coded literals and
+/// sequences are stored.
+struct BackwardBits<'a> {
+    data: &'a [u8],
+    /// The number of bits left to read
+    pos: usize,
+}
+
+impl<'a> BackwardBits<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        match data.last() {
+            Some(last) if *last != 0 => Ok(BackwardBits {
+                data,
+                pos: data.len() * 8 - last.leading_zeros() as usize - 1,
+            }),
+            _ => Err(invalid("bitstream without end mark")),
+        }
+    }
+
+    /// The next `n` bits, up to 56, with zeros past the start.
+    fn peek(&self, n: u32) -> u64 {
+        let n = n as usize;
+        if n == 0 || self.pos == 0 {
+            return 0;
+        }
+        let start = self.pos.saturating_sub(n);
+        let first = start / 8;
+        let word = self.data[first..=(self.pos - 1) / 8]
+            .iter()
+            .rev()
+            .fold(0u64, |word, byte| word << 8 | *byte as u64);
+        let available = self.pos - start;
+        let value = (word >> (start - first * 8)) & ((1u64 << available) - 1);
+        value << (n - available)
+    }
+
+    /// The next `n` bits, failing past the start.
+    fn bits(&mut self, n: u32) -> Result<u64> {
+        if n as usize > self.pos {
+            return Err(invalid("truncated bitstream"));
+        }
+        Ok(self.padded(n))
+    }
+
+    /// The next `n` bits, with zeros past the start.
+    fn padded(&mut self, n: u32) -> u64 {
+        let value = self.peek(n);
+        self.pos = self.pos.saturating_sub(n as usize);
+        value
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+}
+
+/// Writing a little endian bitstream ending with a mark, to be read with
+/// `BackwardBits`.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            out: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Put the low `n` bits of `value`, up to 32.
+    fn put(&mut self, value: u64, n: u32) {
+        self.bits |= (value & ((1 << n) - 1)) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.put(1, 1);
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// A finite state entropy encoding table, see `FSE_buildCTable` of the
+/// reference implementation.
+struct FseEncoder {
+    log: u32,
+    states: Vec<u32>,
+    /// The state offset and bit count deltas of each symbol
+    symbols: Vec<(i32, u32)>,
+}
+
+impl FseEncoder {
+    fn new((probabilities, log): (&[i16], u32)) -> Result<Self> {
+        let size = 1u32 << log;
+        let symbols = spread_symbols(probabilities, log)?;
+        let mut next: Vec<u32> = probabilities
+            .iter()
+            .scan(0, |total, p| {
+                let start = *total;
+                *total += p.unsigned_abs() as u32;
+                Some(start)
+            })
+            .collect();
+        let mut states = vec![0; size as usize];
+        for (position, symbol) in symbols.iter().enumerate() {
+            states[next[*symbol as usize] as usize] = size + position as u32;
+            next[*symbol as usize] += 1;
+        }
+        let mut total = 0i32;
+        let symbols = probabilities
+            .iter()
+            .map(|probability| match *probability {
+                0 => (0, ((log + 1) << 16) - size),
+                -1 | 1 => {
+                    total += 1;
+                    (total - 2, (log << 16) - size)
+                }
+                probability => {
+                    let probability = probability as u32;
+                    let max_bits = log - highest_bit(probability - 1);
+                    let delta = (
+                        total - probability as i32,
+                        (max_bits << 16) - (probability << max_bits),
+                    );
+                    total += probability as i32;
+                    delta
+                }
+            })
+            .collect();
+        Ok(FseEncoder {
+            log,
+            states,
+            symbols,
+        })
+    }
+
+    /// The state to start encoding with, ending with `symbol`.
+    fn start(&self, symbol: u8) -> u32 {
+        let (find, delta) = self.symbols[symbol as usize];
+        let bits = (delta + (1 << 15)) >> 16;
+        let value = (bits << 16) - delta;
+        self.states[((value >> bits) as i32 + find) as usize]
+    }
+
+    /// Encode `symbol`, moving from `state` to the state before it.
+    fn encode(&self, state: &mut u32, symbol: u8, writer: &mut BitWriter) {
+        let (find, delta) = self.symbols[symbol as usize];
+        let bits = (*state + delta) >> 16;
+        writer.put(*state as u64, bits);
+        *state = self.states[((*state >> bits) as i32 + find) as usize];
+    }
+
+    fn finish(&self, state: u32, writer: &mut BitWriter) {
+        writer.put(state as u64, self.log);
+    }
+}
+
+/// The code of `value` in a table of `bases`, and its extra bits.
+fn length_code(value: u32, bases: &[u32], extra: &[u32]) -> (u8, u64, u32) {
+    let code = bases.iter().rposition(|base| *base <= value).unwrap_or(0);
+    (code as u8, (value - bases[code]) as u64, extra[code])
+}
+
+fn put_block_header(out: &mut Vec<u8>, last: u32, kind: u32, size: usize) {
+    let header = last | kind << 1 | (size as u32) << 3;
+    out.extend_from_slice(&header.to_le_bytes()[..3]);
+}
+
+/// The hash chains of the positions of `data` by their first bytes.
+struct Matcher<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    previous: Vec<usize>,
+    /// The positions below are in the chains
+    inserted: usize,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Matcher {
+            data,
+            head: vec![usize::MAX; HASH_SIZE],
+            previous: vec![usize::MAX; data.len()],
+            inserted: 0,
+        }
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let [a, b, c] = [self.data[i], self.data[i + 1], self.data[i + 2]];
+        ((a as usize) << 8 ^ (b as usize) << 4 ^ c as usize ^ (a as usize) << 12) & (HASH_SIZE - 1)
+    }
+
+    /// Add the positions up to `end` to the chains.
+    fn insert(&mut self, end: usize) {
+        let end = end.min((self.data.len() + 1).saturating_sub(MIN_MATCH));
+        while self.inserted < end {
+            let h = self.hash(self.inserted);
+            self.previous[self.inserted] = self.head[h];
+            self.head[h] = self.inserted;
+            self.inserted += 1;
+        }
+    }
+
+    /// The length and offset of the longest match at `i` ending by `end`.
+    fn find(&self, i: usize, end: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if i + MIN_MATCH > end {
+            return best;
+        }
+        let max = MAX_MATCH.min(end - i);
+        let mut candidate = self.head[self.hash(i)];
+        let mut chain = 0;
+        while candidate != usize::MAX && chain < MAX_CHAIN {
+            let length = (0..max)
+                .take_while(|k| self.data[candidate + k] == self.data[i + k])
+                .count();
+            if length > best.0 {
+                best = (length, i - candidate);
+            }
+            candidate = self.previous[candidate];
+            chain += 1;
+        }
+        best
+    }
+}
+
+/// The compressed block of `data[start..end]`, with raw literals and the
+/// predefined codes of sequences, if it has any matches.
+fn compress_block(data: &[u8], start: usize, end: usize, matcher: &mut Matcher) -> Option<Vec<u8>> {
+    let mut literals = Vec::new();
+    let mut sequences = Vec::new();
+    let (mut anchor, mut i) = (start, start);
+    while i < end {
+        matcher.insert(i);
+        let (length, offset) = matcher.find(i, end);
+        if length >= MIN_MATCH {
+            literals.extend_from_slice(&data[anchor..i]);
+            sequences.push(((i - anchor) as u32, (offset + 3) as u32, length as u32));
+            i += length;
+            anchor = i;
+        } else {
+            i += 1;
+        }
+    }
+    matcher.insert(end);
+    if sequences.is_empty() {
+        return None;
+    }
+    literals.extend_from_slice(&data[anchor..end]);
+
+    // raw literals with a size of 20 bits
+    let size = literals.len() as u32;
+    let mut block = vec![
+        (0b1100 | (size & 0xf) << 4) as u8,
+        (size >> 4) as u8,
+        (size >> 12) as u8,
+    ];
+    block.extend_from_slice(&literals);
+    let count = sequences.len();
+    match count {
+        0..=127 => block.push(count as u8),
+        128..=0x7eff => block.extend_from_slice(&[(count >> 8) as u8 + 0x80, count as u8]),
+        _ => {
+            block.push(0xff);
+            block.extend_from_slice(&((count - 0x7f00) as u16).to_le_bytes());
+        }
+    }
+    // predefined codes
+    block.push(0);
+
+    let codes = [
+        FseEncoder::new(LITERALS_LENGTH_DISTRIBUTION).ok()?,
+        FseEncoder::new(OFFSET_DISTRIBUTION).ok()?,
+        FseEncoder::new(MATCH_LENGTH_DISTRIBUTION).ok()?,
+    ];
+    let coded: Vec<_> = sequences
+        .iter()
+        .map(|(literals_length, offset, match_length)| {
+            let offset_code = highest_bit(*offset);
+            [
+                length_code(
+                    *literals_length,
+                    &LITERALS_LENGTH_BASE,
+                    &LITERALS_LENGTH_EXTRA,
+                ),
+                (
+                    offset_code as u8,
+                    (*offset - (1 << offset_code)) as u64,
+                    offset_code,
+                ),
+                length_code(*match_length, &MATCH_LENGTH_BASE, &MATCH_LENGTH_EXTRA),
+            ]
+        })
+        .collect();
+    // encoded from the last sequence, so they are decoded from the first
+    let mut writer = BitWriter::new();
+    let last = coded.last()?;
+    let mut states = [
+        codes[0].start(last[0].0),
+        codes[1].start(last[1].0),
+        codes[2].start(last[2].0),
+    ];
+    let put_extra = |writer: &mut BitWriter, sequence: &[(u8, u64, u32); 3]| {
+        for (_, value, bits) in [sequence[0], sequence[2], sequence[1]] {
+            writer.put(value, bits);
+        }
+    };
+    put_extra(&mut writer, last);
+    for sequence in coded.iter().rev().skip(1) {
+        for index in [1, 2, 0] {
+            codes[index].encode(&mut states[index], sequence[index].0, &mut writer);
+        }
+        put_extra(&mut writer, sequence);
+    }
+    for index in [2, 1, 0] {
+        codes[index].finish(states[index], &mut writer);
+    }
+    block.extend_from_slice(&writer.finish());
+    Some(block)
+}
+
+/// The XXH64 hash of `data` with seed 0, whose low 32 bits are the
+/// checksum of frames.
+fn xxh64(data: &[u8]) -> u64 {
+    const PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+    const PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+    const PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+    const PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+    const PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+    let round = |acc: u64, lane: u64| {
+        acc.wrapping_add(lane.wrapping_mul(PRIME_2))
+            .rotate_left(31)
+            .wrapping_mul(PRIME_1)
+    };
+    let u64_at = |bytes: &[u8]| {
+        let mut lane = [0; 8];
+        lane.copy_from_slice(&bytes[..8]);
+        u64::from_le_bytes(lane)
+    };
+
+    let mut stripes = data.chunks_exact(32);
+    let mut hash = if data.len() >= 32 {
+        let mut acc = [
+            PRIME_1.wrapping_add(PRIME_2),
+            PRIME_2,
+            0,
+            0u64.wrapping_sub(PRIME_1),
+        ];
+        for stripe in &mut stripes {
+            for (i, acc) in acc.iter_mut().enumerate() {
+                *acc = round(*acc, u64_at(&stripe[8 * i..]));
+            }
+        }
+        let mut hash = acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18));
+        for acc in acc {
+            hash = (hash ^ round(0, acc))
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+        }
+        hash
+    } else {
+        PRIME_5
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    let mut rest = stripes.remainder();
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, u64_at(rest)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME_1)
+            .wrapping_add(PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let lane = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64;
+        hash = (hash ^ lane.wrapping_mul(PRIME_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME_2)
+            .wrapping_add(PRIME_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash = (hash ^ (*byte as u64).wrapping_mul(PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME_1);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME_3);
+    hash ^ hash >> 32
+}
+
+#[cfg(test)]
+mod test {
+    use data_encoding::BASE64;
+
+    use super::{compress, decompress, xxh64};
+
+    // python3 -c 'print("{\"_type\": \"link\"}" * 20, end="")' | zstd -19
+    const CLI_ZSTD: &str = "KLUv/WRUAMUAAIh7Il90eXBlIjogImxpbmsifQEAgUidSxsdHOc=";
+
+    #[test]
+    fn zstd_round_trip() {
+        let data = br#"{"_type": "link"}"#.repeat(20);
+        let cli = BASE64.decode(CLI_ZSTD.as_bytes()).unwrap();
+        assert_eq!(decompress(&cli, 1 << 20).unwrap(), data);
+
+        for data in [
+            &b""[..],
+            b"a",
+            &data,
+            &(0..=255).cycle().take(300_000).collect::<Vec<u8>>(),
+        ] {
+            let compressed = compress(data);
+            assert_eq!(decompress(&compressed, 1 << 20).unwrap(), data);
+        }
+        let compressed = compress(&data);
+        assert!(compressed.len() < data.len() / 4);
+
+        // concatenated and skippable frames, limits and corruption
+        let skippable = [0x50, 0x2a, 0x4d, 0x18, 2, 0, 0, 0, 1, 2];
+        let concatenated = [&compressed[..], &skippable, &cli].concat();
+        assert_eq!(
+            decompress(&concatenated, 1 << 20).unwrap(),
+            [&data[..], &data].concat()
+        );
+        assert!(decompress(&compressed, data.len() - 1).is_err());
+        let mut corrupt = compressed.clone();
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 1;
+        assert!(decompress(&corrupt, 1 << 20).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1], 1 << 20).is_err());
+        assert!(decompress(b"", 1 << 20).is_err());
+    }
+
+    #[test]
+    fn zstd_of_the_reference_implementation() {
+        // a link with 1200 materials, by `zstd -19`, so with Huffman coded
+        // literals and the codes of sequences described in its two blocks
+        let compressed = std::fs::read("tests/test_compression/materials.json.zst").unwrap();
+        let data = decompress(&compressed, 1 << 20).unwrap();
+        let (_, hashes) =
+            crate::crypto::calculate_hashes(&data[..], &[crate::crypto::HashAlgorithm::Sha256])
+                .unwrap();
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(hashes.values().next().unwrap().value()),
+            include_str!("../../../tests/test_compression/materials.json.sha256").trim()
+        );
+        assert_eq!(decompress(&compress(&data), 1 << 20).unwrap(), data);
+    }
+
+    #[test]
+    fn xxh64_digests() {
+        assert_eq!(xxh64(b""), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(b"a"), 0xd24e_c4f1_a98c_6e5b);
+        assert_eq!(
+            xxh64(b"Nobody inspects the spammish repetition"),
+            0xfbcea83c8a378bf1
+        );
+    }
+}
@@ -1,18 +1,85 @@
 //! Supporting Functions and Types (VirtualTargetPath)
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::str;
 
 use serde::de::{Deserialize, Deserializer, Error as DeserializeError};
 use serde_derive::Serialize;
 
 use crate::crypto::{HashAlgorithm, HashValue};
-use crate::Result;
+use crate::{Error, Result};
 
 /// Description of a target, used in verification.
 pub type TargetDescription = HashMap<HashAlgorithm, HashValue>;
 
+/// Scheme of plain files, which is also assumed when a path has no scheme.
+pub const FILE_SCHEME: &str = "file";
+
+/// Longest name of a step or inspection, leaving room for the key ID and
+/// extension in the file names of links.
+pub const MAX_STEP_NAME_LEN: usize = 200;
+
+/// Check that `name` is a valid name of a step or inspection.
+///
+/// Links are stored as `<name>.<key ID prefix>.link`, so names are limited
+/// to ASCII letters, digits, `.`, `_` and `-`, may not start with `.` or
+/// `-`, and may be at most `MAX_STEP_NAME_LEN` long.
+///
+/// ```
+/// # use in_toto::models::{sanitize_step_name, validate_step_name};
+/// assert!(validate_step_name("write-code").is_ok());
+/// assert!(validate_step_name("../write code").is_err());
+/// assert_eq!(sanitize_step_name("//main:hello"), "main_hello");
+/// ```
+pub fn validate_step_name(name: &str) -> Result<()> {
+    let invalid = |reason: &str| {
+        Err(Error::IllegalArgument(format!(
+            "invalid step name {:?}: {}",
+            name, reason
+        )))
+    };
+    if name.is_empty() {
+        return invalid("it is empty");
+    }
+    if name.len() > MAX_STEP_NAME_LEN {
+        return invalid(&format!("it is longer than {}", MAX_STEP_NAME_LEN));
+    }
+    if name.starts_with('.') || name.starts_with('-') {
+        return invalid("it starts with '.' or '-'");
+    }
+    if let Some(c) = name.chars().find(|c| !is_step_name_char(*c)) {
+        return invalid(&format!("{:?} is not allowed", c));
+    }
+    Ok(())
+}
+
+/// Turn `name` into a valid step name, see `validate_step_name`, by
+/// replacing disallowed characters with `_` and dropping leading ones.
+pub fn sanitize_step_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| if is_step_name_char(c) { c } else { '_' })
+        .collect();
+    let mut sanitized = sanitized.trim_start_matches(['.', '-', '_']).to_string();
+    sanitized.truncate(MAX_STEP_NAME_LEN);
+    match sanitized.is_empty() {
+        true => "_".into(),
+        false => sanitized,
+    }
+}
+
+fn is_step_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
 /// Wrapper for the Virtual path to a target.
+///
+/// Besides file paths, the path may be a scheme-prefixed resource identifier
+/// as described in [ITE-4], e.g. `git+https://github.com/in-toto/in-toto-rs`,
+/// `pkg:pypi/in-toto@1.0.0` or `docker://alpine:3.16`, so non-file artifacts
+/// can be recorded as materials and products.
+///
+/// [ITE-4]: https://github.com/in-toto/ITE/blob/master/ITE/4/README.adoc
 #[derive(Debug, Clone, PartialEq, Hash, Eq, PartialOrd, Ord, Serialize)]
 pub struct VirtualTargetPath(String);
 
@@ -23,15 +90,85 @@ impl VirtualTargetPath {
         Ok(VirtualTargetPath(path))
     }
 
+    /// Create a new `VirtualTargetPath` for the resource `resource` of `scheme`.
+    ///
+    /// ```
+    /// # use in_toto::models::VirtualTargetPath;
+    /// let path = VirtualTargetPath::from_uri("pkg", "pypi/in-toto@1.0.0").unwrap();
+    /// assert_eq!(path.value(), "pkg:pypi/in-toto@1.0.0");
+    /// assert_eq!(path.scheme(), Some("pkg"));
+    /// ```
+    pub fn from_uri(scheme: &str, resource: &str) -> Result<Self> {
+        if !is_valid_scheme(scheme) {
+            return Err(Error::IllegalArgument(format!(
+                "invalid artifact scheme {:?}",
+                scheme
+            )));
+        }
+        Self::new(format!("{}:{}", scheme, resource))
+    }
+
     /// The string value of the path.
     pub fn value(&self) -> &str {
         &self.0
     }
+
+    /// The ITE-4 scheme of this path, or `None` if it is a plain file path.
+    ///
+    /// Single letter schemes are not accepted to keep Windows drive letters
+    /// (`C:\...`) from being mistaken for a scheme.
+    pub fn scheme(&self) -> Option<&str> {
+        let (scheme, _) = self.0.split_once(':')?;
+        match scheme.len() > 1 && is_valid_scheme(scheme) {
+            true => Some(scheme),
+            false => None,
+        }
+    }
+
+    /// The resource identifier with the scheme stripped.
+    pub fn resource(&self) -> &str {
+        match self.scheme() {
+            Some(scheme) => &self.0[scheme.len() + 1..],
+            None => &self.0,
+        }
+    }
+
+    /// Whether this path refers to a file, i.e. has no scheme or the `file` scheme.
+    pub fn is_file(&self) -> bool {
+        matches!(self.scheme(), None | Some(FILE_SCHEME))
+    }
+
+    /// This path with Windows separators `\` replaced by `/`, as layouts
+    /// expect. Resource identifiers of other schemes are left as they are.
+    ///
+    /// ```
+    /// # use in_toto::models::VirtualTargetPath;
+    /// let path = VirtualTargetPath::new(r"build\out\app.exe".into()).unwrap();
+    /// assert_eq!(path.with_forward_slashes().value(), "build/out/app.exe");
+    /// ```
+    pub fn with_forward_slashes(&self) -> Self {
+        match self.is_file() {
+            true => VirtualTargetPath(self.0.replace('\\', "/")),
+            false => self.clone(),
+        }
+    }
 }
 
-impl ToString for VirtualTargetPath {
-    fn to_string(&self) -> String {
-        self.0.clone()
+/// Check `scheme` against the RFC 3986 grammar:
+/// `ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )`
+fn is_valid_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {
+            chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        _ => false,
+    }
+}
+
+impl Display for VirtualTargetPath {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(&self.0)
     }
 }
 
@@ -41,3 +178,80 @@ impl<'de> Deserialize<'de> for VirtualTargetPath {
         VirtualTargetPath::new(s).map_err(|e| DeserializeError::custom(format!("{:?}", e)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{sanitize_step_name, validate_step_name, VirtualTargetPath, MAX_STEP_NAME_LEN};
+
+    #[test]
+    fn step_names() {
+        for name in ["build", "write-code", "test_1.2", "_private"] {
+            assert!(validate_step_name(name).is_ok(), "{}", name);
+        }
+        let long = "a".repeat(MAX_STEP_NAME_LEN + 1);
+        for name in [
+            "",
+            ".hidden",
+            "-rf",
+            "a/b",
+            "a\\b",
+            "a b",
+            "caf\u{e9}",
+            &long,
+        ] {
+            assert!(validate_step_name(name).is_err(), "{:?}", name);
+            assert!(validate_step_name(&sanitize_step_name(name)).is_ok());
+        }
+        assert_eq!(sanitize_step_name("../build it"), "build_it");
+        assert_eq!(sanitize_step_name("..."), "_");
+    }
+
+    #[test]
+    fn forward_slashes() {
+        let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+        for (windows, expected) in [
+            (r"src\foo.py", "src/foo.py"),
+            (r"C:\build\foo.py", "C:/build/foo.py"),
+            (r"file:src\foo.py", "file:src/foo.py"),
+            ("src/foo.py", "src/foo.py"),
+            (r"pkg:generic/a\b", r"pkg:generic/a\b"),
+        ] {
+            assert_eq!(path(windows).with_forward_slashes(), path(expected));
+        }
+    }
+
+    #[test]
+    fn file_paths_have_no_scheme() {
+        for path in ["foo.py", "src/foo.py", "/abs/foo.py", "C:\\foo.py", ":foo"] {
+            let path = VirtualTargetPath::new(path.into()).unwrap();
+            assert_eq!(path.scheme(), None, "{}", path);
+            assert_eq!(path.resource(), path.value());
+            assert!(path.is_file());
+        }
+    }
+
+    #[test]
+    fn uri_paths() {
+        let path =
+            VirtualTargetPath::new("git+https://github.com/in-toto/in-toto-rs".into()).unwrap();
+        assert_eq!(path.scheme(), Some("git+https"));
+        assert_eq!(path.resource(), "//github.com/in-toto/in-toto-rs");
+        assert!(!path.is_file());
+
+        let path = VirtualTargetPath::new("file:src/foo.py".into()).unwrap();
+        assert_eq!(path.scheme(), Some("file"));
+        assert_eq!(path.resource(), "src/foo.py");
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn from_uri() {
+        let path = VirtualTargetPath::from_uri("docker", "//alpine:3.16").unwrap();
+        assert_eq!(path.value(), "docker://alpine:3.16");
+        assert_eq!(path.scheme(), Some("docker"));
+
+        assert!(VirtualTargetPath::from_uri("", "foo").is_err());
+        assert!(VirtualTargetPath::from_uri("1pkg", "foo").is_err());
+        assert!(VirtualTargetPath::from_uri("pkg/", "foo").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Ordering of the steps of a layout.
+//!
+//! A step depends on another step if one of its `MATCH` rules matches
+//! against the other step's products, i.e. it can only run once the other
+//! step is done. Steps without such a relation have no ordering constraint
+//! and may run concurrently, as in a CI pipeline fanning out. Steps may be
+//! put into a named group to state that they run concurrently, which is
+//! only possible if none of them depends on another, even transitively.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Error, Result};
+
+use super::step::Step;
+use super::supply_chain_item::SupplyChainItem;
+use super::LayoutMetadata;
+
+impl LayoutMetadata {
+    /// The names of the steps each step depends on, by step name.
+    pub fn step_dependencies(&self) -> BTreeMap<&str, BTreeSet<&str>> {
+        self.steps()
+            .iter()
+            .map(|step| (step.name(), product_sources(step)))
+            .collect()
+    }
+
+    /// Check that the steps can be run in some order: every `MATCH` rule
+    /// refers to an existing step (or, for inspections, an inspection), the
+    /// dependencies between steps have no cycle, and no step depends on a
+    /// step of its own group.
+    pub fn validate_steps(&self) -> Result<()> {
+        self.stages().map(|_| ())
+    }
+
+    /// Split the steps into stages that have to run one after the other,
+    /// where the steps of one stage may run concurrently. Steps are taken
+    /// into the earliest stage possible, keeping their layout order within.
+    ///
+    /// Fails if the steps cannot be ordered, see `validate_steps`.
+    pub fn stages(&self) -> Result<Vec<Vec<&Step>>> {
+        let steps: BTreeMap<&str, &Step> = self.steps().iter().map(|s| (s.name(), s)).collect();
+        if steps.len() != self.steps().len() {
+            return Err(Error::IllegalArgument(
+                "layout has steps with the same name".into(),
+            ));
+        }
+        self.validate_match_references(&steps)?;
+
+        let dependencies = self.step_dependencies();
+        let mut stage_of: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut stages: Vec<Vec<&Step>> = Vec::new();
+        while stage_of.len() < steps.len() {
+            let ready: Vec<&Step> = self
+                .steps()
+                .iter()
+                .filter(|step| !stage_of.contains_key(step.name()))
+                .filter(|step| {
+                    dependencies[step.name()]
+                        .iter()
+                        .all(|dep| stage_of.contains_key(dep))
+                })
+                .collect();
+            if ready.is_empty() {
+                let cycle: Vec<&str> = self
+                    .steps()
+                    .iter()
+                    .map(|s| s.name())
+                    .filter(|name| !stage_of.contains_key(name))
+                    .collect();
+                return Err(Error::IllegalArgument(format!(
+                    "the products of steps {:?} depend on each other",
+                    cycle
+                )));
+            }
+            for step in &ready {
+                stage_of.insert(step.name(), stages.len());
+            }
+            stages.push(ready);
+        }
+
+        self.validate_groups(&dependencies)?;
+        Ok(stages)
+    }
+
+    fn validate_match_references(&self, steps: &BTreeMap<&str, &Step>) -> Result<()> {
+        let unknown = |item: &str, from: &str| {
+            Error::IllegalArgument(format!(
+                "{} matches artifacts from unknown step {}",
+                item, from
+            ))
+        };
+        for step in self.steps() {
+            for from in match_sources(&step.supply_chain_item) {
+                if !steps.contains_key(from) {
+                    return Err(unknown(step.name(), from));
+                }
+            }
+        }
+        let inspections: BTreeSet<&str> = self.inspect().iter().map(|i| i.name()).collect();
+        for inspection in self.inspect() {
+            for from in match_sources(&inspection.supply_chain_item) {
+                if !steps.contains_key(from) && !inspections.contains(from) {
+                    return Err(unknown(inspection.name(), from));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_groups(&self, dependencies: &BTreeMap<&str, BTreeSet<&str>>) -> Result<()> {
+        for step in self.steps() {
+            let group = match &step.group {
+                Some(group) => group,
+                None => continue,
+            };
+            // walk all steps this one depends on, directly or not
+            let mut seen = BTreeSet::new();
+            let mut pending: Vec<&str> = dependencies[step.name()].iter().copied().collect();
+            while let Some(name) = pending.pop() {
+                if !seen.insert(name) {
+                    continue;
+                }
+                let other = self.steps().iter().find(|s| s.name() == name);
+                if other.and_then(|s| s.group.as_ref()) == Some(group) {
+                    return Err(Error::IllegalArgument(format!(
+                        "step {} depends on step {} of the same group {}",
+                        step.name(),
+                        name,
+                        group
+                    )));
+                }
+                pending.extend(dependencies[name].iter().copied());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The steps referred to by the `MATCH` rules of `item`.
+fn match_sources(item: &SupplyChainItem) -> BTreeSet<&str> {
+    item.expected_materials()
+        .iter()
+        .chain(item.expected_products())
+        .filter(|rule| rule.rule_type() == "MATCH")
+        .filter_map(|rule| rule.from_step())
+        .collect()
+}
+
+/// The other steps whose products `step` matches against.
+fn product_sources(step: &Step) -> BTreeSet<&str> {
+    let item = &step.supply_chain_item;
+    item.expected_materials()
+        .iter()
+        .chain(item.expected_products())
+        .filter(|rule| rule.rule_type() == "MATCH" && !rule.with_materials())
+        .filter_map(|rule| rule.from_step())
+        .filter(|from| *from != step.name())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::models::inspection::Inspection;
+    use crate::models::rule::{ArtifactRule, ArtifactRuleBuilder};
+    use crate::models::step::Step;
+    use crate::models::LayoutMetadataBuilder;
+
+    fn match_products(from: &str) -> ArtifactRule {
+        ArtifactRuleBuilder::new()
+            .rule("MATCH")
+            .pattern("*")
+            .with_products()
+            .from_step(from)
+            .build()
+            .unwrap()
+    }
+
+    fn match_materials(from: &str) -> ArtifactRule {
+        ArtifactRuleBuilder::new()
+            .rule("MATCH")
+            .pattern("*")
+            .with_materials()
+            .from_step(from)
+            .build()
+            .unwrap()
+    }
+
+    fn names(stages: Vec<Vec<&Step>>) -> Vec<Vec<&str>> {
+        stages
+            .into_iter()
+            .map(|stage| stage.into_iter().map(|s| s.name()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn fan_out_stages() {
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("package").add_expected_material(match_products("test")))
+            .add_step(Step::new("checkout"))
+            .add_step(
+                Step::new("lint")
+                    .group("checks")
+                    .add_expected_material(match_products("checkout")),
+            )
+            .add_step(
+                Step::new("test")
+                    .group("checks")
+                    .add_expected_material(match_products("checkout"))
+                    // reading the same materials is no dependency
+                    .add_expected_material(match_materials("lint")),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            names(layout.stages().unwrap()),
+            [vec!["checkout"], vec!["lint", "test"], vec!["package"]]
+        );
+        assert!(layout.validate_steps().is_ok());
+    }
+
+    #[test]
+    fn impossible_dependencies() {
+        // cycle
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("a").add_expected_material(match_products("b")))
+            .add_step(Step::new("b").add_expected_product(match_products("a")))
+            .build()
+            .unwrap();
+        assert!(layout.validate_steps().is_err());
+
+        // dependency within a group, transitively
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("a").group("g"))
+            .add_step(Step::new("b").add_expected_material(match_products("a")))
+            .add_step(
+                Step::new("c")
+                    .group("g")
+                    .add_expected_material(match_products("b")),
+            )
+            .build()
+            .unwrap();
+        assert!(layout.validate_steps().is_err());
+
+        // unknown step
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("a").add_expected_material(match_products("b")))
+            .build()
+            .unwrap();
+        assert!(layout.validate_steps().is_err());
+
+        // inspections may match from steps and inspections, steps not from inspections
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("a"))
+            .add_inspect(Inspection::new("i").add_expected_material(match_products("a")))
+            .add_inspect(Inspection::new("j").add_expected_material(match_products("i")))
+            .build()
+            .unwrap();
+        assert!(layout.validate_steps().is_ok());
+        let layout = LayoutMetadataBuilder::new()
+            .add_step(Step::new("a").add_expected_material(match_products("i")))
+            .add_inspect(Inspection::new("i"))
+            .build()
+            .unwrap();
+        assert!(layout.validate_steps().is_err());
+    }
+
+    #[test]
+    fn group_round_trip() {
+        let step = Step::new("lint").group("checks");
+        let json = serde_json::to_value(&step).unwrap();
+        assert_eq!(json["group"], "checks");
+        assert_eq!(serde_json::from_value::<Step>(json).unwrap(), step);
+        let json = serde_json::to_value(Step::new("lint")).unwrap();
+        assert!(json.get("group").is_none());
+    }
+}
@@ -0,0 +1,240 @@
+//! Plain-English description of a layout, for review before signing it.
+
+use std::fmt::Write;
+
+use super::rule::ArtifactRule;
+use super::supply_chain_item::SupplyChainItem;
+use super::{format_datetime, LayoutMetadata};
+
+impl LayoutMetadata {
+    /// Describe what this layout requires in plain English: who may sign
+    /// each step and how many signatures it needs, where its artifacts have
+    /// to come from, and which inspections are run.
+    ///
+    /// ```
+    /// # use in_toto::models::{step::Step, rule::ArtifactRuleBuilder, LayoutMetadataBuilder};
+    /// let layout = LayoutMetadataBuilder::new()
+    ///     .add_step(
+    ///         Step::new("write-code").threshold(1).add_expected_product(
+    ///             ArtifactRuleBuilder::new()
+    ///                 .rule("CREATE")
+    ///                 .pattern("foo.py")
+    ///                 .build()
+    ///                 .unwrap(),
+    ///         ),
+    ///     )
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(layout
+    ///     .explain()
+    ///     .contains("files matching \"foo.py\" must be newly created"));
+    /// ```
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        // writing to a String cannot fail
+        let _ = self.write_explanation(&mut out);
+        out
+    }
+
+    fn write_explanation(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "This layout expires on {}.",
+            format_datetime(self.expires())
+        )?;
+        match (self.layout_version(), self.supersedes()) {
+            (Some(version), Some(old)) => writeln!(
+                out,
+                "It is version {} of the layout, superseding version {}.",
+                version,
+                old.version()
+            )?,
+            (Some(version), None) => writeln!(out, "It is version {} of the layout.", version)?,
+            (None, _) => (),
+        }
+        if !self.readme().is_empty() {
+            writeln!(out, "{}", self.readme())?;
+        }
+
+        writeln!(out)?;
+        match self.steps().len() {
+            0 => writeln!(out, "It has no steps.")?,
+            1 => writeln!(out, "It has 1 step:")?,
+            n => writeln!(out, "It has {} steps:", n)?,
+        }
+        for step in self.steps() {
+            writeln!(out)?;
+            writeln!(out, "Step \"{}\"", step.name())?;
+            if let Some(group) = &step.group {
+                writeln!(
+                    out,
+                    "  runs concurrently with the other steps of group \"{}\".",
+                    group
+                )?;
+            }
+            match step.threshold {
+                0 | 1 => writeln!(out, "  must be signed by one of these functionaries:")?,
+                n => writeln!(out, "  must be signed by {} of these functionaries:", n)?,
+            }
+            if step.pub_keys.is_empty() {
+                writeln!(out, "    (none, so the step can never be verified)")?;
+            }
+            for key_id in &step.pub_keys {
+                match self.keys().get(key_id) {
+                    Some(key) => writeln!(out, "    - {}", key)?,
+                    None => writeln!(out, "    - {} (not a key of this layout)", key_id)?,
+                }
+            }
+            if !step.expected_command.is_empty() {
+                writeln!(
+                    out,
+                    "  is expected to run: {}",
+                    step.expected_command.to_shell_string()
+                )?;
+            }
+            write_rules(out, &step.supply_chain_item)?;
+        }
+
+        if !self.inspect().is_empty() {
+            writeln!(out)?;
+            writeln!(
+                out,
+                "When verifying, these inspections are run in the verifier's directory:"
+            )?;
+        }
+        for inspection in self.inspect() {
+            writeln!(out)?;
+            writeln!(out, "Inspection \"{}\"", inspection.name())?;
+            writeln!(out, "  runs: {}", inspection.run.to_shell_string())?;
+            write_rules(out, &inspection.supply_chain_item)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_rules(out: &mut String, item: &SupplyChainItem) -> std::fmt::Result {
+    for (kind, rules) in [
+        ("materials", item.expected_materials()),
+        ("products", item.expected_products()),
+    ] {
+        if rules.is_empty() {
+            writeln!(out, "  puts no restrictions on its {}.", kind)?;
+            continue;
+        }
+        writeln!(out, "  checks its {} in this order:", kind)?;
+        for rule in rules {
+            writeln!(out, "    - {}", explain_rule(rule))?;
+        }
+        if !rules
+            .iter()
+            .any(|r| r.rule_type() == "DISALLOW" && r.pattern() == "*")
+        {
+            writeln!(out, "    Any other {} are allowed.", kind)?;
+        }
+    }
+    Ok(())
+}
+
+/// Describe a single artifact rule.
+fn explain_rule(rule: &ArtifactRule) -> String {
+    let pattern = rule.pattern();
+    match rule.rule_type() {
+        "MATCH" => {
+            let source = match rule.source_path_prefix() {
+                Some(prefix) => format!("files matching {:?} in {:?}", pattern, prefix),
+                None => format!("files matching {:?}", pattern),
+            };
+            let target = match rule.with_materials() {
+                true => "materials",
+                false => "products",
+            };
+            let destination = match rule.destination_path_prefix() {
+                Some(prefix) => format!(" in {:?}", prefix),
+                None => String::new(),
+            };
+            format!(
+                "{} must be identical to the {} of \"{}\"{}",
+                source,
+                target,
+                rule.from_step().unwrap_or_default(),
+                destination
+            )
+        }
+        "CREATE" => format!("files matching {:?} must be newly created", pattern),
+        "DELETE" => format!("files matching {:?} must be deleted", pattern),
+        "MODIFY" => format!("files matching {:?} must be modified", pattern),
+        "ALLOW" => format!("files matching {:?} are allowed", pattern),
+        "DISALLOW" => format!(
+            "no remaining files matching {:?} are allowed, failing verification",
+            pattern
+        ),
+        "REQUIRE" => format!("{:?} must be present", pattern),
+        typ => format!("unknown rule {} {:?}", typ, pattern),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+
+    use crate::crypto::PublicKey;
+    use crate::models::inspection::Inspection;
+    use crate::models::step::{Command, Step};
+    use crate::models::LayoutMetadataBuilder;
+
+    const ALICE_PUB_KEY: &[u8] = include_bytes!("../../../tests/ed25519/ed25519-1.pub");
+
+    #[test]
+    fn explain_layout() {
+        let alice = PublicKey::from_ed25519(ALICE_PUB_KEY).unwrap();
+        let rules = |json: &str| serde_json::from_str(json).unwrap();
+        let layout = LayoutMetadataBuilder::new()
+            .expires(DateTime::from_timestamp(0, 0).unwrap())
+            .readme("Demo supply chain".into())
+            .add_key(alice.clone())
+            .add_step(
+                Step::new("package")
+                    .threshold(2)
+                    .add_key(alice.key_id().clone())
+                    .expected_command(Command::new(["tar", "zcvf", "foo bar.tar.gz"]))
+                    .expected_materials(rules(
+                        r#"[["MATCH", "*", "IN", "src", "WITH", "PRODUCTS", "FROM", "write-code"], ["DISALLOW", "*"]]"#,
+                    )),
+            )
+            .add_inspect(
+                Inspection::new("untar")
+                    .run("tar xzf foo.tar.gz".into())
+                    .expected_products(rules(r#"[["REQUIRE", "foo.py"]]"#)),
+            )
+            .build()
+            .unwrap();
+
+        let expected = format!(
+            r#"This layout expires on 1970-01-01T00:00:00Z.
+Demo supply chain
+
+It has 1 step:
+
+Step "package"
+  must be signed by 2 of these functionaries:
+    - {}
+  is expected to run: tar zcvf 'foo bar.tar.gz'
+  checks its materials in this order:
+    - files matching "*" in "src" must be identical to the products of "write-code"
+    - no remaining files matching "*" are allowed, failing verification
+  puts no restrictions on its products.
+
+When verifying, these inspections are run in the verifier's directory:
+
+Inspection "untar"
+  runs: tar xzf foo.tar.gz
+  puts no restrictions on its materials.
+  checks its products in this order:
+    - "foo.py" must be present
+    Any other products are allowed.
+"#,
+            alice
+        );
+        assert_eq!(layout.explain(), expected);
+    }
+}
@@ -0,0 +1,311 @@
+//! Drafting a layout from the links of a pipeline run.
+//!
+//! Writing the first layout of an existing pipeline by hand is the largest
+//! hurdle to adopting in-toto. `LayoutMetadataBuilder::infer` takes the
+//! links of a run known to be good and drafts a layout it would pass:
+//!
+//! * a step for each step name seen, ordered by the flow of artifacts, to be
+//!   signed by the functionaries who signed its links, all of them needed
+//! * the command of the link as the expected command
+//! * for materials, a `MATCH` rule for each artifact created or modified by
+//!   an earlier step, see `ArtifactFlow`, and an `ALLOW` rule for the other
+//!   ones
+//! * for products, a `CREATE`, `MODIFY`, `DELETE` or `ALLOW` rule for each
+//!   artifact, depending on how the step changed it
+//! * a final `DISALLOW *` for both
+//!
+//! The rules name every artifact, so the draft is strict but verbose. It is
+//! meant to be reviewed, e.g. with `LayoutMetadata::explain`, and refined
+//! by hand, replacing rules with patterns and setting the readme and
+//! expiration, before it is signed.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::warn;
+
+use super::rule::{ArtifactRule, ArtifactRuleBuilder};
+use super::step::Step;
+use super::LayoutMetadataBuilder;
+use crate::crypto::{KeyId, PublicKey};
+use crate::models::{ArtifactFlow, LinkMetadata, Metablock, MetadataWrapper, VirtualTargetPath};
+use crate::store::MetadataStore;
+use crate::{Error, Result};
+
+/// The links of one step, merged.
+struct ObservedStep<'a> {
+    link: &'a LinkMetadata,
+    signers: BTreeSet<KeyId>,
+}
+
+impl LayoutMetadataBuilder {
+    /// Draft a layout passed by `links`, the signed links of a known good
+    /// run of the supply chain. `keys` are the public keys of the
+    /// functionaries, those of signers not among them have to be added to
+    /// the draft by hand.
+    ///
+    /// Fails if the links of a step differ, or if the steps depend on each
+    /// other's products in a cycle.
+    pub fn infer(links: &[Metablock], keys: &[PublicKey]) -> Result<Self> {
+        let mut steps: BTreeMap<&str, ObservedStep> = BTreeMap::new();
+        for metablock in links {
+            let link = match metablock.metadata() {
+                MetadataWrapper::Link(link) => link,
+                MetadataWrapper::Layout(_) => {
+                    return Err(Error::IllegalArgument(
+                        "cannot infer a layout from a layout".into(),
+                    ))
+                }
+            };
+            let signers = metablock.signatures().iter().map(|s| s.key_id().clone());
+            match steps.get_mut(link.name().as_str()) {
+                Some(step)
+                    if step.link.materials() != link.materials()
+                        || step.link.products() != link.products() =>
+                {
+                    return Err(Error::IllegalArgument(format!(
+                        "the links of step {} record different artifacts",
+                        link.name()
+                    )))
+                }
+                Some(step) => step.signers.extend(signers),
+                None => {
+                    steps.insert(
+                        link.name(),
+                        ObservedStep {
+                            link,
+                            signers: signers.collect(),
+                        },
+                    );
+                }
+            }
+        }
+
+        let flow = ArtifactFlow::of(steps.values().map(|step| step.link));
+        let mut layout = LayoutMetadataBuilder::new();
+        let key_ids: BTreeSet<&KeyId> = steps.values().flat_map(|s| &s.signers).collect();
+        for key_id in key_ids {
+            match keys.iter().find(|key| key.key_id() == key_id) {
+                Some(key) => layout = layout.add_key(key.clone()),
+                None => warn!("No public key given for functionary {}", key_id),
+            }
+        }
+        for name in order(&steps, &flow)? {
+            let observed = &steps[name];
+            let mut step = Step::new(name)
+                .threshold(observed.signers.len() as u32)
+                .expected_command(observed.link.command().clone())
+                .expected_materials(material_rules(observed.link, &flow)?)
+                .expected_products(product_rules(observed.link)?);
+            for key_id in &observed.signers {
+                step = step.add_key(key_id.clone());
+            }
+            layout = layout.add_step(step);
+        }
+        Ok(layout)
+    }
+
+    /// Draft a layout from the links kept in `store`, see `infer`. Entries
+    /// not ending in `.link` are ignored.
+    pub fn infer_from_store<S: MetadataStore + ?Sized>(
+        store: &S,
+        keys: &[PublicKey],
+    ) -> Result<Self> {
+        let mut links = Vec::new();
+        for name in store.list()? {
+            if !name.ends_with(".link") {
+                continue;
+            }
+            if let Some(link) = store.get_metablock(&name)? {
+                // a link signed by several keys is stored once for each
+                if !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+        }
+        Self::infer(&links, keys)
+    }
+}
+
+/// Whether `step` created or modified the artifact `path` it produced.
+fn changed(link: &LinkMetadata, path: &VirtualTargetPath) -> bool {
+    link.materials().get(path) != link.products().get(path)
+}
+
+/// The step names ordered so that each step comes after the steps producing
+/// its materials, otherwise by name.
+fn order<'a>(
+    steps: &'a BTreeMap<&str, ObservedStep>,
+    flow: &'a ArtifactFlow,
+) -> Result<Vec<&'a str>> {
+    let mut dependencies: BTreeMap<&str, BTreeSet<&str>> =
+        steps.keys().map(|name| (*name, BTreeSet::new())).collect();
+    for (consumer, producers) in flow.step_dependencies() {
+        if let Some(sources) = dependencies.get_mut(consumer) {
+            sources.extend(producers);
+        }
+    }
+    let mut ordered = Vec::new();
+    while !dependencies.is_empty() {
+        let ready = dependencies
+            .iter()
+            .find(|(_, sources)| sources.is_empty())
+            .map(|(name, _)| *name)
+            .ok_or_else(|| {
+                Error::IllegalArgument(format!(
+                    "the steps {:?} use each other's products in a cycle",
+                    dependencies.keys().collect::<Vec<_>>()
+                ))
+            })?;
+        dependencies.remove(ready);
+        for sources in dependencies.values_mut() {
+            sources.remove(ready);
+        }
+        ordered.push(ready);
+    }
+    Ok(ordered)
+}
+
+fn material_rules(link: &LinkMetadata, flow: &ArtifactFlow) -> Result<Vec<ArtifactRule>> {
+    let mut rules = Vec::new();
+    for path in link.materials().keys() {
+        let pattern = escape_pattern(path.value());
+        let rule = match flow.producer(link.name(), path.value()) {
+            Some(step) => ArtifactRuleBuilder::new()
+                .rule("MATCH")
+                .pattern(&pattern)
+                .with_products()
+                .from_step(step),
+            None => ArtifactRuleBuilder::new().rule("ALLOW").pattern(&pattern),
+        };
+        rules.push(rule.build()?);
+    }
+    rules.push(disallow_all()?);
+    Ok(rules)
+}
+
+fn product_rules(link: &LinkMetadata) -> Result<Vec<ArtifactRule>> {
+    let mut rules = Vec::new();
+    for path in link.products().keys() {
+        let typ = match link.materials().contains_key(path) {
+            false => "CREATE",
+            true if changed(link, path) => "MODIFY",
+            true => "ALLOW",
+        };
+        rules.push(rule(typ, path)?);
+    }
+    for path in link.materials().keys() {
+        if !link.products().contains_key(path) {
+            rules.push(rule("DELETE", path)?);
+        }
+    }
+    rules.push(disallow_all()?);
+    Ok(rules)
+}
+
+fn rule(typ: &str, path: &VirtualTargetPath) -> Result<ArtifactRule> {
+    ArtifactRuleBuilder::new()
+        .rule(typ)
+        .pattern(&escape_pattern(path.value()))
+        .build()
+}
+
+fn disallow_all() -> Result<ArtifactRule> {
+    ArtifactRuleBuilder::new()
+        .rule("DISALLOW")
+        .pattern("*")
+        .build()
+}
+
+/// A pattern matching exactly `path`, with the wildcards in it escaped.
+fn escape_pattern(path: &str) -> String {
+    path.chars()
+        .map(|c| match c {
+            '*' | '?' | '[' => format!("[{}]", c),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::escape_pattern;
+    use crate::models::step::Command;
+    use crate::models::LayoutMetadataBuilder;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{functionary_key, key, link, links, owner_key, sign, store};
+    use crate::verifylib::in_toto_verify_with_store;
+
+    #[test]
+    fn infer_canned_supply_chain() {
+        let owner = owner_key();
+        let functionary = functionary_key();
+        let store = store(&functionary);
+
+        let layout =
+            LayoutMetadataBuilder::infer_from_store(&store, &[functionary.public().clone()])
+                .unwrap()
+                .build()
+                .unwrap();
+        let names: Vec<&str> = layout.steps().iter().map(|s| s.name()).collect();
+        assert_eq!(names, ["write-code", "package"]);
+        assert_eq!(
+            layout.steps()[1].expected_command,
+            Command::new(["tar", "zcvf", "foo.tar.gz", "foo.py"])
+        );
+        let explained = layout.explain();
+        assert!(explained.contains("must be newly created"), "{}", explained);
+        assert!(explained.contains("write-code"), "{}", explained);
+
+        // the draft is passed by the run it was inferred from
+        let signed = sign(Box::new(layout), &[&owner]);
+        assert!(in_toto_verify_with_store(&signed, &[owner.public()], &store, None).is_ok());
+
+        // but not by a run creating other artifacts
+        let mut other = links(&functionary);
+        other[0] = sign(
+            Box::new(link("write-code", &[], &[("foo.py", b"other"), ("x", b"")])),
+            &[&functionary],
+        );
+        let mut tampered = MemoryStore::new();
+        for link in &other {
+            tampered.put_link(link).unwrap();
+        }
+        assert!(in_toto_verify_with_store(&signed, &[owner.public()], &tampered, None).is_err());
+    }
+
+    #[test]
+    fn infer_rejects_inconsistent_links() {
+        let a = sign(
+            Box::new(link("build", &[], &[("a", b"1")])),
+            &[&key("alice")],
+        );
+        let b = sign(Box::new(link("build", &[], &[("a", b"2")])), &[&key("bob")]);
+        assert!(LayoutMetadataBuilder::infer(&[a.clone(), b], &[]).is_err());
+
+        // both signers are required when their links agree
+        let c = sign(Box::new(link("build", &[], &[("a", b"1")])), &[&key("bob")]);
+        let layout = LayoutMetadataBuilder::infer(&[a, c], &[])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(layout.steps()[0].threshold, 2);
+        assert!(layout.keys().is_empty());
+
+        // steps consuming each other's products
+        let x = sign(
+            Box::new(link("x", &[("a", b"1")], &[("b", b"1")])),
+            &[&key("alice")],
+        );
+        let y = sign(
+            Box::new(link("y", &[("b", b"1")], &[("a", b"1")])),
+            &[&key("alice")],
+        );
+        assert!(LayoutMetadataBuilder::infer(&[x, y], &[]).is_err());
+    }
+
+    #[test]
+    fn escape_wildcards() {
+        assert_eq!(escape_pattern("src/a.c"), "src/a.c");
+        assert_eq!(escape_pattern("a*b?[c]"), "a[*]b[?][[]c]");
+    }
+}
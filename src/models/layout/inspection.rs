@@ -7,8 +7,8 @@ use super::{rule::ArtifactRule, step::Command, supply_chain_item::SupplyChainIte
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Inspection {
     #[serde(flatten)]
-    supply_chain_item: SupplyChainItem,
-    run: Command,
+    pub supply_chain_item: SupplyChainItem,
+    pub run: Command,
 }
 
 impl Inspection {
@@ -19,6 +19,11 @@ impl Inspection {
         }
     }
 
+    /// Name of this Inspection
+    pub fn name(&self) -> &str {
+        self.supply_chain_item.name()
+    }
+
     /// Set expected command for this Inspection
     pub fn run(mut self, command: Command) -> Self {
         self.run = command;
@@ -0,0 +1,136 @@
+//! Distributing the keys needed to verify a supply chain.
+//!
+//! A layout only carries the keys of its functionaries, while the keys of
+//! the project owners signing it have to reach verifiers some other way.
+//! `KeyBundle` puts both into a single JSON file, the keys in the
+//! securesystemslib format with PEM encoded public keys, which verifiers can
+//! load as their trust for `verifylib::in_toto_verify_with_bundle`.
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::crypto::{KeyId, PublicKey};
+use crate::models::{Metablock, MetadataWrapper};
+use crate::{Error, Result};
+
+/// The keys of the owners of a layout and of its functionaries.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBundle {
+    owners: BTreeMap<KeyId, PublicKey>,
+    #[serde(default)]
+    functionaries: BTreeMap<KeyId, PublicKey>,
+}
+
+impl KeyBundle {
+    /// Bundle the keys of the signed layout `layout`, which has to be signed
+    /// by all `owner_keys`.
+    pub fn of(layout: &Metablock, owner_keys: &[&PublicKey]) -> Result<Self> {
+        if owner_keys.is_empty() {
+            return Err(Error::IllegalArgument(
+                "a key bundle needs the keys of the layout owners".into(),
+            ));
+        }
+        let layout = match layout.verify(owner_keys.len() as u32, owner_keys.iter().copied())? {
+            MetadataWrapper::Layout(layout) => layout,
+            MetadataWrapper::Link(_) => {
+                return Err(Error::IllegalArgument(
+                    "the metadata to bundle the keys of is not a layout".into(),
+                ))
+            }
+        };
+        Ok(KeyBundle {
+            owners: owner_keys
+                .iter()
+                .map(|key| (key.key_id().clone(), (*key).clone()))
+                .collect(),
+            functionaries: layout
+                .keys()
+                .iter()
+                .map(|(id, key)| (id.clone(), key.clone()))
+                .collect(),
+        })
+    }
+
+    /// The keys of the layout owners, to verify the layout with
+    pub fn owner_keys(&self) -> Vec<&PublicKey> {
+        self.owners.values().collect()
+    }
+
+    /// The keys of the functionaries of the layout
+    pub fn functionary_keys(&self) -> &BTreeMap<KeyId, PublicKey> {
+        &self.functionaries
+    }
+
+    /// Check that the signed layout `layout` authorizes no other
+    /// functionary keys than those bundled. Signatures are not verified.
+    pub fn check_layout(&self, layout: &Metablock) -> Result<()> {
+        let layout = match layout.metadata() {
+            MetadataWrapper::Layout(layout) => layout,
+            MetadataWrapper::Link(_) => {
+                return Err(Error::VerificationFailure(
+                    "the metadata to verify is not a layout".into(),
+                ))
+            }
+        };
+        for (key_id, key) in layout.keys() {
+            if self.functionaries.get(key_id) != Some(key) {
+                return Err(Error::VerificationFailure(format!(
+                    "the layout authorizes key {}, which is not in the key bundle",
+                    key_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Serialize the bundle as JSON.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Parse a bundle written by `to_bytes`, failing on keys bundled under
+    /// the ID of another key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bundle: KeyBundle = serde_json::from_slice(bytes)?;
+        if bundle.owners.is_empty() {
+            return Err(Error::Encoding("the key bundle has no owner keys".into()));
+        }
+        let keys = bundle.owners.iter().chain(bundle.functionaries.iter());
+        if let Some((key_id, _)) = keys.into_iter().find(|(id, key)| key.key_id() != *id) {
+            return Err(Error::Encoding(format!(
+                "the key bundled as {} has another key ID",
+                key_id
+            )));
+        }
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KeyBundle;
+    use crate::test_utils::{functionary_key, key, owner_key, signed_layout};
+
+    #[test]
+    fn bundle_layout_keys() {
+        let owner = owner_key();
+        let functionary = functionary_key();
+        let layout = signed_layout(&owner, &functionary);
+
+        let bundle = KeyBundle::of(&layout, &[owner.public()]).unwrap();
+        assert_eq!(bundle.owner_keys(), vec![owner.public()]);
+        assert_eq!(
+            bundle.functionary_keys().get(functionary.key_id()),
+            Some(functionary.public())
+        );
+        assert!(bundle.check_layout(&layout).is_ok());
+        let parsed = KeyBundle::from_bytes(&bundle.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, bundle);
+
+        // keys that did not sign, and layouts authorizing other keys
+        assert!(KeyBundle::of(&layout, &[key("mallory").public()]).is_err());
+        let other = signed_layout(&owner, &key("mallory"));
+        assert!(bundle.check_layout(&other).is_err());
+    }
+}
@@ -9,11 +9,13 @@ use std::collections::HashMap;
 use crate::crypto::KeyId;
 use crate::crypto::PublicKey;
 use crate::interchange::{DataInterchange, Json};
-use crate::models::{Metadata, MetadataType, MetadataWrapper};
+use crate::models::{
+    validate_step_name, Metablock, Metadata, MetadataType, MetadataWrapper, SpecVersion,
+};
 use crate::Result;
 
-use super::Layout;
 use super::{inspection::Inspection, step::Step};
+use super::{Layout, LayoutReference};
 
 /// Helper to construct `LayoutMetadata`
 pub struct LayoutMetadataBuilder {
@@ -22,6 +24,9 @@ pub struct LayoutMetadataBuilder {
     keys: HashMap<KeyId, PublicKey>,
     steps: Vec<Step>,
     inspect: Vec<Inspection>,
+    spec_version: Option<SpecVersion>,
+    layout_version: Option<u64>,
+    supersedes: Option<LayoutReference>,
 }
 
 impl Default for LayoutMetadataBuilder {
@@ -42,6 +47,9 @@ impl LayoutMetadataBuilder {
             keys: HashMap::new(),
             expires: Utc::now() + Duration::days(365),
             readme: String::new(),
+            spec_version: None,
+            layout_version: None,
+            supersedes: None,
         }
     }
 
@@ -99,14 +107,52 @@ impl LayoutMetadataBuilder {
         self
     }
 
+    /// Set the spec version this layout declares, which is left out by default
+    pub fn spec_version(mut self, spec_version: SpecVersion) -> Self {
+        self.spec_version = Some(spec_version);
+        self
+    }
+
+    /// Set the version of this layout, which is left out by default. Newer
+    /// layouts of a project have higher versions.
+    pub fn layout_version(mut self, layout_version: u64) -> Self {
+        self.layout_version = Some(layout_version);
+        self
+    }
+
+    /// Make this layout supersede the signed layout `old`, referring to it
+    /// by digest. Unless set to a higher one, the version of this layout
+    /// becomes the one after the version of `old`.
+    pub fn supersedes(mut self, old: &Metablock) -> Result<Self> {
+        let reference = LayoutReference::of(old)?;
+        let version = reference.version() + 1;
+        if self.layout_version.unwrap_or(0) < version {
+            self.layout_version = Some(version);
+        }
+        self.supersedes = Some(reference);
+        Ok(self)
+    }
+
+    /// Build the layout, failing if a step or inspection has a name that is
+    /// not a valid step name, see `validate_step_name`.
     pub fn build(self) -> Result<LayoutMetadata> {
-        Ok(LayoutMetadata::new(
+        for name in self.steps.iter().map(|step| step.name()) {
+            validate_step_name(name)?;
+        }
+        for name in self.inspect.iter().map(|inspection| inspection.name()) {
+            validate_step_name(name)?;
+        }
+        let mut meta = LayoutMetadata::new(
             self.expires,
             self.readme,
             self.keys,
             self.steps,
             self.inspect,
-        ))
+        );
+        meta.set_spec_version(self.spec_version);
+        meta.set_layout_version(self.layout_version);
+        meta.set_supersedes(self.supersedes);
+        Ok(meta)
     }
 }
 
@@ -118,6 +164,9 @@ pub struct LayoutMetadata {
     keys: HashMap<KeyId, PublicKey>,
     expires: DateTime<Utc>,
     readme: String,
+    spec_version: Option<SpecVersion>,
+    layout_version: Option<u64>,
+    supersedes: Option<LayoutReference>,
 }
 
 impl LayoutMetadata {
@@ -134,9 +183,32 @@ impl LayoutMetadata {
             keys,
             expires,
             readme,
+            spec_version: None,
+            layout_version: None,
+            supersedes: None,
         }
     }
 
+    pub(crate) fn set_layout_version(&mut self, layout_version: Option<u64>) {
+        self.layout_version = layout_version;
+    }
+
+    pub(crate) fn set_supersedes(&mut self, supersedes: Option<LayoutReference>) {
+        self.supersedes = supersedes;
+    }
+
+    pub(crate) fn set_spec_version(&mut self, spec_version: Option<SpecVersion>) {
+        self.spec_version = spec_version;
+    }
+
+    pub(crate) fn steps_mut(&mut self) -> &mut Vec<Step> {
+        &mut self.steps
+    }
+
+    pub(crate) fn inspect_mut(&mut self) -> &mut Vec<Inspection> {
+        &mut self.inspect
+    }
+
     /// Restrictions for each step within the supply chain
     pub fn steps(&self) -> &Vec<Step> {
         &self.steps
@@ -161,6 +233,21 @@ impl LayoutMetadata {
     pub fn readme(&self) -> &String {
         &self.readme
     }
+
+    /// The spec version this layout declares, if any
+    pub fn spec_version(&self) -> Option<SpecVersion> {
+        self.spec_version
+    }
+
+    /// The version of this layout, if any
+    pub fn layout_version(&self) -> Option<u64> {
+        self.layout_version
+    }
+
+    /// The older layout this one supersedes, if any
+    pub fn supersedes(&self) -> Option<&LayoutReference> {
+        self.supersedes.as_ref()
+    }
 }
 
 impl Metadata for LayoutMetadata {
@@ -3,32 +3,53 @@
 use std::collections::BTreeMap;
 
 use chrono::prelude::*;
-use chrono::TimeZone;
 use chrono::{DateTime, Utc};
 use log::warn;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::crypto::{KeyId, PublicKey};
+use crate::models::SpecVersion;
 use crate::{Error, Result};
 
 use self::{inspection::Inspection, step::Step};
 
+mod dependency;
+mod explain;
+mod infer;
 pub mod inspection;
+mod key_bundle;
 pub mod metadata;
+mod optimize;
 pub mod rule;
 pub mod step;
+mod supersede;
 pub mod supply_chain_item;
+mod template;
 
+pub use key_bundle::KeyBundle;
 pub use metadata::{LayoutMetadata, LayoutMetadataBuilder};
+pub use optimize::{optimize_rules, redundant_rules, RedundantRule};
+pub use supersede::{custody_link, LayoutReference, CUSTODY_STEP_NAME};
+pub use template::StepTemplate;
 
+/// Serialized form of `LayoutMetadata`, see `models::spec` for the
+/// differences between spec versions.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct Layout {
     #[serde(rename = "_type")]
     typ: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spec_version: Option<SpecVersion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layout_version: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    supersedes: Option<LayoutReference>,
     expires: String,
+    #[serde(default)]
     readme: String,
     keys: BTreeMap<KeyId, PublicKey>,
     steps: Vec<Step>,
+    #[serde(default)]
     inspect: Vec<Inspection>,
 }
 
@@ -36,6 +57,9 @@ impl Layout {
     pub fn from(meta: &LayoutMetadata) -> Result<Self> {
         Ok(Layout {
             typ: String::from("layout"),
+            spec_version: meta.spec_version(),
+            layout_version: meta.layout_version(),
+            supersedes: meta.supersedes().cloned(),
             expires: format_datetime(meta.expires()),
             readme: meta.readme().to_string(),
             keys: meta
@@ -63,18 +87,23 @@ impl Layout {
             })
             .collect();
 
-        Ok(LayoutMetadata::new(
+        let mut meta = LayoutMetadata::new(
             parse_datetime(&self.expires)?,
             self.readme,
             keys_with_correct_key_id,
             self.steps,
             self.inspect,
-        ))
+        );
+        meta.set_spec_version(self.spec_version);
+        meta.set_layout_version(self.layout_version);
+        meta.set_supersedes(self.supersedes);
+        Ok(meta)
     }
 }
 
 fn parse_datetime(ts: &str) -> Result<DateTime<Utc>> {
-    Utc.datetime_from_str(ts, "%FT%TZ")
+    NaiveDateTime::parse_from_str(ts, "%FT%TZ")
+        .map(|dt| dt.and_utc())
         .map_err(|e| Error::Encoding(format!("Can't parse DateTime: {:?}", e)))
 }
 
@@ -83,16 +112,20 @@ fn format_datetime(ts: &DateTime<Utc>) -> String {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes, deprecated)]
 mod test {
     use assert_json_diff::assert_json_eq;
     use chrono::{DateTime, NaiveDateTime, Utc};
     use serde_json::json;
 
-    use crate::{crypto::PublicKey, models::layout::format_datetime};
+    use crate::{
+        crypto::PublicKey,
+        models::{layout::format_datetime, SpecVersion},
+    };
 
     use super::{
         inspection::Inspection, parse_datetime, rule::ArtifactRuleBuilder, step::Step, Layout,
-        LayoutMetadataBuilder,
+        LayoutMetadata, LayoutMetadataBuilder,
     };
 
     const ALICE_PUB_KEY: &'static [u8] = include_bytes!("../../../tests/ed25519/ed25519-1.pub");
@@ -189,6 +222,46 @@ mod test {
         Layout::from(&metadata).unwrap()
     }
 
+    #[test]
+    fn layout_spec_version() {
+        // layouts of spec 0.9 may come without readme and inspect
+        let json = json!({
+            "_type": "layout",
+            "expires": "1970-01-01T00:00:00Z",
+            "keys": {},
+            "steps": []
+        });
+        let layout: LayoutMetadata = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(layout.spec_version(), None);
+        assert_eq!(layout.readme(), "");
+        assert!(layout.inspect().is_empty());
+        assert!(serde_json::to_value(&layout)
+            .unwrap()
+            .get("spec_version")
+            .is_none());
+
+        let mut json = json;
+        json["spec_version"] = json!("1.0");
+        let layout: LayoutMetadata = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(layout.spec_version(), Some(SpecVersion::V1_0));
+        assert_eq!(
+            serde_json::to_value(&layout).unwrap()["spec_version"],
+            "1.0"
+        );
+
+        json["spec_version"] = json!("2.0");
+        assert!(serde_json::from_value::<LayoutMetadata>(json).is_err());
+
+        let layout = LayoutMetadataBuilder::new()
+            .spec_version(SpecVersion::V0_9)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&layout).unwrap()["spec_version"],
+            "0.9"
+        );
+    }
+
     #[test]
     fn serialize_layout() {
         let layout = get_example_layout_metadata();
@@ -0,0 +1,225 @@
+//! Finding and removing redundant artifact rules.
+//!
+//! The rules of a step are applied as a queue, each consuming the artifacts
+//! it matches, so a rule may never see an artifact it could match:
+//!
+//! * after `ALLOW *` or `DISALLOW *` no artifact is left, they are either
+//!   consumed or verification failed
+//! * after `ALLOW` or `DISALLOW` with a pattern, no artifact matching the
+//!   same pattern is left
+//! * a rule repeating an earlier one finds nothing the earlier one left
+//!
+//! `REQUIRE` consumes nothing and fails if the artifact is gone, so it is
+//! never shadowed, but an earlier `REQUIRE` of the same artifact as a later
+//! one is redundant. Removing all redundant rules at once keeps the outcome
+//! of verification, as each is redundant because of a rule that is kept.
+//! Layouts generated or grown over time are often full of such rules, which
+//! slow verification down and hide what the layout actually requires.
+
+use std::fmt;
+
+use super::rule::ArtifactRule;
+use super::supply_chain_item::SupplyChainItem;
+use super::LayoutMetadata;
+
+/// A rule of a layout not changing the outcome of verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedundantRule {
+    pointer: String,
+    reason: String,
+}
+
+impl RedundantRule {
+    /// JSON pointer to the rule in the layout, e.g.
+    /// `/steps/1/expected_products/3`
+    pub fn pointer(&self) -> &str {
+        &self.pointer
+    }
+
+    /// Why the rule is redundant
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for RedundantRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.pointer, self.reason)
+    }
+}
+
+/// The indices of the redundant rules of `rules`, each with the reason.
+pub fn redundant_rules(rules: &[ArtifactRule]) -> Vec<(usize, String)> {
+    let mut redundant = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.rule_type() == "REQUIRE" {
+            let later = rules[i + 1..].iter().position(|later| {
+                later.rule_type() == "REQUIRE" && later.pattern() == rule.pattern()
+            });
+            if let Some(j) = later {
+                redundant.push((
+                    i,
+                    format!("{} is required again by rule {}", rule.pattern(), i + 1 + j),
+                ));
+            }
+            continue;
+        }
+        if let Some(j) = rules[..i].iter().position(|earlier| earlier == rule) {
+            redundant.push((i, format!("repeats rule {}", j)));
+            continue;
+        }
+        let shadowing = rules[..i].iter().position(|earlier| {
+            matches!(earlier.rule_type(), "ALLOW" | "DISALLOW")
+                && (earlier.pattern() == "*"
+                    || (earlier.pattern() == rule.pattern() && rule.source_path_prefix().is_none()))
+        });
+        if let Some(j) = shadowing {
+            redundant.push((
+                i,
+                format!(
+                    "no artifact matching {} is left after rule {}",
+                    rule.pattern(),
+                    j
+                ),
+            ));
+        }
+    }
+    redundant
+}
+
+/// `rules` without their redundant rules, see `redundant_rules`.
+pub fn optimize_rules(rules: &[ArtifactRule]) -> Vec<ArtifactRule> {
+    let redundant: Vec<usize> = redundant_rules(rules).into_iter().map(|(i, _)| i).collect();
+    rules
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !redundant.contains(i))
+        .map(|(_, rule)| rule.clone())
+        .collect()
+}
+
+impl LayoutMetadata {
+    /// The rules of the steps and inspections of this layout not changing
+    /// the outcome of verification.
+    pub fn redundant_rules(&self) -> Vec<RedundantRule> {
+        let steps = self
+            .steps()
+            .iter()
+            .map(|step| ("steps", &step.supply_chain_item));
+        let inspections = self
+            .inspect()
+            .iter()
+            .map(|inspection| ("inspect", &inspection.supply_chain_item));
+        let mut items: Vec<(&str, usize, &SupplyChainItem)> = Vec::new();
+        for (i, (list, item)) in steps.enumerate() {
+            items.push((list, i, item));
+        }
+        for (i, (list, item)) in inspections.enumerate() {
+            items.push((list, i, item));
+        }
+
+        let mut found = Vec::new();
+        for (list, i, item) in items {
+            for (rules, name) in [
+                (item.expected_materials(), "expected_materials"),
+                (item.expected_products(), "expected_products"),
+            ] {
+                for (j, reason) in redundant_rules(rules) {
+                    found.push(RedundantRule {
+                        pointer: format!("/{}/{}/{}/{}", list, i, name, j),
+                        reason,
+                    });
+                }
+            }
+        }
+        found
+    }
+
+    /// This layout with the redundant rules of its steps and inspections
+    /// removed. It verifies the same supply chains, but is a different
+    /// layout to be signed again.
+    pub fn optimized(mut self) -> Self {
+        for step in self.steps_mut() {
+            optimize_item(&mut step.supply_chain_item);
+        }
+        for inspection in self.inspect_mut() {
+            optimize_item(&mut inspection.supply_chain_item);
+        }
+        self
+    }
+}
+
+fn optimize_item(item: &mut SupplyChainItem) {
+    let materials = optimize_rules(item.expected_materials());
+    let products = optimize_rules(item.expected_products());
+    item.set_expected_materials(materials);
+    item.set_expected_products(products);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{optimize_rules, redundant_rules};
+    use crate::models::rule::ArtifactRule;
+    use crate::models::step::Step;
+    use crate::models::LayoutMetadataBuilder;
+
+    fn rules(json: &str) -> Vec<ArtifactRule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn find_redundant_rules() {
+        let rules = rules(
+            r#"[
+                ["REQUIRE", "foo"],
+                ["MATCH", "*", "WITH", "PRODUCTS", "FROM", "build"],
+                ["ALLOW", "*.md"],
+                ["CREATE", "*.md"],
+                ["MATCH", "*", "WITH", "PRODUCTS", "FROM", "build"],
+                ["MATCH", "*.md", "IN", "docs", "WITH", "PRODUCTS", "FROM", "docs"],
+                ["REQUIRE", "foo"],
+                ["DISALLOW", "*"],
+                ["ALLOW", "bar"],
+                ["REQUIRE", "bar"]
+            ]"#,
+        );
+        let found: Vec<usize> = redundant_rules(&rules)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(found, [0, 3, 4, 8]);
+        assert_eq!(
+            redundant_rules(&rules)[0].1,
+            "foo is required again by rule 6"
+        );
+        assert_eq!(redundant_rules(&rules)[2].1, "repeats rule 1");
+
+        let optimized = optimize_rules(&rules);
+        assert_eq!(optimized.len(), 6);
+        assert!(redundant_rules(&optimized).is_empty());
+    }
+
+    #[test]
+    fn optimize_layout() {
+        let step = Step::new("build").expected_products(rules(
+            r#"[["CREATE", "a.out"], ["DISALLOW", "*"], ["DISALLOW", "*"]]"#,
+        ));
+        let layout = LayoutMetadataBuilder::new().add_step(step).build().unwrap();
+        let redundant = layout.redundant_rules();
+        assert_eq!(redundant.len(), 1);
+        assert_eq!(
+            redundant[0].to_string(),
+            "/steps/0/expected_products/2: repeats rule 1"
+        );
+
+        let optimized = layout.optimized();
+        assert!(optimized.redundant_rules().is_empty());
+        assert_eq!(
+            optimized.steps()[0]
+                .supply_chain_item
+                .expected_products()
+                .len(),
+            2
+        );
+    }
+}
@@ -138,7 +138,7 @@ use std::result::Result as StdResult;
 use lazy_static::lazy_static;
 use serde::{
     de::{self, SeqAccess, Unexpected, Visitor},
-    ser::{Serialize, SerializeSeq},
+    ser::{self, Serialize, SerializeSeq},
     Deserialize,
 };
 
@@ -290,18 +290,66 @@ pub struct ArtifactRule {
     inner: HashMap<String, String>,
 }
 
-impl ArtifactRule {}
+impl ArtifactRule {
+    /// Type of the rule, one of `MATCH`, `CREATE`, `DELETE`,
+    /// `MODIFY`, `ALLOW`, `REQUIRE` or `DISALLOW`
+    pub fn rule_type(&self) -> &str {
+        // set by ArtifactRuleBuilder::build and the deserializer
+        &self.inner[TYPE]
+    }
+
+    /// This rule with its pattern and path prefixes mapped by `map`
+    pub(crate) fn map_paths<F: Fn(&str) -> String>(&self, map: F) -> ArtifactRule {
+        let mut inner = self.inner.clone();
+        for key in [PATTERN, SOURCE_PATH_PREFIX, DESTINATION_PATH_PREFIX] {
+            if let Some(value) = inner.get_mut(key) {
+                *value = map(value);
+            }
+        }
+        ArtifactRule { inner }
+    }
+
+    /// `<pattern>` of the rule
+    pub fn pattern(&self) -> &str {
+        &self.inner[PATTERN]
+    }
+
+    /// `<source-path-prefix>` of a `MATCH` rule
+    pub fn source_path_prefix(&self) -> Option<&str> {
+        self.inner.get(SOURCE_PATH_PREFIX).map(String::as_str)
+    }
+
+    /// `<destination-path-prefix>` of a `MATCH` rule
+    pub fn destination_path_prefix(&self) -> Option<&str> {
+        self.inner.get(DESTINATION_PATH_PREFIX).map(String::as_str)
+    }
+
+    /// Whether a `MATCH` rule matches against the `MATERIALS`
+    /// (`false` for `PRODUCTS`) of its step
+    pub fn with_materials(&self) -> bool {
+        self.inner.get(TARGET).map(String::as_str) == Some(MATERIALS)
+    }
+
+    /// `<step>` of a `MATCH` rule
+    pub fn from_step(&self) -> Option<&str> {
+        self.inner.get(STEP).map(String::as_str)
+    }
+}
 
 impl Serialize for ArtifactRule {
     fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let typ = self.inner.get(TYPE).unwrap().to_owned();
-        // here use unwrap() because when an ArtifactRule is
-        // successfully built from ArtifactRuleBuilder, the key 'TYPE' is
-        // ensured to exist in the inner map.
-        let pattern = self.inner.get(PATTERN).unwrap().to_owned();
+        // the fields of the type of a rule are ensured to exist when it is
+        // built with ArtifactRuleBuilder
+        let field = |name: &str| {
+            self.inner.get(name).cloned().ok_or_else(|| {
+                <S::Error as ser::Error>::custom(format!("artifact rule has no {}", name))
+            })
+        };
+        let typ = field(TYPE)?;
+        let pattern = field(PATTERN)?;
         let mut statement = vec![typ.clone(), pattern];
 
         if &typ[..] == "MATCH" {
@@ -310,7 +358,7 @@ impl Serialize for ArtifactRule {
                 statement.push(src.into());
             }
 
-            let target = self.inner.get(TARGET).unwrap().to_owned();
+            let target = field(TARGET)?;
             statement.push(WITH.into());
             statement.push(target);
 
@@ -319,7 +367,7 @@ impl Serialize for ArtifactRule {
                 statement.push(dst.into());
             }
 
-            let step = self.inner.get(STEP).unwrap().to_owned();
+            let step = field(STEP)?;
             statement.push(FROM.into());
             statement.push(step);
         }
@@ -1,5 +1,8 @@
 //! in-toto layout's Step
 
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
 use serde::de::{Deserialize, Deserializer, Error as DeserializeError};
@@ -12,28 +15,207 @@ use crate::{Error, Result};
 use super::rule::ArtifactRule;
 use super::supply_chain_item::SupplyChainItem;
 
-/// Wrapper type for a command in step.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
-pub struct Command(String);
+/// Wrapper type for a command in step, held as an argv list.
+///
+/// Metadata may write a command either as one shell string or as a list of
+/// arguments. The form a command was read in is kept for serializing, so the
+/// canonical form and signatures of existing metadata are preserved, while
+/// commands are compared token-wise: `tar  zcvf 'foo.tar.gz'` equals
+/// `["tar", "zcvf", "foo.tar.gz"]`.
+#[derive(Clone, Debug)]
+pub struct Command {
+    argv: Vec<String>,
+    // the original string, if the command was given as one
+    raw: Option<String>,
+}
+
+impl Command {
+    /// Create a `Command` from its arguments, serialized as a list.
+    pub fn new<I, S>(argv: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Command {
+            argv: argv.into_iter().map(Into::into).collect(),
+            raw: None,
+        }
+    }
+
+    /// The arguments of this command.
+    pub fn argv(&self) -> &[String] {
+        &self.argv
+    }
+
+    /// Whether this command has no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.argv.is_empty()
+    }
+
+    /// Join the arguments into a shell string, quoting where needed such
+    /// that `split_shell` gives back the same arguments.
+    pub fn to_shell_string(&self) -> String {
+        self.argv
+            .iter()
+            .map(|arg| quote_shell(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Split a shell string into arguments following POSIX shell quoting rules:
+/// single quotes are literal, double quotes allow `\` escapes of `"`, `\`,
+/// `$` and `` ` ``, and a backslash outside of quotes escapes any character.
+/// No expansion is done.
+pub fn split_shell(string: &str) -> Result<Vec<String>> {
+    let mut argv = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = string.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    argv.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated(string, "single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            // escaped newlines are removed
+                            Some('\n') => {}
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err(unterminated(string, "double quote")),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err(unterminated(string, "double quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => current.get_or_insert_with(String::new).push(c),
+                None => return Err(unterminated(string, "escape")),
+            },
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    argv.extend(current);
+    Ok(argv)
+}
+
+fn unterminated(string: &str, what: &str) -> Error {
+    Error::IllegalArgument(format!("unterminated {} in command {:?}", what, string))
+}
+
+/// Quote `arg` for a POSIX shell, leaving it as is if that is safe.
+fn quote_shell(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\"'\"'"))
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command {
+            argv: Vec::new(),
+            raw: Some(String::new()),
+        }
+    }
+}
+
+impl PartialEq for Command {
+    fn eq(&self, other: &Self) -> bool {
+        self.argv == other.argv
+    }
+}
+
+impl Eq for Command {}
+
+impl Hash for Command {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.argv.hash(state)
+    }
+}
+
+impl PartialOrd for Command {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Command {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.argv.cmp(&other.argv)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.raw {
+            Some(raw) => f.write_str(raw),
+            None => f.write_str(&self.to_shell_string()),
+        }
+    }
+}
 
 impl From<String> for Command {
+    /// Strings that are not valid shell syntax are split on whitespace.
     fn from(str: String) -> Self {
-        Command(str)
+        let argv = split_shell(&str)
+            .unwrap_or_else(|_| str.split_whitespace().map(String::from).collect());
+        Command {
+            argv,
+            raw: Some(str),
+        }
     }
 }
 
 impl From<&str> for Command {
     fn from(str: &str) -> Self {
-        Command(str.to_string())
+        Command::from(str.to_string())
+    }
+}
+
+impl From<Vec<String>> for Command {
+    fn from(argv: Vec<String>) -> Self {
+        Command::new(argv)
+    }
+}
+
+impl From<&[&str]> for Command {
+    fn from(argv: &[&str]) -> Self {
+        Command::new(argv.iter().copied())
     }
 }
 
 impl FromStr for Command {
     type Err = Error;
 
-    /// Parse a Command from a string.
+    /// Parse a Command from a shell string, failing on unbalanced quotes.
     fn from_str(string: &str) -> Result<Self> {
-        Ok(Command(string.to_owned()))
+        Ok(Command {
+            argv: split_shell(string)?,
+            raw: Some(string.to_owned()),
+        })
     }
 }
 
@@ -42,15 +224,127 @@ impl Serialize for Command {
     where
         S: Serializer,
     {
-        self.0.serialize(ser)
+        match &self.raw {
+            Some(raw) => raw.serialize(ser),
+            None => self.argv.serialize(ser),
+        }
     }
 }
 
 impl<'de> Deserialize<'de> for Command {
     fn deserialize<D: Deserializer<'de>>(de: D) -> ::std::result::Result<Self, D::Error> {
-        let string: String = Deserialize::deserialize(de)?;
-        Command::from_str(&string).map_err(|e| DeserializeError::custom(format!("{:?}", e)))
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Form {
+            String(String),
+            List(Vec<String>),
+        }
+        match Form::deserialize(de)
+            .map_err(|_| DeserializeError::custom("command must be a string or a list"))?
+        {
+            Form::String(string) => Ok(Command::from(string)),
+            Form::List(argv) => Ok(Command::new(argv)),
+        }
+    }
+}
+
+/// The interpreters `CommandNormalization::strip_interpreters` strips, by the
+/// file name of their executable, with any version suffix like `python3.11`.
+pub const INTERPRETERS: &[&str] = &[
+    "bash", "dash", "node", "perl", "php", "pwsh", "python", "ruby", "sh", "zsh",
+];
+
+/// How commands are normalized before comparing the command of a link to the
+/// `expected_command` of its step, so the same step run on machines laid out
+/// differently is not reported as running another command.
+///
+/// ```
+/// # use in_toto::models::step::{Command, CommandNormalization};
+/// let normalization = CommandNormalization::new()
+///     .strip_interpreters(true)
+///     .executable_names(true);
+/// assert!(normalization.matches(
+///     &Command::from("./build.py --release"),
+///     &Command::from("/usr/bin/python3 build.py --release"),
+/// ));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandNormalization {
+    strip_interpreters: bool,
+    executable_names: bool,
+}
+
+impl CommandNormalization {
+    /// Compare commands as they are
+    pub fn new() -> Self {
+        Self::default()
     }
+
+    /// Drop a leading `env` with its `NAME=value` arguments, and an
+    /// interpreter of `INTERPRETERS` running a script, so `python3 build.py`
+    /// and `/usr/bin/env python build.py` are `build.py`. Interpreters given
+    /// options, like `python3 -c ...`, are kept.
+    pub fn strip_interpreters(mut self, strip_interpreters: bool) -> Self {
+        self.strip_interpreters = strip_interpreters;
+        self
+    }
+
+    /// Compare executables by their file name, so `/usr/bin/make`,
+    /// `./make` and `make` are `make`
+    pub fn executable_names(mut self, executable_names: bool) -> Self {
+        self.executable_names = executable_names;
+        self
+    }
+
+    /// `command` normalized
+    pub fn normalize(&self, command: &Command) -> Command {
+        let mut argv = command.argv();
+        if self.strip_interpreters {
+            if argv.first().map(|arg| file_name(arg)) == Some("env") {
+                argv = &argv[1..];
+                while argv.first().is_some_and(|arg| is_assignment(arg)) {
+                    argv = &argv[1..];
+                }
+            }
+            if argv.len() > 1 && is_interpreter(&argv[0]) && !argv[1].starts_with('-') {
+                argv = &argv[1..];
+            }
+        }
+        let mut argv = argv.to_vec();
+        if self.executable_names {
+            if let Some(executable) = argv.first_mut() {
+                *executable = file_name(executable).to_string();
+            }
+        }
+        Command::new(argv)
+    }
+
+    /// Whether `expected` and `actual` are the same once normalized
+    pub fn matches(&self, expected: &Command, actual: &Command) -> bool {
+        self.normalize(expected) == self.normalize(actual)
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+fn is_assignment(arg: &str) -> bool {
+    match arg.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn is_interpreter(executable: &str) -> bool {
+    let name = file_name(executable);
+    let name = name.strip_suffix(".exe").unwrap_or(name);
+    INTERPRETERS.iter().any(|interpreter| {
+        name.strip_prefix(interpreter)
+            .is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+    })
 }
 
 /// Step represents an in-toto step of the supply chain performed by a functionary.
@@ -64,12 +358,16 @@ impl<'de> Deserialize<'de> for Command {
 pub struct Step {
     #[serde(skip, default = "default_step")]
     typ: String,
-    threshold: u32,
+    pub threshold: u32,
     #[serde(flatten)]
-    supply_chain_item: SupplyChainItem,
+    pub supply_chain_item: SupplyChainItem,
     #[serde(rename = "pubkeys")]
-    pub_keys: Vec<KeyId>,
-    expected_command: Command,
+    pub pub_keys: Vec<KeyId>,
+    pub expected_command: Command,
+    /// Steps of the same group run concurrently, so none of them may
+    /// depend on the products of another
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
 }
 
 fn default_step() -> String {
@@ -84,9 +382,15 @@ impl Step {
             expected_command: Command::default(),
             threshold: 0,
             supply_chain_item: SupplyChainItem::new(name.into()),
+            group: None,
         }
     }
 
+    /// Name of this Step
+    pub fn name(&self) -> &str {
+        self.supply_chain_item.name()
+    }
+
     /// Add a pub key for this Step
     pub fn add_key(mut self, key: KeyId) -> Self {
         self.pub_keys.push(key);
@@ -99,6 +403,12 @@ impl Step {
         self
     }
 
+    /// Put this Step into the group of concurrently running steps `group`
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
     /// Set threshold for this Step
     pub fn threshold(mut self, threshold: u32) -> Self {
         self.threshold = threshold;
@@ -142,7 +452,7 @@ mod test {
 
     use crate::{crypto::KeyId, models::rule::ArtifactRuleBuilder, Result};
 
-    use super::Step;
+    use super::{split_shell, Command, CommandNormalization, Step};
 
     #[test]
     fn serialize_step() -> Result<()> {
@@ -229,4 +539,76 @@ mod test {
         assert_eq!(step_parsed, step);
         Ok(())
     }
+
+    #[test]
+    fn split_shell_quoting() {
+        assert_eq!(
+            split_shell(r#"tar  zcvf 'foo bar.tar.gz' "a \"b\"" c\ d ''"#).unwrap(),
+            ["tar", "zcvf", "foo bar.tar.gz", "a \"b\"", "c d", ""]
+        );
+        assert_eq!(split_shell("  ").unwrap(), Vec::<String>::new());
+        assert!(split_shell("echo 'hi").is_err());
+        assert!(split_shell("echo \"hi").is_err());
+        assert!(split_shell("echo \\").is_err());
+    }
+
+    #[test]
+    fn command_shell_string_round_trip() {
+        let command = Command::new(["sh", "-c", "echo 'hi' > out", ""]);
+        assert_eq!(
+            command.to_shell_string(),
+            r#"sh -c 'echo '"'"'hi'"'"' > out' ''"#
+        );
+        assert_eq!(
+            split_shell(&command.to_shell_string()).unwrap(),
+            command.argv()
+        );
+    }
+
+    #[test]
+    fn command_compares_token_wise() {
+        let from_string: Command = "tar  zcvf 'foo.tar.gz'".parse().unwrap();
+        let from_list = Command::new(["tar", "zcvf", "foo.tar.gz"]);
+        assert_eq!(from_string, from_list);
+        assert_ne!(from_list, Command::from("tar zcvf foo.tar"));
+        assert!("tar 'zcvf".parse::<Command>().is_err());
+        assert_eq!(Command::from("tar 'zcvf").argv(), ["tar", "'zcvf"]);
+    }
+
+    #[test]
+    fn command_keeps_serialized_form() {
+        for json in [
+            json!("tar  zcvf 'foo.tar.gz'"),
+            json!(["tar", "zcvf", "foo.tar.gz"]),
+        ] {
+            let command: Command = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(command.argv(), ["tar", "zcvf", "foo.tar.gz"]);
+            assert_eq!(serde_json::to_value(&command).unwrap(), json);
+        }
+        assert_eq!(serde_json::to_value(Command::default()).unwrap(), json!(""));
+        assert!(serde_json::from_value::<Command>(json!(1)).is_err());
+    }
+
+    #[test]
+    fn normalize_commands() {
+        let command = |s: &str| Command::from(s);
+        let normalization = CommandNormalization::new().strip_interpreters(true);
+        for (raw, normalized) in [
+            ("python3 build.py -v", "build.py -v"),
+            ("/usr/bin/env PYTHONPATH=. python3.11 build.py", "build.py"),
+            ("bash -e build.sh", "bash -e build.sh"),
+            ("python", "python"),
+            ("pythonic build.py", "pythonic build.py"),
+            ("make all", "make all"),
+        ] {
+            assert_eq!(normalization.normalize(&command(raw)), command(normalized));
+        }
+        assert!(!normalization.matches(&command("./build.py"), &command("python build.py")));
+
+        let normalization = normalization.executable_names(true);
+        assert!(normalization.matches(&command("./build.py"), &command("python build.py")));
+        assert!(normalization.matches(&command("/usr/bin/make"), &command("make")));
+        assert!(!normalization.matches(&command("make a"), &command("make b")));
+        assert!(!CommandNormalization::new().matches(&command("./make"), &command("make")));
+    }
 }
@@ -0,0 +1,187 @@
+//! Updating a layout by superseding it with a newer one.
+//!
+//! A layout may carry a `layout_version` and refer to the layout it
+//! supersedes by digest. Since the new layout may be signed by new project
+//! owners, the owners of the old layout hand over custody with a link of the
+//! step `CUSTODY_STEP_NAME`, whose material is the old and whose product is
+//! the new signed layout. Verifiers that know the old layout can then follow
+//! the chain of layouts and reject a rollback to an older one, see
+//! `verifylib::verify_layout_update`.
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::crypto::{self, HashAlgorithm};
+use crate::interchange::{DataInterchange, Json};
+use crate::models::{
+    LinkMetadata, LinkMetadataBuilder, Metablock, MetadataWrapper, TargetDescription,
+    VirtualTargetPath,
+};
+use crate::{Error, Result};
+
+/// Name of the step whose link hands over custody from a layout to the one
+/// superseding it.
+pub const CUSTODY_STEP_NAME: &str = "supersede-layout";
+
+/// Reference to a signed layout by version and digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayoutReference {
+    version: u64,
+    digest: TargetDescription,
+}
+
+impl LayoutReference {
+    /// Refer to the signed layout `layout`, a layout without version has
+    /// version 0.
+    pub fn of(layout: &Metablock) -> Result<Self> {
+        Ok(LayoutReference {
+            version: signed_layout_version(layout)?,
+            digest: metablock_digest(layout)?,
+        })
+    }
+
+    /// The version of the layout referred to
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// The digest of the signed layout referred to
+    pub fn digest(&self) -> &TargetDescription {
+        &self.digest
+    }
+
+    /// Whether this refers to the signed layout `layout`
+    pub fn matches(&self, layout: &Metablock) -> Result<bool> {
+        Ok(*self == LayoutReference::of(layout)?)
+    }
+}
+
+/// The version of the signed layout `layout`, without verifying it.
+fn signed_layout_version(layout: &Metablock) -> Result<u64> {
+    match layout.metadata() {
+        MetadataWrapper::Layout(layout) => Ok(layout.layout_version().unwrap_or(0)),
+        MetadataWrapper::Link(_) => Err(Error::IllegalArgument(
+            "the metadata to refer to is not a layout".into(),
+        )),
+    }
+}
+
+/// The sha256 digest of the canonical form of `metablock`, signatures
+/// included.
+fn metablock_digest(metablock: &Metablock) -> Result<TargetDescription> {
+    let bytes = Json::canonicalize(&serde_json::to_value(metablock)?)?;
+    let (_, digest) = crypto::calculate_hashes(&bytes[..], &[HashAlgorithm::Sha256])?;
+    Ok(digest)
+}
+
+fn layout_artifact(version: u64) -> Result<VirtualTargetPath> {
+    VirtualTargetPath::new(format!("layout-v{}", version))
+}
+
+/// The unsigned link of custody from the signed layout `old` to the signed
+/// layout `new` superseding it, to be signed by the owners of `old`.
+pub fn custody_link(old: &Metablock, new: &Metablock) -> Result<LinkMetadata> {
+    let reference = match new.metadata() {
+        MetadataWrapper::Layout(layout) => layout.supersedes(),
+        MetadataWrapper::Link(_) => None,
+    };
+    match reference {
+        Some(reference) if reference.matches(old)? => (),
+        _ => {
+            return Err(Error::IllegalArgument(
+                "the new layout does not supersede the old one".into(),
+            ))
+        }
+    }
+    let material = LayoutReference::of(old)?;
+    let product = LayoutReference::of(new)?;
+    LinkMetadataBuilder::new()
+        .name(CUSTODY_STEP_NAME.into())
+        .materials(BTreeMap::from([(
+            layout_artifact(material.version)?,
+            material.digest,
+        )]))
+        .products(BTreeMap::from([(
+            layout_artifact(product.version)?,
+            product.digest,
+        )]))
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+
+    use super::{custody_link, LayoutReference, CUSTODY_STEP_NAME};
+    use crate::models::{LayoutMetadata, LayoutMetadataBuilder};
+    use crate::test_utils::{key, sign};
+
+    #[test]
+    fn supersede_layout() {
+        let owner = key("owner");
+        let old = sign(
+            Box::new(
+                LayoutMetadataBuilder::new()
+                    .layout_version(3)
+                    .build()
+                    .unwrap(),
+            ),
+            &[&owner],
+        );
+        let new_layout = LayoutMetadataBuilder::new()
+            .expires(DateTime::from_timestamp(0, 0).unwrap())
+            .supersedes(&old)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(new_layout.layout_version(), Some(4));
+        let reference = new_layout.supersedes().unwrap().clone();
+        assert_eq!(reference.version(), 3);
+        assert!(reference.matches(&old).unwrap());
+
+        // the fields round trip and are only serialized when set
+        let json = serde_json::to_value(&new_layout).unwrap();
+        assert_eq!(json["layout_version"], 4);
+        assert_eq!(
+            serde_json::from_value::<LayoutMetadata>(json).unwrap(),
+            new_layout
+        );
+        let json = serde_json::to_value(LayoutMetadataBuilder::new().build().unwrap()).unwrap();
+        assert!(json.get("layout_version").is_none());
+        assert!(json.get("supersedes").is_none());
+
+        let new = sign(Box::new(new_layout), &[&key("new owner")]);
+        let custody = custody_link(&old, &new).unwrap();
+        assert_eq!(custody.name(), CUSTODY_STEP_NAME);
+        assert_eq!(
+            custody.materials().values().next(),
+            Some(LayoutReference::of(&old).unwrap().digest())
+        );
+        assert_eq!(
+            custody.products().values().next(),
+            Some(LayoutReference::of(&new).unwrap().digest())
+        );
+
+        // a re-signed old layout is a different layout
+        let other = sign(
+            Box::new(
+                LayoutMetadataBuilder::new()
+                    .layout_version(3)
+                    .build()
+                    .unwrap(),
+            ),
+            &[&key("mallory")],
+        );
+        assert!(!reference.matches(&other).unwrap());
+        assert!(custody_link(&other, &new).is_err());
+        // an explicit higher version is kept
+        let newer = LayoutMetadataBuilder::new()
+            .layout_version(10)
+            .supersedes(&old)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(newer.layout_version(), Some(10));
+    }
+}
@@ -0,0 +1,244 @@
+//! Templates of layouts for common pipelines.
+//!
+//! Most supply chains are a chain of steps, each working on what the step
+//! before it left behind, and most layouts spell out the same rules for
+//! them. Rules are applied as a queue, each consuming the artifacts it
+//! matches, so getting them right is subtle: a missing `DISALLOW *` lets
+//! any artifact pass, and a `MATCH` of the wrong step or list does not tie
+//! the steps together. `LayoutMetadataBuilder::add_chain` adds steps with
+//! these rules, for each step in order:
+//!
+//! * for materials, `MATCH *` with the products of the step before, or
+//!   nothing for the first step, then `DISALLOW *`
+//! * for products, `MATCH *` with the products of the step before, so
+//!   unchanged artifacts pass, then a `CREATE` rule for each pattern of
+//!   artifacts the step creates, then `DISALLOW *`
+//!
+//! So no step may modify or delete what it got, and each may only create
+//! what it is declared to. `LayoutMetadataBuilder::clone_build_test_package`
+//! is the most common such chain.
+
+use super::rule::{ArtifactRule, ArtifactRuleBuilder};
+use super::step::{Command, Step};
+use super::LayoutMetadataBuilder;
+use crate::crypto::PublicKey;
+use crate::Result;
+
+/// A step of a chain of steps, see `LayoutMetadataBuilder::add_chain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepTemplate {
+    name: String,
+    keys: Vec<PublicKey>,
+    threshold: u32,
+    command: Option<Command>,
+    creates: Vec<String>,
+}
+
+impl StepTemplate {
+    /// The step `name` signed by one of the functionaries `keys`, creating
+    /// no artifacts.
+    pub fn new(name: &str, keys: &[&PublicKey]) -> Self {
+        StepTemplate {
+            name: name.into(),
+            keys: keys.iter().map(|key| (*key).clone()).collect(),
+            threshold: 1,
+            command: None,
+            creates: Vec::new(),
+        }
+    }
+
+    /// Require links of `threshold` of the functionaries
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Expect the step to run `command`
+    pub fn expected_command(mut self, command: Command) -> Self {
+        self.command = Some(command);
+        self
+    }
+
+    /// Allow the step to create the artifacts matching one of `patterns`,
+    /// e.g. `target/release/*`
+    pub fn creates(mut self, patterns: &[&str]) -> Self {
+        self.creates = patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+}
+
+impl LayoutMetadataBuilder {
+    /// Add `steps` as a chain, each working on the products of the one
+    /// before it, along with the keys of their functionaries. See the
+    /// module documentation for the rules.
+    pub fn add_chain(mut self, steps: Vec<StepTemplate>) -> Result<Self> {
+        let mut previous: Option<String> = None;
+        for template in steps {
+            let mut materials = Vec::new();
+            let mut products = Vec::new();
+            if let Some(previous) = &previous {
+                materials.push(match_products_of(previous)?);
+                products.push(match_products_of(previous)?);
+            }
+            materials.push(disallow_all()?);
+            for pattern in &template.creates {
+                products.push(
+                    ArtifactRuleBuilder::new()
+                        .rule("CREATE")
+                        .pattern(pattern)
+                        .build()?,
+                );
+            }
+            products.push(disallow_all()?);
+
+            let mut step = Step::new(&template.name)
+                .threshold(template.threshold)
+                .expected_materials(materials)
+                .expected_products(products);
+            if let Some(command) = template.command {
+                step = step.expected_command(command);
+            }
+            for key in template.keys {
+                step = step.add_key(key.key_id().clone());
+                self = self.add_key(key);
+            }
+            previous = Some(template.name);
+            self = self.add_step(step);
+        }
+        Ok(self)
+    }
+
+    /// Add the chain of steps `clone`, `build`, `test` and `package`, each
+    /// signed by one of the functionaries `keys`: the sources are cloned
+    /// into an empty directory, the build creates the artifacts matching
+    /// `build_outputs` next to them, the tests create nothing, and packaging
+    /// creates the artifacts matching `packages`. See `add_chain`, for
+    /// other functionaries or commands for the steps.
+    pub fn clone_build_test_package(
+        self,
+        keys: &[&PublicKey],
+        build_outputs: &[&str],
+        packages: &[&str],
+    ) -> Result<Self> {
+        self.add_chain(vec![
+            StepTemplate::new("clone", keys).creates(&["*"]),
+            StepTemplate::new("build", keys).creates(build_outputs),
+            StepTemplate::new("test", keys),
+            StepTemplate::new("package", keys).creates(packages),
+        ])
+    }
+}
+
+fn match_products_of(step: &str) -> Result<ArtifactRule> {
+    ArtifactRuleBuilder::new()
+        .rule("MATCH")
+        .pattern("*")
+        .with_products()
+        .from_step(step)
+        .build()
+}
+
+fn disallow_all() -> Result<ArtifactRule> {
+    ArtifactRuleBuilder::new()
+        .rule("DISALLOW")
+        .pattern("*")
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::StepTemplate;
+    use crate::models::LayoutMetadataBuilder;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{functionary_key, link, owner_key, sign};
+    use crate::verifylib::in_toto_verify_with_store;
+
+    #[test]
+    fn chain_rules() {
+        let functionary = functionary_key();
+        let layout = LayoutMetadataBuilder::new()
+            .add_chain(vec![
+                StepTemplate::new("fetch", &[functionary.public()]).creates(&["*"]),
+                StepTemplate::new("build", &[functionary.public()])
+                    .creates(&["out/*"])
+                    .threshold(2),
+            ])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(layout.keys().len(), 1);
+        let build = serde_json::to_value(&layout.steps()[1]).unwrap();
+        assert_eq!(build["threshold"], 2);
+        assert_eq!(
+            build["expected_materials"],
+            json!([
+                ["MATCH", "*", "WITH", "PRODUCTS", "FROM", "fetch"],
+                ["DISALLOW", "*"]
+            ])
+        );
+        assert_eq!(
+            build["expected_products"],
+            json!([
+                ["MATCH", "*", "WITH", "PRODUCTS", "FROM", "fetch"],
+                ["CREATE", "out/*"],
+                ["DISALLOW", "*"]
+            ])
+        );
+    }
+
+    type Artifacts<'a> = &'a [(&'a str, &'a [u8])];
+
+    #[test]
+    fn verify_clone_build_test_package() {
+        let (owner, functionary) = (owner_key(), functionary_key());
+        let layout = LayoutMetadataBuilder::new()
+            .clone_build_test_package(&[functionary.public()], &["app"], &["app.tar.gz"])
+            .unwrap()
+            .build()
+            .unwrap();
+        let signed = sign(Box::new(layout), &[&owner]);
+        let src: Artifacts = &[("main.c", b"int main;")];
+        let built: Artifacts = &[("main.c", b"int main;"), ("app", b"\x7fELF")];
+        let packaged: Artifacts = &[
+            ("main.c", b"int main;"),
+            ("app", b"\x7fELF"),
+            ("app.tar.gz", b"\x1f\x8b"),
+        ];
+        let verify = |steps: &[(&str, Artifacts, Artifacts)]| {
+            let mut store = MemoryStore::new();
+            for (name, materials, products) in steps {
+                let signed = sign(Box::new(link(name, materials, products)), &[&functionary]);
+                store.put_link(&signed).unwrap();
+            }
+            in_toto_verify_with_store(&signed, &[owner.public()], &store, None)
+        };
+
+        assert!(verify(&[
+            ("clone", &[], src),
+            ("build", src, built),
+            ("test", built, built),
+            ("package", built, packaged),
+        ])
+        .is_ok());
+
+        // tests modifying the build, builds creating other artifacts
+        let patched: Artifacts = &[("main.c", b"int main;"), ("app", b"patched")];
+        assert!(verify(&[
+            ("clone", &[], src),
+            ("build", src, built),
+            ("test", built, patched),
+            ("package", patched, packaged),
+        ])
+        .is_err());
+        let extra: Artifacts = &[("main.c", b"int main;"), ("app", b"\x7fELF"), ("x", b"")];
+        assert!(verify(&[
+            ("clone", &[], src),
+            ("build", src, extra),
+            ("test", extra, extra),
+            ("package", extra, packaged),
+        ])
+        .is_err());
+    }
+}
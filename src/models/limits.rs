@@ -0,0 +1,122 @@
+//! Bounds on metadata read from untrusted sources.
+//!
+//! Links are written by functionaries and often fetched from stores the
+//! verifier does not control. Before their signatures are checked, nothing
+//! keeps them from being huge, so services verifying them parse them with
+//! `MetadataLimits` to reject metadata that would exhaust their memory.
+
+use crate::models::{Metablock, MetadataWrapper};
+use crate::{Error, Result};
+
+/// Default largest size of serialized metadata, 16 MiB
+pub const DEFAULT_MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+
+/// Default largest number of materials or products of a link
+pub const DEFAULT_MAX_ARTIFACTS: usize = 100_000;
+
+/// Default largest number of signatures of a metablock
+pub const DEFAULT_MAX_SIGNATURES: usize = 64;
+
+/// Limits on the size of metadata to accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataLimits {
+    max_size: usize,
+    max_artifacts: usize,
+    max_signatures: usize,
+}
+
+impl Default for MetadataLimits {
+    fn default() -> Self {
+        MetadataLimits {
+            max_size: DEFAULT_MAX_METADATA_SIZE,
+            max_artifacts: DEFAULT_MAX_ARTIFACTS,
+            max_signatures: DEFAULT_MAX_SIGNATURES,
+        }
+    }
+}
+
+impl MetadataLimits {
+    /// The default limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept serialized metadata of at most `max_size` bytes
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Accept links with at most `max_artifacts` materials and as many
+    /// products
+    pub fn max_artifacts(mut self, max_artifacts: usize) -> Self {
+        self.max_artifacts = max_artifacts;
+        self
+    }
+
+    /// Accept metablocks with at most `max_signatures` signatures
+    pub fn max_signatures(mut self, max_signatures: usize) -> Self {
+        self.max_signatures = max_signatures;
+        self
+    }
+
+    /// Parse the serialized metablock `bytes`, failing before parsing if it
+    /// is too large, or after parsing if it has too many signatures or
+    /// artifacts.
+    pub fn parse_metablock(&self, bytes: &[u8]) -> Result<Metablock> {
+        if bytes.len() > self.max_size {
+            return Err(Error::VerificationFailure(format!(
+                "metadata of {} bytes exceeds the limit of {} bytes",
+                bytes.len(),
+                self.max_size
+            )));
+        }
+        let metablock = serde_json::from_slice(bytes)?;
+        self.check(&metablock)?;
+        Ok(metablock)
+    }
+
+    /// Check that `metablock` has not too many signatures or artifacts.
+    pub fn check(&self, metablock: &Metablock) -> Result<()> {
+        let signatures = metablock.signatures().len();
+        if signatures > self.max_signatures {
+            return Err(Error::VerificationFailure(format!(
+                "metadata with {} signatures exceeds the limit of {}",
+                signatures, self.max_signatures
+            )));
+        }
+        if let MetadataWrapper::Link(link) = metablock.metadata() {
+            let artifacts = link.materials().len().max(link.products().len());
+            if artifacts > self.max_artifacts {
+                return Err(Error::VerificationFailure(format!(
+                    "link {} with {} artifacts exceeds the limit of {}",
+                    link.name(),
+                    artifacts,
+                    self.max_artifacts
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MetadataLimits;
+    use crate::test_utils::{key, link, sign};
+
+    #[test]
+    fn reject_huge_metadata() {
+        let link = link("build", &[("a.c", b"a"), ("b.c", b"b")], &[("a.o", b"o")]);
+        let signed = sign(Box::new(link), &[&key("alice"), &key("bob")]);
+        let bytes = serde_json::to_vec(&signed).unwrap();
+
+        let limits = MetadataLimits::new();
+        assert_eq!(limits.parse_metablock(&bytes).unwrap(), signed);
+        let small = limits.max_size(bytes.len() - 1);
+        assert!(small.parse_metablock(&bytes).is_err());
+        assert!(limits.max_artifacts(1).parse_metablock(&bytes).is_err());
+        assert!(limits.max_artifacts(2).parse_metablock(&bytes).is_ok());
+        assert!(limits.max_signatures(1).parse_metablock(&bytes).is_err());
+    }
+}
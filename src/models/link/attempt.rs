@@ -0,0 +1,105 @@
+//! Attempts of a step.
+//!
+//! A step retried, e.g. by a CI system after a flaky failure, leaves a link
+//! for every attempt. The link of a retry records its attempt number as the
+//! byproduct `ATTEMPT_BYPRODUCT`, and supersedes the links of earlier
+//! attempts signed by the same functionary: verification only considers the
+//! link of the last attempt of each functionary. Links without the
+//! byproduct are first attempts.
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::byproducts::ByProducts;
+use crate::crypto::KeyId;
+use crate::models::{link_filename, LinkMetadata};
+use crate::{Error, Result};
+
+/// Name of the byproduct holding the `Attempt` of a link, as JSON.
+pub const ATTEMPT_BYPRODUCT: &str = "attempt";
+
+/// The filename of the link of attempt `number` of `step_name` signed by
+/// `key_id`, for keeping the links of all attempts side by side, e.g.
+/// `build.776a00e2.attempt-2.link`. Verification finds them, like the name
+/// given by `link_filename`.
+pub fn attempt_filename(step_name: &str, key_id: &KeyId, number: u32) -> String {
+    let name = link_filename(step_name, key_id);
+    format!("{}.attempt-{}.link", name.trim_end_matches(".link"), number)
+}
+
+/// The attempt of a step a link was recorded in, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    number: u32,
+}
+
+impl Attempt {
+    /// Attempt `number`, which must be at least 1
+    pub fn new(number: u32) -> Result<Self> {
+        if number == 0 {
+            return Err(Error::IllegalArgument("attempts are counted from 1".into()));
+        }
+        Ok(Attempt { number })
+    }
+
+    /// The number of the attempt
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// Whether a link of this attempt supersedes one of attempt `other`
+    pub fn supersedes(&self, other: &Attempt) -> bool {
+        self.number > other.number
+    }
+
+    /// The attempt `link` was recorded in, the first if it was not marked.
+    pub fn of(link: &LinkMetadata) -> Result<Self> {
+        Ok(Self::from_byproducts(link.byproducts())?.unwrap_or(Attempt { number: 1 }))
+    }
+
+    /// `byproducts` with this attempt as byproduct `ATTEMPT_BYPRODUCT`
+    pub fn to_byproducts(&self, byproducts: ByProducts) -> Result<ByProducts> {
+        Ok(byproducts.set_other_field(ATTEMPT_BYPRODUCT.into(), serde_json::to_string(self)?))
+    }
+
+    /// The attempt recorded in `byproducts`, if it was recorded.
+    pub fn from_byproducts(byproducts: &ByProducts) -> Result<Option<Self>> {
+        match byproducts.other_field_str(ATTEMPT_BYPRODUCT)? {
+            Some(json) => {
+                let attempt: Attempt = serde_json::from_str(json)?;
+                Self::new(attempt.number).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attempt_filename, Attempt};
+    use crate::crypto::KeyId;
+    use crate::models::byproducts::ByProducts;
+
+    #[test]
+    fn attempt_round_trip() {
+        assert!(Attempt::new(0).is_err());
+        let second = Attempt::new(2).unwrap();
+        assert!(second.supersedes(&Attempt::new(1).unwrap()));
+        assert!(!second.supersedes(&second));
+
+        let byproducts = second.to_byproducts(ByProducts::new()).unwrap();
+        assert_eq!(byproducts.other_fields()["attempt"], r#"{"number":2}"#);
+        assert_eq!(Attempt::from_byproducts(&byproducts).unwrap(), Some(second));
+        assert_eq!(Attempt::from_byproducts(&ByProducts::new()).unwrap(), None);
+
+        let zero = ByProducts::new().set_other_field("attempt".into(), r#"{"number":0}"#.into());
+        assert!(Attempt::from_byproducts(&zero).is_err());
+
+        let key_id: KeyId = "776a00e29f3559e0141b3b096f696abc6cfb0c657ab40f441132b345b08453f5"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            attempt_filename("build", &key_id, 2),
+            "build.776a00e2.attempt-2.link"
+        );
+    }
+}
@@ -3,6 +3,9 @@
 use std::collections::BTreeMap;
 
 use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Error, Result};
 
 /// byproducts of a link file
 /// # Example
@@ -18,7 +21,9 @@ use serde_derive::{Deserialize, Serialize};
 /// //  .set_other_fields(other_byproducts);
 /// ```
 ///
-/// Also, can directly set a whole BTree<String, String> as other_fields
+/// Also, can directly set a whole BTree<String, String> as other_fields. Other
+/// fields may hold any JSON value, as links of the reference implementation
+/// in the wild hold numbers and nested objects
 ///
 /// ```
 /// use std::collections::BTreeMap;
@@ -39,7 +44,7 @@ pub struct ByProducts {
     stderr: String,
     stdout: String,
     #[serde(flatten)]
-    other_fields: BTreeMap<String, String>,
+    other_fields: BTreeMap<String, Value>,
 }
 
 impl ByProducts {
@@ -74,12 +79,22 @@ impl ByProducts {
     /// Warning: This operation will overwrite all the present other-field
     /// set by `set_other_field` or `set_other_fields` before.
     pub fn set_other_fields(mut self, other_fields: BTreeMap<String, String>) -> Self {
-        self.other_fields = other_fields;
+        self.other_fields = other_fields
+            .into_iter()
+            .map(|(key, value)| (key, Value::String(value)))
+            .collect();
         self
     }
 
     /// Insert another field
     pub fn set_other_field(mut self, key: String, value: String) -> Self {
+        self.other_fields.insert(key, Value::String(value));
+        self
+    }
+
+    /// Insert another field holding any JSON value, e.g. a number or an
+    /// object
+    pub fn set_other_value(mut self, key: String, value: Value) -> Self {
         self.other_fields.insert(key, value);
         self
     }
@@ -100,9 +115,22 @@ impl ByProducts {
     }
 
     /// Get other fields
-    pub fn other_fields(&self) -> &BTreeMap<String, String> {
+    pub fn other_fields(&self) -> &BTreeMap<String, Value> {
         &self.other_fields
     }
+
+    /// Get the string of other field `key`, if it is set. Fails if it holds
+    /// another JSON value.
+    pub fn other_field_str(&self, key: &str) -> Result<Option<&str>> {
+        match self.other_fields.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(value) => Err(Error::Encoding(format!(
+                "byproduct {} is no string but {}",
+                key, value
+            ))),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -200,4 +228,29 @@ mod tests {
         let deserialized_byproducts: ByProducts = serde_json::from_str(json).unwrap();
         assert_eq!(byproducts, deserialized_byproducts);
     }
+
+    #[test]
+    fn roundtrip_byproducts_json_values() {
+        let json = json!({
+            "return-value": 0,
+            "stderr": "",
+            "stdout": "",
+            "duration": 1.5,
+            "retries": 2,
+            "tool": {"name": "make", "flags": ["-j", 4]},
+            "note": "text"
+        });
+        let byproducts: ByProducts = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(byproducts.other_fields()["tool"]["flags"][1], 4);
+        let built = ByProducts::new()
+            .set_other_value("duration".into(), json!(1.5))
+            .set_other_value("retries".into(), json!(2))
+            .set_other_value("tool".into(), json["tool"].clone())
+            .set_other_field("note".into(), "text".into());
+        assert_eq!(built, byproducts);
+        assert_eq!(byproducts.other_field_str("note").unwrap(), Some("text"));
+        assert_eq!(byproducts.other_field_str("missing").unwrap(), None);
+        assert!(byproducts.other_field_str("retries").is_err());
+        assert_eq!(serde_json::to_value(byproducts).unwrap(), json);
+    }
 }
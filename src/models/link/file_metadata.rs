@@ -0,0 +1,234 @@
+//! Metadata of artifact files recorded next to their digests.
+//!
+//! For a build to be reproduced its products have to match in more than
+//! content: an executable that lost its execute bit, or a file owned by
+//! another user in a package, is a different product. `RecordOptions`
+//! can record such metadata of files as custom fields of their
+//! `TargetDescription`, named `FileField::name` and holding the value as a
+//! big-endian integer, so artifact rules comparing artifacts compare it as
+//! well:
+//!
+//! ```json
+//! "bin/tool": {"sha256": "...", "file-mode": "000001ed"}
+//! ```
+
+use std::convert::TryFrom;
+use std::fs::Metadata;
+
+use chrono::{DateTime, Utc};
+
+use crate::crypto::{HashAlgorithm, HashValue};
+use crate::models::TargetDescription;
+use crate::{Error, Result};
+
+/// A file metadata field recorded in a `TargetDescription`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileField {
+    /// The permission bits, e.g. `0o755`, without the type of the file
+    Mode,
+    /// The user ID of the owner
+    Owner,
+    /// The group ID of the owner
+    Group,
+    /// The modification time, in whole seconds since the Unix epoch
+    ModificationTime,
+}
+
+impl FileField {
+    /// All fields
+    pub const ALL: [FileField; 4] = [
+        FileField::Mode,
+        FileField::Owner,
+        FileField::Group,
+        FileField::ModificationTime,
+    ];
+
+    /// The name of the field in a `TargetDescription`
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileField::Mode => "file-mode",
+            FileField::Owner => "file-uid",
+            FileField::Group => "file-gid",
+            FileField::ModificationTime => "file-mtime",
+        }
+    }
+
+    /// The key of the field in a `TargetDescription`
+    pub fn algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::Unknown(self.name().into())
+    }
+}
+
+/// The metadata of a file recorded with its digests, fields not recorded
+/// being `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    mode: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    mtime: Option<i64>,
+}
+
+impl FileMetadata {
+    /// The `fields` of a file with `metadata`. Fields the platform does not
+    /// have, like owners on Windows, are left out.
+    pub fn from_fs(metadata: &Metadata, fields: &[FileField]) -> Result<Self> {
+        let mut file = FileMetadata::default();
+        for field in fields {
+            match field {
+                FileField::Mode => file.mode = mode(metadata),
+                FileField::Owner => file.uid = owner(metadata).map(|(uid, _)| uid),
+                FileField::Group => file.gid = owner(metadata).map(|(_, gid)| gid),
+                FileField::ModificationTime => {
+                    file.mtime = Some(DateTime::<Utc>::from(metadata.modified()?).timestamp())
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// The metadata recorded in `hashes`, failing on malformed fields.
+    pub fn from_target(hashes: &TargetDescription) -> Result<Self> {
+        let small = |field: FileField| -> Result<Option<u32>> {
+            Ok(field_bytes(hashes, field)?.map(u32::from_be_bytes))
+        };
+        Ok(FileMetadata {
+            mode: small(FileField::Mode)?,
+            uid: small(FileField::Owner)?,
+            gid: small(FileField::Group)?,
+            mtime: field_bytes(hashes, FileField::ModificationTime)?.map(i64::from_be_bytes),
+        })
+    }
+
+    /// The permission bits
+    pub fn mode(&self) -> Option<u32> {
+        self.mode
+    }
+
+    /// The user ID of the owner
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// The group ID of the owner
+    pub fn gid(&self) -> Option<u32> {
+        self.gid
+    }
+
+    /// The modification time, in seconds since the Unix epoch
+    pub fn mtime(&self) -> Option<i64> {
+        self.mtime
+    }
+
+    /// Record the fields in `hashes`, replacing those recorded before.
+    pub fn add_to(&self, hashes: &mut TargetDescription) {
+        let fields = [
+            (FileField::Mode, self.mode.map(|v| v.to_be_bytes().to_vec())),
+            (FileField::Owner, self.uid.map(|v| v.to_be_bytes().to_vec())),
+            (FileField::Group, self.gid.map(|v| v.to_be_bytes().to_vec())),
+            (
+                FileField::ModificationTime,
+                self.mtime.map(|v| v.to_be_bytes().to_vec()),
+            ),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                hashes.insert(field.algorithm(), HashValue::new(value));
+            }
+        }
+    }
+
+    /// `hashes` without the fields of file metadata, only the digests.
+    pub fn digests(hashes: &TargetDescription) -> TargetDescription {
+        let fields: Vec<HashAlgorithm> = FileField::ALL.iter().map(FileField::algorithm).collect();
+        hashes
+            .iter()
+            .filter(|(algorithm, _)| !fields.contains(algorithm))
+            .map(|(algorithm, value)| (algorithm.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// The value of `field` in `hashes` if recorded, failing if it is not `N`
+/// bytes long.
+fn field_bytes<const N: usize>(
+    hashes: &TargetDescription,
+    field: FileField,
+) -> Result<Option<[u8; N]>> {
+    match hashes.get(&field.algorithm()) {
+        Some(value) => <[u8; N]>::try_from(value.value()).map(Some).map_err(|_| {
+            Error::Encoding(format!(
+                "{} of {} bytes, not {}",
+                field.name(),
+                value.value().len(),
+                N
+            ))
+        }),
+        None => Ok(None),
+    }
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn owner(metadata: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.uid(), metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_metadata: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileField, FileMetadata};
+    use crate::crypto::{HashAlgorithm, HashValue};
+    use crate::models::TargetDescription;
+
+    #[test]
+    fn fields_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tool");
+        std::fs::write(&path, "#!/bin/sh").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let metadata = std::fs::metadata(&path).unwrap();
+        let file = FileMetadata::from_fs(&metadata, &FileField::ALL).unwrap();
+        assert!(file.mtime().unwrap() > 0);
+        if cfg!(unix) {
+            assert_eq!(file.mode(), Some(0o755));
+            assert!(file.uid().is_some() && file.gid().is_some());
+        }
+
+        let mut hashes = TargetDescription::new();
+        hashes.insert(HashAlgorithm::Sha256, HashValue::new(vec![1; 32]));
+        let digests = hashes.clone();
+        file.add_to(&mut hashes);
+        assert_eq!(FileMetadata::from_target(&hashes).unwrap(), file);
+        assert_eq!(FileMetadata::digests(&hashes), digests);
+
+        let json = serde_json::to_value(&hashes).unwrap();
+        if cfg!(unix) {
+            assert_eq!(json["file-mode"], "000001ed");
+        }
+        let parsed: TargetDescription = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, hashes);
+
+        hashes.insert(FileField::Mode.algorithm(), HashValue::new(vec![1]));
+        assert!(FileMetadata::from_target(&hashes).is_err());
+    }
+}
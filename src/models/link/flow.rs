@@ -0,0 +1,234 @@
+//! Flow of artifacts between the steps of a supply chain.
+//!
+//! A step consumed an artifact produced by another step if one of its
+//! materials has the path and hashes of an artifact the other step created
+//! or modified. Products a step merely passed on unchanged do not count, the
+//! artifact is attributed to the step that last changed it. `ArtifactFlow`
+//! builds the resulting provenance graph from links and flags the artifacts
+//! at its edges:
+//!
+//! * materials no step produced, which came from outside the supply chain
+//! * products no other step consumed, the final outputs, or leftovers
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::LinkMetadata;
+use crate::models::{TargetDescription, VirtualTargetPath};
+
+/// An artifact `producer` created or modified and `consumer` used as
+/// material.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArtifactEdge {
+    path: VirtualTargetPath,
+    producer: String,
+    consumer: String,
+}
+
+impl ArtifactEdge {
+    /// Path of the artifact
+    pub fn path(&self) -> &str {
+        self.path.value()
+    }
+
+    /// Name of the step producing the artifact
+    pub fn producer(&self) -> &str {
+        &self.producer
+    }
+
+    /// Name of the step consuming the artifact
+    pub fn consumer(&self) -> &str {
+        &self.consumer
+    }
+}
+
+/// An artifact of a step.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StepArtifact {
+    step: String,
+    path: VirtualTargetPath,
+}
+
+impl StepArtifact {
+    /// Name of the step
+    pub fn step(&self) -> &str {
+        &self.step
+    }
+
+    /// Path of the artifact
+    pub fn path(&self) -> &str {
+        self.path.value()
+    }
+}
+
+/// The provenance graph of the artifacts of a set of links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactFlow {
+    edges: BTreeSet<ArtifactEdge>,
+    unsourced: Vec<StepArtifact>,
+    unconsumed: Vec<StepArtifact>,
+}
+
+impl ArtifactFlow {
+    /// Trace the artifacts between `links`. Links of the same step, e.g.
+    /// signed by different functionaries, are taken together. If several
+    /// steps produced an artifact with the same hashes, the first by name is
+    /// taken as its producer.
+    pub fn of<'a, I>(links: I) -> Self
+    where
+        I: IntoIterator<Item = &'a LinkMetadata>,
+    {
+        let links: Vec<&LinkMetadata> = links.into_iter().collect();
+        let mut produced: BTreeMap<&VirtualTargetPath, BTreeMap<&str, &TargetDescription>> =
+            BTreeMap::new();
+        for link in &links {
+            for (path, hashes) in link.products() {
+                if link.materials().get(path) != Some(hashes) {
+                    produced
+                        .entry(path)
+                        .or_default()
+                        .insert(link.name(), hashes);
+                }
+            }
+        }
+
+        let mut edges = BTreeSet::new();
+        let mut unsourced = BTreeSet::new();
+        for link in &links {
+            for (path, hashes) in link.materials() {
+                let producer = produced.get(path).and_then(|steps| {
+                    steps.iter().find(|(step, produced)| {
+                        **step != link.name().as_str() && **produced == hashes
+                    })
+                });
+                match producer {
+                    Some((producer, _)) => edges.insert(ArtifactEdge {
+                        path: path.clone(),
+                        producer: producer.to_string(),
+                        consumer: link.name().clone(),
+                    }),
+                    None => unsourced.insert(StepArtifact {
+                        step: link.name().clone(),
+                        path: path.clone(),
+                    }),
+                };
+            }
+        }
+
+        let consumed: BTreeSet<(&str, &VirtualTargetPath)> = edges
+            .iter()
+            .map(|edge| (edge.producer.as_str(), &edge.path))
+            .collect();
+        let unconsumed = produced
+            .iter()
+            .flat_map(|(path, steps)| steps.keys().map(move |step| (*step, *path)))
+            .filter(|artifact| !consumed.contains(artifact))
+            .map(|(step, path)| StepArtifact {
+                step: step.to_string(),
+                path: path.clone(),
+            })
+            .collect::<BTreeSet<_>>();
+
+        ArtifactFlow {
+            edges,
+            unsourced: unsourced.into_iter().collect(),
+            unconsumed: unconsumed.into_iter().collect(),
+        }
+    }
+
+    /// All artifacts passed from one step to another
+    pub fn edges(&self) -> impl Iterator<Item = &ArtifactEdge> {
+        self.edges.iter()
+    }
+
+    /// The step that produced the material `path` of step `step`, if any
+    pub fn producer(&self, step: &str, path: &str) -> Option<&str> {
+        self.edges
+            .iter()
+            .find(|edge| edge.consumer == step && edge.path.value() == path)
+            .map(|edge| edge.producer.as_str())
+    }
+
+    /// The steps that consumed the artifact `path` produced by step `step`
+    pub fn consumers(&self, step: &str, path: &str) -> Vec<&str> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.producer == step && edge.path.value() == path)
+            .map(|edge| edge.consumer.as_str())
+            .collect()
+    }
+
+    /// The names of the steps whose products each step consumed, by step
+    /// name. Steps consuming no products are left out.
+    pub fn step_dependencies(&self) -> BTreeMap<&str, BTreeSet<&str>> {
+        let mut dependencies: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for edge in &self.edges {
+            dependencies
+                .entry(&edge.consumer)
+                .or_default()
+                .insert(&edge.producer);
+        }
+        dependencies
+    }
+
+    /// Materials no other step produced, by step and path
+    pub fn unsourced_materials(&self) -> &[StepArtifact] {
+        &self.unsourced
+    }
+
+    /// Artifacts created or modified by a step that no other step consumed,
+    /// by step and path
+    pub fn unconsumed_products(&self) -> &[StepArtifact] {
+        &self.unconsumed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ArtifactFlow;
+    use crate::test_utils::link;
+
+    #[test]
+    fn trace_artifacts_between_steps() {
+        let links = [
+            link("fetch", &[], &[("src/a.c", b"a"), ("README", b"r")]),
+            link("patch", &[("src/a.c", b"a")], &[("src/a.c", b"a2")]),
+            // builds with the patched source, and passes the readme on
+            link(
+                "build",
+                &[("src/a.c", b"a2"), ("README", b"r"), ("tool", b"t")],
+                &[("README", b"r"), ("a.out", b"o")],
+            ),
+            link(
+                "package",
+                &[("README", b"r"), ("a.out", b"o")],
+                &[("a.tgz", b"z")],
+            ),
+        ];
+        let flow = ArtifactFlow::of(&links);
+
+        assert_eq!(flow.producer("patch", "src/a.c"), Some("fetch"));
+        assert_eq!(flow.producer("build", "src/a.c"), Some("patch"));
+        // the readme passed through build unchanged is attributed to fetch
+        assert_eq!(flow.producer("package", "README"), Some("fetch"));
+        assert_eq!(flow.consumers("fetch", "README"), ["build", "package"]);
+        assert_eq!(flow.edges().count(), 5);
+
+        let dependencies = flow.step_dependencies();
+        let build: Vec<&str> = dependencies["build"].iter().copied().collect();
+        assert_eq!(build, ["fetch", "patch"]);
+        assert!(!dependencies.contains_key("fetch"));
+
+        let unsourced: Vec<_> = flow
+            .unsourced_materials()
+            .iter()
+            .map(|a| (a.step(), a.path()))
+            .collect();
+        assert_eq!(unsourced, [("build", "tool")]);
+        let unconsumed: Vec<_> = flow
+            .unconsumed_products()
+            .iter()
+            .map(|a| (a.step(), a.path()))
+            .collect();
+        assert_eq!(unconsumed, [("package", "a.tgz")]);
+    }
+}
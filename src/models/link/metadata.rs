@@ -6,14 +6,16 @@ use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
 
 use crate::crypto::{self, PrivateKey};
 use crate::interchange::{DataInterchange, Json};
-use crate::Result;
+use crate::{Error, Result};
 
 use crate::models::step::Command;
 use crate::models::{
-    Link, Metablock, Metadata, MetadataType, MetadataWrapper, TargetDescription, VirtualTargetPath,
+    validate_step_name, Link, Metablock, Metadata, MetadataType, MetadataWrapper, SpecVersion,
+    TargetDescription, VirtualTargetPath,
 };
 
 use super::byproducts::ByProducts;
@@ -26,6 +28,9 @@ pub struct LinkMetadataBuilder {
     env: Option<BTreeMap<String, String>>,
     byproducts: ByProducts,
     command: Command,
+    spec_version: Option<SpecVersion>,
+    // the first error of adding an artifact, returned by `build`
+    error: Option<Error>,
 }
 
 impl Default for LinkMetadataBuilder {
@@ -43,9 +48,49 @@ impl LinkMetadataBuilder {
             env: None,
             byproducts: ByProducts::new(),
             command: Command::default(),
+            spec_version: None,
+            error: None,
         }
     }
 
+    /// Create a `LinkMetadataBuilder` holding all fields of `link`.
+    pub fn from_metadata(link: LinkMetadata) -> Self {
+        LinkMetadataBuilder {
+            name: link.name,
+            materials: link.materials,
+            products: link.products,
+            env: link.env,
+            byproducts: link.byproducts,
+            command: link.command,
+            spec_version: link.spec_version,
+            error: None,
+        }
+    }
+
+    /// Load an existing link from `path` to amend it before signing it
+    /// again, e.g. an unfinished link of a step recorded in two phases.
+    ///
+    /// The file may hold a signed `Metablock` or bare link metadata. Existing
+    /// signatures are dropped and NOT verified, as they are invalidated by any
+    /// change anyway.
+    pub fn from_link<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let value: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let link = match value.get("signed") {
+            Some(_) => match serde_json::from_value::<Metablock>(value)?.metadata() {
+                MetadataWrapper::Link(link) => link.clone(),
+                MetadataWrapper::Layout(_) => {
+                    return Err(Error::IllegalArgument(format!(
+                        "{} does not hold a link",
+                        path.display()
+                    )))
+                }
+            },
+            None => serde_json::from_value(value)?,
+        };
+        Ok(Self::from_metadata(link))
+    }
+
     /// Set the name number for this link
     pub fn name(mut self, name: String) -> Self {
         self.name = name;
@@ -64,21 +109,27 @@ impl LinkMetadataBuilder {
         self
     }
 
+    /// Add the file `material_path` as a material, hashed with sha256. Failing
+    /// to hash it fails `build`.
     pub fn add_material(mut self, material_path: VirtualTargetPath) -> Self {
-        let file = File::open(material_path.to_string()).unwrap();
-        let mut reader = BufReader::new(file);
-        let (_length, hashes) =
-            crypto::calculate_hashes(&mut reader, &[crypto::HashAlgorithm::Sha256]).unwrap();
-        self.materials.insert(material_path, hashes);
+        match hash_file(&material_path) {
+            Ok(hashes) => {
+                self.materials.insert(material_path, hashes);
+            }
+            Err(e) => self.error = self.error.or(Some(e)),
+        }
         self
     }
 
-    pub fn add_product(mut self, material_path: VirtualTargetPath) -> Self {
-        let file = File::open(material_path.to_string()).unwrap();
-        let mut reader = BufReader::new(file);
-        let (_length, hashes) =
-            crypto::calculate_hashes(&mut reader, &[crypto::HashAlgorithm::Sha256]).unwrap();
-        self.products.insert(material_path, hashes);
+    /// Add the file `product_path` as a product, hashed with sha256. Failing
+    /// to hash it fails `build`.
+    pub fn add_product(mut self, product_path: VirtualTargetPath) -> Self {
+        match hash_file(&product_path) {
+            Ok(hashes) => {
+                self.products.insert(product_path, hashes);
+            }
+            Err(e) => self.error = self.error.or(Some(e)),
+        }
         self
     }
 
@@ -100,15 +151,31 @@ impl LinkMetadataBuilder {
         self
     }
 
+    /// Set the spec version this link declares, which is left out by default
+    pub fn spec_version(mut self, spec_version: SpecVersion) -> Self {
+        self.spec_version = Some(spec_version);
+        self
+    }
+
+    /// Build the link, failing if it has a name that is not a valid step
+    /// name, see `validate_step_name`.
     pub fn build(self) -> Result<LinkMetadata> {
-        LinkMetadata::new(
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if !self.name.is_empty() {
+            validate_step_name(&self.name)?;
+        }
+        let mut meta = LinkMetadata::new(
             self.name,
             self.materials,
             self.products,
             self.env,
             self.byproducts,
             self.command,
-        )
+        )?;
+        meta.set_spec_version(self.spec_version);
+        Ok(meta)
     }
 
     /// Construct a new `Metablock<D, LinkMetadata>`.
@@ -128,6 +195,14 @@ impl LinkMetadataBuilder {
     }
 }
 
+/// The sha256 digest of the file `path`.
+fn hash_file(path: &VirtualTargetPath) -> Result<TargetDescription> {
+    let mut reader = BufReader::new(File::open(path.value())?);
+    let (_length, hashes) =
+        crypto::calculate_hashes(&mut reader, &[crypto::HashAlgorithm::Sha256])?;
+    Ok(hashes)
+}
+
 /// link metadata
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LinkMetadata {
@@ -137,6 +212,7 @@ pub struct LinkMetadata {
     env: Option<BTreeMap<String, String>>,
     byproducts: ByProducts,
     command: Command,
+    spec_version: Option<SpecVersion>,
 }
 
 impl LinkMetadata {
@@ -156,9 +232,14 @@ impl LinkMetadata {
             env,
             byproducts,
             command,
+            spec_version: None,
         })
     }
 
+    pub(crate) fn set_spec_version(&mut self, spec_version: Option<SpecVersion>) {
+        self.spec_version = spec_version;
+    }
+
     // The step this link is associated to
     pub fn name(&self) -> &String {
         &self.name
@@ -188,6 +269,11 @@ impl LinkMetadata {
     pub fn command(&self) -> &Command {
         &self.command
     }
+
+    // The spec version the link declares, if any
+    pub fn spec_version(&self) -> Option<SpecVersion> {
+        self.spec_version
+    }
 }
 
 impl Metadata for LinkMetadata {
@@ -228,10 +314,15 @@ impl<'de> Deserialize<'de> for LinkMetadata {
 mod test {
     use serde_json::json;
 
+    use crate::crypto::PrivateKey;
+    use crate::interchange::Json;
     use crate::models::{
-        byproducts::ByProducts, step::Command, LinkMetadata, LinkMetadataBuilder, VirtualTargetPath,
+        byproducts::ByProducts, step::Command, LinkMetadata, LinkMetadataBuilder, SpecVersion,
+        VirtualTargetPath,
     };
 
+    const ALICE_PRIVATE_KEY: &[u8] = include_bytes!("../../../tests/ed25519/ed25519-1");
+
     #[test]
     fn serialize_linkmetadata() {
         let link_metadata = LinkMetadataBuilder::new()
@@ -268,6 +359,92 @@ mod test {
         assert_eq!(json, serialized_linkmetadata);
     }
 
+    #[test]
+    fn builder_rejects_invalid_names() {
+        assert!(LinkMetadataBuilder::new()
+            .name("../write-code".into())
+            .build()
+            .is_err());
+        assert!(LinkMetadataBuilder::new()
+            .name("write-code".into())
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn builder_from_link() {
+        let link = LinkMetadataBuilder::from_link("tests/test_metadata/demo.link")
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            link.products().keys().collect::<Vec<_>>(),
+            [&VirtualTargetPath::new("tests/test_link/foo.tar.gz".into()).unwrap()]
+        );
+        assert_eq!(link.byproducts().stderr(), "a foo.py\n");
+
+        // amend and sign again
+        let key = PrivateKey::from_ed25519(ALICE_PRIVATE_KEY).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let unfinished = dir.path().join(".build.5a1a1d17.link-unfinished");
+        let metablock = LinkMetadataBuilder::from_metadata(link)
+            .name("build".into())
+            .signed::<Json>(&key)
+            .unwrap();
+        std::fs::write(&unfinished, serde_json::to_vec(&metablock).unwrap()).unwrap();
+
+        let amended = LinkMetadataBuilder::from_link(&unfinished)
+            .unwrap()
+            .byproducts(ByProducts::new().set_return_value(0))
+            .build()
+            .unwrap();
+        assert_eq!(amended.name(), "build");
+        assert_eq!(amended.byproducts(), &ByProducts::new().set_return_value(0));
+
+        // bare link metadata
+        std::fs::write(&unfinished, serde_json::to_vec(&amended).unwrap()).unwrap();
+        let bare = LinkMetadataBuilder::from_link(&unfinished)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(bare, amended);
+
+        assert!(LinkMetadataBuilder::from_link("tests/test_metadata/demo.layout").is_err());
+        assert!(LinkMetadataBuilder::from_link("tests/test_metadata/missing.link").is_err());
+    }
+
+    #[test]
+    fn linkmetadata_spec_version() {
+        // links of spec 0.9 may come without environment and byproducts
+        let json = json!({
+            "_type": "link",
+            "name": "package",
+            "materials": {},
+            "products": {},
+            "command": "tar zcvf foo.tar.gz foo.py"
+        });
+        let link: LinkMetadata = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(link.spec_version(), None);
+        assert_eq!(link.byproducts(), &ByProducts::new());
+        assert!(serde_json::to_value(&link)
+            .unwrap()
+            .get("spec_version")
+            .is_none());
+
+        let mut json = json;
+        json["spec_version"] = json!("0.9");
+        let link: LinkMetadata = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(link.spec_version(), Some(SpecVersion::V0_9));
+        let rebuilt = LinkMetadataBuilder::from_metadata(link.clone())
+            .build()
+            .unwrap();
+        assert_eq!(rebuilt, link);
+        assert_eq!(serde_json::to_value(&link).unwrap()["spec_version"], "0.9");
+
+        json["spec_version"] = json!("0.9-dev");
+        assert!(serde_json::from_value::<LinkMetadata>(json).is_err());
+    }
+
     #[test]
     fn deserialize_linkmetadata() {
         let json = r#"{
@@ -304,4 +481,12 @@ mod test {
         let deserialized_link_metadata: LinkMetadata = serde_json::from_str(json).unwrap();
         assert_eq!(link_metadata, deserialized_link_metadata);
     }
+
+    #[test]
+    fn add_missing_artifact() {
+        let builder = LinkMetadataBuilder::new()
+            .add_material(VirtualTargetPath::new("tests/test_link/missing".into()).unwrap())
+            .add_product(VirtualTargetPath::new("tests/test_link/foo.tar.gz".into()).unwrap());
+        assert!(builder.build().is_err());
+    }
 }
@@ -7,28 +7,38 @@ use std::str;
 use crate::Result;
 use serde_derive::{Deserialize, Serialize};
 
+pub mod attempt;
 pub mod byproducts;
+pub mod file_metadata;
+mod flow;
 pub mod metadata;
+pub mod network;
+pub mod skipped;
+pub mod times;
+pub mod toolchain;
+pub use flow::{ArtifactEdge, ArtifactFlow, StepArtifact};
 pub use metadata::{LinkMetadata, LinkMetadataBuilder};
 
-use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::models::{SpecVersion, TargetDescription, VirtualTargetPath};
 
 use self::byproducts::ByProducts;
 
 use super::step::Command;
 
-// FIXME, we need to tag a spec
-//const SPEC_VERSION: &str = "0.9-dev";
-
+/// Serialized form of `LinkMetadata`, see `models::spec` for the
+/// differences between spec versions.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Link {
     #[serde(rename = "_type")]
     typ: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spec_version: Option<SpecVersion>,
     name: String,
     materials: BTreeMap<VirtualTargetPath, TargetDescription>,
     products: BTreeMap<VirtualTargetPath, TargetDescription>,
-    #[serde(rename = "environment")]
+    #[serde(rename = "environment", default)]
     env: Option<BTreeMap<String, String>>,
+    #[serde(default)]
     byproducts: ByProducts,
     command: Command,
 }
@@ -37,6 +47,7 @@ impl Link {
     pub fn from(meta: &LinkMetadata) -> Result<Self> {
         Ok(Link {
             typ: String::from("link"),
+            spec_version: meta.spec_version(),
             name: meta.name().to_string(),
             materials: (*meta.materials()).clone(),
             products: (*meta.products()).clone(),
@@ -47,13 +58,15 @@ impl Link {
     }
 
     pub fn try_into(self) -> Result<LinkMetadata> {
-        LinkMetadata::new(
+        let mut meta = LinkMetadata::new(
             self.name,
             self.materials,
             self.products,
             self.env,
             self.byproducts,
             self.command,
-        )
+        )?;
+        meta.set_spec_version(self.spec_version);
+        Ok(meta)
     }
 }
@@ -0,0 +1,149 @@
+//! Network destinations contacted by the command of a step.
+//!
+//! The tracer records the host names the command looked up and the
+//! addresses it contacted as the byproduct `NETWORK_BYPRODUCT`, so that
+//! verifiers can check a step only fetched from allowed sources.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, SocketAddr};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::byproducts::ByProducts;
+use crate::{Error, Result};
+
+/// Name of the byproduct holding the `NetworkAccesses` of a command, as JSON.
+pub const NETWORK_BYPRODUCT: &str = "network";
+
+/// Port of DNS servers, whose traffic is how host names are learned.
+const DNS_PORT: u16 = 53;
+
+/// The host names a command looked up, with the addresses they resolved to,
+/// and the addresses it contacted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkAccesses {
+    #[serde(default)]
+    hosts: BTreeMap<String, BTreeSet<IpAddr>>,
+    #[serde(default)]
+    addresses: BTreeSet<SocketAddr>,
+}
+
+impl NetworkAccesses {
+    /// No network accesses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note that `host` was looked up, resolving to `resolved`
+    pub fn add_host(&mut self, host: String, resolved: impl IntoIterator<Item = IpAddr>) {
+        self.hosts.entry(host).or_default().extend(resolved);
+    }
+
+    /// Note that `address` was contacted
+    pub fn add_address(&mut self, address: SocketAddr) {
+        self.addresses.insert(address);
+    }
+
+    /// The host names looked up, with the addresses they resolved to
+    pub fn hosts(&self) -> &BTreeMap<String, BTreeSet<IpAddr>> {
+        &self.hosts
+    }
+
+    /// The addresses contacted
+    pub fn addresses(&self) -> &BTreeSet<SocketAddr> {
+        &self.addresses
+    }
+
+    /// Whether nothing was looked up or contacted
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.addresses.is_empty()
+    }
+
+    /// `byproducts` with these accesses as byproduct `NETWORK_BYPRODUCT`
+    pub fn to_byproducts(&self, byproducts: ByProducts) -> Result<ByProducts> {
+        Ok(byproducts.set_other_field(NETWORK_BYPRODUCT.into(), serde_json::to_string(self)?))
+    }
+
+    /// The accesses recorded in `byproducts`, if they were recorded.
+    pub fn from_byproducts(byproducts: &ByProducts) -> Result<Option<Self>> {
+        match byproducts.other_field_str(NETWORK_BYPRODUCT)? {
+            Some(json) => Ok(Some(serde_json::from_str(json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Check that only `allowed_hosts` were contacted. A host is allowed if
+    /// it is listed, or a subdomain of a listed `*.example.com`. Addresses
+    /// must have been resolved from an allowed host, except loopback
+    /// addresses and DNS servers.
+    pub fn verify_allowed(&self, allowed_hosts: &[&str]) -> Result<()> {
+        let allowed = |host: &str| {
+            allowed_hosts
+                .iter()
+                .any(|pattern| match pattern.strip_prefix("*.") {
+                    Some(domain) => host
+                        .strip_suffix(domain)
+                        .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                    None => host == *pattern,
+                })
+        };
+        if let Some(host) = self.hosts.keys().find(|host| !allowed(host)) {
+            return Err(Error::VerificationFailure(format!(
+                "host {} is not an allowed source",
+                host
+            )));
+        }
+        let resolved: BTreeSet<&IpAddr> = self.hosts.values().flatten().collect();
+        for address in &self.addresses {
+            if address.ip().is_loopback()
+                || address.port() == DNS_PORT
+                || resolved.contains(&address.ip())
+            {
+                continue;
+            }
+            return Err(Error::VerificationFailure(format!(
+                "address {} was not resolved from an allowed source",
+                address
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NetworkAccesses;
+    use crate::models::byproducts::ByProducts;
+
+    #[test]
+    fn allowed_sources() {
+        let mut accesses = NetworkAccesses::new();
+        assert!(NetworkAccesses::from_byproducts(&ByProducts::new())
+            .unwrap()
+            .is_none());
+        accesses.add_host(
+            "files.pythonhosted.org".into(),
+            ["151.101.0.223".parse().unwrap()],
+        );
+        accesses.add_address("151.101.0.223:443".parse().unwrap());
+        accesses.add_address("10.0.0.1:53".parse().unwrap());
+        accesses.add_address("[::1]:8080".parse().unwrap());
+
+        let byproducts = accesses.to_byproducts(ByProducts::new()).unwrap();
+        let recorded = NetworkAccesses::from_byproducts(&byproducts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recorded, accesses);
+
+        assert!(recorded.verify_allowed(&["files.pythonhosted.org"]).is_ok());
+        assert!(recorded.verify_allowed(&["*.pythonhosted.org"]).is_ok());
+        assert!(recorded
+            .verify_allowed(&["*.files.pythonhosted.org"])
+            .is_err());
+        assert!(recorded.verify_allowed(&["pypi.org"]).is_err());
+
+        // contacting an address without looking it up
+        accesses.add_address("192.0.2.1:443".parse().unwrap());
+        assert!(accesses.verify_allowed(&["*.pythonhosted.org"]).is_err());
+    }
+}
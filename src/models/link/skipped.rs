@@ -0,0 +1,74 @@
+//! Artifacts of a step left out for their size.
+//!
+//! Build steps may leave multi-gigabyte intermediate files behind, which
+//! take long to hash. With `RecordOptions::max_file_size` such files can be
+//! skipped, and as skipping an artifact hides it from the artifact rules,
+//! `in_toto_run` records which were skipped as the byproduct
+//! `SKIPPED_ARTIFACTS_BYPRODUCT`.
+
+use std::collections::BTreeSet;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::byproducts::ByProducts;
+use crate::models::VirtualTargetPath;
+use crate::Result;
+
+/// Name of the byproduct holding the `SkippedArtifacts` of a step, as JSON.
+pub const SKIPPED_ARTIFACTS_BYPRODUCT: &str = "skipped-artifacts";
+
+/// The materials and products of a step that were not recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedArtifacts {
+    #[serde(default)]
+    materials: BTreeSet<VirtualTargetPath>,
+    #[serde(default)]
+    products: BTreeSet<VirtualTargetPath>,
+}
+
+impl SkippedArtifacts {
+    /// No skipped artifacts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The materials skipped
+    pub fn materials(&self) -> &BTreeSet<VirtualTargetPath> {
+        &self.materials
+    }
+
+    /// The products skipped
+    pub fn products(&self) -> &BTreeSet<VirtualTargetPath> {
+        &self.products
+    }
+
+    /// Whether no artifact was skipped
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty() && self.products.is_empty()
+    }
+
+    pub(crate) fn materials_mut(&mut self) -> &mut BTreeSet<VirtualTargetPath> {
+        &mut self.materials
+    }
+
+    pub(crate) fn products_mut(&mut self) -> &mut BTreeSet<VirtualTargetPath> {
+        &mut self.products
+    }
+
+    /// `byproducts` with these artifacts as byproduct
+    /// `SKIPPED_ARTIFACTS_BYPRODUCT`
+    pub fn to_byproducts(&self, byproducts: ByProducts) -> Result<ByProducts> {
+        Ok(byproducts.set_other_field(
+            SKIPPED_ARTIFACTS_BYPRODUCT.into(),
+            serde_json::to_string(self)?,
+        ))
+    }
+
+    /// The artifacts recorded as skipped in `byproducts`, if any were.
+    pub fn from_byproducts(byproducts: &ByProducts) -> Result<Option<Self>> {
+        match byproducts.other_field_str(SKIPPED_ARTIFACTS_BYPRODUCT)? {
+            Some(json) => Ok(Some(serde_json::from_str(json)?)),
+            None => Ok(None),
+        }
+    }
+}
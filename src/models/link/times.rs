@@ -0,0 +1,138 @@
+//! Modification times of the artifacts of a step.
+//!
+//! Timestamps are the most common source of irreproducible builds. Build
+//! tools honoring `SOURCE_DATE_EPOCH` clamp the times they write to it, so
+//! a product modified after it shows a tool that does not. `in_toto_run`
+//! can record the epoch it ran with and the times of the artifacts as the
+//! byproduct `ARTIFACT_TIMES_BYPRODUCT`, for tooling to check from the link
+//! alone. Times are normalized to whole seconds since the Unix epoch, as
+//! file systems differ in their precision.
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::byproducts::ByProducts;
+use crate::models::VirtualTargetPath;
+use crate::{Error, Result};
+
+/// Name of the byproduct holding the `ArtifactTimes` of a step, as JSON.
+pub const ARTIFACT_TIMES_BYPRODUCT: &str = "artifact-times";
+
+/// Name of the environment variable with the build's timestamp.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// The `SOURCE_DATE_EPOCH` of a step and the modification times of its
+/// artifacts, in seconds since the Unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ArtifactTimes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_date_epoch: Option<i64>,
+    #[serde(default)]
+    materials: BTreeMap<VirtualTargetPath, i64>,
+    #[serde(default)]
+    products: BTreeMap<VirtualTargetPath, i64>,
+}
+
+impl ArtifactTimes {
+    /// No times, for a step run with `source_date_epoch`
+    pub fn new(source_date_epoch: Option<i64>) -> Self {
+        ArtifactTimes {
+            source_date_epoch,
+            ..Default::default()
+        }
+    }
+
+    /// No times, for a step run with the `SOURCE_DATE_EPOCH` of this
+    /// process. Fails if it is set but no integer.
+    pub fn from_env() -> Result<Self> {
+        let epoch = match std::env::var(SOURCE_DATE_EPOCH) {
+            Ok(epoch) => Some(epoch.trim().parse().map_err(|_| {
+                Error::IllegalArgument(format!("{} {:?} is no integer", SOURCE_DATE_EPOCH, epoch))
+            })?),
+            Err(_) => None,
+        };
+        Ok(Self::new(epoch))
+    }
+
+    /// The `SOURCE_DATE_EPOCH` the step ran with, if any
+    pub fn source_date_epoch(&self) -> Option<i64> {
+        self.source_date_epoch
+    }
+
+    /// Modification times of the materials
+    pub fn materials(&self) -> &BTreeMap<VirtualTargetPath, i64> {
+        &self.materials
+    }
+
+    /// Modification times of the products
+    pub fn products(&self) -> &BTreeMap<VirtualTargetPath, i64> {
+        &self.products
+    }
+
+    pub(crate) fn materials_mut(&mut self) -> &mut BTreeMap<VirtualTargetPath, i64> {
+        &mut self.materials
+    }
+
+    pub(crate) fn products_mut(&mut self) -> &mut BTreeMap<VirtualTargetPath, i64> {
+        &mut self.products
+    }
+
+    /// The products modified after `SOURCE_DATE_EPOCH`, whose times were
+    /// not clamped to it. Empty if the step ran without it.
+    pub fn unclamped_products(&self) -> Vec<&VirtualTargetPath> {
+        match self.source_date_epoch {
+            Some(epoch) => self
+                .products
+                .iter()
+                .filter(|(_, time)| **time > epoch)
+                .map(|(path, _)| path)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// `byproducts` with these times as byproduct `ARTIFACT_TIMES_BYPRODUCT`
+    pub fn to_byproducts(&self, byproducts: ByProducts) -> Result<ByProducts> {
+        Ok(byproducts.set_other_field(
+            ARTIFACT_TIMES_BYPRODUCT.into(),
+            serde_json::to_string(self)?,
+        ))
+    }
+
+    /// The times recorded in `byproducts`, if they were recorded.
+    pub fn from_byproducts(byproducts: &ByProducts) -> Result<Option<Self>> {
+        match byproducts.other_field_str(ARTIFACT_TIMES_BYPRODUCT)? {
+            Some(json) => Ok(Some(serde_json::from_str(json)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ArtifactTimes;
+    use crate::models::byproducts::ByProducts;
+    use crate::models::VirtualTargetPath;
+
+    #[test]
+    fn unclamped_products_round_trip() {
+        let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+        let mut times = ArtifactTimes::new(Some(1_000));
+        times.materials_mut().insert(path("src/a.c"), 2_000);
+        times.products_mut().insert(path("a.out"), 1_000);
+        times.products_mut().insert(path("a.tar"), 1_700_000_000);
+        assert_eq!(times.unclamped_products(), [&path("a.tar")]);
+
+        let byproducts = times.to_byproducts(ByProducts::new()).unwrap();
+        assert_eq!(
+            ArtifactTimes::from_byproducts(&byproducts).unwrap(),
+            Some(times)
+        );
+        assert_eq!(
+            ArtifactTimes::from_byproducts(&ByProducts::new()).unwrap(),
+            None
+        );
+    }
+}
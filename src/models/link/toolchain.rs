@@ -0,0 +1,89 @@
+//! Digests of the toolchain definitions of a step.
+//!
+//! Files like `rust-toolchain.toml`, `.nvmrc` or a `Dockerfile` decide which
+//! compiler or image a step builds with. Listing them as materials of every
+//! step makes layouts noisy, so `RunOptions::toolchain_digests` records the
+//! sha256 digests of the `TOOLCHAIN_FILES` present in the run directory in
+//! the environment of the link instead, keyed by `environment_key`:
+//!
+//! ```json
+//! "environment": {"toolchain:rust-toolchain.toml": "sha256:8f43..."}
+//! ```
+//!
+//! Verifiers pin a toolchain by comparing `toolchain_digest` to the digest
+//! they expect.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use data_encoding::HEXLOWER;
+
+use crate::crypto::{self, HashAlgorithm};
+use crate::models::LinkMetadata;
+use crate::Result;
+
+/// The toolchain definition files recorded, by name.
+pub const TOOLCHAIN_FILES: &[&str] = &[
+    "rust-toolchain.toml",
+    "rust-toolchain",
+    ".nvmrc",
+    ".node-version",
+    ".python-version",
+    ".tool-versions",
+    "Dockerfile",
+];
+
+/// The prefix of the environment keys of toolchain digests.
+pub const TOOLCHAIN_ENV_PREFIX: &str = "toolchain:";
+
+/// The environment key of the digest of the toolchain file `file`.
+pub fn environment_key(file: &str) -> String {
+    format!("{}{}", TOOLCHAIN_ENV_PREFIX, file)
+}
+
+/// The digests of the `TOOLCHAIN_FILES` in `dir`, as environment entries.
+/// Files missing are left out.
+pub fn toolchain_digests(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut digests = BTreeMap::new();
+    for file in TOOLCHAIN_FILES {
+        let path = dir.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        let reader = BufReader::new(File::open(&path)?);
+        let (_, hashes) = crypto::calculate_hashes(reader, &[HashAlgorithm::Sha256])?;
+        let digest = HEXLOWER.encode(hashes[&HashAlgorithm::Sha256].value());
+        digests.insert(environment_key(file), format!("sha256:{}", digest));
+    }
+    Ok(digests)
+}
+
+/// The digest of the toolchain file `file` recorded in `link`, like
+/// `sha256:8f43...`
+pub fn toolchain_digest<'a>(link: &'a LinkMetadata, file: &str) -> Option<&'a str> {
+    link.env()
+        .as_ref()?
+        .get(&environment_key(file))
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{toolchain_digests, TOOLCHAIN_ENV_PREFIX};
+
+    #[test]
+    fn digest_toolchain_files() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(toolchain_digests(dir.path()).unwrap().is_empty());
+        std::fs::write(dir.path().join(".nvmrc"), "").unwrap();
+        std::fs::create_dir(dir.path().join("Dockerfile")).unwrap();
+        let digests = toolchain_digests(dir.path()).unwrap();
+        assert_eq!(digests.len(), 1);
+        assert_eq!(
+            digests[&format!("{}.nvmrc", TOOLCHAIN_ENV_PREFIX)],
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
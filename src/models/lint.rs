@@ -0,0 +1,466 @@
+//! Checking link and layout files against the specification.
+//!
+//! Parsing fails on the first problem of a metadata file and serde's error
+//! rarely says where it is. `lint` checks the raw JSON instead and reports
+//! every problem found, each located by a JSON pointer (RFC 6901), e.g.
+//! `/signed/steps/1/expected_materials/0` for the first material rule of
+//! the second step. Signatures are not verified.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use super::rule::ArtifactRule;
+use super::Metablock;
+use crate::Result;
+
+/// A problem of a metadata file found by `lint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintIssue {
+    pointer: String,
+    message: String,
+}
+
+impl LintIssue {
+    /// JSON pointer to the offending value, empty for the whole file
+    pub fn pointer(&self) -> &str {
+        &self.pointer
+    }
+
+    /// What is wrong with the value
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pointer.is_empty() {
+            true => write!(f, "{}", self.message),
+            false => write!(f, "{}: {}", self.pointer, self.message),
+        }
+    }
+}
+
+/// Check the signed link or layout `bytes`, returning the problems found.
+/// Fails only if `bytes` is no JSON at all.
+pub fn lint(bytes: &[u8]) -> Result<Vec<LintIssue>> {
+    let value: Value = serde_json::from_slice(bytes)?;
+    Ok(lint_value(&value))
+}
+
+/// Check the signed link or layout `value`, returning the problems found.
+pub fn lint_value(value: &Value) -> Vec<LintIssue> {
+    let mut linter = Linter::default();
+    linter.metablock(value);
+    // anything else keeping the file from being parsed
+    if linter.issues.is_empty() {
+        if let Err(e) = serde_json::from_value::<Metablock>(value.clone()) {
+            linter.issue(&Pointer::root(), e.to_string());
+        }
+    }
+    linter.issues
+}
+
+/// A JSON pointer to a value being checked.
+#[derive(Clone)]
+struct Pointer(String);
+
+impl Pointer {
+    fn root() -> Self {
+        Pointer(String::new())
+    }
+
+    fn key(&self, key: &str) -> Self {
+        Pointer(format!(
+            "{}/{}",
+            self.0,
+            key.replace('~', "~0").replace('/', "~1")
+        ))
+    }
+
+    fn index(&self, index: usize) -> Self {
+        Pointer(format!("{}/{}", self.0, index))
+    }
+}
+
+#[derive(Default)]
+struct Linter {
+    issues: Vec<LintIssue>,
+}
+
+impl Linter {
+    fn issue(&mut self, at: &Pointer, message: String) {
+        self.issues.push(LintIssue {
+            pointer: at.0.clone(),
+            message,
+        });
+    }
+
+    fn object<'a>(&mut self, value: &'a Value, at: &Pointer) -> Option<&'a Map<String, Value>> {
+        let object = value.as_object();
+        if object.is_none() {
+            self.issue(at, format!("expected an object, found {}", kind(value)));
+        }
+        object
+    }
+
+    fn array<'a>(&mut self, value: &'a Value, at: &Pointer) -> Option<&'a Vec<Value>> {
+        let array = value.as_array();
+        if array.is_none() {
+            self.issue(at, format!("expected an array, found {}", kind(value)));
+        }
+        array
+    }
+
+    fn string<'a>(&mut self, value: &'a Value, at: &Pointer) -> Option<&'a str> {
+        let string = value.as_str();
+        if string.is_none() {
+            self.issue(at, format!("expected a string, found {}", kind(value)));
+        }
+        string
+    }
+
+    /// The field `key` of `object`, reporting it missing
+    fn field<'a>(
+        &mut self,
+        object: &'a Map<String, Value>,
+        key: &str,
+        at: &Pointer,
+    ) -> Option<(&'a Value, Pointer)> {
+        match object.get(key) {
+            Some(value) => Some((value, at.key(key))),
+            None => {
+                self.issue(at, format!("missing field {:?}", key));
+                None
+            }
+        }
+    }
+
+    fn hex(&mut self, value: &Value, at: &Pointer, what: &str) {
+        if let Some(string) = self.string(value, at) {
+            if string.is_empty() {
+                self.issue(at, format!("empty {}", what));
+            } else if string.len() % 2 != 0 || !string.chars().all(|c| c.is_ascii_hexdigit()) {
+                self.issue(at, format!("{} {:?} is no hex string", what, string));
+            }
+        }
+    }
+
+    fn key_id(&mut self, value: &Value, at: &Pointer) {
+        self.hex(value, at, "key ID");
+        if let Some(key_id) = value.as_str() {
+            if !key_id.is_empty() && key_id.len() != 64 {
+                self.issue(at, format!("key ID {:?} is not 64 characters long", key_id));
+            }
+        }
+    }
+
+    fn metablock(&mut self, value: &Value) {
+        let root = Pointer::root();
+        let metablock = match self.object(value, &root) {
+            Some(metablock) => metablock,
+            None => return,
+        };
+        if let Some((signatures, at)) = self.field(metablock, "signatures", &root) {
+            for (i, signature) in self
+                .array(signatures, &at)
+                .into_iter()
+                .flatten()
+                .enumerate()
+            {
+                let at = at.index(i);
+                if let Some(signature) = self.object(signature, &at) {
+                    if let Some((key_id, at)) = self.field(signature, "keyid", &at) {
+                        self.key_id(key_id, &at);
+                    }
+                    if let Some((sig, at)) = self.field(signature, "sig", &at) {
+                        self.hex(sig, &at, "signature");
+                    }
+                }
+            }
+        }
+        let (signed, at) = match self.field(metablock, "signed", &root) {
+            Some(signed) => signed,
+            None => return,
+        };
+        let signed = match self.object(signed, &at) {
+            Some(signed) => signed,
+            None => return,
+        };
+        match self.field(signed, "_type", &at) {
+            Some((Value::String(typ), _)) if typ == "link" => self.link(signed, &at),
+            Some((Value::String(typ), _)) if typ == "layout" => self.layout(signed, &at),
+            Some((typ, at)) => self.issue(
+                &at,
+                format!("expected \"link\" or \"layout\", found {}", typ),
+            ),
+            None => (),
+        }
+    }
+
+    fn link(&mut self, link: &Map<String, Value>, at: &Pointer) {
+        if let Some((name, at)) = self.field(link, "name", at) {
+            self.string(name, &at);
+        }
+        for artifacts in ["materials", "products"] {
+            if let Some((value, at)) = self.field(link, artifacts, at) {
+                self.artifacts(value, &at);
+            }
+        }
+        if let Some((byproducts, at)) = self.field(link, "byproducts", at) {
+            if let Some(byproducts) = self.object(byproducts, &at) {
+                match byproducts.get("return-value") {
+                    Some(Value::Number(n)) if n.is_i64() => (),
+                    Some(value) => self.issue(
+                        &at.key("return-value"),
+                        format!("expected an integer, found {}", value),
+                    ),
+                    None => (),
+                }
+            }
+        }
+    }
+
+    fn artifacts(&mut self, value: &Value, at: &Pointer) {
+        for (path, hashes) in self.object(value, at).into_iter().flatten() {
+            let at = at.key(path);
+            let hashes = match self.object(hashes, &at) {
+                Some(hashes) => hashes,
+                None => continue,
+            };
+            if hashes.is_empty() {
+                self.issue(&at, "no hashes".into());
+            }
+            for (algorithm, digest) in hashes {
+                self.hex(digest, &at.key(algorithm), "digest");
+            }
+        }
+    }
+
+    fn layout(&mut self, layout: &Map<String, Value>, at: &Pointer) {
+        if let Some((expires, at)) = self.field(layout, "expires", at) {
+            if let Some(expires) = self.string(expires, &at) {
+                if chrono::DateTime::parse_from_rfc3339(expires).is_err() {
+                    self.issue(&at, format!("{:?} is no RFC 3339 date", expires));
+                }
+            }
+        }
+        let mut keys = BTreeSet::new();
+        if let Some((value, at)) = self.field(layout, "keys", at) {
+            for (key_id, key) in self.object(value, &at).into_iter().flatten() {
+                let at = at.key(key_id);
+                self.key_id(&Value::String(key_id.clone()), &at);
+                self.object(key, &at);
+                keys.insert(key_id.as_str());
+            }
+        }
+
+        let mut names = BTreeSet::new();
+        for items in ["steps", "inspect"] {
+            let (value, at) = match self.field(layout, items, at) {
+                Some(items) => items,
+                None => continue,
+            };
+            for (i, item) in self.array(value, &at).into_iter().flatten().enumerate() {
+                let at = at.index(i);
+                if let Some(name) = item.get("_name").and_then(Value::as_str) {
+                    if !names.insert(name) {
+                        self.issue(&at.key("_name"), format!("duplicate name {:?}", name));
+                    }
+                }
+            }
+        }
+
+        for items in ["steps", "inspect"] {
+            let (value, at) = match layout.get(items) {
+                Some(value) => (value, at.key(items)),
+                None => continue,
+            };
+            for (i, item) in value.as_array().into_iter().flatten().enumerate() {
+                let at = at.index(i);
+                let item = match self.object(item, &at) {
+                    Some(item) => item,
+                    None => continue,
+                };
+                if let Some((name, at)) = self.field(item, "_name", &at) {
+                    self.string(name, &at);
+                }
+                for rules in ["expected_materials", "expected_products"] {
+                    if let Some((value, at)) = self.field(item, rules, &at) {
+                        self.rules(value, &at, &names);
+                    }
+                }
+                if items == "steps" {
+                    self.step(item, &at, &keys);
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, step: &Map<String, Value>, at: &Pointer, keys: &BTreeSet<&str>) {
+        let mut pubkeys = 0;
+        if let Some((value, at)) = self.field(step, "pubkeys", at) {
+            for (i, key_id) in self.array(value, &at).into_iter().flatten().enumerate() {
+                let at = at.index(i);
+                self.key_id(key_id, &at);
+                if let Some(key_id) = key_id.as_str() {
+                    if !keys.contains(key_id) {
+                        self.issue(&at, format!("key {} is not in the layout's keys", key_id));
+                    }
+                }
+                pubkeys += 1;
+            }
+        }
+        if let Some((threshold, at)) = self.field(step, "threshold", at) {
+            match threshold.as_u64() {
+                Some(0) => self.issue(&at, "threshold must be at least 1".into()),
+                Some(threshold) if threshold > pubkeys => self.issue(
+                    &at,
+                    format!(
+                        "threshold {} exceeds the {} keys of the step",
+                        threshold, pubkeys
+                    ),
+                ),
+                Some(_) => (),
+                None => self.issue(
+                    &at,
+                    format!("expected a positive integer, found {}", threshold),
+                ),
+            }
+        }
+    }
+
+    fn rules(&mut self, value: &Value, at: &Pointer, names: &BTreeSet<&str>) {
+        for (i, rule) in self.array(value, at).into_iter().flatten().enumerate() {
+            let at = at.index(i);
+            match serde_json::from_value::<ArtifactRule>(rule.clone()) {
+                Ok(rule) => {
+                    if let Some(step) = rule.from_step() {
+                        if !names.contains(step) {
+                            self.issue(&at, format!("no step or inspection is named {:?}", step));
+                        }
+                    }
+                }
+                Err(e) => self.issue(&at, format!("invalid rule {}: {}", rule, e)),
+            }
+        }
+    }
+}
+
+/// The JSON type of `value`, for messages
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{lint, lint_value};
+
+    fn issues(value: serde_json::Value) -> Vec<String> {
+        lint_value(&value).iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn lint_demo_metadata() {
+        for file in ["demo.layout", "demo.link"] {
+            let bytes = std::fs::read(format!("tests/test_metadata/{}", file)).unwrap();
+            assert_eq!(lint(&bytes).unwrap(), [], "{}", file);
+        }
+        assert!(lint(b"not json").is_err());
+    }
+
+    #[test]
+    fn lint_broken_link() {
+        let link = json!({
+            "signatures": [{"keyid": "", "sig": "abc"}],
+            "signed": {
+                "_type": "link",
+                "name": "build",
+                "materials": {"src/a.c": {"sha256": "not hex"}},
+                "products": {"a.out": {}},
+                "byproducts": {"return-value": 0.0},
+                "command": [],
+                "environment": null
+            }
+        });
+        assert_eq!(
+            issues(link),
+            [
+                "/signatures/0/keyid: empty key ID",
+                "/signatures/0/sig: signature \"abc\" is no hex string",
+                "/signed/materials/src~1a.c/sha256: digest \"not hex\" is no hex string",
+                "/signed/products/a.out: no hashes",
+                "/signed/byproducts/return-value: expected an integer, found 0.0",
+            ]
+        );
+        assert_eq!(
+            issues(json!({"signatures": [], "signed": {"_type": "links"}})),
+            ["/signed/_type: expected \"link\" or \"layout\", found \"links\""]
+        );
+    }
+
+    #[test]
+    fn lint_broken_layout() {
+        let key_id = "e0294a3f17cc8563c3ed5fceb3bd8d3f6bfeeaca499b5c9572729ae015566554";
+        let layout = json!({
+            "signatures": [],
+            "signed": {
+                "_type": "layout",
+                "expires": "tomorrow",
+                "readme": "",
+                "keys": {},
+                "steps": [{
+                    "_name": "build",
+                    "threshold": 2,
+                    "pubkeys": [key_id],
+                    "expected_materials": [["MATCH", "*", "WITH", "PRODUCTS", "FROM", "fetch"]],
+                    "expected_products": [["CREATE"], ["ALLOW", "*"]],
+                    "expected_command": []
+                }],
+                "inspect": [{
+                    "_name": "build",
+                    "expected_materials": [],
+                    "expected_products": [],
+                    "run": []
+                }]
+            }
+        });
+        let found = issues(layout);
+        assert_eq!(
+            found[0],
+            "/signed/expires: \"tomorrow\" is no RFC 3339 date"
+        );
+        assert_eq!(
+            found[1],
+            "/signed/inspect/0/_name: duplicate name \"build\""
+        );
+        assert_eq!(
+            found[2],
+            "/signed/steps/0/expected_materials/0: no step or inspection is named \"fetch\""
+        );
+        assert!(found[3].starts_with("/signed/steps/0/expected_products/0: invalid rule"));
+        assert_eq!(
+            found[4],
+            format!(
+                "/signed/steps/0/pubkeys/0: key {} is not in the layout's keys",
+                key_id
+            )
+        );
+        assert_eq!(
+            found[5],
+            "/signed/steps/0/threshold: threshold 2 exceeds the 1 keys of the step"
+        );
+        assert_eq!(found.len(), 6);
+    }
+}
@@ -3,8 +3,9 @@
 //! Metadata is the top level abstract for both layout metadata and link
 //! metadata. Metadata it is devided into two types
 //! * enum `MetadataWrapper` is used to do serialize, deserialize and
-//! other object unsafe operations.
+//!   other object unsafe operations.
 //! * trait `Metadata` is used to work for trait object.
+//!
 //! The reason please refer to issue https://github.com/in-toto/in-toto-rs/issues/33
 //!
 //! # Metablock
@@ -22,7 +23,7 @@ use std::str;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use crate::crypto::{KeyId, PrivateKey, PublicKey, Signature};
+use crate::crypto::{ExternalCommandSigner, KeyId, PrivateKey, PublicKey, Signature};
 use crate::error::Error;
 use crate::interchange::{DataInterchange, Json};
 use crate::Result;
@@ -31,6 +32,15 @@ use super::{LayoutMetadata, LinkMetadata};
 
 pub const FILENAME_FORMAT: &str = "{step_name}.{keyid:.8}.link";
 
+/// Filename of a link whose step is still being recorded.
+pub const UNFINISHED_FILENAME_FORMAT: &str = ".{step_name}.{keyid:.8}.link-unfinished";
+
+/// The filename of the link of `step_name` signed by `key_id`,
+/// following `FILENAME_FORMAT`.
+pub fn link_filename(step_name: &str, key_id: &KeyId) -> String {
+    format!("{}.{:.8}.link", step_name, key_id.to_string())
+}
+
 #[derive(Debug, Serialize, Deserialize, Hash, PartialEq, Eq, EnumIter, Clone, Copy)]
 pub enum MetadataType {
     Layout,
@@ -107,7 +117,7 @@ pub trait Metadata {
 /// All signed files (link and layout files) have the format.
 /// * `signatures`: A pubkey => signature map. signatures are for the metadata.
 /// * `metadata`: <ROLE> dictionary. Also known as signed metadata. e.g., link
-/// or layout.
+///   or layout.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Metablock {
     signatures: Vec<Signature>,
@@ -140,6 +150,27 @@ impl Metablock {
         &self.signatures
     }
 
+    /// This metablock with the signature of `key_id` annotated with who
+    /// made it, see `Signature::with_signer_id`. The signed metadata and
+    /// signatures stay valid. Fails if there is no signature of `key_id`.
+    pub fn with_signer_id(mut self, key_id: &KeyId, signer_id: &str) -> Result<Self> {
+        let signature = self
+            .signatures
+            .iter_mut()
+            .find(|sig| sig.key_id() == key_id)
+            .ok_or_else(|| {
+                Error::IllegalArgument(format!("the metadata is not signed by {}", key_id))
+            })?;
+        *signature = signature.clone().with_signer_id(signer_id);
+        Ok(self)
+    }
+
+    /// An immutable reference to the metadata, whose signatures are
+    /// NOT verified. Use `verify` for trusted access.
+    pub fn metadata(&self) -> &MetadataWrapper {
+        &self.metadata
+    }
+
     /// Verify this metadata.
     /// Each signature in the Metablock signed by an authorized key
     /// is a legal signature. Only legal the number signatures is
@@ -211,6 +242,34 @@ impl Metablock {
 
         Ok(self.metadata.clone())
     }
+
+    /// Add a signature by `private_key` to the serialized link or layout
+    /// file `raw`, e.g. so a second functionary can endorse a step after
+    /// reviewing its link.
+    ///
+    /// The signature covers the `signed` object exactly as it appears in
+    /// `raw`, not as this crate would serialize it again, so fields unknown
+    /// to this crate stay covered and existing signatures stay valid. Fails
+    /// if `raw` is not a signed file or `private_key` signed it already.
+    pub fn cosign(raw: &[u8], private_key: &PrivateKey) -> Result<Vec<u8>> {
+        serde_json::from_slice::<Metablock>(raw)?;
+        let mut file: serde_json::Value = serde_json::from_slice(raw)?;
+        let payload = Json::canonicalize(&file["signed"])?;
+        let sig = private_key.sign(&payload)?;
+
+        let signatures = file["signatures"]
+            .as_array_mut()
+            .ok_or_else(|| Error::Encoding("signatures must be an array".into()))?;
+        let key_id = serde_json::to_value(sig.key_id())?;
+        if signatures.iter().any(|s| s["keyid"] == key_id) {
+            return Err(Error::IllegalArgument(format!(
+                "the metadata was signed by {} already",
+                sig.key_id()
+            )));
+        }
+        signatures.push(serde_json::to_value(&sig)?);
+        Ok(serde_json::to_vec_pretty(&file)?)
+    }
 }
 
 /// A helper to build Metablock
@@ -254,14 +313,24 @@ impl MetablockBuilder {
         Ok(self)
     }
 
+    /// Sign the metadata using external signing commands, replacing any existing
+    /// signatures with the same `KeyId`.
+    pub fn sign_external(mut self, signers: &[&ExternalCommandSigner]) -> Result<Self> {
+        let raw = self.metadata.to_bytes()?;
+
+        signers.iter().try_for_each(|signer| -> Result<()> {
+            let sig = signer.sign(&raw)?;
+            self.signatures.insert(sig.key_id().clone(), sig);
+            Ok(())
+        })?;
+
+        Ok(self)
+    }
+
     /// Construct a new `Metablock` using the included signatures, sorting the signatures by
     /// `KeyId`.
     pub fn build(self) -> Metablock {
-        let mut signatures = self
-            .signatures
-            .into_iter()
-            .map(|(_k, v)| v)
-            .collect::<Vec<_>>();
+        let mut signatures = self.signatures.into_values().collect::<Vec<_>>();
         signatures.sort_unstable_by(|a, b| a.key_id().cmp(b.key_id()));
 
         Metablock {
@@ -272,6 +341,7 @@ impl MetablockBuilder {
 }
 
 #[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes, deprecated)]
 mod tests {
     use std::{fs, str::FromStr};
 
@@ -279,7 +349,7 @@ mod tests {
     use serde_json::json;
 
     use crate::{
-        crypto::{PrivateKey, PublicKey},
+        crypto::{ExternalCommandSigner, PrivateKey, PublicKey},
         models::{
             byproducts::ByProducts,
             inspection::Inspection,
@@ -566,4 +636,78 @@ mod tests {
         let authorized_keys = vec![&public_key];
         assert!(metablock.verify(1, authorized_keys).is_ok());
     }
+
+    #[test]
+    fn sign_metablock_with_external_command() {
+        let link_metadata = LinkMetadataBuilder::new()
+            .name("".into())
+            .command(Command::from("tar zcvf foo.tar.gz foo.py"))
+            .build()
+            .unwrap();
+        let public_key =
+            PublicKey::from_spki(BOB_PUB_KEY, crate::crypto::SignatureScheme::RsaSsaPssSha256)
+                .unwrap();
+        let signer = ExternalCommandSigner::new(
+            "sh",
+            &[
+                "-c",
+                "openssl dgst -sha256 -sigopt rsa_padding_mode:pss -sigopt rsa_pss_saltlen:32 \
+                 -keyform DER -sign tests/rsa/rsa-4096.pk8.der | base64",
+            ],
+            public_key.key_id().clone(),
+        );
+        let metablock = MetablockBuilder::from_metadata(Box::new(link_metadata))
+            .sign_external(&[&signer])
+            .unwrap()
+            .build();
+
+        assert_eq!(metablock.signatures().len(), 1);
+        assert!(metablock.verify(1, vec![&public_key]).is_ok());
+    }
+
+    #[test]
+    fn cosign_signed_link_file() {
+        let alice = crate::test_utils::key("alice");
+        let bob = crate::test_utils::key("bob");
+        let link = crate::test_utils::link("build", &[("src.c", b"int main;")], &[]);
+        let signed = crate::test_utils::sign(Box::new(link), &[&alice]);
+        let raw = serde_json::to_vec(&signed).unwrap();
+
+        let cosigned = Metablock::cosign(&raw, &bob).unwrap();
+        let metablock: Metablock = serde_json::from_slice(&cosigned).unwrap();
+        assert_eq!(metablock.metadata(), signed.metadata());
+        assert_eq!(metablock.signatures().len(), 2);
+        assert!(metablock.verify(2, [alice.public(), bob.public()]).is_ok());
+
+        // a second signature of the same key, and files that are not signed metadata
+        assert!(Metablock::cosign(&cosigned, &alice).is_err());
+        assert!(Metablock::cosign(b"{}", &bob).is_err());
+    }
+
+    #[test]
+    fn annotate_signer_of_metablock() {
+        let alice = crate::test_utils::key("alice");
+        let bob = crate::test_utils::key("bob");
+        let link = crate::test_utils::link("build", &[("src.c", b"int main;")], &[]);
+        let signed = crate::test_utils::sign(Box::new(link), &[&alice]);
+
+        let annotated = signed
+            .clone()
+            .with_signer_id(alice.key_id(), "alice@corp / job 1234")
+            .unwrap();
+        let json = serde_json::to_value(&annotated).unwrap();
+        assert_eq!(json["signatures"][0]["signer-id"], "alice@corp / job 1234");
+        let annotated: Metablock = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            annotated.signatures()[0].signer_id(),
+            Some("alice@corp / job 1234")
+        );
+        // the annotation is not signed
+        assert!(annotated.verify(1, [alice.public()]).is_ok());
+        assert_eq!(annotated.metadata(), signed.metadata());
+
+        let json = serde_json::to_value(&signed).unwrap();
+        assert!(json["signatures"][0].get("signer-id").is_none());
+        assert!(signed.with_signer_id(bob.key_id(), "bob").is_err());
+    }
 }
@@ -1,18 +1,35 @@
 //! Models used in in-toto
 
-mod envelope;
+pub(crate) mod envelope;
 mod helpers;
 mod layout;
+mod limits;
 mod link;
+mod lint;
+#[allow(hidden_glob_reexports)]
 mod metadata;
 mod predicate;
+mod spec;
 mod statement;
 
+pub use envelope::{
+    envelope_to_metablock, link_to_statement, metablock_to_envelope, statement_to_link,
+    CosignBundle, CosignEnvelope, CosignSignature, DSSEVersion, EnvelopeFile, PayloadCompression,
+    SIGSTORE_BUNDLE_MEDIA_TYPE,
+};
 pub use helpers::*;
 pub use layout::*;
+pub use limits::{
+    MetadataLimits, DEFAULT_MAX_ARTIFACTS, DEFAULT_MAX_METADATA_SIZE, DEFAULT_MAX_SIGNATURES,
+};
 pub use link::*;
+pub use lint::{lint, lint_value, LintIssue};
 pub use metadata::*;
-pub use predicate::{PredicateLayout, PredicateVer, PredicateWrapper};
+pub use predicate::{
+    BuilderPolicy, PredicateLayout, PredicateVer, PredicateWrapper, GITHUB_HOSTED_BUILDER_ID,
+    GOOGLE_CLOUD_BUILD_BUILDER_ID, SLSA_GITHUB_GENERATOR_BUILDER_PREFIX,
+};
+pub use spec::SpecVersion;
 pub use statement::{StatementVer, StatementWrapper};
 
 #[cfg(test)]
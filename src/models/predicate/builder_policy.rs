@@ -0,0 +1,162 @@
+//! Trusted builders of SLSA provenance.
+//!
+//! SLSA provenance names the builder that produced it by its `builder.id`.
+//! Which IDs to trust depends on the build platform, so the common hosted
+//! platforms are available as presets, next to hand-written constraints.
+
+use super::PredicateWrapper;
+use crate::{Error, Result};
+
+/// Builder ID of GitHub Actions workflows run on GitHub-hosted runners.
+pub const GITHUB_HOSTED_BUILDER_ID: &str = "https://github.com/actions/runner/github-hosted";
+
+/// Prefix of the builder IDs of the reusable workflows of the SLSA GitHub
+/// generator, followed by the workflow and its ref.
+pub const SLSA_GITHUB_GENERATOR_BUILDER_PREFIX: &str =
+    "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/";
+
+/// Builder ID of Google Cloud Build, optionally followed by `@` and a version.
+pub const GOOGLE_CLOUD_BUILD_BUILDER_ID: &str =
+    "https://cloudbuild.googleapis.com/GoogleHostedWorker";
+
+/// Prefix of the builder IDs of GitLab.com runners, followed by the project
+/// and `/-/runners/` and the runner ID.
+const GITLAB_COM_PREFIX: &str = "https://gitlab.com/";
+
+/// The builders whose provenance to accept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuilderPolicy {
+    /// GitHub Actions on GitHub-hosted runners.
+    GitHubHosted,
+    /// A released reusable workflow of the SLSA GitHub generator, i.e. one
+    /// referenced by a tag.
+    SlsaGitHubGenerator,
+    /// A runner of a project on GitLab.com.
+    GitLabHosted,
+    /// Google Cloud Build.
+    GoogleCloudBuild,
+    /// Exactly the given builder ID.
+    Exact(String),
+    /// Any builder ID starting with the given prefix.
+    Prefix(String),
+    /// Any of the given policies.
+    AnyOf(Vec<BuilderPolicy>),
+}
+
+impl BuilderPolicy {
+    /// Whether the builder `builder_id` is trusted.
+    pub fn allows(&self, builder_id: &str) -> bool {
+        match self {
+            BuilderPolicy::GitHubHosted => builder_id == GITHUB_HOSTED_BUILDER_ID,
+            BuilderPolicy::SlsaGitHubGenerator => builder_id
+                .strip_prefix(SLSA_GITHUB_GENERATOR_BUILDER_PREFIX)
+                .and_then(|workflow| workflow.split_once("@refs/tags/"))
+                .is_some_and(|(workflow, tag)| !workflow.is_empty() && !tag.is_empty()),
+            BuilderPolicy::GitLabHosted => builder_id
+                .strip_prefix(GITLAB_COM_PREFIX)
+                .and_then(|rest| rest.split_once("/-/runners/"))
+                .is_some_and(|(project, runner)| {
+                    !project.is_empty() && !runner.is_empty() && !runner.contains('/')
+                }),
+            BuilderPolicy::GoogleCloudBuild => {
+                match builder_id.strip_prefix(GOOGLE_CLOUD_BUILD_BUILDER_ID) {
+                    Some(version) => version.is_empty() || version.starts_with('@'),
+                    None => false,
+                }
+            }
+            BuilderPolicy::Exact(id) => builder_id == id,
+            BuilderPolicy::Prefix(prefix) => builder_id.starts_with(prefix.as_str()),
+            BuilderPolicy::AnyOf(policies) => policies.iter().any(|p| p.allows(builder_id)),
+        }
+    }
+
+    /// Check that `predicate` is SLSA provenance of a trusted builder.
+    pub fn verify(&self, predicate: &PredicateWrapper) -> Result<()> {
+        let builder_id = builder_id(predicate)?;
+        if !self.allows(builder_id) {
+            return Err(Error::VerificationFailure(format!(
+                "provenance of builder {} is not accepted by {:?}",
+                builder_id, self
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The builder ID of the SLSA provenance `predicate`.
+fn builder_id(predicate: &PredicateWrapper) -> Result<&str> {
+    match predicate {
+        PredicateWrapper::SLSAProvenanceV0_1(provenance) => Ok(&provenance.builder().id.0),
+        PredicateWrapper::SLSAProvenanceV0_2(provenance) => Ok(&provenance.builder.id.0),
+        PredicateWrapper::LinkV0_2(_) => Err(Error::VerificationFailure(
+            "a link predicate names no builder".into(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::BuilderPolicy;
+    use crate::models::PredicateWrapper;
+
+    #[test]
+    fn builder_presets() {
+        let cases = [
+            (
+                BuilderPolicy::GitHubHosted,
+                "https://github.com/actions/runner/github-hosted",
+                "https://github.com/actions/runner/self-hosted",
+            ),
+            (
+                BuilderPolicy::SlsaGitHubGenerator,
+                "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/generator_generic_slsa3.yml@refs/tags/v1.9.0",
+                "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/generator_generic_slsa3.yml@refs/heads/main",
+            ),
+            (
+                BuilderPolicy::GitLabHosted,
+                "https://gitlab.com/group/project/-/runners/12345",
+                "https://gitlab.example.com/group/project/-/runners/12345",
+            ),
+            (
+                BuilderPolicy::GoogleCloudBuild,
+                "https://cloudbuild.googleapis.com/GoogleHostedWorker@v0.3",
+                "https://cloudbuild.googleapis.com/GoogleHostedWorkerX",
+            ),
+            (
+                BuilderPolicy::Prefix("https://ci.example.com/".into()),
+                "https://ci.example.com/worker/1",
+                "https://ci.example.org/worker/1",
+            ),
+        ];
+        for (policy, trusted, untrusted) in cases {
+            assert!(policy.allows(trusted), "{:?} rejects {}", policy, trusted);
+            assert!(
+                !policy.allows(untrusted),
+                "{:?} allows {}",
+                policy,
+                untrusted
+            );
+        }
+        assert!(BuilderPolicy::GoogleCloudBuild
+            .allows("https://cloudbuild.googleapis.com/GoogleHostedWorker"));
+        let any = BuilderPolicy::AnyOf(vec![
+            BuilderPolicy::GitHubHosted,
+            BuilderPolicy::Exact("https://ci.example.com".into()),
+        ]);
+        assert!(any.allows("https://ci.example.com"));
+        assert!(!any.allows("https://ci.example.com/other"));
+    }
+
+    #[test]
+    fn verify_provenance() {
+        let provenance: PredicateWrapper = serde_json::from_value(json!({
+            "builder": {"id": "https://github.com/actions/runner/github-hosted"},
+            "buildType": "https://actions.github.io/buildtypes/workflow/v1"
+        }))
+        .unwrap();
+        assert!(BuilderPolicy::GitHubHosted.verify(&provenance).is_ok());
+        assert!(BuilderPolicy::GitLabHosted.verify(&provenance).is_err());
+    }
+}
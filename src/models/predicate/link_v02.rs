@@ -6,7 +6,7 @@ use super::{PredicateLayout, PredicateVer, PredicateWrapper};
 use crate::interchange::{DataInterchange, Json};
 use crate::models::byproducts::ByProducts;
 use crate::models::step::Command;
-use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::models::{LinkMetadata, LinkMetadataBuilder, TargetDescription, VirtualTargetPath};
 use crate::Result;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -23,6 +23,38 @@ pub struct LinkV02 {
     byproducts: ByProducts,
 }
 
+impl LinkV02 {
+    /// The link of the step, with the subject of the statement as its
+    /// `products`
+    pub fn to_link(
+        &self,
+        products: BTreeMap<VirtualTargetPath, TargetDescription>,
+    ) -> Result<LinkMetadata> {
+        LinkMetadataBuilder::new()
+            .name(self.name.clone())
+            .materials(self.materials.clone())
+            .products(products)
+            .env(self.env.clone())
+            .command(self.command.clone())
+            .byproducts(self.byproducts.clone())
+            .build()
+    }
+}
+
+/// The predicate of a link, without its products, which are the subject of
+/// the statement.
+impl From<&LinkMetadata> for LinkV02 {
+    fn from(link: &LinkMetadata) -> Self {
+        LinkV02 {
+            name: link.name().clone(),
+            materials: link.materials().clone(),
+            env: link.env().clone(),
+            command: link.command().clone(),
+            byproducts: link.byproducts().clone(),
+        }
+    }
+}
+
 impl PredicateLayout for LinkV02 {
     fn to_bytes(&self) -> Result<Vec<u8>> {
         Json::canonicalize(&Json::serialize(self)?)
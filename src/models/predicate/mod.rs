@@ -1,10 +1,15 @@
 //! in-toto link
 
+mod builder_policy;
 pub mod link_v02;
 pub mod slsa_provenance_v01;
 pub mod slsa_provenance_v02;
 use std::convert::TryFrom;
 
+pub use builder_policy::{
+    BuilderPolicy, GITHUB_HOSTED_BUILDER_ID, GOOGLE_CLOUD_BUILD_BUILDER_ID,
+    SLSA_GITHUB_GENERATOR_BUILDER_PREFIX,
+};
 pub use link_v02::LinkV02;
 use serde_json::Value;
 pub use slsa_provenance_v01::SLSAProvenanceV01;
@@ -108,7 +113,6 @@ impl PredicateWrapper {
 
     /// Auto judge the `PredicateWrapper` version from `serde:Value`
     pub fn judge_from_value(value: &Value) -> Result<PredicateVer> {
-        println!("{:?}", value);
         for version in PredicateVer::iter() {
             let wrapper = PredicateWrapper::from_value(value.clone(), version);
             if wrapper.is_ok() {
@@ -112,6 +112,13 @@ pub struct SLSAProvenanceV01 {
     materials: Option<Vec<Material>>,
 }
 
+impl SLSAProvenanceV01 {
+    /// The builder that produced the provenance
+    pub fn builder(&self) -> &Builder {
+        &self.builder
+    }
+}
+
 impl PredicateLayout for SLSAProvenanceV01 {
     fn to_bytes(&self) -> Result<Vec<u8>> {
         Json::canonicalize(&Json::serialize(self)?)
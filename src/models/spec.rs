@@ -0,0 +1,99 @@
+//! Versions of the in-toto specification.
+//!
+//! Metadata may carry a `spec_version` field naming the specification it was
+//! written against. Releases of the python implementation older than 1.0 do
+//! not write the field and may omit fields that later became mandatory, so
+//! every version specific difference is listed here:
+//!
+//! * `spec_version` is only serialized when it was set, keeping the
+//!   canonical form, and therefore the signatures, of metadata without it.
+//! * links of spec 0.9 may omit `environment` and `byproducts`.
+//! * layouts of spec 0.9 may omit `readme` and `inspect`.
+//!
+//! Missing fields are filled with their defaults when reading any version.
+
+use serde::de::{Deserialize, Deserializer, Error as DeserializeError};
+use serde::ser::{Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+/// A supported version of the in-toto specification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpecVersion {
+    /// Version 0.9, written by python in-toto before 1.0.
+    V0_9,
+    /// Version 1.0.
+    #[default]
+    V1_0,
+}
+
+impl SpecVersion {
+    /// The string form used in metadata, e.g. `"1.0"`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpecVersion::V0_9 => "0.9",
+            SpecVersion::V1_0 => "1.0",
+        }
+    }
+}
+
+impl Display for SpecVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SpecVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "0.9" => Ok(SpecVersion::V0_9),
+            "1.0" => Ok(SpecVersion::V1_0),
+            _ => Err(Error::IllegalArgument(format!(
+                "unsupported in-toto spec version {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+impl Serialize for SpecVersion {
+    fn serialize<S>(&self, ser: S) -> ::std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ser.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for SpecVersion {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> ::std::result::Result<Self, D::Error> {
+        let s: String = Deserialize::deserialize(de)?;
+        s.parse()
+            .map_err(|e| DeserializeError::custom(format!("{:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::SpecVersion;
+
+    #[test]
+    fn spec_version_serde() {
+        for (version, s) in [(SpecVersion::V0_9, "0.9"), (SpecVersion::V1_0, "1.0")] {
+            assert_eq!(serde_json::to_value(version).unwrap(), json!(s));
+            assert_eq!(
+                serde_json::from_value::<SpecVersion>(json!(s)).unwrap(),
+                version
+            );
+            assert_eq!(version.to_string(), s);
+        }
+        assert!(serde_json::from_value::<SpecVersion>(json!("0.9-dev")).is_err());
+        assert!(serde_json::from_value::<SpecVersion>(json!(1.0)).is_err());
+    }
+}
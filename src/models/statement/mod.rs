@@ -4,6 +4,7 @@ use serde_json::Value;
 pub use state_naive::StateNaive;
 pub use state_v01::StateV01;
 
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
@@ -13,7 +14,7 @@ use serde_derive::Serialize;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
-use super::{LinkMetadata, PredicateLayout};
+use super::{LinkMetadata, PredicateLayout, TargetDescription, VirtualTargetPath};
 use crate::Error;
 use crate::Result;
 
@@ -100,10 +101,19 @@ impl StatementWrapper {
         meta: LinkMetadata,
         predicate: Option<Box<dyn PredicateLayout>>,
         version: StatementVer,
-    ) -> Self {
-        match version {
-            StatementVer::Naive => Self::Naive(StateNaive::merge(meta, predicate).unwrap()),
-            StatementVer::V0_1 => Self::V0_1(StateV01::merge(meta, predicate).unwrap()),
+    ) -> Result<Self> {
+        Ok(match version {
+            StatementVer::Naive => Self::Naive(StateNaive::merge(meta, predicate)?),
+            StatementVer::V0_1 => Self::V0_1(StateV01::merge(meta, predicate)?),
+        })
+    }
+
+    /// The artifacts the statement is about: the subject, or the products
+    /// of a naive statement
+    pub fn subject(&self) -> &BTreeMap<VirtualTargetPath, TargetDescription> {
+        match self {
+            StatementWrapper::Naive(statement) => statement.products(),
+            StatementWrapper::V0_1(statement) => statement.subject(),
         }
     }
 
@@ -26,6 +26,18 @@ pub struct StateNaive {
     byproducts: ByProducts,
 }
 
+impl StateNaive {
+    /// Name of the step
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The products of the step
+    pub fn products(&self) -> &BTreeMap<VirtualTargetPath, TargetDescription> {
+        &self.products
+    }
+}
+
 impl StateLayout for StateNaive {
     fn version(&self) -> StatementVer {
         StatementVer::Naive
@@ -119,7 +131,8 @@ pub mod test {
 
     #[test]
     fn create_statement_from_meta() {
-        let state = StatementWrapper::from_meta(BLANK_META.clone(), None, StatementVer::Naive);
+        let state =
+            StatementWrapper::from_meta(BLANK_META.clone(), None, StatementVer::Naive).unwrap();
         let real = Box::new(STATE_NAIVE.clone()).into_enum();
 
         assert_eq!(state, real);
@@ -28,6 +28,23 @@ pub struct StateV01 {
     predicate: PredicateWrapper,
 }
 
+impl StateV01 {
+    /// The artifacts the statement is about
+    pub fn subject(&self) -> &BTreeMap<VirtualTargetPath, TargetDescription> {
+        &self.subject
+    }
+
+    /// The type of the predicate
+    pub fn predicate_type(&self) -> PredicateVer {
+        self.predicate_type
+    }
+
+    /// The predicate
+    pub fn predicate(&self) -> &PredicateWrapper {
+        &self.predicate
+    }
+}
+
 impl StateLayout for StateV01 {
     fn version(&self) -> StatementVer {
         StatementVer::V0_1
@@ -126,10 +143,13 @@ pub mod test {
             BLANK_META.clone(),
             Some(Box::new(PREDICATE_LINK_V02.clone())),
             StatementVer::V0_1,
-        );
+        )
+        .unwrap();
         let real = Box::new(STATE_V01.clone()).into_enum();
 
         assert_eq!(link, real);
+        // a v0.1 statement cannot be made without a predicate
+        assert!(StatementWrapper::from_meta(BLANK_META.clone(), None, StatementVer::V0_1).is_err());
     }
 
     #[test]
@@ -0,0 +1,344 @@
+//! Resolvers for ITE-4 artifacts that are not plain files.
+//!
+//! An artifact name like `maven:org.example/lib@1.0` or `pkg:pypi/in-toto@1.0.0`
+//! cannot be opened and hashed like a file. A [`Resolver`] knows how to hash
+//! one such resource type, and a [`ResolverRegistry`] maps URI schemes to the
+//! resolvers responsible for them. The registry is consulted when recording
+//! materials and products, so third parties can plug in their own types.
+//!
+//! A URI may also stand for several artifacts, like a git tree for the blobs
+//! in it or an OCI index for its images. An [`ArtifactResolver`] resolves a
+//! URI to all the artifacts it stands for, as [`FileResolver`] walks a
+//! directory, and is registered with `ResolverRegistry::register_artifacts`.
+//!
+//! Resolvers of common schemes are provided, like [`GitResolver`] and
+//! [`HttpResolver`] for downloads, and `OciResolver` for container images
+//! with the `oci` feature.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug};
+
+use crate::crypto::HashAlgorithm;
+use crate::models::{TargetDescription, VirtualTargetPath, FILE_SCHEME};
+use crate::runlib::{record_artifacts_with_options, RecordOptions};
+use crate::{Error, Result};
+
+mod git;
+mod http;
+#[cfg(feature = "oci")]
+mod oci;
+
+pub use git::{GitResolver, GIT_SCHEME};
+pub use http::{HttpResolver, DEFAULT_MAX_DOWNLOAD_SIZE};
+#[cfg(feature = "oci")]
+pub use oci::{OciResolver, OCI_SCHEME};
+
+/// Computes the digests of the resources behind an ITE-4 URI scheme.
+///
+/// Any `Fn(&VirtualTargetPath, &[HashAlgorithm]) -> Result<TargetDescription>`
+/// closure is a `Resolver`.
+pub trait Resolver: Send + Sync {
+    /// Hash the resource `path` with each of `hash_algorithms`.
+    fn hash(
+        &self,
+        path: &VirtualTargetPath,
+        hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription>;
+}
+
+impl<F> Resolver for F
+where
+    F: Fn(&VirtualTargetPath, &[HashAlgorithm]) -> Result<TargetDescription> + Send + Sync,
+{
+    fn hash(
+        &self,
+        path: &VirtualTargetPath,
+        hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription> {
+        self(path, hash_algorithms)
+    }
+}
+
+/// Resolves an ITE-4 URI to the artifacts it stands for.
+///
+/// Every `Resolver` is an `ArtifactResolver` resolving a URI to itself.
+pub trait ArtifactResolver: Send + Sync {
+    /// The artifacts `path` stands for, recorded as `options` say, e.g. with
+    /// their hash algorithms.
+    fn resolve(
+        &self,
+        path: &VirtualTargetPath,
+        options: &RecordOptions,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>>;
+}
+
+impl<R: Resolver> ArtifactResolver for R {
+    fn resolve(
+        &self,
+        path: &VirtualTargetPath,
+        options: &RecordOptions,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let hashes = self.hash(path, options.algorithms())?;
+        Ok(BTreeMap::from([(path.clone(), hashes)]))
+    }
+}
+
+/// The `ArtifactResolver` of files, recording the files below a path by
+/// walking the file system as `record_artifacts_with_options` does, e.g. for
+/// resolvers checking out sources to record them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileResolver;
+
+impl ArtifactResolver for FileResolver {
+    fn resolve(
+        &self,
+        path: &VirtualTargetPath,
+        options: &RecordOptions,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        // the path is a file even if a resolver is registered for what looks
+        // like its scheme, so resolving it cannot come back here
+        let options = RecordOptions {
+            resolvers: None,
+            ..options.clone()
+        };
+        record_artifacts_with_options(&[path.value()], &options)
+    }
+}
+
+/// Mapping from URI schemes to the `Resolver` or `ArtifactResolver`
+/// handling them.
+///
+/// Files are always recorded by walking the filesystem, so the `file` scheme
+/// cannot be registered.
+#[derive(Default)]
+pub struct ResolverRegistry {
+    resolvers: HashMap<String, Box<dyn Resolver>>,
+    artifact_resolvers: HashMap<String, Box<dyn ArtifactResolver>>,
+}
+
+impl ResolverRegistry {
+    /// Create an empty `ResolverRegistry`.
+    pub fn new() -> Self {
+        ResolverRegistry::default()
+    }
+
+    /// Register `resolver` for `scheme`, replacing any previous resolver.
+    ///
+    /// ```
+    /// # use in_toto::models::TargetDescription;
+    /// # use in_toto::resolver::ResolverRegistry;
+    /// let mut registry = ResolverRegistry::new();
+    /// registry
+    ///     .register("maven", |_: &_, _: &_| Ok(TargetDescription::new()))
+    ///     .unwrap();
+    /// assert!(registry.get("maven").is_some());
+    /// ```
+    pub fn register<R>(&mut self, scheme: &str, resolver: R) -> Result<()>
+    where
+        R: Resolver + 'static,
+    {
+        check_scheme(scheme)?;
+        self.artifact_resolvers.remove(scheme);
+        self.resolvers.insert(scheme.into(), Box::new(resolver));
+        Ok(())
+    }
+
+    /// Register `resolver` for `scheme`, replacing any previous resolver, so
+    /// a URI of `scheme` is recorded as the artifacts it resolves to.
+    pub fn register_artifacts<R>(&mut self, scheme: &str, resolver: R) -> Result<()>
+    where
+        R: ArtifactResolver + 'static,
+    {
+        check_scheme(scheme)?;
+        self.resolvers.remove(scheme);
+        self.artifact_resolvers
+            .insert(scheme.into(), Box::new(resolver));
+        Ok(())
+    }
+
+    /// The resolver registered for `scheme` with `register`, if any.
+    pub fn get(&self, scheme: &str) -> Option<&dyn Resolver> {
+        self.resolvers.get(scheme).map(|r| r.as_ref())
+    }
+
+    /// Whether a resolver is registered for `scheme`.
+    pub fn contains(&self, scheme: &str) -> bool {
+        self.resolvers.contains_key(scheme) || self.artifact_resolvers.contains_key(scheme)
+    }
+
+    /// The artifacts `path` stands for, resolved by the resolver registered
+    /// for its scheme, or by `FileResolver` for files. Fails if an artifact
+    /// has no digests.
+    pub fn resolve(
+        &self,
+        path: &VirtualTargetPath,
+        options: &RecordOptions,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let scheme = path.scheme().unwrap_or(FILE_SCHEME);
+        if scheme == FILE_SCHEME {
+            return FileResolver.resolve(path, options);
+        }
+        let resolver = match self.artifact_resolvers.get(scheme) {
+            Some(resolver) => resolver,
+            None => {
+                let hashes = self.hash(path, options.algorithms())?;
+                return Ok(BTreeMap::from([(path.clone(), hashes)]));
+            }
+        };
+        let artifacts = resolver.resolve(path, options)?;
+        if let Some((artifact, _)) = artifacts.iter().find(|(_, hashes)| hashes.is_empty()) {
+            return Err(Error::IllegalArgument(format!(
+                "resolver for scheme {:?} returned no digests for {}",
+                scheme, artifact
+            )));
+        }
+        Ok(artifacts)
+    }
+
+    /// Hash `path` with the resolver registered for its scheme.
+    pub fn hash(
+        &self,
+        path: &VirtualTargetPath,
+        hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription> {
+        let scheme = path.scheme().unwrap_or(FILE_SCHEME);
+        let resolver = self.get(scheme).ok_or_else(|| {
+            Error::IllegalArgument(format!(
+                "cannot record artifact {}: no resolver for scheme {:?}",
+                path, scheme
+            ))
+        })?;
+        let hashes = resolver.hash(path, hash_algorithms)?;
+        if hashes.is_empty() {
+            return Err(Error::IllegalArgument(format!(
+                "resolver for scheme {:?} returned no digests for {}",
+                scheme, path
+            )));
+        }
+        Ok(hashes)
+    }
+}
+
+impl Debug for ResolverRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut schemes: Vec<_> = self
+            .resolvers
+            .keys()
+            .chain(self.artifact_resolvers.keys())
+            .collect();
+        schemes.sort();
+        f.debug_struct("ResolverRegistry")
+            .field("schemes", &schemes)
+            .finish()
+    }
+}
+
+/// A registry is only equal to itself, as resolvers cannot be compared.
+impl PartialEq for ResolverRegistry {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for ResolverRegistry {}
+
+/// Fails unless `scheme` is a valid scheme to register a resolver for.
+fn check_scheme(scheme: &str) -> Result<()> {
+    // validate the scheme the same way paths are parsed
+    let probe = VirtualTargetPath::from_uri(scheme, "")?;
+    if probe.scheme() != Some(scheme) {
+        return Err(Error::IllegalArgument(format!(
+            "invalid artifact scheme {:?}",
+            scheme
+        )));
+    }
+    if scheme == FILE_SCHEME {
+        return Err(Error::IllegalArgument(
+            "resolver for file scheme cannot be replaced".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::{calculate_hashes, HashAlgorithm};
+
+    fn hash_resource(
+        path: &VirtualTargetPath,
+        hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription> {
+        let (_, hashes) = calculate_hashes(path.resource().as_bytes(), hash_algorithms)?;
+        Ok(hashes)
+    }
+
+    #[test]
+    fn register_and_hash() {
+        let mut registry = ResolverRegistry::new();
+        registry.register("maven", hash_resource).unwrap();
+        assert!(registry.contains("maven"));
+
+        let path = VirtualTargetPath::new("maven:org.example/lib@1.0".into()).unwrap();
+        let hashes = registry.hash(&path, &[HashAlgorithm::Sha256]).unwrap();
+        assert!(hashes.contains_key(&HashAlgorithm::Sha256));
+
+        let path = VirtualTargetPath::new("nuget:Example.Lib/1.0".into()).unwrap();
+        assert!(registry.hash(&path, &[HashAlgorithm::Sha256]).is_err());
+    }
+
+    #[test]
+    fn register_rejects_bad_schemes() {
+        let mut registry = ResolverRegistry::new();
+        assert!(registry.register("file", hash_resource).is_err());
+        assert!(registry.register("c", hash_resource).is_err());
+        assert!(registry.register("no/slash", hash_resource).is_err());
+        assert!(registry.register("", hash_resource).is_err());
+    }
+
+    #[test]
+    fn empty_digests_are_rejected() {
+        let mut registry = ResolverRegistry::new();
+        registry
+            .register("pkg", |_: &_, _: &_| Ok(TargetDescription::new()))
+            .unwrap();
+        let path = VirtualTargetPath::new("pkg:pypi/in-toto@1.0.0".into()).unwrap();
+        assert!(registry.hash(&path, &[HashAlgorithm::Sha256]).is_err());
+    }
+
+    /// Resolves a URI to itself and the files below a directory.
+    struct Tree(String);
+
+    impl ArtifactResolver for Tree {
+        fn resolve(
+            &self,
+            path: &VirtualTargetPath,
+            options: &RecordOptions,
+        ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+            let root = VirtualTargetPath::new(self.0.clone())?;
+            let mut artifacts = FileResolver.resolve(&root, options)?;
+            artifacts.insert(path.clone(), hash_resource(path, options.algorithms())?);
+            Ok(artifacts)
+        }
+    }
+
+    #[test]
+    fn resolve_artifacts() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), "a").unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        let mut registry = ResolverRegistry::new();
+        registry.register_artifacts("tree", Tree(root)).unwrap();
+        assert!(registry.contains("tree"));
+        assert!(registry.get("tree").is_none());
+
+        let path = VirtualTargetPath::new("tree:HEAD".into()).unwrap();
+        let artifacts = registry.resolve(&path, &RecordOptions::new()).unwrap();
+        assert_eq!(artifacts.len(), 2);
+        assert!(artifacts.contains_key(&path));
+
+        registry.register("tree", hash_resource).unwrap();
+        let artifacts = registry.resolve(&path, &RecordOptions::new()).unwrap();
+        assert_eq!(artifacts.len(), 1);
+    }
+}
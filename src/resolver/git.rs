@@ -0,0 +1,180 @@
+//! Recording git objects as artifacts.
+//!
+//! A step building "the source at commit X" does not need every file of the
+//! checkout as a material: the ID of the commit already fixes all of them.
+//! `GitResolver` records `git:` URIs naming a revision of a repository, like
+//! `git:v1.2.0`, `git:HEAD` or `git:HEAD^{tree}`, with the ID git gives the
+//! object as its digest, keyed by the type of the object as in-toto resource
+//! descriptors do:
+//!
+//! ```json
+//! "git:v1.2.0": {"gitTag": "5b1c...", "gitCommit": "e83c..."}
+//! ```
+//!
+//! Annotated tags are recorded with the commit they point to as well.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use data_encoding::HEXLOWER_PERMISSIVE;
+
+use super::Resolver;
+use crate::crypto::{HashAlgorithm, HashValue};
+use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::{Error, Result};
+
+/// The scheme of git artifacts.
+pub const GIT_SCHEME: &str = "git";
+
+/// Records `git:` URIs naming revisions of the repository at a path, by
+/// running `git`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitResolver {
+    repository: PathBuf,
+}
+
+impl GitResolver {
+    /// Resolve revisions in the repository `repository`, or the one it is in
+    pub fn new<P: AsRef<Path>>(repository: P) -> Self {
+        GitResolver {
+            repository: repository.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The repository revisions are resolved in
+    pub fn repository(&self) -> &Path {
+        &self.repository
+    }
+
+    /// The output of `git args` in the repository, trimmed.
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.repository)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::LinkGatheringError(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// The ID of the object `revision` names, which has to exist.
+    fn object_id(&self, revision: &str) -> Result<HashValue> {
+        let id = self.git(&["rev-parse", "--verify", "--end-of-options", revision])?;
+        let id = HEXLOWER_PERMISSIVE
+            .decode(id.as_bytes())
+            .map_err(|e| Error::Encoding(format!("git object ID {:?}: {}", id, e)))?;
+        Ok(HashValue::new(id))
+    }
+}
+
+impl Resolver for GitResolver {
+    /// The digests of the object `path` names. Git IDs do not depend on the
+    /// hash algorithms asked for, so those are ignored.
+    fn hash(
+        &self,
+        path: &VirtualTargetPath,
+        _hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription> {
+        let revision = path.resource();
+        if path.scheme() != Some(GIT_SCHEME) || revision.is_empty() {
+            return Err(Error::IllegalArgument(format!(
+                "{} is no git revision",
+                path
+            )));
+        }
+        let id = self.object_id(revision)?;
+        let kind = self.git(&["cat-file", "-t", &HEXLOWER_PERMISSIVE.encode(id.value())])?;
+        let key = match kind.as_str() {
+            "commit" => "gitCommit",
+            "tree" => "gitTree",
+            "blob" => "gitBlob",
+            "tag" => "gitTag",
+            _ => {
+                return Err(Error::LinkGatheringError(format!(
+                    "{} is a git {}",
+                    path, kind
+                )))
+            }
+        };
+        let mut hashes = TargetDescription::new();
+        hashes.insert(HashAlgorithm::Unknown(key.into()), id);
+        if kind == "tag" {
+            if let Ok(commit) = self.object_id(&format!("{}^{{commit}}", revision)) {
+                hashes.insert(HashAlgorithm::Unknown("gitCommit".into()), commit);
+            }
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{GitResolver, GIT_SCHEME};
+    use crate::crypto::HashAlgorithm;
+    use crate::models::VirtualTargetPath;
+    use crate::resolver::ResolverRegistry;
+    use crate::runlib::RecordOptions;
+
+    #[test]
+    fn resolve_git_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args([
+                    "-c",
+                    "user.name=in-toto",
+                    "-c",
+                    "user.email=in-toto@example.com",
+                ])
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            String::from_utf8(output.stdout).unwrap().trim().to_string()
+        };
+        git(&["init", "-q"]);
+        std::fs::write(dir.path().join("README"), "hello").unwrap();
+        git(&["add", "README"]);
+        git(&["commit", "-q", "-m", "first"]);
+        git(&["tag", "-a", "-m", "release", "v1.0"]);
+        let commit = git(&["rev-parse", "HEAD"]);
+
+        let mut resolvers = ResolverRegistry::new();
+        resolvers
+            .register(GIT_SCHEME, GitResolver::new(dir.path()))
+            .unwrap();
+        let resolve = |uri: &str| {
+            let path = VirtualTargetPath::new(uri.into()).unwrap();
+            resolvers
+                .resolve(&path, &RecordOptions::new())
+                .map(|artifacts| artifacts[&path].clone())
+        };
+        let key = |key: &str| HashAlgorithm::Unknown(key.into());
+        let hex = |hashes: &crate::models::TargetDescription, k: &str| {
+            data_encoding::HEXLOWER.encode(hashes[&key(k)].value())
+        };
+
+        let head = resolve("git:HEAD").unwrap();
+        assert_eq!(hex(&head, "gitCommit"), commit);
+        let tag = resolve("git:v1.0").unwrap();
+        assert_eq!(hex(&tag, "gitCommit"), commit);
+        assert_eq!(hex(&tag, "gitTag"), git(&["rev-parse", "v1.0"]));
+        let tree = resolve("git:HEAD^{tree}").unwrap();
+        assert_eq!(hex(&tree, "gitTree"), git(&["rev-parse", "HEAD^{tree}"]));
+        assert!(resolve("git:HEAD:README")
+            .unwrap()
+            .contains_key(&key("gitBlob")));
+        assert!(resolve("git:no-such-branch").is_err());
+        assert!(resolve("git:").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Recording downloads as artifacts.
+//!
+//! Steps building from upstream release tarballs consume them by URL. Rather
+//! than downloading them to a file only to record it, `HttpResolver` records
+//! `https:` URIs by the digests of what they serve:
+//!
+//! ```json
+//! "https://example.com/app-1.0.tar.gz": {"sha256": "5b1c..."}
+//! ```
+//!
+//! Requests are sent with a `HttpTransport`, which also follows redirects
+//! if it should, as this crate does not speak TLS. The body is hashed as it
+//! is read from `HttpTransport::send_streaming`. Responses larger than the
+//! size limit are rejected: by their `Content-Length` before reading them,
+//! and once reading crosses the limit for responses without one.
+
+use std::io::Read;
+
+use http::header::CONTENT_LENGTH;
+use http::{Request, StatusCode};
+
+use super::Resolver;
+use crate::crypto::{self, HashAlgorithm};
+use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::store::HttpTransport;
+use crate::{Error, Result};
+
+/// The size limit of downloads by default, 1 GiB.
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Records `https:` and `http:` URIs by the digests and length of what they
+/// serve.
+///
+/// ```
+/// # use in_toto::resolver::{HttpResolver, ResolverRegistry};
+/// # use in_toto::store::HttpTransport;
+/// # struct Client;
+/// # impl HttpTransport for Client {
+/// #     fn send(&self, _: http::Request<Vec<u8>>) -> in_toto::Result<http::Response<Vec<u8>>> {
+/// #         unimplemented!()
+/// #     }
+/// # }
+/// let mut resolvers = ResolverRegistry::new();
+/// resolvers
+///     .register("https", HttpResolver::new(Client).max_size(64 * 1024 * 1024))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpResolver<T> {
+    transport: T,
+    max_size: u64,
+}
+
+impl<T: HttpTransport> HttpResolver<T> {
+    /// Download URIs with `transport`, up to `DEFAULT_MAX_DOWNLOAD_SIZE`
+    pub fn new(transport: T) -> Self {
+        HttpResolver {
+            transport,
+            max_size: DEFAULT_MAX_DOWNLOAD_SIZE,
+        }
+    }
+
+    /// Reject downloads larger than `max_size` bytes
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// The body of the response to `GET url`, ending after the size limit.
+    fn download(&self, url: &str) -> Result<impl Read> {
+        let request = Request::get(url)
+            .body(Vec::new())
+            .map_err(|e| Error::IllegalArgument(format!("invalid URL {}: {}", url, e)))?;
+        let response = self.transport.send_streaming(request)?;
+        if response.status() != StatusCode::OK {
+            return Err(Error::LinkGatheringError(format!(
+                "downloading {}: status {}",
+                url,
+                response.status()
+            )));
+        }
+        let length = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+        if let Some(length) = length {
+            self.check_size(url, length)?;
+        }
+        // one byte more tells a body of the limit from a larger one
+        Ok(response.into_body().take(self.max_size.saturating_add(1)))
+    }
+
+    fn check_size(&self, url: &str, size: u64) -> Result<()> {
+        if size > self.max_size {
+            return Err(Error::LinkGatheringError(format!(
+                "{} is {} bytes, more than the limit of {}",
+                url, size, self.max_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<T: HttpTransport + Send + Sync> Resolver for HttpResolver<T> {
+    fn hash(
+        &self,
+        path: &VirtualTargetPath,
+        hash_algorithms: &[HashAlgorithm],
+    ) -> Result<TargetDescription> {
+        if !matches!(path.scheme(), Some("https" | "http")) {
+            return Err(Error::IllegalArgument(format!("{} is no HTTP URL", path)));
+        }
+        let body = self.download(path.value())?;
+        let (length, hashes) = crypto::calculate_hashes(body, hash_algorithms)?;
+        if length > self.max_size {
+            return Err(Error::LinkGatheringError(format!(
+                "{} is more than the limit of {} bytes",
+                path, self.max_size
+            )));
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use http::header::CONTENT_LENGTH;
+    use http::{Request, Response, StatusCode};
+
+    use super::HttpResolver;
+    use crate::crypto::HashAlgorithm;
+    use crate::models::VirtualTargetPath;
+    use crate::resolver::Resolver;
+    use crate::store::HttpTransport;
+    use crate::Result;
+
+    struct Server;
+
+    impl HttpTransport for Server {
+        fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+            let response = Response::builder();
+            Ok(match request.uri().path() {
+                "/app.tar.gz" => response.body(b"tarball".to_vec()),
+                "/lying" => response
+                    .header(CONTENT_LENGTH, "1000000")
+                    .body(b"short".to_vec()),
+                _ => response.status(StatusCode::NOT_FOUND).body(Vec::new()),
+            }
+            .unwrap())
+        }
+    }
+
+    /// Serves an endless body without a `Content-Length`.
+    struct Endless;
+
+    impl HttpTransport for Endless {
+        fn send(&self, _: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+            unreachable!("the body does not fit into memory")
+        }
+
+        fn send_streaming(&self, _: Request<Vec<u8>>) -> Result<Response<Box<dyn Read>>> {
+            Ok(Response::new(Box::new(std::io::repeat(0)) as Box<dyn Read>))
+        }
+    }
+
+    #[test]
+    fn resolve_downloads() {
+        let resolver = HttpResolver::new(Server).max_size(16);
+        let hash = |uri: &str| {
+            resolver.hash(
+                &VirtualTargetPath::new(uri.into()).unwrap(),
+                &[HashAlgorithm::Sha256],
+            )
+        };
+        let hashes = hash("https://example.com/app.tar.gz").unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(hashes[&HashAlgorithm::Sha256].value()),
+            "db4b4d0d1cb480bf9aeea253771c00febe627f236765fa37d6a5614f079a3aa0"
+        );
+        assert!(hash("https://example.com/missing").is_err());
+        assert!(hash("https://example.com/lying").is_err());
+        assert!(HttpResolver::new(Server)
+            .max_size(4)
+            .hash(
+                &VirtualTargetPath::new("https://example.com/app.tar.gz".into()).unwrap(),
+                &[HashAlgorithm::Sha256]
+            )
+            .is_err());
+        assert!(hash("ftp://example.com/app.tar.gz").is_err());
+
+        let error = HttpResolver::new(Endless)
+            .max_size(1024 * 1024)
+            .hash(
+                &VirtualTargetPath::new("https://example.com/endless".into()).unwrap(),
+                &[HashAlgorithm::Sha256],
+            )
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("more than the limit"),
+            "{}",
+            error
+        );
+    }
+}
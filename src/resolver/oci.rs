@@ -0,0 +1,549 @@
+//! Recording OCI images as artifacts, see the `oci` feature.
+//!
+//! A container build step produces an image, not files: exporting it to a
+//! tarball only to hash it is slow and the tarball is not what is deployed.
+//! `OciResolver` records `oci:` URIs naming an image, like `oci:app:1.0` or
+//! `oci:app@sha256:5b1c...`, by the digest of its manifest, and each blob the
+//! manifest refers to as an artifact of its own:
+//!
+//! ```json
+//! "oci:app:1.0": {"sha256": "5b1c..."},
+//! "oci:app:1.0#config": {"sha256": "e83c..."},
+//! "oci:app:1.0#layer/0": {"sha256": "9f2a..."}
+//! ```
+//!
+//! For an image index, e.g. of a multi-platform image, the manifests it lists
+//! are recorded as `#manifest/<n>`, with their blobs below them, like
+//! `oci:app:1.0#manifest/0/layer/0`. Manifests are fetched and checked
+//! against their digests, layers are not fetched at all: their digests are
+//! taken from the manifests.
+//!
+//! Images are read from an [OCI image layout] directory, as written by
+//! buildah, skopeo or `docker buildx --output type=oci`, or from a registry
+//! with a `HttpTransport`.
+//!
+//! [OCI image layout]: https://github.com/opencontainers/image-spec/blob/main/image-layout.md
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use data_encoding::HEXLOWER_PERMISSIVE;
+use http::header::{ACCEPT, AUTHORIZATION};
+use http::{Request, StatusCode};
+use ring::digest::{digest, SHA256, SHA512};
+use serde_derive::Deserialize;
+
+use super::ArtifactResolver;
+use crate::crypto::{HashAlgorithm, HashValue};
+use crate::models::{TargetDescription, VirtualTargetPath};
+use crate::runlib::RecordOptions;
+use crate::store::HttpTransport;
+use crate::{Error, Result};
+
+/// The scheme of OCI image artifacts.
+pub const OCI_SCHEME: &str = "oci";
+
+/// The annotation naming the manifests of an image layout.
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// The manifest media types asked registries for.
+const MANIFEST_MEDIA_TYPES: &[&str] = &[
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// How deep image indexes may be nested.
+const MAX_INDEX_DEPTH: usize = 4;
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    #[serde(default)]
+    annotations: BTreeMap<String, String>,
+}
+
+/// An image manifest or an image index, told apart by their fields.
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    #[serde(default)]
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+enum Source {
+    Layout(PathBuf),
+    Registry {
+        transport: Box<dyn HttpTransport + Send + Sync>,
+        url: String,
+        token: Option<String>,
+    },
+}
+
+/// Records `oci:` URIs naming images, by the digests of their manifests and
+/// blobs.
+pub struct OciResolver {
+    source: Source,
+}
+
+impl OciResolver {
+    /// Resolve images in the OCI image layout at `dir`. References are the
+    /// `org.opencontainers.image.ref.name` annotations of its `index.json`,
+    /// or a digest like `app@sha256:5b1c...`.
+    pub fn layout<P: AsRef<Path>>(dir: P) -> Self {
+        OciResolver {
+            source: Source::Layout(dir.as_ref().to_path_buf()),
+        }
+    }
+
+    /// Resolve images in the registry at `url`, like
+    /// `https://registry.example.com`, fetching manifests with `transport`.
+    /// References are repositories with a tag or a digest, like
+    /// `team/app:1.0`, with `latest` for neither.
+    pub fn registry<T>(transport: T, url: &str) -> Self
+    where
+        T: HttpTransport + Send + Sync + 'static,
+    {
+        OciResolver {
+            source: Source::Registry {
+                transport: Box::new(transport),
+                url: url.trim_end_matches('/').to_string(),
+                token: None,
+            },
+        }
+    }
+
+    /// Authenticate to the registry with the bearer token `token`. Layouts
+    /// need no token, it is ignored for them.
+    pub fn token(mut self, token: &str) -> Self {
+        if let Source::Registry { token: t, .. } = &mut self.source {
+            *t = Some(token.to_string());
+        }
+        self
+    }
+
+    /// The digest and bytes of the manifest `reference` names.
+    fn root(&self, reference: &str) -> Result<(String, Vec<u8>)> {
+        let (name, tag, digest) = split_reference(reference);
+        let digest = match (&self.source, digest) {
+            (_, Some(digest)) => digest.to_string(),
+            (Source::Layout(dir), None) => layout_ref(dir, reference, tag)?,
+            (Source::Registry { .. }, None) => {
+                let bytes = self.manifest(name, tag.unwrap_or("latest"))?;
+                let digest = format!("sha256:{}", HEXLOWER_PERMISSIVE.encode(&sha256(&bytes)));
+                return Ok((digest, bytes));
+            }
+        };
+        let bytes = self.manifest(name, &digest)?;
+        check_digest(&digest, &bytes)?;
+        Ok((digest, bytes))
+    }
+
+    /// The bytes of the manifest of the repository `name` that `reference`,
+    /// a tag or a digest, names. Layouts have a single repository and only
+    /// know digests.
+    fn manifest(&self, name: &str, reference: &str) -> Result<Vec<u8>> {
+        match &self.source {
+            Source::Layout(dir) => {
+                let (algorithm, hex) = split_digest(reference)?;
+                let path = dir.join("blobs").join(algorithm).join(hex);
+                fs::read(&path).map_err(|e| {
+                    Error::LinkGatheringError(format!(
+                        "cannot read manifest {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })
+            }
+            Source::Registry {
+                transport,
+                url,
+                token,
+            } => {
+                let mut request =
+                    Request::get(format!("{}/v2/{}/manifests/{}", url, name, reference))
+                        .header(ACCEPT, MANIFEST_MEDIA_TYPES.join(", "));
+                if let Some(token) = token {
+                    request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+                }
+                let request = request
+                    .body(Vec::new())
+                    .map_err(|e| Error::IllegalArgument(e.to_string()))?;
+                let response = transport.send(request)?;
+                if response.status() != StatusCode::OK {
+                    return Err(Error::LinkGatheringError(format!(
+                        "fetching manifest {} of {} from {}: status {}",
+                        reference,
+                        name,
+                        url,
+                        response.status()
+                    )));
+                }
+                Ok(response.into_body())
+            }
+        }
+    }
+
+    /// Record the manifest `bytes` with the digest `digest` as `uri` with
+    /// the fragment `fragment`, and the blobs it refers to below it.
+    fn record(
+        &self,
+        name: &str,
+        uri: &str,
+        fragment: &str,
+        digest: &str,
+        bytes: &[u8],
+        depth: usize,
+        artifacts: &mut BTreeMap<VirtualTargetPath, TargetDescription>,
+    ) -> Result<()> {
+        let manifest: Manifest = serde_json::from_slice(bytes)
+            .map_err(|e| Error::Encoding(format!("manifest {} of {}: {}", digest, uri, e)))?;
+        let child = |part: String| match fragment.is_empty() {
+            true => part,
+            false => format!("{}/{}", fragment, part),
+        };
+        artifacts.insert(artifact(uri, fragment)?, hashes(digest)?);
+
+        for (i, descriptor) in manifest.manifests.iter().enumerate() {
+            if depth >= MAX_INDEX_DEPTH {
+                return Err(Error::LinkGatheringError(format!(
+                    "image indexes of {} are nested deeper than {}",
+                    uri, MAX_INDEX_DEPTH
+                )));
+            }
+            let bytes = self.manifest(name, &descriptor.digest)?;
+            check_digest(&descriptor.digest, &bytes)?;
+            self.record(
+                name,
+                uri,
+                &child(format!("manifest/{}", i)),
+                &descriptor.digest,
+                &bytes,
+                depth + 1,
+                artifacts,
+            )?;
+        }
+        if let Some(config) = &manifest.config {
+            artifacts.insert(
+                artifact(uri, &child("config".into()))?,
+                hashes(&config.digest)?,
+            );
+        }
+        for (i, layer) in manifest.layers.iter().enumerate() {
+            artifacts.insert(
+                artifact(uri, &child(format!("layer/{}", i)))?,
+                hashes(&layer.digest)?,
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ArtifactResolver for OciResolver {
+    /// The manifest and blobs of the image `path` names. OCI digests do not
+    /// depend on the hash algorithms asked for, so those are ignored.
+    fn resolve(
+        &self,
+        path: &VirtualTargetPath,
+        _options: &RecordOptions,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let reference = path.resource();
+        if path.scheme() != Some(OCI_SCHEME) || reference.is_empty() || reference.contains('#') {
+            return Err(Error::IllegalArgument(format!(
+                "{} is no OCI image reference",
+                path
+            )));
+        }
+        let (digest, bytes) = self.root(reference)?;
+        let (name, _, _) = split_reference(reference);
+        let mut artifacts = BTreeMap::new();
+        self.record(name, path.value(), "", &digest, &bytes, 0, &mut artifacts)?;
+        Ok(artifacts)
+    }
+}
+
+impl Debug for OciResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("OciResolver");
+        match &self.source {
+            Source::Layout(dir) => debug.field("layout", dir),
+            Source::Registry { url, .. } => debug.field("registry", url),
+        };
+        debug.finish()
+    }
+}
+
+/// The repository, tag and digest of `reference`, `name[:tag][@digest]`.
+fn split_reference(reference: &str) -> (&str, Option<&str>, Option<&str>) {
+    let (name, digest) = match reference.split_once('@') {
+        Some((name, digest)) => (name, Some(digest)),
+        None => (reference, None),
+    };
+    // a `:` before the last `/` separates a registry port, not a tag
+    let tag_start = name.rfind('/').map_or(0, |i| i + 1);
+    match name[tag_start..].rfind(':') {
+        Some(i) => (
+            &name[..tag_start + i],
+            Some(&name[tag_start + i + 1..]),
+            digest,
+        ),
+        None => (name, None, digest),
+    }
+}
+
+/// The digest of the manifest of the layout `dir` named `reference`, or by
+/// its tag `tag`.
+fn layout_ref(dir: &Path, reference: &str, tag: Option<&str>) -> Result<String> {
+    let path = dir.join("index.json");
+    let index = fs::read(&path)
+        .map_err(|e| Error::LinkGatheringError(format!("cannot read {}: {}", path.display(), e)))?;
+    let index: Manifest = serde_json::from_slice(&index)
+        .map_err(|e| Error::Encoding(format!("{}: {}", path.display(), e)))?;
+    let named = |name: &str| {
+        index
+            .manifests
+            .iter()
+            .find(|m| m.annotations.get(REF_NAME_ANNOTATION).map(String::as_str) == Some(name))
+    };
+    named(reference)
+        .or_else(|| tag.and_then(named))
+        .map(|m| m.digest.clone())
+        .ok_or_else(|| {
+            Error::LinkGatheringError(format!(
+                "no image {} in the layout {}",
+                reference,
+                dir.display()
+            ))
+        })
+}
+
+/// The algorithm and hex encoded value of `digest`, like `sha256:5b1c...`.
+fn split_digest(digest: &str) -> Result<(&str, &str)> {
+    match digest.split_once(':') {
+        Some((algorithm, hex))
+            if !algorithm.is_empty()
+                && algorithm
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '_' | '-'))
+                && !hex.is_empty()
+                && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            Ok((algorithm, hex))
+        }
+        _ => Err(Error::Encoding(format!("invalid OCI digest {:?}", digest))),
+    }
+}
+
+/// The digest `digest` as the digests of an artifact.
+fn hashes(digest: &str) -> Result<TargetDescription> {
+    let (algorithm, hex) = split_digest(digest)?;
+    let value = HEXLOWER_PERMISSIVE
+        .decode(hex.as_bytes())
+        .map_err(|e| Error::Encoding(format!("invalid OCI digest {:?}: {}", digest, e)))?;
+    let algorithm = match algorithm {
+        "sha256" => HashAlgorithm::Sha256,
+        "sha512" => HashAlgorithm::Sha512,
+        other => HashAlgorithm::Unknown(other.into()),
+    };
+    let mut hashes = TargetDescription::new();
+    hashes.insert(algorithm, HashValue::new(value));
+    Ok(hashes)
+}
+
+/// Fails unless `bytes` have the digest `digest`.
+fn check_digest(digest: &str, bytes: &[u8]) -> Result<()> {
+    let (algorithm, hex) = split_digest(digest)?;
+    let actual = match algorithm {
+        "sha256" => sha256(bytes),
+        "sha512" => digest_bytes(&SHA512, bytes),
+        other => {
+            return Err(Error::LinkGatheringError(format!(
+                "cannot check OCI digest {}: unsupported algorithm {}",
+                digest, other
+            )))
+        }
+    };
+    if !HEXLOWER_PERMISSIVE
+        .encode(&actual)
+        .eq_ignore_ascii_case(hex)
+    {
+        return Err(Error::VerificationFailure(format!(
+            "the manifest fetched does not have the digest {}",
+            digest
+        )));
+    }
+    Ok(())
+}
+
+fn sha256(bytes: &[u8]) -> Vec<u8> {
+    digest_bytes(&SHA256, bytes)
+}
+
+fn digest_bytes(algorithm: &'static ring::digest::Algorithm, bytes: &[u8]) -> Vec<u8> {
+    digest(algorithm, bytes).as_ref().to_vec()
+}
+
+fn artifact(uri: &str, fragment: &str) -> Result<VirtualTargetPath> {
+    match fragment.is_empty() {
+        true => VirtualTargetPath::new(uri.into()),
+        false => VirtualTargetPath::new(format!("{}#{}", uri, fragment)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use data_encoding::HEXLOWER;
+    use http::{Request, Response, StatusCode};
+    use ring::digest::{digest, SHA256};
+    use serde_json::json;
+
+    use super::{split_reference, OciResolver, OCI_SCHEME};
+    use crate::crypto::HashAlgorithm;
+    use crate::models::VirtualTargetPath;
+    use crate::resolver::ResolverRegistry;
+    use crate::runlib::RecordOptions;
+    use crate::store::HttpTransport;
+    use crate::Result;
+
+    /// Write `bytes` as a blob of the layout `dir`, returning its digest.
+    fn blob(dir: &Path, bytes: &[u8]) -> String {
+        let hex = HEXLOWER.encode(digest(&SHA256, bytes).as_ref());
+        let blobs = dir.join("blobs").join("sha256");
+        std::fs::create_dir_all(&blobs).unwrap();
+        std::fs::write(blobs.join(&hex), bytes).unwrap();
+        format!("sha256:{}", hex)
+    }
+
+    fn image(dir: &Path) -> (String, String, String) {
+        let config = blob(dir, b"{}");
+        let layer = blob(dir, b"layer");
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {"mediaType": "application/vnd.oci.image.config.v1+json", "digest": config, "size": 2},
+            "layers": [{"mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": layer, "size": 5}]
+        });
+        let manifest = blob(dir, manifest.to_string().as_bytes());
+        (manifest, config, layer)
+    }
+
+    fn resolve(
+        resolver: OciResolver,
+        uri: &str,
+    ) -> Result<std::collections::BTreeMap<VirtualTargetPath, crate::models::TargetDescription>>
+    {
+        let mut resolvers = ResolverRegistry::new();
+        resolvers.register_artifacts(OCI_SCHEME, resolver).unwrap();
+        resolvers.resolve(&VirtualTargetPath::new(uri.into())?, &RecordOptions::new())
+    }
+
+    fn sha256(
+        artifacts: &std::collections::BTreeMap<VirtualTargetPath, crate::models::TargetDescription>,
+        uri: &str,
+    ) -> String {
+        let hashes = &artifacts[&VirtualTargetPath::new(uri.into()).unwrap()];
+        format!(
+            "sha256:{}",
+            HEXLOWER.encode(hashes[&HashAlgorithm::Sha256].value())
+        )
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(split_reference("app"), ("app", None, None));
+        assert_eq!(
+            split_reference("team/app:1.0"),
+            ("team/app", Some("1.0"), None)
+        );
+        assert_eq!(
+            split_reference("localhost:5000/app@sha256:00"),
+            ("localhost:5000/app", None, Some("sha256:00"))
+        );
+    }
+
+    #[test]
+    fn resolve_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let (manifest, config, layer) = image(dir.path());
+        let index = json!({
+            "schemaVersion": 2,
+            "manifests": [{"digest": manifest, "annotations": {"org.opencontainers.image.ref.name": "1.0"}}]
+        });
+        let index_blob = blob(dir.path(), index.to_string().as_bytes());
+        std::fs::write(dir.path().join("index.json"), index.to_string()).unwrap();
+
+        let artifacts = resolve(OciResolver::layout(dir.path()), "oci:app:1.0").unwrap();
+        assert_eq!(artifacts.len(), 3);
+        assert_eq!(sha256(&artifacts, "oci:app:1.0"), manifest);
+        assert_eq!(sha256(&artifacts, "oci:app:1.0#config"), config);
+        assert_eq!(sha256(&artifacts, "oci:app:1.0#layer/0"), layer);
+
+        let uri = format!("oci:app@{}", index_blob);
+        let artifacts = resolve(OciResolver::layout(dir.path()), &uri).unwrap();
+        assert_eq!(sha256(&artifacts, &uri), index_blob);
+        assert_eq!(sha256(&artifacts, &format!("{}#manifest/0", uri)), manifest);
+        assert_eq!(
+            sha256(&artifacts, &format!("{}#manifest/0/layer/0", uri)),
+            layer
+        );
+
+        assert!(resolve(OciResolver::layout(dir.path()), "oci:app:2.0").is_err());
+        assert!(resolve(
+            OciResolver::layout(dir.path()),
+            "oci:app@sha256:../index.json"
+        )
+        .is_err());
+        let hex = manifest.trim_start_matches("sha256:");
+        std::fs::write(dir.path().join("blobs/sha256").join(hex), "{}").unwrap();
+        assert!(resolve(OciResolver::layout(dir.path()), "oci:1.0").is_err());
+    }
+
+    struct Registry(HashMap<String, Vec<u8>>);
+
+    impl HttpTransport for Registry {
+        fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+            assert_eq!(request.headers()["authorization"], "Bearer secret");
+            let (status, body) = match self.0.get(&request.uri().to_string()) {
+                Some(body) => (StatusCode::OK, body.clone()),
+                None => (StatusCode::NOT_FOUND, Vec::new()),
+            };
+            Ok(Response::builder().status(status).body(body).unwrap())
+        }
+    }
+
+    #[test]
+    fn resolve_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let (manifest, _, layer) = image(dir.path());
+        let hex = manifest.trim_start_matches("sha256:");
+        let bytes = std::fs::read(dir.path().join("blobs/sha256").join(hex)).unwrap();
+        let url = "https://registry.example.com/v2/team/app/manifests";
+        let registry = || {
+            let manifests = HashMap::from([
+                (format!("{}/1.0", url), bytes.clone()),
+                (format!("{}/{}", url, manifest), bytes.clone()),
+                (format!("{}/latest", url), b"{}".to_vec()),
+            ]);
+            OciResolver::registry(Registry(manifests), "https://registry.example.com/")
+                .token("secret")
+        };
+
+        let artifacts = resolve(registry(), "oci:team/app:1.0").unwrap();
+        assert_eq!(sha256(&artifacts, "oci:team/app:1.0"), manifest);
+        assert_eq!(sha256(&artifacts, "oci:team/app:1.0#layer/0"), layer);
+        let uri = format!("oci:team/app@{}", manifest);
+        assert_eq!(sha256(&resolve(registry(), &uri).unwrap(), &uri), manifest);
+        assert_eq!(resolve(registry(), "oci:team/app").unwrap().len(), 1);
+        assert!(resolve(registry(), "oci:team/app:2.0").is_err());
+        assert!(resolve(registry(), &format!("oci:team/app@{}", layer)).is_err());
+    }
+}
@@ -1,23 +1,51 @@
 //! A tool that functionaries can use to create link metadata about a step.
 
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, warn};
 use path_clean::clean;
-use std::collections::{BTreeMap, HashSet};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{canonicalize as canonicalize_path, symlink_metadata, File};
-use std::io::{self, BufReader, Write};
-use std::process::Command;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use walkdir::WalkDir;
 
 use crate::crypto::HashAlgorithm;
 use crate::interchange::Json;
+use crate::metrics::{Metrics, MetricsHook};
+use crate::models::attempt::Attempt;
 use crate::models::byproducts::ByProducts;
-use crate::models::{Metablock, TargetDescription};
+use crate::models::file_metadata::{FileField, FileMetadata};
+use crate::models::skipped::SkippedArtifacts;
+use crate::models::times::ArtifactTimes;
+use crate::models::toolchain::toolchain_digests;
+use crate::models::{Metablock, TargetDescription, FILE_SCHEME};
+use crate::resolver::ResolverRegistry;
+use crate::verifylib::fnmatch;
 use crate::{
     crypto,
     crypto::PrivateKey,
-    models::{LinkMetadataBuilder, VirtualTargetPath},
+    models::{step::Command, LinkMetadataBuilder, VirtualTargetPath},
 };
 use crate::{Error, Result};
 
+mod cache;
+mod progress;
+mod stats;
+mod throttle;
+
+pub use cache::{HashCache, RACY_WINDOW};
+pub use progress::RecordProgress;
+pub use stats::{RecordStats, RunStats};
+
+use progress::ProgressHook;
+use throttle::OpenFiles;
+
 /// Reads and hashes an artifact given its path as a string literal,
 /// returning the `VirtualTargetPath` and `TargetDescription` of the file as a tuple, wrapped in `Result`.
 pub fn record_artifact(
@@ -32,37 +60,530 @@ pub fn record_artifact(
     Ok((VirtualTargetPath::new(lstripped_path)?, hashes))
 }
 
+/// Records a non-file artifact given its ITE-4 URI, e.g. `pkg:pypi/in-toto@1.0.0`,
+/// and its already computed `TargetDescription`, returning both as a tuple, wrapped in `Result`.
+/// The resulting pair can be inserted into the materials or products of a link.
+pub fn record_resource_artifact(
+    uri: &str,
+    hashes: TargetDescription,
+) -> Result<(VirtualTargetPath, TargetDescription)> {
+    let path = VirtualTargetPath::new(uri.into())?;
+    if path.scheme().is_none() {
+        return Err(Error::IllegalArgument(format!(
+            "artifact {} is not a URI",
+            path
+        )));
+    }
+    if hashes.is_empty() {
+        return Err(Error::IllegalArgument(format!(
+            "artifact {} has no digests",
+            path
+        )));
+    }
+    Ok((path, hashes))
+}
+
 /// Given an artifact path in `&str` format, left strip path for given artifact based an optional array of `lstrip_paths` provided,
 /// returning the stripped file path in String format wrapped in `Result`.
 fn apply_left_strip(path: &str, lstrip_paths: Option<&[&str]>) -> Result<String> {
-    // If lstrip_paths is None, skip strip.
-    // Else, check if path starts with any given lstrip paths and strip
-    if lstrip_paths.is_none() {
-        return Ok(String::from(path));
-    }
-    let l_paths = lstrip_paths.unwrap();
-    let mut stripped_path = path;
-    let mut find_prefix = "";
-    for l_path in l_paths.iter() {
-        if !path.starts_with(l_path) {
-            continue;
+    // Strip the longest of the lstrip paths the path starts with, if any
+    let prefix = lstrip_paths
+        .unwrap_or_default()
+        .iter()
+        .filter(|prefix| path.starts_with(**prefix))
+        .max_by_key(|prefix| prefix.len());
+    let stripped_path = match prefix {
+        Some(prefix) => &path[prefix.len()..],
+        None => path,
+    };
+    Ok(String::from(stripped_path))
+}
+
+/// Verify the names of `hash_algorithms` are valid, defaulting to Sha256.
+fn parse_hash_algorithms(hash_algorithms: Option<&[&str]>) -> Result<Vec<HashAlgorithm>> {
+    let available_algorithms = HashAlgorithm::return_all();
+    match hash_algorithms {
+        Some(hashes) => {
+            let mut map = vec![];
+            for hash in hashes {
+                match available_algorithms.get(*hash) {
+                    Some(value) => map.push(value.clone()),
+                    None => return Err(Error::UnknownHashAlgorithm((*hash).to_string())),
+                }
+            }
+            Ok(map)
         }
-        // if find possible prefix longer than
-        if !find_prefix.is_empty() && find_prefix.len() >= l_path.len() {
-            continue;
+        None => Ok(vec![HashAlgorithm::Sha256]),
+    }
+}
+
+/// What recording artifacts does with symbolic links below the paths
+/// recorded. Links given as paths themselves are always followed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Record linked files under the path of the link and walk linked
+    /// directories, except links to a directory they are in, which would
+    /// loop. Dangling links are skipped.
+    #[default]
+    Follow,
+    /// Skip symbolic links
+    Skip,
+    /// Record each link under its own path, with the digests of the path it
+    /// points to rather than of its content, as `git` does. Linked
+    /// directories are not walked and dangling links are recorded too.
+    Target,
+}
+
+/// What recording artifacts does with file paths that are not valid UTF-8,
+/// as Linux file systems allow, or with symbolic links pointing to one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8Policy {
+    /// Fail with `Error::NonUtf8Path`
+    #[default]
+    Error,
+    /// Skip them with a warning, and everything below such a directory
+    Skip,
+    /// Record them with invalid sequences replaced by `U+FFFD`, so paths
+    /// differing only there collide
+    Lossy,
+}
+
+/// What recording artifacts does with files larger than
+/// `RecordOptions::max_file_size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSizePolicy {
+    /// Leave them out with a warning. `in_toto_run` lists them in the
+    /// byproduct `skipped::SKIPPED_ARTIFACTS_BYPRODUCT`.
+    Skip,
+    /// Fail with `Error::LinkGatheringError`
+    Error,
+    /// Hash them anyway with a warning, e.g. to find out which files of a
+    /// step are large
+    Hash,
+}
+
+/// How `record_artifacts_with_options` records artifacts.
+///
+/// ```
+/// # use in_toto::crypto::HashAlgorithm;
+/// # use in_toto::runlib::{record_artifacts_with_options, RecordOptions};
+/// let options = RecordOptions::new()
+///     .hash_algorithms(&[HashAlgorithm::Sha256, HashAlgorithm::Sha512])
+///     .exclude_patterns(&[".hidden", "*.tmp"])
+///     .lstrip_paths(&["tests/"]);
+/// let materials = record_artifacts_with_options(&["tests/test_runlib"], &options).unwrap();
+/// assert!(materials.values().all(|hashes| hashes.len() == 2));
+/// assert!(materials.keys().all(|path| !path.value().contains(".hidden")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordOptions {
+    pub(crate) hash_algorithms: Vec<HashAlgorithm>,
+    pub(crate) lstrip_paths: Vec<String>,
+    pub(crate) exclude_patterns: Vec<String>,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) base_path: Option<String>,
+    pub(crate) forward_slashes: bool,
+    pub(crate) non_utf8_paths: NonUtf8Policy,
+    pub(crate) include_hidden: bool,
+    pub(crate) hashing_threads: usize,
+    pub(crate) hash_cache: Option<Arc<HashCache>>,
+    pub(crate) max_file_size: Option<(u64, FileSizePolicy)>,
+    pub(crate) metrics: Option<MetricsHook>,
+    pub(crate) progress: Option<ProgressHook>,
+    pub(crate) file_fields: Vec<FileField>,
+    pub(crate) read_size: usize,
+    pub(crate) open_files: Option<Arc<OpenFiles>>,
+    pub(crate) max_pending: usize,
+    pub(crate) empty_directories: bool,
+    pub(crate) expand_globs: bool,
+    pub(crate) resolvers: Option<Arc<ResolverRegistry>>,
+    #[cfg(all(feature = "mmap", unix))]
+    pub(crate) mmap_large_files: bool,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        RecordOptions {
+            hash_algorithms: vec![HashAlgorithm::Sha256],
+            lstrip_paths: Vec::new(),
+            exclude_patterns: Vec::new(),
+            symlinks: SymlinkPolicy::default(),
+            base_path: None,
+            forward_slashes: cfg!(windows),
+            non_utf8_paths: NonUtf8Policy::default(),
+            include_hidden: true,
+            hashing_threads: 1,
+            hash_cache: None,
+            max_file_size: None,
+            metrics: None,
+            progress: None,
+            file_fields: Vec::new(),
+            read_size: crypto::HASH_BUFFER_SIZE,
+            open_files: None,
+            max_pending: PENDING_ARTIFACTS,
+            empty_directories: false,
+            expand_globs: false,
+            resolvers: None,
+            #[cfg(all(feature = "mmap", unix))]
+            mmap_large_files: false,
         }
-        stripped_path = path.strip_prefix(l_path).ok_or_else(|| {
-            Error::from(io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "Lstrip Error: error stripping {} from path {}",
-                    l_path, path
-                ),
-            ))
-        })?;
-        find_prefix = l_path;
     }
-    Ok(String::from(stripped_path))
+}
+
+impl RecordOptions {
+    /// Record sha256 digests of the artifacts at their paths.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record digests with each of `hash_algorithms`, e.g. sha256 and
+    /// sha512 as the reference implementation can
+    pub fn hash_algorithms(mut self, hash_algorithms: &[HashAlgorithm]) -> Self {
+        self.hash_algorithms = hash_algorithms.to_vec();
+        self
+    }
+
+    /// Strip the longest of `lstrip_paths` a file path starts with from it,
+    /// as `--lstrip-paths` of the reference implementation. Recording fails
+    /// if two files end up with the same path.
+    pub fn lstrip_paths(mut self, lstrip_paths: &[&str]) -> Self {
+        self.lstrip_paths = lstrip_paths.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Skip files and directories matching one of `exclude_patterns`, like
+    /// `*.pyc`, `target/**` or `.git`. A pattern is matched against the
+    /// path as walked and relative to the directory recorded, before
+    /// stripping prefixes, with `*` also matching `/` as python's `fnmatch`
+    /// does. A pattern without `/` also matches any single component of the
+    /// path, as in `.gitignore` files. Below an excluded directory nothing
+    /// is recorded.
+    pub fn exclude_patterns(mut self, exclude_patterns: &[&str]) -> Self {
+        self.exclude_patterns = exclude_patterns.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    /// Handle symbolic links as `symlinks` says
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Find the paths recorded below `base_path` rather than the current
+    /// directory, as `--base-path` of the reference implementation. The
+    /// artifacts are still recorded with the paths given, e.g. `src/main.rs`
+    /// for `src` found in `base_path`, absolute paths are not affected.
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        self.base_path = Some(clean(base_path));
+        self
+    }
+
+    /// Record paths with `/` in place of the `\` separators of Windows, as
+    /// layouts expect, see `VirtualTargetPath::with_forward_slashes`. This
+    /// is the default on Windows only, elsewhere `\` may be part of a file
+    /// name. Patterns and prefixes given are normalized alike.
+    pub fn forward_slashes(mut self, forward_slashes: bool) -> Self {
+        self.forward_slashes = forward_slashes;
+        self
+    }
+
+    /// Handle file paths that are not valid UTF-8, which links cannot hold,
+    /// as `non_utf8_paths` says
+    pub fn non_utf8_paths(mut self, non_utf8_paths: NonUtf8Policy) -> Self {
+        self.non_utf8_paths = non_utf8_paths;
+        self
+    }
+
+    /// Whether to record hidden files and walk hidden directories, as by
+    /// default. Names starting with `.` like `.env` or `.cache` are hidden,
+    /// and on Windows files with the hidden attribute. The paths given to
+    /// record are recorded even if they are hidden themselves.
+    pub fn include_hidden(mut self, include_hidden: bool) -> Self {
+        self.include_hidden = include_hidden;
+        self
+    }
+
+    /// Whether to expand paths recorded containing `*`, `?` or `[` like a
+    /// shell does, e.g. `dist/*.tar.gz`, rather than recording them as
+    /// given. Each component of the path is matched against the entries of
+    /// the directories matched so far, `*` not matching `/`, and names
+    /// starting with `.` only match patterns starting with `.`. A pattern
+    /// matching nothing fails, as a missing path does.
+    pub fn expand_globs(mut self, expand_globs: bool) -> Self {
+        self.expand_globs = expand_globs;
+        self
+    }
+
+    /// Whether to record directories without any entries, so rules can
+    /// require a directory to exist even if it holds no files. An empty
+    /// directory is recorded with its name ending in `/`, e.g. `logs/`, and
+    /// the digests of an empty string.
+    pub fn empty_directories(mut self, empty_directories: bool) -> Self {
+        self.empty_directories = empty_directories;
+        self
+    }
+
+    /// Hash files on `hashing_threads` threads rather than one, e.g. on
+    /// `std::thread::available_parallelism` threads for large trees. The
+    /// artifacts recorded are the same.
+    pub fn hashing_threads(mut self, hashing_threads: usize) -> Self {
+        self.hashing_threads = hashing_threads.max(1);
+        self
+    }
+
+    /// Take the digests of files unchanged since they were recorded with
+    /// `hash_cache` from it rather than hashing them again, and add those of
+    /// the files hashed. See `HashCache` for when a file counts as unchanged.
+    pub fn hash_cache(mut self, hash_cache: Arc<HashCache>) -> Self {
+        self.hash_cache = Some(hash_cache);
+        self
+    }
+
+    /// Handle files larger than `max_file_size` bytes as `policy` says,
+    /// rather than hashing files of any size. Only files found walking the
+    /// paths recorded are checked.
+    pub fn max_file_size(mut self, max_file_size: u64, policy: FileSizePolicy) -> Self {
+        self.max_file_size = Some((max_file_size, policy));
+        self
+    }
+
+    /// Report each file hashed, its size and how long hashing it took to
+    /// `metrics`
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(MetricsHook(metrics));
+        self
+    }
+
+    /// Record `fields` of the metadata of files with their digests, see
+    /// `file_metadata`
+    pub fn file_fields(mut self, fields: &[FileField]) -> Self {
+        self.file_fields = fields.to_vec();
+        self
+    }
+
+    /// Read files to hash in chunks of `read_size` bytes rather than
+    /// `crypto::HASH_BUFFER_SIZE`. Larger reads take fewer round trips on
+    /// file systems mounted over the network, like NFS or SMB.
+    pub fn read_size(mut self, read_size: usize) -> Self {
+        self.read_size = read_size.max(1);
+        self
+    }
+
+    /// Keep at most `max_open_files` files open for hashing at a time,
+    /// across all recordings with these options or their clones however many
+    /// hashing threads they run, so as not to overload the metadata server
+    /// of a network file system.
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.open_files = Some(Arc::new(OpenFiles::new(max_open_files)));
+        self
+    }
+
+    /// Pause walking while `max_pending` artifacts found wait to be hashed,
+    /// rather than 256, so walking and the metadata it reads keep closer pace
+    /// with hashing.
+    pub fn max_pending_artifacts(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending.max(1);
+        self
+    }
+
+    /// Report the artifacts found and recorded to `progress`
+    pub fn progress(mut self, progress: Arc<dyn RecordProgress>) -> Self {
+        self.progress = Some(ProgressHook(progress));
+        self
+    }
+
+    /// Hash non-file ITE-4 artifacts, e.g. `maven:org.example/lib@1.0`, with
+    /// the resolver `resolvers` holds for their scheme, as
+    /// `record_artifacts_with_resolvers` does
+    pub fn resolvers(mut self, resolvers: Arc<ResolverRegistry>) -> Self {
+        self.resolvers = Some(resolvers);
+        self
+    }
+
+    /// Hash files of at least `crypto::MMAP_THRESHOLD` bytes memory-mapped
+    /// rather than read, so they are not copied. By default all files are
+    /// read.
+    ///
+    /// # Safety
+    ///
+    /// No file recorded with these options may be truncated or written to
+    /// while it is hashed, e.g. because the files are on a read-only
+    /// snapshot. A truncated file raises `SIGBUS` once the hash reaches a
+    /// page past its new end, and a file written to changes under the hash,
+    /// which is undefined behavior.
+    #[cfg(all(feature = "mmap", unix))]
+    pub unsafe fn mmap_large_files(mut self) -> Self {
+        self.mmap_large_files = true;
+        self
+    }
+
+    /// Whether the file `name` of `size` bytes is to be hashed, as the size
+    /// policy says, adding it to `skipped` if not.
+    fn within_max_file_size(
+        &self,
+        name: &str,
+        size: u64,
+        skipped: &mut BTreeSet<VirtualTargetPath>,
+    ) -> Result<bool> {
+        let (max_file_size, policy) = match self.max_file_size {
+            Some((max_file_size, policy)) if size > max_file_size => (max_file_size, policy),
+            _ => return Ok(true),
+        };
+        match policy {
+            FileSizePolicy::Skip => {
+                warn!(
+                    "Skipping {} of {} bytes, more than {}",
+                    name, size, max_file_size
+                );
+                let prefixes = self.lstrip();
+                let lstrip: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+                skipped.insert(VirtualTargetPath::new(apply_left_strip(
+                    name,
+                    Some(&lstrip),
+                )?)?);
+                Ok(false)
+            }
+            FileSizePolicy::Error => Err(Error::LinkGatheringError(format!(
+                "{} of {} bytes is larger than {}",
+                name, size, max_file_size
+            ))),
+            FileSizePolicy::Hash => {
+                warn!(
+                    "Hashing {} of {} bytes, more than {}",
+                    name, size, max_file_size
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    /// `path` with its separators normalized if asked to
+    fn separators(&self, path: &str) -> String {
+        match self.forward_slashes {
+            true => path.replace('\\', "/"),
+            false => path.to_string(),
+        }
+    }
+
+    /// Where the artifact `path` is found on the file system
+    fn locate(&self, path: &str) -> String {
+        match &self.base_path {
+            Some(base_path) if !Path::new(path).is_absolute() => {
+                clean(&format!("{}/{}", base_path, path))
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// `paths` with the globs among them expanded if asked to.
+    fn expand_paths(&self, paths: &[&str]) -> Result<Vec<String>> {
+        let mut expanded = Vec::new();
+        for path in paths {
+            let artifact = VirtualTargetPath::new((*path).into())?;
+            match self.expand_globs && artifact.is_file() && is_glob(artifact.resource()) {
+                true => expanded.extend(self.expand_glob(artifact.resource())?),
+                false => expanded.push(path.to_string()),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// The paths matching the glob `pattern`, sorted, see `expand_globs`.
+    fn expand_glob(&self, pattern: &str) -> Result<Vec<String>> {
+        let join = |prefix: &str, name: &str| match prefix {
+            "" => name.to_string(),
+            "/" => format!("/{}", name),
+            _ => format!("{}/{}", prefix, name),
+        };
+        let root = if pattern.starts_with('/') { "/" } else { "" };
+        let mut matches = vec![root.to_string()];
+        for component in pattern.split('/').filter(|c| !c.is_empty()) {
+            if !is_glob(component) {
+                matches = matches
+                    .iter()
+                    .map(|prefix| join(prefix, component))
+                    .collect();
+                continue;
+            }
+            let mut next = Vec::new();
+            for prefix in &matches {
+                let dir = self.locate(if prefix.is_empty() { "." } else { prefix });
+                if !Path::new(&dir).is_dir() {
+                    continue;
+                }
+                let mut names = Vec::new();
+                for entry in std::fs::read_dir(&dir)? {
+                    let name = match entry?.file_name().into_string() {
+                        Ok(name) => name,
+                        Err(_) => continue,
+                    };
+                    if name.starts_with('.') && !component.starts_with('.') {
+                        continue;
+                    }
+                    if fnmatch(component, &name) {
+                        names.push(join(prefix, &name));
+                    }
+                }
+                names.sort();
+                next.extend(names);
+            }
+            matches = next;
+        }
+        matches.retain(|path| symlink_metadata(self.locate(path)).is_ok());
+        if matches.is_empty() {
+            return Err(Error::LinkGatheringError(format!(
+                "no artifacts match {}",
+                pattern
+            )));
+        }
+        Ok(matches)
+    }
+
+    /// The hash algorithms artifacts are recorded with
+    pub fn algorithms(&self) -> &[HashAlgorithm] {
+        &self.hash_algorithms
+    }
+
+    /// Whether `path`, found below `root`, matches one of the exclude
+    /// patterns
+    pub(crate) fn excludes(&self, path: &str, root: &str) -> bool {
+        let relative = path
+            .strip_prefix(root.trim_end_matches('/'))
+            .and_then(|rest| rest.strip_prefix('/'))
+            .unwrap_or(path);
+        self.exclude_patterns.iter().any(|pattern| {
+            let pattern = &self.separators(pattern);
+            fnmatch(pattern, path)
+                || fnmatch(pattern, relative)
+                || (!pattern.contains('/')
+                    && path.split('/').any(|component| fnmatch(pattern, component)))
+        })
+    }
+
+    /// Options with the hash algorithms and prefixes named by the arguments
+    /// of `record_artifacts`, where given.
+    fn with_arguments(
+        &self,
+        hash_algorithms: Option<&[&str]>,
+        lstrip_paths: Option<&[&str]>,
+    ) -> Result<Self> {
+        let mut options = self.clone();
+        if hash_algorithms.is_some() {
+            options = options.hash_algorithms(&parse_hash_algorithms(hash_algorithms)?);
+        }
+        if let Some(lstrip_paths) = lstrip_paths {
+            options = options.lstrip_paths(lstrip_paths);
+        }
+        Ok(options)
+    }
+
+    /// The prefixes to strip, with their separators normalized
+    fn lstrip(&self) -> Vec<String> {
+        self.lstrip_paths
+            .iter()
+            .map(|prefix| self.separators(prefix))
+            .collect()
+    }
 }
 
 /// Traverses through the passed array of paths, hashes the content of files
@@ -82,79 +603,899 @@ fn apply_left_strip(path: &str, lstrip_paths: Option<&[&str]>) -> Result<String>
 /// # use in_toto::runlib::{record_artifacts};
 /// let materials = record_artifacts(&["tests/test_runlib"], None, None).unwrap();
 /// ```
+///
+/// See `record_artifacts_with_options` to give typed hash algorithms.
 pub fn record_artifacts(
     paths: &[&str],
     hash_algorithms: Option<&[&str]>,
     lstrip_paths: Option<&[&str]>,
 ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
-    // Verify hash_algorithms inputs are valid
-    let available_algorithms = HashAlgorithm::return_all();
-    let hash_algorithms = match hash_algorithms {
-        Some(hashes) => {
-            let mut map = vec![];
-            for hash in hashes {
-                if !available_algorithms.contains_key(*hash) {
-                    return Err(Error::UnknownHashAlgorithm((*hash).to_string()));
+    record_artifacts_with_resolvers(
+        paths,
+        hash_algorithms,
+        lstrip_paths,
+        &ResolverRegistry::new(),
+    )
+}
+
+/// Like `record_artifacts`, but non-file ITE-4 artifacts in `paths`, e.g.
+/// `maven:org.example/lib@1.0`, are hashed by the resolver `resolvers` holds
+/// for their scheme. `lstrip_paths` only applies to files.
+///
+/// # Examples
+///
+/// ```
+/// # use in_toto::crypto::{calculate_hashes, HashAlgorithm};
+/// # use in_toto::models::VirtualTargetPath;
+/// # use in_toto::resolver::ResolverRegistry;
+/// # use in_toto::runlib::record_artifacts_with_resolvers;
+/// let mut resolvers = ResolverRegistry::new();
+/// resolvers
+///     .register("maven", |path: &VirtualTargetPath, algorithms: &[HashAlgorithm]| {
+///         Ok(calculate_hashes(path.resource().as_bytes(), algorithms)?.1)
+///     })
+///     .unwrap();
+/// let materials = record_artifacts_with_resolvers(
+///     &["tests/test_runlib", "maven:org.example/lib@1.0"],
+///     None,
+///     None,
+///     &resolvers,
+/// )
+/// .unwrap();
+/// ```
+pub fn record_artifacts_with_resolvers(
+    paths: &[&str],
+    hash_algorithms: Option<&[&str]>,
+    lstrip_paths: Option<&[&str]>,
+    resolvers: &ResolverRegistry,
+) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+    let options = RecordOptions::new().with_arguments(hash_algorithms, lstrip_paths)?;
+    record(paths, &options, resolvers, None, None, None)
+}
+
+/// Like `record_artifacts`, with the artifacts recorded as `options` say,
+/// non-file ITE-4 artifacts by `RecordOptions::resolvers`.
+pub fn record_artifacts_with_options(
+    paths: &[&str],
+    options: &RecordOptions,
+) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+    let no_resolvers = ResolverRegistry::new();
+    let resolvers = options.resolvers.as_deref().unwrap_or(&no_resolvers);
+    record(paths, options, resolvers, None, None, None)
+}
+
+/// An artifact found walking the paths recorded, waiting to be hashed.
+enum Pending {
+    /// A file, or a symbolic link to one, at `path` recorded with its content
+    /// as `name`, of `size` bytes, with the ID of the file if it has other
+    /// hard links
+    File {
+        path: PathBuf,
+        name: String,
+        size: u64,
+        hardlink: Option<FileId>,
+    },
+    /// A symbolic link at `path` recorded with the path it points to as
+    /// `name`
+    LinkTarget { path: PathBuf, name: String },
+    /// A resource hashed by its resolver
+    Resource(VirtualTargetPath),
+    /// An empty directory at `path`, recorded as `name` with a trailing `/`
+    Directory { path: PathBuf, name: String },
+}
+
+/// How many artifacts found may wait to be hashed before walking pauses by
+/// default, see `RecordOptions::max_pending_artifacts`.
+const PENDING_ARTIFACTS: usize = 256;
+
+/// Record the artifacts in `paths` in two stages running concurrently: this
+/// thread walks the paths, sending the artifacts it finds to the threads
+/// hashing them, so walking large trees does not wait for hashing and vice
+/// versa. Each hashing thread collects its own artifacts, which are merged
+/// once the walk is done, so the result does not depend on which thread
+/// hashed what. A file with several hard links, as in package manager
+/// stores, is hashed once and its digests are shared between the threads.
+fn record(
+    paths: &[&str],
+    options: &RecordOptions,
+    resolvers: &ResolverRegistry,
+    mut times: Option<&mut BTreeMap<VirtualTargetPath, i64>>,
+    skipped: Option<&mut BTreeSet<VirtualTargetPath>>,
+    stats: Option<&mut RecordStats>,
+) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::sync_channel(options.max_pending);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let record_times = times.is_some();
+    let hardlinks = Hardlinks::default();
+    thread::scope(|scope| {
+        let hashers: Vec<_> = (0..options.hashing_threads)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                let hardlinks = &hardlinks;
+                scope.spawn(move || {
+                    hash_pending(receiver, hardlinks, options, resolvers, record_times)
+                })
+            })
+            .collect();
+        // walking fails once all hashers failed and dropped the receiver
+        drop(receiver);
+        let mut walk_skipped = BTreeSet::new();
+        let walked = walk(paths, options, resolvers, &sender, &mut walk_skipped);
+        drop(sender);
+        let mut artifacts = BTreeMap::new();
+        let mut hashed_stats = RecordStats::default();
+        let mut failure = None;
+        for hasher in hashers {
+            let hashed = hasher
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            match hashed {
+                Ok((hashed, hashed_times, thread_stats)) if failure.is_none() => {
+                    hashed_stats.merge(thread_stats);
+                    for (path, hashes) in hashed {
+                        let modified = hashed_times.get(&path).copied();
+                        if let Err(e) = insert_artifact(
+                            &mut artifacts,
+                            times.as_deref_mut(),
+                            path,
+                            hashes,
+                            modified,
+                        ) {
+                            failure = Some(e);
+                            break;
+                        }
+                    }
                 }
-                let value = available_algorithms.get(*hash).unwrap();
-                map.push(value.clone());
+                Ok(_) => (),
+                Err(e) => failure = failure.or(Some(e)),
             }
-            map
         }
-        None => vec![HashAlgorithm::Sha256],
-    };
-    let hash_algorithms = &hash_algorithms[..];
+        // the walker stops once the hashers failed, so their error comes first
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        walked?;
+        if let Some(skipped) = skipped {
+            skipped.extend(walk_skipped);
+        }
+        if let Some(stats) = stats {
+            hashed_stats.finish(artifacts.len(), start.elapsed());
+            *stats = hashed_stats;
+        }
+        Ok(artifacts)
+    })
+}
 
-    // Initialize artifacts
-    let mut artifacts: BTreeMap<VirtualTargetPath, TargetDescription> = BTreeMap::new();
-    // For each path provided, walk the directory and add all files to artifacts
-    for path in paths {
+/// Whether `path` is a resource for its resolver rather than a file: its
+/// scheme has a resolver in `resolvers`, or it is a URI with an authority
+/// like `https://example.com/lib.tar.gz`. Other paths with a colon, like
+/// `notes:draft.txt`, are files.
+fn is_resource(path: &VirtualTargetPath, resolvers: &ResolverRegistry) -> bool {
+    match path.scheme() {
+        None | Some(FILE_SCHEME) => false,
+        Some(scheme) => resolvers.contains(scheme) || path.resource().starts_with("//"),
+    }
+}
+
+/// Walk `paths`, sending the artifacts to record to `pending` and adding
+/// those skipped for their size to `skipped`.
+fn walk(
+    paths: &[&str],
+    options: &RecordOptions,
+    resolvers: &ResolverRegistry,
+    pending: &SyncSender<Pending>,
+    skipped: &mut BTreeSet<VirtualTargetPath>,
+) -> Result<()> {
+    let send = |artifact: Pending| {
+        if let Some(ProgressHook(progress)) = &options.progress {
+            match &artifact {
+                Pending::File { name, size, .. } => progress.discovered(name, Some(*size)),
+                Pending::LinkTarget { name, .. } => progress.discovered(name, None),
+                Pending::Resource(path) => progress.discovered(path.value(), None),
+                Pending::Directory { name, .. } => progress.discovered(name, None),
+            }
+        }
+        pending
+            .send(artifact)
+            .map_err(|_| Error::LinkGatheringError("hashing artifacts stopped".into()))
+    };
+    for path in options.expand_paths(paths)? {
+        // Only files are walked, other ITE-4 resources are hashed by their
+        // resolver
+        let path = VirtualTargetPath::new(path)?;
+        if is_resource(&path, resolvers) {
+            send(Pending::Resource(path))?;
+            continue;
+        }
         // Normalize path
-        let path = clean(path);
-        let mut walker = WalkDir::new(path).follow_links(true).into_iter();
-        let mut visited_sym_links = HashSet::new();
+        let root = match path.is_file() {
+            true => clean(path.resource()),
+            false => clean(path.value()),
+        };
+        let follow = options.symlinks == SymlinkPolicy::Follow;
+        let located = options.locate(&root);
+        let mut walker = WalkDir::new(&located).follow_links(follow).into_iter();
         while let Some(entry) = walker.next() {
             let path = dir_entry_to_path(entry)?;
-            let file_type = std::fs::symlink_metadata(&path)?.file_type();
-            // If entry is a symlink, check it's unvisited. If so, continue.
-            if file_type.is_symlink() {
-                if visited_sym_links.contains(&path) {
+            let walked = match path_string(&path, options.non_utf8_paths)? {
+                Some(walked) => walked,
+                None => {
+                    if path.is_dir() {
+                        walker.skip_current_dir();
+                    }
+                    continue;
+                }
+            };
+            // named as if walking `root` in the current directory
+            let name = match walked.strip_prefix(&located) {
+                Some(rest) => clean(&format!("{}{}", root, rest)),
+                None => clean(&walked),
+            };
+            let name = options.separators(&name);
+            if !options.include_hidden && path != Path::new(&located) && is_hidden(&path) {
+                debug!("Skipping hidden {}", name);
+                if path.is_dir() {
                     walker.skip_current_dir();
-                } else {
-                    visited_sym_links.insert(String::from(&path));
-                    // s_path: the actual path the symbolic link is pointing to
-                    let s_path = match std::fs::read_link(&path)?.as_path().to_str() {
-                        Some(str) => String::from(str),
-                        None => break,
-                    };
-                    if symlink_metadata(&s_path)?.file_type().is_file() {
-                        let (virtual_target_path, hashes) =
-                            record_artifact(&path, hash_algorithms, lstrip_paths)?;
-                        if artifacts.contains_key(&virtual_target_path) {
-                            return Err(Error::LinkGatheringError(format!(
-                                "non unique stripped path {}",
-                                virtual_target_path.to_string()
-                            )));
+                }
+                continue;
+            }
+            let metadata = symlink_metadata(&path)?;
+            let file_type = metadata.file_type();
+            if options.excludes(&name, &options.separators(&root)) {
+                // walking on from a file would skip its siblings instead
+                if std::fs::metadata(&path).is_ok_and(|m| m.is_dir()) {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+            // The walker descends into linked directories when following
+            // links, and reports links to one of their ancestors as loops,
+            // which are not walked again. Links to files are recorded here.
+            if file_type.is_symlink() {
+                match options.symlinks {
+                    SymlinkPolicy::Skip => debug!("Skipping symbolic link {}", name),
+                    SymlinkPolicy::Target => send(Pending::LinkTarget { path, name })?,
+                    SymlinkPolicy::Follow => match std::fs::metadata(&path) {
+                        Ok(metadata) if metadata.is_file() => {
+                            if !options.within_max_file_size(&name, metadata.len(), skipped)? {
+                                continue;
+                            }
+                            let hardlink = hardlink_id(&metadata);
+                            send(Pending::File {
+                                path,
+                                name,
+                                size: metadata.len(),
+                                hardlink,
+                            })?
                         }
-                        artifacts.insert(virtual_target_path, hashes);
-                    }
+                        Ok(metadata) if metadata.is_dir() => {
+                            if options.empty_directories && is_empty_dir(&path)? {
+                                send(Pending::Directory { path, name })?
+                            }
+                        }
+                        Ok(_) => (),
+                        Err(e) => warn!("Skipping dangling symbolic link {}: {}", name, e),
+                    },
                 }
+                continue;
             }
             // If entry is a file, open and hash the file
             if file_type.is_file() {
-                let (virtual_target_path, hashes) =
-                    record_artifact(&path, hash_algorithms, lstrip_paths)?;
-                if artifacts.contains_key(&virtual_target_path) {
-                    return Err(Error::LinkGatheringError(format!(
-                        "non unique stripped path {}",
-                        virtual_target_path.to_string()
-                    )));
+                if !options.within_max_file_size(&name, metadata.len(), skipped)? {
+                    continue;
+                }
+                let hardlink = hardlink_id(&metadata);
+                send(Pending::File {
+                    path,
+                    name,
+                    size: metadata.len(),
+                    hardlink,
+                })?;
+            } else if file_type.is_dir() && options.empty_directories && is_empty_dir(&path)? {
+                send(Pending::Directory { path, name })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the directory `path` has no entries at all, hidden or not.
+fn is_empty_dir(path: &Path) -> Result<bool> {
+    Ok(std::fs::read_dir(path)?.next().is_none())
+}
+
+/// Hash the artifacts received from `pending` until the walk is done,
+/// returning them with their modification times if `record_times` and the
+/// statistics of the files hashed.
+#[allow(clippy::type_complexity)]
+fn hash_pending(
+    pending: Arc<Mutex<Receiver<Pending>>>,
+    hardlinks: &Hardlinks,
+    options: &RecordOptions,
+    resolvers: &ResolverRegistry,
+    record_times: bool,
+) -> Result<(
+    BTreeMap<VirtualTargetPath, TargetDescription>,
+    BTreeMap<VirtualTargetPath, i64>,
+    RecordStats,
+)> {
+    let prefixes = options.lstrip();
+    let lstrip: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+    let lstrip_paths = Some(&lstrip[..]);
+
+    let mut artifacts: BTreeMap<VirtualTargetPath, TargetDescription> = BTreeMap::new();
+    let mut times = BTreeMap::new();
+    let mut stats = RecordStats::default();
+    loop {
+        // the lock is only held while waiting for the next artifact
+        let next = pending
+            .lock()
+            .map_err(|_| Error::Programming("a hashing thread panicked".into()))?
+            .recv();
+        let artifact = match next {
+            Ok(artifact) => artifact,
+            Err(_) => break,
+        };
+        let times = if record_times { Some(&mut times) } else { None };
+        let (name, bytes) = match artifact {
+            Pending::File {
+                path,
+                name,
+                hardlink,
+                ..
+            } => {
+                let bytes = record_file(
+                    &path,
+                    &name,
+                    options,
+                    lstrip_paths,
+                    hardlink.map(|id| (id, hardlinks)),
+                    &mut artifacts,
+                    times,
+                    &mut stats,
+                )?;
+                (name, bytes)
+            }
+            Pending::LinkTarget { path, name } => {
+                record_link_target(&path, &name, options, lstrip_paths, &mut artifacts, times)?;
+                (name, 0)
+            }
+            Pending::Resource(path) => {
+                let name = path.value().to_string();
+                for (path, hashes) in resolvers.resolve(&path, options)? {
+                    insert_artifact(&mut artifacts, None, path, hashes, None)?;
                 }
-                artifacts.insert(virtual_target_path, hashes);
+                (name, 0)
+            }
+            Pending::Directory { path, name } => {
+                record_directory(&path, &name, options, lstrip_paths, &mut artifacts, times)?;
+                (name, 0)
+            }
+        };
+        if let Some(ProgressHook(progress)) = &options.progress {
+            progress.recorded(&name, bytes);
+        }
+    }
+    Ok((artifacts, times, stats))
+}
+
+/// The size and digests of `file`, read as `options` say, adding how long
+/// each algorithm took to `times`.
+fn hash_file(
+    file: &File,
+    options: &RecordOptions,
+    times: &mut HashMap<HashAlgorithm, Duration>,
+) -> Result<(u64, TargetDescription)> {
+    #[cfg(all(feature = "mmap", unix))]
+    if options.mmap_large_files {
+        // SAFETY: setting `mmap_large_files` promises the files recorded do
+        // not change while they are hashed
+        return unsafe {
+            crypto::calculate_mapped_file_hashes(
+                file,
+                &options.hash_algorithms,
+                options.read_size,
+                Some(times),
+            )
+        };
+    }
+    crypto::calculate_hashes_timed(
+        file,
+        &options.hash_algorithms,
+        options.read_size,
+        Some(times),
+    )
+}
+
+/// Hash the file `path` into `artifacts` as `name`, unless its digests are
+/// cached or known from another hard link `hardlink` identifies, noting its
+/// modification time in `times` if given and the file hashed in `stats`.
+/// Returns the number of bytes hashed.
+fn record_file(
+    path: &Path,
+    name: &str,
+    options: &RecordOptions,
+    lstrip_paths: Option<&[&str]>,
+    hardlink: Option<(FileId, &Hardlinks)>,
+    artifacts: &mut BTreeMap<VirtualTargetPath, TargetDescription>,
+    times: Option<&mut BTreeMap<VirtualTargetPath, i64>>,
+    stats: &mut RecordStats,
+) -> Result<u64> {
+    let hash_algorithms = &options.hash_algorithms[..];
+    // read before hashing, so a file changing meanwhile is hashed again
+    let metadata = match (&options.hash_cache, &times) {
+        (None, None) if options.file_fields.is_empty() => None,
+        _ => Some(std::fs::metadata(path)?),
+    };
+    let cache = options.hash_cache.as_deref().zip(metadata.as_ref());
+    let linked = match hardlink {
+        Some((id, hardlinks)) => lock_hardlinks(hardlinks)?.get(&id).cloned(),
+        None => None,
+    };
+    let cached = linked
+        .or_else(|| cache.and_then(|(cache, metadata)| cache.get(path, metadata, hash_algorithms)));
+    let mut hashed = 0;
+    let hashes = match cached {
+        Some(hashes) => hashes,
+        None => {
+            let _open = options.open_files.as_ref().map(|files| files.acquire());
+            let start = Instant::now();
+            let file = File::open(path)?;
+            let mut algorithm_times = HashMap::new();
+            let (length, hashes) = hash_file(&file, options, &mut algorithm_times)?;
+            stats.file_hashed(length, algorithm_times);
+            if let Some(MetricsHook(metrics)) = &options.metrics {
+                metrics.file_hashed(length, start.elapsed());
+            }
+            hashed = length;
+            if let Some((cache, metadata)) = cache {
+                cache.insert(path, metadata, &hashes);
+            }
+            hashes
+        }
+    };
+    if let Some((id, hardlinks)) = hardlink {
+        lock_hardlinks(hardlinks)?.insert(id, hashes.clone());
+    }
+    // added after caching, as e.g. a change of mode leaves the stamp alone
+    let mut hashes = hashes;
+    if let (false, Some(metadata)) = (options.file_fields.is_empty(), &metadata) {
+        FileMetadata::from_fs(metadata, &options.file_fields)?.add_to(&mut hashes);
+    }
+    let virtual_target_path = VirtualTargetPath::new(apply_left_strip(name, lstrip_paths)?)?;
+    let modified = match (times.is_some(), &metadata) {
+        (true, Some(metadata)) => Some(modification_time(metadata)?),
+        _ => None,
+    };
+    insert_artifact(artifacts, times, virtual_target_path, hashes, modified)?;
+    Ok(hashed)
+}
+
+/// A file by device and inode, the same for all its hard links.
+type FileId = (u64, u64);
+
+/// The digests of the files with several hard links hashed so far, shared by
+/// the hashing threads.
+type Hardlinks = Mutex<HashMap<FileId, TargetDescription>>;
+
+/// The ID of the file with `metadata`, if it has more than one hard link.
+#[cfg(unix)]
+fn hardlink_id(metadata: &std::fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hardlink_id(_metadata: &std::fs::Metadata) -> Option<FileId> {
+    None
+}
+
+fn lock_hardlinks(
+    hardlinks: &Hardlinks,
+) -> Result<std::sync::MutexGuard<'_, HashMap<FileId, TargetDescription>>> {
+    hardlinks
+        .lock()
+        .map_err(|_| Error::Programming("a hashing thread panicked".into()))
+}
+
+/// Hash the path the symbolic link `path` points to into `artifacts` as
+/// `name`, noting the modification time of the link in `times` if given.
+fn record_link_target(
+    path: &Path,
+    name: &str,
+    options: &RecordOptions,
+    lstrip_paths: Option<&[&str]>,
+    artifacts: &mut BTreeMap<VirtualTargetPath, TargetDescription>,
+    times: Option<&mut BTreeMap<VirtualTargetPath, i64>>,
+) -> Result<()> {
+    let hash_algorithms = &options.hash_algorithms[..];
+    let target = match path_string(&std::fs::read_link(path)?, options.non_utf8_paths)? {
+        Some(target) => target,
+        None => return Ok(()),
+    };
+    let (_length, hashes) = crypto::calculate_hashes(target.as_bytes(), hash_algorithms)?;
+    let virtual_target_path = VirtualTargetPath::new(apply_left_strip(name, lstrip_paths)?)?;
+    let modified = match times {
+        Some(_) => Some(modification_time(&symlink_metadata(path)?)?),
+        None => None,
+    };
+    insert_artifact(artifacts, times, virtual_target_path, hashes, modified)
+}
+
+/// Record the empty directory `path` into `artifacts` as `name` followed by
+/// `/`, with the digests of an empty string.
+fn record_directory(
+    path: &Path,
+    name: &str,
+    options: &RecordOptions,
+    lstrip_paths: Option<&[&str]>,
+    artifacts: &mut BTreeMap<VirtualTargetPath, TargetDescription>,
+    times: Option<&mut BTreeMap<VirtualTargetPath, i64>>,
+) -> Result<()> {
+    let (_length, hashes) = crypto::calculate_hashes(&b""[..], &options.hash_algorithms)?;
+    let stripped = apply_left_strip(name, lstrip_paths)?;
+    let virtual_target_path = VirtualTargetPath::new(format!("{}/", stripped))?;
+    let modified = match times {
+        Some(_) => Some(modification_time(&std::fs::metadata(path)?)?),
+        None => None,
+    };
+    insert_artifact(artifacts, times, virtual_target_path, hashes, modified)
+}
+
+/// Add the artifact `path` to `artifacts`, failing if it is already there,
+/// and its modification time `modified` to `times`.
+fn insert_artifact(
+    artifacts: &mut BTreeMap<VirtualTargetPath, TargetDescription>,
+    times: Option<&mut BTreeMap<VirtualTargetPath, i64>>,
+    path: VirtualTargetPath,
+    hashes: TargetDescription,
+    modified: Option<i64>,
+) -> Result<()> {
+    if artifacts.contains_key(&path) {
+        return Err(Error::LinkGatheringError(format!(
+            "non unique stripped path {}",
+            path
+        )));
+    }
+    if let (Some(times), Some(modified)) = (times, modified) {
+        times.insert(path.clone(), modified);
+    }
+    artifacts.insert(path, hashes);
+    Ok(())
+}
+
+/// The modification time in `metadata`, in whole seconds since the Unix
+/// epoch.
+fn modification_time(metadata: &std::fs::Metadata) -> Result<i64> {
+    let modified = DateTime::<Utc>::from(metadata.modified()?);
+    Ok(modified.timestamp())
+}
+
+/// Whether the output of a wrapped command is echoed and recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Echo the output to the stdout and stderr of this process as it is
+    /// written, and record it in the byproducts.
+    #[default]
+    Tee,
+    /// Only record the output in the byproducts.
+    CaptureOnly,
+    /// Neither echo nor record the output, the byproducts `stdout` and
+    /// `stderr` are left empty.
+    Quiet,
+}
+
+/// Name of the byproduct holding when the command of a step started.
+pub const START_TIME_BYPRODUCT: &str = "start-time";
+
+/// Name of the byproduct holding when the command of a step ended.
+pub const END_TIME_BYPRODUCT: &str = "end-time";
+
+/// Name of the byproduct holding the timeout, in seconds as a number, a
+/// command was killed after, see `RunOptions::timeout`.
+pub const TIMEOUT_BYPRODUCT: &str = "timeout";
+
+/// The environment key of the directory a step ran in, with `/` as
+/// separator, as the reference implementation records it.
+pub const WORKDIR_ENV: &str = "workdir";
+
+/// The prefix of the environment keys of the variables a step ran with, see
+/// `RunOptions::record_variables`.
+pub const VARIABLE_ENV_PREFIX: &str = "variable:";
+
+/// What to do when a wrapped command exits with a non-zero return value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitPolicy {
+    /// Record the return value in the byproducts and carry on, as the
+    /// specification has it.
+    #[default]
+    RecordAndContinue,
+    /// Fail with `Error::CommandFailed`, holding the byproducts, so that a
+    /// failing build fails the step.
+    FailFast,
+}
+
+/// The environment a wrapped command runs in.
+///
+/// Variables of this process, e.g. tokens of the CI system, influence the
+/// command and may end up in its recorded output unless the environment is
+/// restricted. The executable is looked up in the `PATH` of the restricted
+/// environment, so it is mostly passed on.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CommandEnv {
+    /// Inherit the environment of this process.
+    #[default]
+    Inherit,
+    /// Inherit only the variables named, those set in this process.
+    Allowlist(Vec<String>),
+    /// Run with exactly the variables given.
+    Explicit(BTreeMap<String, String>),
+}
+
+impl CommandEnv {
+    /// Set up `cmd` to run in this environment.
+    pub(crate) fn apply(&self, cmd: &mut process::Command) {
+        match self {
+            CommandEnv::Inherit => {}
+            CommandEnv::Allowlist(names) => {
+                let vars = std::env::vars_os().filter(|(name, _)| {
+                    names
+                        .iter()
+                        .any(|allowed| name.to_str() == Some(allowed.as_str()))
+                });
+                cmd.env_clear().envs(vars);
+            }
+            CommandEnv::Explicit(vars) => {
+                cmd.env_clear().envs(vars);
             }
         }
     }
-    Ok(artifacts)
+
+    /// The value of the variable `name` in this environment, if set.
+    fn var(&self, name: &str) -> Option<String> {
+        match self {
+            CommandEnv::Inherit => std::env::var(name).ok(),
+            CommandEnv::Allowlist(names) if names.iter().any(|allowed| allowed == name) => {
+                std::env::var(name).ok()
+            }
+            CommandEnv::Allowlist(_) => None,
+            CommandEnv::Explicit(vars) => vars.get(name).cloned(),
+        }
+    }
+}
+
+/// Options for running the command of a step, see `run_command_with_options`.
+///
+/// # Examples
+///
+/// ```
+/// # use in_toto::runlib::{run_command_with_options, OutputMode, RunOptions};
+/// let options = RunOptions::new()
+///     .run_dir("tests")
+///     .output(OutputMode::CaptureOnly);
+/// let byproducts = run_command_with_options(&["sh", "-c", "printf hello"], &options).unwrap();
+/// assert_eq!(byproducts.stdout(), "hello");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    pub(crate) run_dir: Option<String>,
+    pub(crate) record_in_run_dir: bool,
+    pub(crate) output: OutputMode,
+    pub(crate) command_env: CommandEnv,
+    pub(crate) exit_policy: ExitPolicy,
+    pub(crate) timestamps: bool,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) record: RecordOptions,
+    pub(crate) artifact_times: bool,
+    pub(crate) toolchain_digests: bool,
+    pub(crate) record_workdir: bool,
+    pub(crate) record_variables: Vec<String>,
+    pub(crate) attempt: Option<Attempt>,
+    pub(crate) signer_id: Option<String>,
+}
+
+impl RunOptions {
+    /// Run in the current directory, teeing the output.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the command in `run_dir`
+    pub fn run_dir(mut self, run_dir: &str) -> Self {
+        self.run_dir = Some(run_dir.to_string());
+        self
+    }
+
+    /// Find relative materials and products in the run directory rather than
+    /// the current one, recording them relative to it, as if the step ran in
+    /// a checkout with the checkout as its current directory. A `base_path`
+    /// of the record options takes precedence.
+    pub fn record_in_run_dir(mut self, record_in_run_dir: bool) -> Self {
+        self.record_in_run_dir = record_in_run_dir;
+        self
+    }
+
+    /// Echo and record the output of the command as `output` says
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Run the command in the environment `command_env` says, by default the
+    /// one of this process
+    pub fn command_env(mut self, command_env: CommandEnv) -> Self {
+        self.command_env = command_env;
+        self
+    }
+
+    /// Handle a non-zero return value of the command as `exit_policy` says
+    pub fn exit_policy(mut self, exit_policy: ExitPolicy) -> Self {
+        self.exit_policy = exit_policy;
+        self
+    }
+
+    /// Record when the command started and ended in the byproducts
+    /// `START_TIME_BYPRODUCT` and `END_TIME_BYPRODUCT`, as RFC 3339 UTC
+    /// timestamps
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// Kill the command if it still runs at `deadline`, failing with
+    /// `Error::Timeout`
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Kill the command if it still runs after `timeout`, along with the
+    /// processes it started, being its process group on Unix. Unlike at a
+    /// deadline, the byproducts are recorded, with the return value of the
+    /// killed command and the timeout as `TIMEOUT_BYPRODUCT`, so the exit
+    /// policy decides whether the step fails.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Record materials and products as `record` says. The hash algorithms
+    /// and prefixes given to `in_toto_run_with_options`, if any, take
+    /// precedence.
+    pub fn record(mut self, record: RecordOptions) -> Self {
+        self.record = record;
+        self
+    }
+
+    /// Record the `SOURCE_DATE_EPOCH` of the step and the modification times
+    /// of its artifacts as the byproduct
+    /// `models::times::ARTIFACT_TIMES_BYPRODUCT`, see `ArtifactTimes`
+    pub fn artifact_times(mut self, artifact_times: bool) -> Self {
+        self.artifact_times = artifact_times;
+        self
+    }
+
+    /// Record the digests of the toolchain files in the run directory in
+    /// the environment of the link, see `models::toolchain`
+    pub fn toolchain_digests(mut self, toolchain_digests: bool) -> Self {
+        self.toolchain_digests = toolchain_digests;
+        self
+    }
+
+    /// Record the absolute path of the run directory in the environment of
+    /// the link, as `WORKDIR_ENV`
+    pub fn record_workdir(mut self, record_workdir: bool) -> Self {
+        self.record_workdir = record_workdir;
+        self
+    }
+
+    /// Record the values of the variables `names` in the environment the
+    /// command runs in, those set, in the environment of the link, each
+    /// keyed by its name prefixed with `VARIABLE_ENV_PREFIX`. Only name
+    /// variables safe to publish, the link is no place for secrets.
+    pub fn record_variables(mut self, names: &[&str]) -> Self {
+        self.record_variables = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Mark the link as recorded in `attempt` of the step, as the byproduct
+    /// `models::attempt::ATTEMPT_BYPRODUCT`, superseding the links of
+    /// earlier attempts
+    pub fn attempt(mut self, attempt: Attempt) -> Self {
+        self.attempt = Some(attempt);
+        self
+    }
+
+    /// Annotate the signature of the link with who made it, e.g. `alice@corp
+    /// / job 1234`, see `Signature::with_signer_id`
+    pub fn signer_id(mut self, signer_id: &str) -> Self {
+        self.signer_id = Some(signer_id.to_string());
+        self
+    }
+
+    /// The environment of the link: the toolchain digests, run directory
+    /// and variables recorded, or `None` if nothing is.
+    fn link_env(&self) -> Result<Option<BTreeMap<String, String>>> {
+        let run_dir = Path::new(self.run_dir.as_deref().unwrap_or("."));
+        let mut env = match self.toolchain_digests {
+            true => toolchain_digests(run_dir)?,
+            false => BTreeMap::new(),
+        };
+        if self.record_workdir {
+            let workdir = canonicalize_path(run_dir)?;
+            env.insert(
+                WORKDIR_ENV.to_string(),
+                workdir.to_string_lossy().replace('\\', "/"),
+            );
+        }
+        for name in &self.record_variables {
+            if let Some(value) = self.command_env.var(name) {
+                env.insert(format!("{}{}", VARIABLE_ENV_PREFIX, name), value);
+            }
+        }
+        Ok(Some(env).filter(|env| !env.is_empty()))
+    }
+
+    /// The link built by `builder` signed by `key`, with its signer
+    /// annotated, or unsigned for inspection purposes if there is no key.
+    fn sign(&self, builder: LinkMetadataBuilder, key: Option<&PrivateKey>) -> Result<Metablock> {
+        let key = match key {
+            Some(key) => key,
+            None => return builder.unsigned::<Json>(),
+        };
+        let link = builder.signed::<Json>(key)?;
+        match &self.signer_id {
+            Some(signer_id) => link.with_signer_id(key.key_id(), signer_id),
+            None => Ok(link),
+        }
+    }
+
+    /// `byproducts` with the times the command ran, if they are recorded.
+    pub(crate) fn stamp(
+        &self,
+        byproducts: ByProducts,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> ByProducts {
+        if !self.timestamps {
+            return byproducts;
+        }
+        let format = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Micros, true);
+        byproducts
+            .set_other_field(START_TIME_BYPRODUCT.into(), format(start))
+            .set_other_field(END_TIME_BYPRODUCT.into(), format(end))
+    }
+
+    /// The byproducts of a command, or an error if the exit policy says so.
+    pub(crate) fn check_exit(&self, byproducts: ByProducts) -> Result<ByProducts> {
+        match self.exit_policy {
+            ExitPolicy::FailFast if byproducts.return_value() != 0 => {
+                Err(Error::CommandFailed(Box::new(byproducts)))
+            }
+            _ => Ok(byproducts),
+        }
+    }
+}
+
+/// The return value of a command exiting with `status`. A command killed by
+/// a signal has the negated signal as return value, as in the reference
+/// implementation.
+pub(crate) fn return_value(status: process::ExitStatus) -> Result<i32> {
+    if let Some(code) = status.code() {
+        return Ok(code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return Ok(-signal);
+        }
+    }
+    Err(Error::RunLibError(
+        "Process terminated by signal".to_string(),
+    ))
 }
 
 /// Given command arguments, executes commands on a software supply chain step
@@ -174,6 +1515,16 @@ pub fn record_artifacts(
 /// let byproducts = run_command(&["sh", "-c", "printf hello"], Some("tests")).unwrap();
 /// ```
 pub fn run_command(cmd_args: &[&str], run_dir: Option<&str>) -> Result<ByProducts> {
+    let mut options = RunOptions::new();
+    if let Some(dir) = run_dir {
+        options = options.run_dir(dir);
+    }
+    run_command_with_options(cmd_args, &options)
+}
+
+/// Like `run_command`, with the directory to run in, what to do with the
+/// output of the command and with a non-zero return value given by `options`.
+pub fn run_command_with_options(cmd_args: &[&str], options: &RunOptions) -> Result<ByProducts> {
     // Format output into Byproduct
 
     if cmd_args.is_empty() {
@@ -200,15 +1551,23 @@ pub fn run_command(cmd_args: &[&str], run_dir: Option<&str>) -> Result<ByProduct
 
     // TODO: Validate executable
 
-    let mut cmd = Command::new(executable);
+    let mut cmd = process::Command::new(executable);
     let mut cmd = cmd.args(args);
 
-    if let Some(dir) = run_dir {
+    if let Some(dir) = &options.run_dir {
         cmd = cmd.current_dir(dir)
     }
+    options.command_env.apply(cmd);
+    #[cfg(unix)]
+    if options.timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        cmd = cmd.process_group(0);
+    }
 
-    let output = match cmd.output() {
-        Ok(out) => out,
+    let start = Utc::now();
+    let timeout_at = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut child = match OutputReaders::configure(cmd, options.output).spawn() {
+        Ok(child) => child,
         Err(err) => {
             return Err(Error::IllegalArgument(format!(
                 "Something went wrong with run_command inside in_toto_run. Error: {:?}",
@@ -216,41 +1575,156 @@ pub fn run_command(cmd_args: &[&str], run_dir: Option<&str>) -> Result<ByProduct
             )))
         }
     };
-
-    // Emit stdout, stderror
-    io::stdout().write_all(&output.stdout)?;
-    io::stderr().write_all(&output.stderr)?;
-
-    // Write to byproducts
-    let stdout = match String::from_utf8(output.stdout) {
-        Ok(output) => output,
-        Err(error) => {
-            return Err(Error::from(io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Utf8Error: {}", error),
-            )))
-        }
+    let readers = OutputReaders::start(&mut child, options.output);
+    let kill_at = match (options.deadline, timeout_at) {
+        (Some(deadline), Some(timeout_at)) => Some(deadline.min(timeout_at)),
+        (deadline, timeout_at) => deadline.or(timeout_at),
     };
-    let stderr = match String::from_utf8(output.stderr) {
-        Ok(output) => output,
-        Err(error) => {
-            return Err(Error::from(io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Utf8Error: {}", error),
-            )))
-        }
+    let mut timed_out = None;
+    let status = match kill_at {
+        Some(kill_at) => match wait_until(&mut child, kill_at, options.timeout.is_some())? {
+            (status, false) => status,
+            (status, true) if timeout_at == Some(kill_at) => {
+                timed_out = options.timeout;
+                status
+            }
+            // processes started by the command may still hold its output
+            // open, so the output is not waited for
+            (_, true) => {
+                return Err(Error::Timeout(format!(
+                    "command {} was killed at its deadline",
+                    executable
+                )));
+            }
+        },
+        None => child.wait()?,
     };
-    let status = output
-        .status
-        .code()
-        .ok_or_else(|| Error::RunLibError("Process terminated by signal".to_string()))?;
+    let end = Utc::now();
+    let (stdout, stderr) = readers.finish()?;
 
-    let byproducts = ByProducts::new()
+    let mut byproducts = ByProducts::new()
         .set_stdout(stdout)
         .set_stderr(stderr)
-        .set_return_value(status);
+        .set_return_value(return_value(status)?);
+    if let Some(timeout) = timed_out {
+        warn!(
+            "Command {} was killed after {}s",
+            executable,
+            timeout.as_secs_f64()
+        );
+        byproducts =
+            byproducts.set_other_value(TIMEOUT_BYPRODUCT.into(), json!(timeout.as_secs_f64()));
+    }
+
+    options.check_exit(options.stamp(byproducts, start, end))
+}
+
+/// Wait for `child` to exit, killing it at `deadline`, with its process
+/// group if `group`. Returns the exit status and whether it was killed.
+fn wait_until(
+    child: &mut process::Child,
+    deadline: Instant,
+    group: bool,
+) -> Result<(process::ExitStatus, bool)> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, false));
+        }
+        if Instant::now() >= deadline {
+            kill(child, group)?;
+            return Ok((child.wait()?, true));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Kill `child`, and the other processes of its process group if `group`.
+fn kill(child: &mut process::Child, group: bool) -> Result<()> {
+    #[cfg(unix)]
+    if group {
+        use std::convert::TryFrom;
+        // the child leads its own process group, see `RunOptions::timeout`
+        let pgid = libc::pid_t::try_from(child.id())
+            .map_err(|e| Error::RunLibError(format!("process ID {}: {}", child.id(), e)))?;
+        // SAFETY: kill has no memory effects, and the group is that of the
+        // child, which was not waited for yet
+        if unsafe { libc::kill(-pgid, libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+    }
+    Ok(child.kill()?)
+}
+
+/// Threads reading the output of a child, echoing it as an `OutputMode` says.
+pub(crate) struct OutputReaders {
+    stdout: Option<thread::JoinHandle<io::Result<Vec<u8>>>>,
+    stderr: Option<thread::JoinHandle<io::Result<Vec<u8>>>>,
+}
+
+impl OutputReaders {
+    /// Set up the stdio of `cmd` for `mode`.
+    pub(crate) fn configure(cmd: &mut process::Command, mode: OutputMode) -> &mut process::Command {
+        let output = || match mode {
+            OutputMode::Quiet => Stdio::null(),
+            OutputMode::Tee | OutputMode::CaptureOnly => Stdio::piped(),
+        };
+        cmd.stdin(Stdio::null()).stdout(output()).stderr(output())
+    }
+
+    /// Start reading the output of `child`, spawned from a command set up
+    /// by `configure`.
+    pub(crate) fn start(child: &mut process::Child, mode: OutputMode) -> Self {
+        let tee = mode == OutputMode::Tee;
+        OutputReaders {
+            stdout: child
+                .stdout
+                .take()
+                .map(|pipe| Self::read(pipe, tee.then(io::stdout))),
+            stderr: child
+                .stderr
+                .take()
+                .map(|pipe| Self::read(pipe, tee.then(io::stderr))),
+        }
+    }
+
+    fn read<R, W>(mut pipe: R, mut echo: Option<W>) -> thread::JoinHandle<io::Result<Vec<u8>>>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let mut buf = [0; 8192];
+            loop {
+                let read = match pipe.read(&mut buf) {
+                    Ok(0) => return Ok(output),
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                if let Some(echo) = &mut echo {
+                    echo.write_all(&buf[..read])?;
+                    echo.flush()?;
+                }
+                output.extend_from_slice(&buf[..read]);
+            }
+        })
+    }
 
-    Ok(byproducts)
+    /// Wait for the output to end, returning stdout and stderr.
+    pub(crate) fn finish(self) -> Result<(String, String)> {
+        let join = |reader: Option<thread::JoinHandle<io::Result<Vec<u8>>>>| -> Result<String> {
+            let output = match reader {
+                Some(reader) => reader
+                    .join()
+                    .map_err(|_| io::Error::other("output reader panicked"))??,
+                None => Vec::new(),
+            };
+            String::from_utf8(output)
+                .map_err(|error| Error::from(io::Error::other(format!("Utf8Error: {}", error))))
+        };
+        Ok((join(self.stdout)?, join(self.stderr)?))
+    }
 }
 
 // TODO: implement default trait for in_toto_run's parameters
@@ -276,7 +1750,7 @@ pub fn run_command(cmd_args: &[&str], run_dir: Option<&str>) -> Result<ByProduct
 /// // If you pass --test to `rustdoc`, it will even test it for you!
 /// # use in_toto::runlib::{in_toto_run};
 /// # use in_toto::crypto::PrivateKey;
-/// const ED25519_1_PRIVATE_KEY: &'static [u8] = include_bytes!("../tests/ed25519/ed25519-1");
+/// const ED25519_1_PRIVATE_KEY: &[u8] = include_bytes!("../tests/ed25519/ed25519-1");
 /// let key = PrivateKey::from_ed25519(ED25519_1_PRIVATE_KEY).unwrap();
 /// let link = in_toto_run("example", Some("tests"), &["tests/test_runlib"], &["tests/test_runlib"],  &["sh", "-c", "echo 'in_toto says hi' >> hello_intoto"], Some(&key), Some(&["sha512", "sha256"]), Some(&["tests/test_runlib/"])).unwrap();
 /// let json = serde_json::to_value(&link).unwrap();
@@ -284,54 +1758,405 @@ pub fn run_command(cmd_args: &[&str], run_dir: Option<&str>) -> Result<ByProduct
 /// ```
 pub fn in_toto_run(
     name: &str,
-    run_dir: Option<&str>,
-    material_paths: &[&str],
-    product_paths: &[&str],
+    run_dir: Option<&str>,
+    material_paths: &[&str],
+    product_paths: &[&str],
+    cmd_args: &[&str],
+    key: Option<&PrivateKey>,
+    hash_algorithms: Option<&[&str]>,
+    lstrip_paths: Option<&[&str]>,
+    // env: Option<BTreeMap<String, String>>
+) -> Result<Metablock> {
+    let mut options = RunOptions::new();
+    if let Some(dir) = run_dir {
+        options = options.run_dir(dir);
+    }
+    in_toto_run_with_options(
+        name,
+        material_paths,
+        product_paths,
+        cmd_args,
+        key,
+        hash_algorithms,
+        lstrip_paths,
+        &options,
+    )
+}
+
+/// Like `in_toto_run`, with the command run as `options` say, see
+/// `run_command_with_options`.
+pub fn in_toto_run_with_options(
+    name: &str,
+    material_paths: &[&str],
+    product_paths: &[&str],
+    cmd_args: &[&str],
+    key: Option<&PrivateKey>,
+    hash_algorithms: Option<&[&str]>,
+    lstrip_paths: Option<&[&str]>,
+    options: &RunOptions,
+) -> Result<Metablock> {
+    let (link, _stats) = in_toto_run_with_stats(
+        name,
+        material_paths,
+        product_paths,
+        cmd_args,
+        key,
+        hash_algorithms,
+        lstrip_paths,
+        options,
+    )?;
+    Ok(link)
+}
+
+/// Like `in_toto_run_with_options`, returning the statistics of the run
+/// with the link, see `stats`.
+pub fn in_toto_run_with_stats(
+    name: &str,
+    material_paths: &[&str],
+    product_paths: &[&str],
+    cmd_args: &[&str],
+    key: Option<&PrivateKey>,
+    hash_algorithms: Option<&[&str]>,
+    lstrip_paths: Option<&[&str]>,
+    options: &RunOptions,
+) -> Result<(Metablock, RunStats)> {
+    let mut stats = RunStats::default();
+    let mut record_options = options
+        .record
+        .with_arguments(hash_algorithms, lstrip_paths)?;
+    if let (true, Some(run_dir), None) = (
+        options.record_in_run_dir,
+        &options.run_dir,
+        &record_options.base_path,
+    ) {
+        record_options = record_options.base_path(run_dir);
+    }
+
+    let mut artifacts = StepArtifacts::new(&record_options, options)?;
+
+    // Record Materials: Given the material_paths, recursively traverse and record files in given path(s)
+    let materials = artifacts.materials(material_paths, Some(&mut stats.materials))?;
+
+    // Execute commands provided in cmd_args
+    let start = Instant::now();
+    let byproducts = run_command_with_options(cmd_args, options)?;
+    stats.command = start.elapsed();
+
+    // Record Products: Given the product_paths, recursively traverse and record files in given path(s),
+    // and sign the link with key param supplied. If no key is found, return Metablock with
+    // no signatures (for inspection purposes)
+    let product_stats = &mut stats.products;
+    let link = step_link(
+        name,
+        cmd_args,
+        materials,
+        |artifacts| artifacts.products(product_paths, Some(product_stats)),
+        byproducts,
+        artifacts,
+        key,
+        options,
+    )?;
+    Ok((link, stats))
+}
+
+/// The artifacts of a step recorded as `record` says, collecting their
+/// times if `RunOptions::artifact_times` says so and those skipped, to be
+/// kept in the byproducts of its link.
+struct StepArtifacts<'a> {
+    record: &'a RecordOptions,
+    times: Option<ArtifactTimes>,
+    skipped: SkippedArtifacts,
+}
+
+impl<'a> StepArtifacts<'a> {
+    fn new(record: &'a RecordOptions, options: &RunOptions) -> Result<Self> {
+        let times = match options.artifact_times {
+            true => Some(ArtifactTimes::from_env()?),
+            false => None,
+        };
+        Ok(StepArtifacts {
+            record,
+            times,
+            skipped: SkippedArtifacts::new(),
+        })
+    }
+
+    /// Record the materials in `paths`.
+    fn materials(
+        &mut self,
+        paths: &[&str],
+        stats: Option<&mut RecordStats>,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let no_resolvers = ResolverRegistry::new();
+        let resolvers = self.record.resolvers.as_deref().unwrap_or(&no_resolvers);
+        record(
+            paths,
+            self.record,
+            resolvers,
+            self.times.as_mut().map(ArtifactTimes::materials_mut),
+            Some(self.skipped.materials_mut()),
+            stats,
+        )
+    }
+
+    /// Record the products in `paths`.
+    fn products(
+        &mut self,
+        paths: &[&str],
+        stats: Option<&mut RecordStats>,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let no_resolvers = ResolverRegistry::new();
+        let resolvers = self.record.resolvers.as_deref().unwrap_or(&no_resolvers);
+        record(
+            paths,
+            self.record,
+            resolvers,
+            self.times.as_mut().map(ArtifactTimes::products_mut),
+            Some(self.skipped.products_mut()),
+            stats,
+        )
+    }
+
+    /// `byproducts` with the times and skipped artifacts recorded.
+    fn to_byproducts(&self, mut byproducts: ByProducts) -> Result<ByProducts> {
+        if let Some(times) = &self.times {
+            byproducts = times.to_byproducts(byproducts)?;
+        }
+        if !self.skipped.is_empty() {
+            byproducts = self.skipped.to_byproducts(byproducts)?;
+        }
+        Ok(byproducts)
+    }
+}
+
+/// The link of step `name`, which ran `cmd_args`, with the `materials`
+/// recorded as `artifacts`, the products `record_products` records with
+/// them and the `byproducts` of the command, stamped as `options` say and
+/// signed by `key`, or unsigned without one. The products are recorded in
+/// a thread of their own while the byproducts are stamped and the
+/// environment of the link, e.g. its toolchain digests, is collected, as
+/// neither waits for the other.
+fn step_link(
+    name: &str,
+    cmd_args: &[&str],
+    materials: BTreeMap<VirtualTargetPath, TargetDescription>,
+    record_products: impl FnOnce(&mut StepArtifacts) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>>
+        + Send,
+    byproducts: ByProducts,
+    mut artifacts: StepArtifacts,
+    key: Option<&PrivateKey>,
+    options: &RunOptions,
+) -> Result<Metablock> {
+    let (products, byproducts, env) = thread::scope(|scope| {
+        let products = scope.spawn(|| record_products(&mut artifacts));
+        let byproducts = match options.attempt {
+            Some(attempt) => attempt.to_byproducts(byproducts),
+            None => Ok(byproducts),
+        };
+        let env = options.link_env();
+        let products = products
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (products, byproducts, env)
+    });
+    let products = products?;
+    let byproducts = artifacts.to_byproducts(byproducts?)?;
+
+    // Create link based on values collected above
+    let mut link_metadata_builder = LinkMetadataBuilder::new()
+        .name(name.to_string())
+        .materials(materials)
+        .byproducts(byproducts)
+        .products(products);
+    if !cmd_args.is_empty() {
+        link_metadata_builder =
+            link_metadata_builder.command(Command::new(cmd_args.iter().copied()));
+    }
+    if let Some(env) = env? {
+        link_metadata_builder = link_metadata_builder.env(Some(env));
+    }
+    options.sign(link_metadata_builder, key)
+}
+
+/// Creates the link of step `name` from artifacts hashed elsewhere, e.g. by a
+/// remote build service, without touching the filesystem: nothing is run,
+/// read or hashed. `cmd_args` is the command the step ran, recorded as is.
+///
+/// Each artifact needs a digest, and the digests of known algorithms have
+/// to be of their length, so the link can be verified like a recorded one.
+/// Paths are recorded with forward slashes, as `record_artifacts` does.
+pub fn in_toto_link(
+    name: &str,
+    materials: BTreeMap<VirtualTargetPath, TargetDescription>,
+    products: BTreeMap<VirtualTargetPath, TargetDescription>,
+    cmd_args: &[&str],
+    byproducts: ByProducts,
+    key: Option<&PrivateKey>,
+) -> Result<Metablock> {
+    in_toto_link_with_options(
+        name,
+        materials,
+        products,
+        cmd_args,
+        byproducts,
+        key,
+        &RunOptions::new(),
+    )
+}
+
+/// Like `in_toto_link`, signing and stamping the link as `options` say, e.g.
+/// with its `signer_id` and `attempt`. Options of running and recording do
+/// not apply.
+pub fn in_toto_link_with_options(
+    name: &str,
+    materials: BTreeMap<VirtualTargetPath, TargetDescription>,
+    products: BTreeMap<VirtualTargetPath, TargetDescription>,
+    cmd_args: &[&str],
+    byproducts: ByProducts,
+    key: Option<&PrivateKey>,
+    options: &RunOptions,
+) -> Result<Metablock> {
+    let check = |artifacts: BTreeMap<VirtualTargetPath, TargetDescription>| {
+        artifacts
+            .into_iter()
+            .map(|(path, hashes)| {
+                check_digests(&path, &hashes)?;
+                Ok((path.with_forward_slashes(), hashes))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()
+    };
+    let mut byproducts = byproducts;
+    if let Some(attempt) = options.attempt {
+        byproducts = attempt.to_byproducts(byproducts)?;
+    }
+    let mut builder = LinkMetadataBuilder::new()
+        .name(name.to_string())
+        .materials(check(materials)?)
+        .products(check(products)?)
+        .byproducts(byproducts);
+    if !cmd_args.is_empty() {
+        builder = builder.command(Command::new(cmd_args.iter().copied()));
+    }
+    options.sign(builder, key)
+}
+
+/// Fails unless `path` has a digest, and those of known algorithms are of
+/// their length.
+fn check_digests(path: &VirtualTargetPath, hashes: &TargetDescription) -> Result<()> {
+    if hashes.is_empty() {
+        return Err(Error::IllegalArgument(format!(
+            "artifact {} has no digests",
+            path
+        )));
+    }
+    for (algorithm, value) in hashes {
+        let len = match algorithm {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+            HashAlgorithm::Unknown(_) => continue,
+        };
+        if value.value().len() != len {
+            return Err(Error::IllegalArgument(format!(
+                "{} digest of artifact {} is {} bytes, not {}",
+                algorithm.name(),
+                path,
+                value.value().len(),
+                len
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Like `in_toto_run_with_options`, but instead of given paths, the regular
+/// files below the run directory the command opened are recorded, see `tracer::trace_command`.
+/// Files it only read are its materials, files it wrote its products. The
+/// artifacts are hashed after the command ran, so files it both read and
+/// wrote are only recorded as products, and paths are relative to the run
+/// directory. Otherwise they are recorded as the record options of `options`
+/// say, as the paths given to `in_toto_run_with_options` would be.
+/// The network destinations it contacted are kept in the byproduct
+/// `models::network::NETWORK_BYPRODUCT`.
+#[cfg(all(feature = "tracer", target_os = "linux", target_arch = "x86_64"))]
+pub fn in_toto_run_traced(
+    name: &str,
     cmd_args: &[&str],
     key: Option<&PrivateKey>,
     hash_algorithms: Option<&[&str]>,
     lstrip_paths: Option<&[&str]>,
-    // env: Option<BTreeMap<String, String>>
+    options: &RunOptions,
 ) -> Result<Metablock> {
-    // Record Materials: Given the material_paths, recursively traverse and record files in given path(s)
-    let materials = record_artifacts(material_paths, hash_algorithms, lstrip_paths)?;
+    let mut record_options = options
+        .record
+        .with_arguments(hash_algorithms, lstrip_paths)?;
+    let (byproducts, accesses) = crate::tracer::trace_command(cmd_args, options)?;
+    let root = canonicalize_path(options.run_dir.as_deref().unwrap_or("."))?;
+    // The accessed files are found in the run directory, by their names
+    // rather than as patterns.
+    match root.to_str() {
+        Some(root) => record_options = record_options.base_path(root),
+        None => return Err(Error::NonUtf8Path(root)),
+    }
+    record_options.expand_globs = false;
 
-    // Execute commands provided in cmd_args
-    let byproducts = run_command(cmd_args, run_dir)?;
+    let names = |paths: Vec<PathBuf>| -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for path in paths {
+            if let Some(name) = path_string(&path, record_options.non_utf8_paths)? {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    };
+    let material_names = names(accesses.materials_under(&root))?;
+    let product_names = names(accesses.products_under(&root))?;
+    let material_paths: Vec<&str> = material_names.iter().map(String::as_str).collect();
+    let product_paths: Vec<&str> = product_names.iter().map(String::as_str).collect();
 
-    // Record Products: Given the product_paths, recursively traverse and record files in given path(s)
-    let products = record_artifacts(product_paths, hash_algorithms, lstrip_paths)?;
+    let mut artifacts = StepArtifacts::new(&record_options, options)?;
+    let materials = artifacts.materials(&material_paths, None)?;
+    step_link(
+        name,
+        cmd_args,
+        materials,
+        |artifacts| artifacts.products(&product_paths, None),
+        byproducts,
+        artifacts,
+        key,
+        options,
+    )
+}
 
-    // Create link based on values collected above
-    let link_metadata_builder = LinkMetadataBuilder::new()
-        .name(name.to_string())
-        .materials(materials)
-        .byproducts(byproducts)
-        .products(products);
+/// Whether the path `path` has characters of glob patterns.
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
 
-    // Sign the link with key param supplied. If no key is found, return Metablock with
-    // no signatures (for inspection purposes)
-    match key {
-        Some(k) => link_metadata_builder.signed::<Json>(k),
-        None => link_metadata_builder.unsigned::<Json>(),
+/// Whether the file or directory at `path` is hidden: its name starts with
+/// `.`, or it has the hidden attribute on Windows.
+fn is_hidden(path: &Path) -> bool {
+    let dotted = path
+        .file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        let attribute = symlink_metadata(path)
+            .is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0);
+        dotted || attribute
     }
+    #[cfg(not(windows))]
+    dotted
 }
 
-/// A private helper function that, given a `DirEntry`, return the entry's path as a `String`
-/// wrapped in `Result`. If the entry's path is invalid, `Error` is returned.
+/// A private helper function that, given a `DirEntry`, return the entry's path wrapped in
+/// `Result`. If the walk failed at the entry, `Error` is returned.
 fn dir_entry_to_path(
     entry: std::result::Result<walkdir::DirEntry, walkdir::Error>,
-) -> Result<String> {
+) -> Result<PathBuf> {
     let path = match entry {
-        Ok(dir_entry) => match dir_entry.path().to_str() {
-            Some(str) => String::from(str),
-            None => {
-                return Err(Error::IllegalArgument(format!(
-                    "Invalid Path {}; non-UTF-8 string",
-                    dir_entry.path().display()
-                )))
-            }
-        },
+        Ok(dir_entry) => dir_entry.into_path(),
         // If WalkDir errored, check if it's due to a symbolic link loop sighted,
         // if so, override the error and continue using the symbolic link path.
         // If this doesn't work, something hacky to consider would be reinvoking WalkDir
@@ -342,42 +2167,53 @@ fn dir_entry_to_path(
         // If this is not the desired behavior and we want to record the symbolic link's content
         // , we can probably do it in a hacky way by recursively calling record_artifacts and
         // extending the results to artifacts variable.
+        // Dangling symbolic links are passed on the same way, to be skipped.
         Err(error) => {
-            if error.loop_ancestor().is_some() {
+            let dangling = error.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound)
+                && error
+                    .path()
+                    .is_some_and(|path| symlink_metadata(path).is_ok_and(|m| m.is_symlink()));
+            if error.loop_ancestor().is_some() || dangling {
                 match error.path() {
                     None => {
-                        return Err(Error::from(io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("Walkdir Error: {}", error),
-                        )))
-                    }
-                    Some(error_path) => {
-                        let sym_path = match error_path.to_str() {
-                            Some(str) => String::from(str),
-                            None => {
-                                return Err(Error::IllegalArgument(format!(
-                                    "Invalid Path {}; non-UTF-8 string",
-                                    error_path.display()
-                                )))
-                            }
-                        };
-                        // TODO: Emit a warning that a symlink cycle is detected and it will be skipped
-                        // Add it to the link itself
-                        sym_path
+                        return Err(Error::from(io::Error::other(format!(
+                            "Walkdir Error: {}",
+                            error
+                        ))))
                     }
+                    // TODO: Emit a warning that a symlink cycle is detected and it will be skipped
+                    // Add it to the link itself
+                    Some(error_path) => error_path.to_path_buf(),
                 }
             } else {
-                return Err(Error::from(io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Walkdir Error: {}", error),
-                )));
+                return Err(Error::from(io::Error::other(format!(
+                    "Walkdir Error: {}",
+                    error
+                ))));
             }
         }
     };
-    Ok(clean(&path))
+    Ok(path)
+}
+
+/// `path` as a string, or what `policy` says if it is not valid UTF-8:
+/// `None` to skip it, a lossy conversion or `Error::NonUtf8Path`.
+fn path_string(path: &Path, policy: NonUtf8Policy) -> Result<Option<String>> {
+    if let Some(path) = path.to_str() {
+        return Ok(Some(path.to_string()));
+    }
+    match policy {
+        NonUtf8Policy::Error => Err(Error::NonUtf8Path(path.to_path_buf())),
+        NonUtf8Policy::Skip => {
+            warn!("Skipping non-UTF-8 path {}", path.display());
+            Ok(None)
+        }
+        NonUtf8Policy::Lossy => Ok(Some(path.to_string_lossy().into_owned())),
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use data_encoding::HEXLOWER;
     use std::collections::HashMap;
@@ -431,6 +2267,108 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_record_uri_artifacts() {
+        assert_eq!(
+            record_artifacts(&["file:tests/test_runlib"], None, None).unwrap(),
+            record_artifacts(&["tests/test_runlib"], None, None).unwrap()
+        );
+        assert!(
+            record_artifacts(&["git+https://github.com/in-toto/in-toto-rs"], None, None).is_err()
+        );
+
+        let hashes = create_target_description(
+            crypto::HashAlgorithm::Sha256,
+            b"25623b53e0984428da972f4c635706d32d01ec92dcd2ab39066082e0b9488c9d",
+        );
+        let (path, recorded) =
+            record_resource_artifact("pkg:pypi/in-toto@1.0.0", hashes.clone()).unwrap();
+        assert_eq!(path.scheme(), Some("pkg"));
+        assert_eq!(recorded, hashes);
+        assert!(record_resource_artifact("tests/test_runlib", hashes.clone()).is_err());
+        assert!(record_resource_artifact("pkg:pypi/in-toto@1.0.0", HashMap::new()).is_err());
+
+        let mut resolvers = ResolverRegistry::new();
+        resolvers
+            .register("pkg", move |_: &_, _: &_| Ok(hashes.clone()))
+            .unwrap();
+        let artifacts = record_artifacts_with_resolvers(
+            &["tests/test_runlib/hello./world", "pkg:pypi/in-toto@1.0.0"],
+            None,
+            None,
+            &resolvers,
+        )
+        .unwrap();
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(
+            artifacts[&VirtualTargetPath::new("pkg:pypi/in-toto@1.0.0".into()).unwrap()],
+            artifacts[&VirtualTargetPath::new("tests/test_runlib/hello./world".into()).unwrap()]
+        );
+
+        // steps hash them with the resolvers of their record options
+        let options = RunOptions::new().record(RecordOptions::new().resolvers(Arc::new(resolvers)));
+        let link = in_toto_run_with_options(
+            "test",
+            &["pkg:pypi/in-toto@1.0.0"],
+            &[],
+            &["true"],
+            None,
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let link = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            link.materials()
+                .keys()
+                .map(|path| path.value())
+                .collect::<Vec<_>>(),
+            ["pkg:pypi/in-toto@1.0.0"]
+        );
+        assert!(in_toto_run_with_options(
+            "test",
+            &["pkg:pypi/in-toto@1.0.0"],
+            &[],
+            &["true"],
+            None,
+            None,
+            None,
+            &RunOptions::new(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_record_files_with_colons() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["a:b.txt", "notes:draft.txt"] {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        let options = RecordOptions::new().base_path(dir.path().to_str().unwrap());
+        let artifacts =
+            record_artifacts_with_options(&["a:b.txt", "notes:draft.txt"], &options).unwrap();
+        let names: Vec<_> = artifacts.keys().map(VirtualTargetPath::value).collect();
+        assert_eq!(names, ["a:b.txt", "notes:draft.txt"]);
+
+        // unless a resolver is registered for the scheme
+        let hashes = create_target_description(
+            crypto::HashAlgorithm::Sha256,
+            b"25623b53e0984428da972f4c635706d32d01ec92dcd2ab39066082e0b9488c9d",
+        );
+        let resolved = hashes.clone();
+        let mut resolvers = ResolverRegistry::new();
+        resolvers
+            .register("notes", move |_: &_, _: &_| Ok(resolved.clone()))
+            .unwrap();
+        let options = options.resolvers(Arc::new(resolvers));
+        let artifacts = record_artifacts_with_options(&["notes:draft.txt"], &options).unwrap();
+        assert_eq!(artifacts.values().collect::<Vec<_>>(), [&hashes]);
+    }
+
     #[test]
     fn test_prefix_record_artifacts() {
         let mut expected: BTreeMap<VirtualTargetPath, TargetDescription> = BTreeMap::new();
@@ -462,6 +2400,680 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_record_artifacts_with_options() {
+        let both = [HashAlgorithm::Sha256, HashAlgorithm::Sha512];
+        let options = RecordOptions::new()
+            .hash_algorithms(&both)
+            .lstrip_paths(&["tests/test_runlib/"]);
+        let artifacts = record_artifacts_with_options(&["tests/test_runlib"], &options).unwrap();
+        let sha256 =
+            record_artifacts(&["tests/test_runlib"], None, Some(&["tests/test_runlib/"])).unwrap();
+        assert_eq!(
+            artifacts.keys().collect::<Vec<_>>(),
+            sha256.keys().collect::<Vec<_>>()
+        );
+        for (path, hashes) in &artifacts {
+            let mut algorithms: Vec<_> = hashes.keys().cloned().collect();
+            algorithms.sort();
+            assert_eq!(algorithms, both);
+            assert_eq!(
+                hashes[&HashAlgorithm::Sha256],
+                sha256[path][&HashAlgorithm::Sha256]
+            );
+        }
+
+        // in_toto_run takes them from the run options, unless given names
+        let options = RunOptions::new().record(RecordOptions::new().hash_algorithms(&both));
+        let material_algorithms = |hash_algorithms: Option<&[&str]>| {
+            let link = in_toto_run_with_options(
+                "test",
+                &["tests/test_runlib"],
+                &[],
+                &[],
+                None,
+                hash_algorithms,
+                None,
+                &options,
+            )
+            .unwrap();
+            match link.metadata() {
+                crate::models::MetadataWrapper::Link(link) => link
+                    .materials()
+                    .values()
+                    .map(|hashes| hashes.len())
+                    .collect::<Vec<_>>(),
+                _ => unreachable!(),
+            }
+        };
+        assert!(material_algorithms(None).iter().all(|n| *n == 2));
+        assert!(material_algorithms(Some(&["sha512"]))
+            .iter()
+            .all(|n| *n == 1));
+    }
+
+    #[test]
+    fn test_record_artifacts_below_base_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for file in ["src/main.rs", "src/main.rs.orig", "README"] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        let options = RecordOptions::new()
+            .base_path(&format!("{}/", root))
+            .exclude_patterns(&["src/*.orig"]);
+        let paths = |paths: &[&str], options: &RecordOptions| {
+            let artifacts = record_artifacts_with_options(paths, options).unwrap();
+            artifacts
+                .keys()
+                .map(|path| path.value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&["src"], &options), ["src/main.rs"]);
+        // as recording `.` in the current directory would
+        assert_eq!(paths(&["."], &options), ["README", "src/main.rs"]);
+        let stripped = options.clone().lstrip_paths(&["src/"]);
+        assert_eq!(paths(&["src"], &stripped), ["main.rs"]);
+
+        // absolute paths are recorded as they are
+        let readme = format!("{}/README", root);
+        assert_eq!(paths(&[&readme], &options), [readme.as_str()]);
+    }
+
+    #[test]
+    fn test_record_artifacts_with_forward_slashes() {
+        // names with backslashes stand in for the paths walked on Windows
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for file in [r"out\app.exe", r"out\app.pdb", "README"] {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        let options = RecordOptions::new()
+            .forward_slashes(true)
+            .exclude_patterns(&[r"out\*.pdb"])
+            .lstrip_paths(&[&format!("{}/", root)]);
+        let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["README", "out/app.exe"]);
+
+        let options = options.forward_slashes(false);
+        let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["README", r"out\app.exe"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_record_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let bad = dir.path().join(OsStr::from_bytes(b"bad\xff"));
+        std::fs::write(&bad, "bad").unwrap();
+        std::fs::write(dir.path().join("good"), "good").unwrap();
+        let options = RecordOptions::new().lstrip_paths(&[&format!("{}/", root)]);
+
+        assert_eq!(
+            record_artifacts_with_options(&[root], &options),
+            Err(Error::NonUtf8Path(bad))
+        );
+        let paths = |policy: NonUtf8Policy| {
+            let options = options.clone().non_utf8_paths(policy);
+            let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+            artifacts
+                .keys()
+                .map(|path| path.value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(NonUtf8Policy::Skip), ["good"]);
+        assert_eq!(paths(NonUtf8Policy::Lossy), ["bad\u{fffd}", "good"]);
+    }
+
+    #[test]
+    fn test_record_artifacts_pipeline() {
+        // more artifacts than may wait to be hashed
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for i in 0..PENDING_ARTIFACTS * 3 {
+            let path = dir.path().join(format!("{}/{}", i % 7, i));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, i.to_string()).unwrap();
+        }
+        let options = RecordOptions::new().lstrip_paths(&[&format!("{}/", root)]);
+        let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+        assert_eq!(artifacts.len(), PENDING_ARTIFACTS * 3);
+        let (_, hashes) = crypto::calculate_hashes(&b"42"[..], &[HashAlgorithm::Sha256]).unwrap();
+        assert_eq!(
+            artifacts[&VirtualTargetPath::new("0/42".into()).unwrap()],
+            hashes
+        );
+
+        // errors of either stage are reported
+        let missing = format!("{}/missing", root);
+        assert!(record_artifacts_with_options(&[root, &missing], &options).is_err());
+        assert!(record_artifacts_with_options(&[root, root], &options).is_err());
+
+        // hashing on several threads records the same, collisions included
+        let parallel = options.clone().hashing_threads(4);
+        assert_eq!(
+            record_artifacts_with_options(&[root], &parallel).unwrap(),
+            artifacts
+        );
+        assert!(record_artifacts_with_options(&[root, &missing], &parallel).is_err());
+        assert!(record_artifacts_with_options(&[root, root], &parallel).is_err());
+    }
+
+    #[test]
+    fn test_record_artifacts_excluding() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for file in [
+            "a.py",
+            "a.pyc",
+            "pkg/b.py",
+            "pkg/b.pyc",
+            "target/debug/app",
+            "pkg/target/keep",
+            ".git/HEAD",
+            "pkg/.git/HEAD",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        let options = RecordOptions::new()
+            .exclude_patterns(&["*.pyc", "target/**", ".git"])
+            .lstrip_paths(&[&format!("{}/", root)]);
+        let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["a.py", "pkg/b.py", "pkg/target/keep"]);
+    }
+
+    #[test]
+    fn test_record_artifacts_hardlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a");
+        std::fs::write(&file, "shared").unwrap();
+        std::fs::hard_link(&file, dir.path().join("b")).unwrap();
+        std::fs::write(dir.path().join("c"), "other").unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert_eq!(hardlink_id(&metadata).is_some(), cfg!(unix));
+        assert_eq!(
+            hardlink_id(&std::fs::metadata(dir.path().join("c")).unwrap()),
+            None
+        );
+
+        let root = dir.path().to_str().unwrap();
+        let options = RecordOptions::new().hashing_threads(2);
+        let artifacts = record_artifacts_with_options(&[root], &options).unwrap();
+        let hashes: Vec<&TargetDescription> = artifacts.values().collect();
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[0], hashes[1]);
+        assert_ne!(hashes[0], hashes[2]);
+    }
+
+    #[test]
+    fn test_record_artifacts_max_file_size() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("small"), "x").unwrap();
+        std::fs::write(dir.path().join("large"), "x".repeat(100)).unwrap();
+        let root = dir.path().to_str().unwrap();
+        let record = |policy| {
+            record_artifacts_with_options(&[root], &RecordOptions::new().max_file_size(10, policy))
+        };
+
+        let artifacts = record(FileSizePolicy::Skip).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, [format!("{}/small", root)]);
+        assert!(record(FileSizePolicy::Error).is_err());
+        assert_eq!(record(FileSizePolicy::Hash).unwrap().len(), 2);
+
+        let cmd = format!("printf 123456789012 > {}/out", root);
+        let link = in_toto_run_with_options(
+            "test",
+            &[root],
+            &[&format!("{}/out", root)],
+            &["sh", "-c", &cmd],
+            None,
+            None,
+            Some(&[&format!("{}/", root)]),
+            &RunOptions::new().record(RecordOptions::new().max_file_size(10, FileSizePolicy::Skip)),
+        )
+        .unwrap();
+        let link = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(link.materials().len(), 1);
+        assert!(link.products().is_empty());
+        let skipped = SkippedArtifacts::from_byproducts(link.byproducts())
+            .unwrap()
+            .unwrap();
+        let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+        assert_eq!(
+            skipped.materials().iter().collect::<Vec<_>>(),
+            [&path("large")]
+        );
+        assert_eq!(
+            skipped.products().iter().collect::<Vec<_>>(),
+            [&path("out")]
+        );
+    }
+
+    #[test]
+    fn test_record_artifacts_file_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), "abc").unwrap();
+        let root = dir.path().to_str().unwrap();
+        let cache = Arc::new(HashCache::new());
+        let options = RecordOptions::new()
+            .hash_cache(cache)
+            .file_fields(&[FileField::ModificationTime]);
+        let record = |options: &RecordOptions| {
+            let artifacts = record_artifacts_with_options(&[root], options).unwrap();
+            artifacts.into_values().next().unwrap()
+        };
+        let hashes = record(&options);
+        assert_eq!(hashes.len(), 2);
+        let modified = modification_time(&std::fs::metadata(dir.path().join("a")).unwrap());
+        assert_eq!(
+            FileMetadata::from_target(&hashes).unwrap().mtime(),
+            Some(modified.unwrap())
+        );
+        assert_eq!(
+            FileMetadata::digests(&hashes),
+            record(&RecordOptions::new())
+        );
+    }
+
+    #[test]
+    fn test_in_toto_run_toolchain_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("rust-toolchain.toml"), "").unwrap();
+        let run = |toolchain_digests| {
+            let options = RunOptions::new()
+                .run_dir(dir.path().to_str().unwrap())
+                .toolchain_digests(toolchain_digests);
+            let link = in_toto_run_with_options("test", &[], &[], &[], None, None, None, &options)
+                .unwrap();
+            match link.metadata() {
+                crate::models::MetadataWrapper::Link(link) => link.clone(),
+                _ => unreachable!(),
+            }
+        };
+        let link = run(true);
+        assert_eq!(
+            crate::models::toolchain::toolchain_digest(&link, "rust-toolchain.toml"),
+            Some("sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        assert_eq!(link.env().as_ref().unwrap().len(), 1);
+        assert_eq!(run(false).env(), &None);
+    }
+
+    #[test]
+    fn test_in_toto_run_record_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let vars = BTreeMap::from([
+            ("CI_JOB".to_string(), "1234".to_string()),
+            ("CI_TOKEN".to_string(), "secret".to_string()),
+        ]);
+        let options = RunOptions::new()
+            .run_dir(dir.path().to_str().unwrap())
+            .command_env(CommandEnv::Explicit(vars))
+            .record_workdir(true)
+            .record_variables(&["CI_JOB", "CI_RUNNER"]);
+        let link =
+            in_toto_run_with_options("test", &[], &[], &["true"], None, None, None, &options)
+                .unwrap();
+        let env = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.env().clone().unwrap(),
+            _ => unreachable!(),
+        };
+        let workdir = canonicalize_path(dir.path()).unwrap();
+        assert_eq!(
+            env,
+            BTreeMap::from([
+                ("variable:CI_JOB".to_string(), "1234".to_string()),
+                (
+                    WORKDIR_ENV.to_string(),
+                    workdir.to_str().unwrap().to_string()
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_in_toto_run_records_products_beside_link_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = format!("{}/out", dir.path().to_str().unwrap());
+        let cmd = format!("printf 1234 > {}", out);
+        let options = RunOptions::new()
+            .record_workdir(true)
+            .attempt(Attempt::new(2).unwrap());
+        let link = in_toto_run_with_options(
+            "test",
+            &[],
+            &[&out],
+            &["sh", "-c", &cmd],
+            None,
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let link = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(link.products().len(), 1);
+        assert!(link.env().as_ref().unwrap().contains_key(WORKDIR_ENV));
+        assert_eq!(Attempt::of(&link).unwrap().number(), 2);
+
+        // products that cannot be recorded fail the run
+        let missing = format!("{}/missing", dir.path().to_str().unwrap());
+        let run = in_toto_run_with_options(
+            "test",
+            &[],
+            &[&missing],
+            &["true"],
+            None,
+            None,
+            None,
+            &options,
+        );
+        assert!(run.is_err());
+    }
+
+    #[test]
+    fn test_in_toto_run_with_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), "abc").unwrap();
+        std::fs::write(dir.path().join("b"), "defgh").unwrap();
+        let root = dir.path().to_str().unwrap();
+        let cmd = format!("printf 1234 > {}/out", root);
+        let options = RunOptions::new().record(RecordOptions::new().hashing_threads(2));
+        let (_, stats) = in_toto_run_with_stats(
+            "test",
+            &[root],
+            &[&format!("{}/out", root)],
+            &["sh", "-c", &cmd],
+            None,
+            Some(&["sha256", "sha512"]),
+            None,
+            &options,
+        )
+        .unwrap();
+        let materials = stats.materials();
+        assert_eq!(materials.artifacts(), 2);
+        assert_eq!(materials.files_hashed(), 2);
+        assert_eq!(materials.bytes_hashed(), 8);
+        assert_eq!(materials.algorithm_times().len(), 2);
+        assert!(materials.algorithm_times()[&HashAlgorithm::Sha512] <= materials.duration() * 2);
+        assert_eq!(stats.products().artifacts(), 1);
+        assert_eq!(stats.products().bytes_hashed(), 4);
+        assert!(stats.command() > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_in_toto_run_record_in_run_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("src"), "abc").unwrap();
+        let options = RunOptions::new()
+            .run_dir(dir.path().to_str().unwrap())
+            .record_in_run_dir(true);
+        let link = in_toto_run_with_options(
+            "build",
+            &["src"],
+            &["out"],
+            &["sh", "-c", "printf 1234 > out"],
+            None,
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let link = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.clone(),
+            _ => unreachable!(),
+        };
+        let names = |artifacts: &BTreeMap<VirtualTargetPath, TargetDescription>| {
+            artifacts
+                .keys()
+                .map(|p| p.value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(link.materials()), ["src"]);
+        assert_eq!(names(link.products()), ["out"]);
+    }
+
+    #[test]
+    fn test_in_toto_link() {
+        let key = crate::test_utils::functionary_key();
+        let hashes = create_target_description(
+            crypto::HashAlgorithm::Sha256,
+            b"25623b53e0984428da972f4c635706d32d01ec92dcd2ab39066082e0b9488c9d",
+        );
+        let artifact = |path: &str, hashes: &TargetDescription| {
+            BTreeMap::from([(VirtualTargetPath::new(path.into()).unwrap(), hashes.clone())])
+        };
+        let options = RunOptions::new().signer_id("ci");
+        let link = in_toto_link_with_options(
+            "build",
+            artifact("pkg:pypi/in-toto@1.0.0", &hashes),
+            artifact(r"dist\app", &hashes),
+            &["make"],
+            ByProducts::new(),
+            Some(&key),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(link.signatures()[0].signer_id(), Some("ci"));
+        let link = match link.metadata() {
+            crate::models::MetadataWrapper::Link(link) => link.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(link.products(), &artifact("dist/app", &hashes));
+        assert_eq!(link.command(), &Command::from("make"));
+
+        let link = |materials| {
+            in_toto_link(
+                "build",
+                materials,
+                BTreeMap::new(),
+                &[],
+                ByProducts::new(),
+                None,
+            )
+        };
+        assert!(link(artifact("src", &TargetDescription::new())).is_err());
+        let short = create_target_description(crypto::HashAlgorithm::Sha256, b"0123");
+        assert!(link(artifact("src", &short)).is_err());
+        let custom = create_target_description(HashAlgorithm::Unknown("gitCommit".into()), b"0123");
+        assert!(link(artifact("src", &custom)).is_ok());
+    }
+
+    #[test]
+    fn test_record_artifacts_io_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            std::fs::write(dir.path().join(i.to_string()), vec![b'x'; i * 100]).unwrap();
+        }
+        let root = dir.path().to_str().unwrap();
+        let options = RecordOptions::new()
+            .hashing_threads(4)
+            .read_size(7)
+            .max_open_files(2)
+            .max_pending_artifacts(1);
+        assert_eq!(options.clone(), options);
+        assert_ne!(options, options.clone().max_open_files(2));
+        assert_eq!(
+            record_artifacts_with_options(&[root], &options).unwrap(),
+            record_artifacts_with_options(&[root], &RecordOptions::new()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_record_artifacts_expand_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("dist")).unwrap();
+        std::fs::create_dir_all(dir.path().join("crates/a")).unwrap();
+        std::fs::create_dir_all(dir.path().join("crates/b")).unwrap();
+        for file in [
+            "dist/a.tar.gz",
+            "dist/b.tar.gz",
+            "dist/.c.tar.gz",
+            "dist/d.zip",
+            "crates/a/Cargo.toml",
+        ] {
+            std::fs::write(dir.path().join(file), file).unwrap();
+        }
+        let root = dir.path().to_str().unwrap();
+        let options = RecordOptions::new().base_path(root).expand_globs(true);
+        let record = |paths: &[&str], options: &RecordOptions| {
+            record_artifacts_with_options(paths, options).map(|artifacts| {
+                let paths: Vec<String> = artifacts.keys().map(|path| path.to_string()).collect();
+                paths
+            })
+        };
+
+        assert_eq!(
+            record(&["dist/*.tar.gz"], &options).unwrap(),
+            ["dist/a.tar.gz", "dist/b.tar.gz"]
+        );
+        assert_eq!(
+            record(&["dist/.*", "crates/*/Cargo.toml"], &options).unwrap(),
+            ["crates/a/Cargo.toml", "dist/.c.tar.gz"]
+        );
+        assert_eq!(record(&["dist/?.zip"], &options).unwrap(), ["dist/d.zip"]);
+        assert!(record(&["dist/*.whl"], &options).is_err());
+        let literal = RecordOptions::new().base_path(root);
+        assert!(record(&["dist/*.tar.gz"], &literal).is_err());
+    }
+
+    #[test]
+    fn test_record_artifacts_empty_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("logs")).unwrap();
+        std::fs::create_dir_all(dir.path().join("src/empty")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "").unwrap();
+        let root = dir.path().to_str().unwrap();
+        let lstrip = format!("{}/", root);
+        let record = |empty_directories| {
+            let options = RecordOptions::new()
+                .lstrip_paths(&[&lstrip])
+                .empty_directories(empty_directories);
+            record_artifacts_with_options(&[root], &options).unwrap()
+        };
+
+        let artifacts = record(true);
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["logs/", "src/empty/", "src/main.rs"]);
+        assert_eq!(
+            artifacts.values().next(),
+            artifacts.values().last(),
+            "empty directories are hashed as empty strings"
+        );
+        assert_eq!(record(false).len(), 1);
+    }
+
+    #[test]
+    fn test_record_artifacts_progress() {
+        #[derive(Default)]
+        struct Events(Mutex<Vec<String>>);
+        impl RecordProgress for Events {
+            fn discovered(&self, path: &str, size: Option<u64>) {
+                let event = format!("found {} of {:?} bytes", path, size);
+                self.0.lock().unwrap().push(event);
+            }
+            fn recorded(&self, path: &str, bytes: u64) {
+                let event = format!("recorded {}, hashing {} bytes", path, bytes);
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), "abc").unwrap();
+        let root = dir.path().to_str().unwrap();
+        let events = Arc::new(Events::default());
+        let options = RecordOptions::new().progress(events.clone());
+        record_artifacts_with_options(&[root], &options).unwrap();
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                format!("found {}/a of Some(3) bytes", root),
+                format!("recorded {}/a, hashing 3 bytes", root)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_artifacts_without_hidden() {
+        let options = RecordOptions::new().include_hidden(false);
+        let artifacts = record_artifacts_with_options(&["tests/test_runlib"], &options).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["tests/test_runlib/hello./world"]);
+
+        // hidden paths given are recorded, but not what is hidden below them
+        let artifacts =
+            record_artifacts_with_options(&["tests/test_runlib/.hidden"], &options).unwrap();
+        let paths: Vec<&str> = artifacts.keys().map(|path| path.value()).collect();
+        assert_eq!(paths, ["tests/test_runlib/.hidden/foo"]);
+
+        let artifacts =
+            record_artifacts_with_options(&["tests/test_runlib"], &RecordOptions::new()).unwrap();
+        assert_eq!(artifacts.len(), 3);
+    }
+
+    #[test]
+    fn test_in_toto_run_artifact_times() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        let material = dir.path().join("src.c");
+        std::fs::write(&material, "int main;").unwrap();
+        File::options()
+            .write(true)
+            .open(&material)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_millis(1_000_500))
+            .unwrap();
+        let product = format!("{}/out", root);
+        let cmd = format!("printf x > {}", product);
+
+        let run = |options: &RunOptions| {
+            let link = in_toto_run_with_options(
+                "test",
+                &[&format!("{}/src.c", root)],
+                &[&product],
+                &["sh", "-c", &cmd],
+                None,
+                None,
+                Some(&[&format!("{}/", root)]),
+                options,
+            )
+            .unwrap();
+            match link.metadata() {
+                crate::models::MetadataWrapper::Link(link) => {
+                    ArtifactTimes::from_byproducts(link.byproducts()).unwrap()
+                }
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(run(&RunOptions::new()), None);
+
+        let times = run(&RunOptions::new().artifact_times(true)).unwrap();
+        let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+        assert_eq!(times.materials()[&path("src.c")], 1_000);
+        assert!(times.products()[&path("out")] > 1_000);
+        assert_eq!(
+            times.source_date_epoch(),
+            ArtifactTimes::from_env().unwrap().source_date_epoch()
+        );
+    }
+
     #[test]
     fn test_left_strip() {
         let mut stripped_path: String;
@@ -509,6 +3121,43 @@ mod test {
         assert_eq!(stripped_path, "foo");
     }
 
+    #[test]
+    fn test_left_strip_collisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_str().unwrap();
+        for file in ["build/output/app", "dist/app", "dist/lib"] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        let output = format!("{}/build/output/", root);
+        let dist = format!("{}/dist/", root);
+
+        let options = RecordOptions::new().lstrip_paths(&[&output]);
+        let artifacts = record_artifacts_with_options(&[&output, &dist], &options).unwrap();
+        assert!(artifacts.contains_key(&VirtualTargetPath::new("app".into()).unwrap()));
+
+        let options = options.lstrip_paths(&[&output, &dist]);
+        match record_artifacts_with_options(&[&output, &dist], &options) {
+            Err(Error::LinkGatheringError(message)) => {
+                assert_eq!(message, "non unique stripped path app")
+            }
+            result => panic!("expected a collision, got {:?}", result),
+        }
+        // materials and products are stripped and checked alike
+        let link = in_toto_run(
+            "test",
+            None,
+            &[root],
+            &[],
+            &[],
+            None,
+            None,
+            Some(&[&output, &dist]),
+        );
+        assert!(matches!(link, Err(Error::LinkGatheringError(_))));
+    }
+
     #[test]
     fn test_run_command() {
         let byproducts = run_command(&["sh", "-c", "printf hello"], Some("tests")).unwrap();
@@ -524,4 +3173,107 @@ mod test {
             true
         );
     }
+
+    #[test]
+    fn test_run_command_exit_policy() {
+        let cmd = ["sh", "-c", "printf partial; exit 2"];
+        let byproducts = run_command(&cmd, None).unwrap();
+        assert_eq!(byproducts.return_value(), 2);
+
+        let options = RunOptions::new()
+            .output(OutputMode::CaptureOnly)
+            .exit_policy(ExitPolicy::FailFast);
+        match run_command_with_options(&cmd, &options) {
+            Err(Error::CommandFailed(byproducts)) => {
+                assert_eq!(byproducts.return_value(), 2);
+                assert_eq!(byproducts.stdout(), "partial");
+            }
+            other => panic!("expected a failed command, got {:?}", other),
+        }
+        assert!(run_command_with_options(&["true"], &options).is_ok());
+
+        // killed by SIGKILL
+        let byproducts = run_command(&["sh", "-c", "kill -9 $$"], None).unwrap();
+        assert_eq!(byproducts.return_value(), -9);
+    }
+
+    #[test]
+    fn test_run_command_timeout() {
+        let options = RunOptions::new()
+            .output(OutputMode::CaptureOnly)
+            .timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        // the background sleep holds the output open unless killed as well
+        let cmd = ["sh", "-c", "printf started; sleep 30 & sleep 30"];
+        let byproducts = run_command_with_options(&cmd, &options).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(20));
+        assert_eq!(byproducts.return_value(), -9);
+        assert_eq!(byproducts.stdout(), "started");
+        assert_eq!(byproducts.other_fields()[TIMEOUT_BYPRODUCT], json!(0.2));
+
+        let byproducts = run_command_with_options(&["true"], &options).unwrap();
+        assert!(byproducts.other_fields().is_empty());
+        let options = options.exit_policy(ExitPolicy::FailFast);
+        assert!(matches!(
+            run_command_with_options(&cmd, &options),
+            Err(Error::CommandFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_run_command_env() {
+        let env = |env: CommandEnv| {
+            let options = RunOptions::new()
+                .output(OutputMode::CaptureOnly)
+                .command_env(env);
+            run_command_with_options(&["env"], &options)
+                .unwrap()
+                .stdout()
+                .to_string()
+        };
+        let vars = BTreeMap::from([("IN_TOTO_STEP".to_string(), "build".to_string())]);
+        assert_eq!(env(CommandEnv::Explicit(vars)), "IN_TOTO_STEP=build\n");
+        let path = env(CommandEnv::Allowlist(vec![
+            "PATH".into(),
+            "NO_SUCH_VAR".into(),
+        ]));
+        assert_eq!(path.lines().count(), 1);
+        assert!(path.starts_with("PATH="));
+        assert!(env(CommandEnv::Inherit).contains("PATH="));
+    }
+
+    #[test]
+    fn test_run_command_timestamps() {
+        let options = RunOptions::new().timestamps(true);
+        let before = Utc::now();
+        let byproducts = run_command_with_options(&["true"], &options).unwrap();
+        let time = |name: &str| {
+            DateTime::parse_from_rfc3339(byproducts.other_fields()[name].as_str().unwrap())
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        let (start, end) = (time(START_TIME_BYPRODUCT), time(END_TIME_BYPRODUCT));
+        assert!(before <= start && start <= end && end <= Utc::now());
+        assert!(byproducts.other_fields()[START_TIME_BYPRODUCT]
+            .as_str()
+            .unwrap()
+            .ends_with('Z'));
+
+        let byproducts = run_command(&["true"], None).unwrap();
+        assert!(byproducts.other_fields().is_empty());
+    }
+
+    #[test]
+    fn test_run_command_output_modes() {
+        let cmd = ["sh", "-c", "printf out; printf err >&2"];
+        let options = RunOptions::new().output(OutputMode::CaptureOnly);
+        let byproducts = run_command_with_options(&cmd, &options).unwrap();
+        assert_eq!(byproducts.stdout(), "out");
+        assert_eq!(byproducts.stderr(), "err");
+
+        let options = RunOptions::new().output(OutputMode::Quiet);
+        let byproducts = run_command_with_options(&cmd, &options).unwrap();
+        assert_eq!(byproducts.stdout(), "");
+        assert_eq!(byproducts.stderr(), "");
+    }
 }
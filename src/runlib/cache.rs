@@ -0,0 +1,304 @@
+//! Caching the digests of files across recordings.
+//!
+//! Recording a large tree again, e.g. the materials of every step of a
+//! pipeline in the same checkout, mostly hashes files that did not change.
+//! A `HashCache` keeps the digests of the files recorded along with their
+//! size, modification time and inode, and files these still match are not
+//! hashed again. Like the index of git, this trusts that a file changed
+//! only if one of these did: a file rewritten with content of the same size
+//! and its modification time set back is recorded with its old digests.
+//!
+//! Modification times are only as fine as the file system keeps them, so a
+//! file written again right after it was hashed may keep its time. Digests
+//! of files modified less than `RACY_WINDOW` before they were hashed are
+//! not cached.
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::crypto::HashAlgorithm;
+use crate::models::TargetDescription;
+use crate::{Error, Result};
+
+/// How long before being hashed a file must have been modified last for
+/// its digests to be cached.
+pub const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// The version of the format of cache files.
+const CACHE_VERSION: u32 = 1;
+
+/// What a file is recognized by.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    inode: u64,
+}
+
+impl Stamp {
+    /// The stamp of the file with `metadata`, if its modification time is
+    /// known and older than `RACY_WINDOW`.
+    fn of(metadata: &Metadata) -> Option<Stamp> {
+        let modified = metadata.modified().ok()?;
+        let age = SystemTime::now().duration_since(modified).ok()?;
+        if age < RACY_WINDOW {
+            return None;
+        }
+        let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+        Some(Stamp {
+            size: metadata.len(),
+            modified_secs: since_epoch.as_secs(),
+            modified_nanos: since_epoch.subsec_nanos(),
+            inode: inode(metadata),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    stamp: Stamp,
+    hashes: TargetDescription,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, Entry>,
+}
+
+/// Digests of files recorded before, see `RecordOptions::hash_cache`.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use in_toto::runlib::{record_artifacts_with_options, HashCache, RecordOptions};
+/// # let dir = tempfile::tempdir().unwrap();
+/// # let cache_file = dir.path().join("hashes.json");
+/// let cache = Arc::new(HashCache::open(&cache_file).unwrap());
+/// let options = RecordOptions::new().hash_cache(Arc::clone(&cache));
+/// let materials = record_artifacts_with_options(&["tests/test_runlib"], &options).unwrap();
+/// cache.save().unwrap();
+/// ```
+#[derive(Debug, Default)]
+pub struct HashCache {
+    file: Option<PathBuf>,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl HashCache {
+    /// An empty cache kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cache saved at `file`, or an empty one saved there by `save` if
+    /// there is no such file yet. A file that cannot be read as cache, e.g.
+    /// of another version of this crate, is ignored.
+    pub fn open<P: AsRef<Path>>(file: P) -> Result<Self> {
+        let file = file.as_ref().to_path_buf();
+        let entries = match std::fs::read(&file) {
+            Ok(content) => match serde_json::from_slice::<CacheFile>(&content) {
+                Ok(cache) if cache.version == CACHE_VERSION => cache.entries,
+                Ok(cache) => {
+                    warn!(
+                        "Ignoring hash cache {} of version {}",
+                        file.display(),
+                        cache.version
+                    );
+                    HashMap::new()
+                }
+                Err(e) => {
+                    warn!("Ignoring hash cache {}: {}", file.display(), e);
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(HashCache {
+            file: Some(file),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Save the cache to the file it was opened from, replacing the file
+    /// at once, so a cache being saved can be opened concurrently. Files
+    /// that no longer exist are left out. A cache kept in memory is not
+    /// saved.
+    pub fn save(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let mut entries = self.lock()?;
+        entries.retain(|path, _| Path::new(path).exists());
+        let cache = CacheFile {
+            version: CACHE_VERSION,
+            entries: entries.clone(),
+        };
+        drop(entries);
+        let dir = match file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer(&mut temp, &cache)?;
+        temp.persist(file)?;
+        Ok(())
+    }
+
+    /// The number of files in the cache
+    pub fn len(&self) -> usize {
+        self.lock().map(|entries| entries.len()).unwrap_or_default()
+    }
+
+    /// Whether the cache holds no files
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all files
+    pub fn clear(&self) -> Result<()> {
+        self.lock()?.clear();
+        Ok(())
+    }
+
+    /// The digests with `algorithms` of the file at `path` with `metadata`,
+    /// if cached for the file as it is now.
+    pub(crate) fn get(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+        algorithms: &[HashAlgorithm],
+    ) -> Option<TargetDescription> {
+        let key = key(path)?;
+        let stamp = Stamp::of(metadata)?;
+        let entries = self.lock().ok()?;
+        let entry = entries.get(&key).filter(|entry| entry.stamp == stamp)?;
+        let hashes = algorithms
+            .iter()
+            .map(|algorithm| Some((algorithm.clone(), entry.hashes.get(algorithm)?.clone())))
+            .collect::<Option<TargetDescription>>()?;
+        debug!("Using cached digests of {}", key);
+        Some(hashes)
+    }
+
+    /// Cache `hashes` of the file at `path` with `metadata`, as read before
+    /// hashing it.
+    pub(crate) fn insert(&self, path: &Path, metadata: &Metadata, hashes: &TargetDescription) {
+        let (key, stamp) = match (key(path), Stamp::of(metadata)) {
+            (Some(key), Some(stamp)) => (key, stamp),
+            _ => return,
+        };
+        let mut entries = match self.lock() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        match entries.get_mut(&key) {
+            // keep digests of other algorithms of the same file
+            Some(entry) if entry.stamp == stamp => entry.hashes.extend(hashes.clone()),
+            _ => {
+                entries.insert(
+                    key,
+                    Entry {
+                        stamp,
+                        hashes: hashes.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Entry>>> {
+        self.entries
+            .lock()
+            .map_err(|_| Error::Programming("a thread using the hash cache panicked".into()))
+    }
+}
+
+/// A cache is only equal to itself, as files may change between comparing
+/// the entries of two caches and using them.
+impl PartialEq for HashCache {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for HashCache {}
+
+/// The absolute path of `path`, the same from any working directory.
+fn key(path: &Path) -> Option<String> {
+    std::path::absolute(path).ok()?.to_str().map(String::from)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    use super::HashCache;
+    use crate::runlib::{record_artifacts_with_options, RecordOptions};
+
+    fn set_modified(path: &std::path::Path, modified: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[test]
+    fn record_with_cache() {
+        let (dir, cache_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let file = dir.path().join("main.c");
+        let root = dir.path().to_str().unwrap();
+        let cache_file = cache_dir.path().join("hashes.json");
+        let old = SystemTime::now() - Duration::from_secs(60);
+        std::fs::write(&file, "int main;").unwrap();
+        set_modified(&file, old);
+
+        let cache = Arc::new(HashCache::open(&cache_file).unwrap());
+        let options = RecordOptions::new().hash_cache(Arc::clone(&cache));
+        let recorded = record_artifacts_with_options(&[root], &options).unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.save().unwrap();
+
+        // same size, time and inode, so the cached digests are recorded
+        let cache = Arc::new(HashCache::open(&cache_file).unwrap());
+        std::fs::write(&file, "int nain;").unwrap();
+        set_modified(&file, old);
+        let options = RecordOptions::new().hash_cache(Arc::clone(&cache));
+        assert_eq!(
+            record_artifacts_with_options(&[root], &options).unwrap(),
+            recorded
+        );
+        // a later modification is noticed, but not cached while racy
+        set_modified(&file, SystemTime::now());
+        assert_ne!(
+            record_artifacts_with_options(&[root], &options).unwrap(),
+            recorded
+        );
+        std::fs::remove_file(&file).unwrap();
+        cache.save().unwrap();
+        assert!(HashCache::open(&cache_file).unwrap().is_empty());
+    }
+}
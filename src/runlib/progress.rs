@@ -0,0 +1,44 @@
+//! Reporting the progress of recording artifacts.
+//!
+//! Recording large materials or products takes a while. A `RecordProgress`
+//! given in `RecordOptions::progress` learns of each artifact as the walk
+//! finds it and again once it is recorded, so tools can show how far
+//! recording is, e.g. as the number of bytes hashed of those found so far.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+/// What recording artifacts reports. Artifacts are found by one thread and
+/// recorded by the hashing threads, so implementations are called from
+/// several threads, and artifacts are recorded while others are still found.
+pub trait RecordProgress: Send + Sync {
+    /// The artifact `path` was found and is to be recorded, a file of
+    /// `size` bytes if `size` is given.
+    fn discovered(&self, path: &str, size: Option<u64>) {
+        let _ = (path, size);
+    }
+
+    /// The artifact `path` was recorded, hashing `bytes` of it: none if its
+    /// digests were cached or known from another hard link.
+    fn recorded(&self, path: &str, bytes: u64) {
+        let _ = (path, bytes);
+    }
+}
+
+/// A `RecordProgress` shared by options, compared by identity.
+#[derive(Clone)]
+pub(crate) struct ProgressHook(pub(crate) Arc<dyn RecordProgress>);
+
+impl Debug for ProgressHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHook")
+    }
+}
+
+impl PartialEq for ProgressHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ProgressHook {}
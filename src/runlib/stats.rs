@@ -0,0 +1,100 @@
+//! Statistics of generating links.
+//!
+//! A sudden change in the number or size of the artifacts a step records
+//! often means the build is misconfigured, e.g. a product path pointing at
+//! the whole workspace or an output directory left empty. `in_toto_run_with_stats`
+//! returns `RunStats` next to the link, so CI can log them and alert on such
+//! changes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::crypto::HashAlgorithm;
+
+/// Statistics of recording the materials or products of a step.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordStats {
+    artifacts: usize,
+    files_hashed: usize,
+    bytes_hashed: u64,
+    duration: Duration,
+    algorithm_times: HashMap<HashAlgorithm, Duration>,
+}
+
+impl RecordStats {
+    /// The number of artifacts recorded
+    pub fn artifacts(&self) -> usize {
+        self.artifacts
+    }
+
+    /// The number of files read and hashed, leaving out those whose digests
+    /// were cached or known from another hard link
+    pub fn files_hashed(&self) -> usize {
+        self.files_hashed
+    }
+
+    /// The number of bytes read from the files hashed
+    pub fn bytes_hashed(&self) -> u64 {
+        self.bytes_hashed
+    }
+
+    /// How long recording took, walking and hashing
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// How long each algorithm took hashing the files read. Summed over the
+    /// hashing threads, so the sum may exceed `duration`.
+    pub fn algorithm_times(&self) -> &HashMap<HashAlgorithm, Duration> {
+        &self.algorithm_times
+    }
+
+    /// Count a file of `bytes` hashed, each algorithm taking `times`.
+    pub(crate) fn file_hashed(&mut self, bytes: u64, times: HashMap<HashAlgorithm, Duration>) {
+        self.files_hashed += 1;
+        self.bytes_hashed += bytes;
+        for (algorithm, time) in times {
+            *self.algorithm_times.entry(algorithm).or_default() += time;
+        }
+    }
+
+    /// Add the files hashed by another hashing thread.
+    pub(crate) fn merge(&mut self, other: RecordStats) {
+        self.files_hashed += other.files_hashed;
+        self.bytes_hashed += other.bytes_hashed;
+        for (algorithm, time) in other.algorithm_times {
+            *self.algorithm_times.entry(algorithm).or_default() += time;
+        }
+    }
+
+    /// Note the artifacts recorded in the end and how long it took.
+    pub(crate) fn finish(&mut self, artifacts: usize, duration: Duration) {
+        self.artifacts = artifacts;
+        self.duration = duration;
+    }
+}
+
+/// Statistics of running a step.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunStats {
+    pub(crate) materials: RecordStats,
+    pub(crate) products: RecordStats,
+    pub(crate) command: Duration,
+}
+
+impl RunStats {
+    /// Recording the materials
+    pub fn materials(&self) -> &RecordStats {
+        &self.materials
+    }
+
+    /// Recording the products
+    pub fn products(&self) -> &RecordStats {
+        &self.products
+    }
+
+    /// How long the command ran
+    pub fn command(&self) -> Duration {
+        self.command
+    }
+}
@@ -0,0 +1,101 @@
+//! Limiting the files open for hashing at a time.
+//!
+//! On file systems mounted over the network every open file costs the
+//! metadata server, and a CI farm recording large workspaces on many
+//! threads each can overload it. `RecordOptions::max_open_files` bounds the
+//! files open for hashing at a time across all recordings with the same
+//! options, however many hashing threads they run.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::{Condvar, Mutex, PoisonError};
+
+/// A counting semaphore of open files.
+pub(crate) struct OpenFiles {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl OpenFiles {
+    pub(crate) fn new(max: usize) -> Self {
+        OpenFiles {
+            max: max.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    /// Wait until a file may be opened, which counts as open until the
+    /// guard returned is dropped.
+    pub(crate) fn acquire(&self) -> OpenFile<'_> {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        while *open >= self.max {
+            open = self
+                .closed
+                .wait(open)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *open += 1;
+        OpenFile { files: self }
+    }
+}
+
+impl Debug for OpenFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenFiles").field("max", &self.max).finish()
+    }
+}
+
+/// Two limits are the same only if they are the same limit, as they count
+/// the files of all recordings sharing them.
+impl PartialEq for OpenFiles {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for OpenFiles {}
+
+/// A file counted as open.
+pub(crate) struct OpenFile<'a> {
+    files: &'a OpenFiles,
+}
+
+impl Drop for OpenFile<'_> {
+    fn drop(&mut self) {
+        let mut open = self
+            .files
+            .open
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *open -= 1;
+        self.files.closed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::OpenFiles;
+
+    #[test]
+    fn bound_open_files() {
+        let files = OpenFiles::new(2);
+        let (open, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    let _file = files.acquire();
+                    let now = open.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(most.load(Ordering::SeqCst) <= 2);
+    }
+}
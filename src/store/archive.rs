@@ -0,0 +1,280 @@
+//! Metadata carried inside the packages it is about.
+//!
+//! A package can carry the links and layout of its own supply chain, so it
+//! is verified without fetching them from elsewhere. The metadata is kept
+//! as files in a directory named `EMBEDDED_METADATA_DIR`, at the top of the
+//! package or one directory down, where most formats keep everything:
+//!
+//! * `demo-0.1.0/.in-toto/` in a `.crate` or source distribution
+//! * `demo-0.1.0.dist-info/.in-toto/` in a `.whl`
+//! * `.in-toto/` in an OCI image layer or any tarball
+//!
+//! `embedded_metadata` reads tar archives, also gzip compressed as crates
+//! and most image layers are, and zip archives as wheels and jars are, into
+//! a `MemoryStore` to verify with, e.g.
+//! `in_toto_verify_with_store(&layout, &keys, &store, None)`. The metadata
+//! of a package does not change what is trusted: the layout is checked
+//! against the keys of the project owner as always.
+
+use std::convert::TryFrom;
+use std::path::Path;
+
+use super::{validate_entry_name, MemoryStore, MetadataStore};
+use crate::models::envelope::gzip::{crc32, decompress, inflate_raw};
+use crate::{Error, Result};
+
+/// The name of the directory holding the metadata in a package.
+pub const EMBEDDED_METADATA_DIR: &str = ".in-toto";
+
+/// The largest size of a tar archive unpacked from a gzip file, and of a
+/// metadata file unpacked from a zip archive.
+pub const MAX_UNPACKED_SIZE: usize = 1 << 30;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const ZIP_END_OF_DIRECTORY: &[u8] = b"PK\x05\x06";
+const ZIP_DIRECTORY_ENTRY: &[u8] = b"PK\x01\x02";
+const TAR_BLOCK: usize = 512;
+
+/// The metadata embedded in the package at `path`, see `embedded_metadata`.
+pub fn read_embedded_metadata<P: AsRef<Path>>(path: P) -> Result<MemoryStore> {
+    embedded_metadata(&std::fs::read(path)?)
+}
+
+/// The metadata embedded in `package`, a tar archive, gzip compressed or
+/// not, or a zip archive, by file name. Fails if the format is none of
+/// these, or two metadata files have the same name.
+pub fn embedded_metadata(package: &[u8]) -> Result<MemoryStore> {
+    let mut store = MemoryStore::new();
+    let mut add = |path: &str, content: &[u8]| -> Result<()> {
+        let name = match metadata_name(path) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        validate_entry_name(name)?;
+        if store.get(name)?.is_some() {
+            return Err(Error::Encoding(format!(
+                "package holds more than one {} metadata file {}",
+                EMBEDDED_METADATA_DIR, name
+            )));
+        }
+        store.put(name, content)
+    };
+    if package.starts_with(GZIP_MAGIC) {
+        read_tar(&decompress(package, MAX_UNPACKED_SIZE)?, &mut add)?;
+    } else if package.starts_with(ZIP_MAGIC) {
+        read_zip(package, &mut add)?;
+    } else if package.get(257..262) == Some(b"ustar") {
+        read_tar(package, &mut add)?;
+    } else {
+        return Err(Error::Encoding(
+            "package is neither a tar, gzip nor zip archive".into(),
+        ));
+    }
+    Ok(store)
+}
+
+/// The file name of the file at `path` in a package, if it is metadata.
+fn metadata_name(path: &str) -> Option<&str> {
+    let components: Vec<&str> = path
+        .trim_start_matches("./")
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect();
+    match components[..] {
+        [dir, name] | [_, dir, name] if dir == EMBEDDED_METADATA_DIR => Some(name),
+        _ => None,
+    }
+}
+
+type AddFile<'a> = dyn FnMut(&str, &[u8]) -> Result<()> + 'a;
+
+fn truncated(format: &str) -> Error {
+    Error::Encoding(format!("truncated {} archive", format))
+}
+
+/// Pass the regular files of the tar archive `data` to `add`.
+fn read_tar(data: &[u8], add: &mut AddFile) -> Result<()> {
+    let mut position = 0;
+    // names given by GNU long name and pax headers for the next file
+    let mut next_name: Option<String> = None;
+    while let Some(header) = data.get(position..position + TAR_BLOCK) {
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_size(&header[124..136])?;
+        let start = position + TAR_BLOCK;
+        let content = start
+            .checked_add(size)
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| truncated("tar"))?;
+        position = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        let name = match next_name.take() {
+            Some(name) => name,
+            None => {
+                let name = tar_string(&header[..100]);
+                let prefix = tar_string(&header[345..500]);
+                match &header[257..262] == b"ustar" && !prefix.is_empty() {
+                    true => format!("{}/{}", prefix, name),
+                    false => name,
+                }
+            }
+        };
+        match header[156] {
+            b'0' | 0 => add(&name, content)?,
+            b'L' => next_name = Some(tar_string(content)),
+            b'x' => next_name = pax_path(content),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// The NUL terminated string in `field`
+fn tar_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The size in a tar header, octal or base-256 for large files
+fn tar_size(field: &[u8]) -> Result<usize> {
+    let invalid = || Error::Encoding("invalid size in tar archive".into());
+    if field[0] & 0x80 != 0 {
+        let size = field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |size, &b| {
+                size.checked_mul(256)?.checked_add(u64::from(b))
+            })
+            .ok_or_else(invalid)?;
+        return usize::try_from(size).map_err(|_| invalid());
+    }
+    let digits = tar_string(field);
+    let digits = digits.trim_matches(|c: char| c == ' ' || c == '\0');
+    match digits.is_empty() {
+        true => Ok(0),
+        false => usize::from_str_radix(digits, 8).map_err(|_| invalid()),
+    }
+}
+
+/// The `path` of pax extended header records `content`, lines of the form
+/// `<length> <key>=<value>`.
+fn pax_path(content: &[u8]) -> Option<String> {
+    let content = String::from_utf8_lossy(content);
+    content.lines().find_map(|line| {
+        let (_length, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(String::from)
+    })
+}
+
+/// Pass the metadata files of the zip archive `data` to `add`. Only the
+/// metadata files are unpacked.
+fn read_zip(data: &[u8], add: &mut AddFile) -> Result<()> {
+    let u16_at = |at: usize| -> Result<usize> {
+        let bytes = data.get(at..at + 2).ok_or_else(|| truncated("zip"))?;
+        Ok(usize::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+    };
+    let u32_at = |at: usize| -> Result<u32> {
+        let bytes = data.get(at..at + 4).ok_or_else(|| truncated("zip"))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    // the end of central directory record is followed by a comment of at
+    // most 64 KiB
+    let search_from = data.len().saturating_sub(22 + 0xffff);
+    let end = data[search_from..]
+        .windows(4)
+        .rposition(|window| window == ZIP_END_OF_DIRECTORY)
+        .map(|at| search_from + at)
+        .ok_or_else(|| Error::Encoding("zip archive without central directory".into()))?;
+    let entries = u16_at(end + 10)?;
+    let directory = u32_at(end + 16)?;
+    if directory == u32::MAX {
+        return Err(Error::Encoding("zip64 archives are not supported".into()));
+    }
+
+    let mut position = directory as usize;
+    for _ in 0..entries {
+        if data.get(position..position + 4) != Some(ZIP_DIRECTORY_ENTRY) {
+            return Err(Error::Encoding("invalid zip central directory".into()));
+        }
+        let method = u16_at(position + 10)?;
+        let crc = u32_at(position + 16)?;
+        let compressed_size = u32_at(position + 20)? as usize;
+        let name_length = u16_at(position + 28)?;
+        let extra_length = u16_at(position + 30)?;
+        let comment_length = u16_at(position + 32)?;
+        let local_header = u32_at(position + 42)? as usize;
+        let name = data
+            .get(position + 46..position + 46 + name_length)
+            .ok_or_else(|| truncated("zip"))?;
+        let name = String::from_utf8_lossy(name);
+        position += 46 + name_length + extra_length + comment_length;
+        if name.ends_with('/') || metadata_name(&name).is_none() {
+            continue;
+        }
+
+        let start = local_header + 30 + u16_at(local_header + 26)? + u16_at(local_header + 28)?;
+        let compressed = data
+            .get(start..start + compressed_size)
+            .ok_or_else(|| truncated("zip"))?;
+        let content = match method {
+            0 => compressed.to_vec(),
+            8 => inflate_raw(compressed, MAX_UNPACKED_SIZE)?,
+            _ => {
+                return Err(Error::Encoding(format!(
+                    "{} in zip archive is compressed with unsupported method {}",
+                    name, method
+                )))
+            }
+        };
+        if crc32(&content) != crc {
+            return Err(Error::Encoding(format!(
+                "{} in zip archive fails its CRC-32",
+                name
+            )));
+        }
+        add(&name, &content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{embedded_metadata, metadata_name, read_embedded_metadata};
+    use crate::store::MetadataStore;
+
+    const LAYOUT: &[u8] = include_bytes!("../../tests/test_metadata/demo.layout");
+    const LINK: &[u8] = include_bytes!("../../tests/test_metadata/demo.link");
+
+    #[test]
+    fn read_metadata_of_packages() {
+        for package in [
+            "tests/test_archive/demo-0.1.0.crate",
+            "tests/test_archive/demo-0.1.0-py3-none-any.whl",
+        ] {
+            let store = read_embedded_metadata(package).unwrap();
+            assert_eq!(store.list().unwrap(), ["demo.layout", "demo.link"]);
+            assert_eq!(store.get("demo.layout").unwrap().unwrap(), LAYOUT);
+            assert_eq!(store.get("demo.link").unwrap().unwrap(), LINK);
+        }
+
+        let crate_file = std::fs::read("tests/test_archive/demo-0.1.0.crate").unwrap();
+        let mut corrupt = crate_file.clone();
+        let last = corrupt.len() - 9;
+        corrupt[last] ^= 1;
+        assert!(embedded_metadata(&corrupt).is_err());
+        assert!(embedded_metadata(&crate_file[..crate_file.len() / 2]).is_err());
+        assert!(embedded_metadata(b"not a package").is_err());
+    }
+
+    #[test]
+    fn metadata_paths() {
+        assert_eq!(metadata_name(".in-toto/a.link"), Some("a.link"));
+        assert_eq!(
+            metadata_name("./demo-0.1.0/.in-toto/a.link"),
+            Some("a.link")
+        );
+        assert_eq!(metadata_name("a/b/.in-toto/a.link"), None);
+        assert_eq!(metadata_name(".in-toto/sub/a.link"), None);
+        assert_eq!(metadata_name("src/a.link"), None);
+    }
+}
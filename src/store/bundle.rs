@@ -0,0 +1,246 @@
+//! Collecting the links of one step by several functionaries.
+//!
+//! Steps with a threshold need links from several functionaries, usually
+//! gathered by an orchestrator. `StepLinks` holds the links of one step by
+//! signer, and moves them in and out of a `MetadataStore` or a single bundle
+//! file as a group.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::MetadataStore;
+use crate::crypto::{KeyId, PublicKey};
+use crate::models::step::Step;
+use crate::models::{Metablock, MetadataWrapper};
+use crate::{Error, Result};
+
+/// Serialized form of `StepLinks`.
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    step: String,
+    links: Vec<Metablock>,
+}
+
+/// The links of one step, by the key they were signed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepLinks {
+    step: String,
+    links: BTreeMap<KeyId, Metablock>,
+}
+
+impl StepLinks {
+    /// No links of the step `step` yet.
+    pub fn new(step: &str) -> Self {
+        StepLinks {
+            step: step.to_string(),
+            links: BTreeMap::new(),
+        }
+    }
+
+    /// The name of the step
+    pub fn step(&self) -> &str {
+        &self.step
+    }
+
+    /// Add the signed `link`, failing if it is of another step, unsigned, or
+    /// signed by a key another link was added for already.
+    pub fn add(&mut self, link: Metablock) -> Result<()> {
+        match link.metadata() {
+            MetadataWrapper::Link(metadata) if *metadata.name() == self.step => (),
+            MetadataWrapper::Link(metadata) => {
+                return Err(Error::IllegalArgument(format!(
+                    "link of step {} cannot be collected for step {}",
+                    metadata.name(),
+                    self.step
+                )))
+            }
+            MetadataWrapper::Layout(_) => {
+                return Err(Error::IllegalArgument(format!(
+                    "a layout cannot be collected for step {}",
+                    self.step
+                )))
+            }
+        }
+        if link.signatures().is_empty() {
+            return Err(Error::IllegalArgument(format!(
+                "link of step {} is not signed",
+                self.step
+            )));
+        }
+        if let Some(signature) = link
+            .signatures()
+            .iter()
+            .find(|s| self.links.contains_key(s.key_id()))
+        {
+            return Err(Error::IllegalArgument(format!(
+                "a link of step {} signed by {} was collected already",
+                self.step,
+                signature.key_id()
+            )));
+        }
+        for signature in link.signatures() {
+            self.links.insert(signature.key_id().clone(), link.clone());
+        }
+        Ok(())
+    }
+
+    /// The link signed by `key_id`, if any
+    pub fn get(&self, key_id: &KeyId) -> Option<&Metablock> {
+        self.links.get(key_id)
+    }
+
+    /// The keys links were collected for
+    pub fn signers(&self) -> impl Iterator<Item = &KeyId> {
+        self.links.keys()
+    }
+
+    /// The number of keys links were collected for
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Whether no links were collected
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Check that links were collected from at least as many functionaries
+    /// of `step` as its threshold asks for, verifying their signatures with
+    /// `keys`, e.g. the keys of the layout.
+    pub fn verify_threshold(&self, step: &Step, keys: &HashMap<KeyId, PublicKey>) -> Result<()> {
+        let signed = step
+            .pub_keys
+            .iter()
+            .filter_map(|key_id| Some((self.links.get(key_id)?, keys.get(key_id)?)))
+            .filter(|(link, key)| link.verify(1, [*key]).is_ok())
+            .count();
+        if signed < step.threshold as usize {
+            return Err(Error::VerificationFailure(format!(
+                "step {} needs links of {} functionaries, but has {}",
+                self.step, step.threshold, signed
+            )));
+        }
+        Ok(())
+    }
+
+    /// Collect the links of step `step` kept in `store`, named as given by
+    /// `FILENAME_FORMAT`. Signatures are not verified.
+    pub fn from_store<S: MetadataStore + ?Sized>(store: &S, step: &str) -> Result<Self> {
+        let mut links = StepLinks::new(step);
+        let prefix = format!("{}.", step);
+        for name in store.list()? {
+            if !name.starts_with(&prefix) || !name.ends_with(".link") {
+                continue;
+            }
+            let link = match store.get_metablock(&name) {
+                Ok(Some(link)) => link,
+                _ => continue,
+            };
+            // the prefix also matches steps named like `<step>.more`, and a
+            // link signed by several keys is stored once for each
+            let same_step = matches!(link.metadata(),
+                MetadataWrapper::Link(metadata) if *metadata.name() == step);
+            let known = link
+                .signatures()
+                .iter()
+                .any(|s| links.links.get(s.key_id()) == Some(&link));
+            if same_step && !known {
+                links.add(link)?;
+            }
+        }
+        Ok(links)
+    }
+
+    /// Store all links in `store`, see `MetadataStore::put_link`, returning
+    /// the entry names.
+    pub fn write_to<S: MetadataStore + ?Sized>(&self, store: &mut S) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for link in self.distinct_links() {
+            names.extend(store.put_link(link)?);
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Serialize all links into a single bundle.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let bundle = Bundle {
+            step: self.step.clone(),
+            links: self.distinct_links().into_iter().cloned().collect(),
+        };
+        Ok(serde_json::to_vec_pretty(&bundle)?)
+    }
+
+    /// Parse a bundle written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bundle: Bundle = serde_json::from_slice(bytes)?;
+        let mut links = StepLinks::new(&bundle.step);
+        for link in bundle.links {
+            links.add(link)?;
+        }
+        Ok(links)
+    }
+
+    /// The links, each once even if signed by several keys.
+    fn distinct_links(&self) -> Vec<&Metablock> {
+        let mut distinct: Vec<&Metablock> = Vec::new();
+        for link in self.links.values() {
+            if !distinct.contains(&link) {
+                distinct.push(link);
+            }
+        }
+        distinct
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::StepLinks;
+    use crate::models::step::Step;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{key, link, sign};
+
+    #[test]
+    fn collect_threshold_links() {
+        let alice = key("alice");
+        let bob = key("bob");
+        let carol = key("carol");
+        let build = || Box::new(link("build", &[("src.c", b"int main;")], &[]));
+
+        let mut links = StepLinks::new("build");
+        links.add(sign(build(), &[&alice])).unwrap();
+        links.add(sign(build(), &[&bob, &carol])).unwrap();
+        assert_eq!(links.len(), 3);
+        // a second link of a signer and links of other steps are rejected
+        assert!(links.add(sign(build(), &[&bob])).is_err());
+        let test = Box::new(link("test", &[], &[]));
+        assert!(links.add(sign(test, &[&key("dave")])).is_err());
+
+        let step = Step::new("build")
+            .threshold(3)
+            .add_key(alice.key_id().clone())
+            .add_key(bob.key_id().clone())
+            .add_key(carol.key_id().clone());
+        let keys: HashMap<_, _> = [&alice, &bob, &carol]
+            .iter()
+            .map(|k| (k.key_id().clone(), k.public().clone()))
+            .collect();
+        assert!(links.verify_threshold(&step, &keys).is_ok());
+        let mut partial = StepLinks::new("build");
+        partial.add(sign(build(), &[&alice])).unwrap();
+        assert!(partial.verify_threshold(&step, &keys).is_err());
+
+        // through a store and a bundle
+        let mut store = MemoryStore::new();
+        store
+            .put_link(&sign(Box::new(link("build.more", &[], &[])), &[&alice]))
+            .unwrap();
+        assert_eq!(links.write_to(&mut store).unwrap().len(), 3);
+        assert_eq!(StepLinks::from_store(&store, "build").unwrap(), links);
+        let bundle = links.to_bytes().unwrap();
+        assert_eq!(StepLinks::from_bytes(&bundle).unwrap(), links);
+    }
+}
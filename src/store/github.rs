@@ -0,0 +1,333 @@
+//! Publishing and fetching attestations with GitHub's artifact attestation
+//! API.
+//!
+//! GitHub keeps sigstore bundles by repository and by the sha256 digest of
+//! their subjects. `GithubAttestations` publishes `CosignBundle`s to the
+//! `attestations` endpoint of a repository and fetches those of an artifact,
+//! e.g. to check a release download with `verify_subject`:
+//!
+//! * `POST /repos/<owner>/<repo>/attestations` - publish a bundle
+//! * `GET /repos/<owner>/<repo>/attestations/sha256:<digest>` - the bundles
+//!   of the artifact with the hex encoded sha256 digest `digest`
+//!
+//! The API is only served over HTTPS, which this crate does not speak. The
+//! requests are sent with a `HttpTransport`, to be implemented with the HTTP
+//! client of the application.
+
+use std::io::{Cursor, Read};
+
+use data_encoding::HEXLOWER;
+use http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, LINK, USER_AGENT};
+use http::{HeaderMap, Method, Request, Response, StatusCode};
+use log::debug;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_derive::Deserialize;
+
+use crate::crypto::KeyId;
+use crate::models::{CosignBundle, EnvelopeFile};
+use crate::{Error, Result};
+
+/// The URL of GitHub's REST API.
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// The version of the REST API the requests are made for.
+const GITHUB_API_VERSION: &str = "2022-11-28";
+
+/// The characters encoded in owner and repository names.
+const NAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_');
+
+/// Sends HTTP requests, e.g. with the HTTPS client of an application.
+pub trait HttpTransport {
+    /// The response to `request`. Responses with error status codes are
+    /// responses too, failing is for requests that got no response.
+    fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>>;
+
+    /// The response to `request` with a body read as it arrives, so large
+    /// bodies like downloads are not held in memory at once. By default the
+    /// body `send` returns.
+    fn send_streaming(&self, request: Request<Vec<u8>>) -> Result<Response<Box<dyn Read>>> {
+        let response = self.send(request)?;
+        Ok(response.map(|body| Box::new(Cursor::new(body)) as Box<dyn Read>))
+    }
+}
+
+#[derive(Deserialize)]
+struct Published {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Attestations {
+    attestations: Vec<Attestation>,
+}
+
+#[derive(Deserialize)]
+struct Attestation {
+    #[serde(default)]
+    bundle: Option<CosignBundle>,
+}
+
+/// The attestations of a GitHub repository.
+///
+/// ```
+/// # use in_toto::store::{GithubAttestations, HttpTransport};
+/// # struct Client;
+/// # impl HttpTransport for Client {
+/// #     fn send(&self, _: http::Request<Vec<u8>>) -> in_toto::Result<http::Response<Vec<u8>>> {
+/// #         unimplemented!()
+/// #     }
+/// # }
+/// let attestations = GithubAttestations::new(Client, "in-toto", "in-toto-rs")
+///     .token("ghp_...");
+/// ```
+#[derive(Debug, Clone)]
+pub struct GithubAttestations<T> {
+    transport: T,
+    api_url: String,
+    repository: String,
+    token: Option<String>,
+}
+
+impl<T: HttpTransport> GithubAttestations<T> {
+    /// The attestations of the repository `owner`/`repo` on github.com,
+    /// requested with `transport` without authentication.
+    pub fn new(transport: T, owner: &str, repo: &str) -> Self {
+        GithubAttestations {
+            transport,
+            api_url: GITHUB_API_URL.into(),
+            repository: format!(
+                "{}/{}",
+                utf8_percent_encode(owner, NAME),
+                utf8_percent_encode(repo, NAME)
+            ),
+            token: None,
+        }
+    }
+
+    /// Use the REST API at `api_url`, e.g. of GitHub Enterprise Server,
+    /// instead of `GITHUB_API_URL`
+    pub fn api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.trim_end_matches('/').into();
+        self
+    }
+
+    /// Authenticate with `token`, which needs the `attestations` write
+    /// permission for publishing and read permission of private
+    /// repositories for fetching
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Publish `bundle` as an attestation of the repository, returning the
+    /// ID GitHub assigned to it.
+    pub fn publish(&self, bundle: &CosignBundle) -> Result<u64> {
+        let url = format!("{}/repos/{}/attestations", self.api_url, self.repository);
+        let body = serde_json::to_vec(&serde_json::json!({ "bundle": bundle }))?;
+        let response = self.send(Method::POST, &url, body)?;
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED => {
+                let published: Published = serde_json::from_slice(response.body())?;
+                Ok(published.id)
+            }
+            _ => Err(api_error(&url, &response)),
+        }
+    }
+
+    /// The bundles attested for the artifact with the sha256 digest
+    /// `sha256`, following all pages of the response. Attestations only
+    /// available by URL, without bundle, are left out.
+    pub fn fetch(&self, sha256: &[u8]) -> Result<Vec<CosignBundle>> {
+        let mut url = Some(format!(
+            "{}/repos/{}/attestations/sha256:{}",
+            self.api_url,
+            self.repository,
+            HEXLOWER.encode(sha256)
+        ));
+        let mut bundles = Vec::new();
+        while let Some(page) = url {
+            let response = self.send(Method::GET, &page, Vec::new())?;
+            match response.status() {
+                StatusCode::OK => (),
+                StatusCode::NOT_FOUND => break,
+                _ => return Err(api_error(&page, &response)),
+            }
+            let attestations: Attestations = serde_json::from_slice(response.body())?;
+            for attestation in attestations.attestations {
+                match attestation.bundle {
+                    Some(bundle) => bundles.push(bundle),
+                    None => debug!("Skipping attestation without bundle of {}", page),
+                }
+            }
+            url = next_page(response.headers());
+        }
+        Ok(bundles)
+    }
+
+    /// The envelopes of the bundles attested for the artifact with the
+    /// sha256 digest `sha256`, see `fetch`. Signatures without key ID of
+    /// this crate are attributed to `default_key_id`, see
+    /// `CosignEnvelope::to_envelope`.
+    pub fn fetch_envelopes(
+        &self,
+        sha256: &[u8],
+        default_key_id: Option<&KeyId>,
+    ) -> Result<Vec<EnvelopeFile>> {
+        self.fetch(sha256)?
+            .iter()
+            .map(|bundle| bundle.envelope().to_envelope(default_key_id))
+            .collect()
+    }
+
+    fn send(&self, method: Method, url: &str, body: Vec<u8>) -> Result<Response<Vec<u8>>> {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(url)
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(
+                USER_AGENT,
+                concat!("in-toto-rs/", env!("CARGO_PKG_VERSION")),
+            )
+            .header("X-GitHub-Api-Version", GITHUB_API_VERSION);
+        if !body.is_empty() {
+            request = request.header(CONTENT_TYPE, "application/json");
+        }
+        if let Some(token) = &self.token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = request
+            .body(body)
+            .map_err(|e| Error::IllegalArgument(format!("request to {}: {}", url, e)))?;
+        self.transport.send(request)
+    }
+}
+
+/// The URL of the next page given in the `Link` header, if any.
+fn next_page(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            params
+                .split(';')
+                .any(|param| param.trim() == "rel=\"next\"")
+                .then(|| url.trim().trim_start_matches('<').trim_end_matches('>'))
+                .map(String::from)
+        })
+}
+
+fn api_error(url: &str, response: &Response<Vec<u8>>) -> Error {
+    Error::Opaque(format!(
+        "GitHub API {}: status {}: {}",
+        url,
+        response.status(),
+        String::from_utf8_lossy(response.body()).trim()
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    use http::{Request, Response};
+
+    use super::{GithubAttestations, HttpTransport};
+    use crate::models::{CosignBundle, CosignEnvelope, EnvelopeFile};
+    use crate::Result;
+
+    /// Answers requests with canned responses, recording the requests.
+    #[derive(Default)]
+    struct Canned {
+        requests: RefCell<Vec<Request<Vec<u8>>>>,
+        responses: RefCell<VecDeque<Response<Vec<u8>>>>,
+    }
+
+    impl Canned {
+        fn respond(self, status: u16, link: Option<&str>, body: &str) -> Self {
+            let mut response = Response::builder().status(status);
+            if let Some(link) = link {
+                response = response.header("Link", link);
+            }
+            let response = response.body(body.as_bytes().to_vec()).unwrap();
+            self.responses.borrow_mut().push_back(response);
+            self
+        }
+    }
+
+    impl HttpTransport for &Canned {
+        fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>> {
+            self.requests.borrow_mut().push(request);
+            Ok(self.responses.borrow_mut().pop_front().unwrap())
+        }
+    }
+
+    fn bundle(payload: &str) -> CosignBundle {
+        let envelope = EnvelopeFile::new(
+            payload.into(),
+            "application/vnd.in-toto+json".into(),
+            Vec::new(),
+        );
+        CosignBundle::new(CosignEnvelope::from_envelope(&envelope), &[])
+    }
+
+    #[test]
+    fn publish_attestation() {
+        let transport = Canned::default().respond(201, None, r#"{"id":42}"#);
+        let attestations = GithubAttestations::new(&transport, "in-toto", "in-toto-rs")
+            .api_url("https://github.example.com/api/v3/")
+            .token("secret");
+        assert_eq!(attestations.publish(&bundle("{}")).unwrap(), 42);
+
+        let requests = transport.requests.borrow();
+        let request = &requests[0];
+        assert_eq!(request.method(), "POST");
+        assert_eq!(
+            request.uri(),
+            "https://github.example.com/api/v3/repos/in-toto/in-toto-rs/attestations"
+        );
+        assert_eq!(request.headers()["Authorization"], "Bearer secret");
+        let body: serde_json::Value = serde_json::from_slice(request.body()).unwrap();
+        assert_eq!(body["bundle"]["dsseEnvelope"]["payload"], "e30=");
+
+        let transport = Canned::default().respond(403, None, "forbidden");
+        let attestations = GithubAttestations::new(&transport, "in-toto", "in-toto-rs");
+        assert!(attestations.publish(&bundle("{}")).is_err());
+    }
+
+    #[test]
+    fn fetch_attestations_of_digest() {
+        let page = |payload: &str| {
+            let bundle = serde_json::to_value(bundle(payload)).unwrap();
+            serde_json::json!({ "attestations": [{ "bundle": bundle }, { "bundle_url": "x" }] })
+                .to_string()
+        };
+        let next =
+            r#"<https://api.github.com/repositories/1/attestations/sha256:00?after=a>; rel="next""#;
+        let transport = Canned::default()
+            .respond(200, Some(next), &page("{\"a\":1}"))
+            .respond(200, None, &page("{\"b\":2}"));
+        let attestations = GithubAttestations::new(&transport, "o", "r");
+        let envelopes = attestations.fetch_envelopes(&[0], None).unwrap();
+        let payloads: Vec<&str> = envelopes.iter().map(|e| e.payload().as_str()).collect();
+        assert_eq!(payloads, ["{\"a\":1}", "{\"b\":2}"]);
+
+        let requests = transport.requests.borrow();
+        assert_eq!(
+            requests[0].uri(),
+            "https://api.github.com/repos/o/r/attestations/sha256:00"
+        );
+        assert_eq!(
+            requests[1].uri(),
+            "https://api.github.com/repositories/1/attestations/sha256:00?after=a"
+        );
+        assert!(requests[0].headers().get("Authorization").is_none());
+
+        let transport = Canned::default().respond(404, None, "{}");
+        let attestations = GithubAttestations::new(&transport, "o", "r");
+        assert!(attestations.fetch(&[0]).unwrap().is_empty());
+    }
+}
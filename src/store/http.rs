@@ -0,0 +1,334 @@
+//! Serving a `MetadataStore` over HTTP.
+//!
+//! In distributed setups the links are recorded on build machines while
+//! verification runs elsewhere. `StoreServer` exposes a store, e.g. the
+//! `DirectoryStore` the builders write their links to, and `HttpStore` is
+//! the read-only `MetadataStore` verifiers use to fetch from it:
+//!
+//! * `GET /entries` - the names of all entries, as a JSON array
+//! * `GET /entries/<name>` - the entry `name`
+//! * `GET /steps/<step>` - the names of the links of `step`, as a JSON array
+//! * `GET /sha256/<digest>` - the entry whose bytes have the hex encoded
+//!   sha256 digest `digest`
+//!
+//! Names in paths are percent-encoded. The server speaks plain HTTP/1.1
+//! only, one connection at a time, and is meant to run behind a TLS
+//! terminating proxy where links leave a trusted network. Signatures are not
+//! checked by the server, verifiers check them as for any other store.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use data_encoding::HEXLOWER;
+use http::StatusCode;
+use log::warn;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use ring::digest::{digest, SHA256};
+
+use super::{validate_entry_name, MetadataStore};
+use crate::{Error, Result};
+
+/// Largest request head the server reads.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Serves the entries of a `MetadataStore` over HTTP.
+pub struct StoreServer<S> {
+    listener: TcpListener,
+    store: S,
+}
+
+impl<S: MetadataStore> StoreServer<S> {
+    /// Serve `store` on `addr`, e.g. `0.0.0.0:8080`. Port 0 picks a free
+    /// port, see `local_addr`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, store: S) -> Result<Self> {
+        Ok(StoreServer {
+            listener: TcpListener::bind(addr)?,
+            store,
+        })
+    }
+
+    /// The address the server listens on
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Handle connections until accepting one fails. Failures of single
+    /// connections are logged and do not stop the server.
+    pub fn serve(&self) -> Result<()> {
+        loop {
+            self.handle_next()?;
+        }
+    }
+
+    /// Accept the next connection and answer its request.
+    pub fn handle_next(&self) -> Result<()> {
+        let (mut stream, peer) = self.listener.accept()?;
+        if let Err(e) = self.handle(&mut stream) {
+            warn!("Failed to answer request of {}: {}", peer, e);
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: &mut TcpStream) -> Result<()> {
+        let (status, body) = match read_request_path(stream) {
+            Ok(Some(path)) => self.respond(&path),
+            Ok(None) => (StatusCode::METHOD_NOT_ALLOWED, Vec::new()),
+            Err(e) => (StatusCode::BAD_REQUEST, e.to_string().into_bytes()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or(""),
+            body.len()
+        )?;
+        stream.write_all(&body)?;
+        Ok(stream.flush()?)
+    }
+
+    fn respond(&self, path: &str) -> (StatusCode, Vec<u8>) {
+        let answer = match path.trim_start_matches('/').split_once('/') {
+            None if path == "/entries" => self.store.list().and_then(json),
+            Some(("entries", name)) => decode(name).and_then(|name| self.store.get(&name)),
+            Some(("steps", step)) => decode(step).and_then(|step| self.step_links(&step)),
+            Some(("sha256", hex)) => self.by_digest(hex),
+            _ => Ok(None),
+        };
+        match answer {
+            Ok(Some(body)) => (StatusCode::OK, body),
+            Ok(None) => (StatusCode::NOT_FOUND, Vec::new()),
+            Err(Error::IllegalArgument(e)) => (StatusCode::BAD_REQUEST, e.into_bytes()),
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string().into_bytes(),
+            ),
+        }
+    }
+
+    fn step_links(&self, step: &str) -> Result<Option<Vec<u8>>> {
+        let names: Vec<String> = self
+            .store
+            .list()?
+            .into_iter()
+            .filter(|name| is_link_of(name, step))
+            .collect();
+        json(names)
+    }
+
+    fn by_digest(&self, hex: &str) -> Result<Option<Vec<u8>>> {
+        let hex = hex.to_ascii_lowercase();
+        for name in self.store.list()? {
+            if let Some(bytes) = self.store.get(&name)? {
+                if HEXLOWER.encode(digest(&SHA256, &bytes).as_ref()) == hex {
+                    return Ok(Some(bytes));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A read-only `MetadataStore` fetching the entries of a `StoreServer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpStore {
+    addr: String,
+}
+
+impl HttpStore {
+    /// Fetch from the server at `addr`, e.g. `builder.example.com:8080`.
+    pub fn new(addr: &str) -> Self {
+        HttpStore {
+            addr: addr.to_string(),
+        }
+    }
+
+    /// The names of the links of step `step`, named as given by
+    /// `FILENAME_FORMAT`.
+    pub fn list_step(&self, step: &str) -> Result<Vec<String>> {
+        let path = format!("/steps/{}", utf8_percent_encode(step, NON_ALPHANUMERIC));
+        match self.fetch(&path)? {
+            Some(body) => Ok(serde_json::from_slice(&body)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The entry whose bytes have the sha256 digest `sha256`, or `None` if
+    /// there is no such entry.
+    pub fn get_by_digest(&self, sha256: &[u8]) -> Result<Option<Vec<u8>>> {
+        let bytes = self.fetch(&format!("/sha256/{}", HEXLOWER.encode(sha256)))?;
+        match bytes {
+            Some(bytes) if digest(&SHA256, &bytes).as_ref() != sha256 => {
+                Err(Error::VerificationFailure(
+                    "the entry served does not have the digest asked for".into(),
+                ))
+            }
+            bytes => Ok(bytes),
+        }
+    }
+
+    /// The body of the response to `GET path`, `None` on 404.
+    fn fetch(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let mut stream = TcpStream::connect(&self.addr)?;
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            path, self.addr
+        )?;
+        stream.flush()?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let mut parsed = httparse::Response::new(&mut headers);
+        let head_len = match parsed.parse(&response) {
+            Ok(httparse::Status::Complete(len)) => len,
+            _ => return Err(http_error(&self.addr, "incomplete response")),
+        };
+        let body = response[head_len..].to_vec();
+        match parsed.code {
+            Some(200) => Ok(Some(body)),
+            Some(404) => Ok(None),
+            code => Err(http_error(
+                &self.addr,
+                &format!(
+                    "status {:?}: {}",
+                    code,
+                    String::from_utf8_lossy(&body).trim()
+                ),
+            )),
+        }
+    }
+}
+
+impl MetadataStore for HttpStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        validate_entry_name(name)?;
+        self.fetch(&format!(
+            "/entries/{}",
+            utf8_percent_encode(name, NON_ALPHANUMERIC)
+        ))
+    }
+
+    fn put(&mut self, name: &str, _bytes: &[u8]) -> Result<()> {
+        Err(Error::IllegalArgument(format!(
+            "cannot store {}, the store of {} is read-only",
+            name, self.addr
+        )))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        Err(Error::IllegalArgument(format!(
+            "cannot remove {}, the store of {} is read-only",
+            name, self.addr
+        )))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        match self.fetch("/entries")? {
+            Some(body) => Ok(serde_json::from_slice(&body)?),
+            None => Err(http_error(&self.addr, "no entries served")),
+        }
+    }
+}
+
+/// The path of a `GET` request read from `stream`, `None` for other methods.
+fn read_request_path(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let read = stream.read(&mut buf)?;
+        if read == 0 {
+            return Err(Error::Encoding("incomplete HTTP request".into()));
+        }
+        request.extend_from_slice(&buf[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        match parsed.parse(&request) {
+            Ok(httparse::Status::Complete(_)) => {
+                return Ok(match (parsed.method, parsed.path) {
+                    (Some("GET"), Some(path)) => Some(path.to_string()),
+                    _ => None,
+                })
+            }
+            Ok(httparse::Status::Partial) if request.len() < MAX_REQUEST_LEN => (),
+            Ok(httparse::Status::Partial) => {
+                return Err(Error::Encoding("HTTP request too long".into()))
+            }
+            Err(e) => return Err(Error::Encoding(format!("HTTP request: {}", e))),
+        }
+    }
+}
+
+/// Whether `name` follows `FILENAME_FORMAT` for a link of `step`.
+fn is_link_of(name: &str, step: &str) -> bool {
+    name.strip_prefix(step)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .and_then(|rest| rest.strip_suffix(".link"))
+        .is_some_and(|key_id| !key_id.is_empty() && !key_id.contains('.'))
+}
+
+fn decode(segment: &str) -> Result<String> {
+    percent_decode_str(segment)
+        .decode_utf8()
+        .map(|name| name.into_owned())
+        .map_err(|_| Error::IllegalArgument(format!("invalid name {:?}", segment)))
+}
+
+fn json(names: Vec<String>) -> Result<Option<Vec<u8>>> {
+    Ok(Some(serde_json::to_vec(&names)?))
+}
+
+fn http_error(addr: &str, message: &str) -> Error {
+    Error::Opaque(format!("HTTP store {}: {}", addr, message))
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use ring::digest::{digest, SHA256};
+
+    use super::{HttpStore, StoreServer};
+    use crate::models::link_filename;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{key, link, sign};
+
+    #[test]
+    fn fetch_links_over_http() {
+        let alice = key("alice");
+        let build = sign(Box::new(link("build", &[], &[])), &[&alice]);
+        let test = sign(Box::new(link("build.test", &[], &[])), &[&alice]);
+        let mut store = MemoryStore::new();
+        store.put_link(&build).unwrap();
+        store.put_link(&test).unwrap();
+        store.put("root.layout", b"{}").unwrap();
+
+        let server = StoreServer::bind("127.0.0.1:0", store.clone()).unwrap();
+        let mut remote = HttpStore::new(&server.local_addr().unwrap().to_string());
+        let requests = 7;
+        let serving = thread::spawn(move || {
+            for _ in 0..requests {
+                server.handle_next().unwrap();
+            }
+        });
+
+        assert_eq!(remote.list().unwrap(), store.list().unwrap());
+        let name = link_filename("build", alice.key_id());
+        assert_eq!(remote.list_step("build").unwrap(), [name.as_str()]);
+        assert_eq!(
+            remote.get_link("build", alice.key_id()).unwrap(),
+            Some(build)
+        );
+        assert_eq!(remote.get("missing.link").unwrap(), None);
+        let layout = digest(&SHA256, b"{}");
+        assert_eq!(
+            remote.get_by_digest(layout.as_ref()).unwrap(),
+            Some(b"{}".to_vec())
+        );
+        assert_eq!(remote.get_by_digest(&[0; 32]).unwrap(), None);
+        assert!(remote.list_step("").unwrap().is_empty());
+        assert!(remote.put(&name, b"{}").is_err());
+        serving.join().unwrap();
+    }
+}
@@ -0,0 +1,526 @@
+//! Storage of signed metadata.
+//!
+//! A `MetadataStore` holds serialized metablocks by entry name, such as the
+//! links of a supply chain named as given by `FILENAME_FORMAT`. Verification
+//! reads the links of each step from a store, so links can be kept in memory,
+//! in a directory or anywhere else a store is implemented for.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::{DateTime, Utc};
+
+use crate::crypto::{HashValue, KeyId};
+use crate::models::{link_filename, Metablock, MetadataWrapper};
+use crate::{Error, Result};
+
+mod archive;
+mod bundle;
+mod github;
+#[cfg(feature = "http-server")]
+mod http;
+mod retention;
+mod search;
+mod trust;
+
+pub use archive::{
+    embedded_metadata, read_embedded_metadata, EMBEDDED_METADATA_DIR, MAX_UNPACKED_SIZE,
+};
+pub use bundle::StepLinks;
+pub use github::{GithubAttestations, HttpTransport, GITHUB_API_URL};
+#[cfg(feature = "http-server")]
+pub use http::{HttpStore, StoreServer};
+pub use retention::RetentionPolicy;
+pub use search::Producer;
+pub use trust::{RollbackProtectedStore, TrustCache};
+
+/// Storage of serialized metadata by entry name.
+///
+/// Entry names are plain file names, they must be non-empty and may neither
+/// contain a path separator nor be `.` or `..`.
+pub trait MetadataStore {
+    /// The bytes stored as `name`, or `None` if there is no such entry.
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store `bytes` as `name`, replacing any previous entry.
+    fn put(&mut self, name: &str, bytes: &[u8]) -> Result<()>;
+
+    /// Remove the entry `name`, returning whether it existed.
+    fn remove(&mut self, name: &str) -> Result<bool>;
+
+    /// The names of all entries, sorted.
+    fn list(&self) -> Result<Vec<String>>;
+
+    /// When the entry `name` was last stored, or `None` if there is no such
+    /// entry or the store does not know.
+    fn modified(&self, _name: &str) -> Result<Option<DateTime<Utc>>> {
+        Ok(None)
+    }
+
+    /// Remove all links `policy` does not retain, returning their names.
+    fn prune(&mut self, policy: &RetentionPolicy) -> Result<Vec<String>> {
+        let names = policy.prunable(self, Utc::now())?;
+        for name in &names {
+            self.remove(name)?;
+        }
+        Ok(names)
+    }
+
+    /// The metablock stored as `name`, or `None` if there is no such entry.
+    fn get_metablock(&self, name: &str) -> Result<Option<Metablock>> {
+        match self.get(name)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The link of step `step_name` signed by `key_id`, or `None` if there is
+    /// no such entry. The signature is not verified.
+    fn get_link(&self, step_name: &str, key_id: &KeyId) -> Result<Option<Metablock>> {
+        self.get_metablock(&link_filename(step_name, key_id))
+    }
+
+    /// Store the signed `link` once for every key that signed it, named as
+    /// given by `FILENAME_FORMAT`, and return the entry names.
+    ///
+    /// Fails without storing anything if one of the names already holds a
+    /// link of the same step not signed by the key the name is for, e.g.
+    /// because the key IDs of two functionaries share their prefix. Such a
+    /// link can be put under a name with a longer prefix of the key ID,
+    /// e.g. the full one, where verification finds it.
+    fn put_link(&mut self, link: &Metablock) -> Result<Vec<String>> {
+        store_link(self, link)
+    }
+
+    /// The entries producing an artifact whose hash, of any algorithm, is
+    /// `digest`: links having it as a product they did not have as a
+    /// material, and attestations having it as a subject. Signatures are not
+    /// verified.
+    fn find_producer(&self, digest: &HashValue) -> Result<Vec<Producer>> {
+        search::find_producer(self, digest)
+    }
+}
+
+fn store_link<S: MetadataStore + ?Sized>(store: &mut S, link: &Metablock) -> Result<Vec<String>> {
+    let step_name = match link.metadata() {
+        MetadataWrapper::Link(link) => link.name().clone(),
+        MetadataWrapper::Layout(_) => {
+            return Err(Error::IllegalArgument(
+                "only links can be stored by step name".into(),
+            ))
+        }
+    };
+    if link.signatures().is_empty() {
+        return Err(Error::IllegalArgument(format!(
+            "link of step {} is not signed",
+            step_name
+        )));
+    }
+
+    let mut names = Vec::new();
+    for signature in link.signatures() {
+        let name = link_filename(&step_name, signature.key_id());
+        // an unreadable entry is replaced, like any other previous entry
+        if let Ok(Some(existing)) = store.get_metablock(&name) {
+            let same_step = match existing.metadata() {
+                MetadataWrapper::Link(existing) => *existing.name() == step_name,
+                MetadataWrapper::Layout(_) => false,
+            };
+            if same_step
+                && !existing
+                    .signatures()
+                    .iter()
+                    .any(|s| s.key_id() == signature.key_id())
+            {
+                return Err(Error::IllegalArgument(format!(
+                    "{} already holds a link of step {} signed by another key than {}",
+                    name,
+                    step_name,
+                    signature.key_id()
+                )));
+            }
+        }
+        names.push(name);
+    }
+    let bytes = serde_json::to_vec_pretty(link)?;
+    for name in &names {
+        store.put(name, &bytes)?;
+    }
+    Ok(names)
+}
+
+fn validate_entry_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return Err(Error::IllegalArgument(format!(
+            "invalid metadata entry name {:?}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// A `MetadataStore` keeping all entries in memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStore {
+    entries: BTreeMap<String, (Vec<u8>, DateTime<Utc>)>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the store has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Store `bytes` as `name` like `put`, as if it was stored at `modified`.
+    pub fn put_modified(
+        &mut self,
+        name: &str,
+        bytes: &[u8],
+        modified: DateTime<Utc>,
+    ) -> Result<()> {
+        validate_entry_name(name)?;
+        self.entries
+            .insert(name.to_string(), (bytes.to_vec(), modified));
+        Ok(())
+    }
+}
+
+impl MetadataStore for MemoryStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        validate_entry_name(name)?;
+        Ok(self.entries.get(name).map(|(bytes, _)| bytes.clone()))
+    }
+
+    fn put(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.put_modified(name, bytes, Utc::now())
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        validate_entry_name(name)?;
+        Ok(self.entries.remove(name).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.entries.keys().cloned().collect())
+    }
+
+    fn modified(&self, name: &str) -> Result<Option<DateTime<Utc>>> {
+        validate_entry_name(name)?;
+        Ok(self.entries.get(name).map(|(_, modified)| *modified))
+    }
+}
+
+/// The name of the lock file of a `DirectoryStore`.
+pub const LOCK_FILENAME: &str = ".in-toto.lock";
+
+/// A `MetadataStore` keeping every entry as a file of a directory.
+///
+/// Only regular files directly in the directory are entries. Several stores,
+/// also of different processes such as parallel CI jobs, may write into the
+/// same directory: writes hold an advisory lock on `LOCK_FILENAME` and go to
+/// a uniquely named temporary file first, which is then renamed, so readers
+/// never see partially written entries.
+#[derive(Debug)]
+pub struct DirectoryStore {
+    dir: PathBuf,
+    lock: Option<File>,
+}
+
+impl DirectoryStore {
+    /// Use the directory `dir`, which has to exist.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        DirectoryStore {
+            dir: dir.as_ref().to_path_buf(),
+            lock: None,
+        }
+    }
+
+    /// The directory of the store.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Run `f` holding the lock of the directory, so no other writer
+    /// interferes with what `f` reads and writes. Blocks until the lock is
+    /// acquired. Writes within `f` do not lock again.
+    pub fn locked<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if self.lock.is_some() {
+            return f(self);
+        }
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(LOCK_FILENAME))?;
+        lock.lock()?;
+        self.lock = Some(lock);
+        let result = f(self);
+        // closing the file releases the lock
+        self.lock = None;
+        result
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        validate_entry_name(name)?;
+        Ok(self.dir.join(name))
+    }
+
+    fn write_entry(&self, name: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(name)?;
+        let temp = self.dir.join(format!(
+            ".{}.{}-{:016x}.tmp",
+            name,
+            process::id(),
+            rand::random::<u64>()
+        ));
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .and_then(|mut file| {
+                file.write_all(bytes)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp, &path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl Clone for DirectoryStore {
+    fn clone(&self) -> Self {
+        DirectoryStore::new(&self.dir)
+    }
+}
+
+/// Whether `name` is the lock or a temporary file of a `DirectoryStore`.
+fn is_internal_file(name: &str) -> bool {
+    name == LOCK_FILENAME || (name.starts_with('.') && name.ends_with(".tmp"))
+}
+
+impl MetadataStore for DirectoryStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(name)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(path)?))
+    }
+
+    fn put(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.locked(|store| store.write_entry(name, bytes))
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        let path = self.path(name)?;
+        self.locked(|_| match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        })
+    }
+
+    fn put_link(&mut self, link: &Metablock) -> Result<Vec<String>> {
+        self.locked(|store| store_link(store, link))
+    }
+
+    fn prune(&mut self, policy: &RetentionPolicy) -> Result<Vec<String>> {
+        self.locked(|store| {
+            let names = policy.prunable(store, Utc::now())?;
+            for name in &names {
+                store.remove(name)?;
+            }
+            Ok(names)
+        })
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            match entry.file_name().to_str() {
+                Some(name) if !is_internal_file(name) => names.push(name.to_string()),
+                _ => (),
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn modified(&self, name: &str) -> Result<Option<DateTime<Utc>>> {
+        match fs::metadata(self.path(name)?) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(metadata.modified()?.into())),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DirectoryStore, MemoryStore, MetadataStore, LOCK_FILENAME};
+    use crate::interchange::Json;
+    use crate::models::{
+        link_filename, LayoutMetadataBuilder, LinkMetadataBuilder, MetablockBuilder,
+    };
+    use crate::test_utils::key;
+
+    fn round_trip(store: &mut dyn MetadataStore) {
+        assert_eq!(store.get("a").unwrap(), None);
+        store.put("b", b"2").unwrap();
+        store.put("a", b"1").unwrap();
+        store.put("a", b"3").unwrap();
+        assert_eq!(store.get("a").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(store.list().unwrap(), ["a", "b"]);
+        assert!(store.remove("a").unwrap());
+        assert!(!store.remove("a").unwrap());
+        assert_eq!(store.list().unwrap(), ["b"]);
+
+        for name in ["", ".", "..", "../a", "a/b"] {
+            assert!(store.put(name, b"").is_err());
+            assert!(store.get(name).is_err());
+        }
+    }
+
+    #[test]
+    fn memory_store() {
+        round_trip(&mut MemoryStore::new());
+    }
+
+    #[test]
+    fn directory_store() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let mut store = DirectoryStore::new(dir.path());
+        round_trip(&mut store);
+        assert!(dir.path().join("b").is_file());
+        // the lock file is no entry
+        assert!(dir.path().join(LOCK_FILENAME).is_file());
+        assert_eq!(store.list().unwrap(), ["b"]);
+    }
+
+    #[test]
+    fn concurrent_directory_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let functionaries: Vec<_> = (0..8).map(|i| key(&format!("job-{}", i))).collect();
+        std::thread::scope(|scope| {
+            for (i, functionary) in functionaries.iter().enumerate() {
+                let mut store = DirectoryStore::new(dir.path());
+                scope.spawn(move || {
+                    for run in 0..10 {
+                        let product = run.to_string();
+                        let link = crate::test_utils::link(
+                            &format!("step-{}", i % 2),
+                            &[],
+                            &[(&product, b"")],
+                        );
+                        let link = MetablockBuilder::from_metadata(Box::new(link))
+                            .sign(&[functionary])
+                            .unwrap()
+                            .build();
+                        store.put_link(&link).unwrap();
+                    }
+                });
+            }
+        });
+
+        let store = DirectoryStore::new(dir.path());
+        assert_eq!(store.list().unwrap().len(), functionaries.len());
+        for (i, functionary) in functionaries.iter().enumerate() {
+            let link = store
+                .get_link(&format!("step-{}", i % 2), functionary.public().key_id())
+                .unwrap()
+                .unwrap();
+            assert!(link.verify(1, [functionary.public()]).is_ok());
+        }
+        // no temporary files are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 9);
+    }
+
+    #[test]
+    fn store_links() {
+        let alice = key("alice");
+        let bob = key("bob");
+        let link = LinkMetadataBuilder::new()
+            .name("build".into())
+            .build()
+            .unwrap();
+        let signed = MetablockBuilder::from_metadata(Box::new(link))
+            .sign(&[&alice, &bob])
+            .unwrap()
+            .build();
+
+        let mut store = MemoryStore::new();
+        let mut names = store.put_link(&signed).unwrap();
+        names.sort();
+        let mut expected = [
+            link_filename("build", alice.public().key_id()),
+            link_filename("build", bob.public().key_id()),
+        ];
+        expected.sort();
+        assert_eq!(names, expected);
+        assert_eq!(store.list().unwrap(), expected);
+        assert_eq!(
+            store.get_link("build", bob.public().key_id()).unwrap(),
+            Some(signed.clone())
+        );
+        assert_eq!(store.get_link("test", bob.public().key_id()).unwrap(), None);
+
+        // unsigned links and layouts are not stored by step name
+        let unsigned = LinkMetadataBuilder::new().unsigned::<Json>().unwrap();
+        assert!(store.put_link(&unsigned).is_err());
+        let layout = MetablockBuilder::from_metadata(Box::new(
+            LayoutMetadataBuilder::new().build().unwrap(),
+        ))
+        .sign(&[&alice])
+        .unwrap()
+        .build();
+        assert!(store.put_link(&layout).is_err());
+        assert_eq!(store.len(), 2);
+
+        // another key whose ID has the same prefix as bob's does not replace
+        // his link
+        let mallory = key("mallory");
+        let name = link_filename("build", bob.public().key_id());
+        let forged = MetablockBuilder::from_metadata(Box::new(
+            LinkMetadataBuilder::new()
+                .name("build".into())
+                .build()
+                .unwrap(),
+        ))
+        .sign(&[&mallory])
+        .unwrap()
+        .build();
+        store
+            .put(&name, &serde_json::to_vec(&forged).unwrap())
+            .unwrap();
+        assert!(store.put_link(&signed).is_err());
+        assert_eq!(
+            store.get_link("build", bob.public().key_id()).unwrap(),
+            Some(forged)
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Retention policies deciding which links of a store can be pruned.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+
+use super::MetadataStore;
+use crate::crypto::KeyId;
+use crate::models::MetadataWrapper;
+use crate::verifylib::VerificationReport;
+use crate::Result;
+
+/// The links of each step and set of signers, with when they were stored.
+type Runs = BTreeMap<(String, Vec<KeyId>), Vec<(DateTime<Utc>, String)>>;
+
+/// Which links of a `MetadataStore` to prune.
+///
+/// Only entries named like links (ending in `.link`) that hold a link are
+/// ever pruned, other metadata is left alone. Links retained explicitly, for
+/// example because a retained verification bundle refers to them, are never
+/// pruned, no matter how old or superseded they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    max_age: Option<Duration>,
+    prune_superseded: bool,
+    retained: BTreeSet<String>,
+}
+
+impl RetentionPolicy {
+    /// A policy pruning nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prune links stored more than `max_age` ago.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Prune links stored more than `days` days ago.
+    pub fn max_age_days(self, days: i64) -> Self {
+        self.max_age(Duration::days(days))
+    }
+
+    /// Prune links of which a link of the same step signed by the same keys
+    /// was stored later, i.e. the link was superseded by a newer run.
+    pub fn prune_superseded(mut self) -> Self {
+        self.prune_superseded = true;
+        self
+    }
+
+    /// Never prune the entry `name`.
+    pub fn retain(mut self, name: &str) -> Self {
+        self.retained.insert(name.to_string());
+        self
+    }
+
+    /// Never prune the links a verification accepted, keeping everything
+    /// needed to reproduce it.
+    pub fn retain_report(mut self, report: &VerificationReport) -> Self {
+        self.retained.extend(report.link_entries().iter().cloned());
+        self
+    }
+
+    /// The entries of `store` this policy prunes at `now`, without removing
+    /// them.
+    pub fn prunable<S: MetadataStore + ?Sized>(
+        &self,
+        store: &S,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<String>> {
+        let mut prunable = BTreeSet::new();
+        let mut runs: Runs = BTreeMap::new();
+        for name in store.list()? {
+            if !name.ends_with(".link") || self.retained.contains(&name) {
+                continue;
+            }
+            let modified = match store.modified(&name)? {
+                Some(modified) => modified,
+                None => continue,
+            };
+            let metablock = match store.get_metablock(&name) {
+                Ok(Some(metablock)) => metablock,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!("Not pruning {}: {}", name, e);
+                    continue;
+                }
+            };
+            let step_name = match metablock.metadata() {
+                MetadataWrapper::Link(link) => link.name().clone(),
+                MetadataWrapper::Layout(_) => continue,
+            };
+
+            if self.max_age.is_some_and(|max_age| modified < now - max_age) {
+                prunable.insert(name.clone());
+            }
+            let mut signers: Vec<KeyId> = metablock
+                .signatures()
+                .iter()
+                .map(|s| s.key_id().clone())
+                .collect();
+            signers.sort();
+            runs.entry((step_name, signers))
+                .or_default()
+                .push((modified, name));
+        }
+
+        if self.prune_superseded {
+            for mut links in runs.into_values() {
+                links.sort();
+                links.pop();
+                prunable.extend(links.into_iter().map(|(_, name)| name));
+            }
+        }
+        Ok(prunable.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::RetentionPolicy;
+    use crate::models::{LinkMetadataBuilder, MetablockBuilder};
+    use crate::store::{DirectoryStore, MemoryStore, MetadataStore};
+    use crate::test_utils;
+    use crate::verifylib::in_toto_verify_with_store;
+
+    #[test]
+    fn prune_links() {
+        let functionary = test_utils::functionary_key();
+        let link = |name: &str| {
+            let link = LinkMetadataBuilder::new()
+                .name(name.into())
+                .build()
+                .unwrap();
+            serde_json::to_vec(
+                &MetablockBuilder::from_metadata(Box::new(link))
+                    .sign(&[&functionary])
+                    .unwrap()
+                    .build(),
+            )
+            .unwrap()
+        };
+        let now = Utc::now();
+        let mut store = MemoryStore::new();
+        store
+            .put_modified("old.link", &link("build"), now - Duration::days(10))
+            .unwrap();
+        store
+            .put_modified("retry.link", &link("build"), now - Duration::days(1))
+            .unwrap();
+        store.put_modified("test.link", &link("test"), now).unwrap();
+        store
+            .put_modified("notes.txt", b"old", now - Duration::days(10))
+            .unwrap();
+        store
+            .put_modified("broken.link", b"{", now - Duration::days(10))
+            .unwrap();
+
+        assert!(RetentionPolicy::new()
+            .prunable(&store, now)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            RetentionPolicy::new()
+                .max_age_days(5)
+                .prunable(&store, now)
+                .unwrap(),
+            ["old.link"]
+        );
+        assert_eq!(
+            RetentionPolicy::new()
+                .max_age(Duration::hours(1))
+                .prunable(&store, now)
+                .unwrap(),
+            ["old.link", "retry.link"]
+        );
+        assert_eq!(
+            RetentionPolicy::new()
+                .prune_superseded()
+                .prunable(&store, now)
+                .unwrap(),
+            ["old.link"]
+        );
+        assert!(RetentionPolicy::new()
+            .prune_superseded()
+            .retain("old.link")
+            .prunable(&store, now)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn retain_verified_links() {
+        let owner = test_utils::owner_key();
+        let functionary = test_utils::functionary_key();
+        let layout = test_utils::signed_layout(&owner, &functionary);
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = DirectoryStore::new(dir.path());
+        for link in test_utils::links(&functionary) {
+            store.put_link(&link).unwrap();
+        }
+        let report = in_toto_verify_with_store(&layout, &[owner.public()], &store, None).unwrap();
+        assert_eq!(report.link_entries().len(), 2);
+
+        // a link of an unrelated step, superseded by a newer run
+        let old = test_utils::sign(
+            Box::new(test_utils::link("lint", &[], &[])),
+            &[&functionary],
+        );
+        store
+            .put("lint.0.link", &serde_json::to_vec(&old).unwrap())
+            .unwrap();
+        let age = |name: &str| {
+            std::fs::File::options()
+                .write(true)
+                .open(dir.path().join(name))
+                .unwrap()
+                .set_modified((Utc::now() - Duration::days(30)).into())
+                .unwrap();
+        };
+        age("lint.0.link");
+        store.put_link(&old).unwrap();
+
+        let policy = RetentionPolicy::new()
+            .max_age_days(7)
+            .prune_superseded()
+            .retain_report(&report);
+        assert_eq!(store.prune(&policy).unwrap(), ["lint.0.link"]);
+        assert_eq!(store.list().unwrap().len(), 3);
+
+        // everything is old now, but the verified links are retained
+        for name in store.list().unwrap() {
+            age(&name);
+        }
+        let policy = RetentionPolicy::new().max_age_days(7);
+        let retained = policy.clone().retain_report(&report);
+        assert_eq!(store.prune(&retained).unwrap().len(), 1);
+        assert!(in_toto_verify_with_store(&layout, &[owner.public()], &store, None).is_ok());
+        assert_eq!(store.prune(&policy).unwrap().len(), 2);
+        assert!(store.list().unwrap().is_empty());
+    }
+}
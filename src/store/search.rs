@@ -0,0 +1,159 @@
+//! Finding the metadata that produced an artifact.
+//!
+//! Answering "which build produced this binary?" from a store of links
+//! otherwise means parsing every entry and comparing digests by hand. The
+//! entries searched are signed links, and attestations in DSSE envelopes of
+//! this crate or of cosign whose payload is an in-toto statement. Other
+//! entries, like layouts or keys kept next to the links, are skipped.
+//! Signatures are not verified, so the results only say where to look.
+
+use crate::crypto::HashValue;
+use crate::models::{
+    CosignEnvelope, EnvelopeFile, Metablock, MetadataWrapper, StatementWrapper, TargetDescription,
+    VirtualTargetPath,
+};
+use crate::Result;
+
+use super::MetadataStore;
+
+/// An entry of a store that produced an artifact with the digest searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Producer {
+    entry: String,
+    step: Option<String>,
+    path: VirtualTargetPath,
+}
+
+impl Producer {
+    /// Name of the entry
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    /// Name of the step, `None` for attestations not naming it
+    pub fn step(&self) -> Option<&str> {
+        self.step.as_deref()
+    }
+
+    /// Path of the artifact with the digest
+    pub fn path(&self) -> &str {
+        self.path.value()
+    }
+}
+
+/// See `MetadataStore::find_producer`.
+pub(super) fn find_producer<S: MetadataStore + ?Sized>(
+    store: &S,
+    digest: &HashValue,
+) -> Result<Vec<Producer>> {
+    let mut producers = Vec::new();
+    for entry in store.list()? {
+        let bytes = match store.get(&entry)? {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+        let mut found = |step: Option<&str>, path: &VirtualTargetPath| {
+            producers.push(Producer {
+                entry: entry.clone(),
+                step: step.map(str::to_string),
+                path: path.clone(),
+            })
+        };
+
+        let metablock: Option<Metablock> = serde_json::from_slice(&bytes).ok();
+        if let Some(MetadataWrapper::Link(link)) = metablock.as_ref().map(Metablock::metadata) {
+            for (path, hashes) in link.products() {
+                // an artifact passed on unchanged was produced before
+                if has_digest(hashes, digest) && link.materials().get(path) != Some(hashes) {
+                    found(Some(link.name()), path);
+                }
+            }
+        } else if let Some(statement) = statement(&bytes) {
+            let step = match &statement {
+                StatementWrapper::Naive(statement) => Some(statement.name()),
+                StatementWrapper::V0_1(_) => None,
+            };
+            for (path, hashes) in statement.subject() {
+                if has_digest(hashes, digest) {
+                    found(step, path);
+                }
+            }
+        }
+    }
+    Ok(producers)
+}
+
+/// The statement in the DSSE envelope `bytes`, if it is one.
+fn statement(bytes: &[u8]) -> Option<StatementWrapper> {
+    let payload = match EnvelopeFile::from_bytes(bytes) {
+        Ok(envelope) => envelope.payload().as_bytes().to_vec(),
+        Err(_) => CosignEnvelope::from_bytes(bytes)
+            .ok()?
+            .decoded_payload()
+            .ok()?,
+    };
+    serde_json::from_slice(&payload).ok()
+}
+
+fn has_digest(hashes: &TargetDescription, digest: &HashValue) -> bool {
+    hashes.values().any(|value| value == digest)
+}
+
+#[cfg(test)]
+mod test {
+    use ring::digest::{digest, SHA256};
+
+    use crate::crypto::HashValue;
+    use crate::models::{
+        link_filename, CosignEnvelope, EnvelopeFile, StatementVer, StatementWrapper,
+    };
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{functionary_key, link, store, FOO_PY, FOO_TAR_GZ};
+
+    fn sha256(content: &[u8]) -> HashValue {
+        HashValue::new(digest(&SHA256, content).as_ref().to_vec())
+    }
+
+    #[test]
+    fn find_producing_links() {
+        let functionary = functionary_key();
+        let store = store(&functionary);
+
+        let producers = store.find_producer(&sha256(FOO_TAR_GZ)).unwrap();
+        assert_eq!(producers.len(), 1);
+        assert_eq!(producers[0].step(), Some("package"));
+        assert_eq!(producers[0].path(), "foo.tar.gz");
+        assert_eq!(
+            producers[0].entry(),
+            link_filename("package", functionary.key_id())
+        );
+        // package passes foo.py on, it was produced by write-code
+        let producers = store.find_producer(&sha256(FOO_PY)).unwrap();
+        let steps: Vec<_> = producers.iter().map(|p| p.step()).collect();
+        assert_eq!(steps, [Some("write-code")]);
+        assert!(store.find_producer(&sha256(b"other")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_producing_attestations() {
+        let build = link("build", &[], &[("a.out", b"binary")]);
+        let statement = StatementWrapper::from_meta(build, None, StatementVer::Naive).unwrap();
+        let payload = String::from_utf8(statement.into_trait().to_bytes().unwrap()).unwrap();
+        let envelope = EnvelopeFile::new(payload, "application/vnd.in-toto+json".into(), vec![]);
+        let cosign = CosignEnvelope::from_envelope(&envelope);
+
+        let mut store = MemoryStore::new();
+        store
+            .put("build.dsse", &envelope.to_bytes().unwrap())
+            .unwrap();
+        store
+            .put("build.cosign", &cosign.to_bytes().unwrap())
+            .unwrap();
+        store.put("notes.txt", b"not metadata").unwrap();
+
+        let producers = store.find_producer(&sha256(b"binary")).unwrap();
+        let entries: Vec<_> = producers.iter().map(|p| p.entry()).collect();
+        assert_eq!(entries, ["build.cosign", "build.dsse"]);
+        assert!(producers.iter().all(|p| p.step() == Some("build")));
+    }
+}
@@ -0,0 +1,299 @@
+//! Rollback protection for metadata fetched from a store.
+//!
+//! Whoever controls a remote store may serve metadata older than what a
+//! client has already seen, e.g. an older layout with weaker rules, or stop
+//! serving updates. A `TrustCache` remembers the newest layout and link
+//! seen under each entry name, and a `RollbackProtectedStore` rejects
+//! fetched metadata older than that.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{MetadataStore, RetentionPolicy};
+use crate::models::{LayoutReference, Metablock, MetadataWrapper};
+use crate::{Error, Result};
+
+/// A layout seen before.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SeenLayout {
+    layout: LayoutReference,
+    expires: DateTime<Utc>,
+}
+
+/// The newest metadata seen so far, by entry name. It is meant to be kept
+/// locally between verifications, see `load` and `save`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustCache {
+    layouts: BTreeMap<String, SeenLayout>,
+    links: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl TrustCache {
+    /// An empty cache, trusting whatever is seen first.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the cache stored at `path`, or an empty cache if there is none.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        match fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store the cache at `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The version of the newest layout seen as `name`, if any.
+    pub fn layout_version(&self, name: &str) -> Option<u64> {
+        self.layouts.get(name).map(|seen| seen.layout.version())
+    }
+
+    /// Check the signed `layout` fetched as `name` against the newest layout
+    /// seen as `name`, and remember it if it is newer.
+    ///
+    /// Fails if `layout` has a lower version, or the same version but
+    /// different content or an earlier expiration.
+    pub fn check_layout(&mut self, name: &str, layout: &Metablock) -> Result<()> {
+        let expires = match layout.metadata() {
+            MetadataWrapper::Layout(layout) => *layout.expires(),
+            MetadataWrapper::Link(_) => {
+                return Err(Error::VerificationFailure(format!(
+                    "{} does not hold a layout",
+                    name
+                )))
+            }
+        };
+        let reference = LayoutReference::of(layout)?;
+        if let Some(seen) = self.layouts.get(name) {
+            let version = reference.version();
+            let seen_version = seen.layout.version();
+            if version < seen_version {
+                return Err(Error::VerificationFailure(format!(
+                    "layout {} has version {}, but version {} was seen before",
+                    name, version, seen_version
+                )));
+            }
+            if version == seen_version && reference != seen.layout {
+                return Err(Error::VerificationFailure(format!(
+                    "layout {} of version {} differs from the one seen before",
+                    name, version
+                )));
+            }
+            if expires < seen.expires {
+                return Err(Error::VerificationFailure(format!(
+                    "layout {} expires at {}, before the one seen before",
+                    name, expires
+                )));
+            }
+        }
+        self.layouts.insert(
+            name.to_string(),
+            SeenLayout {
+                layout: reference,
+                expires,
+            },
+        );
+        Ok(())
+    }
+
+    /// Check that the link fetched as `name`, stored at `modified`, was not
+    /// stored before the newest link seen as `name`, and remember it.
+    pub fn check_link(&mut self, name: &str, modified: DateTime<Utc>) -> Result<()> {
+        if let Some(seen) = self.links.get(name) {
+            if modified < *seen {
+                return Err(Error::VerificationFailure(format!(
+                    "link {} was stored at {}, before the one seen at {}",
+                    name, modified, seen
+                )));
+            }
+        }
+        self.links.insert(name.to_string(), modified);
+        Ok(())
+    }
+}
+
+/// A `MetadataStore` checking all links and layouts fetched from another
+/// store against a `TrustCache`.
+///
+/// Links are entries ending in `.link`, only their age can be checked, so
+/// the wrapped store has to know when entries were stored, see
+/// `MetadataStore::modified`. Layouts are checked by version when fetched
+/// with `get_layout`.
+#[derive(Debug)]
+pub struct RollbackProtectedStore<S> {
+    store: S,
+    cache: Mutex<TrustCache>,
+}
+
+impl<S: MetadataStore> RollbackProtectedStore<S> {
+    /// Protect `store` with `cache`.
+    pub fn new(store: S, cache: TrustCache) -> Self {
+        RollbackProtectedStore {
+            store,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    /// The cache including everything seen so far.
+    pub fn cache(&self) -> TrustCache {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Give back the wrapped store and the cache.
+    pub fn into_parts(self) -> (S, TrustCache) {
+        let cache = self
+            .cache
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        (self.store, cache)
+    }
+
+    /// The signed layout stored as `name`, if it is no older than the
+    /// layouts seen as `name` before. The signatures are not verified.
+    pub fn get_layout(&self, name: &str) -> Result<Option<Metablock>> {
+        let layout = match self.store.get_metablock(name)? {
+            Some(layout) => layout,
+            None => return Ok(None),
+        };
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .check_layout(name, &layout)?;
+        Ok(Some(layout))
+    }
+}
+
+impl<S: MetadataStore> MetadataStore for RollbackProtectedStore<S> {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let bytes = match self.store.get(name)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        if name.ends_with(".link") {
+            let modified = self.store.modified(name)?.ok_or_else(|| {
+                Error::VerificationFailure(format!(
+                    "the age of link {} is unknown, so a rollback cannot be ruled out",
+                    name
+                ))
+            })?;
+            self.cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .check_link(name, modified)?;
+        }
+        Ok(Some(bytes))
+    }
+
+    fn put(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        self.store.put(name, bytes)
+    }
+
+    fn remove(&mut self, name: &str) -> Result<bool> {
+        self.store.remove(name)
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        self.store.list()
+    }
+
+    fn modified(&self, name: &str) -> Result<Option<DateTime<Utc>>> {
+        self.store.modified(name)
+    }
+
+    fn put_link(&mut self, link: &Metablock) -> Result<Vec<String>> {
+        self.store.put_link(link)
+    }
+
+    fn prune(&mut self, policy: &RetentionPolicy) -> Result<Vec<String>> {
+        self.store.prune(policy)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{RollbackProtectedStore, TrustCache};
+    use crate::models::LayoutMetadataBuilder;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils;
+    use crate::verifylib::in_toto_verify_with_store;
+
+    #[test]
+    fn reject_rolled_back_layouts() {
+        let owner = test_utils::owner_key();
+        let layout = |version: u64, days: i64| {
+            let layout = LayoutMetadataBuilder::new()
+                .layout_version(version)
+                .expires(Utc::now() + Duration::days(days))
+                .build()
+                .unwrap();
+            serde_json::to_vec(&test_utils::sign(Box::new(layout), &[&owner])).unwrap()
+        };
+        let mut store = RollbackProtectedStore::new(MemoryStore::new(), TrustCache::new());
+        assert_eq!(store.get_layout("root.layout").unwrap(), None);
+        store.put("root.layout", &layout(2, 10)).unwrap();
+        assert!(store.get_layout("root.layout").unwrap().is_some());
+        let newer = layout(3, 10);
+        store.put("root.layout", &newer).unwrap();
+        assert!(store.get_layout("root.layout").unwrap().is_some());
+        assert_eq!(store.cache().layout_version("root.layout"), Some(3));
+
+        // an older version, the same version with other content
+        store.put("root.layout", &layout(2, 20)).unwrap();
+        assert!(store.get_layout("root.layout").is_err());
+        store.put("root.layout", &layout(3, 20)).unwrap();
+        assert!(store.get_layout("root.layout").is_err());
+        store.put("root.layout", &newer).unwrap();
+        assert!(store.get_layout("root.layout").is_ok());
+
+        // the cache survives a restart
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trust.json");
+        assert_eq!(TrustCache::load(&path).unwrap(), TrustCache::new());
+        let (mut inner, cache) = store.into_parts();
+        cache.save(&path).unwrap();
+        inner.put("root.layout", &layout(1, 30)).unwrap();
+        let store = RollbackProtectedStore::new(inner, TrustCache::load(&path).unwrap());
+        assert!(store.get_layout("root.layout").is_err());
+    }
+
+    #[test]
+    fn reject_rolled_back_links() {
+        let owner = test_utils::owner_key();
+        let functionary = test_utils::functionary_key();
+        let layout = test_utils::signed_layout(&owner, &functionary);
+        let links = test_utils::store(&functionary);
+
+        let store = RollbackProtectedStore::new(links.clone(), TrustCache::new());
+        assert!(in_toto_verify_with_store(&layout, &[owner.public()], &store, None).is_ok());
+        let cache = store.cache();
+
+        // the same links again are fine, older copies of them are not
+        let store = RollbackProtectedStore::new(links.clone(), cache.clone());
+        assert!(in_toto_verify_with_store(&layout, &[owner.public()], &store, None).is_ok());
+        let mut old = MemoryStore::new();
+        for name in links.list().unwrap() {
+            let bytes = links.get(&name).unwrap().unwrap();
+            old.put_modified(&name, &bytes, Utc::now() - Duration::days(1))
+                .unwrap();
+        }
+        let store = RollbackProtectedStore::new(old, cache);
+        assert!(in_toto_verify_with_store(&layout, &[owner.public()], &store, None).is_err());
+    }
+}
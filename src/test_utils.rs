@@ -0,0 +1,194 @@
+//! Helpers for testing an in-toto integration without shipping key files.
+//!
+//! Keys are derived from a seed, so the same seed always gives the same key
+//! and key ID. The canned supply chain has the steps `write-code`, creating
+//! `foo.py`, and `package`, packaging it into `foo.tar.gz`:
+//!
+//! ```
+//! use in_toto::test_utils;
+//! use in_toto::verifylib::in_toto_verify_with_store;
+//!
+//! let owner = test_utils::owner_key();
+//! let functionary = test_utils::functionary_key();
+//! let layout = test_utils::signed_layout(&owner, &functionary);
+//! let store = test_utils::store(&functionary);
+//! let report = in_toto_verify_with_store(&layout, &[owner.public()], &store, None).unwrap();
+//! assert_eq!(report.links().len(), 2);
+//! ```
+//!
+//! Only available with the `test_utils` feature.
+
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use ring::digest::{digest, SHA256};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use crate::crypto::{HashAlgorithm, HashValue, PrivateKey, PublicKey};
+use crate::models::rule::ArtifactRule;
+use crate::models::step::{Command, Step};
+use crate::models::{
+    LayoutMetadata, LayoutMetadataBuilder, LinkMetadata, LinkMetadataBuilder, Metablock,
+    MetablockBuilder, Metadata, TargetDescription, VirtualTargetPath,
+};
+use crate::store::MetadataStore;
+
+pub use crate::store::MemoryStore;
+
+/// The content of `foo.py` in the canned supply chain.
+pub const FOO_PY: &[u8] = b"print(\"Hello in-toto\")\n";
+
+/// The content of `foo.tar.gz` in the canned supply chain.
+pub const FOO_TAR_GZ: &[u8] = b"foo.tar.gz";
+
+/// An ed25519 key derived from `seed`.
+pub fn key(seed: &str) -> PrivateKey {
+    let seed = digest(&SHA256, seed.as_bytes());
+    let pair = Ed25519KeyPair::from_seed_unchecked(seed.as_ref()).expect("seed has 32 bytes");
+    let mut bytes = seed.as_ref().to_vec();
+    bytes.extend_from_slice(pair.public_key().as_ref());
+    PrivateKey::from_ed25519(&bytes).expect("valid ed25519 key")
+}
+
+/// The key of the project owner signing the canned layout.
+pub fn owner_key() -> PrivateKey {
+    key("owner")
+}
+
+/// The key of the functionary signing the canned links.
+pub fn functionary_key() -> PrivateKey {
+    key("functionary")
+}
+
+/// The sha256 description of an artifact with `content`.
+pub fn target(content: &[u8]) -> TargetDescription {
+    let hash = HashValue::new(digest(&SHA256, content).as_ref().to_vec());
+    vec![(HashAlgorithm::Sha256, hash)].into_iter().collect()
+}
+
+fn artifacts(artifacts: &[(&str, &[u8])]) -> BTreeMap<VirtualTargetPath, TargetDescription> {
+    artifacts
+        .iter()
+        .map(|(path, content)| {
+            let path = VirtualTargetPath::new(path.to_string()).expect("valid artifact path");
+            (path, target(content))
+        })
+        .collect()
+}
+
+/// An unsigned link of step `name` with the given materials and products,
+/// each given as path and content.
+pub fn link(name: &str, materials: &[(&str, &[u8])], products: &[(&str, &[u8])]) -> LinkMetadata {
+    LinkMetadataBuilder::new()
+        .name(name.to_string())
+        .materials(artifacts(materials))
+        .products(artifacts(products))
+        .build()
+        .expect("valid link")
+}
+
+/// Wrap `metadata` into a metablock signed by all of `keys`.
+pub fn sign(metadata: Box<dyn Metadata>, keys: &[&PrivateKey]) -> Metablock {
+    MetablockBuilder::from_metadata(metadata)
+        .sign(keys)
+        .expect("signing succeeds")
+        .build()
+}
+
+fn rules(json: &str) -> Vec<ArtifactRule> {
+    serde_json::from_str(json).expect("valid rules")
+}
+
+/// The canned layout, whose steps are to be signed by `functionary`. It
+/// expires in 2100.
+pub fn layout(functionary: &PublicKey) -> LayoutMetadata {
+    LayoutMetadataBuilder::new()
+        .expires(DateTime::from_timestamp(4_102_444_800, 0).expect("valid date"))
+        .readme("in-toto test supply chain".into())
+        .add_key(functionary.clone())
+        .add_step(
+            Step::new("write-code")
+                .threshold(1)
+                .add_key(functionary.key_id().clone())
+                .expected_products(rules(r#"[["CREATE", "foo.py"], ["DISALLOW", "*"]]"#)),
+        )
+        .add_step(
+            Step::new("package")
+                .threshold(1)
+                .add_key(functionary.key_id().clone())
+                .expected_command(Command::new(["tar", "zcvf", "foo.tar.gz", "foo.py"]))
+                .expected_materials(rules(
+                    r#"[["MATCH", "foo.py", "WITH", "PRODUCTS", "FROM", "write-code"],
+                        ["DISALLOW", "*"]]"#,
+                ))
+                .expected_products(rules(
+                    r#"[["CREATE", "foo.tar.gz"], ["ALLOW", "foo.py"], ["DISALLOW", "*"]]"#,
+                )),
+        )
+        .build()
+        .expect("valid layout")
+}
+
+/// The canned layout signed by `owner`.
+pub fn signed_layout(owner: &PrivateKey, functionary: &PrivateKey) -> Metablock {
+    sign(Box::new(layout(functionary.public())), &[owner])
+}
+
+/// The links of the canned supply chain, signed by `functionary`.
+pub fn links(functionary: &PrivateKey) -> Vec<Metablock> {
+    let write_code = link("write-code", &[], &[("foo.py", FOO_PY)]);
+    let package = LinkMetadataBuilder::from_metadata(link(
+        "package",
+        &[("foo.py", FOO_PY)],
+        &[("foo.py", FOO_PY), ("foo.tar.gz", FOO_TAR_GZ)],
+    ))
+    .command(Command::new(["tar", "zcvf", "foo.tar.gz", "foo.py"]))
+    .build()
+    .expect("valid link");
+    vec![
+        sign(Box::new(write_code), &[functionary]),
+        sign(Box::new(package), &[functionary]),
+    ]
+}
+
+/// A `MemoryStore` holding the links of the canned supply chain, signed by
+/// `functionary`.
+pub fn store(functionary: &PrivateKey) -> MemoryStore {
+    let mut store = MemoryStore::new();
+    for link in links(functionary) {
+        store.put_link(&link).expect("links are signed");
+    }
+    store
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::verifylib::in_toto_verify_with_store;
+
+    #[test]
+    fn deterministic_keys() {
+        assert_eq!(
+            key("alice").public().key_id(),
+            key("alice").public().key_id()
+        );
+        assert_ne!(key("alice").public().key_id(), key("bob").public().key_id());
+    }
+
+    #[test]
+    fn canned_supply_chain_verifies() {
+        let owner = owner_key();
+        let functionary = functionary_key();
+        let layout = signed_layout(&owner, &functionary);
+        let report =
+            in_toto_verify_with_store(&layout, &[owner.public()], &store(&functionary), None)
+                .unwrap();
+        assert_eq!(report.links()["package"].products().len(), 2);
+
+        // links of a different functionary are not accepted
+        let other = key("mallory");
+        assert!(
+            in_toto_verify_with_store(&layout, &[owner.public()], &store(&other), None).is_err()
+        );
+    }
+}
@@ -0,0 +1,632 @@
+//! Tracing the files a command opens, to record them as artifacts.
+//!
+//! The command and everything it spawns are traced with `ptrace`, every
+//! successful `open`, `openat`, `openat2`, `creat` and `rename` is noted as
+//! a read or a write of the file it names. The addresses passed to
+//! `connect`, `sendto` and `sendmmsg` are noted as contacted, and DNS
+//! queries and answers as host names and what they resolved to. This is
+//! Linux on x86_64 only and behind the feature `tracer`, see
+//! `runlib::in_toto_run_traced`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::Utc;
+
+use crate::models::byproducts::ByProducts;
+use crate::models::network::NetworkAccesses;
+use crate::runlib::{OutputReaders, RunOptions};
+use crate::{Error, Result};
+
+const SYS_OPEN: u64 = 2;
+const SYS_CLOSE: u64 = 3;
+const SYS_CONNECT: u64 = 42;
+const SYS_SENDTO: u64 = 44;
+const SYS_RECVFROM: u64 = 45;
+const SYS_SENDMSG: u64 = 46;
+const SYS_CREAT: u64 = 85;
+const SYS_RENAME: u64 = 82;
+const SYS_OPENAT: u64 = 257;
+const SYS_RENAMEAT: u64 = 264;
+const SYS_RENAMEAT2: u64 = 316;
+const SYS_SENDMMSG: u64 = 307;
+const SYS_OPENAT2: u64 = 437;
+
+/// Longest path read from a tracee.
+const PATH_MAX: usize = 4096;
+
+/// Largest DNS message read from a tracee.
+const DNS_MAX: usize = 4096;
+
+/// Port of DNS servers.
+const DNS_PORT: u16 = 53;
+
+/// Size of `struct mmsghdr`, starting with a `struct msghdr`.
+const MMSGHDR_SIZE: u64 = 64;
+
+/// Most messages of a `sendmmsg` that are read.
+const MMSG_MAX: u64 = 16;
+
+/// The files a traced command read and wrote, as absolute paths.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAccesses {
+    read: BTreeSet<PathBuf>,
+    written: BTreeSet<PathBuf>,
+}
+
+impl FileAccesses {
+    /// The files opened for reading
+    pub fn read(&self) -> &BTreeSet<PathBuf> {
+        &self.read
+    }
+
+    /// The files opened for writing, created or renamed to
+    pub fn written(&self) -> &BTreeSet<PathBuf> {
+        &self.written
+    }
+
+    /// The regular files below `root` that were read but not written, i.e.
+    /// the materials of the command, relative to `root`.
+    pub fn materials_under(&self, root: &Path) -> Vec<PathBuf> {
+        files_under(self.read.difference(&self.written), root)
+    }
+
+    /// The regular files below `root` that were written and still exist,
+    /// i.e. the products of the command, relative to `root`.
+    pub fn products_under(&self, root: &Path) -> Vec<PathBuf> {
+        files_under(self.written.iter(), root)
+    }
+}
+
+fn files_under<'a>(paths: impl Iterator<Item = &'a PathBuf>, root: &Path) -> Vec<PathBuf> {
+    paths
+        .filter(|path| path.is_file())
+        .filter_map(|path| path.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+/// A system call a tracee is in, as seen at its entry.
+enum Pending {
+    Open {
+        path: PathBuf,
+        flags: i32,
+    },
+    Rename {
+        to: PathBuf,
+    },
+    Close {
+        fd: i32,
+    },
+    Connect {
+        fd: i32,
+        to: SocketAddr,
+    },
+    Send {
+        fd: i32,
+        to: Option<SocketAddr>,
+        payloads: Vec<Vec<u8>>,
+    },
+    Recv {
+        fd: i32,
+        buf: u64,
+        from: u64,
+    },
+    Other,
+}
+
+/// Everything noted while tracing.
+#[derive(Default)]
+struct Recording {
+    files: FileAccesses,
+    network: NetworkAccesses,
+    /// The peers of connected sockets, by tracee and file descriptor
+    peers: HashMap<(libc::pid_t, i32), SocketAddr>,
+}
+
+/// Run `cmd_args` as `options` say like `runlib::run_command_with_options`, tracing the
+/// files it and its children open. What they contacted is recorded in the
+/// byproduct `NETWORK_BYPRODUCT`, see `models::network`.
+pub fn trace_command(
+    cmd_args: &[&str],
+    options: &RunOptions,
+) -> Result<(ByProducts, FileAccesses)> {
+    if cmd_args.is_empty() {
+        return Ok((ByProducts::new(), FileAccesses::default()));
+    }
+    let mut cmd = process::Command::new(cmd_args[0]);
+    OutputReaders::configure(cmd.args(&cmd_args[1..]), options.output);
+    if let Some(dir) = &options.run_dir {
+        cmd.current_dir(dir);
+    }
+    options.command_env.apply(&mut cmd);
+    // SAFETY: only the async-signal-safe ptrace is called between fork and exec
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let start = Utc::now();
+    let mut child = cmd.spawn().map_err(|err| {
+        Error::IllegalArgument(format!("Could not trace command {:?}: {}", cmd_args, err))
+    })?;
+    let readers = OutputReaders::start(&mut child, options.output);
+
+    let root = child.id() as libc::pid_t;
+    let (status, recording) = trace(root)?;
+    let end = Utc::now();
+
+    let (stdout, stderr) = readers.finish()?;
+    let byproducts = ByProducts::new()
+        .set_stdout(stdout)
+        .set_stderr(stderr)
+        .set_return_value(status);
+    let byproducts = recording.network.to_byproducts(byproducts)?;
+    let byproducts = options.stamp(byproducts, start, end);
+    Ok((options.check_exit(byproducts)?, recording.files))
+}
+
+/// Trace `root`, stopped at its exec, and all its descendants until `root`
+/// exits, returning its exit code.
+fn trace(root: libc::pid_t) -> Result<(i32, Recording)> {
+    let mut recording = Recording::default();
+    // the system call each tracee is in, if any
+    let mut tracees: HashMap<libc::pid_t, Option<Pending>> = HashMap::new();
+    let mut exit_code = None;
+
+    // only wait for the children of this thread, so that other threads'
+    // children are left alone
+    let wait_flags = libc::__WALL | libc::__WNOTHREAD;
+    let mut status = 0;
+    // SAFETY: plain system calls on a child of this thread
+    unsafe {
+        if libc::waitpid(root, &mut status, wait_flags) == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let options = libc::PTRACE_O_TRACESYSGOOD
+            | libc::PTRACE_O_TRACEFORK
+            | libc::PTRACE_O_TRACEVFORK
+            | libc::PTRACE_O_TRACECLONE
+            | libc::PTRACE_O_TRACEEXEC
+            | libc::PTRACE_O_EXITKILL;
+        libc::ptrace(libc::PTRACE_SETOPTIONS, root, 0, options);
+        libc::ptrace(libc::PTRACE_SYSCALL, root, 0, 0);
+    }
+    tracees.insert(root, None);
+
+    while !tracees.is_empty() {
+        // SAFETY: as above
+        let pid = unsafe { libc::waitpid(-1, &mut status, wait_flags) };
+        if pid == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err.into());
+        }
+        if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+            tracees.remove(&pid);
+            if pid == root {
+                exit_code = Some(match libc::WIFEXITED(status) {
+                    true => libc::WEXITSTATUS(status),
+                    false => -libc::WTERMSIG(status),
+                });
+            }
+            continue;
+        }
+        if !libc::WIFSTOPPED(status) {
+            continue;
+        }
+        let signal = libc::WSTOPSIG(status);
+        let mut deliver = 0;
+        match tracees.get_mut(&pid) {
+            // the initial stop of an auto-attached child
+            None => {
+                tracees.insert(pid, None);
+            }
+            Some(pending) if signal == libc::SIGTRAP | 0x80 => match pending.take() {
+                None => *pending = Some(syscall_entry(pid)),
+                Some(call) => recording.syscall_exit(pid, call),
+            },
+            // fork, clone and exec events
+            Some(_) if signal == libc::SIGTRAP && status >> 16 != 0 => (),
+            Some(_) => deliver = signal,
+        }
+        // SAFETY: `pid` is a stopped tracee
+        unsafe {
+            libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, deliver);
+        }
+    }
+    let exit_code =
+        exit_code.ok_or_else(|| Error::RunLibError("Traced process vanished".to_string()))?;
+    Ok((exit_code, recording))
+}
+
+fn registers(pid: libc::pid_t) -> Option<libc::user_regs_struct> {
+    let mut regs = MaybeUninit::<libc::user_regs_struct>::uninit();
+    // SAFETY: `pid` is a tracee stopped at a system call
+    unsafe {
+        match libc::ptrace(libc::PTRACE_GETREGS, pid, 0, regs.as_mut_ptr()) {
+            -1 => None,
+            _ => Some(regs.assume_init()),
+        }
+    }
+}
+
+fn syscall_entry(pid: libc::pid_t) -> Pending {
+    let regs = match registers(pid) {
+        Some(regs) => regs,
+        None => return Pending::Other,
+    };
+    let open = |dirfd: u64, path: u64, flags: i32| match resolve(pid, dirfd, path) {
+        Some(path) => Pending::Open { path, flags },
+        None => Pending::Other,
+    };
+    let rename = |dirfd: u64, path: u64| match resolve(pid, dirfd, path) {
+        Some(to) => Pending::Rename { to },
+        None => Pending::Other,
+    };
+    let at_fdcwd = libc::AT_FDCWD as u64;
+    match regs.orig_rax {
+        SYS_OPEN => open(at_fdcwd, regs.rdi, regs.rsi as i32),
+        SYS_CREAT => open(at_fdcwd, regs.rdi, libc::O_CREAT | libc::O_WRONLY),
+        SYS_OPENAT => open(regs.rdi, regs.rsi, regs.rdx as i32),
+        // the flags come first in `struct open_how`
+        SYS_OPENAT2 => match peek(pid, regs.rdx) {
+            Some(flags) => open(regs.rdi, regs.rsi, flags as i32),
+            None => Pending::Other,
+        },
+        SYS_RENAME => rename(at_fdcwd, regs.rsi),
+        SYS_RENAMEAT | SYS_RENAMEAT2 => rename(regs.rdx, regs.r10),
+        SYS_CLOSE => Pending::Close {
+            fd: regs.rdi as i32,
+        },
+        SYS_CONNECT => match read_sockaddr(pid, regs.rsi, regs.rdx) {
+            Some(to) => Pending::Connect {
+                fd: regs.rdi as i32,
+                to,
+            },
+            None => Pending::Other,
+        },
+        SYS_SENDTO => Pending::Send {
+            fd: regs.rdi as i32,
+            to: read_sockaddr(pid, regs.r8, regs.r9),
+            payloads: read_bytes(pid, regs.rsi, regs.rdx as usize)
+                .into_iter()
+                .collect(),
+        },
+        SYS_SENDMSG => read_msghdr(pid, regs.rsi)
+            .map(|(to, payload)| Pending::Send {
+                fd: regs.rdi as i32,
+                to,
+                payloads: payload.into_iter().collect(),
+            })
+            .unwrap_or(Pending::Other),
+        SYS_SENDMMSG => {
+            let mut to = None;
+            let mut payloads = Vec::new();
+            for i in 0..regs.rdx.min(MMSG_MAX) {
+                if let Some((addr, payload)) = read_msghdr(pid, regs.rsi + i * MMSGHDR_SIZE) {
+                    to = to.or(addr);
+                    payloads.extend(payload);
+                }
+            }
+            Pending::Send {
+                fd: regs.rdi as i32,
+                to,
+                payloads,
+            }
+        }
+        SYS_RECVFROM => Pending::Recv {
+            fd: regs.rdi as i32,
+            buf: regs.rsi,
+            from: regs.r8,
+        },
+        _ => Pending::Other,
+    }
+}
+
+impl Recording {
+    fn syscall_exit(&mut self, pid: libc::pid_t, call: Pending) {
+        let ret = match registers(pid) {
+            Some(regs) => regs.rax as i64,
+            None => return,
+        };
+        // non-blocking sockets connect in the background
+        let connecting =
+            matches!(call, Pending::Connect { .. }) && ret == -(libc::EINPROGRESS as i64);
+        if ret < 0 && !connecting {
+            return;
+        }
+        match call {
+            Pending::Open { path, flags } => {
+                let mode = flags & libc::O_ACCMODE;
+                if mode != libc::O_WRONLY {
+                    self.files.read.insert(path.clone());
+                }
+                if mode != libc::O_RDONLY || flags & (libc::O_CREAT | libc::O_TRUNC) != 0 {
+                    self.files.written.insert(path);
+                }
+            }
+            Pending::Rename { to } => {
+                self.files.written.insert(to);
+            }
+            Pending::Close { fd } => {
+                self.peers.remove(&(pid, fd));
+            }
+            Pending::Connect { fd, to } => {
+                self.network.add_address(to);
+                self.peers.insert((pid, fd), to);
+            }
+            Pending::Send { fd, to, payloads } => {
+                let to = match to.or_else(|| self.peers.get(&(pid, fd)).copied()) {
+                    Some(to) => to,
+                    None => return,
+                };
+                self.network.add_address(to);
+                if to.port() == DNS_PORT {
+                    for host in payloads.iter().filter_map(|p| parse_dns(p)) {
+                        self.network.add_host(host.0, host.1);
+                    }
+                }
+            }
+            Pending::Recv { fd, buf, from } => {
+                let from =
+                    read_sockaddr_ptr(pid, from).or_else(|| self.peers.get(&(pid, fd)).copied());
+                if from.is_some_and(|from| from.port() == DNS_PORT) {
+                    let response = read_bytes(pid, buf, ret as usize);
+                    if let Some((host, resolved)) = response.as_deref().and_then(parse_dns) {
+                        self.network.add_host(host, resolved);
+                    }
+                }
+            }
+            Pending::Other => (),
+        }
+    }
+}
+
+/// The address `struct sockaddr` of `len` bytes at `addr` holds, if it is
+/// an IPv4 or IPv6 address.
+fn read_sockaddr(pid: libc::pid_t, addr: u64, len: u64) -> Option<SocketAddr> {
+    if addr == 0 {
+        return None;
+    }
+    parse_sockaddr(&read_bytes(pid, addr, (len as usize).min(32))?)
+}
+
+/// Like `read_sockaddr`, for `recvfrom` which returns the address itself.
+/// Its length follows the buffer, the size of `sockaddr_in6` is enough.
+fn read_sockaddr_ptr(pid: libc::pid_t, addr: u64) -> Option<SocketAddr> {
+    read_sockaddr(pid, addr, 28)
+}
+
+fn parse_sockaddr(bytes: &[u8]) -> Option<SocketAddr> {
+    let family = u16::from_ne_bytes([*bytes.first()?, *bytes.get(1)?]) as i32;
+    let port = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+    let ip = match family {
+        libc::AF_INET => {
+            let octets: [u8; 4] = bytes.get(4..8)?.try_into().ok()?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        libc::AF_INET6 => {
+            let octets: [u8; 16] = bytes.get(8..24)?.try_into().ok()?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// The destination and the first buffer of the `struct msghdr` at `addr`.
+fn read_msghdr(pid: libc::pid_t, addr: u64) -> Option<(Option<SocketAddr>, Option<Vec<u8>>)> {
+    // msg_name, msg_namelen, msg_iov, msg_iovlen
+    let name = peek(pid, addr)?;
+    let name_len = peek(pid, addr + 8)? & 0xffff_ffff;
+    let iov = peek(pid, addr + 16)?;
+    let iov_len = peek(pid, addr + 24)?;
+    let payload = match iov_len {
+        0 => None,
+        _ => read_bytes(pid, peek(pid, iov)?, peek(pid, iov + 8)? as usize),
+    };
+    Some((read_sockaddr(pid, name, name_len), payload))
+}
+
+/// The name asked for by the DNS message `message`, with the addresses
+/// answered for it if it is a response.
+fn parse_dns(message: &[u8]) -> Option<(String, Vec<IpAddr>)> {
+    let count = |at: usize| {
+        Some(u16::from_be_bytes([
+            *message.get(at)?,
+            *message.get(at + 1)?,
+        ]))
+    };
+    let questions = count(4)?;
+    let answers = count(6)?;
+    if questions != 1 {
+        return None;
+    }
+    let (host, mut at) = parse_dns_name(message, 12)?;
+    // type and class
+    at += 4;
+    let mut resolved = Vec::new();
+    for _ in 0..answers {
+        let (_, next) = parse_dns_name(message, at)?;
+        let typ = count(next)?;
+        let len = count(next + 8)? as usize;
+        let data = message.get(next + 10..next + 10 + len)?;
+        match (typ, data.len()) {
+            (1, 4) => resolved.push(IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(data).ok()?))),
+            (28, 16) => resolved.push(IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(data).ok()?))),
+            _ => (),
+        }
+        at = next + 10 + len;
+    }
+    Some((host, resolved))
+}
+
+/// The domain name at `at` in `message` and the position after it.
+fn parse_dns_name(message: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // bounds the pointers followed
+    for _ in 0..128 {
+        let len = *message.get(at)? as usize;
+        match len {
+            0 => {
+                let name = labels.join(".").to_lowercase();
+                return Some((name, end.unwrap_or(at + 1)));
+            }
+            // a pointer to an earlier name
+            l if l & 0xc0 == 0xc0 => {
+                let pointer = (l & 0x3f) << 8 | *message.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = pointer;
+            }
+            l => {
+                let label = message.get(at + 1..at + 1 + l)?;
+                labels.push(String::from_utf8(label.to_vec()).ok()?);
+                at += 1 + l;
+            }
+        }
+    }
+    None
+}
+
+/// The absolute path the tracee `pid` names with `path` relative to `dirfd`.
+fn resolve(pid: libc::pid_t, dirfd: u64, path: u64) -> Option<PathBuf> {
+    let path = PathBuf::from(read_string(pid, path)?);
+    if path.is_absolute() {
+        return Some(path);
+    }
+    let dir = match dirfd as i32 {
+        libc::AT_FDCWD => format!("/proc/{}/cwd", pid),
+        fd => format!("/proc/{}/fd/{}", pid, fd),
+    };
+    Some(std::fs::read_link(dir).ok()?.join(path))
+}
+
+fn peek(pid: libc::pid_t, addr: u64) -> Option<u64> {
+    // SAFETY: reading the memory of a stopped tracee, errors are told apart
+    // from data by errno
+    unsafe {
+        *libc::__errno_location() = 0;
+        let word = libc::ptrace(libc::PTRACE_PEEKDATA, pid, addr, 0);
+        match word == -1 && *libc::__errno_location() != 0 {
+            true => None,
+            false => Some(word as u64),
+        }
+    }
+}
+
+fn read_bytes(pid: libc::pid_t, addr: u64, len: usize) -> Option<Vec<u8>> {
+    let len = len.min(DNS_MAX);
+    let mut bytes = Vec::with_capacity(len + 8);
+    let mut addr = addr;
+    while bytes.len() < len {
+        bytes.extend_from_slice(&peek(pid, addr)?.to_ne_bytes());
+        addr += 8;
+    }
+    bytes.truncate(len);
+    Some(bytes)
+}
+
+fn read_string(pid: libc::pid_t, addr: u64) -> Option<OsString> {
+    let mut bytes = Vec::new();
+    let mut addr = addr;
+    while bytes.len() < PATH_MAX {
+        let word = peek(pid, addr)?.to_ne_bytes();
+        match word.iter().position(|b| *b == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&word[..end]);
+                return Some(OsString::from_vec(bytes));
+            }
+            None => bytes.extend_from_slice(&word),
+        }
+        addr += word.len() as u64;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use super::{parse_dns, trace_command};
+    use crate::models::network::NetworkAccesses;
+    use crate::runlib::RunOptions;
+
+    #[test]
+    fn trace_file_accesses() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("in.txt"), "hello").unwrap();
+        fs::write(dir.path().join("unused.txt"), "unused").unwrap();
+        let (byproducts, accesses) = trace_command(
+            &["sh", "-c", "cat in.txt > out.txt && (cat out.txt; exit 3)"],
+            &RunOptions::new().run_dir(dir.path().to_str().unwrap()),
+        )
+        .unwrap();
+        assert_eq!(byproducts.stdout(), "hello");
+        assert_eq!(byproducts.return_value(), 3);
+
+        let root = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            accesses.materials_under(&root),
+            [root.join("in.txt").strip_prefix(&root).unwrap()]
+        );
+        assert_eq!(
+            accesses.products_under(&root),
+            [root.join("out.txt").strip_prefix(&root).unwrap()]
+        );
+    }
+
+    #[test]
+    fn trace_network_accesses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut request = String::new();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_to_string(&mut request).unwrap();
+            request
+        });
+        let script = format!("echo hi >/dev/tcp/{}/{}", address.ip(), address.port());
+        let (byproducts, _) = trace_command(&["bash", "-c", &script], &RunOptions::new()).unwrap();
+        assert_eq!(server.join().unwrap(), "hi\n");
+
+        let network = NetworkAccesses::from_byproducts(&byproducts)
+            .unwrap()
+            .unwrap();
+        assert!(network.addresses().contains(&address));
+        assert!(network.verify_allowed(&[]).is_ok());
+    }
+
+    #[test]
+    fn parse_dns_messages() {
+        // a query for example.com and its answer, the name compressed
+        let query = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\x01\x00\x01";
+        assert_eq!(parse_dns(query), Some(("example.com".into(), vec![])));
+        let mut answer = query.to_vec();
+        answer[2] = 0x81;
+        answer[7] = 1;
+        answer.extend(b"\xc0\x0c\x00\x01\x00\x01\x00\x00\x0e\x10\x00\x04\x5d\xb8\xd8\x22");
+        assert_eq!(
+            parse_dns(&answer),
+            Some(("example.com".into(), vec!["93.184.216.34".parse().unwrap()]))
+        );
+        assert_eq!(parse_dns(&answer[..answer.len() - 1]), None);
+    }
+}
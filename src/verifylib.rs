@@ -1 +1,1330 @@
 //! A tool to be used by the client to perform verification on the final product.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, warn};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::crypto::{KeyId, PublicKey};
+use crate::metrics::{Metrics, MetricsHook, VerificationStage};
+use crate::models::attempt::Attempt;
+use crate::models::inspection::Inspection;
+use crate::models::rule::ArtifactRule;
+use crate::models::step::{CommandNormalization, Step};
+use crate::models::{
+    custody_link, link_filename, KeyBundle, LayoutMetadata, LinkMetadata, Metablock,
+    MetadataLimits, MetadataWrapper, TargetDescription, VirtualTargetPath,
+};
+use crate::resolver::ResolverRegistry;
+use crate::runlib::{in_toto_run_with_options, OutputMode, RecordOptions, RunOptions};
+use crate::store::{DirectoryStore, MetadataStore};
+use crate::{Error, Result};
+
+#[cfg(feature = "attestation-layout")]
+mod attestation_layout;
+mod cache;
+mod compliance;
+mod context;
+mod path_matching;
+mod policy;
+mod provenance;
+mod render;
+mod rule_tester;
+mod snapshot;
+mod subject;
+mod watch;
+
+#[cfg(feature = "attestation-layout")]
+pub use attestation_layout::{
+    AttestationLayout, AttestationStep, ExpectedPredicate, STATEMENT_TYPES,
+};
+pub use cache::{VerificationCache, MIN_CACHE_KEY_LEN};
+pub use compliance::ComplianceReport;
+pub use context::{Tenant, TenantState, VerifierContext};
+pub use path_matching::PathMatching;
+pub use policy::{TrustPolicy, TRUST_POLICY_FILENAME};
+pub use provenance::{
+    parse_npm_attestations, parse_pypi_provenance, verify_package, PackageProvenance, PackageTrust,
+    ProvenanceSubject, NPM_PUBLISH_V0_1, PYPI_PUBLISH_V1, SLSA_PROVENANCE_V1, STATEMENT_V1,
+};
+pub use render::{render_failure, ReportFormat, SNIPPET_LINES};
+pub use rule_tester::RuleTester;
+pub use snapshot::VerifierSnapshot;
+pub use subject::{verify_subject, AttestationTrust, IN_TOTO_PAYLOAD_TYPE};
+pub use watch::{sign_policy_file, PolicyWatch, PolicyWatcher, POLICY_SIGNATURE_EXTENSION};
+
+/// The outcome of an inspection run during verification.
+///
+/// Inspections are recorded like steps, but their link is not signed and only
+/// lives as long as the verification. It is kept here so tooling can look at
+/// what an inspection actually saw.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectionResult {
+    name: String,
+    link: LinkMetadata,
+}
+
+impl InspectionResult {
+    /// Name of the inspection
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The transient link recorded while running the inspection, holding its
+    /// materials, products and byproducts
+    pub fn link(&self) -> &LinkMetadata {
+        &self.link
+    }
+
+    /// The return value of the inspection command
+    pub fn return_value(&self) -> i32 {
+        self.link.byproducts().return_value()
+    }
+}
+
+/// A functionary whose link of a step was accepted.
+///
+/// It displays as the annotation of its signature with a short key ID, e.g.
+/// `alice@corp / job 1234 (776a00e2)`, or as the key ID if there is none.
+/// The annotation is not authenticated, see `Signature::with_signer_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signer {
+    key_id: KeyId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signer_id: Option<String>,
+}
+
+impl Signer {
+    /// The key ID of the functionary, as verified
+    pub fn key_id(&self) -> &KeyId {
+        &self.key_id
+    }
+
+    /// Who signed according to the annotation of the signature, if any
+    pub fn signer_id(&self) -> Option<&str> {
+        self.signer_id.as_deref()
+    }
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.signer_id {
+            Some(signer_id) => write!(f, "{} ({:.8})", signer_id, self.key_id.to_string()),
+            None => write!(f, "{}", self.key_id),
+        }
+    }
+}
+
+/// Everything a successful verification has accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    layout: LayoutMetadata,
+    links: BTreeMap<String, LinkMetadata>,
+    link_entries: BTreeSet<String>,
+    signers: BTreeMap<String, Vec<Signer>>,
+    inspections: Vec<InspectionResult>,
+}
+
+impl VerificationReport {
+    /// The verified layout
+    pub fn layout(&self) -> &LayoutMetadata {
+        &self.layout
+    }
+
+    /// The verified link of each step, by step name
+    pub fn links(&self) -> &BTreeMap<String, LinkMetadata> {
+        &self.links
+    }
+
+    /// The names of the store entries holding the accepted links, e.g. to
+    /// retain them when pruning the store
+    pub fn link_entries(&self) -> &BTreeSet<String> {
+        &self.link_entries
+    }
+
+    /// The functionaries whose links were accepted, by step name
+    pub fn signers(&self) -> &BTreeMap<String, Vec<Signer>> {
+        &self.signers
+    }
+
+    /// The results of all inspections, in the order they were run
+    pub fn inspections(&self) -> &[InspectionResult] {
+        &self.inspections
+    }
+
+    /// The result of the inspection named `name`, if any
+    pub fn inspection(&self, name: &str) -> Option<&InspectionResult> {
+        self.inspections.iter().find(|i| i.name == name)
+    }
+}
+
+/// Options of `in_toto_verify_with_options`.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use in_toto::verifylib::VerifyOptions;
+/// // e.g. for an admission webhook with a timeout of 10 seconds
+/// let options = VerifyOptions::new().timeout(Duration::from_secs(8));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyOptions {
+    deadline: Option<Instant>,
+    limits: MetadataLimits,
+    max_validity: Option<Duration>,
+    min_validity: Option<Duration>,
+    metrics: Option<MetricsHook>,
+    command_normalization: CommandNormalization,
+    path_matching: PathMatching,
+    inspection_output: OutputMode,
+    resolvers: Option<Arc<ResolverRegistry>>,
+}
+
+impl VerifyOptions {
+    /// Verify without a deadline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Abort verification with `Error::Timeout` if it is not done at
+    /// `deadline`, killing running inspections
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Abort verification with `Error::Timeout` if it is not done within
+    /// `timeout` from now, see `deadline`
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    /// Reject the layout and links exceeding `limits`, by default
+    /// `MetadataLimits::new()`
+    pub fn limits(mut self, limits: MetadataLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reject layouts expiring later than `max_validity` from now, e.g. to
+    /// have owners sign layouts again at least once a year
+    pub fn max_validity(mut self, max_validity: Duration) -> Self {
+        self.max_validity = Some(max_validity);
+        self
+    }
+
+    /// Reject layouts expiring within `min_validity` from now, so a layout
+    /// about to expire is noticed while there is time to sign it again
+    pub fn min_validity(mut self, min_validity: Duration) -> Self {
+        self.min_validity = Some(min_validity);
+        self
+    }
+
+    /// Report each verification, its duration and the stage it failed at
+    /// to `metrics`
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(MetricsHook(metrics));
+        self
+    }
+
+    /// Normalize commands as `command_normalization` says before comparing
+    /// those of links to the expected commands of their steps
+    pub fn command_normalization(mut self, command_normalization: CommandNormalization) -> Self {
+        self.command_normalization = command_normalization;
+        self
+    }
+
+    /// Echo and record the output of inspections as `inspection_output`
+    /// says, e.g. `OutputMode::CaptureOnly` to keep it off the stdout of a
+    /// service. By default it is echoed.
+    pub fn inspection_output(mut self, inspection_output: OutputMode) -> Self {
+        self.inspection_output = inspection_output;
+        self
+    }
+
+    /// Hash non-file ITE-4 artifacts the inspections record with the
+    /// resolver `resolvers` holds for their scheme, see
+    /// `RecordOptions::resolvers`. Verifications with resolvers are not
+    /// cached, see `in_toto_verify_cached_with_options`.
+    pub fn resolvers(mut self, resolvers: Arc<ResolverRegistry>) -> Self {
+        self.resolvers = Some(resolvers);
+        self
+    }
+
+    /// Canonicalize artifact paths as `path_matching` says before applying
+    /// artifact rules, e.g. for links recorded on Windows
+    pub fn path_matching(mut self, path_matching: PathMatching) -> Self {
+        self.path_matching = path_matching;
+        self
+    }
+
+    /// What of these options the outcome of a verification depends on, for
+    /// the digests of `VerificationCache`.
+    pub(crate) fn cache_key(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?}",
+            self.limits, self.command_normalization, self.path_matching, self.inspection_output
+        )
+    }
+
+    /// Check the expiration of `layout` against the validity bounds.
+    fn check_validity(&self, layout: &LayoutMetadata) -> Result<()> {
+        let remaining = (*layout.expires() - Utc::now())
+            .to_std()
+            .unwrap_or_default();
+        if let Some(max_validity) = self.max_validity {
+            if remaining > max_validity {
+                return Err(Error::VerificationFailure(format!(
+                    "layout expires at {}, more than {}s from now",
+                    layout.expires(),
+                    max_validity.as_secs()
+                )));
+            }
+        }
+        if let Some(min_validity) = self.min_validity {
+            if remaining < min_validity {
+                return Err(Error::VerificationFailure(format!(
+                    "layout expires at {}, less than {}s from now",
+                    layout.expires(),
+                    min_validity.as_secs()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail with `Error::Timeout` if the deadline passed before `item_name`
+    /// is verified.
+    fn check_deadline(&self, item_name: &str) -> Result<()> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Error::Timeout(format!(
+                "verification was aborted at {}",
+                item_name
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Verifies a supply chain against the signed `layout`, returning what was
+/// verified as a `VerificationReport`, wrapped in `Result`.
+///
+/// # Arguments
+///
+/// * `layout` - The signed layout.
+/// * `layout_keys` - The keys of the project owners, all of whom have to have signed `layout`.
+/// * `link_dir` - The directory holding the links of the steps, named as given by `FILENAME_FORMAT`.
+/// * `inspection_dir` - The directory the inspections are run in and record their artifacts from. If `None` is provided, the current directory is assumed as default.
+///
+/// Verification fails if the layout is badly signed or expired, a step has
+/// fewer links of authorized functionaries than its threshold, links of a
+/// step disagree on their artifacts, an inspection returns non-zero, or any
+/// artifact rule of a step or inspection is not met.
+pub fn in_toto_verify(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    link_dir: &str,
+    inspection_dir: Option<&str>,
+) -> Result<VerificationReport> {
+    in_toto_verify_with_store(
+        layout,
+        layout_keys,
+        &DirectoryStore::new(link_dir),
+        inspection_dir,
+    )
+}
+
+/// Verifies a supply chain like `in_toto_verify`, reading the links of the
+/// steps from `store` instead of a directory.
+pub fn in_toto_verify_with_store(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+) -> Result<VerificationReport> {
+    in_toto_verify_with_options(
+        layout,
+        layout_keys,
+        store,
+        inspection_dir,
+        &VerifyOptions::new(),
+    )
+}
+
+/// Verifies a supply chain like `in_toto_verify_with_store`, as `options`
+/// say.
+pub fn in_toto_verify_with_options(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+    options: &VerifyOptions,
+) -> Result<VerificationReport> {
+    let start = Instant::now();
+    let mut stage = VerificationStage::Limits;
+    let result = verify_stages(
+        layout,
+        layout_keys,
+        store,
+        inspection_dir,
+        options,
+        &mut stage,
+    );
+    if let Some(MetricsHook(metrics)) = &options.metrics {
+        metrics.verification(start.elapsed(), result.as_ref().err().map(|_| stage));
+    }
+    result
+}
+
+/// Verify like `in_toto_verify_with_options`, keeping `stage` at the stage
+/// of verification reached.
+fn verify_stages(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+    options: &VerifyOptions,
+    stage: &mut VerificationStage,
+) -> Result<VerificationReport> {
+    options.limits.check(layout)?;
+    *stage = VerificationStage::Signatures;
+    let layout = verify_layout_signatures(layout, layout_keys)?;
+    *stage = VerificationStage::Expiration;
+    verify_layout_expiration(&layout)?;
+    options.check_validity(&layout)?;
+
+    *stage = VerificationStage::Links;
+    let mut links = BTreeMap::new();
+    let mut link_entries = BTreeSet::new();
+    let mut signers = BTreeMap::new();
+    for step in layout.steps() {
+        options.check_deadline(step.name())?;
+        let (link, entries, step_signers) = verify_step_links(&layout, step, store, options)?;
+        links.insert(step.name().to_string(), link);
+        link_entries.extend(entries);
+        signers.insert(step.name().to_string(), step_signers);
+    }
+    *stage = VerificationStage::Rules;
+    let matched = options.path_matching.links(&links)?;
+    for step in layout.steps() {
+        let item = &step.supply_chain_item;
+        verify_item_rules(
+            step.name(),
+            item.expected_materials(),
+            true,
+            &matched,
+            options,
+        )?;
+        verify_item_rules(
+            step.name(),
+            item.expected_products(),
+            false,
+            &matched,
+            options,
+        )?;
+    }
+
+    *stage = VerificationStage::Inspections;
+    let mut inspections = Vec::new();
+    let mut all_links = links.clone();
+    for inspection in layout.inspect() {
+        let result = run_inspection(inspection, inspection_dir.unwrap_or("."), options)?;
+        all_links.insert(result.name.clone(), result.link.clone());
+        inspections.push(result);
+    }
+    *stage = VerificationStage::Rules;
+    let matched = options.path_matching.links(&all_links)?;
+    for inspection in layout.inspect() {
+        let item = &inspection.supply_chain_item;
+        verify_item_rules(
+            inspection.name(),
+            item.expected_materials(),
+            true,
+            &matched,
+            options,
+        )?;
+        verify_item_rules(
+            inspection.name(),
+            item.expected_products(),
+            false,
+            &matched,
+            options,
+        )?;
+    }
+
+    Ok(VerificationReport {
+        layout,
+        links,
+        link_entries,
+        signers,
+        inspections,
+    })
+}
+
+/// Verifies a supply chain like `in_toto_verify_with_store`, trusting the
+/// keys of `bundle` instead of keys given one by one. The layout has to be
+/// signed by all owners of the bundle and may only authorize functionary keys
+/// of the bundle.
+pub fn in_toto_verify_with_bundle(
+    layout: &Metablock,
+    bundle: &KeyBundle,
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+) -> Result<VerificationReport> {
+    bundle.check_layout(layout)?;
+    in_toto_verify_with_store(layout, &bundle.owner_keys(), store, inspection_dir)
+}
+
+/// Verifies a supply chain like `in_toto_verify_with_store`, unless the
+/// same signed layout was verified with the same keys against the same links
+/// and, if it has inspections, the same files in `inspection_dir` before, as
+/// recorded in `cache`. Then only the expiration of the layout is checked and
+/// the recorded report returned, inspections are not run again.
+/// Successful verifications are recorded in `cache`.
+pub fn in_toto_verify_cached(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+    cache: &mut VerificationCache,
+) -> Result<VerificationReport> {
+    in_toto_verify_cached_with_options(
+        layout,
+        layout_keys,
+        store,
+        inspection_dir,
+        &VerifyOptions::new(),
+        cache,
+    )
+}
+
+/// Verifies like `in_toto_verify_cached`, as `options` say. With
+/// `VerifyOptions::resolvers` the cache is neither read nor written: what
+/// the resolvers hash may change between verifications, and which resolvers
+/// were used cannot be told from the options.
+pub fn in_toto_verify_cached_with_options(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+    store: &dyn MetadataStore,
+    inspection_dir: Option<&str>,
+    options: &VerifyOptions,
+    cache: &mut VerificationCache,
+) -> Result<VerificationReport> {
+    if options.resolvers.is_some() {
+        debug!("Verifying without the cache, as resolvers are set");
+        return in_toto_verify_with_options(layout, layout_keys, store, inspection_dir, options);
+    }
+    let digest = VerificationCache::digest(layout, layout_keys, store, inspection_dir, options)?;
+    if let Some(report) = cache.get(&digest, layout)? {
+        debug!("Verification {} found in the cache", digest);
+        verify_layout_expiration(&report.layout)?;
+        options.check_validity(&report.layout)?;
+        return Ok(report);
+    }
+    let report = in_toto_verify_with_options(layout, layout_keys, store, inspection_dir, options)?;
+    cache.insert(digest, &report);
+    Ok(report)
+}
+
+/// Verifies that the signed layout `new` legitimately supersedes the signed
+/// layout `old`, so a verifier trusting `old` may move on to `new`.
+///
+/// # Arguments
+///
+/// * `old` - The signed layout trusted so far.
+/// * `new` - The signed layout superseding `old`.
+/// * `custody` - The link of custody handing over from `old` to `new`, see `custody_link`.
+/// * `old_keys` - The keys of the owners of `old`, all of whom have to have signed `old` and `custody`.
+///
+/// Fails if `new` does not refer to `old`, has no higher version than `old`,
+/// or if the custody link is not signed by the owners of `old` or is for
+/// other layouts. The signatures of `new` are to be checked with the keys of
+/// its owners by `in_toto_verify`.
+pub fn verify_layout_update(
+    old: &Metablock,
+    new: &Metablock,
+    custody: &Metablock,
+    old_keys: &[&PublicKey],
+) -> Result<()> {
+    let old_layout = verify_layout_signatures(old, old_keys)?;
+    let new_layout = match new.metadata() {
+        MetadataWrapper::Layout(layout) => layout,
+        MetadataWrapper::Link(_) => {
+            return Err(Error::VerificationFailure(
+                "the superseding metadata is not a layout".into(),
+            ))
+        }
+    };
+    match new_layout.supersedes() {
+        Some(reference) if reference.matches(old)? => (),
+        _ => {
+            return Err(Error::VerificationFailure(
+                "the new layout does not supersede the trusted one".into(),
+            ))
+        }
+    }
+    let old_version = old_layout.layout_version().unwrap_or(0);
+    let new_version = new_layout.layout_version().unwrap_or(0);
+    if new_version <= old_version {
+        return Err(Error::VerificationFailure(format!(
+            "layout version {} does not supersede version {}",
+            new_version, old_version
+        )));
+    }
+
+    let keys: HashMap<&KeyId, &PublicKey> = old_keys.iter().map(|k| (k.key_id(), *k)).collect();
+    let link = match custody.verify(keys.len() as u32, keys.values().copied())? {
+        MetadataWrapper::Link(link) => link,
+        MetadataWrapper::Layout(_) => {
+            return Err(Error::VerificationFailure(
+                "the link of custody is not a link".into(),
+            ))
+        }
+    };
+    let expected = custody_link(old, new)?;
+    if link.name() != expected.name()
+        || link.materials() != expected.materials()
+        || link.products() != expected.products()
+    {
+        return Err(Error::VerificationFailure(
+            "the link of custody is for other layouts".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that every key in `layout_keys` has signed `layout`.
+fn verify_layout_signatures(
+    layout: &Metablock,
+    layout_keys: &[&PublicKey],
+) -> Result<LayoutMetadata> {
+    let keys: HashMap<&KeyId, &PublicKey> = layout_keys.iter().map(|k| (k.key_id(), *k)).collect();
+    if keys.is_empty() {
+        return Err(Error::VerificationFailure(
+            "no keys given to verify the layout with".into(),
+        ));
+    }
+    match layout.verify(keys.len() as u32, keys.values().copied())? {
+        MetadataWrapper::Layout(layout) => Ok(layout),
+        MetadataWrapper::Link(_) => Err(Error::VerificationFailure(
+            "the metadata to verify is not a layout".into(),
+        )),
+    }
+}
+
+fn verify_layout_expiration(layout: &LayoutMetadata) -> Result<()> {
+    if *layout.expires() < Utc::now() {
+        return Err(Error::VerificationFailure(format!(
+            "layout expired at {}",
+            layout.expires()
+        )));
+    }
+    Ok(())
+}
+
+/// Load the links of `step` from `store` and check that at least
+/// `threshold` of them are signed by authorized functionaries, and that they
+/// all agree on materials and products. Of the links of a functionary, the
+/// one of the last attempt of the step is taken, see `models::attempt`.
+/// Returns the link along with the store entries and signers of all
+/// accepted links.
+fn verify_step_links(
+    layout: &LayoutMetadata,
+    step: &Step,
+    store: &dyn MetadataStore,
+    options: &VerifyOptions,
+) -> Result<(LinkMetadata, Vec<String>, Vec<Signer>)> {
+    let mut links: Vec<LinkMetadata> = Vec::new();
+    let mut entries: Vec<String> = Vec::new();
+    let mut signers: Vec<Signer> = Vec::new();
+    for key_id in &step.pub_keys {
+        let key = match layout.keys().get(key_id) {
+            Some(key) => key,
+            None => {
+                warn!(
+                    "Key ID {} of step {} is not in the layout",
+                    key_id,
+                    step.name()
+                );
+                continue;
+            }
+        };
+        // the names of the links of functionaries whose key IDs share their
+        // prefix collide, so their links are told apart by signature
+        let mut candidates = match colliding_key_ids(&step.pub_keys, key_id) {
+            true => {
+                debug!(
+                    "Key IDs of step {} share the prefix of {}",
+                    step.name(),
+                    key_id
+                );
+                link_candidates(store, step.name(), key_id)?
+            }
+            false => vec![link_filename(step.name(), key_id)],
+        };
+        candidates.extend(attempt_candidates(store, step.name(), key_id)?);
+
+        // the link of the last attempt supersedes those of earlier ones,
+        // of attempts with the same number the first one found is taken
+        let mut last: Option<(Attempt, String, LinkMetadata, Signer)> = None;
+        for path in candidates {
+            if entries.contains(&path) {
+                continue;
+            }
+            let metablock = match store.get(&path)? {
+                Some(bytes) => options.limits.parse_metablock(&bytes)?,
+                None => continue,
+            };
+            let link = match metablock.verify(1, [key]) {
+                Ok(MetadataWrapper::Link(link)) => link,
+                Ok(MetadataWrapper::Layout(_)) => {
+                    warn!("{} does not hold a link", path);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Ignoring link {} for key {}: {}", path, key_id, e);
+                    continue;
+                }
+            };
+            if link.name() != step.name() {
+                warn!("Ignoring link {} recorded for step {}", path, link.name());
+                continue;
+            }
+            let attempt = match Attempt::of(&link) {
+                Ok(attempt) => attempt,
+                Err(e) => {
+                    warn!("Ignoring link {} with invalid attempt: {}", path, e);
+                    continue;
+                }
+            };
+            let signer = Signer {
+                key_id: key_id.clone(),
+                signer_id: metablock
+                    .signatures()
+                    .iter()
+                    .find(|sig| sig.key_id() == key_id)
+                    .and_then(|sig| sig.signer_id())
+                    .map(String::from),
+            };
+            if let Some((last_attempt, last_path, _, _)) = &last {
+                if !attempt.supersedes(last_attempt) {
+                    debug!("Link {} is superseded by {}", path, last_path);
+                    continue;
+                }
+                debug!("Link {} is superseded by {}", last_path, path);
+            }
+            last = Some((attempt, path, link, signer));
+        }
+        if let Some((_, path, link, signer)) = last {
+            links.push(link);
+            entries.push(path);
+            signers.push(signer);
+        }
+    }
+
+    if links.len() < step.threshold as usize {
+        return Err(Error::VerificationFailure(format!(
+            "step {} requires {} link(s) signed by its functionaries, found {}",
+            step.name(),
+            step.threshold,
+            links.len()
+        )));
+    }
+    let link = links.first().cloned().ok_or_else(|| {
+        Error::VerificationFailure(format!("no link found for step {}", step.name()))
+    })?;
+    if links
+        .iter()
+        .any(|l| l.materials() != link.materials() || l.products() != link.products())
+    {
+        return Err(Error::VerificationFailure(format!(
+            "links of step {} disagree on their materials or products",
+            step.name()
+        )));
+    }
+    if !step.expected_command.is_empty()
+        && !options
+            .command_normalization
+            .matches(&step.expected_command, link.command())
+    {
+        warn!(
+            "Command of step {} was {:?}, expected {:?}",
+            step.name(),
+            link.command().argv(),
+            step.expected_command.argv()
+        );
+    }
+    Ok((link, entries, signers))
+}
+
+/// Whether the link filename of `key_id` is that of another of `key_ids`.
+fn colliding_key_ids(key_ids: &[KeyId], key_id: &KeyId) -> bool {
+    let prefix = |id: &KeyId| format!("{:.8}", id.to_string());
+    key_ids
+        .iter()
+        .any(|other| other != key_id && prefix(other) == prefix(key_id))
+}
+
+/// The entries of `store` that may hold the link of step `step_name`
+/// signed by `key_id`: the usual name, and names with a longer prefix of the
+/// key ID, e.g. the full one.
+fn link_candidates(
+    store: &dyn MetadataStore,
+    step_name: &str,
+    key_id: &KeyId,
+) -> Result<Vec<String>> {
+    let name = link_filename(step_name, key_id);
+    let stem = name.trim_end_matches(".link");
+    let id = key_id.to_string();
+    let mut candidates: Vec<String> = store
+        .list()?
+        .into_iter()
+        .filter(|entry| {
+            entry
+                .strip_prefix(stem)
+                .and_then(|rest| rest.strip_suffix(".link"))
+                .is_some_and(|rest| id[8..].starts_with(rest))
+        })
+        .collect();
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// The entries of `store` holding links of retries of step `step_name`
+/// signed by `key_id`, named as given by `attempt_filename`, by attempt.
+fn attempt_candidates(
+    store: &dyn MetadataStore,
+    step_name: &str,
+    key_id: &KeyId,
+) -> Result<Vec<String>> {
+    let name = link_filename(step_name, key_id);
+    let prefix = format!("{}.attempt-", name.trim_end_matches(".link"));
+    let mut candidates: Vec<(u32, String)> = store
+        .list()?
+        .into_iter()
+        .filter_map(|entry| {
+            let number = entry
+                .strip_prefix(&prefix)?
+                .strip_suffix(".link")?
+                .parse()
+                .ok()?;
+            Some((number, entry))
+        })
+        .collect();
+    candidates.sort();
+    Ok(candidates.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Run `inspection` in `dir`, recording all files of `dir` as its materials
+/// and products.
+fn run_inspection(
+    inspection: &Inspection,
+    dir: &str,
+    options: &VerifyOptions,
+) -> Result<InspectionResult> {
+    options.check_deadline(inspection.name())?;
+    debug!("Running inspection {}", inspection.name());
+    let argv: Vec<&str> = inspection.run.argv().iter().map(String::as_str).collect();
+    let lstrip = format!("{}/", dir.trim_end_matches('/'));
+    let mut run_options = RunOptions::new()
+        .run_dir(dir)
+        .output(options.inspection_output);
+    if let Some(deadline) = options.deadline {
+        run_options = run_options.deadline(deadline);
+    }
+    if let Some(resolvers) = &options.resolvers {
+        run_options = run_options.record(RecordOptions::new().resolvers(Arc::clone(resolvers)));
+    }
+    let metablock = in_toto_run_with_options(
+        inspection.name(),
+        &[dir],
+        &[dir],
+        &argv,
+        None,
+        None,
+        Some(&[&lstrip]),
+        &run_options,
+    )?;
+    let link = match metablock.metadata() {
+        MetadataWrapper::Link(link) => link.clone(),
+        MetadataWrapper::Layout(_) => {
+            return Err(Error::Programming("in_toto_run returned a layout".into()))
+        }
+    };
+    if link.byproducts().return_value() != 0 {
+        return Err(Error::VerificationFailure(format!(
+            "inspection {} returned {}: {}",
+            inspection.name(),
+            link.byproducts().return_value(),
+            link.byproducts().stderr()
+        )));
+    }
+    Ok(InspectionResult {
+        name: inspection.name().to_string(),
+        link,
+    })
+}
+
+/// Apply `rules` to the materials (or products) of the link of `item_name`.
+///
+/// Each rule consumes the artifacts it matches from a queue of not yet
+/// consumed artifacts, so the order of rules matters, e.g. `ALLOW foo` before
+/// `DISALLOW *` allows only `foo`.
+fn verify_item_rules(
+    item_name: &str,
+    rules: &[ArtifactRule],
+    materials: bool,
+    links: &BTreeMap<String, LinkMetadata>,
+    options: &VerifyOptions,
+) -> Result<()> {
+    let link = links
+        .get(item_name)
+        .ok_or_else(|| Error::VerificationFailure(format!("no link found for {}", item_name)))?;
+    let artifacts = match materials {
+        true => link.materials(),
+        false => link.products(),
+    };
+    let mut queue: BTreeSet<&VirtualTargetPath> = artifacts.keys().collect();
+
+    for rule in &options.path_matching.rules(rules) {
+        options.check_deadline(item_name)?;
+        let pattern = rule.pattern();
+        let mut filtered = queue
+            .iter()
+            .copied()
+            .filter(|p| fnmatch(pattern, p.value()));
+        let consumed: BTreeSet<&VirtualTargetPath> = match rule.rule_type() {
+            "MATCH" => queue
+                .iter()
+                .copied()
+                .filter(|p| match_artifact(rule, p, &artifacts[*p], links))
+                .collect(),
+            "CREATE" => filtered
+                .filter(|p| !link.materials().contains_key(*p) && link.products().contains_key(*p))
+                .collect(),
+            "DELETE" => filtered
+                .filter(|p| link.materials().contains_key(*p) && !link.products().contains_key(*p))
+                .collect(),
+            "MODIFY" => filtered
+                .filter(
+                    |p| match (link.materials().get(*p), link.products().get(*p)) {
+                        (Some(before), Some(after)) => before != after,
+                        _ => false,
+                    },
+                )
+                .collect(),
+            "ALLOW" => filtered.collect(),
+            "DISALLOW" => {
+                if let Some(path) = filtered.next() {
+                    return Err(rule_failure(item_name, rule, path));
+                }
+                BTreeSet::new()
+            }
+            "REQUIRE" => {
+                if !queue.iter().any(|p| p.value() == pattern) {
+                    return Err(Error::VerificationFailure(format!(
+                        "{}: rule {} failed, {} is required",
+                        item_name,
+                        serde_json::to_string(rule)?,
+                        pattern
+                    )));
+                }
+                BTreeSet::new()
+            }
+            typ => {
+                return Err(Error::Programming(format!(
+                    "unknown artifact rule type {}",
+                    typ
+                )))
+            }
+        };
+        queue.retain(|p| !consumed.contains(p));
+    }
+    Ok(())
+}
+
+fn rule_failure(item_name: &str, rule: &ArtifactRule, path: &VirtualTargetPath) -> Error {
+    Error::VerificationFailure(format!(
+        "{}: artifact {} is disallowed by rule {}",
+        item_name,
+        path,
+        serde_json::to_string(rule).unwrap_or_default()
+    ))
+}
+
+/// Whether `path` with `hashes` matches the pattern of a `MATCH` rule below
+/// its source prefix, and is found with the same hashes among the artifacts
+/// of the step the rule refers to.
+fn match_artifact(
+    rule: &ArtifactRule,
+    path: &VirtualTargetPath,
+    hashes: &TargetDescription,
+    links: &BTreeMap<String, LinkMetadata>,
+) -> bool {
+    let link = match rule.from_step().and_then(|step| links.get(step)) {
+        Some(link) => link,
+        None => return false,
+    };
+    let destination = match rule.with_materials() {
+        true => link.materials(),
+        false => link.products(),
+    };
+    let relative = match strip_path_prefix(path.value(), rule.source_path_prefix()) {
+        Some(relative) if fnmatch(rule.pattern(), relative) => relative,
+        _ => return false,
+    };
+    let destination_path = match rule.destination_path_prefix() {
+        Some(prefix) if !prefix.is_empty() => {
+            format!("{}/{}", prefix.trim_end_matches('/'), relative)
+        }
+        _ => relative.to_string(),
+    };
+    match VirtualTargetPath::new(destination_path) {
+        Ok(destination_path) => destination.get(&destination_path) == Some(hashes),
+        Err(_) => false,
+    }
+}
+
+fn strip_path_prefix<'a>(path: &'a str, prefix: Option<&str>) -> Option<&'a str> {
+    match prefix {
+        Some(prefix) if !prefix.is_empty() => path
+            .strip_prefix(prefix.trim_end_matches('/'))?
+            .strip_prefix('/'),
+        _ => Some(path),
+    }
+}
+
+/// Shell-style pattern matching as done by python's `fnmatch`: `*` matches
+/// any sequence of characters including `/`, `?` matches any character, and
+/// `[seq]` / `[!seq]` match any character in / not in `seq`.
+///
+/// For `MATCH` rules the pattern is applied to the path relative to the
+/// source prefix, so the prefix itself is not part of `pattern`.
+pub(crate) fn fnmatch(pattern: &str, name: &str) -> bool {
+    let tokens = tokenize_pattern(pattern);
+    let name: Vec<char> = name.chars().collect();
+    // Every token but `*` matches exactly one character, so backtracking to
+    // the last `*` seen is enough.
+    let (mut t, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(PatternToken::Star) => {
+                backtrack = Some((t, n));
+                t += 1;
+            }
+            Some(token) if token.matches(name[n]) => {
+                t += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, from)) => {
+                    t = star + 1;
+                    n = from + 1;
+                    backtrack = Some((star, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|t| *t == PatternToken::Star)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PatternToken {
+    Star,
+    Any,
+    Char(char),
+    Set {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl PatternToken {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            PatternToken::Star | PatternToken::Any => true,
+            PatternToken::Char(expected) => c == *expected,
+            PatternToken::Set { negated, ranges } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+fn tokenize_pattern(pattern: &str) -> Vec<PatternToken> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => tokens.push(PatternToken::Star),
+            '?' => tokens.push(PatternToken::Any),
+            '[' => match parse_set(&chars[i + 1..]) {
+                Some((token, len)) => {
+                    tokens.push(token);
+                    i += len;
+                }
+                // an unclosed `[` is taken literally
+                None => tokens.push(PatternToken::Char('[')),
+            },
+            c => tokens.push(PatternToken::Char(c)),
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Parse the set following a `[`, returning it and the number of characters
+/// it spans including the closing `]`.
+fn parse_set(chars: &[char]) -> Option<(PatternToken, usize)> {
+    let mut i = 0;
+    let negated = chars.first() == Some(&'!');
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    // a `]` right after the opening bracket is part of the set
+    let start = i;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == ']' && i > start {
+            return Some((PatternToken::Set { negated, ranges }, i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&hi| hi != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::{
+        colliding_key_ids, fnmatch, in_toto_verify_with_options, link_candidates, run_inspection,
+        verify_item_rules, VerifyOptions,
+    };
+    use crate::crypto::{HashAlgorithm, HashValue, KeyId};
+    use crate::metrics::{Counters, VerificationStage};
+    use crate::models::inspection::Inspection;
+    use crate::models::rule::ArtifactRule;
+    use crate::models::step::Command;
+    use crate::models::{
+        LayoutMetadataBuilder, LinkMetadata, LinkMetadataBuilder, TargetDescription,
+        VirtualTargetPath,
+    };
+    use crate::runlib::OutputMode;
+    use crate::store::{MemoryStore, MetadataStore};
+    use crate::test_utils::{functionary_key, owner_key, sign};
+    use crate::Error;
+
+    fn artifacts(entries: &[(&str, u8)]) -> BTreeMap<VirtualTargetPath, TargetDescription> {
+        entries
+            .iter()
+            .map(|(path, digest)| {
+                let mut hashes = TargetDescription::new();
+                hashes.insert(HashAlgorithm::Sha256, HashValue::new(vec![*digest]));
+                (VirtualTargetPath::new(path.to_string()).unwrap(), hashes)
+            })
+            .collect()
+    }
+
+    fn link(name: &str, materials: &[(&str, u8)], products: &[(&str, u8)]) -> LinkMetadata {
+        LinkMetadataBuilder::new()
+            .name(name.into())
+            .materials(artifacts(materials))
+            .products(artifacts(products))
+            .build()
+            .unwrap()
+    }
+
+    fn rules(json: &str) -> Vec<ArtifactRule> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn fnmatch_patterns() {
+        assert!(fnmatch("*", "src/foo.py"));
+        assert!(fnmatch("*.py", "src/foo.py"));
+        assert!(fnmatch("src/*", "src/a/b"));
+        assert!(!fnmatch("*.py", "foo.pyc"));
+        assert!(fnmatch("foo.?y", "foo.py"));
+        assert!(fnmatch("foo.[pq]y", "foo.qy"));
+        assert!(!fnmatch("foo.[!pq]y", "foo.py"));
+        assert!(fnmatch("[a-c]*", "bar"));
+        assert!(fnmatch("[]]", "]"));
+        assert!(fnmatch("[", "["));
+        assert!(fnmatch("a*b*c", "aXXbYYbc"));
+        assert!(!fnmatch("a*b*c", "aXXbYY"));
+        assert!(fnmatch("", ""));
+        assert!(!fnmatch("", "a"));
+    }
+
+    #[test]
+    fn create_delete_modify_rules() {
+        let mut links = BTreeMap::new();
+        links.insert(
+            "step".to_string(),
+            link(
+                "step",
+                &[("kept", 1), ("modified", 1), ("deleted", 1)],
+                &[("kept", 1), ("modified", 2), ("created", 1)],
+            ),
+        );
+        let products = rules(
+            r#"[["CREATE", "created"], ["MODIFY", "modified"], ["ALLOW", "kept"], ["DISALLOW", "*"]]"#,
+        );
+        assert!(verify_item_rules("step", &products, false, &links, &VerifyOptions::new()).is_ok());
+        let materials = rules(
+            r#"[["DELETE", "deleted"], ["MODIFY", "mod*"], ["ALLOW", "kept"], ["DISALLOW", "*"]]"#,
+        );
+        assert!(verify_item_rules("step", &materials, true, &links, &VerifyOptions::new()).is_ok());
+
+        // `kept` is neither created nor modified
+        let products = rules(r#"[["CREATE", "*"], ["MODIFY", "*"], ["DISALLOW", "*"]]"#);
+        assert!(
+            verify_item_rules("step", &products, false, &links, &VerifyOptions::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn require_rule() {
+        let mut links = BTreeMap::new();
+        links.insert("step".to_string(), link("step", &[], &[("foo.tar.gz", 1)]));
+        let products = rules(r#"[["REQUIRE", "foo.tar.gz"]]"#);
+        assert!(verify_item_rules("step", &products, false, &links, &VerifyOptions::new()).is_ok());
+        let products = rules(r#"[["ALLOW", "*"], ["REQUIRE", "foo.tar.gz"]]"#);
+        assert!(
+            verify_item_rules("step", &products, false, &links, &VerifyOptions::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn inspection_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let inspection = Inspection::new("greet").run(Command::new(["sh", "-c", "echo hi"]));
+        let stdout = |output| {
+            let options = VerifyOptions::new().inspection_output(output);
+            let result = run_inspection(&inspection, dir, &options).unwrap();
+            result.link().byproducts().stdout().clone()
+        };
+        assert_eq!(stdout(OutputMode::CaptureOnly), "hi\n");
+        assert_eq!(stdout(OutputMode::Quiet), "");
+    }
+
+    #[test]
+    fn rules_past_deadline() {
+        let mut links = BTreeMap::new();
+        links.insert("step".to_string(), link("step", &[], &[("foo.tar.gz", 1)]));
+        let products = rules(r#"[["ALLOW", "*"]]"#);
+        let expired = VerifyOptions::new().deadline(Instant::now());
+        assert!(matches!(
+            verify_item_rules("step", &products, false, &links, &expired),
+            Err(Error::Timeout(_))
+        ));
+        assert!(verify_item_rules("step", &[], false, &links, &expired).is_ok());
+    }
+
+    #[test]
+    fn match_rule_with_prefixes() {
+        let mut links = BTreeMap::new();
+        links.insert(
+            "build".to_string(),
+            link("build", &[], &[("dist/foo", 1), ("dist/bar", 2)]),
+        );
+        links.insert(
+            "package".to_string(),
+            link("package", &[("pkg/foo", 1), ("pkg/bar", 3)], &[]),
+        );
+        let materials = rules(
+            r#"[["MATCH", "*", "IN", "pkg", "WITH", "PRODUCTS", "IN", "dist/", "FROM", "build"], ["DISALLOW", "*"]]"#,
+        );
+        // pkg/bar has different hashes than dist/bar
+        assert!(
+            verify_item_rules("package", &materials, true, &links, &VerifyOptions::new()).is_err()
+        );
+
+        let materials = rules(
+            r#"[["MATCH", "foo", "IN", "pkg", "WITH", "PRODUCTS", "IN", "dist", "FROM", "build"], ["ALLOW", "pkg/bar"], ["DISALLOW", "*"]]"#,
+        );
+        assert!(
+            verify_item_rules("package", &materials, true, &links, &VerifyOptions::new()).is_ok()
+        );
+
+        // a missing step matches nothing
+        let materials =
+            rules(r#"[["MATCH", "*", "WITH", "PRODUCTS", "FROM", "missing"], ["DISALLOW", "*"]]"#);
+        assert!(
+            verify_item_rules("package", &materials, true, &links, &VerifyOptions::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn colliding_key_id_prefixes() {
+        let id = |suffix: char| {
+            KeyId::from_str(&format!("abcd1234{}", suffix.to_string().repeat(56))).unwrap()
+        };
+        let (a, b) = (id('a'), id('b'));
+        let c = KeyId::from_str(&"c".repeat(64)).unwrap();
+        let key_ids = [a.clone(), b.clone(), c.clone()];
+        assert!(colliding_key_ids(&key_ids, &a));
+        assert!(colliding_key_ids(&key_ids, &b));
+        assert!(!colliding_key_ids(&key_ids, &c));
+
+        let mut store = MemoryStore::new();
+        for name in [
+            "build.abcd1234.link".to_string(),
+            format!("build.{}.link", b),
+            "build.abcd1234bb.link".into(),
+            "build.abcd1234.layout".into(),
+            "test.abcd1234.link".into(),
+        ] {
+            store.put(&name, b"{}").unwrap();
+        }
+        assert_eq!(
+            link_candidates(&store, "build", &a).unwrap(),
+            ["build.abcd1234.link"]
+        );
+        assert_eq!(
+            link_candidates(&store, "build", &b).unwrap(),
+            [
+                "build.abcd1234.link".to_string(),
+                "build.abcd1234bb.link".into(),
+                format!("build.{}.link", b)
+            ]
+        );
+    }
+
+    #[test]
+    fn report_metrics() {
+        let counters = Arc::new(Counters::new());
+        let options = VerifyOptions::new().metrics(counters.clone());
+        let owner = owner_key();
+        let layout = sign(
+            Box::new(LayoutMetadataBuilder::new().build().unwrap()),
+            &[&owner],
+        );
+        let verify =
+            |key| in_toto_verify_with_options(&layout, &[key], &MemoryStore::new(), None, &options);
+        assert!(verify(owner.public()).is_ok());
+        assert!(verify(functionary_key().public()).is_err());
+        assert_eq!(counters.verifications(), 2);
+        assert_eq!(counters.failures(VerificationStage::Signatures), 1);
+        assert_eq!(counters.failures(VerificationStage::Links), 0);
+    }
+}
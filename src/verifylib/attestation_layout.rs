@@ -0,0 +1,336 @@
+//! Experimental layouts over attestations, see the `attestation-layout`
+//! feature.
+//!
+//! The in-toto attestation framework moves policies away from link files:
+//! each step of an `AttestationLayout` expects attestations of given
+//! predicate types, e.g. SLSA provenance, signed by enough of its
+//! functionaries, and optionally with attributes of given values. The format
+//! follows the drafts of the in-toto v1.0 policy and will change with them:
+//!
+//! ```json
+//! {
+//!   "expires": "2030-01-01T00:00:00Z",
+//!   "functionaries": {"<keyid>": {"keytype": "ed25519", ...}},
+//!   "steps": [{
+//!     "name": "build",
+//!     "expectedPredicates": [{
+//!       "predicateTypes": ["https://slsa.dev/provenance/v1"],
+//!       "functionaries": ["<keyid>"],
+//!       "threshold": 1,
+//!       "expectedAttributes": {"/predicate/buildDefinition/buildType": "..."}
+//!     }]
+//!   }]
+//! }
+//! ```
+//!
+//! Attributes are named by JSON pointers into the statement. Unlike link
+//! layouts, there are no artifact rules and no inspections yet.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::subject::check_signatures;
+use crate::crypto::{KeyId, PublicKey};
+use crate::models::EnvelopeFile;
+use crate::{Error, Result};
+
+/// The `_type`s of in-toto statements accepted.
+pub const STATEMENT_TYPES: &[&str] = &[
+    "https://in-toto.io/Statement/v0.1",
+    "https://in-toto.io/Statement/v1",
+];
+
+/// A layout whose steps expect attestations rather than links.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationLayout {
+    expires: DateTime<Utc>,
+    functionaries: BTreeMap<KeyId, PublicKey>,
+    steps: Vec<AttestationStep>,
+}
+
+impl AttestationLayout {
+    /// A layout expiring at `expires`, without functionaries and steps
+    pub fn new(expires: DateTime<Utc>) -> Self {
+        AttestationLayout {
+            expires,
+            functionaries: BTreeMap::new(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Trust `key` as a functionary
+    pub fn functionary(mut self, key: &PublicKey) -> Self {
+        self.functionaries.insert(key.key_id().clone(), key.clone());
+        self
+    }
+
+    /// Add the step `step`
+    pub fn step(mut self, step: AttestationStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// When the layout expires
+    pub fn expires(&self) -> &DateTime<Utc> {
+        &self.expires
+    }
+
+    /// The functionaries, by key ID
+    pub fn functionaries(&self) -> &BTreeMap<KeyId, PublicKey> {
+        &self.functionaries
+    }
+
+    /// The steps
+    pub fn steps(&self) -> &[AttestationStep] {
+        &self.steps
+    }
+
+    /// Verify `attestations` against the layout, returning the statements
+    /// accepted for each step by its name. Every expected predicate of every
+    /// step needs an attestation of one of its predicate types, signed by
+    /// enough of its functionaries and with the attributes expected.
+    pub fn verify(&self, attestations: &[EnvelopeFile]) -> Result<BTreeMap<String, Vec<Value>>> {
+        if self.expires < Utc::now() {
+            return Err(Error::VerificationFailure(format!(
+                "attestation layout expired at {}",
+                self.expires
+            )));
+        }
+        let mut accepted = BTreeMap::new();
+        for step in &self.steps {
+            let mut statements = Vec::new();
+            for (i, expected) in step.expected_predicates.iter().enumerate() {
+                let keys = expected.keys(&self.functionaries)?;
+                let statement = attestations
+                    .iter()
+                    .find_map(|envelope| match expected.accepts(envelope, &keys) {
+                        Ok(statement) => Some(statement),
+                        Err(e) => {
+                            debug!("Step {} does not accept an attestation: {}", step.name, e);
+                            None
+                        }
+                    })
+                    .ok_or_else(|| {
+                        Error::VerificationFailure(format!(
+                            "step {}: no accepted attestation for expected predicate {} of {}",
+                            step.name,
+                            i,
+                            expected.predicate_types.join(", ")
+                        ))
+                    })?;
+                statements.push(statement);
+            }
+            accepted.insert(step.name.clone(), statements);
+        }
+        Ok(accepted)
+    }
+}
+
+/// A step of an `AttestationLayout`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationStep {
+    name: String,
+    #[serde(default)]
+    expected_predicates: Vec<ExpectedPredicate>,
+}
+
+impl AttestationStep {
+    /// The step `name`, expecting nothing
+    pub fn new(name: &str) -> Self {
+        AttestationStep {
+            name: name.to_string(),
+            expected_predicates: Vec::new(),
+        }
+    }
+
+    /// Expect an attestation as `expected` says
+    pub fn expect(mut self, expected: ExpectedPredicate) -> Self {
+        self.expected_predicates.push(expected);
+        self
+    }
+
+    /// The name of the step
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The attestations expected
+    pub fn expected_predicates(&self) -> &[ExpectedPredicate] {
+        &self.expected_predicates
+    }
+}
+
+/// An attestation a step expects.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpectedPredicate {
+    predicate_types: Vec<String>,
+    functionaries: Vec<KeyId>,
+    #[serde(default = "default_threshold")]
+    threshold: u32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    expected_attributes: BTreeMap<String, Value>,
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+impl ExpectedPredicate {
+    /// An attestation of one of `predicate_types` signed by one of
+    /// `functionaries`
+    pub fn new(predicate_types: &[&str], functionaries: &[&KeyId]) -> Self {
+        ExpectedPredicate {
+            predicate_types: predicate_types.iter().map(|t| t.to_string()).collect(),
+            functionaries: functionaries.iter().map(|k| (*k).clone()).collect(),
+            threshold: 1,
+            expected_attributes: BTreeMap::new(),
+        }
+    }
+
+    /// Require signatures of `threshold` distinct functionaries
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Require the attribute at the JSON pointer `pointer` of the statement,
+    /// like `/predicate/builder/id`, to be `value`
+    pub fn attribute(mut self, pointer: &str, value: Value) -> Self {
+        self.expected_attributes.insert(pointer.to_string(), value);
+        self
+    }
+
+    /// The predicate types accepted
+    pub fn predicate_types(&self) -> &[String] {
+        &self.predicate_types
+    }
+
+    /// The key IDs of the functionaries
+    pub fn functionaries(&self) -> &[KeyId] {
+        &self.functionaries
+    }
+
+    /// The attributes expected, by JSON pointer
+    pub fn expected_attributes(&self) -> &BTreeMap<String, Value> {
+        &self.expected_attributes
+    }
+
+    /// The keys of the functionaries among `keys`, failing for unknown ones.
+    fn keys(&self, keys: &BTreeMap<KeyId, PublicKey>) -> Result<HashMap<KeyId, PublicKey>> {
+        self.functionaries
+            .iter()
+            .map(|key_id| match keys.get(key_id) {
+                Some(key) => Ok((key_id.clone(), key.clone())),
+                None => Err(Error::VerificationFailure(format!(
+                    "functionary {:?} is not in the layout",
+                    key_id
+                ))),
+            })
+            .collect()
+    }
+
+    /// The statement of `envelope` if it is the attestation expected.
+    fn accepts(&self, envelope: &EnvelopeFile, keys: &HashMap<KeyId, PublicKey>) -> Result<Value> {
+        check_signatures(envelope, keys, self.threshold)?;
+        let statement: Value = serde_json::from_str(envelope.payload())?;
+        let field = |name: &str| statement.get(name).and_then(Value::as_str);
+        if !field("_type").is_some_and(|t| STATEMENT_TYPES.contains(&t)) {
+            return Err(Error::VerificationFailure(
+                "the payload is no in-toto statement".into(),
+            ));
+        }
+        match field("predicateType") {
+            Some(t) if self.predicate_types.iter().any(|p| p == t) => (),
+            t => {
+                return Err(Error::VerificationFailure(format!(
+                    "the predicate type is {:?}",
+                    t
+                )))
+            }
+        }
+        for (pointer, value) in &self.expected_attributes {
+            if statement.pointer(pointer) != Some(value) {
+                return Err(Error::VerificationFailure(format!(
+                    "{} is {:?}, not {}",
+                    pointer,
+                    statement.pointer(pointer),
+                    value
+                )));
+            }
+        }
+        Ok(statement)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    use super::{AttestationLayout, AttestationStep, ExpectedPredicate};
+    use crate::crypto::PrivateKey;
+    use crate::models::{DSSEVersion, EnvelopeFile};
+    use crate::test_utils::key;
+    use crate::verifylib::IN_TOTO_PAYLOAD_TYPE;
+
+    const PROVENANCE: &str = "https://slsa.dev/provenance/v1";
+
+    fn attestation(predicate_type: &str, builder: &str, keys: &[&PrivateKey]) -> EnvelopeFile {
+        let payload = json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{"name": "app", "digest": {"sha256": "00"}}],
+            "predicateType": predicate_type,
+            "predicate": {"runDetails": {"builder": {"id": builder}}}
+        })
+        .to_string();
+        let message = DSSEVersion::V1.pack(payload.as_bytes(), IN_TOTO_PAYLOAD_TYPE.into());
+        let signatures = keys.iter().map(|k| k.sign(&message).unwrap()).collect();
+        EnvelopeFile::new(payload, IN_TOTO_PAYLOAD_TYPE.into(), signatures)
+    }
+
+    #[test]
+    fn verify_attestation_layout() {
+        let (builder, other) = (key("builder"), key("other"));
+        let expected = ExpectedPredicate::new(&[PROVENANCE], &[builder.key_id()])
+            .attribute("/predicate/runDetails/builder/id", json!("ci"));
+        let layout = AttestationLayout::new(Utc::now() + Duration::days(1))
+            .functionary(builder.public())
+            .functionary(other.public())
+            .step(AttestationStep::new("build").expect(expected));
+        let json = serde_json::to_value(&layout).unwrap();
+        assert_eq!(
+            json["steps"][0]["expectedPredicates"][0]["predicateTypes"][0],
+            PROVENANCE
+        );
+        let layout: AttestationLayout = serde_json::from_value(json).unwrap();
+
+        let accepted = layout
+            .verify(&[
+                attestation(PROVENANCE, "ci", &[&other]),
+                attestation(PROVENANCE, "ci", &[&builder]),
+            ])
+            .unwrap();
+        assert_eq!(accepted["build"].len(), 1);
+
+        assert!(layout
+            .verify(&[attestation(PROVENANCE, "laptop", &[&builder])])
+            .is_err());
+        assert!(layout
+            .verify(&[attestation(
+                "https://in-toto.io/Link/v0.2",
+                "ci",
+                &[&builder]
+            )])
+            .is_err());
+        assert!(layout.verify(&[]).is_err());
+
+        let expired = AttestationLayout::new(Utc::now() - Duration::days(1));
+        assert!(expired.verify(&[]).is_err());
+    }
+}
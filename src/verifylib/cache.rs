@@ -0,0 +1,287 @@
+//! Remembering successful verifications.
+//!
+//! Verifying the same supply chain again, e.g. when redeploying, gives the
+//! same result as long as the layout, the keys trusted for it, the links, the
+//! files inspected and the options of verification are the same.
+//! `VerificationCache` records the reports of successful verifications by a
+//! digest of exactly these, so any changed byte forces a full verification
+//! again. The inspected files are those in the inspection directory, for
+//! layouts with inspections: inspections depending on anything else, e.g. on
+//! the network, should not be cached. Verifications with resolvers are not,
+//! see `in_toto_verify_cached_with_options`.
+//!
+//! A cache file is as good as a report of a successful verification, so it
+//! is stored with an HMAC-SHA256 keyed by a secret of the verifier and only
+//! loaded if it is authenticated by it.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+use data_encoding::HEXLOWER;
+use ring::digest::{Context, SHA256};
+use ring::hmac;
+use serde_derive::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use super::{InspectionResult, Signer, VerificationReport, VerifyOptions};
+use crate::crypto::PublicKey;
+use crate::interchange::{DataInterchange, Json};
+use crate::models::{LinkMetadata, Metablock, MetadataWrapper};
+use crate::store::MetadataStore;
+use crate::{Error, Result};
+
+/// The least length of the secrets authenticating cache files, in bytes.
+pub const MIN_CACHE_KEY_LEN: usize = 32;
+
+/// A report as recorded in the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedReport {
+    links: BTreeMap<String, MetadataWrapper>,
+    link_entries: Vec<String>,
+    #[serde(default)]
+    signers: BTreeMap<String, Vec<Signer>>,
+    inspections: Vec<(String, MetadataWrapper)>,
+}
+
+/// The reports of successful verifications, by digest of the signed layout,
+/// the keys it was verified with and all links of the store. It is meant to
+/// be kept locally between verifications, see `load` and `save`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationCache {
+    verifications: BTreeMap<String, CachedReport>,
+}
+
+impl VerificationCache {
+    /// An empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the cache stored at `path` and authenticated by `key`, or an
+    /// empty cache if there is none. Fails if the file is not authenticated
+    /// by `key`, e.g. as it was tampered with.
+    pub fn load<P: AsRef<Path>>(path: P, key: &[u8]) -> Result<Self> {
+        let key = hmac_key(key)?;
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let unauthenticated = || {
+            Error::VerificationFailure(format!(
+                "the verification cache {} is not authenticated",
+                path.as_ref().display()
+            ))
+        };
+        // the tag in hex on the first line, the cache as JSON below
+        let split = bytes
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(unauthenticated)?;
+        let tag = HEXLOWER
+            .decode(&bytes[..split])
+            .map_err(|_| unauthenticated())?;
+        let json = &bytes[split + 1..];
+        hmac::verify(&key, json, &tag).map_err(|_| unauthenticated())?;
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Store the cache at `path`, authenticated by `key`, replacing the file
+    /// as a whole.
+    pub fn save<P: AsRef<Path>>(&self, path: P, key: &[u8]) -> Result<()> {
+        let key = hmac_key(key)?;
+        let json = serde_json::to_vec_pretty(self)?;
+        let tag = hmac::sign(&key, &json);
+        let mut bytes = HEXLOWER.encode(tag.as_ref()).into_bytes();
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&json);
+
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        std::io::Write::write_all(&mut temp, &bytes)?;
+        temp.persist(path)?;
+        Ok(())
+    }
+
+    /// The number of verifications recorded
+    pub fn len(&self) -> usize {
+        self.verifications.len()
+    }
+
+    /// Whether no verifications are recorded
+    pub fn is_empty(&self) -> bool {
+        self.verifications.is_empty()
+    }
+
+    /// Forget all verifications.
+    pub fn clear(&mut self) {
+        self.verifications.clear()
+    }
+
+    /// The digest identifying the verification of `layout` with
+    /// `layout_keys` against the links in `store`, all entries ending in
+    /// `.link` whether the layout names their step or not, with `options`.
+    /// For layouts with inspections the digest covers the files in
+    /// `inspection_dir` as well.
+    pub(crate) fn digest(
+        layout: &Metablock,
+        layout_keys: &[&PublicKey],
+        store: &dyn MetadataStore,
+        inspection_dir: Option<&str>,
+        options: &VerifyOptions,
+    ) -> Result<String> {
+        let mut digest = Digest(Context::new(&SHA256));
+        digest.add(&Json::canonicalize(&serde_json::to_value(layout)?)?);
+        let mut key_ids: Vec<String> = layout_keys.iter().map(|k| k.key_id().to_string()).collect();
+        key_ids.sort();
+        key_ids.dedup();
+        for key_id in &key_ids {
+            digest.add(key_id.as_bytes());
+        }
+        for name in store.list()? {
+            if !name.ends_with(".link") {
+                continue;
+            }
+            if let Some(bytes) = store.get(&name)? {
+                digest.add(name.as_bytes());
+                digest.add(&bytes);
+            }
+        }
+        digest.add(options.cache_key().as_bytes());
+        let inspects = match layout.metadata() {
+            MetadataWrapper::Layout(layout) => !layout.inspect().is_empty(),
+            MetadataWrapper::Link(_) => false,
+        };
+        if inspects {
+            digest.add_tree(Path::new(inspection_dir.unwrap_or(".")))?;
+        }
+        Ok(HEXLOWER.encode(digest.0.finish().as_ref()))
+    }
+
+    /// The report recorded for the verification `digest` of `layout`, if any.
+    pub(crate) fn get(
+        &self,
+        digest: &str,
+        layout: &Metablock,
+    ) -> Result<Option<VerificationReport>> {
+        let cached = match self.verifications.get(digest) {
+            Some(cached) => cached,
+            None => return Ok(None),
+        };
+        let layout = match layout.metadata() {
+            MetadataWrapper::Layout(layout) => layout.clone(),
+            MetadataWrapper::Link(_) => {
+                return Err(Error::VerificationFailure(
+                    "the metadata to verify is not a layout".into(),
+                ))
+            }
+        };
+        let links = cached
+            .links
+            .iter()
+            .map(|(step, link)| Ok((step.clone(), into_link(link)?)))
+            .collect::<Result<_>>()?;
+        let inspections = cached
+            .inspections
+            .iter()
+            .map(|(name, link)| {
+                Ok(InspectionResult {
+                    name: name.clone(),
+                    link: into_link(link)?,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(VerificationReport {
+            layout,
+            links,
+            link_entries: cached.link_entries.iter().cloned().collect(),
+            signers: cached.signers.clone(),
+            inspections,
+        }))
+    }
+
+    /// Record `report` as the outcome of the verification `digest`.
+    pub(crate) fn insert(&mut self, digest: String, report: &VerificationReport) {
+        let cached = CachedReport {
+            links: report
+                .links
+                .iter()
+                .map(|(step, link)| (step.clone(), MetadataWrapper::Link(link.clone())))
+                .collect(),
+            link_entries: report.link_entries.iter().cloned().collect(),
+            signers: report.signers.clone(),
+            inspections: report
+                .inspections
+                .iter()
+                .map(|i| (i.name.clone(), MetadataWrapper::Link(i.link.clone())))
+                .collect(),
+        };
+        self.verifications.insert(digest, cached);
+    }
+}
+
+/// A digest of length-prefixed items.
+struct Digest(Context);
+
+impl Digest {
+    fn add(&mut self, bytes: &[u8]) {
+        self.0.update(&(bytes.len() as u64).to_be_bytes());
+        self.0.update(bytes);
+    }
+
+    /// Add the paths below `dir` with the contents of the files and the
+    /// targets of the symbolic links.
+    fn add_tree(&mut self, dir: &Path) -> Result<()> {
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.map_err(|e| Error::from(std::io::Error::from(e)))?;
+            let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            self.add(relative.as_os_str().as_encoded_bytes());
+            let file_type = entry.file_type();
+            if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                self.add(b"symlink");
+                self.add(target.as_os_str().as_encoded_bytes());
+            } else if file_type.is_file() {
+                let mut file = File::open(entry.path())?;
+                self.add(b"file");
+                self.0.update(&file.metadata()?.len().to_be_bytes());
+                let mut buffer = [0u8; 64 * 1024];
+                loop {
+                    match file.read(&mut buffer)? {
+                        0 => break,
+                        read => self.0.update(&buffer[..read]),
+                    }
+                }
+            } else {
+                self.add(b"directory");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `key` as HMAC-SHA256 key, if it is long enough.
+fn hmac_key(key: &[u8]) -> Result<hmac::Key> {
+    if key.len() < MIN_CACHE_KEY_LEN {
+        return Err(Error::IllegalArgument(format!(
+            "the key of a verification cache needs at least {} bytes",
+            MIN_CACHE_KEY_LEN
+        )));
+    }
+    Ok(hmac::Key::new(hmac::HMAC_SHA256, key))
+}
+
+fn into_link(metadata: &MetadataWrapper) -> Result<LinkMetadata> {
+    match metadata {
+        MetadataWrapper::Link(link) => Ok(link.clone()),
+        MetadataWrapper::Layout(_) => Err(Error::Encoding(
+            "the verification cache holds a layout in place of a link".into(),
+        )),
+    }
+}
@@ -0,0 +1,387 @@
+//! Compliance reports of verified releases.
+//!
+//! Audits ask for the evidence behind a release as a document to file: what
+//! was verified when, who signed each step and which artifacts went in and
+//! out of it. `ComplianceReport` renders a `VerificationReport` as such a
+//! printable document, listing every artifact with its digests rather than
+//! summarizing like `VerificationReport::render`. It renders as Markdown, as
+//! HTML to print, or as PDF.
+//!
+//! The PDF is plain text set in Courier on A4 pages, written without a PDF
+//! library. Characters outside of ASCII are printed as `?`.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use data_encoding::HEXLOWER;
+
+use super::render::{escape, ReportFormat};
+use super::VerificationReport;
+use crate::models::TargetDescription;
+
+/// The number of characters of a line of the PDF, longer lines are wrapped.
+const PDF_LINE_WIDTH: usize = 90;
+
+/// The number of lines of a page of the PDF.
+const PDF_PAGE_LINES: usize = 66;
+
+/// A printable report of the evidence a release was verified with.
+///
+/// ```
+/// # use in_toto::verifylib::{ComplianceReport, VerificationReport};
+/// # fn archive(report: &VerificationReport) -> std::io::Result<()> {
+/// let pdf = ComplianceReport::new(report, "app 1.2.0").to_pdf();
+/// std::fs::write("app-1.2.0-compliance.pdf", pdf)
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ComplianceReport<'a> {
+    report: &'a VerificationReport,
+    release: String,
+    verified_at: DateTime<Utc>,
+}
+
+/// A section of the report, for a step or an inspection.
+struct Section {
+    title: String,
+    fields: Vec<(&'static str, String)>,
+    artifacts: Vec<(&'static str, Vec<(String, String)>)>,
+}
+
+impl<'a> ComplianceReport<'a> {
+    /// The report of `release`, e.g. `app 1.2.0`, as verified by `report`,
+    /// just now
+    pub fn new(report: &'a VerificationReport, release: &str) -> Self {
+        ComplianceReport {
+            report,
+            release: release.to_string(),
+            verified_at: Utc::now(),
+        }
+    }
+
+    /// Record the verification as done at `verified_at`, e.g. for a report
+    /// rendered after the fact
+    pub fn verified_at(mut self, verified_at: DateTime<Utc>) -> Self {
+        self.verified_at = verified_at;
+        self
+    }
+
+    /// This report in `format`
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.markdown(),
+            ReportFormat::Html => self.html(),
+        }
+    }
+
+    /// This report as PDF document
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut lines = vec![self.title(), String::new()];
+        for (name, value) in self.summary() {
+            lines.push(format!("{}: {}", name, value));
+        }
+        for section in self.sections() {
+            lines.push(String::new());
+            lines.push(section.title.clone());
+            lines.push("=".repeat(section.title.len()));
+            for (name, value) in &section.fields {
+                lines.push(format!("{}: {}", name, value));
+            }
+            for (kind, artifacts) in &section.artifacts {
+                lines.push(format!("{}:", kind));
+                if artifacts.is_empty() {
+                    lines.push("  none".into());
+                }
+                for (path, digests) in artifacts {
+                    lines.push(format!("  {}", path));
+                    lines.push(format!("    {}", digests));
+                }
+            }
+        }
+        pdf(&lines)
+    }
+
+    fn title(&self) -> String {
+        format!("in-toto compliance report: {}", self.release)
+    }
+
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        let layout = self.report.layout();
+        vec![
+            ("Release", self.release.clone()),
+            (
+                "Verified at",
+                self.verified_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            ),
+            ("Outcome", "passed".into()),
+            (
+                "Layout expires",
+                layout.expires().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ),
+            ("Steps", layout.steps().len().to_string()),
+            ("Inspections", self.report.inspections().len().to_string()),
+        ]
+    }
+
+    fn sections(&self) -> Vec<Section> {
+        let steps = self.report.layout().steps().iter().filter_map(|step| {
+            let link = self.report.links().get(step.name())?;
+            let signers = self
+                .report
+                .signers()
+                .get(step.name())
+                .map(|signers| {
+                    let signers: Vec<String> = signers.iter().map(|s| s.to_string()).collect();
+                    signers.join(", ")
+                })
+                .unwrap_or_default();
+            Some(Section {
+                title: format!("Step {}", step.name()),
+                fields: vec![
+                    ("Functionaries", signers),
+                    ("Command", link.command().to_string()),
+                ],
+                artifacts: vec![
+                    ("Materials", artifacts(link.materials())),
+                    ("Products", artifacts(link.products())),
+                ],
+            })
+        });
+        let inspections = self.report.inspections().iter().map(|inspection| Section {
+            title: format!("Inspection {}", inspection.name()),
+            fields: vec![
+                ("Command", inspection.link().command().to_string()),
+                ("Return value", inspection.return_value().to_string()),
+            ],
+            artifacts: vec![
+                ("Materials", artifacts(inspection.link().materials())),
+                ("Products", artifacts(inspection.link().products())),
+            ],
+        });
+        steps.chain(inspections).collect()
+    }
+
+    fn markdown(&self) -> String {
+        let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+        let mut out = format!("# {}\n\n", self.title());
+        for (name, value) in self.summary() {
+            out.push_str(&format!("- **{}:** {}\n", name, value));
+        }
+        for section in self.sections() {
+            out.push_str(&format!("\n## {}\n\n", section.title));
+            for (name, value) in &section.fields {
+                out.push_str(&format!("- **{}:** `{}`\n", name, value.replace('`', "'")));
+            }
+            for (kind, artifacts) in &section.artifacts {
+                out.push_str(&format!("\n{}:\n\n", kind));
+                if artifacts.is_empty() {
+                    out.push_str("none\n");
+                    continue;
+                }
+                out.push_str("| Path | Digests |\n|---|---|\n");
+                for (path, digests) in artifacts {
+                    out.push_str(&format!("| {} | `{}` |\n", cell(path), digests));
+                }
+            }
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let title = escape(&self.title());
+        let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!("<title>{}</title>\n", title));
+        out.push_str(
+            "<style>table{border-collapse:collapse}th,td{border:1px solid #ccc;padding:4px 8px}\
+             td code{word-break:break-all}section{break-inside:avoid-page}\
+             @page{size:A4;margin:2cm}</style>\n",
+        );
+        out.push_str(&format!("</head>\n<body>\n<h1>{}</h1>\n<dl>\n", title));
+        for (name, value) in self.summary() {
+            out.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", name, escape(&value)));
+        }
+        out.push_str("</dl>\n");
+        for section in self.sections() {
+            out.push_str(&format!(
+                "<section>\n<h2>{}</h2>\n<dl>\n",
+                escape(&section.title)
+            ));
+            for (name, value) in &section.fields {
+                out.push_str(&format!(
+                    "<dt>{}</dt><dd><code>{}</code></dd>\n",
+                    name,
+                    escape(value)
+                ));
+            }
+            out.push_str("</dl>\n");
+            for (kind, artifacts) in &section.artifacts {
+                out.push_str(&format!("<h3>{}</h3>\n", kind));
+                if artifacts.is_empty() {
+                    out.push_str("<p>none</p>\n");
+                    continue;
+                }
+                out.push_str("<table>\n<tr><th>Path</th><th>Digests</th></tr>\n");
+                for (path, digests) in artifacts {
+                    out.push_str(&format!(
+                        "<tr><td>{}</td><td><code>{}</code></td></tr>\n",
+                        escape(path),
+                        escape(digests)
+                    ));
+                }
+                out.push_str("</table>\n");
+            }
+            out.push_str("</section>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+/// The paths of `artifacts` with their digests, like `sha256:5b1c...`.
+fn artifacts(
+    artifacts: &std::collections::BTreeMap<crate::models::VirtualTargetPath, TargetDescription>,
+) -> Vec<(String, String)> {
+    artifacts
+        .iter()
+        .map(|(path, hashes)| {
+            let digests: Vec<String> = hashes
+                .iter()
+                .map(|(algorithm, value)| {
+                    format!("{}:{}", algorithm.name(), HEXLOWER.encode(value.value()))
+                })
+                .collect();
+            (path.to_string(), digests.join(" "))
+        })
+        .collect()
+}
+
+/// A PDF document of `lines` of text, wrapped and set on as many pages as
+/// they take.
+fn pdf(lines: &[String]) -> Vec<u8> {
+    let mut wrapped = Vec::new();
+    for line in lines {
+        let line: Vec<char> = line
+            .chars()
+            .map(|c| match c.is_ascii() && !c.is_ascii_control() {
+                true => c,
+                false => '?',
+            })
+            .collect();
+        if line.is_empty() {
+            wrapped.push(String::new());
+        }
+        for chunk in line.chunks(PDF_LINE_WIDTH) {
+            wrapped.push(chunk.iter().collect::<String>());
+        }
+    }
+    let pages: Vec<&[String]> = wrapped.chunks(PDF_PAGE_LINES).collect();
+
+    // objects 1 to 3 are the catalog, the page tree and the font, followed
+    // by the page and content objects of each page
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|n| format!("{} 0 R", 4 + 2 * n))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier >>".to_string(),
+    ];
+    for (n, page) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] \
+             /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+            5 + 2 * n
+        ));
+        let mut content = String::from("BT\n/F1 9 Tf\n11 TL\n50 792 Td\n");
+        for line in page.iter() {
+            let line = line
+                .replace('\\', "\\\\")
+                .replace('(', "\\(")
+                .replace(')', "\\)");
+            content.push_str(&format!("({}) Tj T*\n", line));
+        }
+        content.push_str("ET");
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (n, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.push_str(&format!("{} 0 obj\n{}\nendobj\n", n + 1, object));
+    }
+    let xref = out.len();
+    out.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        out.push_str(&format!("{:010} 00000 n \n", offset));
+    }
+    out.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    ));
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{pdf, ComplianceReport, PDF_PAGE_LINES};
+    use crate::test_utils::{functionary_key, owner_key, signed_layout, store};
+    use crate::verifylib::{in_toto_verify_with_store, ReportFormat};
+
+    #[test]
+    fn render_compliance_report() {
+        let (owner, functionary) = (owner_key(), functionary_key());
+        let layout = signed_layout(&owner, &functionary);
+        let report =
+            in_toto_verify_with_store(&layout, &[owner.public()], &store(&functionary), None)
+                .unwrap();
+        let compliance = ComplianceReport::new(&report, "foo 1.0")
+            .verified_at(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap());
+
+        let markdown = compliance.render(ReportFormat::Markdown);
+        assert!(markdown.starts_with("# in-toto compliance report: foo 1.0\n"));
+        assert!(markdown.contains("- **Verified at:** 2030-01-01T00:00:00Z\n"));
+        assert!(markdown.contains("\n## Step package\n"));
+        assert!(markdown.contains(&format!("{}", functionary.key_id())));
+        assert!(markdown.contains("| foo.tar.gz | `sha256:"));
+
+        let html = compliance.render(ReportFormat::Html);
+        assert!(html.contains("<h2>Step write-code</h2>"));
+        assert!(html.contains("<td>foo.py</td><td><code>sha256:"));
+
+        let pdf = compliance.to_pdf();
+        let pdf = String::from_utf8(pdf).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n"));
+        assert!(pdf.contains("(in-toto compliance report: foo 1.0) Tj T*"));
+        assert!(pdf.ends_with("%%EOF\n"));
+    }
+
+    #[test]
+    fn write_pdf() {
+        let lines: Vec<String> = (0..PDF_PAGE_LINES + 1)
+            .map(|n| format!("line (n) {} \u{e9}", n))
+            .collect();
+        let pdf = String::from_utf8(pdf(&lines)).unwrap();
+        assert!(pdf.contains("/Count 2"));
+        assert!(pdf.contains("(line \\(n\\) 0 ?) Tj T*"));
+
+        // the cross-reference table points at the objects
+        let xref = pdf.rsplit("startxref\n").next().unwrap();
+        let xref: usize = xref.lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with("xref\n0 8\n"));
+        let offset: usize = pdf[xref..].lines().nth(5).unwrap()[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with("3 0 obj\n"));
+    }
+}
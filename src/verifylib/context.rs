@@ -0,0 +1,357 @@
+//! Verifying the supply chains of many tenants in one process.
+//!
+//! A service verifying unrelated projects must never verify one project with
+//! what another trusts or has stored. A `VerifierContext` keeps a `Tenant`
+//! per project, with the keys trusted for its layouts, the store of its
+//! links, its options and its own `VerificationCache`, and verifies a layout
+//! only with the tenant it is verified for. Tenants also remember the
+//! highest layout version they verified and reject older layouts, so a
+//! replaced layout cannot be rolled back to.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::{
+    in_toto_verify_cached_with_options, in_toto_verify_with_options, verify_layout_update,
+    VerificationCache, VerificationReport, VerifyOptions,
+};
+use crate::crypto::PublicKey;
+use crate::models::{Metablock, MetadataWrapper};
+use crate::store::MetadataStore;
+use crate::{Error, Result};
+
+/// What a tenant trusts and has verified, as kept in a `VerifierSnapshot`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantState {
+    owner_keys: Vec<PublicKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inspection_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pinned_layout: Option<Metablock>,
+    #[serde(default)]
+    seen_version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache: Option<VerificationCache>,
+}
+
+impl TenantState {
+    /// The keys trusted for the layouts of the tenant
+    pub fn owner_keys(&self) -> &[PublicKey] {
+        &self.owner_keys
+    }
+
+    /// The layout pinned for the tenant, see `Tenant::pin_layout`
+    pub fn pinned_layout(&self) -> Option<&Metablock> {
+        self.pinned_layout.as_ref()
+    }
+
+    /// The highest layout version verified for the tenant
+    pub fn seen_version(&self) -> u64 {
+        self.seen_version
+    }
+
+    /// The cache of the tenant, if its verifications are cached
+    pub fn verification_cache(&self) -> Option<&VerificationCache> {
+        self.cache.as_ref()
+    }
+}
+
+/// What a tenant trusts and where its metadata is.
+pub struct Tenant {
+    state: TenantState,
+    store: Box<dyn MetadataStore + Send>,
+    options: VerifyOptions,
+}
+
+impl Tenant {
+    /// A tenant trusting layouts signed by all of `owner_keys`, with its
+    /// links in `store`.
+    pub fn new<S: MetadataStore + Send + 'static>(owner_keys: &[&PublicKey], store: S) -> Self {
+        Self::from_state(
+            TenantState {
+                owner_keys: owner_keys.iter().map(|key| (*key).clone()).collect(),
+                inspection_dir: None,
+                pinned_layout: None,
+                seen_version: 0,
+                cache: None,
+            },
+            store,
+        )
+    }
+
+    /// The tenant with `state`, e.g. from a `VerifierSnapshot`, and its
+    /// links in `store`.
+    pub fn from_state<S: MetadataStore + Send + 'static>(state: TenantState, store: S) -> Self {
+        Tenant {
+            state,
+            store: Box::new(store),
+            options: VerifyOptions::new(),
+        }
+    }
+
+    /// Verify as `options` say
+    pub fn options(mut self, options: VerifyOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Run the inspections of the tenant in `inspection_dir` rather than the
+    /// current directory
+    pub fn inspection_dir<P: Into<PathBuf>>(mut self, inspection_dir: P) -> Self {
+        self.state.inspection_dir = Some(inspection_dir.into());
+        self
+    }
+
+    /// Remember successful verifications of the tenant in a cache of its
+    /// own, starting with `cache`, see `in_toto_verify_cached`
+    pub fn cache(mut self, cache: VerificationCache) -> Self {
+        self.state.cache = Some(cache);
+        self
+    }
+
+    /// Pin `layout` as the one layout of the tenant, verified by
+    /// `VerifierContext::verify_pinned` and replaced only by
+    /// `update_layout`. Fails if it is not signed by the owners.
+    pub fn pin_layout(mut self, layout: Metablock) -> Result<Self> {
+        let keys: Vec<&PublicKey> = self.state.owner_keys.iter().collect();
+        layout.verify(keys.len() as u32, keys.iter().copied())?;
+        self.state.pinned_layout = Some(layout);
+        Ok(self)
+    }
+
+    /// Pin `new`, which has to supersede the pinned layout as
+    /// `verify_layout_update` checks with `custody`, and trust its
+    /// `new_keys` from now on.
+    pub fn update_layout(
+        &mut self,
+        new: Metablock,
+        custody: &Metablock,
+        new_keys: &[&PublicKey],
+    ) -> Result<()> {
+        let old = self.state.pinned_layout.as_ref().ok_or_else(|| {
+            Error::IllegalArgument("the tenant has no layout pinned to update".into())
+        })?;
+        let old_keys: Vec<&PublicKey> = self.state.owner_keys.iter().collect();
+        verify_layout_update(old, &new, custody, &old_keys)?;
+        new.verify(new_keys.len() as u32, new_keys.iter().copied())?;
+        self.state.owner_keys = new_keys.iter().map(|key| (*key).clone()).collect();
+        self.state.pinned_layout = Some(new);
+        Ok(())
+    }
+
+    /// What the tenant trusts and has verified
+    pub fn state(&self) -> &TenantState {
+        &self.state
+    }
+
+    /// The keys trusted for the layouts of the tenant
+    pub fn owner_keys(&self) -> &[PublicKey] {
+        &self.state.owner_keys
+    }
+
+    /// The store of the links of the tenant
+    pub fn store(&self) -> &dyn MetadataStore {
+        self.store.as_ref()
+    }
+
+    /// The store of the links of the tenant, e.g. to add links to
+    pub fn store_mut(&mut self) -> &mut dyn MetadataStore {
+        self.store.as_mut()
+    }
+
+    /// The cache of the tenant, if its verifications are cached
+    pub fn verification_cache(&self) -> Option<&VerificationCache> {
+        self.state.cache.as_ref()
+    }
+
+    /// Verify against `layout`, rejecting layouts of a lower version than
+    /// verified before.
+    fn verify(&mut self, layout: &Metablock) -> Result<VerificationReport> {
+        let version = match layout.metadata() {
+            MetadataWrapper::Layout(layout) => layout.layout_version().unwrap_or(0),
+            MetadataWrapper::Link(_) => 0,
+        };
+        if version < self.state.seen_version {
+            return Err(Error::VerificationFailure(format!(
+                "layout version {} is older than version {} verified before",
+                version, self.state.seen_version
+            )));
+        }
+        let keys: Vec<&PublicKey> = self.state.owner_keys.iter().collect();
+        let inspection_dir = match &self.state.inspection_dir {
+            Some(dir) => Some(dir.to_str().ok_or_else(|| {
+                Error::IllegalArgument(format!("{} is not UTF-8", dir.display()))
+            })?),
+            None => None,
+        };
+        let store = self.store.as_ref();
+        let report = match &mut self.state.cache {
+            Some(cache) => in_toto_verify_cached_with_options(
+                layout,
+                &keys,
+                store,
+                inspection_dir,
+                &self.options,
+                cache,
+            ),
+            None => {
+                in_toto_verify_with_options(layout, &keys, store, inspection_dir, &self.options)
+            }
+        }?;
+        self.state.seen_version = version;
+        Ok(report)
+    }
+}
+
+/// The tenants of a verifier, by name.
+///
+/// ```
+/// # use in_toto::crypto::{KeyType, PrivateKey, SignatureScheme};
+/// # use in_toto::store::MemoryStore;
+/// # use in_toto::verifylib::{Tenant, VerifierContext};
+/// let pkcs8 = PrivateKey::new(KeyType::Ed25519).unwrap();
+/// let key = PrivateKey::from_pkcs8(&pkcs8, SignatureScheme::Ed25519).unwrap();
+/// let context = VerifierContext::new();
+/// context.add_tenant("project-a", Tenant::new(&[key.public()], MemoryStore::new())).unwrap();
+/// assert_eq!(context.tenants(), ["project-a"]);
+/// ```
+#[derive(Default)]
+pub struct VerifierContext {
+    tenants: RwLock<BTreeMap<String, Arc<Mutex<Tenant>>>>,
+}
+
+impl VerifierContext {
+    /// A context without tenants
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the tenant `name`, failing if there is one of that name already.
+    pub fn add_tenant(&self, name: &str, tenant: Tenant) -> Result<()> {
+        let mut tenants = self.tenants.write().unwrap_or_else(PoisonError::into_inner);
+        if tenants.contains_key(name) {
+            return Err(Error::IllegalArgument(format!(
+                "tenant {} exists already",
+                name
+            )));
+        }
+        tenants.insert(name.to_string(), Arc::new(Mutex::new(tenant)));
+        Ok(())
+    }
+
+    /// Set the tenant `name`, replacing the tenant of that name if any, e.g.
+    /// to rotate the keys it trusts. Verifications of the tenant running
+    /// meanwhile finish with the tenant they started with.
+    pub fn set_tenant(&self, name: &str, tenant: Tenant) {
+        self.tenants
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), Arc::new(Mutex::new(tenant)));
+    }
+
+    /// Remove the tenant `name`, returning whether there was one.
+    pub fn remove_tenant(&self, name: &str) -> bool {
+        self.tenants
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+            .is_some()
+    }
+
+    /// The names of the tenants, sorted
+    pub fn tenants(&self) -> Vec<String> {
+        self.tenants
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Call `f` with the tenant `name`, e.g. to store its links. Other uses
+    /// of the tenant wait until `f` returns.
+    pub fn with_tenant<T, F: FnOnce(&mut Tenant) -> T>(&self, name: &str, f: F) -> Result<T> {
+        let tenant = self.tenant(name)?;
+        let mut tenant = tenant.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(f(&mut tenant))
+    }
+
+    /// Verify the supply chain of the tenant `name` against the signed
+    /// `layout`, with the keys, links and options of the tenant only.
+    /// Verifications of the same tenant run one at a time, those of
+    /// different tenants in parallel.
+    pub fn verify(&self, name: &str, layout: &Metablock) -> Result<VerificationReport> {
+        self.with_tenant(name, |tenant| tenant.verify(layout))?
+    }
+
+    /// Verify the supply chain of the tenant `name` against its pinned
+    /// layout, see `verify`.
+    pub fn verify_pinned(&self, name: &str) -> Result<VerificationReport> {
+        self.with_tenant(name, |tenant| {
+            let layout = tenant.state.pinned_layout.clone().ok_or_else(|| {
+                Error::IllegalArgument(format!("tenant {} has no layout pinned", name))
+            })?;
+            tenant.verify(&layout)
+        })?
+    }
+
+    fn tenant(&self, name: &str) -> Result<Arc<Mutex<Tenant>>> {
+        self.tenants
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::IllegalArgument(format!("unknown tenant {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Tenant, VerifierContext};
+    use crate::models::{LayoutMetadataBuilder, Metablock};
+    use crate::store::MemoryStore;
+    use crate::test_utils::{functionary_key, owner_key, sign};
+    use crate::verifylib::VerificationCache;
+
+    #[test]
+    fn verify_tenants_apart() {
+        let (owner_a, owner_b) = (owner_key(), functionary_key());
+        let layout = |key| -> Metablock {
+            sign(
+                Box::new(LayoutMetadataBuilder::new().build().unwrap()),
+                &[key],
+            )
+        };
+        let context = VerifierContext::new();
+        context
+            .add_tenant(
+                "a",
+                Tenant::new(&[owner_a.public()], MemoryStore::new())
+                    .cache(VerificationCache::new()),
+            )
+            .unwrap();
+        context
+            .add_tenant("b", Tenant::new(&[owner_b.public()], MemoryStore::new()))
+            .unwrap();
+        assert!(context
+            .add_tenant("b", Tenant::new(&[owner_a.public()], MemoryStore::new()))
+            .is_err());
+
+        assert!(context.verify("a", &layout(&owner_a)).is_ok());
+        assert!(context.verify("b", &layout(&owner_a)).is_err());
+        assert!(context.verify("b", &layout(&owner_b)).is_ok());
+        assert!(context.verify("c", &layout(&owner_a)).is_err());
+        let cached = context
+            .with_tenant("a", |tenant| tenant.verification_cache().map(|c| c.len()))
+            .unwrap();
+        assert_eq!(cached, Some(1));
+
+        context.set_tenant("b", Tenant::new(&[owner_a.public()], MemoryStore::new()));
+        assert!(context.verify("b", &layout(&owner_a)).is_ok());
+        assert!(context.remove_tenant("b"));
+        assert_eq!(context.tenants(), ["a"]);
+    }
+}
@@ -0,0 +1,191 @@
+//! Matching artifact rules against paths recorded on other file systems.
+//!
+//! A supply chain built on macOS or Windows and verified on Linux records
+//! `Src\Main.java` where the layout says `src/main.java`: the same file to
+//! the builder, two different paths to the verifier. `PathMatching`, set
+//! with `VerifyOptions::path_matching`, canonicalizes the artifact paths of
+//! the links and the patterns and prefixes of the rules before the rules are
+//! applied. The links in the `VerificationReport` keep the paths recorded.
+
+use std::collections::BTreeMap;
+
+use crate::models::rule::ArtifactRule;
+use crate::models::{LinkMetadata, LinkMetadataBuilder, TargetDescription, VirtualTargetPath};
+use crate::{Error, Result};
+
+/// How artifact paths are canonicalized before artifact rules are applied.
+///
+/// ```
+/// # use in_toto::verifylib::{PathMatching, VerifyOptions};
+/// // for links recorded on Windows
+/// let options = VerifyOptions::new().path_matching(
+///     PathMatching::new()
+///         .case_insensitive(true)
+///         .forward_slashes(true),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathMatching {
+    case_insensitive: bool,
+    forward_slashes: bool,
+}
+
+impl PathMatching {
+    /// Match paths as recorded, case-sensitively and with `/` as the only
+    /// separator
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match paths ignoring their case, as on the default file systems of
+    /// macOS and Windows
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Take `\` in file paths for `/`, as on Windows. Rule patterns cannot
+    /// escape characters with `\` then.
+    pub fn forward_slashes(mut self, forward_slashes: bool) -> Self {
+        self.forward_slashes = forward_slashes;
+        self
+    }
+
+    /// `path` canonicalized
+    pub fn canonicalize(&self, path: &str) -> String {
+        let mut path = match self.forward_slashes {
+            true => path.replace('\\', "/"),
+            false => path.to_string(),
+        };
+        if self.case_insensitive {
+            path = path.to_lowercase();
+        }
+        path
+    }
+
+    /// `rules` with their patterns and prefixes canonicalized
+    pub(crate) fn rules(&self, rules: &[ArtifactRule]) -> Vec<ArtifactRule> {
+        match self.is_identity() {
+            true => rules.to_vec(),
+            false => rules
+                .iter()
+                .map(|rule| rule.map_paths(|path| self.canonicalize(path)))
+                .collect(),
+        }
+    }
+
+    /// `links` with the paths of their file artifacts canonicalized. Fails if
+    /// artifacts of a link with different digests end up at the same path.
+    pub(crate) fn links(
+        &self,
+        links: &BTreeMap<String, LinkMetadata>,
+    ) -> Result<BTreeMap<String, LinkMetadata>> {
+        if self.is_identity() {
+            return Ok(links.clone());
+        }
+        links
+            .iter()
+            .map(|(name, link)| {
+                let link = LinkMetadataBuilder::from_metadata(link.clone())
+                    .materials(self.artifacts(name, link.materials())?)
+                    .products(self.artifacts(name, link.products())?)
+                    .build()?;
+                Ok((name.clone(), link))
+            })
+            .collect()
+    }
+
+    fn artifacts(
+        &self,
+        step: &str,
+        artifacts: &BTreeMap<VirtualTargetPath, TargetDescription>,
+    ) -> Result<BTreeMap<VirtualTargetPath, TargetDescription>> {
+        let mut canonical: BTreeMap<VirtualTargetPath, (&VirtualTargetPath, &TargetDescription)> =
+            BTreeMap::new();
+        for (path, hashes) in artifacts {
+            // resource identifiers of other schemes are not file paths
+            let key = match path.is_file() {
+                true => VirtualTargetPath::new(self.canonicalize(path.value()))?,
+                false => path.clone(),
+            };
+            match canonical.get(&key) {
+                Some((other, other_hashes)) if *other_hashes != hashes => {
+                    return Err(Error::VerificationFailure(format!(
+                        "{}: artifacts {} and {} differ but are matched as the same path",
+                        step, other, path
+                    )))
+                }
+                _ => {
+                    canonical.insert(key, (path, hashes));
+                }
+            }
+        }
+        Ok(canonical
+            .into_iter()
+            .map(|(path, (_, hashes))| (path, hashes.clone()))
+            .collect())
+    }
+
+    fn is_identity(&self) -> bool {
+        !self.case_insensitive && !self.forward_slashes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::PathMatching;
+    use crate::models::rule::ArtifactRuleBuilder;
+    use crate::test_utils::link;
+
+    #[test]
+    fn canonicalize_paths() {
+        let matching = PathMatching::new()
+            .case_insensitive(true)
+            .forward_slashes(true);
+        assert_eq!(matching.canonicalize(r"Src\Main.java"), "src/main.java");
+        assert_eq!(PathMatching::new().canonicalize(r"Src\Main"), r"Src\Main");
+
+        let rule = ArtifactRuleBuilder::new()
+            .rule("MATCH")
+            .pattern("*.PY")
+            .in_source_path_prefix("Src")
+            .with_products()
+            .from_step("write-code")
+            .build()
+            .unwrap();
+        let rules = matching.rules(&[rule]);
+        assert_eq!(rules[0].pattern(), "*.py");
+        assert_eq!(rules[0].source_path_prefix(), Some("src"));
+
+        let links = BTreeMap::from([(
+            "build".to_string(),
+            link(
+                "build",
+                &[(r"Src\Foo.py", b"foo")],
+                &[("pkg:PyPI/Foo", b"foo")],
+            ),
+        )]);
+        let canonical = matching.links(&links).unwrap();
+        let paths = |artifacts: &BTreeMap<_, _>| {
+            artifacts
+                .keys()
+                .map(|p: &crate::models::VirtualTargetPath| p.value().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(canonical["build"].materials()), ["src/foo.py"]);
+        assert_eq!(paths(canonical["build"].products()), ["pkg:PyPI/Foo"]);
+
+        let clashing = BTreeMap::from([(
+            "build".to_string(),
+            link("build", &[("a.py", b"a"), ("A.py", b"b")], &[]),
+        )]);
+        assert!(matching.links(&clashing).is_err());
+        let same = BTreeMap::from([(
+            "build".to_string(),
+            link("build", &[("a.py", b"a"), ("A.py", b"a")], &[]),
+        )]);
+        assert_eq!(matching.links(&same).unwrap()["build"].materials().len(), 1);
+    }
+}
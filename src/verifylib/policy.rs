@@ -0,0 +1,331 @@
+//! Configuring verification declaratively.
+//!
+//! A deployment verifying supply chains, e.g. in CI or at admission, states
+//! what it trusts in a trust policy file, conventionally named
+//! `TRUST_POLICY_FILENAME`, rather than in code:
+//!
+//! ```toml
+//! [layout]
+//! path = "root.layout"          # the signed layout
+//! keys = ["owner.pub.json"]     # owner keys, or a key bundle:
+//! # bundle = "keys.json"
+//! links = "links"               # the links, by default next to the layout
+//!
+//! [expiry]
+//! max_validity_days = 400       # layouts must be signed again yearly
+//! min_validity_days = 7         # and not be about to expire
+//!
+//! [attestations]
+//! predicate_types = ["https://slsa.dev/provenance/v0.2"]
+//! keys = ["builder.pub.json"]
+//! threshold = 1
+//! ```
+//!
+//! Paths are relative to the directory of the policy file. Keys are files
+//! with a public key in the securesystemslib format, as a `KeyBundle` holds
+//! them. Unknown settings are rejected rather than ignored, as a misspelled
+//! setting would silently weaken verification.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_derive::Deserialize;
+
+use super::{in_toto_verify_with_options, AttestationTrust, VerificationReport, VerifyOptions};
+use crate::crypto::PublicKey;
+use crate::interchange::toml;
+use crate::models::{KeyBundle, Metablock, PredicateVer};
+use crate::store::DirectoryStore;
+use crate::{Error, Result};
+
+/// The conventional name of trust policy files.
+pub const TRUST_POLICY_FILENAME: &str = "trust-policy.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    layout: LayoutSection,
+    #[serde(default)]
+    expiry: ExpirySection,
+    attestations: Option<AttestationSection>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LayoutSection {
+    path: String,
+    #[serde(default)]
+    keys: Vec<String>,
+    bundle: Option<String>,
+    links: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpirySection {
+    max_validity_days: Option<u64>,
+    min_validity_days: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttestationSection {
+    predicate_types: Vec<String>,
+    keys: Vec<String>,
+    #[serde(default = "one")]
+    threshold: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+}
+
+/// What a deployment trusts, as read from a trust policy file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustPolicy {
+    layout: PathBuf,
+    owner_keys: Vec<PublicKey>,
+    bundle: Option<KeyBundle>,
+    link_dir: PathBuf,
+    max_validity: Option<Duration>,
+    min_validity: Option<Duration>,
+    predicate_types: Vec<String>,
+    attestation_keys: Vec<PublicKey>,
+    attestation_threshold: u32,
+    key_files: Vec<PathBuf>,
+}
+
+impl TrustPolicy {
+    /// The policy in the file at `path`, with the keys it names loaded.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let base = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Self::parse(&text, base)
+            .map_err(|e| Error::IllegalArgument(format!("{}: {}", path.display(), e)))
+    }
+
+    /// The policy in the TOML document `text`, its paths relative to
+    /// `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<Self> {
+        let file: PolicyFile = serde_json::from_value(toml::parse(text)?)?;
+        let layout = base.join(&file.layout.path);
+        let owner_keys = load_keys(base, &file.layout.keys)?;
+        let bundle = match &file.layout.bundle {
+            Some(bundle) => Some(serde_json::from_slice(&fs::read(base.join(bundle))?)?),
+            None => None,
+        };
+        if owner_keys.is_empty() == bundle.is_none() {
+            return Err(Error::IllegalArgument(
+                "the layout needs either keys or a key bundle to be trusted".into(),
+            ));
+        }
+        let link_dir = match &file.layout.links {
+            Some(links) => base.join(links),
+            None => layout
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| base.to_path_buf()),
+        };
+        let attestations = file.attestations.unwrap_or(AttestationSection {
+            predicate_types: Vec::new(),
+            keys: Vec::new(),
+            threshold: 1,
+        });
+        if !attestations.predicate_types.is_empty() && attestations.keys.is_empty() {
+            return Err(Error::IllegalArgument(
+                "attestations need keys to be trusted".into(),
+            ));
+        }
+        let key_files = file
+            .layout
+            .keys
+            .iter()
+            .chain(&file.layout.bundle)
+            .chain(&attestations.keys)
+            .map(|path| base.join(path))
+            .collect();
+        Ok(TrustPolicy {
+            layout,
+            owner_keys,
+            bundle,
+            link_dir,
+            max_validity: file.expiry.max_validity_days.map(days),
+            min_validity: file.expiry.min_validity_days.map(days),
+            predicate_types: attestations.predicate_types,
+            attestation_keys: load_keys(base, &attestations.keys)?,
+            attestation_threshold: attestations.threshold,
+            key_files,
+        })
+    }
+
+    /// Where the signed layout is
+    pub fn layout_path(&self) -> &Path {
+        &self.layout
+    }
+
+    /// The keys of the owners who have to sign the layout
+    pub fn owner_keys(&self) -> Vec<&PublicKey> {
+        match &self.bundle {
+            Some(bundle) => bundle.owner_keys(),
+            None => self.owner_keys.iter().collect(),
+        }
+    }
+
+    /// The key bundle trusted, if the policy names one
+    pub fn bundle(&self) -> Option<&KeyBundle> {
+        self.bundle.as_ref()
+    }
+
+    /// Where the links of the steps are
+    pub fn link_dir(&self) -> &Path {
+        &self.link_dir
+    }
+
+    /// The files the policy loaded keys and key bundles from
+    pub fn key_files(&self) -> &[PathBuf] {
+        &self.key_files
+    }
+
+    /// The predicate types attestations are required for
+    pub fn predicate_types(&self) -> &[String] {
+        &self.predicate_types
+    }
+
+    /// The options to verify with, bounding the validity of the layout
+    pub fn verify_options(&self) -> VerifyOptions {
+        let mut options = VerifyOptions::new();
+        if let Some(max_validity) = self.max_validity {
+            options = options.max_validity(max_validity);
+        }
+        if let Some(min_validity) = self.min_validity {
+            options = options.min_validity(min_validity);
+        }
+        options
+    }
+
+    /// What attestations of each required predicate type are accepted, e.g.
+    /// for `verify_subject`. Fails for predicate types this crate does not
+    /// know the statements of.
+    pub fn attestation_trusts(&self) -> Result<Vec<AttestationTrust>> {
+        let keys: Vec<&PublicKey> = self.attestation_keys.iter().collect();
+        self.predicate_types
+            .iter()
+            .map(|predicate_type| {
+                let predicate_type = PredicateVer::try_from(predicate_type.clone())?;
+                Ok(AttestationTrust::new(predicate_type, &keys)
+                    .threshold(self.attestation_threshold))
+            })
+            .collect()
+    }
+
+    /// Verify the supply chain of the policy, running the inspections in
+    /// `inspection_dir`, or the current directory if `None`.
+    pub fn verify(&self, inspection_dir: Option<&str>) -> Result<VerificationReport> {
+        let layout: Metablock = serde_json::from_slice(&fs::read(&self.layout)?)?;
+        if let Some(bundle) = &self.bundle {
+            bundle.check_layout(&layout)?;
+        }
+        in_toto_verify_with_options(
+            &layout,
+            &self.owner_keys(),
+            &DirectoryStore::new(&self.link_dir),
+            inspection_dir,
+            &self.verify_options(),
+        )
+    }
+}
+
+fn load_keys(base: &Path, paths: &[String]) -> Result<Vec<PublicKey>> {
+    paths
+        .iter()
+        .map(|path| {
+            let bytes = fs::read(base.join(path))?;
+            serde_json::from_slice(&bytes)
+                .map_err(|e| Error::IllegalArgument(format!("key file {}: {}", path, e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use chrono::{Duration, Utc};
+
+    use super::TrustPolicy;
+    use crate::models::LayoutMetadataBuilder;
+    use crate::test_utils::{owner_key, sign};
+
+    const POLICY: &str = r#"
+        [layout]
+        path = "root.layout"
+        keys = ["owner.json"]
+
+        [expiry]
+        max_validity_days = 30
+
+        [attestations]
+        predicate_types = ["https://slsa.dev/provenance/v0.2"]
+        keys = ["owner.json"]
+    "#;
+
+    #[test]
+    fn verify_with_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let owner = owner_key();
+        fs::write(
+            dir.path().join("owner.json"),
+            serde_json::to_vec(owner.public()).unwrap(),
+        )
+        .unwrap();
+        let write_layout = |days| {
+            let layout = LayoutMetadataBuilder::new()
+                .expires(Utc::now() + Duration::days(days))
+                .build()
+                .unwrap();
+            let signed = sign(Box::new(layout), &[&owner]);
+            fs::write(
+                dir.path().join("root.layout"),
+                serde_json::to_vec(&signed).unwrap(),
+            )
+            .unwrap();
+        };
+        let policy_file = dir.path().join("trust-policy.toml");
+        fs::write(&policy_file, POLICY).unwrap();
+
+        let policy = TrustPolicy::load(&policy_file).unwrap();
+        assert_eq!(policy.owner_keys(), [owner.public()]);
+        assert_eq!(policy.link_dir(), dir.path());
+        assert_eq!(policy.attestation_trusts().unwrap().len(), 1);
+        write_layout(7);
+        assert!(policy.verify(None).is_ok());
+        write_layout(365);
+        assert!(policy.verify(None).is_err());
+    }
+
+    #[test]
+    fn reject_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        for policy in [
+            "[layout]\npath = \"root.layout\"",
+            "[layout]\npath = \"root.layout\"\nkey = [\"owner.json\"]",
+            "[layout]\npath = \"root.layout\"\nkeys = [\"missing.json\"]",
+        ] {
+            assert!(
+                TrustPolicy::parse(policy, dir.path()).is_err(),
+                "{}",
+                policy
+            );
+        }
+    }
+}
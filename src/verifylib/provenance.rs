@@ -0,0 +1,470 @@
+//! Checking packages of npm and PyPI against their provenance.
+//!
+//! The npm registry and PyPI publish attestations of the packages they
+//! serve: npm the SLSA provenance of a package version and a publish
+//! attestation of the registry, PyPI the attestations of PEP 740 for each
+//! distribution file. Both are DSSE envelopes of in-toto statements of
+//! version 1, which are not modeled by `StatementWrapper`, so they are read
+//! as `PackageProvenance` here, with the predicate kept as JSON.
+//!
+//! `parse_npm_attestations` and `parse_pypi_provenance` read the responses
+//! of the registries, and `verify_package` checks a downloaded package
+//! against them as `PackageTrust` says, e.g. as preset by `PackageTrust::npm`
+//! or `PackageTrust::pypi`. Signatures are checked with the keys trusted as
+//! for `verify_subject`. Attestations signed keyless, with a sigstore
+//! certificate, are attributed to the key given on parsing, which is to be
+//! taken from the certificate once its chain was validated.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+use data_encoding::{BASE64, HEXLOWER};
+use log::debug;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde_derive::Deserialize;
+
+use super::subject::{check_signatures, IN_TOTO_PAYLOAD_TYPE};
+use crate::crypto::{
+    calculate_hashes, HashAlgorithm, HashValue, KeyId, PublicKey, Signature, SignatureValue,
+};
+use crate::models::{CosignBundle, EnvelopeFile, TargetDescription};
+use crate::{Error, Result};
+
+/// Statement type of in-toto statements of version 1.
+pub const STATEMENT_V1: &str = "https://in-toto.io/Statement/v1";
+
+/// Predicate type of SLSA provenance of version 1, as published by npm.
+pub const SLSA_PROVENANCE_V1: &str = "https://slsa.dev/provenance/v1";
+
+/// Predicate type of the publish attestations of the npm registry.
+pub const NPM_PUBLISH_V0_1: &str =
+    "https://github.com/npm/attestation/tree/main/specs/publish/v0.1";
+
+/// Predicate type of the publish attestations of PyPI.
+pub const PYPI_PUBLISH_V1: &str = "https://docs.pypi.org/attestations/publish/v1";
+
+/// The characters of npm package names encoded in package URLs, the `@` of
+/// a scope in particular.
+const PURL_NAME: &AsciiSet = &CONTROLS.add(b'@').add(b' ').add(b'%');
+
+/// A subject of a `PackageProvenance`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ProvenanceSubject {
+    name: String,
+    digest: BTreeMap<String, String>,
+}
+
+impl ProvenanceSubject {
+    /// The name of the subject, e.g. `pkg:npm/%40scope/name@1.0.0` or the
+    /// name of a distribution file
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The hex encoded digests of the subject, by algorithm name
+    pub fn digest(&self) -> &BTreeMap<String, String> {
+        &self.digest
+    }
+
+    /// The digests by hash algorithms this crate knows
+    fn hashes(&self) -> TargetDescription {
+        self.digest
+            .iter()
+            .filter_map(|(algorithm, hex)| {
+                let algorithm = match algorithm.as_str() {
+                    "sha256" => HashAlgorithm::Sha256,
+                    "sha512" => HashAlgorithm::Sha512,
+                    _ => return None,
+                };
+                let value = HEXLOWER.decode(hex.to_lowercase().as_bytes()).ok()?;
+                Some((algorithm, HashValue::new(value)))
+            })
+            .collect()
+    }
+}
+
+/// An in-toto statement of version 1 about a package.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageProvenance {
+    #[serde(rename = "_type")]
+    statement_type: String,
+    subject: Vec<ProvenanceSubject>,
+    predicate_type: String,
+    #[serde(default)]
+    predicate: serde_json::Value,
+}
+
+impl PackageProvenance {
+    /// Parse the statement in `envelope`, without checking its signatures.
+    pub fn from_envelope(envelope: &EnvelopeFile) -> Result<Self> {
+        let provenance: Self = serde_json::from_str(envelope.payload())?;
+        if provenance.statement_type != STATEMENT_V1 {
+            return Err(Error::Encoding(format!(
+                "statement of type {} is no {}",
+                provenance.statement_type, STATEMENT_V1
+            )));
+        }
+        Ok(provenance)
+    }
+
+    /// The subjects attested
+    pub fn subject(&self) -> &[ProvenanceSubject] {
+        &self.subject
+    }
+
+    /// The type of the predicate, e.g. `SLSA_PROVENANCE_V1`
+    pub fn predicate_type(&self) -> &str {
+        &self.predicate_type
+    }
+
+    /// The predicate, as JSON
+    pub fn predicate(&self) -> &serde_json::Value {
+        &self.predicate
+    }
+
+    /// The repository the package was built from according to SLSA
+    /// provenance of version 1 made by GitHub Actions, as npm publishes it,
+    /// e.g. `https://github.com/in-toto/in-toto-rs`
+    pub fn source_repository(&self) -> Option<&str> {
+        if self.predicate_type != SLSA_PROVENANCE_V1 {
+            return None;
+        }
+        self.predicate
+            .pointer("/buildDefinition/externalParameters/workflow/repository")
+            .and_then(serde_json::Value::as_str)
+    }
+}
+
+#[derive(Deserialize)]
+struct NpmAttestations {
+    attestations: Vec<NpmAttestation>,
+}
+
+#[derive(Deserialize)]
+struct NpmAttestation {
+    bundle: CosignBundle,
+}
+
+/// The envelopes of the attestations of a package version as served by the
+/// npm registry at `/-/npm/v1/attestations/<name>@<version>`. Signatures
+/// without key ID of this crate are attributed to `default_key_id`, see
+/// `CosignEnvelope::to_envelope`.
+pub fn parse_npm_attestations(
+    bytes: &[u8],
+    default_key_id: Option<&KeyId>,
+) -> Result<Vec<EnvelopeFile>> {
+    let attestations: NpmAttestations = serde_json::from_slice(bytes)?;
+    attestations
+        .attestations
+        .iter()
+        .map(|attestation| attestation.bundle.envelope().to_envelope(default_key_id))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct PypiProvenance {
+    attestation_bundles: Vec<PypiBundle>,
+}
+
+#[derive(Deserialize)]
+struct PypiBundle {
+    attestations: Vec<PypiAttestation>,
+}
+
+#[derive(Deserialize)]
+struct PypiAttestation {
+    envelope: PypiEnvelope,
+}
+
+#[derive(Deserialize)]
+struct PypiEnvelope {
+    statement: String,
+    signature: String,
+}
+
+/// The envelopes of the attestations in a PEP 740 provenance object, as
+/// served by PyPI at `/integrity/<project>/<version>/<file>/provenance`.
+/// PyPI attestations are all signed keyless, their signatures are
+/// attributed to `key_id`.
+pub fn parse_pypi_provenance(bytes: &[u8], key_id: &KeyId) -> Result<Vec<EnvelopeFile>> {
+    let provenance: PypiProvenance = serde_json::from_slice(bytes)?;
+    provenance
+        .attestation_bundles
+        .iter()
+        .flat_map(|bundle| bundle.attestations.iter())
+        .map(|attestation| {
+            let statement = BASE64.decode(attestation.envelope.statement.as_bytes())?;
+            let statement = String::from_utf8(statement)
+                .map_err(|e| Error::Encoding(format!("statement is no UTF-8: {}", e)))?;
+            let signature = BASE64.decode(attestation.envelope.signature.as_bytes())?;
+            Ok(EnvelopeFile::new(
+                statement,
+                IN_TOTO_PAYLOAD_TYPE.into(),
+                vec![Signature::new(
+                    key_id.clone(),
+                    SignatureValue::new(signature),
+                )],
+            ))
+        })
+        .collect()
+}
+
+/// The provenance accepted by `verify_package`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageTrust {
+    subject: String,
+    predicate_type: String,
+    keys: HashMap<KeyId, PublicKey>,
+    threshold: u32,
+    repository: Option<String>,
+}
+
+impl PackageTrust {
+    /// Accept statements with predicate type `predicate_type` about the
+    /// subject named `subject` signed by one of `keys`.
+    pub fn new(subject: &str, predicate_type: &str, keys: &[&PublicKey]) -> Self {
+        PackageTrust {
+            subject: subject.into(),
+            predicate_type: predicate_type.into(),
+            keys: keys
+                .iter()
+                .map(|key| (key.key_id().clone(), (*key).clone()))
+                .collect(),
+            threshold: 1,
+            repository: None,
+        }
+    }
+
+    /// Accept the SLSA provenance of version `version` of the npm package
+    /// `name`, e.g. `@scope/name`, signed by one of `keys`.
+    pub fn npm(name: &str, version: &str, keys: &[&PublicKey]) -> Self {
+        let subject = format!(
+            "pkg:npm/{}@{}",
+            utf8_percent_encode(name, PURL_NAME),
+            version
+        );
+        Self::new(&subject, SLSA_PROVENANCE_V1, keys)
+    }
+
+    /// Accept the publish attestation of PyPI of the distribution file
+    /// `filename`, e.g. `in_toto-1.0.0-py3-none-any.whl`, signed by one of
+    /// `keys`.
+    pub fn pypi(filename: &str, keys: &[&PublicKey]) -> Self {
+        Self::new(filename, PYPI_PUBLISH_V1, keys)
+    }
+
+    /// Accept predicate type `predicate_type` instead, e.g. the
+    /// `NPM_PUBLISH_V0_1` attestation of the registry
+    pub fn predicate_type(mut self, predicate_type: &str) -> Self {
+        self.predicate_type = predicate_type.into();
+        self
+    }
+
+    /// Require signatures of `threshold` distinct keys
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Require the package to be built from `repository`, see
+    /// `PackageProvenance::source_repository`
+    pub fn repository(mut self, repository: &str) -> Self {
+        self.repository = Some(repository.into());
+        self
+    }
+
+    /// The statement in `envelope` if this trust accepts it for a file with
+    /// the digests `hashes`.
+    fn provenance(
+        &self,
+        envelope: &EnvelopeFile,
+        hashes: &TargetDescription,
+    ) -> Result<PackageProvenance> {
+        check_signatures(envelope, &self.keys, self.threshold)?;
+        let provenance = PackageProvenance::from_envelope(envelope)?;
+        if provenance.predicate_type != self.predicate_type {
+            return Err(Error::VerificationFailure(format!(
+                "the predicate type is {}",
+                provenance.predicate_type
+            )));
+        }
+        let subject = provenance
+            .subject
+            .iter()
+            .find(|subject| subject.name == self.subject)
+            .ok_or_else(|| Error::VerificationFailure(format!("{} is no subject", self.subject)))?;
+        let subject_hashes = subject.hashes();
+        let common: Vec<bool> = subject_hashes
+            .iter()
+            .filter_map(|(algorithm, value)| hashes.get(algorithm).map(|hash| hash == value))
+            .collect();
+        if common.is_empty() || !common.iter().all(|equal| *equal) {
+            return Err(Error::VerificationFailure(format!(
+                "the digests of {} differ",
+                self.subject
+            )));
+        }
+        if let Some(repository) = &self.repository {
+            if provenance.source_repository() != Some(repository.as_str()) {
+                return Err(Error::VerificationFailure(format!(
+                    "{} was not built from {}",
+                    self.subject, repository
+                )));
+            }
+        }
+        Ok(provenance)
+    }
+}
+
+/// Check the package downloaded to `file` against the attestations
+/// `envelopes`, returning the first statement `trust` accepts for it.
+///
+/// The file matches the subject if they have a sha256 or sha512 hash in
+/// common and no differing one, as for `verify_subject`.
+pub fn verify_package<P: AsRef<Path>>(
+    file: P,
+    envelopes: &[EnvelopeFile],
+    trust: &PackageTrust,
+) -> Result<PackageProvenance> {
+    let file = file.as_ref();
+    let (_, hashes) = calculate_hashes(
+        File::open(file)?,
+        &[HashAlgorithm::Sha256, HashAlgorithm::Sha512],
+    )?;
+    for (i, envelope) in envelopes.iter().enumerate() {
+        match trust.provenance(envelope, &hashes) {
+            Ok(provenance) => return Ok(provenance),
+            Err(e) => debug!("Attestation {} is not accepted: {}", i, e),
+        }
+    }
+    Err(Error::VerificationFailure(format!(
+        "{} has no accepted provenance of type {} as {}",
+        file.display(),
+        trust.predicate_type,
+        trust.subject
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use data_encoding::{BASE64, HEXLOWER};
+    use ring::digest::{digest, SHA256, SHA512};
+    use serde_json::json;
+
+    use super::{
+        parse_npm_attestations, parse_pypi_provenance, verify_package, PackageTrust,
+        NPM_PUBLISH_V0_1, PYPI_PUBLISH_V1, SLSA_PROVENANCE_V1,
+    };
+    use crate::crypto::PrivateKey;
+    use crate::models::{CosignBundle, CosignEnvelope, DSSEVersion, EnvelopeFile};
+    use crate::test_utils::key;
+    use crate::verifylib::IN_TOTO_PAYLOAD_TYPE;
+
+    fn statement(subject: serde_json::Value, predicate_type: &str) -> String {
+        json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [subject],
+            "predicateType": predicate_type,
+            "predicate": {
+                "buildDefinition": {
+                    "externalParameters": {
+                        "workflow": {"repository": "https://github.com/in-toto/in-toto-rs"}
+                    }
+                }
+            }
+        })
+        .to_string()
+    }
+
+    fn sign(statement: &str, key: &PrivateKey) -> EnvelopeFile {
+        let message = DSSEVersion::V1.pack(statement.as_bytes(), IN_TOTO_PAYLOAD_TYPE.into());
+        EnvelopeFile::new(
+            statement.into(),
+            IN_TOTO_PAYLOAD_TYPE.into(),
+            vec![key.sign(&message).unwrap()],
+        )
+    }
+
+    #[test]
+    fn verify_npm_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let tarball = dir.path().join("name-1.0.0.tgz");
+        fs::write(&tarball, b"package").unwrap();
+        let sha512 = HEXLOWER.encode(digest(&SHA512, b"package").as_ref());
+        let subject = json!({"name": "pkg:npm/%40scope/name@1.0.0", "digest": {"sha512": sha512}});
+
+        let npm = key("npm");
+        let bundle = |predicate_type| {
+            let envelope = sign(&statement(subject.clone(), predicate_type), &npm);
+            let bundle = CosignBundle::new(CosignEnvelope::from_envelope(&envelope), &[]);
+            json!({"predicateType": predicate_type, "bundle": bundle})
+        };
+        let response =
+            json!({"attestations": [bundle(NPM_PUBLISH_V0_1), bundle(SLSA_PROVENANCE_V1)]});
+        let envelopes = parse_npm_attestations(response.to_string().as_bytes(), None).unwrap();
+        assert_eq!(envelopes.len(), 2);
+
+        let trust = PackageTrust::npm("@scope/name", "1.0.0", &[npm.public()]);
+        let provenance = verify_package(&tarball, &envelopes, &trust).unwrap();
+        assert_eq!(provenance.predicate_type(), SLSA_PROVENANCE_V1);
+        assert_eq!(
+            provenance.source_repository(),
+            Some("https://github.com/in-toto/in-toto-rs")
+        );
+        let publish = trust.clone().predicate_type(NPM_PUBLISH_V0_1);
+        assert!(verify_package(&tarball, &envelopes, &publish).is_ok());
+
+        // other repositories, versions, keys and files
+        let fork = trust
+            .clone()
+            .repository("https://github.com/fork/in-toto-rs");
+        assert!(verify_package(&tarball, &envelopes, &fork).is_err());
+        let other_version = PackageTrust::npm("@scope/name", "1.0.1", &[npm.public()]);
+        assert!(verify_package(&tarball, &envelopes, &other_version).is_err());
+        let untrusted = PackageTrust::npm("@scope/name", "1.0.0", &[key("other").public()]);
+        assert!(verify_package(&tarball, &envelopes, &untrusted).is_err());
+        fs::write(&tarball, b"tampered").unwrap();
+        assert!(verify_package(&tarball, &envelopes, &trust).is_err());
+    }
+
+    #[test]
+    fn verify_pypi_provenance() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = "in_toto-1.0.0-py3-none-any.whl";
+        let wheel = dir.path().join(filename);
+        fs::write(&wheel, b"wheel").unwrap();
+        let sha256 = HEXLOWER.encode(digest(&SHA256, b"wheel").as_ref());
+        let subject = json!({"name": filename, "digest": {"sha256": sha256}});
+
+        let publisher = key("publisher");
+        let envelope = sign(&statement(subject, PYPI_PUBLISH_V1), &publisher);
+        let provenance = json!({
+            "version": 1,
+            "attestation_bundles": [{
+                "publisher": {"kind": "GitHub", "repository": "in-toto/in-toto-rs"},
+                "attestations": [{
+                    "version": 1,
+                    "verification_material": {"certificate": "", "transparency_entries": []},
+                    "envelope": {
+                        "statement": BASE64.encode(envelope.payload().as_bytes()),
+                        "signature": BASE64.encode(envelope.signatures()[0].value().as_bytes()),
+                    }
+                }]
+            }]
+        });
+        let envelopes =
+            parse_pypi_provenance(provenance.to_string().as_bytes(), publisher.key_id()).unwrap();
+        assert_eq!(envelopes, [envelope]);
+
+        let trust = PackageTrust::pypi(filename, &[publisher.public()]);
+        let accepted = verify_package(&wheel, &envelopes, &trust).unwrap();
+        assert_eq!(accepted.subject()[0].name(), filename);
+        assert_eq!(accepted.source_repository(), None);
+        let other_file = PackageTrust::pypi("in_toto-1.0.0.tar.gz", &[publisher.public()]);
+        assert!(verify_package(&wheel, &envelopes, &other_file).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Rendering verification results for humans.
+//!
+//! A verification in CI is best summarized where people look, e.g. in a
+//! comment on the pull request or next to the artifacts of a release.
+//! `VerificationReport::render` summarizes what was verified, for every step
+//! the functionaries whose links were accepted and the number of artifacts,
+//! for every inspection its return value and the end of its output.
+//! `render_failure` summarizes a failed verification, e.g. the rule that
+//! disallowed an artifact. Both render as Markdown or as a standalone HTML
+//! document.
+
+use super::VerificationReport;
+use crate::models::attempt::Attempt;
+use crate::Error;
+
+/// The number of lines of inspection output rendered, the last ones.
+pub const SNIPPET_LINES: usize = 20;
+
+/// The format to render verification results in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// GitHub flavored Markdown
+    Markdown,
+    /// A standalone HTML document
+    Html,
+}
+
+/// A step of a report, as rendered in its table.
+struct StepRow {
+    name: String,
+    signers: String,
+    command: String,
+    materials: usize,
+    products: usize,
+}
+
+/// An inspection of a report, as rendered below the table.
+struct InspectionSection {
+    name: String,
+    return_value: i32,
+    outputs: Vec<(&'static str, String)>,
+}
+
+impl VerificationReport {
+    /// Summary of this report in `format`
+    pub fn render(&self, format: ReportFormat) -> String {
+        let title = "in-toto verification passed";
+        let summary = format!(
+            "{} steps and {} inspections verified, the layout expires at {}.",
+            self.layout.steps().len(),
+            self.inspections.len(),
+            self.layout.expires().to_rfc3339()
+        );
+        let rows = self.step_rows();
+        let sections = self.inspection_sections();
+        match format {
+            ReportFormat::Markdown => markdown(title, &summary, &rows, &sections, None),
+            ReportFormat::Html => html(title, &summary, &rows, &sections, None),
+        }
+    }
+
+    fn step_rows(&self) -> Vec<StepRow> {
+        self.layout
+            .steps()
+            .iter()
+            .filter_map(|step| {
+                let link = self.links.get(step.name())?;
+                let mut name = step.name().to_string();
+                if let Ok(attempt) = Attempt::of(link) {
+                    if attempt.number() > 1 {
+                        name.push_str(&format!(" (attempt {})", attempt.number()));
+                    }
+                }
+                let signers = self
+                    .signers
+                    .get(step.name())
+                    .map(|signers| {
+                        let signers: Vec<String> = signers.iter().map(|s| s.to_string()).collect();
+                        signers.join(", ")
+                    })
+                    .unwrap_or_default();
+                Some(StepRow {
+                    name,
+                    signers,
+                    command: link.command().to_string(),
+                    materials: link.materials().len(),
+                    products: link.products().len(),
+                })
+            })
+            .collect()
+    }
+
+    fn inspection_sections(&self) -> Vec<InspectionSection> {
+        self.inspections
+            .iter()
+            .map(|inspection| {
+                let byproducts = inspection.link().byproducts();
+                let outputs = [
+                    ("stdout", byproducts.stdout()),
+                    ("stderr", byproducts.stderr()),
+                ]
+                .iter()
+                .filter(|(_, output)| !output.trim().is_empty())
+                .map(|(stream, output)| (*stream, snippet(output)))
+                .collect();
+                InspectionSection {
+                    name: inspection.name().to_string(),
+                    return_value: inspection.return_value(),
+                    outputs,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Summary of a verification that failed with `error` in `format`
+pub fn render_failure(error: &Error, format: ReportFormat) -> String {
+    let title = "in-toto verification failed";
+    let summary = "The supply chain could not be verified.";
+    let failure = error.to_string();
+    match format {
+        ReportFormat::Markdown => markdown(title, summary, &[], &[], Some(&failure)),
+        ReportFormat::Html => html(title, summary, &[], &[], Some(&failure)),
+    }
+}
+
+/// The last `SNIPPET_LINES` lines of `output`, noting how many were left out.
+fn snippet(output: &str) -> String {
+    let lines: Vec<&str> = output.trim_end().lines().collect();
+    let omitted = lines.len().saturating_sub(SNIPPET_LINES);
+    let mut snippet = String::new();
+    if omitted > 0 {
+        snippet.push_str(&format!("[{} lines omitted]\n", omitted));
+    }
+    snippet.push_str(&lines[omitted..].join("\n"));
+    snippet
+}
+
+fn markdown(
+    title: &str,
+    summary: &str,
+    rows: &[StepRow],
+    sections: &[InspectionSection],
+    failure: Option<&str>,
+) -> String {
+    let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+    let mut out = format!("## {}\n\n{}\n", title, summary);
+    if let Some(failure) = failure {
+        out.push_str(&format!("\n{}", code_block(failure)));
+    }
+    if !rows.is_empty() {
+        out.push_str("\n| Step | Functionaries | Command | Materials | Products |\n");
+        out.push_str("|---|---|---|---:|---:|\n");
+        for row in rows {
+            out.push_str(&format!(
+                "| {} | {} | `{}` | {} | {} |\n",
+                cell(&row.name),
+                cell(&row.signers),
+                cell(&row.command.replace('`', "'")),
+                row.materials,
+                row.products
+            ));
+        }
+    }
+    for section in sections {
+        out.push_str(&format!(
+            "\n### Inspection {}\n\nReturned {}.\n",
+            section.name, section.return_value
+        ));
+        for (stream, output) in &section.outputs {
+            out.push_str(&format!(
+                "\n<details><summary>{}</summary>\n\n{}</details>\n",
+                stream,
+                code_block(output)
+            ));
+        }
+    }
+    out
+}
+
+/// `text` as fenced code block, with a fence longer than any run of
+/// backticks in it.
+fn code_block(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}\n{}\n{}\n", fence, text, fence)
+}
+
+fn html(
+    title: &str,
+    summary: &str,
+    rows: &[StepRow],
+    sections: &[InspectionSection],
+    failure: Option<&str>,
+) -> String {
+    let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n", escape(title)));
+    out.push_str(
+        "<style>table{border-collapse:collapse}th,td{border:1px solid #ccc;padding:4px 8px}\
+         pre{background:#f6f8fa;padding:8px;overflow:auto}</style>\n",
+    );
+    out.push_str("</head>\n<body>\n");
+    out.push_str(&format!(
+        "<h2>{}</h2>\n<p>{}</p>\n",
+        escape(title),
+        escape(summary)
+    ));
+    if let Some(failure) = failure {
+        out.push_str(&format!("<pre>{}</pre>\n", escape(failure)));
+    }
+    if !rows.is_empty() {
+        out.push_str("<table>\n<tr><th>Step</th><th>Functionaries</th><th>Command</th>");
+        out.push_str("<th>Materials</th><th>Products</th></tr>\n");
+        for row in rows {
+            out.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td></tr>\n",
+                escape(&row.name),
+                escape(&row.signers),
+                escape(&row.command),
+                row.materials,
+                row.products
+            ));
+        }
+        out.push_str("</table>\n");
+    }
+    for section in sections {
+        out.push_str(&format!(
+            "<h3>Inspection {}</h3>\n<p>Returned {}.</p>\n",
+            escape(&section.name),
+            section.return_value
+        ));
+        for (stream, output) in &section.outputs {
+            out.push_str(&format!(
+                "<details><summary>{}</summary><pre>{}</pre></details>\n",
+                stream,
+                escape(output)
+            ));
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+pub(super) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{render_failure, snippet, ReportFormat};
+    use crate::models::byproducts::ByProducts;
+    use crate::models::step::{Command, Step};
+    use crate::models::{
+        LayoutMetadataBuilder, LinkMetadataBuilder, TargetDescription, VirtualTargetPath,
+    };
+    use crate::verifylib::{InspectionResult, VerificationReport};
+    use crate::Error;
+
+    fn report() -> VerificationReport {
+        let layout = LayoutMetadataBuilder::new()
+            .expires(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap())
+            .add_step(Step::new("build"))
+            .build()
+            .unwrap();
+        let link = LinkMetadataBuilder::new()
+            .name("build".into())
+            .command(Command::new(["make", "a|b"]))
+            .products(BTreeMap::from([(
+                VirtualTargetPath::new("a.out".into()).unwrap(),
+                TargetDescription::new(),
+            )]))
+            .build()
+            .unwrap();
+        let output: Vec<String> = (1..=25).map(|n| format!("<line {}>", n)).collect();
+        let inspection = LinkMetadataBuilder::new()
+            .name("check".into())
+            .byproducts(
+                ByProducts::new()
+                    .set_return_value(0)
+                    .set_stdout(output.join("\n")),
+            )
+            .build()
+            .unwrap();
+        VerificationReport {
+            layout,
+            links: BTreeMap::from([("build".to_string(), link)]),
+            link_entries: BTreeSet::new(),
+            signers: BTreeMap::new(),
+            inspections: vec![InspectionResult {
+                name: "check".into(),
+                link: inspection,
+            }],
+        }
+    }
+
+    #[test]
+    fn render_report() {
+        let markdown = report().render(ReportFormat::Markdown);
+        assert!(markdown.starts_with("## in-toto verification passed\n"));
+        assert!(markdown.contains("| build |  | `make 'a\\|b'` | 0 | 1 |\n"));
+        assert!(markdown.contains("### Inspection check\n\nReturned 0.\n"));
+        assert!(markdown.contains("[5 lines omitted]\n<line 6>"));
+        assert!(!markdown.contains("<line 5>\n"));
+
+        let html = report().render(ReportFormat::Html);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<td><code>make &#39;a|b&#39;</code></td>"));
+        assert!(html.contains("&lt;line 25&gt;</pre>"));
+    }
+
+    #[test]
+    fn render_failed_verification() {
+        let error = Error::VerificationFailure("artifact `a.out` is disallowed".into());
+        let markdown = render_failure(&error, ReportFormat::Markdown);
+        assert!(markdown.starts_with("## in-toto verification failed\n"));
+        assert!(markdown.contains("\n```\nverification failure: artifact `a.out`"));
+        let html = render_failure(&error, ReportFormat::Html);
+        assert!(html.contains("<pre>verification failure: artifact `a.out` is disallowed</pre>"));
+        assert_eq!(snippet("a\nb\n"), "a\nb");
+    }
+}
@@ -0,0 +1,159 @@
+//! Unit testing artifact rules.
+//!
+//! Gaps in the artifact rules of a layout, a forgotten `DISALLOW *` or a
+//! `MATCH` from the wrong step, are found by verification only once links
+//! slip through them. `RuleTester` applies the rules of steps and inspections
+//! to links written for the test, without keys, signatures or commands run,
+//! so layout authors can test their rules in their own repositories:
+//!
+//! ```
+//! # use std::collections::BTreeMap;
+//! # use in_toto::crypto::{calculate_hashes, HashAlgorithm};
+//! # use in_toto::models::{LinkMetadataBuilder, VirtualTargetPath};
+//! # use in_toto::models::step::Step;
+//! # use in_toto::models::rule::ArtifactRuleBuilder;
+//! # use in_toto::verifylib::RuleTester;
+//! let rule = |rule: &str, pattern: &str| {
+//!     ArtifactRuleBuilder::new().rule(rule).pattern(pattern).build().unwrap()
+//! };
+//! let build = Step::new("build")
+//!     .add_expected_product(rule("CREATE", "app"))
+//!     .add_expected_product(rule("DISALLOW", "*"));
+//!
+//! let link = |product: &str| {
+//!     let (_, hashes) = calculate_hashes(&b"app"[..], &[HashAlgorithm::Sha256]).unwrap();
+//!     let path = VirtualTargetPath::new(product.into()).unwrap();
+//!     LinkMetadataBuilder::new()
+//!         .name("build".into())
+//!         .products(BTreeMap::from([(path, hashes)]))
+//!         .build()
+//!         .unwrap()
+//! };
+//! RuleTester::new([link("app")]).assert_allows(&build.supply_chain_item);
+//! RuleTester::new([link("backdoor")]).assert_rejects(&build.supply_chain_item);
+//! ```
+
+use std::collections::BTreeMap;
+
+use super::{verify_item_rules, VerifyOptions};
+use crate::models::supply_chain_item::SupplyChainItem;
+use crate::models::{LayoutMetadata, LinkMetadata};
+use crate::Result;
+
+/// Applies artifact rules to links given by the test, keyed by their names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTester {
+    links: BTreeMap<String, LinkMetadata>,
+    options: VerifyOptions,
+}
+
+impl RuleTester {
+    /// Test rules against `links`, the link of each step and inspection
+    /// involved, in particular those `MATCH` rules refer to
+    pub fn new<I: IntoIterator<Item = LinkMetadata>>(links: I) -> Self {
+        RuleTester {
+            links: links
+                .into_iter()
+                .map(|link| (link.name().clone(), link))
+                .collect(),
+            options: VerifyOptions::new(),
+        }
+    }
+
+    /// Apply rules as verification with `options` does, e.g. with its
+    /// `PathMatching`
+    pub fn options(mut self, options: VerifyOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Apply the rules `item` expects of its materials and products to the
+    /// link named like it, failing as verification would.
+    pub fn check(&self, item: &SupplyChainItem) -> Result<()> {
+        let links = self.options.path_matching.links(&self.links)?;
+        verify_item_rules(
+            item.name(),
+            item.expected_materials(),
+            true,
+            &links,
+            &self.options,
+        )?;
+        verify_item_rules(
+            item.name(),
+            item.expected_products(),
+            false,
+            &links,
+            &self.options,
+        )
+    }
+
+    /// Apply the rules of all steps and inspections of `layout`, see `check`
+    pub fn check_layout(&self, layout: &LayoutMetadata) -> Result<()> {
+        let steps = layout.steps().iter().map(|step| &step.supply_chain_item);
+        let inspections = layout
+            .inspect()
+            .iter()
+            .map(|inspection| &inspection.supply_chain_item);
+        steps
+            .chain(inspections)
+            .try_for_each(|item| self.check(item))
+    }
+
+    /// Panic with the failure if the rules of `item` reject the links.
+    pub fn assert_allows(&self, item: &SupplyChainItem) {
+        if let Err(e) = self.check(item) {
+            panic!("rules of {} reject the links: {}", item.name(), e);
+        }
+    }
+
+    /// Panic if the rules of `item` allow the links.
+    pub fn assert_rejects(&self, item: &SupplyChainItem) {
+        if self.check(item).is_ok() {
+            panic!("rules of {} allow the links", item.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RuleTester;
+    use crate::models::step::Step;
+    use crate::test_utils::{functionary_key, layout, link};
+    use crate::verifylib::{PathMatching, VerifyOptions};
+
+    #[test]
+    fn test_rules() {
+        let layout = layout(functionary_key().public());
+        let step =
+            |name: &str| -> &Step { layout.steps().iter().find(|s| s.name() == name).unwrap() };
+        let write_code = link("write-code", &[], &[("foo.py", b"foo")]);
+        let package = |product: &str, source: &[u8]| {
+            link("package", &[("foo.py", source)], &[(product, b"tar")])
+        };
+
+        let tester = RuleTester::new([write_code.clone(), package("foo.tar.gz", b"foo")]);
+        tester.assert_allows(&step("write-code").supply_chain_item);
+        tester.assert_allows(&step("package").supply_chain_item);
+        assert!(tester.check_layout(&layout).is_ok());
+
+        let tampered = RuleTester::new([write_code.clone(), package("foo.tar.gz", b"evil")]);
+        tampered.assert_rejects(&step("package").supply_chain_item);
+        assert!(RuleTester::new([write_code.clone()])
+            .check(&step("package").supply_chain_item)
+            .is_err());
+
+        let renamed = RuleTester::new([write_code, package("Foo.TAR.GZ", b"foo")]);
+        renamed.assert_rejects(&step("package").supply_chain_item);
+        renamed
+            .options(VerifyOptions::new().path_matching(PathMatching::new().case_insensitive(true)))
+            .assert_allows(&step("package").supply_chain_item);
+    }
+
+    #[test]
+    #[should_panic(expected = "rules of write-code reject the links")]
+    fn assert_allows_panics() {
+        let layout = layout(functionary_key().public());
+        RuleTester::new([link("write-code", &[], &[("bar.py", b"bar")])])
+            .assert_allows(&layout.steps()[0].supply_chain_item);
+    }
+}
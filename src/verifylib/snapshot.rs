@@ -0,0 +1,145 @@
+//! Provisioning verifiers from snapshots of their state.
+//!
+//! Air-gapped verifiers cannot fetch what they trust, they are provisioned
+//! with it. A `VerifierSnapshot` holds the `TenantState` of every tenant of
+//! a `VerifierContext`: the keys trusted, the layouts pinned, the highest
+//! layout versions verified and the verifications cached. It is taken on a
+//! known-good verifier, stored as JSON and restored on others, which then
+//! trust exactly what it did. The stores of links and the options of the
+//! tenants are not part of it, they are given again on restoring.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde_derive::{Deserialize, Serialize};
+
+use super::context::{Tenant, TenantState, VerifierContext};
+use crate::{Error, Result};
+
+/// The version of the format of snapshots.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The state of all tenants of a `VerifierContext`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifierSnapshot {
+    version: u32,
+    taken: DateTime<Utc>,
+    tenants: BTreeMap<String, TenantState>,
+}
+
+impl VerifierSnapshot {
+    /// Load the snapshot stored at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let snapshot: VerifierSnapshot = serde_json::from_slice(&fs::read(path)?)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::Encoding(format!(
+                "unsupported snapshot version {}",
+                snapshot.version
+            )));
+        }
+        Ok(snapshot)
+    }
+
+    /// Store the snapshot at `path`, replacing the file as a whole.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(&mut temp, self)?;
+        temp.persist(path)?;
+        Ok(())
+    }
+
+    /// When the snapshot was taken
+    pub fn taken(&self) -> &DateTime<Utc> {
+        &self.taken
+    }
+
+    /// The state of each tenant, by name
+    pub fn tenants(&self) -> &BTreeMap<String, TenantState> {
+        &self.tenants
+    }
+}
+
+impl VerifierContext {
+    /// The state of all tenants. Each tenant is taken as it is between its
+    /// verifications.
+    pub fn snapshot(&self) -> VerifierSnapshot {
+        let mut tenants = BTreeMap::new();
+        for name in self.tenants() {
+            // tenants removed meanwhile are left out
+            if let Ok(state) = self.with_tenant(&name, |tenant| tenant.state().clone()) {
+                tenants.insert(name, state);
+            }
+        }
+        VerifierSnapshot {
+            version: SNAPSHOT_VERSION,
+            taken: Utc::now(),
+            tenants,
+        }
+    }
+
+    /// A context with the tenants of `snapshot`, each made by `tenant` from
+    /// its name and state, e.g. with `Tenant::from_state` and the store of
+    /// its links.
+    pub fn restore<F>(snapshot: VerifierSnapshot, mut tenant: F) -> Result<Self>
+    where
+        F: FnMut(&str, TenantState) -> Result<Tenant>,
+    {
+        let context = VerifierContext::new();
+        for (name, state) in snapshot.tenants {
+            context.add_tenant(&name, tenant(&name, state)?)?;
+        }
+        Ok(context)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::VerifierSnapshot;
+    use crate::models::{LayoutMetadataBuilder, Metablock};
+    use crate::store::MemoryStore;
+    use crate::test_utils::{owner_key, sign};
+    use crate::verifylib::{Tenant, VerificationCache, VerifierContext};
+
+    #[test]
+    fn restore_snapshot() {
+        let owner = owner_key();
+        let layout = |version| -> Metablock {
+            let layout = LayoutMetadataBuilder::new()
+                .layout_version(version)
+                .build()
+                .unwrap();
+            sign(Box::new(layout), &[&owner])
+        };
+        let context = VerifierContext::new();
+        let tenant = Tenant::new(&[owner.public()], MemoryStore::new())
+            .cache(VerificationCache::new())
+            .pin_layout(layout(1))
+            .unwrap();
+        context.add_tenant("a", tenant).unwrap();
+        context.verify("a", &layout(2)).unwrap();
+        context.verify_pinned("a").unwrap_err();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        context.snapshot().save(&path).unwrap();
+        let snapshot = VerifierSnapshot::load(&path).unwrap();
+        let state = &snapshot.tenants()["a"];
+        assert_eq!(state.seen_version(), 2);
+        assert_eq!(state.verification_cache().unwrap().len(), 1);
+
+        let restored = VerifierContext::restore(snapshot, |_, state| {
+            Ok(Tenant::from_state(state, MemoryStore::new()))
+        })
+        .unwrap();
+        assert_eq!(restored.tenants(), ["a"]);
+        assert!(restored.verify("a", &layout(1)).is_err());
+        assert!(restored.verify("a", &layout(3)).is_ok());
+    }
+}
@@ -0,0 +1,210 @@
+//! Checking a single artifact against attestations.
+//!
+//! Consumers of a release often have nothing but the file they downloaded
+//! and the attestations published with it, and no layout. `verify_subject`
+//! answers whether the file is what a trusted builder attested: its digest
+//! has to be a subject of an attestation with the required predicate type,
+//! e.g. SLSA provenance, signed by enough of the trusted keys.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+use log::debug;
+
+use crate::crypto::{calculate_hashes, HashAlgorithm, HashValue, KeyId, PublicKey};
+use crate::models::{DSSEVersion, EnvelopeFile, PredicateVer, StatementWrapper, TargetDescription};
+use crate::{Error, Result};
+
+/// Payload type of DSSE envelopes holding in-toto statements.
+pub const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// The attestations accepted by `verify_subject`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationTrust {
+    predicate_type: PredicateVer,
+    keys: HashMap<KeyId, PublicKey>,
+    threshold: u32,
+}
+
+impl AttestationTrust {
+    /// Accept attestations with predicate type `predicate_type` signed by
+    /// one of `keys`.
+    pub fn new(predicate_type: PredicateVer, keys: &[&PublicKey]) -> Self {
+        AttestationTrust {
+            predicate_type,
+            keys: keys
+                .iter()
+                .map(|key| (key.key_id().clone(), (*key).clone()))
+                .collect(),
+            threshold: 1,
+        }
+    }
+
+    /// Require signatures of `threshold` distinct keys
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// The statement in `envelope` if it is signed by enough trusted keys
+    /// and has the required predicate type.
+    fn statement(&self, envelope: &EnvelopeFile) -> Result<StatementWrapper> {
+        check_signatures(envelope, &self.keys, self.threshold)?;
+        let statement: StatementWrapper = serde_json::from_str(envelope.payload())?;
+        match &statement {
+            StatementWrapper::V0_1(s) if s.predicate_type() == self.predicate_type => Ok(statement),
+            _ => Err(Error::VerificationFailure(format!(
+                "the predicate type is not {}",
+                String::from(self.predicate_type)
+            ))),
+        }
+    }
+}
+
+/// Check that `envelope` holds an in-toto statement signed by `threshold`
+/// distinct keys of `keys`, at least one.
+pub(super) fn check_signatures(
+    envelope: &EnvelopeFile,
+    keys: &HashMap<KeyId, PublicKey>,
+    threshold: u32,
+) -> Result<()> {
+    if envelope.payload_type() != IN_TOTO_PAYLOAD_TYPE {
+        return Err(Error::VerificationFailure(format!(
+            "the payload type is {}",
+            envelope.payload_type()
+        )));
+    }
+    let message = DSSEVersion::V1.pack(
+        envelope.payload().as_bytes(),
+        envelope.payload_type().clone(),
+    );
+    let signers: BTreeSet<&KeyId> = envelope
+        .signatures()
+        .iter()
+        .filter(|sig| {
+            keys.get(sig.key_id())
+                .is_some_and(|key| key.verify(&message, sig).is_ok())
+        })
+        .map(|sig| sig.key_id())
+        .collect();
+    if (signers.len() as u32) < threshold.max(1) {
+        return Err(Error::VerificationFailure(format!(
+            "signed by {} trusted keys, {} needed",
+            signers.len(),
+            threshold.max(1)
+        )));
+    }
+    Ok(())
+}
+
+/// Check that the file at `file` is a subject of one of the attestations
+/// `statements` accepted by `trust`, returning the first such statement.
+///
+/// The file matches a subject if they have a sha256 or sha512 hash in
+/// common and no differing one.
+pub fn verify_subject<P: AsRef<Path>>(
+    file: P,
+    statements: &[EnvelopeFile],
+    trust: &AttestationTrust,
+) -> Result<StatementWrapper> {
+    let file = file.as_ref();
+    let (_, hashes) = calculate_hashes(
+        File::open(file)?,
+        &[HashAlgorithm::Sha256, HashAlgorithm::Sha512],
+    )?;
+    for (i, envelope) in statements.iter().enumerate() {
+        let statement = match trust.statement(envelope) {
+            Ok(statement) => statement,
+            Err(e) => {
+                debug!("Attestation {} is not accepted: {}", i, e);
+                continue;
+            }
+        };
+        if statement
+            .subject()
+            .values()
+            .any(|subject| matches(subject, &hashes))
+        {
+            return Ok(statement);
+        }
+    }
+    Err(Error::VerificationFailure(format!(
+        "{} is no subject of an accepted attestation of {}",
+        file.display(),
+        String::from(trust.predicate_type)
+    )))
+}
+
+fn matches(subject: &TargetDescription, hashes: &HashMap<HashAlgorithm, HashValue>) -> bool {
+    let common: Vec<_> = subject
+        .iter()
+        .filter_map(|(alg, value)| hashes.get(alg).map(|hash| hash == value))
+        .collect();
+    !common.is_empty() && common.iter().all(|equal| *equal)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::slice;
+
+    use ring::digest::{digest, SHA256};
+    use serde_json::json;
+
+    use super::{verify_subject, AttestationTrust, IN_TOTO_PAYLOAD_TYPE};
+    use crate::crypto::PrivateKey;
+    use crate::models::{DSSEVersion, EnvelopeFile, PredicateVer};
+    use crate::test_utils::key;
+
+    fn attestation(content: &[u8], predicate_type: &str, keys: &[&PrivateKey]) -> EnvelopeFile {
+        let sha256 = data_encoding::HEXLOWER.encode(digest(&SHA256, content).as_ref());
+        let payload = json!({
+            "_type": "https://in-toto.io/Statement/v0.1",
+            "subject": {"release.tar.gz": {"sha256": sha256}},
+            "predicateType": predicate_type,
+            "predicate": {
+                "byproducts": {"return-value": 0, "stderr": "", "stdout": ""},
+                "command": "",
+                "env": null,
+                "materials": {},
+                "name": "release"
+            }
+        })
+        .to_string();
+        let message = DSSEVersion::V1.pack(payload.as_bytes(), IN_TOTO_PAYLOAD_TYPE.into());
+        let signatures = keys.iter().map(|k| k.sign(&message).unwrap()).collect();
+        EnvelopeFile::new(payload, IN_TOTO_PAYLOAD_TYPE.into(), signatures)
+    }
+
+    #[test]
+    fn verify_release_subject() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("release.tar.gz");
+        fs::write(&file, b"release").unwrap();
+        let (builder, other) = (key("builder"), key("other"));
+        let link = "https://in-toto.io/Link/v0.2";
+        let trust = AttestationTrust::new(PredicateVer::LinkV0_2, &[builder.public()]);
+
+        let attested = attestation(b"release", link, &[&builder]);
+        assert!(verify_subject(&file, slice::from_ref(&attested), &trust).is_ok());
+
+        // other files, keys, thresholds and predicate types
+        let tampered = attestation(b"tampered", link, &[&builder]);
+        assert!(verify_subject(&file, slice::from_ref(&tampered), &trust).is_err());
+        let untrusted = attestation(b"release", link, &[&other]);
+        assert!(verify_subject(&file, &[untrusted], &trust).is_err());
+        assert!(verify_subject(
+            &file,
+            slice::from_ref(&attested),
+            &trust.clone().threshold(2)
+        )
+        .is_err());
+        let provenance =
+            AttestationTrust::new(PredicateVer::SLSAProvenanceV0_2, &[builder.public()]);
+        assert!(verify_subject(&file, slice::from_ref(&attested), &provenance).is_err());
+
+        // any accepted attestation will do
+        assert!(verify_subject(&file, &[tampered, attested], &trust).is_ok());
+    }
+}
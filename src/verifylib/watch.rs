@@ -0,0 +1,303 @@
+//! Reloading trust policies of long-running verifiers.
+//!
+//! Services verifying supply chains, e.g. admission controllers, rotate the
+//! keys they trust by changing their trust policy file or the key files it
+//! names. `PolicyWatcher` checks these files for changes, on demand or
+//! periodically in a thread of its own, and swaps in the policy read from
+//! them as a whole: verifications always see either the old or the new
+//! policy. A policy failing to load, e.g. while its files are being written,
+//! leaves the policy in use as it is.
+//!
+//! Policy files may be required to be signed, with the signatures stored as
+//! a JSON list next to the file in `<policy>POLICY_SIGNATURE_EXTENSION`, see
+//! `sign_policy_file`.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::warn;
+use ring::digest::{Context, SHA256};
+
+use super::TrustPolicy;
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::{Error, Result};
+
+/// What is appended to the path of a policy file for the path of its
+/// signatures.
+pub const POLICY_SIGNATURE_EXTENSION: &str = ".sig";
+
+fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(POLICY_SIGNATURE_EXTENSION);
+    PathBuf::from(signature_path)
+}
+
+/// Sign the policy file at `path` with `keys`, replacing its signatures.
+pub fn sign_policy_file<P: AsRef<Path>>(path: P, keys: &[&PrivateKey]) -> Result<()> {
+    let path = path.as_ref();
+    let text = fs::read(path)?;
+    let signatures = keys
+        .iter()
+        .map(|key| key.sign(&text))
+        .collect::<Result<Vec<Signature>>>()?;
+    fs::write(
+        signature_path(path),
+        serde_json::to_vec_pretty(&signatures)?,
+    )?;
+    Ok(())
+}
+
+/// A trust policy kept up to date with its files.
+#[derive(Debug)]
+pub struct PolicyWatcher {
+    path: PathBuf,
+    signers: Vec<PublicKey>,
+    policy: RwLock<Arc<TrustPolicy>>,
+    digest: Mutex<Vec<u8>>,
+}
+
+impl PolicyWatcher {
+    /// Watch the policy file at `path`, failing if it does not load.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_signers(path.as_ref(), Vec::new())
+    }
+
+    /// Watch the policy file at `path`, which has to be signed by one of
+    /// `signers`, failing if it does not load.
+    pub fn signed<P: AsRef<Path>>(path: P, signers: &[&PublicKey]) -> Result<Self> {
+        if signers.is_empty() {
+            return Err(Error::IllegalArgument(
+                "signed policies need keys to be trusted".into(),
+            ));
+        }
+        Self::with_signers(
+            path.as_ref(),
+            signers.iter().map(|key| (*key).clone()).collect(),
+        )
+    }
+
+    fn with_signers(path: &Path, signers: Vec<PublicKey>) -> Result<Self> {
+        let (policy, digest) = load(path, &signers)?;
+        Ok(PolicyWatcher {
+            path: path.to_path_buf(),
+            signers,
+            policy: RwLock::new(Arc::new(policy)),
+            digest: Mutex::new(digest),
+        })
+    }
+
+    /// The path of the policy file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The policy as last loaded
+    pub fn policy(&self) -> Arc<TrustPolicy> {
+        self.policy
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Load the policy again if its files changed, returning whether it did.
+    /// The policy in use is kept if the changed files fail to load.
+    pub fn reload(&self) -> Result<bool> {
+        let mut digest = self.digest.lock().unwrap_or_else(PoisonError::into_inner);
+        let text = match fs::read(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        if digest_files(&self.path, &self.signers, &text, &self.policy())? == *digest {
+            return Ok(false);
+        }
+        let (policy, new_digest) = load(&self.path, &self.signers)?;
+        *self.policy.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(policy);
+        *digest = new_digest;
+        Ok(true)
+    }
+
+    /// Check for changes every `interval` in a thread of its own, until the
+    /// `PolicyWatch` returned is dropped. Failures to load are logged.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> PolicyWatch {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let watcher = Arc::clone(self);
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                if let Err(e) = watcher.reload() {
+                    warn!(
+                        "Keeping the trust policy, {} failed to load: {}",
+                        watcher.path.display(),
+                        e
+                    );
+                }
+            }
+        });
+        PolicyWatch {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// The policy in the file at `path`, and the digest of its files.
+fn load(path: &Path, signers: &[PublicKey]) -> Result<(TrustPolicy, Vec<u8>)> {
+    let text = fs::read(path)?;
+    check_signatures(path, signers, &text)?;
+    let base = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let policy = std::str::from_utf8(&text)
+        .map_err(|_| Error::Encoding("not UTF-8".into()))
+        .and_then(|text| TrustPolicy::parse(text, base))
+        .map_err(|e| Error::IllegalArgument(format!("{}: {}", path.display(), e)))?;
+    let digest = digest_files(path, signers, &text, &policy)?;
+    Ok((policy, digest))
+}
+
+fn check_signatures(path: &Path, signers: &[PublicKey], text: &[u8]) -> Result<()> {
+    if signers.is_empty() {
+        return Ok(());
+    }
+    let signatures: Vec<Signature> = serde_json::from_slice(&fs::read(signature_path(path))?)?;
+    let signed = signatures.iter().any(|signature| {
+        signers
+            .iter()
+            .any(|key| key.key_id() == signature.key_id() && key.verify(text, signature).is_ok())
+    });
+    if !signed {
+        return Err(Error::VerificationFailure(format!(
+            "{} is not signed by a trusted key",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// The digest of the policy file with contents `text`, its signatures if
+/// signed and the key files of `policy`, as they are now.
+fn digest_files(
+    path: &Path,
+    signers: &[PublicKey],
+    text: &[u8],
+    policy: &TrustPolicy,
+) -> Result<Vec<u8>> {
+    let mut context = Context::new(&SHA256);
+    context.update(&(text.len() as u64).to_le_bytes());
+    context.update(text);
+    let mut files = policy.key_files().to_vec();
+    if !signers.is_empty() {
+        files.push(signature_path(path));
+    }
+    for file in files {
+        context.update(file.to_string_lossy().as_bytes());
+        match fs::read(&file) {
+            Ok(bytes) => {
+                context.update(&[1]);
+                context.update(&(bytes.len() as u64).to_le_bytes());
+                context.update(&bytes);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => context.update(&[0]),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(context.finish().as_ref().to_vec())
+}
+
+/// A thread checking a `PolicyWatcher` for changes, stopped when dropped.
+#[derive(Debug)]
+pub struct PolicyWatch {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for PolicyWatch {
+    fn drop(&mut self) {
+        // the thread stops as soon as the channel is closed
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::{sign_policy_file, PolicyWatcher};
+    use crate::test_utils::{functionary_key, owner_key};
+
+    #[test]
+    fn reload_signed_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let (owner, functionary) = (owner_key(), functionary_key());
+        for (file, key) in [("owner.json", &owner), ("functionary.json", &functionary)] {
+            fs::write(
+                dir.path().join(file),
+                serde_json::to_vec(key.public()).unwrap(),
+            )
+            .unwrap();
+        }
+        let policy_file = dir.path().join("trust-policy.toml");
+        let write_policy = |key: &str| {
+            fs::write(
+                &policy_file,
+                format!("[layout]\npath = \"root.layout\"\nkeys = [\"{}\"]\n", key),
+            )
+            .unwrap();
+        };
+        write_policy("owner.json");
+        assert!(PolicyWatcher::signed(&policy_file, &[owner.public()]).is_err());
+        sign_policy_file(&policy_file, &[&owner]).unwrap();
+        let watcher = PolicyWatcher::signed(&policy_file, &[owner.public()]).unwrap();
+        assert_eq!(watcher.policy().owner_keys(), [owner.public()]);
+        assert!(!watcher.reload().unwrap());
+
+        // unsigned changes are not picked up
+        write_policy("functionary.json");
+        assert!(watcher.reload().is_err());
+        assert_eq!(watcher.policy().owner_keys(), [owner.public()]);
+
+        sign_policy_file(&policy_file, &[&owner]).unwrap();
+        assert!(watcher.reload().unwrap());
+        assert_eq!(watcher.policy().owner_keys(), [functionary.public()]);
+
+        // nor are changes signed by others
+        write_policy("owner.json");
+        sign_policy_file(&policy_file, &[&functionary]).unwrap();
+        assert!(watcher.reload().is_err());
+        assert_eq!(watcher.policy().owner_keys(), [functionary.public()]);
+    }
+
+    #[test]
+    fn watch_key_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (owner, functionary) = (owner_key(), functionary_key());
+        let key_file = dir.path().join("owner.json");
+        fs::write(&key_file, serde_json::to_vec(owner.public()).unwrap()).unwrap();
+        let policy_file = dir.path().join("trust-policy.toml");
+        fs::write(
+            &policy_file,
+            "[layout]\npath = \"root.layout\"\nkeys = [\"owner.json\"]\n",
+        )
+        .unwrap();
+
+        let watcher = Arc::new(PolicyWatcher::new(&policy_file).unwrap());
+        let _watch = watcher.watch(Duration::from_millis(10));
+        fs::write(&key_file, serde_json::to_vec(functionary.public()).unwrap()).unwrap();
+        let start = Instant::now();
+        while watcher.policy().owner_keys() != [functionary.public()] {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
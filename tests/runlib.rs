@@ -1,8 +1,14 @@
+// The tests predate these lints and are kept as they were written.
+#![allow(clippy::print_with_newline, clippy::useless_vec)]
+
 use in_toto::{
-    crypto::{KeyType, PrivateKey, SignatureScheme},
+    crypto::{calculate_hashes, HashAlgorithm, KeyType, PrivateKey, SignatureScheme},
     interchange::Json,
-    models::{byproducts::ByProducts, LinkMetadataBuilder, VirtualTargetPath},
-    runlib::in_toto_run,
+    models::{
+        byproducts::ByProducts, step::Command, LinkMetadataBuilder, MetadataWrapper,
+        VirtualTargetPath,
+    },
+    runlib::{in_toto_run, record_artifacts_with_options, RecordOptions, SymlinkPolicy},
 };
 use std::fs::{canonicalize, write};
 use std::os::unix::fs;
@@ -32,8 +38,8 @@ Test Cases
 - in_toto_run_record_file
 - in_toto_run_record_new_file
 - in_toto_run_record_modified_file (TODO)
-- in_toto_run_record_symlink_file (TODO)
-- in_toto_run_record_symlink_cycle (TODO)
+- in_toto_run_record_symlink_file
+- in_toto_run_record_symlink_cycle
 - in_toto_run_handle_nonexistent_materials (TODO)
 - in_toto_run_test_key_signature (TODO)
 - One test where things *fail*
@@ -58,6 +64,7 @@ fn in_toto_run_record_file() {
     let expected = LinkMetadataBuilder::new()
         .name(String::from("test"))
         .byproducts(byproducts)
+        .command(Command::new(["sh", "-c", "echo 'in_toto says hi'"]))
         .add_material(VirtualTargetPath::new(format!("{}/foo.txt", dir_path)).unwrap())
         .add_product(VirtualTargetPath::new(format!("{}/foo.txt", dir_path)).unwrap())
         .signed::<Json>(&TEST_PRIVATE_KEY)
@@ -122,6 +129,11 @@ fn in_toto_run_record_new_file() {
         .add_product(VirtualTargetPath::new(format!("{}/foo.txt", dir_path)).unwrap())
         .add_product(VirtualTargetPath::new(format!("{}/bar.txt", dir_path)).unwrap())
         .byproducts(byproducts)
+        .command(Command::new([
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("echo 'in_toto says hi' >> {}/bar.txt", dir_path),
+        ]))
         .signed::<Json>(&TEST_PRIVATE_KEY)
         .unwrap();
 
@@ -166,6 +178,7 @@ fn in_toto_run_record_symlink_file() {
         .add_product(VirtualTargetPath::new(format!("{}/foo.txt", dir_path)).unwrap())
         .add_product(VirtualTargetPath::new(format!("{}/symfile.txt", dir_path)).unwrap())
         .byproducts(byproducts)
+        .command(Command::new(["sh", "-c", "echo 'in_toto says hi'"]))
         .signed::<Json>(&TEST_PRIVATE_KEY)
         .unwrap();
 
@@ -190,5 +203,129 @@ fn in_toto_run_record_symlink_file() {
 
 #[test]
 fn in_toto_run_record_symlink_cycle() {
-    // TODO
+    let dir = tempdir().unwrap();
+    let dir_canonical = canonicalize(dir.path()).unwrap();
+    let dir_path = dir_canonical.to_str().unwrap();
+
+    // a relative link to a file, a link to an ancestor and a dangling link
+    write(format!("{}/foo.txt", dir_path), "lorem ipsum").unwrap();
+    std::fs::create_dir(format!("{}/sub", dir_path)).unwrap();
+    fs::symlink("../foo.txt", format!("{}/sub/rel.txt", dir_path)).unwrap();
+    fs::symlink("..", format!("{}/sub/loop", dir_path)).unwrap();
+    fs::symlink("missing", format!("{}/dangling", dir_path)).unwrap();
+
+    let result = in_toto_run(
+        "test",
+        None,
+        &[dir_path],
+        &[],
+        &[],
+        None,
+        None,
+        Some(&[&format!("{}/", dir_path)]),
+    )
+    .unwrap();
+    let link = match result.metadata() {
+        MetadataWrapper::Link(link) => link.clone(),
+        MetadataWrapper::Layout(_) => unreachable!(),
+    };
+    let paths: Vec<&str> = link.materials().keys().map(|p| p.value()).collect();
+    assert_eq!(paths, ["foo.txt", "sub/rel.txt"]);
+    let foo = &link.materials()[&VirtualTargetPath::new("foo.txt".into()).unwrap()];
+    assert_eq!(
+        &link.materials()[&VirtualTargetPath::new("sub/rel.txt".into()).unwrap()],
+        foo
+    );
+
+    // or skip them
+    let options = RecordOptions::new()
+        .symlinks(SymlinkPolicy::Skip)
+        .lstrip_paths(&[&format!("{}/", dir_path)]);
+    let artifacts = record_artifacts_with_options(&[dir_path], &options).unwrap();
+    let paths: Vec<&str> = artifacts.keys().map(|p| p.value()).collect();
+    assert_eq!(paths, ["foo.txt"]);
+
+    // or record where they point
+    let options = options.symlinks(SymlinkPolicy::Target);
+    let artifacts = record_artifacts_with_options(&[dir_path], &options).unwrap();
+    let paths: Vec<&str> = artifacts.keys().map(|p| p.value()).collect();
+    assert_eq!(paths, ["dangling", "foo.txt", "sub/loop", "sub/rel.txt"]);
+    let (_, target) = calculate_hashes("../foo.txt".as_bytes(), &[HashAlgorithm::Sha256]).unwrap();
+    assert_eq!(
+        artifacts[&VirtualTargetPath::new("sub/rel.txt".into()).unwrap()],
+        target
+    );
+}
+
+#[cfg(all(feature = "tracer", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn in_toto_run_traced_records_opened_files() {
+    use in_toto::runlib::{in_toto_run_traced, RunOptions};
+
+    let dir = tempdir().unwrap();
+    let run_dir = dir.path().to_str().unwrap();
+    write(dir.path().join("foo.txt"), "lorem ipsum").unwrap();
+    write(dir.path().join("bar.txt"), "unused").unwrap();
+
+    let link = in_toto_run_traced(
+        "traced",
+        &["sh", "-c", "cp foo.txt baz.txt"],
+        Some(&TEST_PRIVATE_KEY),
+        None,
+        None,
+        &RunOptions::new().run_dir(run_dir),
+    )
+    .unwrap();
+    let link = match link.metadata() {
+        MetadataWrapper::Link(link) => link.clone(),
+        MetadataWrapper::Layout(_) => unreachable!(),
+    };
+    let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+    assert_eq!(
+        link.materials().keys().collect::<Vec<_>>(),
+        [&path("foo.txt")]
+    );
+    assert_eq!(
+        link.products().keys().collect::<Vec<_>>(),
+        [&path("baz.txt")]
+    );
+}
+
+#[cfg(all(feature = "tracer", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn in_toto_run_traced_applies_record_options() {
+    use in_toto::runlib::{in_toto_run_traced, RunOptions};
+
+    let dir = tempdir().unwrap();
+    let run_dir = dir.path().to_str().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    write(dir.path().join("src/foo.txt"), "lorem ipsum").unwrap();
+    write(dir.path().join("src/build.log"), "noise").unwrap();
+
+    let record = RecordOptions::new()
+        .hash_algorithms(&[HashAlgorithm::Sha512])
+        .exclude_patterns(&["*.log"]);
+    let link = in_toto_run_traced(
+        "traced",
+        &["sh", "-c", "cat src/foo.txt src/build.log > src/baz.txt"],
+        Some(&TEST_PRIVATE_KEY),
+        None,
+        Some(&["src/"]),
+        &RunOptions::new().run_dir(run_dir).record(record),
+    )
+    .unwrap();
+    let link = match link.metadata() {
+        MetadataWrapper::Link(link) => link.clone(),
+        MetadataWrapper::Layout(_) => unreachable!(),
+    };
+    let path = |p: &str| VirtualTargetPath::new(p.into()).unwrap();
+    let (_, foo) = calculate_hashes("lorem ipsum".as_bytes(), &[HashAlgorithm::Sha512]).unwrap();
+    assert_eq!(
+        link.materials().iter().collect::<Vec<_>>(),
+        [(&path("foo.txt"), &foo)]
+    );
+    assert_eq!(
+        link.products().keys().collect::<Vec<_>>(),
+        [&path("baz.txt")]
+    );
 }
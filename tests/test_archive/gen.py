@@ -0,0 +1,41 @@
+#!/usr/bin/env python3
+"""Generate packages carrying the demo metadata below `.in-toto`."""
+import gzip
+import io
+import tarfile
+import zipfile
+
+MTIME = 1667952000
+METADATA = ["demo.layout", "demo.link"]
+
+
+def read(name):
+    with open("../test_metadata/" + name, "rb") as f:
+        return f.read()
+
+
+def crate():
+    tar = io.BytesIO()
+    with tarfile.open(fileobj=tar, mode="w", format=tarfile.GNU_FORMAT) as archive:
+        files = [("demo-0.1.0/src/lib.rs", b"pub fn demo() {}\n")]
+        files += [("demo-0.1.0/.in-toto/" + name, read(name)) for name in METADATA]
+        for path, content in files:
+            info = tarfile.TarInfo(path)
+            info.size, info.mtime, info.mode = len(content), MTIME, 0o644
+            archive.addfile(info, io.BytesIO(content))
+    with open("demo-0.1.0.crate", "wb") as f:
+        f.write(gzip.compress(tar.getvalue(), mtime=MTIME))
+
+
+def wheel():
+    with zipfile.ZipFile("demo-0.1.0-py3-none-any.whl", "w") as archive:
+        files = [("demo/__init__.py", b"")]
+        files += [("demo-0.1.0.dist-info/.in-toto/" + name, read(name)) for name in METADATA]
+        for path, content in files:
+            info = zipfile.ZipInfo(path, (2022, 11, 9, 0, 0, 0))
+            info.compress_type = zipfile.ZIP_DEFLATED
+            archive.writestr(info, content)
+
+
+crate()
+wheel()
@@ -0,0 +1,522 @@
+use chrono::{Duration, Utc};
+use in_toto::{
+    crypto::{PrivateKey, PublicKey, SignatureScheme},
+    interchange::Json,
+    models::{
+        attempt::{attempt_filename, Attempt},
+        custody_link,
+        inspection::Inspection,
+        link_filename,
+        step::Step,
+        KeyBundle, LayoutMetadataBuilder, Metablock, MetablockBuilder, MetadataLimits,
+        VirtualTargetPath,
+    },
+    resolver::ResolverRegistry,
+    runlib::{in_toto_run_with_options, RunOptions},
+    store::DirectoryStore,
+    verifylib::{
+        in_toto_verify, in_toto_verify_cached, in_toto_verify_with_bundle,
+        in_toto_verify_with_options, verify_layout_update, VerificationCache, VerifyOptions,
+    },
+    Error,
+};
+use std::fs::{canonicalize, write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
+use tempfile::{tempdir, TempDir};
+
+const OWNER_PRIVATE_KEY: &[u8] = include_bytes!("./ed25519/ed25519-1");
+const FUNCTIONARY_PRIVATE_KEY: &[u8] = include_bytes!("./ed25519/ed25519-2.pk8.der");
+
+struct Demo {
+    owner: PrivateKey,
+    functionary: PrivateKey,
+    work_dir: TempDir,
+    link_dir: TempDir,
+}
+
+impl Demo {
+    /// Run the steps `write-code` and `package`, storing their links.
+    fn new() -> Self {
+        let demo = Demo {
+            owner: PrivateKey::from_ed25519(OWNER_PRIVATE_KEY).unwrap(),
+            functionary: PrivateKey::from_pkcs8(FUNCTIONARY_PRIVATE_KEY, SignatureScheme::Ed25519)
+                .unwrap(),
+            work_dir: tempdir().unwrap(),
+            link_dir: tempdir().unwrap(),
+        };
+        demo.run_step("write-code", &[], "echo 'print(1)' > foo.py");
+        demo.run_step("package", &[demo.work()], "tar cf foo.tar foo.py");
+        demo
+    }
+
+    fn work(&self) -> &str {
+        self.work_dir.path().to_str().unwrap()
+    }
+
+    fn run_step(&self, name: &str, materials: &[&str], command: &str) {
+        let filename = link_filename(name, self.functionary.public().key_id());
+        self.run_step_with(name, materials, command, &RunOptions::new(), &filename);
+    }
+
+    /// Run step `name` as `options` say, storing its link as `filename`.
+    fn run_step_with(
+        &self,
+        name: &str,
+        materials: &[&str],
+        command: &str,
+        options: &RunOptions,
+        filename: &str,
+    ) {
+        let work = canonicalize(self.work_dir.path()).unwrap();
+        let work = work.to_str().unwrap();
+        let lstrip = format!("{}/", work);
+        let link = in_toto_run_with_options(
+            name,
+            materials,
+            &[work],
+            &["sh", "-c", command],
+            Some(&self.functionary),
+            None,
+            Some(&[&lstrip]),
+            &options.clone().run_dir(work),
+        )
+        .unwrap();
+        let path = self.link_dir.path().join(filename);
+        write(path, serde_json::to_vec(&link).unwrap()).unwrap();
+    }
+
+    fn layout(&self, builder: LayoutMetadataBuilder) -> Metablock {
+        let metadata = builder
+            .add_key(self.functionary.public().clone())
+            .build()
+            .unwrap();
+        MetablockBuilder::from_metadata(Box::new(metadata))
+            .sign(&[&self.owner])
+            .unwrap()
+            .build()
+    }
+
+    fn verify(
+        &self,
+        layout: &Metablock,
+    ) -> in_toto::Result<in_toto::verifylib::VerificationReport> {
+        let owner: &PublicKey = self.owner.public();
+        in_toto_verify(
+            layout,
+            &[owner],
+            self.link_dir.path().to_str().unwrap(),
+            Some(self.work()),
+        )
+    }
+}
+
+fn steps(functionary: &PrivateKey) -> Vec<Step> {
+    let key_id = functionary.public().key_id().clone();
+    vec![
+        Step::new("write-code")
+            .threshold(1)
+            .add_key(key_id.clone())
+            .expected_products(rules(r#"[["CREATE", "foo.py"], ["DISALLOW", "*"]]"#)),
+        Step::new("package")
+            .threshold(1)
+            .add_key(key_id)
+            .expected_command("tar cf foo.tar foo.py".into())
+            .expected_materials(rules(
+                r#"[["MATCH", "foo.py", "WITH", "PRODUCTS", "FROM", "write-code"], ["DISALLOW", "*"]]"#,
+            ))
+            .expected_products(rules(
+                r#"[["CREATE", "foo.tar"], ["ALLOW", "foo.py"], ["DISALLOW", "*"]]"#,
+            )),
+    ]
+}
+
+fn untar() -> Inspection {
+    Inspection::new("untar")
+        .run(["sh", "-c", "mkdir out && tar xf foo.tar -C out"][..].into())
+        .expected_materials(rules(
+            r#"[["MATCH", "foo.tar", "WITH", "PRODUCTS", "FROM", "package"], ["ALLOW", "foo.py"], ["DISALLOW", "*"]]"#,
+        ))
+        .expected_products(rules(
+            r#"[["MATCH", "foo.py", "IN", "out", "WITH", "PRODUCTS", "FROM", "write-code"], ["MATCH", "*", "WITH", "PRODUCTS", "FROM", "package"], ["DISALLOW", "*"]]"#,
+        ))
+}
+
+fn rules(json: &str) -> Vec<in_toto::models::rule::ArtifactRule> {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn verify_supply_chain() {
+    let demo = Demo::new();
+    let layout = demo.layout(
+        LayoutMetadataBuilder::new()
+            .steps(steps(&demo.functionary))
+            .add_inspect(untar()),
+    );
+
+    let report = demo.verify(&layout).unwrap();
+    assert_eq!(
+        report.links().keys().collect::<Vec<_>>(),
+        ["package", "write-code"]
+    );
+
+    // the inspection link is kept in the report
+    let inspection = report.inspection("untar").unwrap();
+    assert_eq!(inspection.return_value(), 0);
+    let products = inspection.link().products();
+    let extracted = VirtualTargetPath::new("out/foo.py".into()).unwrap();
+    assert_eq!(
+        products[&extracted],
+        report.links()["write-code"].products()[&VirtualTargetPath::new("foo.py".into()).unwrap()]
+    );
+    assert!(inspection
+        .link()
+        .materials()
+        .contains_key(&VirtualTargetPath::new("foo.tar".into()).unwrap()));
+}
+
+#[test]
+fn verify_with_key_bundle() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let bundle = KeyBundle::of(&layout, &[demo.owner.public()]).unwrap();
+    let bundle = KeyBundle::from_bytes(&bundle.to_bytes().unwrap()).unwrap();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+
+    assert!(in_toto_verify_with_bundle(&layout, &bundle, &store, Some(demo.work())).is_ok());
+
+    // a layout authorizing a key missing from the bundle
+    let other = PrivateKey::from_ed25519(OWNER_PRIVATE_KEY).unwrap();
+    let metadata = LayoutMetadataBuilder::new()
+        .steps(steps(&demo.functionary))
+        .add_key(demo.functionary.public().clone())
+        .add_key(other.public().clone())
+        .build()
+        .unwrap();
+    let layout = MetablockBuilder::from_metadata(Box::new(metadata))
+        .sign(&[&demo.owner])
+        .unwrap()
+        .build();
+    assert!(in_toto_verify_with_bundle(&layout, &bundle, &store, Some(demo.work())).is_err());
+}
+
+const CACHE_KEY: &[u8] = b"a secret of the verifier, 32 byte";
+
+#[test]
+fn verify_with_cache() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let owner: &PublicKey = demo.owner.public();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+    let cache_file = demo.work_dir.path().join("cache.json");
+
+    let mut cache = VerificationCache::load(&cache_file, CACHE_KEY).unwrap();
+    let report = in_toto_verify_cached(&layout, &[owner], &store, None, &mut cache).unwrap();
+    assert_eq!(cache.len(), 1);
+    cache.save(&cache_file, CACHE_KEY).unwrap();
+
+    let mut cache = VerificationCache::load(&cache_file, CACHE_KEY).unwrap();
+    let cached = in_toto_verify_cached(&layout, &[owner], &store, None, &mut cache).unwrap();
+    assert_eq!(cached, report);
+    assert_eq!(cache.len(), 1);
+
+    // the same link serialized differently is verified again
+    let name = link_filename("package", demo.functionary.public().key_id());
+    let path = demo.link_dir.path().join(name);
+    let link: Metablock = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    write(&path, serde_json::to_vec_pretty(&link).unwrap()).unwrap();
+    in_toto_verify_cached(&layout, &[owner], &store, None, &mut cache).unwrap();
+    assert_eq!(cache.len(), 2);
+}
+
+#[test]
+fn verify_with_cache_authenticated() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let owner: &PublicKey = demo.owner.public();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+    let cache_dir = tempdir().unwrap();
+    let cache_file = cache_dir.path().join("cache.json");
+
+    let mut cache = VerificationCache::new();
+    in_toto_verify_cached(&layout, &[owner], &store, None, &mut cache).unwrap();
+    cache.save(&cache_file, CACHE_KEY).unwrap();
+    assert!(VerificationCache::load(&cache_file, &[0; 32]).is_err());
+    assert!(cache.save(&cache_file, b"short").is_err());
+
+    // a report planted in the cache file is rejected
+    let bytes = std::fs::read(&cache_file).unwrap();
+    let tampered = String::from_utf8(bytes)
+        .unwrap()
+        .replace("package", "packagf");
+    write(&cache_file, tampered).unwrap();
+    assert!(matches!(
+        VerificationCache::load(&cache_file, CACHE_KEY),
+        Err(Error::VerificationFailure(_))
+    ));
+}
+
+#[test]
+fn verify_with_cache_inspected_files() {
+    let demo = Demo::new();
+    let list = Inspection::new("list")
+        .run(["tar", "tf", "foo.tar"][..].into())
+        .expected_materials(rules(
+            r#"[["MATCH", "foo.tar", "WITH", "PRODUCTS", "FROM", "package"], ["ALLOW", "foo.py"], ["DISALLOW", "*"]]"#,
+        ));
+    let layout = demo.layout(
+        LayoutMetadataBuilder::new()
+            .steps(steps(&demo.functionary))
+            .add_inspect(list),
+    );
+    let owner: &PublicKey = demo.owner.public();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+    let verify = |cache: &mut VerificationCache, options: &VerifyOptions| {
+        in_toto::verifylib::in_toto_verify_cached_with_options(
+            &layout,
+            &[owner],
+            &store,
+            Some(demo.work()),
+            options,
+            cache,
+        )
+    };
+
+    let mut cache = VerificationCache::new();
+    verify(&mut cache, &VerifyOptions::new()).unwrap();
+    verify(&mut cache, &VerifyOptions::new()).unwrap();
+    assert_eq!(cache.len(), 1);
+
+    // other options are verified again
+    let options = VerifyOptions::new().limits(MetadataLimits::new().max_artifacts(10));
+    verify(&mut cache, &options).unwrap();
+    assert_eq!(cache.len(), 2);
+
+    // resolvers cannot be told apart, so their verifications are not cached
+    let options = VerifyOptions::new().resolvers(Arc::new(ResolverRegistry::new()));
+    verify(&mut cache, &options).unwrap();
+    assert_eq!(cache.len(), 2);
+
+    // as is a product swapped after the verification
+    write(demo.work_dir.path().join("foo.tar"), b"swapped").unwrap();
+    assert!(verify(&mut cache, &VerifyOptions::new()).is_err());
+}
+
+#[test]
+fn verify_fails_on_links_exceeding_limits() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let owner: &PublicKey = demo.owner.public();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+
+    let verify = |limits: MetadataLimits| {
+        let options = VerifyOptions::new().limits(limits);
+        in_toto_verify_with_options(&layout, &[owner], &store, None, &options)
+    };
+    assert!(verify(MetadataLimits::new()).is_ok());
+    assert!(verify(MetadataLimits::new().max_artifacts(0)).is_err());
+    assert!(verify(MetadataLimits::new().max_size(64)).is_err());
+}
+
+#[test]
+fn verify_fails_on_disallowed_artifact() {
+    let demo = Demo::new();
+    write(Path::new(demo.work()).join("evil"), "rm -rf /").unwrap();
+    let layout = demo.layout(
+        LayoutMetadataBuilder::new()
+            .steps(steps(&demo.functionary))
+            .add_inspect(untar()),
+    );
+    assert!(demo.verify(&layout).is_err());
+}
+
+#[test]
+fn verify_aborts_hanging_inspection_at_deadline() {
+    let demo = Demo::new();
+    let layout = demo.layout(
+        LayoutMetadataBuilder::new()
+            .steps(steps(&demo.functionary))
+            .add_inspect(Inspection::new("hang").run("sleep 10".into())),
+    );
+    let owner: &PublicKey = demo.owner.public();
+    let store = DirectoryStore::new(demo.link_dir.path().to_str().unwrap());
+    let options = VerifyOptions::new().timeout(StdDuration::from_millis(500));
+
+    let start = Instant::now();
+    let result =
+        in_toto_verify_with_options(&layout, &[owner], &store, Some(demo.work()), &options);
+    assert!(matches!(result, Err(Error::Timeout(_))));
+    assert!(start.elapsed() < StdDuration::from_secs(5));
+}
+
+#[test]
+fn verify_fails_on_failing_inspection() {
+    let demo = Demo::new();
+    let layout = demo.layout(
+        LayoutMetadataBuilder::new()
+            .steps(steps(&demo.functionary))
+            .add_inspect(Inspection::new("fail").run("false".into())),
+    );
+    assert!(demo.verify(&layout).is_err());
+}
+
+#[test]
+fn verify_superseded_attempts() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let key_id = demo.functionary.public().key_id();
+
+    // the first attempt left a stray product, the link of its retry is
+    // kept next to it
+    let work = demo.work_dir.path();
+    std::fs::remove_file(work.join("foo.tar")).unwrap();
+    demo.run_step(
+        "package",
+        &[demo.work()],
+        "tar cf foo.tar foo.py && touch junk",
+    );
+    for product in ["foo.tar", "junk"] {
+        std::fs::remove_file(work.join(product)).unwrap();
+    }
+    let retry = RunOptions::new().attempt(Attempt::new(2).unwrap());
+    demo.run_step_with(
+        "package",
+        &[demo.work()],
+        "tar cf foo.tar foo.py",
+        &retry,
+        &attempt_filename("package", key_id, 2),
+    );
+    let report = demo.verify(&layout).unwrap();
+    let attempt = Attempt::of(&report.links()["package"]).unwrap();
+    assert_eq!(attempt.number(), 2);
+
+    // without it, the failed attempt is all there is
+    std::fs::remove_file(
+        demo.link_dir
+            .path()
+            .join(attempt_filename("package", key_id, 2)),
+    )
+    .unwrap();
+    assert!(demo.verify(&layout).is_err());
+}
+
+#[test]
+fn verify_reports_signer_hints() {
+    let demo = Demo::new();
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps(&demo.functionary)));
+    let key_id = demo.functionary.public().key_id();
+
+    std::fs::remove_file(demo.work_dir.path().join("foo.tar")).unwrap();
+    demo.run_step_with(
+        "package",
+        &[demo.work()],
+        "tar cf foo.tar foo.py",
+        &RunOptions::new().signer_id("alice@corp / job 1234"),
+        &link_filename("package", key_id),
+    );
+    let report = demo.verify(&layout).unwrap();
+    let package = &report.signers()["package"];
+    assert_eq!(package[0].key_id(), key_id);
+    assert_eq!(
+        package[0].to_string(),
+        format!("alice@corp / job 1234 ({:.8})", key_id.to_string())
+    );
+    assert_eq!(
+        report.signers()["write-code"][0].to_string(),
+        key_id.to_string()
+    );
+}
+
+#[test]
+fn verify_fails_on_missing_links() {
+    let demo = Demo::new();
+    let mut steps = steps(&demo.functionary);
+    steps.push(
+        Step::new("review")
+            .threshold(1)
+            .add_key(demo.functionary.public().key_id().clone()),
+    );
+    let layout = demo.layout(LayoutMetadataBuilder::new().steps(steps));
+    assert!(demo.verify(&layout).is_err());
+}
+
+#[test]
+fn verify_fails_on_bad_layout() {
+    let demo = Demo::new();
+
+    let expired = demo.layout(
+        LayoutMetadataBuilder::new()
+            .expires(Utc::now() - Duration::days(1))
+            .steps(steps(&demo.functionary)),
+    );
+    assert!(demo.verify(&expired).is_err());
+
+    // signed by a functionary instead of the owner
+    let metadata = LayoutMetadataBuilder::new()
+        .steps(steps(&demo.functionary))
+        .build()
+        .unwrap();
+    let layout = MetablockBuilder::from_metadata(Box::new(metadata))
+        .sign(&[&demo.functionary])
+        .unwrap()
+        .build();
+    assert!(demo.verify(&layout).is_err());
+
+    // a link is no layout
+    let link = in_toto::models::LinkMetadataBuilder::new()
+        .signed::<Json>(&demo.owner)
+        .unwrap();
+    assert!(demo.verify(&link).is_err());
+}
+
+#[test]
+fn verify_superseding_layout() {
+    let demo = Demo::new();
+    let old = demo.layout(
+        LayoutMetadataBuilder::new()
+            .layout_version(1)
+            .steps(steps(&demo.functionary)),
+    );
+    // a new owner takes over, adding an inspection
+    let new_owner = PrivateKey::from_pkcs8(
+        include_bytes!("./ed25519/ed25519-3.pk8.der"),
+        SignatureScheme::Ed25519,
+    )
+    .unwrap();
+    let metadata = LayoutMetadataBuilder::new()
+        .supersedes(&old)
+        .unwrap()
+        .steps(steps(&demo.functionary))
+        .add_inspect(untar())
+        .add_key(demo.functionary.public().clone())
+        .build()
+        .unwrap();
+    let new = MetablockBuilder::from_metadata(Box::new(metadata))
+        .sign(&[&new_owner])
+        .unwrap()
+        .build();
+    let custody = MetablockBuilder::from_metadata(Box::new(custody_link(&old, &new).unwrap()))
+        .sign(&[&demo.owner])
+        .unwrap()
+        .build();
+
+    verify_layout_update(&old, &new, &custody, &[demo.owner.public()]).unwrap();
+    assert!(in_toto_verify(
+        &new,
+        &[new_owner.public()],
+        demo.link_dir.path().to_str().unwrap(),
+        Some(demo.work()),
+    )
+    .is_ok());
+
+    // custody has to be handed over by the old owner
+    let forged = MetablockBuilder::from_metadata(Box::new(custody_link(&old, &new).unwrap()))
+        .sign(&[&new_owner])
+        .unwrap()
+        .build();
+    assert!(verify_layout_update(&old, &new, &forged, &[demo.owner.public()]).is_err());
+    // going back to the old layout is no update
+    assert!(verify_layout_update(&new, &old, &custody, &[new_owner.public()]).is_err());
+}